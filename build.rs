@@ -0,0 +1,7 @@
+fn main() {
+    // The release assets of self-update are named after the target triple.
+    println!(
+        "cargo:rustc-env=RARSCAN_TARGET={}",
+        std::env::var("TARGET").expect("cargo sets TARGET")
+    );
+}
@@ -0,0 +1,24 @@
+//! Pretty-prints the events published by `rarscan --event-socket <path>`.
+//!
+//! Usage: cargo run --example event_client -- <path>
+
+#[cfg(unix)]
+fn main() -> anyhow::Result<()> {
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixStream,
+    };
+
+    let path = std::env::args().nth(1).expect("usage: event_client <socket path>");
+    let stream = UnixStream::connect(&path)?;
+    for line in BufReader::new(stream).lines() {
+        let event: serde_json::Value = serde_json::from_str(&line?)?;
+        println!("{}", serde_json::to_string_pretty(&event)?);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("Unix domain sockets are not available on this platform.");
+}
@@ -0,0 +1,1466 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Context;
+use glob::Pattern;
+use regex::Regex;
+#[cfg(feature = "zip")]
+use zip::{result::ZipError, ZipArchive};
+
+use crate::{
+    failure::ExtractionError,
+    fds, format_size, longnames,
+    naming::ArchiveNaming,
+    ownership,
+    prealloc::{self, Preallocate},
+    privileges,
+    rarstream::{RarStream, ReadError},
+    renamemap::Renamed,
+    sidecar, store,
+    tarball::{self, Compression},
+    volumes::{self, LinkedChain, RenamedChain},
+};
+
+/// Whether `path` is a zip archive, never when rarscan is built without the `zip` feature.
+pub fn is_zip_file(path: &Path) -> bool {
+    cfg!(feature = "zip") && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Whether `path` is a rar archive, from its extension or else from its signature.
+pub fn is_rar_file(path: &Path) -> bool {
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("rar")) {
+        return true;
+    }
+    let mut start = [0; 8];
+    let _fds = fds::acquire(1);
+    File::open(path)
+        .and_then(|mut file| file.read(&mut start))
+        .is_ok_and(|len| Format::detect_rar(&start[..len]).is_some())
+}
+
+/// The start of the signatures of every rar version, the byte after it is the version.
+const RAR_SIGNATURE: &[u8] = b"Rar!\x1a\x07";
+const RAR4_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x00";
+const RAR5_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x01\x00";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rar4,
+    Rar5,
+    #[cfg(feature = "zip")]
+    Zip,
+    Tar(Compression),
+}
+
+impl Format {
+    /// Identifies the rar version from the signature at the start of the file.
+    pub fn detect_rar(start: &[u8]) -> Option<Format> {
+        if start.starts_with(RAR5_SIGNATURE) {
+            Some(Format::Rar5)
+        } else if start.starts_with(RAR4_SIGNATURE) {
+            Some(Format::Rar4)
+        } else {
+            None
+        }
+    }
+
+    fn read_rar(path: &Path) -> anyhow::Result<Format> {
+        let mut start = [0; 8];
+        let _fds = fds::acquire(1);
+        let mut file = File::open(path).map_err(|e| read_error(path, e))?;
+        let len = file.read(&mut start).map_err(|e| read_error(path, e))?;
+        match Format::detect_rar(&start[..len]) {
+            Some(format) => Ok(format),
+            // Of a version after RAR5, which unrar would only call a bad archive.
+            None if len > RAR_SIGNATURE.len() && start.starts_with(RAR_SIGNATURE) => Err(ExtractionError::Unknown {
+                message: "archive requires a rar format newer than RAR5, which is not supported".into(),
+            }
+            .into()),
+            // Self-extracting archives have an executable before the signature, leave them to unrar.
+            None => Ok(Format::Rar4),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Rar4 => "RAR4",
+            Format::Rar5 => "RAR5",
+            #[cfg(feature = "zip")]
+            Format::Zip => "zip",
+            Format::Tar(compression) => return compression.fmt(f),
+        })
+    }
+}
+
+/// An entry of an archive, as listed from its headers.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// Path of the entry relative to the destination, which differs from the path inside the archive when it's
+    /// flattened.
+    pub filename: PathBuf,
+    pub unpacked_size: u64,
+    pub directory: bool,
+    pub encrypted: bool,
+    /// Continued from the previous part or into the next one.
+    pub split: bool,
+    /// CRC32 of the content. Unknown for split entries, whose headers only have the CRC of their piece, and for RAR5
+    /// entries hashed with BLAKE2 instead.
+    pub crc: Option<u32>,
+}
+
+impl Entry {
+    pub fn is_directory(&self) -> bool {
+        self.directory
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.directory
+    }
+}
+
+/// What the destination holds in place of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Found {
+    Missing,
+    Directory,
+    /// A file of this size.
+    File(u64),
+}
+
+/// How the destination compares to an entry of the archive, for the already-extracted check.
+#[derive(Debug)]
+pub struct EntryCheck<'a> {
+    pub name: &'a Path,
+    /// Where the entry is looked for, its current path when it's renamed.
+    pub path: PathBuf,
+    /// Size wanted, `None` for a directory.
+    pub expected: Option<u64>,
+    pub found: Found,
+}
+
+impl EntryCheck<'_> {
+    pub fn matches(&self) -> bool {
+        match (self.expected, self.found) {
+            (None, found) => found == Found::Directory,
+            (Some(size), found) => found == Found::File(size),
+        }
+    }
+}
+
+pub struct Archive {
+    pub path: PathBuf,
+    pub format: Format,
+    pub solid: bool,
+    pub encrypted_headers: bool,
+    pub headers: Vec<Entry>,
+    parts_glob: PathBuf,
+    parts_filter: Option<Regex>,
+    stripped_dir: Option<PathBuf>,
+    /// Entries extracted under a shortened name, by their name relative to the destination.
+    renames: HashMap<PathBuf, PathBuf>,
+    preallocate: Preallocate,
+    /// Whether the entries stored without compression are copied out of their volume rather than through unrar.
+    fast_store_copy: bool,
+    /// Bytes the extraction may write before it's aborted.
+    max_written: Option<u64>,
+    /// Whether rar archives are written by rarscan so that `max_written` stops them within a file.
+    stream_budget: bool,
+    /// Entries renamed by something else since their extraction, by their name relative to the destination.
+    renamed: HashMap<PathBuf, Renamed>,
+    /// Of the encrypted entries or headers, from the sidecar of the archive.
+    password: Option<String>,
+    /// Entries left out of the extraction, by their name inside of the archive.
+    exclude: Vec<Pattern>,
+    /// Names of the files left out by `exclude`.
+    excluded: Vec<PathBuf>,
+    /// The volumes followed through their headers, when some were renamed after another set.
+    volumes: Option<LinkedChain>,
+}
+
+impl Archive {
+    pub fn open(path: impl Into<PathBuf>, naming: &ArchiveNaming) -> anyhow::Result<Archive> {
+        Archive::open_with_password(path, naming, None)
+    }
+
+    /// Opens an archive whose headers or entries may be encrypted with `password`. Tarballs have no password.
+    pub fn open_with_password(
+        path: impl Into<PathBuf>,
+        naming: &ArchiveNaming,
+        password: Option<&str>,
+    ) -> anyhow::Result<Archive> {
+        let path = path.into();
+        let password = password.map(str::to_string);
+        #[cfg(feature = "zip")]
+        if is_zip_file(&path) {
+            return Archive::open_zip(path, password);
+        }
+        if let Some(compression) = Compression::of(&path) {
+            return Archive::open_tar(path, compression);
+        }
+
+        let format = Format::read_rar(&path)?;
+        let mut volumes = None;
+        let listed = match list_rar(&path, &path, password.as_deref()) {
+            // The later volumes may be there under the name of another set, or in the directories next to this one.
+            Err(e) if matches!(e.downcast_ref(), Some(ExtractionError::MissingVolume { .. })) => {
+                let chain = match RenamedChain::resolve(&path) {
+                    Ok(None) if naming.parts_across_dirs() => RenamedChain::resolve_across_dirs(&path),
+                    chain => chain,
+                };
+                match chain.and_then(|chain| chain.map(RenamedChain::link).transpose()) {
+                    Ok(Some(linked)) => {
+                        let listed = list_rar(&path, linked.first_link(), password.as_deref());
+                        volumes = Some(linked);
+                        listed
+                    }
+                    _ => Err(e),
+                }
+            }
+            listed => listed,
+        };
+        let (solid, encrypted_headers, headers) = listed?;
+
+        let (parts_glob, parts_filter) = match naming.parts_glob(&path) {
+            Some(glob) => (glob, naming.custom_part_pattern().cloned()),
+            None => (unrar::Archive::new(&path).all_parts(), None),
+        };
+
+        Ok(Archive {
+            parts_glob,
+            parts_filter,
+            path,
+            format,
+            solid,
+            encrypted_headers,
+            headers,
+            stripped_dir: None,
+            renames: HashMap::new(),
+            preallocate: Preallocate::Auto,
+            fast_store_copy: false,
+            max_written: None,
+            stream_budget: false,
+            renamed: HashMap::new(),
+            password,
+            exclude: Vec::new(),
+            excluded: Vec::new(),
+            volumes,
+        })
+    }
+
+    #[cfg(feature = "zip")]
+    fn open_zip(path: PathBuf, password: Option<String>) -> anyhow::Result<Archive> {
+        let _fds = fds::acquire(1);
+        let mut archive = open_zip_archive(&path)?;
+        let mut headers = Vec::new();
+        for index in 0..archive.len() {
+            let file = archive.by_index_raw(index).map_err(zip_error)?;
+            if file.encrypted() && password.is_none() {
+                anyhow::bail!("encrypted zip archives are not supported without a password");
+            }
+            let filename = file
+                .enclosed_name()
+                .with_context(|| format!("zip entry {:?} has an unsafe path", file.name()))?;
+            headers.push(Entry {
+                filename,
+                unpacked_size: file.size(),
+                directory: file.is_dir(),
+                encrypted: file.encrypted(),
+                split: false,
+                crc: (!file.is_dir()).then_some(file.crc32()),
+            });
+        }
+
+        Ok(Archive {
+            // Split zips aren't supported, the glob matches only the file itself.
+            parts_glob: PathBuf::from(glob::Pattern::escape(&path.to_string_lossy())),
+            parts_filter: None,
+            path,
+            format: Format::Zip,
+            solid: false,
+            encrypted_headers: false,
+            headers,
+            stripped_dir: None,
+            renames: HashMap::new(),
+            preallocate: Preallocate::Auto,
+            fast_store_copy: false,
+            max_written: None,
+            stream_budget: false,
+            renamed: HashMap::new(),
+            password,
+            exclude: Vec::new(),
+            excluded: Vec::new(),
+            volumes: None,
+        })
+    }
+
+    /// Lists the files and directories of a tarball, which means decompressing all of it. Links aren't listed, they
+    /// are created along with the files but not checked.
+    fn open_tar(path: PathBuf, compression: Compression) -> anyhow::Result<Archive> {
+        let _fds = fds::acquire(1);
+        let mut archive = tarball::open(&path, compression).context("open tarball")?;
+        let mut headers = Vec::new();
+        for entry in archive.entries().context("read tarball")? {
+            let entry = entry.context("read tarball")?;
+            let name = entry.path().context("read tarball")?;
+            let filename =
+                tarball::enclosed_name(&name).with_context(|| format!("tar entry {:?} has an unsafe path", name))?;
+            let kind = entry.header().entry_type();
+            if kind.is_file() || kind.is_dir() {
+                headers.push(Entry {
+                    filename,
+                    unpacked_size: if kind.is_dir() { 0 } else { entry.size() },
+                    directory: kind.is_dir(),
+                    encrypted: false,
+                    split: false,
+                    crc: None,
+                });
+            }
+        }
+
+        Ok(Archive {
+            parts_glob: PathBuf::from(glob::Pattern::escape(&path.to_string_lossy())),
+            parts_filter: None,
+            path,
+            format: Format::Tar(compression),
+            solid: false,
+            encrypted_headers: false,
+            headers,
+            stripped_dir: None,
+            renames: HashMap::new(),
+            preallocate: Preallocate::Auto,
+            fast_store_copy: false,
+            max_written: None,
+            stream_budget: false,
+            renamed: HashMap::new(),
+            password: None,
+            exclude: Vec::new(),
+            excluded: Vec::new(),
+            volumes: None,
+        })
+    }
+
+    /// Short description of the format and the flags found in the headers.
+    pub fn describe(&self) -> String {
+        let mut flags = vec![self.format.to_string()];
+        if self.solid {
+            flags.push("solid".into());
+        }
+        if self.encrypted_headers {
+            flags.push("encrypted headers".into());
+        }
+        let encrypted = self.headers.iter().filter(|header| header.encrypted).count();
+        if encrypted > 0 {
+            flags.push(format!("{} encrypted entries", encrypted));
+        }
+        let split = self.headers.iter().filter(|header| header.split).count();
+        if split > 0 {
+            flags.push(format!("{} split entries", split));
+        }
+        flags.join(", ")
+    }
+
+    /// Returns what the archive requires that can't be extracted, if anything. Checked before extraction so that it
+    /// fails with an explicit message rather than deep inside of unrar.
+    pub fn unsupported_feature(&self) -> Option<&'static str> {
+        let encrypted = self.encrypted_headers || self.headers.iter().any(|header| header.encrypted);
+        if encrypted && self.password.is_none() {
+            return Some("a password");
+        }
+        None
+    }
+
+    /// Leaves the entries matching `exclude` out of the extraction and of the checks of the destination. Returns how
+    /// many there are.
+    pub fn set_exclude(&mut self, exclude: Vec<Pattern>) -> usize {
+        let before = self.headers.len();
+        let (excluded, headers) = std::mem::take(&mut self.headers)
+            .into_iter()
+            .partition(|header| sidecar::is_excluded(&exclude, &header.filename));
+        self.headers = headers;
+        self.excluded = excluded
+            .into_iter()
+            .filter(|header: &Entry| header.is_file())
+            .map(|header| header.filename)
+            .collect();
+        self.exclude = exclude;
+        before - self.headers.len()
+    }
+
+    /// Names of the files left out of the extraction by the exclude patterns of the sidecar.
+    pub fn excluded(&self) -> &[PathBuf] {
+        &self.excluded
+    }
+
+    /// Where the entry `name` is found in `dest`, at the path it was renamed to by something else if it was.
+    pub fn extracted_path(&self, dest: &Path, name: &Path) -> PathBuf {
+        match self.renamed.get(name) {
+            Some(renamed) => renamed.path.clone(),
+            None => dest.join(name),
+        }
+    }
+
+    fn is_excluded(&self, name: &Path) -> bool {
+        sidecar::is_excluded(&self.exclude, name)
+    }
+
+    /// When every entry lives under a single top-level directory named like the directory holding the archive, strip
+    /// that directory from the entries so that its content gets extracted directly into the destination. Returns the
+    /// stripped directory, archives with mixed top-level entries are left untouched.
+    pub fn flatten_single_dir(&mut self) -> Option<PathBuf> {
+        let mut top_level = None;
+        for header in &self.headers {
+            let first = match header.filename.components().next() {
+                Some(Component::Normal(first)) => first,
+                _ => return None,
+            };
+            // A file at the top-level means there isn't a single directory to strip.
+            if header.is_file() && header.filename.components().count() == 1 {
+                return None;
+            }
+            match top_level {
+                None => top_level = Some(first),
+                Some(top_level) if top_level != first => return None,
+                Some(_) => {}
+            }
+        }
+
+        let top_level = PathBuf::from(top_level?);
+        let parent_name = self.path.parent()?.file_name()?;
+        if normalize_name(&top_level.to_string_lossy()) != normalize_name(&parent_name.to_string_lossy()) {
+            return None;
+        }
+
+        for header in &mut self.headers {
+            header.filename = header.filename.strip_prefix(&top_level).unwrap().to_path_buf();
+        }
+        self.stripped_dir = Some(top_level.clone());
+        Some(top_level)
+    }
+
+    /// Destination paths of the entries that exceed the file name or path length limits.
+    pub fn long_names(&self, dest: &Path) -> Vec<PathBuf> {
+        self.headers
+            .iter()
+            .map(|header| dest.join(&header.filename))
+            .filter(|path| longnames::too_long(path))
+            .collect()
+    }
+
+    /// Shortens the names of the entries with components over the file name limit. Returns the renamed entries, along
+    /// with their new name.
+    pub fn shorten_long_names(&mut self) -> Vec<(PathBuf, PathBuf)> {
+        let mut renamed = Vec::new();
+        for header in &mut self.headers {
+            let short = longnames::shorten(&header.filename);
+            if short != header.filename {
+                let name = std::mem::replace(&mut header.filename, short.clone());
+                self.renames.insert(name.clone(), short.clone());
+                renamed.push((name, short));
+            }
+        }
+        renamed
+    }
+
+    /// Path relative to the destination of an entry named `name` inside the archive.
+    fn output_name(&self, name: &Path) -> PathBuf {
+        let name = match &self.stripped_dir {
+            Some(dir) => name.strip_prefix(dir).unwrap_or(name),
+            None => name,
+        };
+        self.renames.get(name).cloned().unwrap_or_else(|| name.to_path_buf())
+    }
+
+    /// An archive is empty when it has no file entries, only directories or nothing at all.
+    pub fn is_empty(&self) -> bool {
+        !self.headers.iter().any(|header| header.is_file())
+    }
+
+    pub fn unpacked_size(&self) -> u64 {
+        self.headers.iter().map(|header| header.unpacked_size).sum()
+    }
+
+    /// Size of the archive on disk, all parts included. The unrar bindings don't expose the packed size of individual
+    /// entries, so this includes the archive headers as well.
+    pub fn packed_size(&self) -> anyhow::Result<u64> {
+        let mut total = 0;
+        for part in self.list_parts()? {
+            let len = part.metadata().context("stat part")?.len();
+            log::debug!("'{}' is {}", part.display(), format_size(len));
+            total += len;
+        }
+        Ok(total)
+    }
+
+    pub fn is_already_extracted(&self, dest: &Path) -> anyhow::Result<bool> {
+        Ok(self.changed_entry(dest)?.is_none())
+    }
+
+    /// Points the already-extracted check of renamed entries at their current path.
+    pub fn set_renamed(&mut self, renamed: HashMap<PathBuf, Renamed>) {
+        self.renamed = renamed;
+    }
+
+    /// Points the check of more entries at another path, the ones `dest` stores under another spelling.
+    pub fn add_renamed(&mut self, renamed: HashMap<PathBuf, Renamed>) {
+        self.renamed.extend(renamed);
+    }
+
+    pub fn renamed(&self, name: &Path) -> Option<&Renamed> {
+        self.renamed.get(name)
+    }
+
+    /// The first entry missing from `dest` or with another size there, `None` when the archive is extracted. Renamed
+    /// entries are looked for under their current path, with their size as mapped.
+    pub fn changed_entry(&self, dest: &Path) -> anyhow::Result<Option<&Path>> {
+        for header in self.headers.iter() {
+            let check = self.check_entry(dest, header)?;
+            if check.matches() {
+                continue;
+            }
+            match (check.expected, check.found) {
+                (_, Found::Missing) => log::debug!("'{}' not found in destination", check.path.display()),
+                (None, _) => log::debug!("'{}' is not a directory in destination", header.filename.display()),
+                (Some(want), Found::File(got)) => {
+                    log::debug!("'{}' size mismatch, got {} want {}", check.path.display(), got, want)
+                }
+                (Some(_), Found::Directory) => log::debug!("'{}' is a directory in destination", check.path.display()),
+            }
+            return Ok(Some(&header.filename));
+        }
+        Ok(None)
+    }
+
+    /// What `dest` holds for the entry `header`.
+    pub fn check_entry<'a>(&self, dest: &Path, header: &'a Entry) -> io::Result<EntryCheck<'a>> {
+        let renamed = self.renamed.get(&header.filename);
+        let path = renamed.map_or_else(|| dest.join(&header.filename), |renamed| renamed.path.clone());
+        let expected = match header.is_directory() {
+            true => None,
+            false => Some(renamed.and_then(|renamed| renamed.size).unwrap_or(header.unpacked_size)),
+        };
+        let found = match fs::metadata(&path) {
+            Ok(md) if md.is_dir() => Found::Directory,
+            Ok(md) => Found::File(md.len()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Found::Missing,
+            Err(e) => return Err(e),
+        };
+        Ok(EntryCheck {
+            name: &header.filename,
+            path,
+            expected,
+            found,
+        })
+    }
+
+    /// Whether the extracted files are allocated up front, rar archives are extracted through [`RarStream`] for it.
+    pub fn set_preallocate(&mut self, preallocate: Preallocate) {
+        self.preallocate = preallocate;
+    }
+
+    /// Copies the entries stored without compression out of their volume, checked against the CRC32 of their header.
+    /// The others are extracted by unrar as usual.
+    pub fn set_fast_store_copy(&mut self, enabled: bool) {
+        self.fast_store_copy = enabled;
+    }
+
+    /// Aborts the extraction once it wrote more than `max` bytes, counted as the files are written. The file it got to
+    /// is removed. Unrar writes the files of rar archives whole, they're counted once written unless
+    /// [`set_stream_budget`](Self::set_stream_budget) is set.
+    pub fn set_max_written(&mut self, max: Option<u64>) {
+        self.max_written = max;
+    }
+
+    pub fn max_written(&self) -> Option<u64> {
+        self.max_written
+    }
+
+    /// Extracts rar archives through [`RarStream`] so that `max_written` stops them within the file being written.
+    pub fn set_stream_budget(&mut self, enabled: bool) {
+        self.stream_budget = enabled;
+    }
+
+    pub fn stream_budget(&self) -> bool {
+        self.stream_budget
+    }
+
+    /// The password the archive was opened with.
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    /// Extracts every entry into `dest`. `on_extracted` is called with the size actually written for each file, an
+    /// error aborts the extraction.
+    pub fn extract_into(
+        &self,
+        dest: &Path,
+        on_extracted: impl FnMut(&Path, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        match self.format {
+            Format::Rar4 | Format::Rar5
+                if self.preallocate.streams_rar() || self.fast_store_copy || self.stream_budget =>
+            {
+                self.extract_rar_streamed(dest, on_extracted)
+            }
+            Format::Rar4 | Format::Rar5 => self.extract_rar(dest, on_extracted),
+            #[cfg(feature = "zip")]
+            Format::Zip => self.extract_zip(dest, on_extracted),
+            Format::Tar(compression) => self.extract_tar(compression, dest, on_extracted),
+        }
+    }
+
+    fn extract_rar(
+        &self,
+        dest: &Path,
+        mut on_extracted: impl FnMut(&Path, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut budget = Budget::new(self.max_written);
+        // The archive and the file being extracted.
+        let _fds = fds::acquire(2);
+        let archive = match &self.password {
+            Some(password) => unrar::Archive::with_password(self.unrar_path(), password),
+            None => unrar::Archive::new(self.unrar_path()),
+        };
+        let mut archive = archive.open_for_processing()?;
+        while let Some(header) = archive.read_header().map_err(|e| self.classify(e, dest, None))? {
+            let filename = self.output_name(&header.entry().filename);
+            let path = dest.join(&filename);
+            archive = if self.is_excluded(&header.entry().filename) {
+                header.skip()?
+            } else if header.entry().is_file() {
+                let extracted = if self.stripped_dir.is_some() || !self.renames.is_empty() {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent).map_err(|e| create_error(parent, e))?;
+                    }
+                    header.extract_to(&path)
+                } else {
+                    header.extract_with_base(dest)
+                };
+                let archive = extracted.map_err(|e| self.classify(e, &path, Some(&filename)))?;
+                let written = fs::metadata(&path).context("stat extracted file")?.len();
+                if budget.count(written).is_err() {
+                    return Err(budget.abort(&path, &filename).into());
+                }
+                on_extracted(&filename, written)?;
+                archive
+            } else if header.entry().is_directory() {
+                // Directories only get created implicitly for the files they contain, create them explicitly so that
+                // empty directories are extracted too.
+                fs::create_dir_all(&path).map_err(|e| create_error(&path, e))?;
+                header.skip()?
+            } else {
+                header.skip()?
+            };
+        }
+        Ok(())
+    }
+
+    /// Like `extract_rar`, with the files written by rarscan from the data unrar decompresses so that they can be
+    /// preallocated, counted against `max_written`, or copied straight out of their volume. Links are still left to
+    /// unrar.
+    fn extract_rar_streamed(
+        &self,
+        dest: &Path,
+        mut on_extracted: impl FnMut(&Path, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let stored = match self.fast_store_copy && self.password.is_none() {
+            true => store::stored_entries(&self.list_parts()?).unwrap_or_else(|e| {
+                log::debug!("-> Could not find the stored entries: {}", e);
+                HashMap::new()
+            }),
+            false => HashMap::new(),
+        };
+        let mut budget = Budget::new(self.max_written);
+        let _fds = fds::acquire(2);
+        let mut archive =
+            RarStream::open(self.unrar_path(), self.password.as_deref()).map_err(|e| self.classify(e, dest, None))?;
+        while let Some(entry) = archive.next_entry().map_err(|e| self.classify(e, dest, None))? {
+            // Checked when listed already, the volumes may have changed since.
+            if tarball::enclosed_name(&entry.filename).is_none() {
+                return Err(ExtractionError::Unknown {
+                    message: format!("rar entry {:?} has an unsafe path", entry.filename),
+                }
+                .into());
+            }
+            let filename = self.output_name(&entry.filename);
+            let path = dest.join(&filename);
+            if self.is_excluded(&entry.filename) {
+                archive.skip()?;
+                continue;
+            }
+            if entry.directory {
+                fs::create_dir_all(&path).map_err(|e| create_error(&path, e))?;
+                archive.skip()?;
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| create_error(parent, e))?;
+            }
+            if entry.link {
+                archive
+                    .extract_to(&path)
+                    .map_err(|e| self.classify(e, &path, Some(&filename)))?;
+                let written = fs::symlink_metadata(&path).context("stat extracted link")?.len();
+                on_extracted(&filename, written)?;
+                continue;
+            }
+            let mut out = prealloc::create(&path, entry.unpacked_size, self.preallocate.streams_rar())
+                .map_err(|e| create_error(&path, e))?;
+            let stored = stored
+                .get(&entry.filename)
+                .filter(|stored| stored.size == entry.unpacked_size);
+            let copied = match stored.map(|stored| store::copy_entry(stored, &mut out)) {
+                Some(Ok(())) => true,
+                Some(Err(e)) => {
+                    log::warn!(
+                        "-> Could not copy '{}' out of its volume, extracting it instead: {}",
+                        filename.display(),
+                        e
+                    );
+                    out.set_len(0)
+                        .and_then(|()| out.rewind())
+                        .map_err(|e| create_error(&path, e))?;
+                    false
+                }
+                None => false,
+            };
+            let mut written = 0;
+            let result = match stored.filter(|_| copied) {
+                Some(stored) => {
+                    written = stored.size;
+                    let _ = budget.count(stored.size);
+                    archive.skip().map_err(ReadError::Unrar)
+                }
+                None => archive.read(&mut |data| {
+                    out.write_all(data)?;
+                    written += data.len() as u64;
+                    budget.count(data.len() as u64)
+                }),
+            };
+            if budget.exceeded() {
+                drop(out);
+                return Err(budget.abort(&path, &filename).into());
+            }
+            prealloc::finish(&out, written).map_err(|e| create_error(&path, e))?;
+            match result {
+                Ok(()) => {}
+                Err(ReadError::Unrar(e)) => return Err(self.classify(e, &path, Some(&filename)).into()),
+                Err(ReadError::Write(e)) => return Err(create_error(&path, e).into()),
+            }
+            // What unrar would have done when writing the file.
+            if let Some(mtime) = entry.mtime {
+                out.set_modified(mtime).map_err(|e| create_error(&path, e))?;
+            }
+            #[cfg(unix)]
+            if let Some(mode) = entry.mode {
+                use std::os::unix::fs::PermissionsExt;
+                out.set_permissions(fs::Permissions::from_mode(mode))
+                    .map_err(|e| create_error(&path, e))?;
+            }
+            on_extracted(&filename, written)?;
+        }
+        Ok(())
+    }
+
+    /// Extracts the files `names`, all in the same directory of the archive, alone into `dir` under their file name.
+    /// For the dry-run to look inside of the archives nested in this one, rar archives only.
+    pub fn extract_entries(&self, names: &HashSet<PathBuf>, dir: &Path) -> anyhow::Result<()> {
+        if !matches!(self.format, Format::Rar4 | Format::Rar5) {
+            anyhow::bail!("only the entries of rar archives can be extracted alone");
+        }
+        let _fds = fds::acquire(2);
+        let mut archive =
+            RarStream::open(self.unrar_path(), self.password.as_deref()).map_err(|e| self.classify(e, dir, None))?;
+        while let Some(entry) = archive.next_entry().map_err(|e| self.classify(e, dir, None))? {
+            let filename = self.output_name(&entry.filename);
+            if entry.directory || entry.link || !names.contains(&filename) {
+                archive.skip()?;
+                continue;
+            }
+            let path = dir.join(filename.file_name().unwrap_or_default());
+            let mut out = File::create(&path).map_err(|e| create_error(&path, e))?;
+            match archive.read(&mut |data| out.write_all(data)) {
+                Ok(()) => {}
+                Err(ReadError::Unrar(e)) => return Err(self.classify(e, &path, Some(&filename)).into()),
+                Err(ReadError::Write(e)) => return Err(create_error(&path, e).into()),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "zip")]
+    fn extract_zip(
+        &self,
+        dest: &Path,
+        mut on_extracted: impl FnMut(&Path, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut budget = Budget::new(self.max_written);
+        let _fds = fds::acquire(2);
+        let mut archive = open_zip_archive(&self.path)?;
+        // The headers left once the excluded entries are taken out, in the order of the archive.
+        let mut headers = self.headers.iter();
+        for index in 0..archive.len() {
+            if !self.exclude.is_empty() {
+                let file = archive.by_index_raw(index).map_err(zip_error)?;
+                if file.enclosed_name().is_some_and(|name| self.is_excluded(&name)) {
+                    continue;
+                }
+            }
+            let header = headers.next().expect("a header per entry");
+            let path = dest.join(&header.filename);
+            if header.is_directory() {
+                fs::create_dir_all(&path).map_err(|e| create_error(&path, e))?;
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| create_error(parent, e))?;
+            }
+            let file = match &self.password {
+                Some(password) => archive.by_index_decrypt(index, password.as_bytes()),
+                None => archive.by_index(index),
+            };
+            let mut file = file.map_err(|e| match e {
+                ZipError::InvalidPassword if self.password.is_some() => ExtractionError::WrongPassword.into(),
+                e => zip_error(e),
+            })?;
+            let mut out = prealloc::create(&path, header.unpacked_size, self.preallocate.enabled())
+                .map_err(|e| create_error(&path, e))?;
+            let result = io::copy(
+                &mut file,
+                &mut Budgeted {
+                    out: &mut out,
+                    budget: &mut budget,
+                },
+            );
+            if budget.exceeded() {
+                drop(out);
+                return Err(budget.abort(&path, &header.filename).into());
+            }
+            let written = out.stream_position().map_err(|e| create_error(&path, e))?;
+            prealloc::finish(&out, written).map_err(|e| create_error(&path, e))?;
+            match result {
+                Ok(_) => {}
+                // The zip reader reports bad checksums and truncated data as invalid data.
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    return Err(ExtractionError::Corrupt {
+                        volume: Some(self.path.clone()),
+                        file: Some(header.filename.clone()),
+                    }
+                    .into())
+                }
+                Err(e) => return Err(create_error(&path, e).into()),
+            };
+            on_extracted(&header.filename, written)?;
+        }
+        Ok(())
+    }
+
+    /// Symlinks are created only when they point inside of `dest`, and hard links only to entries of the tarball,
+    /// others are skipped with a warning. Device files and fifos are skipped. The symlinks are created last, once
+    /// every file is written, and then only when they resolve inside of `dest` through the ones created before them:
+    /// nothing of the tarball is written through a link of the tarball.
+    fn extract_tar(
+        &self,
+        compression: Compression,
+        dest: &Path,
+        mut on_extracted: impl FnMut(&Path, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut budget = Budget::new(self.max_written);
+        let _fds = fds::acquire(2);
+        let corrupt = |file: Option<&Path>| ExtractionError::Corrupt {
+            volume: Some(self.path.clone()),
+            file: file.map(Path::to_path_buf),
+        };
+        let mut archive = tarball::open(&self.path, compression).map_err(|_| corrupt(None))?;
+        let mut symlinks = Vec::new();
+        for entry in archive.entries().map_err(|_| corrupt(None))? {
+            let mut entry = entry.map_err(|_| corrupt(None))?;
+            let name = entry.path().map_err(|_| corrupt(None))?;
+            let name = tarball::enclosed_name(&name).ok_or_else(|| corrupt(None))?;
+            if self.is_excluded(&name) {
+                continue;
+            }
+            let filename = self.output_name(&name);
+            let path = dest.join(&filename);
+            let kind = entry.header().entry_type();
+            if !kind.is_file() && !kind.is_dir() && !kind.is_symlink() && !kind.is_hard_link() {
+                log::debug!("Skipping special file '{}' of tarball.", name.display());
+                continue;
+            }
+            let parent_dir = filename.parent().unwrap_or(Path::new(""));
+            let checked = if kind.is_dir() { filename.as_path() } else { parent_dir };
+            if tarball::through_symlink(dest, checked) {
+                log::warn!(
+                    "-> Not extracting '{}' of tarball, it would be written through a symlink.",
+                    name.display()
+                );
+                continue;
+            }
+            if kind.is_dir() {
+                fs::create_dir_all(&path).map_err(|e| create_error(&path, e))?;
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).map_err(|e| create_error(parent, e))?;
+            }
+            if kind.is_symlink() || kind.is_hard_link() {
+                let target = entry.link_name().map_err(|_| corrupt(Some(&name)))?;
+                let target = target.ok_or_else(|| corrupt(Some(&name)))?.into_owned();
+                if kind.is_symlink() {
+                    symlinks.push((name, filename, target));
+                    continue;
+                }
+                self.extract_tar_link(&name, &filename, &target, false, dest, &mut on_extracted)?;
+                continue;
+            }
+            // Replaced rather than written through.
+            if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()) {
+                fs::remove_file(&path).map_err(|e| create_error(&path, e))?;
+            }
+            let size = entry.size();
+            let mut out =
+                prealloc::create(&path, size, self.preallocate.enabled()).map_err(|e| create_error(&path, e))?;
+            let mut written = 0;
+            let mut buf = vec![0; 64 * 1024];
+            let result = loop {
+                let len = match entry.read(&mut buf) {
+                    Ok(0) => break Ok(()),
+                    Ok(len) => len,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break Err(corrupt(Some(&name))),
+                };
+                if let Err(e) = out.write_all(&buf[..len]) {
+                    break Err(create_error(&path, e));
+                }
+                written += len as u64;
+                if budget.count(len as u64).is_err() {
+                    break Ok(());
+                }
+            };
+            if budget.exceeded() {
+                drop(out);
+                return Err(budget.abort(&path, &filename).into());
+            }
+            prealloc::finish(&out, written).map_err(|e| create_error(&path, e))?;
+            result?;
+            // A truncated tarball ends the entry early.
+            if written != size {
+                return Err(corrupt(Some(&name)).into());
+            }
+            if let Ok(mtime) = entry.header().mtime() {
+                let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime);
+                out.set_modified(mtime).map_err(|e| create_error(&path, e))?;
+            }
+            #[cfg(unix)]
+            if let Ok(mode) = entry.header().mode() {
+                use std::os::unix::fs::PermissionsExt;
+                // Without the setuid and setgid bits, which a tarball has no business handing out.
+                out.set_permissions(fs::Permissions::from_mode(mode & 0o1777))
+                    .map_err(|e| create_error(&path, e))?;
+            }
+            on_extracted(&filename, written)?;
+        }
+        for (name, filename, target) in symlinks {
+            self.extract_tar_link(&name, &filename, &target, true, dest, &mut on_extracted)?;
+        }
+        Ok(())
+    }
+
+    /// Creates the link `filename` of `dest` for the tar entry `name` pointing at `target`, replacing a file or a link
+    /// that was there. Links pointing outside of `dest` are skipped with a warning.
+    fn extract_tar_link(
+        &self,
+        name: &Path,
+        filename: &Path,
+        target: &Path,
+        symlink: bool,
+        dest: &Path,
+        on_extracted: &mut impl FnMut(&Path, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let path = dest.join(filename);
+        let parent_dir = filename.parent().unwrap_or(Path::new(""));
+        let source = match symlink {
+            true => Some(target.to_path_buf())
+                .filter(|_| tarball::link_within(filename, target))
+                .filter(|_| tarball::resolves_within(dest, &parent_dir.join(target))),
+            false => tarball::enclosed_name(target)
+                .map(|target| self.output_name(&target))
+                .filter(|target| !tarball::through_symlink(dest, target)),
+        };
+        let Some(source) = source.filter(|_| !tarball::through_symlink(dest, parent_dir)) else {
+            log::warn!(
+                "-> Not creating link '{}' to '{}', it points outside of the destination.",
+                name.display(),
+                target.display()
+            );
+            return Ok(());
+        };
+        if fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir()) {
+            log::warn!("-> Not creating link '{}', a directory is there.", name.display());
+            return Ok(());
+        }
+        let _ = fs::remove_file(&path);
+        match symlink {
+            true => make_symlink(&source, &path),
+            false => fs::hard_link(dest.join(&source), &path),
+        }
+        .map_err(|e| create_error(&path, e))?;
+        let written = fs::symlink_metadata(&path).context("stat extracted link")?.len();
+        on_extracted(filename, written)
+    }
+
+    /// Classifies an error of the unrar library, `path` is what was being written and `file` the entry being
+    /// extracted.
+    fn classify(&self, e: unrar::error::UnrarError, path: &Path, file: Option<&Path>) -> ExtractionError {
+        use unrar::error::{Code, When};
+
+        match e.code {
+            Code::BadData | Code::BadArchive => ExtractionError::Corrupt {
+                volume: (!unrar::Archive::new(&self.path).is_multipart()).then(|| self.path.clone()),
+                file: file.map(Path::to_path_buf),
+            },
+            Code::EOpen if e.when == When::Process => ExtractionError::MissingVolume {
+                name: missing_volume(&self.path),
+            },
+            Code::MissingPassword | Code::BadPassword => ExtractionError::WrongPassword,
+            Code::ERead => ExtractionError::ReadError {
+                path: self.path.clone(),
+                io_kind: None,
+            },
+            Code::ECreate | Code::EWrite => create_failure(path, probe_create(path)),
+            _ => ExtractionError::Unknown { message: e.to_string() },
+        }
+    }
+
+    /// The volumes of the set, the chain of a renamed set whatever their names.
+    pub fn list_parts(&self) -> anyhow::Result<Vec<PathBuf>> {
+        if let Some(volumes) = &self.volumes {
+            return Ok(volumes.volumes().to_vec());
+        }
+        glob_parts(&self.parts_glob, self.parts_filter.as_ref())
+    }
+
+    /// The volumes followed through their headers because some were renamed after another set.
+    pub fn renamed_volumes(&self) -> Option<&[PathBuf]> {
+        self.volumes
+            .as_ref()
+            .filter(|volumes| volumes.common_parent().is_none())
+            .map(LinkedChain::volumes)
+    }
+
+    /// The volumes followed through their headers across the directories below a common parent, with that parent.
+    pub fn spread_volumes(&self) -> Option<(&Path, &[PathBuf])> {
+        let volumes = self.volumes.as_ref()?;
+        Some((volumes.common_parent()?, volumes.volumes()))
+    }
+
+    /// What unrar opens, the link to the first volume of a renamed set.
+    fn unrar_path(&self) -> &Path {
+        self.volumes
+            .as_ref()
+            .map_or(self.path.as_path(), LinkedChain::first_link)
+    }
+}
+
+/// Parts of the set of the rar at `path`, for when it couldn't be opened. With the parts looked for across
+/// directories, the files named like its volumes in the directories next to its own too.
+pub fn list_set_parts(path: &Path, naming: &ArchiveNaming) -> anyhow::Result<Vec<PathBuf>> {
+    let mut parts = match naming.parts_glob(path) {
+        Some(glob) => glob_parts(&glob, naming.custom_part_pattern())?,
+        None => glob_parts(&unrar::Archive::new(path).all_parts(), None)?,
+    };
+    if naming.parts_across_dirs() {
+        parts.extend(volumes::sibling_volumes(path).context("list volumes across directories")?);
+        parts.sort();
+        parts.dedup();
+    }
+    Ok(parts)
+}
+
+fn glob_parts(parts_glob: &Path, parts_filter: Option<&Regex>) -> anyhow::Result<Vec<PathBuf>> {
+    let mut results = Vec::new();
+    for entry in glob::glob(&parts_glob.to_string_lossy()).context("glob parts")? {
+        let entry = entry?;
+        if let Some(filter) = parts_filter {
+            let file_name = entry.file_name().unwrap_or_default().to_string_lossy();
+            if !filter.is_match(&file_name) {
+                continue;
+            }
+        }
+        results.push(entry);
+    }
+    Ok(results)
+}
+
+/// Lowercase alphanumerics only, so that `Release.Name` matches `release name` or `Release_Name`.
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Lists the entries of the rar at `path` opened through `open`, a link to it or to the first volume of its chain.
+/// Returns whether it's solid and has encrypted headers, with the entries.
+fn list_rar(path: &Path, open: &Path, password: Option<&str>) -> anyhow::Result<(bool, bool, Vec<Entry>)> {
+    let mut headers = Vec::new();
+    let _fds = fds::acquire(1);
+    let archive = match password {
+        Some(password) => unrar::Archive::with_password(open, password),
+        None => unrar::Archive::new(open),
+    };
+    let archive = archive.open_for_listing().map_err(|e| match e.code {
+        unrar::error::Code::MissingPassword | unrar::error::Code::BadPassword => ExtractionError::WrongPassword.into(),
+        _ => anyhow::Error::new(e),
+    })?;
+    let solid = archive.is_solid();
+    let encrypted_headers = archive.has_encrypted_headers();
+    for header in archive {
+        // Listing goes through every volume, a missing one fails here already.
+        let header = header.map_err(|e| match e.code {
+            unrar::error::Code::EOpen => ExtractionError::MissingVolume {
+                name: missing_volume(path),
+            }
+            .into(),
+            unrar::error::Code::ERead => ExtractionError::ReadError {
+                path: path.to_path_buf(),
+                io_kind: None,
+            }
+            .into(),
+            unrar::error::Code::MissingPassword | unrar::error::Code::BadPassword => {
+                ExtractionError::WrongPassword.into()
+            }
+            _ => anyhow::Error::new(e),
+        })?;
+        // Written by rarscan itself when streamed, nothing may land outside of the destination.
+        if tarball::enclosed_name(&header.filename).is_none() {
+            return Err(ExtractionError::Unknown {
+                message: format!("rar entry {:?} has an unsafe path", header.filename),
+            }
+            .into());
+        }
+        headers.push(Entry {
+            directory: header.is_directory(),
+            encrypted: header.is_encrypted(),
+            split: header.is_split(),
+            crc: (!header.is_split() && header.file_crc != 0).then_some(header.file_crc),
+            filename: header.filename,
+            unpacked_size: header.unpacked_size,
+        });
+        // Lets the tests of debug builds check that a panic in the middle of a listing fails that archive alone.
+        #[cfg(debug_assertions)]
+        if std::env::var_os("RARSCAN_TEST_PANIC_LISTING").is_some_and(|name| Some(name.as_os_str()) == path.file_name())
+        {
+            panic!("listing '{}' panicked for the test", path.display());
+        }
+    }
+    Ok((solid, encrypted_headers, headers))
+}
+
+/// First volume of the set of `path` missing on disk, for the `.partN.rar` and `.rNN` naming schemes.
+fn missing_volume(path: &Path) -> Option<PathBuf> {
+    let archive = unrar::Archive::new(path);
+    if archive.is_multipart() {
+        return (1..10000)
+            .map_while(|n| archive.nth_part(n))
+            .find(|part| !part.exists());
+    }
+    if !path.with_extension("r00").exists() {
+        return None;
+    }
+    (0..100)
+        .map(|n| path.with_extension(format!("r{:02}", n)))
+        .find(|part| !part.exists())
+}
+
+#[cfg(unix)]
+pub fn make_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+pub fn make_symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The bytes an extraction may write, counted as they're written rather than once a file is complete: a single entry
+/// holding more than its header declares would fill the disk before that.
+struct Budget {
+    max: Option<u64>,
+    written: u64,
+}
+
+impl Budget {
+    fn new(max: Option<u64>) -> Budget {
+        Budget { max, written: 0 }
+    }
+
+    fn exceeded(&self) -> bool {
+        self.max.is_some_and(|max| self.written > max)
+    }
+
+    /// Counts `len` more bytes, an error once they exceed the budget.
+    fn count(&mut self, len: u64) -> io::Result<()> {
+        self.written += len;
+        match self.exceeded() {
+            true => Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                "more than the archive declares",
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Removes `path`, the file of the entry `file` the extraction got to when it exceeded the budget.
+    fn abort(&self, path: &Path, file: &Path) -> ExtractionError {
+        let _ = fs::remove_file(path);
+        ExtractionError::Oversized {
+            file: file.to_path_buf(),
+            written: self.written,
+        }
+    }
+}
+
+/// Writes to `out`, counted against `budget`.
+#[cfg(feature = "zip")]
+struct Budgeted<'a, W> {
+    out: W,
+    budget: &'a mut Budget,
+}
+
+#[cfg(feature = "zip")]
+impl<W: Write> Write for Budgeted<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.out.write(buf)?;
+        self.budget.count(len as u64)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn read_error(path: &Path, e: io::Error) -> ExtractionError {
+    ExtractionError::ReadError {
+        path: path.to_path_buf(),
+        io_kind: Some(e.kind()),
+    }
+}
+
+fn create_error(path: &Path, e: io::Error) -> ExtractionError {
+    create_failure(path, Some(e.kind()))
+}
+
+/// Why `path` couldn't be created or written, told apart when it or its directory belongs to another user.
+fn create_failure(path: &Path, io_kind: Option<io::ErrorKind>) -> ExtractionError {
+    let denied = matches!(
+        io_kind,
+        Some(io::ErrorKind::PermissionDenied | io::ErrorKind::AlreadyExists)
+    );
+    match denied.then(|| ownership::foreign_owner(path)).flatten() {
+        Some((path, uid)) => ExtractionError::NotOwned {
+            path,
+            owner: privileges::describe_uid(uid),
+        },
+        None => ExtractionError::CreateError {
+            path: path.to_path_buf(),
+            io_kind,
+        },
+    }
+}
+
+/// Reason why `path` can't be created, found by creating it. unrar only reports that it couldn't.
+fn probe_create(path: &Path) -> Option<io::ErrorKind> {
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Some(e.kind());
+        }
+    }
+    match File::options().write(true).create_new(true).open(path) {
+        Ok(_) => {
+            let _ = fs::remove_file(path);
+            None
+        }
+        Err(e) => Some(e.kind()),
+    }
+}
+
+#[cfg(feature = "zip")]
+fn open_zip_archive(path: &Path) -> anyhow::Result<ZipArchive<File>> {
+    let file = File::open(path).context("open zip")?;
+    ZipArchive::new(file).map_err(zip_error)
+}
+
+/// Turns zip errors into messages that say what isn't supported rather than just failing.
+#[cfg(feature = "zip")]
+fn zip_error(e: ZipError) -> anyhow::Error {
+    match e {
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) | ZipError::InvalidPassword => {
+            anyhow::anyhow!("encrypted zip archives are not supported")
+        }
+        ZipError::UnsupportedArchive(reason) => anyhow::anyhow!("unsupported zip archive: {}", reason),
+        ZipError::CompressionMethodNotSupported(method) => {
+            anyhow::anyhow!(
+                "zip compression method {} is not supported, only stored and deflate are",
+                method
+            )
+        }
+        ZipError::InvalidArchive(reason) => anyhow::anyhow!("invalid zip archive: {}", reason),
+        e => anyhow::Error::new(e).context("zip"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_rar_format() {
+        assert_eq!(Format::detect_rar(b"Rar!\x1a\x07\x00\xcf\x90"), Some(Format::Rar4));
+        assert_eq!(Format::detect_rar(b"Rar!\x1a\x07\x01\x00\x33"), Some(Format::Rar5));
+        assert_eq!(Format::detect_rar(b"Rar!\x1a\x07"), None);
+        assert_eq!(Format::detect_rar(b"PK\x03\x04"), None);
+    }
+
+    #[test]
+    fn formats_of_the_fixtures() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let naming = ArchiveNaming::default();
+        let rar4 = Archive::open(fixtures.join("plain.rar"), &naming).unwrap();
+        assert_eq!(rar4.format, Format::Rar4);
+        assert_eq!(rar4.unsupported_feature(), None);
+        let rar5 = Archive::open(fixtures.join("rar5.rar"), &naming).unwrap();
+        assert_eq!(rar5.format, Format::Rar5);
+        assert_eq!(rar5.describe(), "RAR5");
+        assert_eq!(rar5.headers[0].filename, Path::new("dir/hello.txt"));
+        assert_eq!(rar5.unsupported_feature(), None);
+        let encrypted = Archive::open(fixtures.join("encrypted.rar"), &naming).unwrap();
+        assert_eq!(encrypted.unsupported_feature(), Some("a password"));
+
+        let Err(e) = Archive::open(fixtures.join("future.rar"), &naming) else {
+            panic!("opened a rar of an unknown version");
+        };
+        match e.downcast_ref() {
+            Some(ExtractionError::Unknown { message }) => assert!(message.contains("newer than RAR5"), "{}", message),
+            _ => panic!("unexpected error: {:#}", e),
+        }
+    }
+
+    fn archive(solid: bool, encrypted_headers: bool, entries: &[(bool, bool)]) -> Archive {
+        Archive {
+            path: PathBuf::from("/a/b.rar"),
+            format: Format::Rar5,
+            solid,
+            encrypted_headers,
+            headers: entries
+                .iter()
+                .map(|&(encrypted, split)| Entry {
+                    filename: PathBuf::from("f"),
+                    unpacked_size: 1,
+                    directory: false,
+                    encrypted,
+                    split,
+                    crc: None,
+                })
+                .collect(),
+            parts_glob: PathBuf::from("/a/b.rar"),
+            parts_filter: None,
+            stripped_dir: None,
+            renames: HashMap::new(),
+            preallocate: Preallocate::Auto,
+            fast_store_copy: false,
+            max_written: None,
+            stream_budget: false,
+            renamed: HashMap::new(),
+            password: None,
+            exclude: Vec::new(),
+            excluded: Vec::new(),
+            volumes: None,
+        }
+    }
+
+    #[test]
+    fn unsupported_features() {
+        assert_eq!(archive(true, false, &[(false, true)]).unsupported_feature(), None);
+        assert_eq!(archive(false, true, &[]).unsupported_feature(), Some("a password"));
+        assert_eq!(
+            archive(false, false, &[(false, false), (true, false)]).unsupported_feature(),
+            Some("a password")
+        );
+    }
+
+    #[test]
+    fn extraction_stops_within_the_file_past_the_budget() {
+        let dir = std::env::temp_dir().join(format!("rarscan-archive-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("out")).unwrap();
+        let path = dir.join("a.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for (name, size) in [("small.bin", 1000), ("large.bin", 300_000)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(size as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, &vec![7; size][..]).unwrap();
+        }
+        builder.into_inner().unwrap();
+
+        let mut archive = Archive::open(&path, &ArchiveNaming::default()).unwrap();
+        archive.set_max_written(Some(100_000));
+        let mut extracted = Vec::new();
+        let e = archive
+            .extract_into(&dir.join("out"), |file, _| {
+                extracted.push(file.to_path_buf());
+                Ok(())
+            })
+            .unwrap_err();
+        match e.downcast_ref() {
+            Some(ExtractionError::Oversized { file, written }) => {
+                assert_eq!(file, Path::new("large.bin"));
+                assert!(*written < 200_000, "wrote {}", written);
+            }
+            _ => panic!("unexpected error: {:#}", e),
+        }
+        assert_eq!(extracted, [PathBuf::from("small.bin")]);
+        assert!(!dir.join("out/large.bin").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rar_files_past_the_budget_are_removed() {
+        let dir = std::env::temp_dir().join(format!("rarscan-archive-budget-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("plain.rar");
+        fs::write(&path, include_bytes!("../fixtures/plain.rar")).unwrap();
+
+        for stream_budget in [false, true] {
+            let out = dir.join(format!("out-{}", stream_budget));
+            fs::create_dir_all(&out).unwrap();
+            let mut archive = Archive::open(&path, &ArchiveNaming::default()).unwrap();
+            archive.set_max_written(Some(0));
+            archive.set_stream_budget(stream_budget);
+            let e = archive.extract_into(&out, |_, _| Ok(())).unwrap_err();
+            match e.downcast_ref() {
+                Some(ExtractionError::Oversized { file, .. }) => assert!(!out.join(file).exists()),
+                _ => panic!("unexpected error: {:#}", e),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn describe_flags() {
+        assert_eq!(archive(false, false, &[(false, false)]).describe(), "RAR5");
+        assert_eq!(
+            archive(true, false, &[(true, true), (false, true)]).describe(),
+            "RAR5, solid, 1 encrypted entries, 2 split entries"
+        );
+    }
+}
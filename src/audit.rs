@@ -0,0 +1,194 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use regex::Regex;
+use serde_json::json;
+
+use crate::{changelog::FileState, datetime};
+
+/// How the lines of the audit log are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AuditFormat {
+    /// A JSON object per line.
+    Json,
+    /// Tab-separated fields, with tabs, newlines and backslashes in paths escaped with a backslash.
+    Tsv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Remove,
+    Overwrite,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Remove => "remove",
+            Action::Overwrite => "overwrite",
+        }
+    }
+}
+
+/// Permanent record of the files removed and overwritten, appended to and never truncated. A line is written and
+/// flushed before the action it records is taken, a crash in between leaves a record of something that wasn't done
+/// rather than the opposite.
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    format: AuditFormat,
+    fsync: bool,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, format: AuditFormat, fsync: bool) -> anyhow::Result<AuditLog> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("open audit log '{}'", path.display()))?;
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            file,
+            format,
+            fsync,
+        })
+    }
+
+    /// Records that `path` is about to be removed or overwritten because of `archive`, by `rule`.
+    pub fn record(&mut self, action: Action, path: &Path, archive: Option<&Path>, rule: &str) -> anyhow::Result<()> {
+        let line = format_line(
+            self.format,
+            SystemTime::now(),
+            action,
+            path,
+            FileState::of(path),
+            archive,
+            rule,
+        );
+        // A single write, appends of a line are never interleaved with other writers.
+        self.file
+            .write_all(line.as_bytes())
+            .with_context(|| format!("append to audit log '{}'", self.path.display()))?;
+        if self.fsync {
+            self.file
+                .sync_data()
+                .with_context(|| format!("sync audit log '{}'", self.path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+fn format_line(
+    format: AuditFormat,
+    now: SystemTime,
+    action: Action,
+    path: &Path,
+    state: Option<FileState>,
+    archive: Option<&Path>,
+    rule: &str,
+) -> String {
+    let size = state.map(|state| state.size);
+    let mtime = state.and_then(|state| state.mtime).map(datetime::rfc3339);
+    match format {
+        AuditFormat::Json => {
+            let mut line = json!({
+                "time": datetime::rfc3339(now),
+                "action": action.as_str(),
+                "path": path.to_string_lossy(),
+                "size": size,
+                "mtime": mtime,
+                "archive": archive.map(|archive| archive.to_string_lossy()),
+                "rule": rule,
+            })
+            .to_string();
+            line.push('\n');
+            line
+        }
+        AuditFormat::Tsv => {
+            let fields = [
+                datetime::rfc3339(now),
+                action.as_str().to_string(),
+                escape(&path.to_string_lossy()),
+                size.map(|size| size.to_string()).unwrap_or_default(),
+                mtime.unwrap_or_default(),
+                archive
+                    .map(|archive| escape(&archive.to_string_lossy()))
+                    .unwrap_or_default(),
+                escape(rule),
+            ];
+            format!("{}\n", fields.join("\t"))
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Prints the lines of the audit log at `path` matching `pattern`, returns how many did.
+pub fn grep(path: &Path, pattern: &Regex) -> anyhow::Result<u64> {
+    let file = File::open(path).with_context(|| format!("open audit log '{}'", path.display()))?;
+    let mut matched = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("read audit log '{}'", path.display()))?;
+        if pattern.is_match(&line) {
+            println!("{}", line);
+            matched += 1;
+        }
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn lines_hold_every_field() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let state = Some(FileState {
+            size: 42,
+            mtime: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+        });
+        let line = |format, path: &str, state| {
+            format_line(
+                format,
+                now,
+                Action::Remove,
+                Path::new(path),
+                state,
+                Some(Path::new("/tv/show.rar")),
+                "age",
+            )
+        };
+        assert_eq!(
+            line(AuditFormat::Tsv, "/tv/show\t1.r00", state),
+            "2023-11-14T22:13:20Z\tremove\t/tv/show\\t1.r00\t42\t2020-09-13T12:26:40Z\t/tv/show.rar\tage\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&line(AuditFormat::Json, "/tv/show.r00", None)).unwrap();
+        assert_eq!(json["action"], "remove");
+        assert_eq!(json["path"], "/tv/show.r00");
+        assert_eq!(json["size"], serde_json::Value::Null);
+        assert_eq!(json["archive"], "/tv/show.rar");
+        assert_eq!(json["rule"], "age");
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use time::{Date, Month};
+
+use crate::{datetime::TimeZone, dedup, format_size, sidecar};
+
+/// File of a backup holding the fingerprint of the first part of the archive, written once every part is copied. A
+/// backup without one is incomplete.
+const MARKER: &str = ".rarscan-backup";
+/// Suffix of a part being copied, renamed once complete.
+const PARTIAL_SUFFIX: &str = ".rarscan-partial";
+const COPY_CHUNK: usize = 1 << 20;
+/// How often the progress of a copy is logged.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Copies of the parts of archives made by --backup-parts-to before they are extracted or removed, in
+/// `DIR/YYYY-MM-DD/SET/`, dated in the zone of --timezone.
+pub struct Backups {
+    dir: PathBuf,
+    zone: TimeZone,
+}
+
+/// What backing up the parts of an archive did.
+#[derive(Debug, PartialEq)]
+pub enum Backup {
+    /// An identical backup exists already in this directory.
+    Existing(PathBuf),
+    /// The parts were backed up into this directory, `copied` bytes streamed and `reflinked` bytes shared with the
+    /// original.
+    Made { dir: PathBuf, copied: u64, reflinked: u64 },
+}
+
+impl Backups {
+    pub fn new(dir: PathBuf, zone: TimeZone) -> Backups {
+        Backups { dir, zone }
+    }
+
+    /// Backs up `parts`, the files of `archive` about to be extracted or removed, unless a backup of the same archive
+    /// holds them with the same sizes already.
+    pub fn back_up(&self, archive: &Path, parts: &[PathBuf], now: SystemTime) -> io::Result<Backup> {
+        let set = sidecar::set_name(archive);
+        let fingerprint = match archive.exists() {
+            true => dedup::Fingerprint::of_part(archive)?.to_string(),
+            false => String::new(),
+        };
+        for dir in self.dated_dirs()?.into_iter().rev() {
+            let candidate = dir.join(&set);
+            if read_marker(&candidate).as_deref() == Some(fingerprint.as_str()) && holds(&candidate, parts) {
+                return Ok(Backup::Existing(candidate));
+            }
+        }
+
+        // Another archive of the same name backed up the same day goes next to it.
+        let day = self.dir.join(self.date_of(now).to_string());
+        let mut n = 1;
+        let target = loop {
+            let candidate = match n {
+                1 => day.join(&set),
+                n => day.join(format!("{}.{}", set, n)),
+            };
+            match read_marker(&candidate) {
+                Some(marker) if marker != fingerprint => n += 1,
+                _ => break candidate,
+            }
+        };
+        fs::create_dir_all(&target)?;
+        match fs::remove_file(target.join(MARKER)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let (mut copied, mut reflinked) = (0, 0);
+        for part in parts {
+            let dest = target.join(part.file_name().unwrap_or_default());
+            if same_size(part, &dest) {
+                continue;
+            }
+            let size = fs::metadata(part)?.len();
+            if copy_part(part, &dest)? {
+                reflinked += size;
+            } else {
+                copied += size;
+            }
+        }
+        let mut marker = File::create(target.join(MARKER))?;
+        marker.write_all(fingerprint.as_bytes())?;
+        marker.sync_all()?;
+        Ok(Backup::Made {
+            dir: target,
+            copied,
+            reflinked,
+        })
+    }
+
+    /// Whether `path` is a backup.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.dir)
+    }
+
+    /// Removes the backups of the days more than `retention` before `now`, or lists them in a dry-run.
+    pub fn purge(&self, retention: Duration, now: SystemTime, dry_run: bool) -> io::Result<Vec<PathBuf>> {
+        let oldest = self.date_of(now - retention);
+        let mut purged = Vec::new();
+        for dir in self.dated_dirs()? {
+            if parse_date(&dir).is_some_and(|date| date < oldest) {
+                if !dry_run {
+                    fs::remove_dir_all(&dir)?;
+                }
+                purged.push(dir);
+            }
+        }
+        Ok(purged)
+    }
+
+    fn date_of(&self, t: SystemTime) -> Date {
+        self.zone.to_local(t).date()
+    }
+
+    /// The directories of the days with backups, oldest first.
+    fn dated_dirs(&self) -> io::Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut dirs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() && parse_date(&path).is_some() {
+                dirs.push(path);
+            }
+        }
+        dirs.sort();
+        Ok(dirs)
+    }
+}
+
+/// The day of a directory named `YYYY-MM-DD`.
+fn parse_date(dir: &Path) -> Option<Date> {
+    let mut fields = dir.file_name()?.to_str()?.splitn(3, '-');
+    let year = fields.next()?.parse().ok()?;
+    let month = Month::try_from(fields.next()?.parse::<u8>().ok()?).ok()?;
+    Date::from_calendar_date(year, month, fields.next()?.parse().ok()?).ok()
+}
+
+fn read_marker(dir: &Path) -> Option<String> {
+    fs::read_to_string(dir.join(MARKER)).ok()
+}
+
+/// Whether `dir` holds a file of the same name and size for each of `parts`.
+fn holds(dir: &Path, parts: &[PathBuf]) -> bool {
+    parts
+        .iter()
+        .all(|part| same_size(part, &dir.join(part.file_name().unwrap_or_default())))
+}
+
+fn same_size(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.len() == b.len(),
+        _ => false,
+    }
+}
+
+/// Copies `from` to `to` with its mtime, a reflink when the filesystem allows it. Returns whether it was one.
+fn copy_part(from: &Path, to: &Path) -> io::Result<bool> {
+    let mut partial = to.as_os_str().to_owned();
+    partial.push(PARTIAL_SUFFIX);
+    let partial = PathBuf::from(partial);
+    match fs::remove_file(&partial) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let reflinked = match dedup::reflink(from, &partial) {
+        Ok(()) => true,
+        Err(e) => {
+            log::debug!("-> No reflink of '{}': {}", from.display(), e);
+            stream_copy(from, &partial)?;
+            false
+        }
+    };
+    let copy = File::options().write(true).open(&partial)?;
+    copy.set_modified(fs::metadata(from)?.modified()?)?;
+    copy.sync_all()?;
+    fs::rename(&partial, to)?;
+    Ok(reflinked)
+}
+
+/// Copies `from` to `to` a chunk at a time, logging how far it got now and then.
+fn stream_copy(from: &Path, to: &Path) -> io::Result<()> {
+    let mut src = File::open(from)?;
+    let mut dst = File::create(to)?;
+    let total = src.metadata()?.len();
+    let mut buf = vec![0; COPY_CHUNK];
+    let mut done = 0;
+    let mut logged = Instant::now();
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        dst.write_all(&buf[..n])?;
+        done += n as u64;
+        if logged.elapsed() >= PROGRESS_INTERVAL {
+            log::info!(
+                "-> Backing up '{}': {} of {}.",
+                from.display(),
+                format_size(done),
+                format_size(total)
+            );
+            logged = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn backups_are_reused_and_purged_by_day() {
+        let root = std::env::temp_dir().join(format!("rarscan-backup-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let downloads = root.join("downloads");
+        fs::create_dir_all(&downloads).unwrap();
+        let parts = [downloads.join("show.rar"), downloads.join("show.r00")];
+        fs::write(&parts[0], b"first part").unwrap();
+        fs::write(&parts[1], b"second part").unwrap();
+        let backups = Backups::new(root.join("backups"), TimeZone::UTC);
+        // 2023-11-14.
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let dir = root.join("backups/2023-11-14/show");
+        let Backup::Made {
+            dir: made,
+            copied,
+            reflinked,
+        } = backups.back_up(&parts[0], &parts, now).unwrap()
+        else {
+            panic!("no backup made");
+        };
+        assert_eq!(made, dir);
+        assert_eq!(copied + reflinked, 21);
+        assert_eq!(fs::read(dir.join("show.r00")).unwrap(), b"second part");
+        assert!(!dir.join("show.r00.rarscan-partial").exists());
+        assert_eq!(
+            backups.back_up(&parts[0], &parts, now + DAY).unwrap(),
+            Backup::Existing(dir.clone())
+        );
+
+        // Another archive of the same name.
+        fs::write(&parts[0], b"other first part").unwrap();
+        let Backup::Made { dir: made, .. } = backups.back_up(&parts[0], &parts[..1], now).unwrap() else {
+            panic!("no backup made");
+        };
+        assert_eq!(made, root.join("backups/2023-11-14/show.2"));
+
+        assert!(backups.purge(DAY, now + DAY, false).unwrap().is_empty());
+        assert_eq!(
+            backups.purge(DAY, now + 2 * DAY, false).unwrap(),
+            [root.join("backups/2023-11-14")]
+        );
+        assert!(!dir.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// IO errors this close together count toward suspending a device even with successes in between.
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Stops the work on a device after repeated IO errors. A dying disk fails every archive on it with EIO, each one
+/// taking long and making the disk worse.
+pub struct DeviceBreaker {
+    /// IO errors in a row that suspend a device, twice as many within the window do too.
+    threshold: usize,
+    devices: HashMap<u64, DeviceErrors>,
+}
+
+#[derive(Default)]
+struct DeviceErrors {
+    consecutive: usize,
+    recent: VecDeque<Instant>,
+    total: usize,
+    suspended: bool,
+}
+
+impl DeviceBreaker {
+    pub fn new(threshold: usize) -> DeviceBreaker {
+        DeviceBreaker {
+            threshold: threshold.max(1),
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Counts an IO error on `device` at `now`, true when it suspends the device.
+    pub fn failure(&mut self, device: u64, now: Instant) -> bool {
+        let errors = self.devices.entry(device).or_default();
+        errors.consecutive += 1;
+        errors.total += 1;
+        errors.recent.push_back(now);
+        while errors.recent.front().is_some_and(|&at| now.duration_since(at) > WINDOW) {
+            errors.recent.pop_front();
+        }
+        let tripped = errors.consecutive >= self.threshold || errors.recent.len() >= 2 * self.threshold;
+        let suspended = tripped && !errors.suspended;
+        errors.suspended |= tripped;
+        suspended
+    }
+
+    /// Work on `device` went through, the errors in a row start over.
+    pub fn success(&mut self, device: u64) {
+        if let Some(errors) = self.devices.get_mut(&device) {
+            errors.consecutive = 0;
+        }
+    }
+
+    pub fn is_suspended(&self, device: u64) -> bool {
+        self.devices.get(&device).is_some_and(|errors| errors.suspended)
+    }
+
+    /// IO errors counted on `device` during the run.
+    pub fn errors(&self, device: u64) -> usize {
+        self.devices.get(&device).map_or(0, |errors| errors.total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::PathBuf};
+
+    use super::*;
+    use crate::failure::ExtractionError;
+
+    #[test]
+    fn io_errors_suspend_their_device_only() {
+        let (sick, healthy) = (1, 2);
+        let mut breaker = DeviceBreaker::new(3);
+        let now = Instant::now();
+        let eio = ExtractionError::ReadError {
+            path: PathBuf::from("/mnt/sick/show.rar"),
+            io_kind: Some(io::ErrorKind::Other),
+        };
+        let failures = [
+            eio.clone(),
+            ExtractionError::WrongPassword,
+            ExtractionError::Corrupt {
+                volume: None,
+                file: None,
+            },
+            ExtractionError::ReadError {
+                path: PathBuf::from("/mnt/sick/movie.rar"),
+                io_kind: Some(io::ErrorKind::PermissionDenied),
+            },
+        ];
+        // Bad passwords, damaged archives and permissions don't count.
+        for _ in failures.iter().filter(|failure| failure.is_io()) {
+            assert!(!breaker.failure(sick, now));
+        }
+        breaker.success(healthy);
+        assert!(!breaker.failure(sick, now));
+        breaker.success(sick);
+        assert!(!breaker.failure(sick, now));
+        assert!(!breaker.failure(sick, now));
+        assert!(breaker.failure(sick, now));
+        assert!(breaker.is_suspended(sick));
+        assert!(!breaker.failure(sick, now));
+        assert_eq!(breaker.errors(sick), 6);
+        assert!(!breaker.is_suspended(healthy));
+
+        // Spread out errors with successes between them, within the window.
+        let mut breaker = DeviceBreaker::new(3);
+        let tripped: Vec<bool> = (0..6)
+            .map(|i| {
+                breaker.success(sick);
+                breaker.failure(sick, now + Duration::from_secs(i * 60))
+            })
+            .collect();
+        assert_eq!(tripped, [false, false, false, false, false, true]);
+        assert!(eio.is_io());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::fds;
+
+/// Size and mtime of a file before it was touched.
+#[derive(Debug, Clone, Copy)]
+pub struct FileState {
+    pub size: u64,
+    pub mtime: Option<SystemTime>,
+}
+
+impl FileState {
+    /// State of the file at `path`, `None` when it doesn't exist.
+    pub fn of(path: &Path) -> Option<FileState> {
+        let md = fs::metadata(path).ok()?;
+        Some(FileState {
+            size: md.len(),
+            mtime: md.modified().ok(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum Change {
+    /// A file written by an extraction, `previous` is set when it was overwritten.
+    Written {
+        path: PathBuf,
+        archive: PathBuf,
+        previous: Option<FileState>,
+        size: u64,
+    },
+    Removed {
+        path: PathBuf,
+        archive: Option<PathBuf>,
+        previous: Option<FileState>,
+    },
+    DirRemoved {
+        path: PathBuf,
+    },
+    /// An extracted file moved away once it aged.
+    Moved {
+        path: PathBuf,
+        to: PathBuf,
+        archive: PathBuf,
+        size: u64,
+    },
+    MtimeChanged {
+        path: PathBuf,
+        archive: PathBuf,
+        previous: Option<FileState>,
+        mtime: SystemTime,
+    },
+}
+
+fn secs(t: SystemTime) -> f64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+fn previous_to_json(previous: &Option<FileState>) -> (Value, Value) {
+    match previous {
+        Some(state) => (state.size.into(), state.mtime.map(secs).into()),
+        None => (Value::Null, Value::Null),
+    }
+}
+
+impl Change {
+    fn to_json(&self, applied: bool) -> Value {
+        match self {
+            Change::Written {
+                path,
+                archive,
+                previous,
+                size,
+            } => {
+                let (old_size, old_mtime) = previous_to_json(previous);
+                json!({
+                    "change": if previous.is_some() { "overwritten" } else { "created" },
+                    "path": path.to_string_lossy(),
+                    "archive": archive.to_string_lossy(),
+                    "old_size": old_size,
+                    "old_mtime": old_mtime,
+                    "new_size": size,
+                    "applied": applied,
+                })
+            }
+            Change::Removed {
+                path,
+                archive,
+                previous,
+            } => {
+                let (old_size, old_mtime) = previous_to_json(previous);
+                json!({
+                    "change": "removed",
+                    "path": path.to_string_lossy(),
+                    "archive": archive.as_ref().map(|p| p.to_string_lossy()),
+                    "old_size": old_size,
+                    "old_mtime": old_mtime,
+                    "applied": applied,
+                })
+            }
+            Change::DirRemoved { path } => json!({
+                "change": "dir_removed",
+                "path": path.to_string_lossy(),
+                "applied": applied,
+            }),
+            Change::Moved {
+                path,
+                to,
+                archive,
+                size,
+            } => json!({
+                "change": "moved",
+                "path": path.to_string_lossy(),
+                "to": to.to_string_lossy(),
+                "archive": archive.to_string_lossy(),
+                "old_size": size,
+                "applied": applied,
+            }),
+            Change::MtimeChanged {
+                path,
+                archive,
+                previous,
+                mtime,
+            } => {
+                let (_, old_mtime) = previous_to_json(previous);
+                json!({
+                    "change": "mtime_changed",
+                    "path": path.to_string_lossy(),
+                    "archive": archive.to_string_lossy(),
+                    "old_mtime": old_mtime,
+                    "new_mtime": secs(*mtime),
+                    "applied": applied,
+                })
+            }
+        }
+    }
+}
+
+/// Every filesystem mutation of a run, written as JSON Lines once the run is over. In a dry-run the changes are the
+/// ones that would have been made and are marked as not applied.
+pub struct ChangeLog {
+    path: PathBuf,
+    applied: bool,
+    changes: Vec<Change>,
+}
+
+impl ChangeLog {
+    pub fn new(path: impl Into<PathBuf>, applied: bool) -> ChangeLog {
+        ChangeLog {
+            path: path.into(),
+            applied,
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    pub fn write(&self) -> anyhow::Result<()> {
+        let _fds = fds::acquire(1);
+        let file = File::create(&self.path).with_context(|| format!("create changelog '{}'", self.path.display()))?;
+        let mut out = BufWriter::new(file);
+        for change in &self.changes {
+            writeln!(out, "{}", change.to_json(self.applied)).context("write changelog")?;
+        }
+        out.flush().context("write changelog")
+    }
+}
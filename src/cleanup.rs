@@ -0,0 +1,87 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::dirlock;
+
+/// Directories holding a file with this name are never removed.
+pub const KEEP_MARKER: &str = ".rarscan-keep";
+
+/// Removes the directories that are empty once the `removed` files are gone, starting from `dirs` and going up to
+/// `root_dir`, which is never removed itself. Subdirectories are handled first so that a directory containing only
+/// directories emptied that way goes as well. An empty directory the removals didn't empty, one extracted from an
+/// archive or made by the user, stays and keeps its parents. In a dry-run nothing is removed but the directories that
+/// would go are returned.
+pub fn remove_empty_dirs(
+    root_dir: &Path,
+    dirs: &HashSet<PathBuf>,
+    removed: &HashSet<PathBuf>,
+    dry_run: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut purge = Purge {
+        root_dir,
+        dry_run,
+        gone: removed.clone(),
+        removed_dirs: Vec::new(),
+    };
+
+    // Deepest first, so that nested affected directories are settled before their parents are looked at.
+    let mut dirs: Vec<&PathBuf> = dirs.iter().filter(|dir| dir.starts_with(root_dir)).collect();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+    for dir in dirs {
+        if purge.gone.contains(dir) || !dir.is_dir() {
+            continue;
+        }
+        if !purge.remove_if_empty(dir)? {
+            continue;
+        }
+        let mut parent = dir.parent();
+        while let Some(dir) = parent {
+            if !purge.remove_if_empty(dir)? {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+    Ok(purge.removed_dirs)
+}
+
+struct Purge<'a> {
+    root_dir: &'a Path,
+    dry_run: bool,
+    gone: HashSet<PathBuf>,
+    removed_dirs: Vec<PathBuf>,
+}
+
+impl Purge<'_> {
+    fn remove_if_empty(&mut self, dir: &Path) -> io::Result<bool> {
+        if dir == self.root_dir || !dir.starts_with(self.root_dir) {
+            return Ok(false);
+        }
+        // A lock left by an instance that's gone doesn't keep a directory, a fresh one does.
+        let mut stale_locks = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_name() == dirlock::LOCK_FILE && dirlock::is_stale(&entry.path()) {
+                stale_locks.push(entry.path());
+                continue;
+            }
+            if entry.file_name() == KEEP_MARKER || !self.gone.contains(&entry.path()) {
+                return Ok(false);
+            }
+        }
+        log::info!("Removing empty directory '{}'.", dir.display());
+        if !self.dry_run {
+            for lock in stale_locks {
+                fs::remove_file(lock)?;
+            }
+            fs::remove_dir(dir)?;
+        }
+        self.gone.insert(dir.to_path_buf());
+        self.removed_dirs.push(dir.to_path_buf());
+        Ok(true)
+    }
+}
@@ -0,0 +1,105 @@
+use std::{ffi::OsString, fs, path::PathBuf};
+
+/// The flag naming the config file.
+const FLAG: &str = "--config";
+
+/// The command line with the flags of the file given to --config in front of its own, which win over them.
+pub fn expand(args: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    let Some(i) = args
+        .iter()
+        .position(|arg| arg == FLAG || arg.to_str().is_some_and(|arg| arg.starts_with("--config=")))
+    else {
+        return Ok(args);
+    };
+    let path = match args[i].to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+        Some(path) => PathBuf::from(path),
+        None => args.get(i + 1).map(PathBuf::from).ok_or("--config needs a file")?,
+    };
+    let text = fs::read_to_string(&path).map_err(|e| format!("could not read config '{}': {}", path.display(), e))?;
+    let flags = parse(&text).map_err(|e| format!("config '{}' {}", path.display(), e))?;
+    let mut expanded = args[..1].to_vec();
+    expanded.extend(flags.into_iter().map(OsString::from));
+    expanded.extend(args[1..].iter().cloned());
+    Ok(expanded)
+}
+
+/// The flags of a config file: a `name = value` option or a `name` switch per line, named like the flags without
+/// their dashes. Blank lines and the ones starting with `#` are skipped.
+pub fn parse(text: &str) -> Result<Vec<String>, String> {
+    let mut flags = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (line, None),
+        };
+        if name.is_empty() || name.starts_with('-') || name.contains(char::is_whitespace) {
+            return Err(format!("line {}: '{}' isn't the name of a flag", i + 1, name));
+        }
+        flags.push(format!("--{}", name));
+        flags.extend(value.map(str::to_string));
+    }
+    Ok(flags)
+}
+
+/// A config file `parse` reads `options` back from, a value of `None` is a switch.
+pub fn render(header: &str, options: &[(&str, Option<String>)]) -> String {
+    let mut text: String = header.lines().map(|line| format!("# {}\n", line)).collect();
+    for (name, value) in options {
+        match value {
+            Some(value) => text.push_str(&format!("{} = {}\n", name, value)),
+            None => text.push_str(&format!("{}\n", name)),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_flags_go_before_the_command_line() {
+        let text = render(
+            "Written by a test.",
+            &[
+                ("remove-after-hours", Some("48".into())),
+                ("dest-template", Some("/mnt/media/{year} x".into())),
+                ("dry-run", None),
+            ],
+        );
+        assert_eq!(
+            parse(&text).unwrap(),
+            [
+                "--remove-after-hours",
+                "48",
+                "--dest-template",
+                "/mnt/media/{year} x",
+                "--dry-run"
+            ]
+        );
+        assert_eq!(parse("\n  # comment\nfsync\n").unwrap(), ["--fsync"]);
+        assert!(parse("--fsync").unwrap_err().contains("line 1"));
+        assert!(parse("a\nremove after = 1").unwrap_err().contains("line 2"));
+
+        let path = std::env::temp_dir().join(format!("rarscan-config-test-{}", std::process::id()));
+        fs::write(&path, "remove-after-hours = 48\n").unwrap();
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        let config = format!("--config={}", path.display());
+        assert_eq!(
+            expand(args(&["rarscan", &config, "/data"])).unwrap(),
+            args(&["rarscan", "--remove-after-hours", "48", &config, "/data"])
+        );
+        assert_eq!(
+            expand(args(&["rarscan", "/data"])).unwrap(),
+            args(&["rarscan", "/data"])
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(expand(args(&["rarscan", &config]))
+            .unwrap_err()
+            .contains("could not read"));
+    }
+}
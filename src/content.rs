@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use anyhow::Context;
+use glob::{MatchOptions, Pattern};
+
+use crate::archive::Archive;
+
+/// Glob patterns of --require-content, matched against the paths of the files of an archive within its destination.
+/// An archive has its content when any of its files matches one.
+#[derive(Debug, Clone)]
+pub struct RequiredContent {
+    patterns: Vec<Pattern>,
+}
+
+impl RequiredContent {
+    pub fn new(patterns: &[String]) -> anyhow::Result<RequiredContent> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| Pattern::new(pattern).with_context(|| format!("invalid content pattern '{}'", pattern)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(RequiredContent { patterns })
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.patterns.iter().map(Pattern::as_str)
+    }
+
+    /// The first file of `archive` matching a pattern which is in `dest`, after the renames and the routing. A dry-run
+    /// extracts nothing, there it's enough that the archive has one.
+    pub fn find<'a>(&self, archive: &'a Archive, dest: &Path, dry_run: bool) -> Option<&'a Path> {
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        archive
+            .headers
+            .iter()
+            .filter(|header| header.is_file())
+            .map(|header| header.filename.as_path())
+            .filter(|name| {
+                self.patterns
+                    .iter()
+                    .any(|pattern| pattern.matches_path_with(name, options))
+            })
+            .find(|name| dry_run || dest.join(name).is_file())
+    }
+}
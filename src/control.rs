@@ -0,0 +1,61 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// How often a paused queue checks whether it may go on.
+pub const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Requests made to a running queue from another thread. Pausing and quitting are checked between archives, skipping
+/// between the entries of the archive being extracted.
+#[derive(Debug, Default)]
+pub struct Control {
+    paused: AtomicBool,
+    skip: AtomicBool,
+    quit: AtomicBool,
+}
+
+impl Control {
+    /// Pauses after the current archive, or resumes. Returns whether the queue is now paused.
+    #[cfg(feature = "tui")]
+    pub fn toggle_pause(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Abandons the archive being extracted, its parts are kept.
+    #[cfg(feature = "tui")]
+    pub fn skip_current(&self) {
+        self.skip.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a skip was requested, which is then considered handled.
+    pub fn take_skip(&self) -> bool {
+        self.skip.swap(false, Ordering::Relaxed)
+    }
+
+    /// Stops once the current archive is done.
+    #[cfg(feature = "tui")]
+    pub fn quit(&self) {
+        self.quit.store(true, Ordering::Relaxed);
+    }
+
+    pub fn quitting(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+
+    /// Blocks while paused, unless asked to quit.
+    pub fn wait_while_paused(&self) {
+        while self.is_paused() && !self.quitting() {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::paths;
+
+/// A request made to a running instance through its control socket, one JSON object per line such as
+/// `{"command": "prioritize", "path": "/tv/show/show.rar"}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Status,
+    Queue,
+    /// Moves a queued archive to the front.
+    Prioritize(PathBuf),
+    /// Takes an archive out of the queue.
+    Drop(PathBuf),
+    /// Stops before the next archive, until resumed.
+    Pause,
+    Resume,
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Request, String> {
+        let value: Value = serde_json::from_str(line).map_err(|e| format!("invalid request: {}", e))?;
+        let path = || {
+            value
+                .get("path")
+                .and_then(Value::as_str)
+                .map(PathBuf::from)
+                .ok_or_else(|| "missing path".to_string())
+        };
+        match value.get("command").and_then(Value::as_str) {
+            Some("status") => Ok(Request::Status),
+            Some("queue") => Ok(Request::Queue),
+            Some("prioritize") => Ok(Request::Prioritize(path()?)),
+            Some("drop") => Ok(Request::Drop(path()?)),
+            Some("pause") => Ok(Request::Pause),
+            Some("resume") => Ok(Request::Resume),
+            Some(command) => Err(format!("unknown command '{}'", command)),
+            None => Err("missing command".into()),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Request::Status => json!({ "command": "status" }),
+            Request::Queue => json!({ "command": "queue" }),
+            Request::Prioritize(path) => json!({ "command": "prioritize", "path": path.to_string_lossy() }),
+            Request::Drop(path) => json!({ "command": "drop", "path": path.to_string_lossy() }),
+            Request::Pause => json!({ "command": "pause" }),
+            Request::Resume => json!({ "command": "resume" }),
+        }
+    }
+}
+
+pub fn error(message: impl std::fmt::Display) -> Value {
+    json!({ "ok": false, "error": message.to_string() })
+}
+
+/// Whether `a` and `b` are the same archive, as given or in their canonical spelling.
+pub fn same_path(a: &Path, b: &Path) -> bool {
+    a == b || paths::canonical(a) == paths::canonical(b)
+}
+
+/// Prints the response to `request` for a person, as rows and tables.
+pub fn print_response(request: &Request, response: &Value) {
+    let str_of = |key: &str| {
+        response
+            .get(key)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    match request {
+        Request::Status => {
+            let rows = [
+                ("Paused", "paused"),
+                ("Paused until", "paused_until"),
+                ("Current archive", "current_archive"),
+                ("Queued archives", "queue_length"),
+                ("Deferred archives", "deferred"),
+                ("Archives processed", "archives_processed"),
+                ("Archives extracted", "archives_extracted"),
+                ("Archives failed", "archives_failed"),
+            ];
+            for (label, key) in rows {
+                let value = match response.get(key) {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(Value::Null) | None => "-".to_string(),
+                    Some(value) => value.to_string(),
+                };
+                println!("{:<24} {}", label, value);
+            }
+        }
+        Request::Queue => {
+            let queue = response
+                .get("queue")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            println!("{:>6}  Archive", "#");
+            for entry in &queue {
+                let position = entry.get("position").and_then(Value::as_u64).unwrap_or_default();
+                let path = entry.get("path").and_then(Value::as_str).unwrap_or_default();
+                println!("{:>6}  {}", position, path);
+            }
+            for path in response.get("deferred").and_then(Value::as_array).into_iter().flatten() {
+                println!("{:>6}  {}", "later", path.as_str().unwrap_or_default());
+            }
+        }
+        Request::Prioritize(_) => println!("'{}' is now at position {}.", str_of("path"), response["position"]),
+        Request::Drop(_) => println!(
+            "Dropped '{}', it was at position {}.",
+            str_of("path"),
+            response["position"]
+        ),
+        Request::Pause => println!("Paused, the next archive waits until resumed."),
+        Request::Resume => println!("Resumed."),
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use std::{
+        fs,
+        io::{self, BufRead, BufReader, Write},
+        os::unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use anyhow::Context;
+    use serde_json::Value;
+
+    use super::{error, Request};
+
+    /// A client slower than this to send its request or read the response is dropped.
+    const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+    /// The running instance answers between archives, which may take a while.
+    const RESPONSE_TIMEOUT: Duration = Duration::from_secs(300);
+
+    /// Control socket of a running instance, polled by the queue between archives.
+    pub struct CtlSocket {
+        path: PathBuf,
+        listener: UnixListener,
+    }
+
+    impl CtlSocket {
+        pub fn bind(path: &Path) -> anyhow::Result<Option<CtlSocket>> {
+            // A socket left behind by a previous run would make bind fail, but never remove anything else.
+            if let Ok(md) = fs::symlink_metadata(path) {
+                if md.file_type().is_socket() {
+                    fs::remove_file(path).context("remove stale control socket")?;
+                }
+            }
+            let listener = UnixListener::bind(path).context("bind control socket")?;
+            listener
+                .set_nonblocking(true)
+                .context("set control socket non-blocking")?;
+            log::info!("Listening for control requests on '{}'.", path.display());
+            Ok(Some(CtlSocket {
+                path: path.to_path_buf(),
+                listener,
+            }))
+        }
+
+        /// Answers the requests of the clients waiting, with `handle`.
+        pub fn serve(&self, mut handle: impl FnMut(Request) -> Value) {
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = serve_client(stream, &mut handle) {
+                            log::debug!("Control client failed: {}", e);
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    impl Drop for CtlSocket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn serve_client(stream: UnixStream, handle: &mut impl FnMut(Request) -> Value) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let response = match Request::parse(&line) {
+            Ok(request) => handle(request),
+            Err(e) => error(e),
+        };
+        let mut stream = &stream;
+        writeln!(stream, "{}", response)?;
+        stream.flush()
+    }
+
+    /// Sends `request` to the instance listening on `path` and waits for its response.
+    pub fn send(path: &Path, request: &Request) -> anyhow::Result<Value> {
+        let stream = UnixStream::connect(path).with_context(|| {
+            format!(
+                "connect to '{}', is rarscan running with --control-socket?",
+                path.display()
+            )
+        })?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        writeln!(&stream, "{}", request.to_json()).context("send request")?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).context("read response")?;
+        let response: Value = serde_json::from_str(&line).context("parse response")?;
+        if response.get("ok") != Some(&Value::Bool(true)) {
+            let message = response.get("error").and_then(Value::as_str).unwrap_or("unknown error");
+            anyhow::bail!("{}", message);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(not(unix))]
+mod socket {
+    use std::path::Path;
+
+    use serde_json::Value;
+
+    use super::Request;
+
+    pub struct CtlSocket;
+
+    impl CtlSocket {
+        pub fn bind(_: &Path) -> anyhow::Result<Option<CtlSocket>> {
+            log::warn!("Unix domain sockets are not available on this platform, --control-socket is ignored.");
+            Ok(None)
+        }
+
+        pub fn serve(&self, _: impl FnMut(Request) -> Value) {}
+    }
+
+    pub fn send(_: &Path, _: &Request) -> anyhow::Result<Value> {
+        anyhow::bail!("Unix domain sockets are not available on this platform")
+    }
+}
+
+pub use socket::{send, CtlSocket};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(Request::parse(r#"{"command":"queue"}"#), Ok(Request::Queue));
+        assert_eq!(
+            Request::parse(r#"{"command":"drop","path":"/tv/a.rar"}"#),
+            Ok(Request::Drop(PathBuf::from("/tv/a.rar")))
+        );
+        assert!(Request::parse(r#"{"command":"prioritize"}"#).is_err());
+        assert!(Request::parse(r#"{"command":"restart"}"#).is_err());
+        let request = Request::Prioritize(PathBuf::from("/tv/b.rar"));
+        assert_eq!(Request::parse(&request.to_json().to_string()), Ok(request));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn requests_round_trip_over_the_socket() {
+        let path = std::env::temp_dir().join(format!("rarscan-ctl-test-{}.sock", std::process::id()));
+        let socket = CtlSocket::bind(&path).unwrap().unwrap();
+        let client = {
+            let path = path.clone();
+            std::thread::spawn(move || send(&path, &Request::Drop(PathBuf::from("/tv/a.rar"))))
+        };
+        let mut served = None;
+        while served.is_none() {
+            socket.serve(|request| {
+                served = Some(request);
+                json!({ "ok": true, "position": 3 })
+            });
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(served, Some(Request::Drop(PathBuf::from("/tv/a.rar"))));
+        assert_eq!(client.join().unwrap().unwrap()["position"], 3);
+    }
+}
@@ -0,0 +1,519 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use time::{
+    format_description::{self, well_known::Rfc3339, OwnedFormatItem},
+    Date, Month, OffsetDateTime, UtcOffset,
+};
+
+/// Format of the dates shown unless --time-format is given.
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+const LOCALTIME: &str = "/etc/localtime";
+
+/// Time zone the dates are shown in, with the rules of a tz database zone when it has some.
+#[derive(Debug, Clone)]
+pub enum TimeZone {
+    Fixed(UtcOffset),
+    Zone(Box<ZoneRules>),
+}
+
+impl TimeZone {
+    pub const UTC: TimeZone = TimeZone::Fixed(UtcOffset::UTC);
+
+    /// `utc`, `local` or the name of a zone of the tz database such as `Europe/Paris`. For `local`, `TZ` and then
+    /// `/etc/localtime` are read, `local_offset` is the offset to fall back to when neither gives rules, and UTC
+    /// without one.
+    pub fn resolve(name: &str, local_offset: Option<UtcOffset>) -> Result<TimeZone, String> {
+        match name {
+            "utc" | "UTC" => Ok(TimeZone::UTC),
+            "local" => Ok(TimeZone::local(local_offset)),
+            _ => load_zone(name).map(|rules| TimeZone::Zone(Box::new(rules))),
+        }
+    }
+
+    fn local(local_offset: Option<UtcOffset>) -> TimeZone {
+        let from_env = env::var("TZ").ok().filter(|tz| !tz.is_empty()).and_then(|tz| {
+            let tz = tz.strip_prefix(':').unwrap_or(&tz).to_string();
+            load_zone(&tz).ok().or_else(|| {
+                PosixTz::parse(&tz).map(|posix| ZoneRules {
+                    name: tz.clone(),
+                    initial: posix.std,
+                    transitions: Vec::new(),
+                    footer: Some(posix),
+                })
+            })
+        });
+        let rules = from_env.or_else(|| {
+            let data = fs::read(LOCALTIME).ok()?;
+            ZoneRules::parse(LOCALTIME, &data)
+        });
+        match rules {
+            Some(rules) => TimeZone::Zone(Box::new(rules)),
+            None => TimeZone::Fixed(local_offset.unwrap_or(UtcOffset::UTC)),
+        }
+    }
+
+    pub fn is_utc(&self) -> bool {
+        matches!(self, TimeZone::Fixed(offset) if offset.is_utc())
+    }
+
+    pub fn offset_at(&self, t: OffsetDateTime) -> UtcOffset {
+        match self {
+            TimeZone::Fixed(offset) => *offset,
+            TimeZone::Zone(rules) => rules.offset_at(t.unix_timestamp()),
+        }
+    }
+
+    pub fn to_local(&self, t: SystemTime) -> OffsetDateTime {
+        let t = OffsetDateTime::from(t);
+        t.to_offset(self.offset_at(t))
+    }
+}
+
+/// How the dates are shown: the time zone and the format. Settled once on the main thread at startup, and the only
+/// way dates are shown afterwards: the local offset can't be looked up soundly once other threads exist, and nothing
+/// looks it up then.
+#[derive(Debug, Clone)]
+pub struct TimeContext {
+    pub zone: TimeZone,
+    pub format: OwnedFormatItem,
+}
+
+impl TimeContext {
+    /// UTC and the default format, until the context of the run is settled.
+    pub fn utc() -> TimeContext {
+        TimeContext {
+            zone: TimeZone::UTC,
+            format: parse_format(DEFAULT_FORMAT).expect("default time format"),
+        }
+    }
+
+    pub fn format(&self, t: SystemTime) -> String {
+        self.zone
+            .to_local(t)
+            .format(&self.format)
+            .unwrap_or_else(|_| "Unknown".into())
+    }
+}
+
+/// The offset of the local time zone from the time crate, the fallback of `local` when neither TZ nor /etc/localtime
+/// give its rules. Only sound while the process has a single thread, so it's looked up once at startup. Builds
+/// without the `local-offset` feature never look it up.
+pub fn startup_local_offset() -> Result<UtcOffset, String> {
+    #[cfg(feature = "local-offset")]
+    return UtcOffset::current_local_offset().map_err(|e| e.to_string());
+    #[cfg(not(feature = "local-offset"))]
+    Err("built without the local-offset feature".into())
+}
+
+/// A zone read from a TZif file: its transitions and the POSIX TZ rule past the last one.
+#[derive(Debug, Clone)]
+pub struct ZoneRules {
+    name: String,
+    /// Offset before the first transition.
+    initial: UtcOffset,
+    /// Unix time of each transition and the offset from then on, in order.
+    transitions: Vec<(i64, UtcOffset)>,
+    footer: Option<PosixTz>,
+}
+
+fn load_zone(name: &str) -> Result<ZoneRules, String> {
+    let path = if Path::new(name).is_absolute() {
+        PathBuf::from(name)
+    } else {
+        if name
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err(format!("invalid time zone '{}'", name));
+        }
+        let dir = env::var_os("TZDIR").map_or_else(|| PathBuf::from(ZONEINFO_DIR), PathBuf::from);
+        dir.join(name)
+    };
+    let data = fs::read(&path).map_err(|e| format!("unknown time zone '{}': {}", name, e))?;
+    ZoneRules::parse(name, &data).ok_or_else(|| format!("'{}' is not a TZif file", path.display()))
+}
+
+impl ZoneRules {
+    /// Parses a TZif file, the 64-bit data of version 2 and later when it has some.
+    fn parse(name: &str, data: &[u8]) -> Option<ZoneRules> {
+        let header = Header::parse(data)?;
+        let v1_end = 44 + header.data_len(4);
+        let (header, start, time_size) = match data[4] {
+            0 => (header, 44, 4),
+            _ => (Header::parse(data.get(v1_end..)?)?, v1_end + 44, 8),
+        };
+        let read_time = |at: usize| -> Option<i64> {
+            let bytes = data.get(at..at + time_size)?;
+            Some(match time_size {
+                4 => i32::from_be_bytes(bytes.try_into().ok()?) as i64,
+                _ => i64::from_be_bytes(bytes.try_into().ok()?),
+            })
+        };
+        let indices_at = start + header.timecnt * time_size;
+        let types_at = indices_at + header.timecnt;
+        let types = (0..header.typecnt)
+            .map(|i| {
+                let at = types_at + i * 6;
+                let offset = i32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?);
+                UtcOffset::from_whole_seconds(offset).ok()
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let transitions = (0..header.timecnt)
+            .map(|i| {
+                let t = read_time(start + i * time_size)?;
+                let offset = *types.get(*data.get(indices_at + i)? as usize)?;
+                Some((t, offset))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let footer = match time_size {
+            8 => {
+                let footer = data.get(start + header.data_len(8)..)?;
+                let footer = std::str::from_utf8(footer).ok()?;
+                PosixTz::parse(footer.trim_matches('\n'))
+            }
+            _ => None,
+        };
+        Some(ZoneRules {
+            name: name.to_string(),
+            initial: *types.first()?,
+            transitions,
+            footer,
+        })
+    }
+
+    fn offset_at(&self, t: i64) -> UtcOffset {
+        let after = self.transitions.partition_point(|(at, _)| *at <= t);
+        if let Some(footer) = self.footer.as_ref().filter(|_| after == self.transitions.len()) {
+            return footer.offset_at(t);
+        }
+        match after {
+            0 => self.initial,
+            n => self.transitions[n - 1].1,
+        }
+    }
+}
+
+impl std::fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TimeZone::Fixed(offset) if offset.is_utc() => write!(f, "UTC"),
+            TimeZone::Fixed(offset) => write!(f, "{}", offset),
+            TimeZone::Zone(rules) => write!(f, "{}", rules.name),
+        }
+    }
+}
+
+struct Header {
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Header> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let count = |i: usize| -> Option<usize> {
+            let at = 20 + i * 4;
+            Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize)
+        };
+        Some(Header {
+            isutcnt: count(0)?,
+            isstdcnt: count(1)?,
+            leapcnt: count(2)?,
+            timecnt: count(3)?,
+            typecnt: count(4)?,
+            charcnt: count(5)?,
+        })
+    }
+
+    /// Length of the data following the header, with times of `time_size` bytes.
+    fn data_len(&self, time_size: usize) -> usize {
+        self.timecnt * (time_size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+/// A POSIX TZ string such as `CET-1CEST,M3.5.0,M10.5.0/3`.
+#[derive(Debug, Clone, PartialEq)]
+struct PosixTz {
+    std: UtcOffset,
+    dst: Option<Dst>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Dst {
+    offset: UtcOffset,
+    start: Rule,
+    end: Rule,
+}
+
+/// A day of the year and the local time of day a change happens at, in seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rule {
+    day: RuleDay,
+    time: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleDay {
+    /// `Jn`, 1 to 365 without ever counting February 29.
+    Julian(u16),
+    /// `n`, 0 to 365 counting February 29.
+    Ordinal(u16),
+    /// `Mm.w.d`, day `d` (0 is Sunday) of week `w` (5 is the last) of month `m`.
+    Weekday { month: u8, week: u8, weekday: u8 },
+}
+
+impl PosixTz {
+    fn parse(s: &str) -> Option<PosixTz> {
+        let mut s = s;
+        skip_name(&mut s)?;
+        let std = -parse_offset(&mut s)?;
+        if s.is_empty() {
+            return Some(PosixTz {
+                std: UtcOffset::from_whole_seconds(std as i32).ok()?,
+                dst: None,
+            });
+        }
+        skip_name(&mut s)?;
+        let dst = match s.starts_with(',') || s.is_empty() {
+            true => std + 3600,
+            false => -parse_offset(&mut s)?,
+        };
+        // The United States rules, when the string has none.
+        let (start, end) = match s.strip_prefix(',') {
+            Some(rules) => {
+                let (start, end) = rules.split_once(',')?;
+                (Rule::parse(start)?, Rule::parse(end)?)
+            }
+            None if s.is_empty() => (Rule::parse("M3.2.0")?, Rule::parse("M11.1.0")?),
+            None => return None,
+        };
+        Some(PosixTz {
+            std: UtcOffset::from_whole_seconds(std as i32).ok()?,
+            dst: Some(Dst {
+                offset: UtcOffset::from_whole_seconds(dst as i32).ok()?,
+                start,
+                end,
+            }),
+        })
+    }
+
+    fn offset_at(&self, t: i64) -> UtcOffset {
+        let Some(dst) = &self.dst else {
+            return self.std;
+        };
+        let std = self.std.whole_seconds() as i64;
+        let Ok(local) = OffsetDateTime::from_unix_timestamp(t + std) else {
+            return self.std;
+        };
+        let year = local.year();
+        // A change happens at a local time, the one before the change.
+        let start = dst.start.unix_time(year) - std;
+        let end = dst.end.unix_time(year) - dst.offset.whole_seconds() as i64;
+        let in_dst = match start < end {
+            true => start <= t && t < end,
+            // Southern hemisphere, daylight saving time spans the new year.
+            false => !(end <= t && t < start),
+        };
+        match in_dst {
+            true => dst.offset,
+            false => self.std,
+        }
+    }
+}
+
+impl Rule {
+    fn parse(s: &str) -> Option<Rule> {
+        let (day, time) = match s.split_once('/') {
+            Some((day, time)) => (day, parse_duration(&mut &*time)?),
+            None => (s, 2 * 3600),
+        };
+        let day = if let Some(n) = day.strip_prefix('J') {
+            RuleDay::Julian(n.parse().ok().filter(|n| (1..=365).contains(n))?)
+        } else if let Some(rule) = day.strip_prefix('M') {
+            let mut fields = rule.split('.').map(|field| field.parse::<u8>().ok());
+            let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+            let valid = (1..=12).contains(&month) && (1..=5).contains(&week) && weekday <= 6;
+            (valid && fields.next().is_none()).then_some(RuleDay::Weekday { month, week, weekday })?
+        } else {
+            RuleDay::Ordinal(day.parse().ok().filter(|n| *n <= 365)?)
+        };
+        Some(Rule { day, time })
+    }
+
+    /// Unix time of the change in `year`, as if the local time were UTC.
+    fn unix_time(&self, year: i32) -> i64 {
+        let leap = time::util::days_in_year_month(year, Month::February) == 29;
+        let date = match self.day {
+            RuleDay::Julian(n) => Date::from_ordinal_date(year, if leap && n >= 60 { n + 1 } else { n }).ok(),
+            RuleDay::Ordinal(n) => Date::from_ordinal_date(year, (n + 1).min(365 + leap as u16)).ok(),
+            RuleDay::Weekday { month, week, weekday } => Month::try_from(month).ok().and_then(|month| {
+                let first = Date::from_calendar_date(year, month, 1).ok()?;
+                let offset = (weekday + 7 - first.weekday().number_days_from_sunday()) % 7;
+                let mut day = 1 + offset + 7 * (week - 1);
+                while day > time::util::days_in_year_month(year, month) {
+                    day -= 7;
+                }
+                Date::from_calendar_date(year, month, day).ok()
+            }),
+        };
+        let midnight = date.map_or(0, |date| date.midnight().assume_utc().unix_timestamp());
+        midnight + self.time
+    }
+}
+
+/// Skips a zone abbreviation, either letters or anything between `<` and `>`.
+fn skip_name(s: &mut &str) -> Option<()> {
+    let len = match s.strip_prefix('<') {
+        Some(rest) => rest.find('>')? + 2,
+        None => s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len()),
+    };
+    (len >= 3).then(|| *s = &s[len..])
+}
+
+/// `[+-]hh[:mm[:ss]]` in seconds, west of UTC being positive in TZ strings.
+fn parse_offset(s: &mut &str) -> Option<i64> {
+    let sign = match s.chars().next()? {
+        '-' => -1,
+        _ => 1,
+    };
+    *s = s.strip_prefix(['+', '-']).unwrap_or(s);
+    Some(sign * parse_duration(s)?)
+}
+
+/// `hh[:mm[:ss]]` in seconds, hours may be up to 167 and negative in rule times.
+fn parse_duration(s: &mut &str) -> Option<i64> {
+    let negative = s.starts_with('-');
+    *s = s.strip_prefix(['+', '-']).unwrap_or(s);
+    let end = s.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(s.len());
+    let (value, rest) = s.split_at(end);
+    *s = rest;
+    let mut seconds = 0;
+    for (i, field) in value.split(':').enumerate() {
+        let n: i64 = field.parse().ok().filter(|_| i < 3)?;
+        seconds += n * [3600, 60, 1][i];
+    }
+    Some(if negative { -seconds } else { seconds })
+}
+
+/// Parses a strftime-like format into the format description of the time crate. Supported: `%Y %y %m %b %B %d %e
+/// %j %a %A %H %I %p %M %S %z %:z %F %T %%`.
+pub fn parse_format(s: &str) -> Result<OwnedFormatItem, String> {
+    let mut description = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            if matches!(c, '[' | ']' | '\\') {
+                description.push('\\');
+            }
+            description.push(c);
+            continue;
+        }
+        let item = match chars.next() {
+            Some('Y') => "[year]",
+            Some('y') => "[year repr:last_two]",
+            Some('m') => "[month]",
+            Some('b') => "[month repr:short]",
+            Some('B') => "[month repr:long]",
+            Some('d') => "[day]",
+            Some('e') => "[day padding:space]",
+            Some('j') => "[ordinal]",
+            Some('a') => "[weekday repr:short]",
+            Some('A') => "[weekday]",
+            Some('H') => "[hour]",
+            Some('I') => "[hour repr:12]",
+            Some('p') => "[period]",
+            Some('M') => "[minute]",
+            Some('S') => "[second]",
+            Some('z') => "[offset_hour sign:mandatory][offset_minute]",
+            Some(':') if chars.next() == Some('z') => "[offset_hour sign:mandatory]:[offset_minute]",
+            Some('F') => "[year]-[month]-[day]",
+            Some('T') => "[hour]:[minute]:[second]",
+            Some('%') => "%",
+            Some(c) => return Err(format!("unsupported conversion '%{}' in '{}'", c, s)),
+            None => return Err(format!("'{}' ends with a lone '%'", s)),
+        };
+        description.push_str(item);
+    }
+    format_description::parse_owned::<2>(&description).map_err(|e| format!("invalid time format '{}': {}", s, e))
+}
+
+/// RFC 3339 in UTC, how dates are written in JSON whatever the time zone and format of the log.
+pub fn rfc3339(t: SystemTime) -> String {
+    OffsetDateTime::from(t).format(&Rfc3339).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unix time of a UTC date and time given as `[year, month, day, hour, minute, second]`.
+    fn at([year, month, day, hour, minute, second]: [i32; 6]) -> i64 {
+        let date = Date::from_calendar_date(year, Month::try_from(month as u8).unwrap(), day as u8).unwrap();
+        let time = time::Time::from_hms(hour as u8, minute as u8, second as u8).unwrap();
+        date.with_time(time).assume_utc().unix_timestamp()
+    }
+
+    #[test]
+    fn posix_rules() {
+        let paris = PosixTz::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert_eq!(paris.offset_at(at([2024, 1, 15, 12, 0, 0])).whole_hours(), 1);
+        assert_eq!(paris.offset_at(at([2024, 7, 15, 12, 0, 0])).whole_hours(), 2);
+        // The last Sunday of March 2024 is the 31st, at 02:00 local.
+        assert_eq!(paris.offset_at(at([2024, 3, 31, 0, 59, 59])).whole_hours(), 1);
+        assert_eq!(paris.offset_at(at([2024, 3, 31, 1, 0, 0])).whole_hours(), 2);
+        assert_eq!(paris.offset_at(at([2024, 10, 27, 0, 59, 59])).whole_hours(), 2);
+        assert_eq!(paris.offset_at(at([2024, 10, 27, 1, 0, 0])).whole_hours(), 1);
+
+        let sydney = PosixTz::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(at([2024, 1, 15, 0, 0, 0])).whole_hours(), 11);
+        assert_eq!(sydney.offset_at(at([2024, 7, 15, 0, 0, 0])).whole_hours(), 10);
+
+        let kolkata = PosixTz::parse("IST-5:30").unwrap();
+        assert_eq!(kolkata.offset_at(0).whole_minutes(), 330);
+        assert_eq!(PosixTz::parse("<-03>3").unwrap().std.whole_hours(), -3);
+        assert_eq!(PosixTz::parse("X1"), None);
+    }
+
+    #[test]
+    fn strftime_formats() {
+        let t = OffsetDateTime::from_unix_timestamp(at([2020, 1, 2, 8, 30, 5])).unwrap();
+        let format = |s: &str| t.format(&parse_format(s).unwrap()).unwrap();
+        assert_eq!(format(DEFAULT_FORMAT), "2020-01-02 08:30:05 +0000");
+        assert_eq!(
+            format("%d %b %Y, %I:%M %p [%:z] 100%%"),
+            "02 Jan 2020, 08:30 AM [+00:00] 100%"
+        );
+        assert!(parse_format("%Q").is_err());
+        assert!(parse_format("%").is_err());
+    }
+
+    #[test]
+    fn threads_show_dates_in_the_zone_of_the_context() {
+        let context = TimeContext {
+            zone: TimeZone::Fixed(UtcOffset::from_hms(5, 30, 0).unwrap()),
+            format: parse_format(DEFAULT_FORMAT).unwrap(),
+        };
+        let t = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(at([2020, 1, 2, 8, 30, 5]) as u64);
+        let shown = std::thread::scope(|scope| scope.spawn(|| context.format(t)).join().unwrap());
+        assert_eq!(shown, "2020-01-02 14:00:05 +0530");
+        assert_eq!(shown, context.format(t));
+        assert_eq!(TimeContext::utc().format(t), "2020-01-02 08:30:05 +0000");
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use clap::ValueEnum;
+
+use crate::{archive::Archive, fds};
+
+/// Bytes hashed at each end of the first part.
+const FINGERPRINT_CHUNK: u64 = 64 * 1024;
+
+/// What to do with an archive identical to one already extracted somewhere else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Extract it again.
+    ExtractAll,
+    /// Leave it untouched.
+    Skip,
+    /// Link the files already extracted from the other one into its destination.
+    Link,
+}
+
+/// Cheap identity of an archive: the size of its first part, the CRC32 of the start and end of that part and the
+/// number of entries. Matching fingerprints are only a hint, the entries are compared before relying on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    size: u64,
+    head_crc: u32,
+    tail_crc: u32,
+    entries: usize,
+}
+
+impl Fingerprint {
+    pub fn of(archive: &Archive) -> io::Result<Fingerprint> {
+        Ok(Fingerprint {
+            entries: archive.headers.len(),
+            ..Fingerprint::of_part(&archive.path)?
+        })
+    }
+
+    /// The fingerprint of the first part alone, without opening the archive. Its number of entries is 0.
+    pub fn of_part(path: &Path) -> io::Result<Fingerprint> {
+        let _fds = fds::acquire(1);
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let head_crc = crc_of(&mut file, 0)?;
+        let tail_crc = crc_of(&mut file, size.saturating_sub(FINGERPRINT_CHUNK))?;
+        Ok(Fingerprint {
+            size,
+            head_crc,
+            tail_crc,
+            entries: 0,
+        })
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{:08x}:{:08x}:{}",
+            self.size, self.head_crc, self.tail_crc, self.entries
+        )
+    }
+}
+
+fn crc_of(file: &mut File, offset: u64) -> io::Result<u32> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    file.take(FINGERPRINT_CHUNK).read_to_end(&mut buf)?;
+    Ok(crc32fast::hash(&buf))
+}
+
+/// Makes `to` the same file as `from`: a hard link, or a reflink when the two are on different filesystems that
+/// support it. Whatever was at `to` is replaced.
+pub fn link_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::remove_file(to) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    match fs::hard_link(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => imp::reflink(from, to),
+        result => result,
+    }
+}
+
+/// Creates `to` as a copy of `from` sharing its blocks, which fails unless both are on the same filesystem and it
+/// supports it.
+pub fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    imp::reflink(from, to)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{fs, fs::File, io, os::fd::AsRawFd, path::Path};
+
+    pub fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+        let src = File::open(from)?;
+        let dst = File::create_new(to)?;
+        // SAFETY: both descriptors are open for the duration of the call.
+        if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } != 0 {
+            let e = io::Error::last_os_error();
+            drop(dst);
+            let _ = fs::remove_file(to);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::{io, path::Path};
+
+    pub fn reflink(_: &Path, _: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::CrossesDevices.into())
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+/// The lock file an instance holds in a destination directory while it extracts into it.
+pub const LOCK_FILE: &str = ".rarscan.dirlock";
+
+/// Who holds the lock of a directory, as recorded in its lock file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holder {
+    pub pid: Option<u32>,
+    pub archive: PathBuf,
+}
+
+/// The lock of a destination directory, released when dropped. The lock is an advisory lock on the lock file, which
+/// the kernel releases when its holder dies: a lock file no process has locked is stale, whatever it says.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+    /// Holds the lock until it's closed.
+    _file: File,
+}
+
+impl DirLock {
+    /// Locks `dir` for the extraction of `archive`, or tells who holds it. A stale lock file is taken over.
+    pub fn acquire(dir: &Path, archive: &Path) -> io::Result<Result<DirLock, Holder>> {
+        let path = dir.join(LOCK_FILE);
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+            if !imp::try_lock(&file)? {
+                return Ok(Err(read_holder(&mut file)?));
+            }
+            // Removed by its holder between the open and the lock, the lock of a file no one else can open is no
+            // lock at all.
+            if !imp::same_file(&file, &path)? {
+                continue;
+            }
+            let stale = read_holder(&mut file)?;
+            if let Some(pid) = stale.pid.filter(|pid| *pid != std::process::id()) {
+                log::info!(
+                    "-> Taking over the stale lock of '{}' left by process {}.",
+                    dir.display(),
+                    pid
+                );
+            }
+            file.set_len(0)?;
+            file.rewind()?;
+            writeln!(file, "{}\n{}", std::process::id(), archive.display())?;
+            return Ok(Ok(DirLock { path, _file: file }));
+        }
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        // Removed while still locked, then unlocked by the close.
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("Could not remove lock '{}': {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Whether the lock file at `path` is left by an instance that's gone, so that it may be removed.
+pub fn is_stale(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    // Unlocked with the close.
+    imp::try_lock(&file).unwrap_or(false)
+}
+
+fn read_holder(file: &mut File) -> io::Result<Holder> {
+    let mut text = String::new();
+    file.rewind()?;
+    file.read_to_string(&mut text)?;
+    let mut lines = text.lines();
+    Ok(Holder {
+        pid: lines.next().and_then(|pid| pid.parse().ok()),
+        archive: PathBuf::from(lines.next().unwrap_or_default()),
+    })
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        fs::{self, File},
+        io,
+        os::unix::{fs::MetadataExt, io::AsRawFd},
+        path::Path,
+    };
+
+    /// Takes the exclusive lock of `file` without waiting, false when another open file holds it.
+    pub fn try_lock(file: &File) -> io::Result<bool> {
+        // SAFETY: flock only takes the descriptor, which stays open for the call.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(true);
+        }
+        match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(false),
+            e => Err(e),
+        }
+    }
+
+    /// Whether `path` still names the file open as `file`.
+    pub fn same_file(file: &File, path: &Path) -> io::Result<bool> {
+        let open = file.metadata()?;
+        match fs::metadata(path) {
+            Ok(named) => Ok(open.dev() == named.dev() && open.ino() == named.ino()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{fs::File, io, path::Path};
+
+    /// Without flock, every lock is taken: the instances aren't kept apart.
+    pub fn try_lock(_: &File) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    pub fn same_file(_: &File, path: &Path) -> io::Result<bool> {
+        Ok(path.exists())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_held_lock_names_its_holder_and_a_stale_one_is_taken_over() {
+        let dir = std::env::temp_dir().join(format!("rarscan-dirlock-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(LOCK_FILE);
+
+        let lock = DirLock::acquire(&dir, Path::new("/data/a.rar")).unwrap().unwrap();
+        assert!(!is_stale(&path));
+        // The lock is held by the open file, a second one in the same process doesn't get it either.
+        let holder = DirLock::acquire(&dir, Path::new("/data/b.rar")).unwrap().unwrap_err();
+        assert_eq!(
+            holder,
+            Holder {
+                pid: Some(std::process::id()),
+                archive: PathBuf::from("/data/a.rar"),
+            }
+        );
+        drop(lock);
+        assert!(!path.exists());
+
+        // Left by a process that died holding it.
+        fs::write(&path, "4194304\n/data/c.rar\n").unwrap();
+        assert!(is_stale(&path));
+        let lock = DirLock::acquire(&dir, Path::new("/data/b.rar")).unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n/data/b.rar\n", std::process::id())
+        );
+        drop(lock);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
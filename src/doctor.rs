@@ -0,0 +1,169 @@
+use std::{
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::Context;
+
+use crate::{archive::Archive, format_size, naming::ArchiveNaming, prealloc, privileges};
+
+/// Fixtures embedded in the binary, written to a scratch directory by the self-test.
+const FIXTURES: &[(&str, &[u8])] = &[
+    ("plain.rar", include_bytes!("../fixtures/plain.rar")),
+    ("multi.part1.rar", include_bytes!("../fixtures/multi.part1.rar")),
+    ("multi.part2.rar", include_bytes!("../fixtures/multi.part2.rar")),
+    ("multi.part3.rar", include_bytes!("../fixtures/multi.part3.rar")),
+    ("encrypted.rar", include_bytes!("../fixtures/encrypted.rar")),
+    ("large.rar", include_bytes!("../fixtures/large.rar")),
+];
+
+const PLAIN_CONTENT: &[u8] = b"hello from rarscan\n";
+const MULTI_SIZE: u64 = 5000;
+/// Declared by the only entry of large.rar, past what 32 bits hold.
+const LARGE_SIZE: u64 = 6 << 30;
+
+type Check = fn(&Path) -> anyhow::Result<()>;
+
+/// Prints the versions rarscan was built with and runs the self-test. Returns false if a check failed.
+pub fn run() -> anyhow::Result<bool> {
+    println!("rarscan {}", env!("CARGO_PKG_VERSION"));
+    // SAFETY: returns a constant, doesn't touch any state.
+    println!("UnRAR DLL API version {}", unsafe { unrar_sys::RARGetDllVersion() });
+    println!("Target {}-{}, {} bits", env::consts::ARCH, env::consts::OS, usize::BITS);
+    if prealloc::max_reserve() < LARGE_SIZE {
+        println!(
+            "Preallocation {}",
+            match prealloc::max_reserve() {
+                0 => "unavailable".to_string(),
+                max => format!("up to {} per file", format_size(max)),
+            }
+        );
+    }
+    println!("Running as {}", privileges::current_user());
+    println!("Event socket {}", if cfg!(unix) { "available" } else { "unavailable" });
+    println!(
+        "In-use detection {}",
+        if Path::new("/proc/self/fd").is_dir() {
+            "available"
+        } else {
+            "unavailable"
+        }
+    );
+    println!();
+
+    let dir = env::temp_dir().join(format!("rarscan-doctor-{}", process::id()));
+    self_test_in(&dir)
+}
+
+/// Runs the self-test in a scratch directory which is removed afterwards.
+fn self_test_in(dir: &Path) -> anyhow::Result<bool> {
+    fs::create_dir_all(dir).context("create self-test directory")?;
+    let result = self_test(dir);
+    let _ = fs::remove_dir_all(dir);
+    result
+}
+
+fn self_test(dir: &Path) -> anyhow::Result<bool> {
+    for (name, bytes) in FIXTURES {
+        fs::write(dir.join(name), bytes).with_context(|| format!("write fixture '{}'", name))?;
+    }
+
+    let checks: [(&str, Check); 4] = [
+        ("extract", check_extract),
+        ("multipart", check_multipart),
+        ("encrypted", check_encrypted),
+        ("large", check_large),
+    ];
+    let mut ok = true;
+    for (name, check) in checks {
+        match check(dir) {
+            Ok(()) => println!("{:<10} pass", name),
+            Err(e) => {
+                println!("{:<10} FAIL {:#}", name, e);
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+fn extract(dir: &Path, name: &str) -> anyhow::Result<(Archive, PathBuf)> {
+    let archive = Archive::open(dir.join(name), &ArchiveNaming::default()).context("archive open")?;
+    let dest = dir.join(format!("{}.out", name));
+    fs::create_dir_all(&dest).context("create destination")?;
+    archive.extract_into(&dest, |_, _| Ok(())).context("extract_into")?;
+    Ok((archive, dest))
+}
+
+fn check_extract(dir: &Path) -> anyhow::Result<()> {
+    let (archive, dest) = extract(dir, "plain.rar")?;
+    anyhow::ensure!(
+        archive.unsupported_feature().is_none(),
+        "archive reported as unsupported"
+    );
+    let content = fs::read(dest.join("dir/hello.txt")).context("read extracted file")?;
+    anyhow::ensure!(content == PLAIN_CONTENT, "extracted content differs");
+    anyhow::ensure!(archive.is_already_extracted(&dest)?, "extracted files not recognized");
+    Ok(())
+}
+
+fn check_multipart(dir: &Path) -> anyhow::Result<()> {
+    let (archive, dest) = extract(dir, "multi.part1.rar")?;
+    let parts = archive.list_parts().context("list parts")?;
+    anyhow::ensure!(parts.len() == 3, "found {} parts, expected 3", parts.len());
+    let size = fs::metadata(dest.join("big.bin")).context("stat extracted file")?.len();
+    anyhow::ensure!(size == MULTI_SIZE, "extracted {} bytes, expected {}", size, MULTI_SIZE);
+    Ok(())
+}
+
+fn check_encrypted(dir: &Path) -> anyhow::Result<()> {
+    let archive = Archive::open(dir.join("encrypted.rar"), &ArchiveNaming::default()).context("archive open")?;
+    anyhow::ensure!(
+        archive.unsupported_feature() == Some("a password"),
+        "password protection not detected"
+    );
+    Ok(())
+}
+
+/// Sizes past 4 GiB, from the headers and the filesystem, must survive the trip through 32-bit builds. The file is
+/// sparse, it takes no space.
+fn check_large(dir: &Path) -> anyhow::Result<()> {
+    let archive = Archive::open(dir.join("large.rar"), &ArchiveNaming::default()).context("archive open")?;
+    let size = archive.unpacked_size();
+    anyhow::ensure!(
+        size == LARGE_SIZE,
+        "declared size read as {}, expected {}",
+        size,
+        LARGE_SIZE
+    );
+    let dest = dir.join("large.rar.out");
+    fs::create_dir_all(&dest).context("create destination")?;
+    let file = File::create(dest.join("large.bin")).context("create sparse file")?;
+    file.set_len(LARGE_SIZE).context("grow sparse file past 4 GiB")?;
+    let size = file.metadata().context("stat sparse file")?.len();
+    anyhow::ensure!(
+        size == LARGE_SIZE,
+        "file size read as {}, expected {}",
+        size,
+        LARGE_SIZE
+    );
+    anyhow::ensure!(
+        archive.is_already_extracted(&dest)?,
+        "file past 4 GiB not recognized as extracted"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_test_passes() {
+        let dir = env::temp_dir().join(format!("rarscan-doctor-test-{}", process::id()));
+        assert!(self_test_in(&dir).unwrap());
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::fds;
+
+/// Flushes `files` to disk, then the directories from `dest` down to each file so that their entries survive a crash
+/// too. The parent of `dest` is included as `dest` may have just been created.
+pub fn sync_extracted(dest: &Path, files: &[PathBuf]) -> io::Result<()> {
+    let mut dirs = BTreeSet::new();
+    for path in files {
+        let _fds = fds::acquire(1);
+        File::open(path)?.sync_all()?;
+        for dir in path.ancestors().skip(1) {
+            dirs.insert(dir);
+            if dir == dest {
+                break;
+            }
+        }
+    }
+    if let Some(parent) = dest.parent() {
+        dirs.insert(parent);
+    }
+    // Deepest first, a directory is synced after the entries it holds.
+    for dir in dirs.into_iter().rev() {
+        sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    let _fds = fds::acquire(1);
+    match File::open(dir)?.sync_all() {
+        // Some filesystems, network ones in particular, can't sync directories and say so with EINVAL. Their entries
+        // are as durable as they're going to get.
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            log::debug!(
+                "'{}' can't be synced, its filesystem doesn't support it.",
+                dir.display()
+            );
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Directories can't be opened for syncing here, their entries are flushed along with the metadata of the files.
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::format_size;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EstimateFormat {
+    Table,
+    Json,
+}
+
+/// Where the throughput of an estimate comes from.
+#[derive(Debug, Clone, Copy)]
+pub enum Throughput {
+    /// Measured by a previous run and kept in the state file.
+    Measured(f64),
+    /// Given with --assume-throughput.
+    Assumed(f64),
+}
+
+impl Throughput {
+    fn bytes_per_sec(self) -> f64 {
+        match self {
+            Throughput::Measured(rate) | Throughput::Assumed(rate) => rate,
+        }
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Throughput::Measured(_) => "measured",
+            Throughput::Assumed(_) => "assumed",
+        }
+    }
+}
+
+/// Work a run over a directory would do.
+#[derive(Debug, Default)]
+pub struct Estimate {
+    pub archives: u64,
+    pub to_extract: u64,
+    pub packed_bytes: u64,
+    pub unpacked_bytes: u64,
+    pub freed_bytes: u64,
+    pub throughput: Option<Throughput>,
+}
+
+impl Estimate {
+    /// Time the extractions would take, `None` without a throughput to go by.
+    pub fn duration(&self) -> Option<Duration> {
+        let rate = self.throughput?.bytes_per_sec();
+        (rate > 0.0).then(|| Duration::from_secs_f64(self.unpacked_bytes as f64 / rate))
+    }
+
+    pub fn print(&self, format: EstimateFormat) {
+        match format {
+            EstimateFormat::Table => self.print_table(),
+            EstimateFormat::Json => println!("{}", self.to_json()),
+        }
+    }
+
+    fn print_table(&self) {
+        println!("{:<24} {}", "Archives", self.archives);
+        println!("{:<24} {}", "Archives to extract", self.to_extract);
+        println!("{:<24} {}", "Packed bytes to read", format_size(self.packed_bytes));
+        println!("{:<24} {}", "Unpacked bytes to write", format_size(self.unpacked_bytes));
+        println!("{:<24} {}", "Bytes freed by removal", format_size(self.freed_bytes));
+        match self.throughput {
+            Some(throughput) => println!(
+                "{:<24} {}/s ({})",
+                "Throughput",
+                format_size(throughput.bytes_per_sec() as u64),
+                throughput.source()
+            ),
+            None => println!("{:<24} unknown, use --assume-throughput", "Throughput"),
+        }
+        match self.duration() {
+            Some(duration) => println!("{:<24} {}", "Estimated duration", format_duration(duration)),
+            None => println!("{:<24} unknown", "Estimated duration"),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "archives": self.archives,
+            "to_extract": self.to_extract,
+            "packed_bytes": self.packed_bytes,
+            "unpacked_bytes": self.unpacked_bytes,
+            "freed_bytes": self.freed_bytes,
+            "throughput_bytes_per_sec": self.throughput.map(Throughput::bytes_per_sec),
+            "throughput_source": self.throughput.map(Throughput::source),
+            "duration_secs": self.duration().map(|duration| duration.as_secs_f64()),
+        })
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
@@ -0,0 +1,739 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+#[cfg(feature = "tui")]
+use crate::tui::DashboardSink;
+use crate::{
+    archive::Format, datetime, failure::ExtractionError, protocol, scanner::Flagged, snapshot::Snapshot,
+    walk::ScanProgress,
+};
+
+/// Events published to external tooling while a run progresses.
+pub enum Event<'a> {
+    ScanStart {
+        root_dir: &'a Path,
+    },
+    ArchiveFound {
+        path: &'a Path,
+    },
+    /// Sent now and then while the scan goes on, with the .rar files seen and the archives enqueued so far.
+    ScanProgress {
+        progress: &'a ScanProgress,
+        rar_files: u64,
+        archives: u64,
+    },
+    ScanDone {
+        dirs: u64,
+        rar_files: u64,
+        archives: u64,
+        duration: Duration,
+    },
+    /// Processing of an archive begins, `ArchiveDone` follows.
+    ArchiveStart {
+        archive: &'a Path,
+    },
+    ExtractStart {
+        archive: &'a Path,
+        /// The id of the archive, which stays the same when it's renamed or moved.
+        archive_id: Option<&'a str>,
+        format: Format,
+        dest: &'a Path,
+        packed_size: u64,
+        unpacked_size: u64,
+    },
+    FileExtracted {
+        archive: &'a Path,
+        file: &'a Path,
+        size: u64,
+    },
+    ExtractDone {
+        archive: &'a Path,
+        timing: &'a ArchiveTiming,
+    },
+    /// An archive is done with, `outcome` tells how it went.
+    ArchiveDone {
+        archive: &'a Path,
+        /// `None` when it wasn't opened.
+        archive_id: Option<&'a str>,
+        outcome: &'static str,
+    },
+    PartRemoved {
+        path: &'a Path,
+    },
+    /// `audit` found an extracted file that no longer matches its CRC, `archive` is the one it came from.
+    Corruption {
+        path: &'a Path,
+        archive: Option<&'a Path>,
+        expected: u32,
+        found: u32,
+    },
+    RunSummary {
+        summary: &'a RunSummary,
+    },
+}
+
+/// Wall-clock time spent extracting an archive.
+#[derive(Debug)]
+pub struct ArchiveTiming {
+    pub path: PathBuf,
+    pub duration: Duration,
+    pub bytes: u64,
+}
+
+impl ArchiveTiming {
+    pub fn mb_per_sec(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs == 0.0 {
+            return f64::INFINITY;
+        }
+        self.bytes as f64 / 1_000_000.0 / secs
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    /// Files with the .rar extension found by the scan, and the ones enqueued as the root of their set. The others
+    /// are later parts of a set or ignored.
+    pub rar_files_seen: u64,
+    pub root_archives_enqueued: u64,
+    /// Directories the scan went through, and how long it took.
+    pub scan_dirs: u64,
+    pub scan_duration: Duration,
+    /// The root directory, when it's empty and listed as a mount point.
+    pub unmounted_root: Option<PathBuf>,
+    pub archives_processed: u64,
+    pub archives_extracted: u64,
+    /// Tarballs found inside of archives and unpacked, not counted as extracted archives.
+    pub tarballs_unpacked: u64,
+    pub parts_removed: u64,
+    /// Filesystem operations run in batches, mtime fixups and removals, how many of them were tried again after a
+    /// transient error and how long the batches took.
+    pub fs_ops: u64,
+    pub fs_op_retries: u64,
+    pub fs_op_duration: Duration,
+    pub tiered_files: u64,
+    pub tiered_bytes: u64,
+    pub empty_dirs_removed: u64,
+    /// Extractions and removals a dry-run would have done. Moves and empty directories are counted as done.
+    pub pending_extractions: u64,
+    pub pending_removals: u64,
+    /// Archives found extracted by something else and recorded as if rarscan had extracted them.
+    pub adopted_extractions: u64,
+    /// Archives found extracted by something else and left unrecorded.
+    pub unrecorded_extractions: u64,
+    /// Files hashed by --verify-crc, and the ones trusted from the state file instead.
+    pub verified_files: u64,
+    pub verify_cache_hits: u64,
+    pub empty_archives: Vec<PathBuf>,
+    pub oversized_archives: Vec<PathBuf>,
+    pub busy_archives: Vec<PathBuf>,
+    pub unsupported_archives: Vec<PathBuf>,
+    pub no_space_archives: Vec<PathBuf>,
+    /// Archives not tried because their destination filled up during an earlier extraction and is still short.
+    pub blocked_archives: Vec<PathBuf>,
+    pub suspected_fakes: Vec<PathBuf>,
+    pub long_name_archives: Vec<PathBuf>,
+    /// Archives with entries whose names differ only in what the filesystem of their destination ignores.
+    pub name_conflict_archives: Vec<PathBuf>,
+    /// Archives whose extraction failed, with the reason.
+    pub failed_archives: Vec<(PathBuf, ExtractionError)>,
+    /// Snapshots taken before removing, one per filesystem.
+    pub snapshots: Vec<Snapshot>,
+    /// Names of the snapshots deleted for being past the retention.
+    pub snapshots_pruned: Vec<String>,
+    /// Archives missing volumes that their recovery volumes can rebuild, with the count of each.
+    pub repairable_archives: Vec<(PathBuf, usize, usize)>,
+    /// Files eligible for removal left for a later run by the removal cap, with their size.
+    pub capped_removals: Vec<(PathBuf, u64)>,
+    /// Archives past their threshold whose parts --keep-newest kept, with their rank and their release.
+    pub spared_archives: Vec<(PathBuf, usize, String)>,
+    /// Devices no longer used after IO errors, by the device mounted and with the count of errors.
+    pub suspended_devices: Vec<(String, usize)>,
+    /// Archives skipped because of a suspended device.
+    pub suspended_archives: Vec<PathBuf>,
+    /// Archives left for a later run because a download is still writing to their directory.
+    pub downloading_archives: Vec<PathBuf>,
+    /// Archives left alone because their sidecar says so.
+    pub sidecar_skipped_archives: Vec<PathBuf>,
+    /// Sets whose volumes don't all follow the name of the first, with the volumes found through their headers.
+    pub renamed_sets: Vec<(PathBuf, Vec<PathBuf>)>,
+    /// Archives moved or deleted by something else between the scan and their processing.
+    pub vanished_archives: Vec<PathBuf>,
+    /// Bytes of parts backed up by --backup-parts-to with a streaming copy, and with a reflink.
+    pub backup_copied_bytes: u64,
+    pub backup_reflinked_bytes: u64,
+    /// Archives whose parts could not be backed up, with the error. Their parts are kept.
+    pub backup_failures: Vec<(PathBuf, String)>,
+    /// Archives some parts of which could not be removed, with the error of each. They are retried the next run.
+    pub partial_removals: Vec<(PathBuf, Vec<(PathBuf, String)>)>,
+    /// Days of backups removed by --backup-retention.
+    pub purged_backups: Vec<PathBuf>,
+    /// Archives whose extraction was abandoned from the dashboard.
+    pub skipped_archives: Vec<PathBuf>,
+    /// Archives extracted again too many times because their files keep changing, with the count and the last file
+    /// found changed.
+    pub flapping_archives: Vec<(PathBuf, u32, PathBuf)>,
+    /// Archives listed inside of other archives and what became of them, grouped by the archive listing them.
+    pub nested_archives: Vec<(PathBuf, Vec<NestedEntry>)>,
+    /// Archives extracted with media files that failed the probe, with the reason for each file.
+    pub invalid_payload_archives: Vec<(PathBuf, Vec<(PathBuf, String)>)>,
+    /// Archives extracted without a file matching --require-content, and chains none of whose members has one.
+    pub missing_content_archives: Vec<PathBuf>,
+    /// Archives left for a later run because another instance kept their destination locked, with the destination.
+    pub locked_archives: Vec<(PathBuf, PathBuf)>,
+    /// Extracted files that went through --scan-command, and the ones it flagged.
+    pub scanned_files: u64,
+    pub flagged_files: u64,
+    pub quarantined_archives: Vec<QuarantineSummary>,
+    /// Archives identical to one extracted elsewhere: the original, the duplicate and what was done with it.
+    pub duplicates: Vec<(PathBuf, PathBuf, &'static str)>,
+    /// Chains of nested archives considered for removal, and what became of them.
+    pub chains: Vec<ChainSummary>,
+    /// Archives grouped by release directory, only for runs scanning a root directory.
+    pub releases: Vec<ReleaseSummary>,
+    pub timings: Vec<ArchiveTiming>,
+    /// Ids of the archives opened by the run, by path.
+    pub archive_ids: BTreeMap<PathBuf, String>,
+}
+
+impl RunSummary {
+    /// The `n` extractions with the lowest throughput, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&ArchiveTiming> {
+        let mut timings: Vec<_> = self.timings.iter().collect();
+        timings.sort_by(|a, b| a.mb_per_sec().total_cmp(&b.mb_per_sec()));
+        timings.truncate(n);
+        timings
+    }
+}
+
+/// A root archive, the archives nested in it with the archive each was found in, and whether their parts were removed.
+#[derive(Debug)]
+pub struct ChainSummary {
+    pub root: PathBuf,
+    pub nested: Vec<(PathBuf, PathBuf)>,
+    pub status: &'static str,
+}
+
+/// An archive listed in the headers of another one: whether it was filtered, ignored, missing or enqueued, and the
+/// outcome of its own processing when it was.
+#[derive(Debug)]
+pub struct NestedEntry {
+    pub path: PathBuf,
+    pub fate: &'static str,
+    pub outcome: Option<&'static str>,
+}
+
+/// Files of an archive flagged by the scanner, and the directory of the quarantine they were moved to.
+#[derive(Debug)]
+pub struct QuarantineSummary {
+    pub archive: PathBuf,
+    pub dir: PathBuf,
+    pub files: Vec<Flagged>,
+}
+
+/// Archives of a release directory and how they fared.
+#[derive(Debug)]
+pub struct ReleaseSummary {
+    pub name: String,
+    pub archives: Vec<(PathBuf, &'static str)>,
+    /// Every archive is extracted.
+    pub complete: bool,
+    /// Archives skipped, deferred or vanished.
+    pub problems: u64,
+    /// Size of the archives and leftovers still in the release once the run is over.
+    pub pending_removal_bytes: u64,
+}
+
+fn releases_to_json(releases: &[ReleaseSummary], ids: &BTreeMap<PathBuf, String>) -> Value {
+    releases
+        .iter()
+        .map(|release| {
+            let archives: Vec<Value> = release
+                .archives
+                .iter()
+                .map(|(path, status)| {
+                    json!({
+                        "path": path.to_string_lossy(),
+                        "id": ids.get(path),
+                        "status": status,
+                    })
+                })
+                .collect();
+            json!({
+                "release": release.name,
+                "archives": archives,
+                "complete": release.complete,
+                "problems": release.problems,
+                "pending_removal_bytes": release.pending_removal_bytes,
+            })
+        })
+        .collect()
+}
+
+/// Number of failed archives by kind of failure.
+fn failure_counts(failed: &[(PathBuf, ExtractionError)]) -> Value {
+    let mut counts = serde_json::Map::new();
+    for (_, failure) in failed {
+        let count = counts.entry(failure.kind()).or_insert(Value::from(0));
+        *count = Value::from(count.as_u64().unwrap_or(0) + 1);
+    }
+    Value::Object(counts)
+}
+
+fn paths_to_json(paths: &[PathBuf]) -> Value {
+    paths.iter().map(|p| Value::from(p.to_string_lossy())).collect()
+}
+
+impl Event<'_> {
+    fn to_json(&self, dropped_events: u64) -> Value {
+        match self {
+            Event::ScanStart { root_dir } => json!({
+                "event": "scan_start",
+                "root_dir": root_dir.to_string_lossy(),
+            }),
+            Event::ArchiveFound { path } => json!({
+                "event": "archive_found",
+                "path": path.to_string_lossy(),
+            }),
+            Event::ScanProgress {
+                progress,
+                rar_files,
+                archives,
+            } => json!({
+                "event": "scan_progress",
+                "dirs": progress.dirs,
+                "top_dirs_entered": progress.top_dirs_entered,
+                "top_dirs": progress.top_dirs,
+                "rar_files": rar_files,
+                "archives": archives,
+            }),
+            Event::ScanDone {
+                dirs,
+                rar_files,
+                archives,
+                duration,
+            } => json!({
+                "event": "scan_done",
+                "dirs": dirs,
+                "rar_files": rar_files,
+                "archives": archives,
+                "duration_secs": duration.as_secs_f64(),
+            }),
+            Event::ArchiveStart { archive } => json!({
+                "event": "archive_start",
+                "archive": archive.to_string_lossy(),
+            }),
+            Event::ExtractStart {
+                archive,
+                archive_id,
+                format,
+                dest,
+                packed_size,
+                unpacked_size,
+            } => json!({
+                "event": "extract_start",
+                "archive": archive.to_string_lossy(),
+                "archive_id": archive_id,
+                "format": format.to_string(),
+                "dest": dest.to_string_lossy(),
+                "packed_size": packed_size,
+                "unpacked_size": unpacked_size,
+            }),
+            Event::FileExtracted { archive, file, size } => json!({
+                "event": "file_extracted",
+                "archive": archive.to_string_lossy(),
+                "file": file.to_string_lossy(),
+                "size": size,
+            }),
+            Event::ExtractDone { archive, timing } => json!({
+                "event": "extract_done",
+                "archive": archive.to_string_lossy(),
+                "duration_secs": timing.duration.as_secs_f64(),
+                "bytes": timing.bytes,
+                "mb_per_sec": timing.mb_per_sec(),
+            }),
+            Event::ArchiveDone {
+                archive,
+                archive_id,
+                outcome,
+            } => json!({
+                "event": "archive_done",
+                "archive": archive.to_string_lossy(),
+                "archive_id": archive_id,
+                "outcome": outcome,
+            }),
+            Event::PartRemoved { path } => json!({
+                "event": "part_removed",
+                "path": path.to_string_lossy(),
+            }),
+            Event::Corruption {
+                path,
+                archive,
+                expected,
+                found,
+            } => json!({
+                "event": "corruption",
+                "path": path.to_string_lossy(),
+                "archive": archive.map(|archive| archive.to_string_lossy()),
+                "expected_crc": format!("{:08x}", expected),
+                "found_crc": format!("{:08x}", found),
+            }),
+            Event::RunSummary { summary } => json!({
+                "event": "run_summary",
+                "rar_files_seen": summary.rar_files_seen,
+                "root_archives_enqueued": summary.root_archives_enqueued,
+                "scan_dirs": summary.scan_dirs,
+                "scan_duration_secs": summary.scan_duration.as_secs_f64(),
+                "unmounted_root": summary.unmounted_root.as_ref().map(|root| root.to_string_lossy()),
+                "archives_processed": summary.archives_processed,
+                "archives_extracted": summary.archives_extracted,
+                "tarballs_unpacked": summary.tarballs_unpacked,
+                "parts_removed": summary.parts_removed,
+                "fs_ops": summary.fs_ops,
+                "fs_op_retries": summary.fs_op_retries,
+                "fs_op_duration_secs": summary.fs_op_duration.as_secs_f64(),
+                "tiered_files": summary.tiered_files,
+                "tiered_bytes": summary.tiered_bytes,
+                "empty_dirs_removed": summary.empty_dirs_removed,
+                "pending_extractions": summary.pending_extractions,
+                "pending_removals": summary.pending_removals,
+                "adopted_extractions": summary.adopted_extractions,
+                "unrecorded_extractions": summary.unrecorded_extractions,
+                "verified_files": summary.verified_files,
+                "verify_cache_hits": summary.verify_cache_hits,
+                "empty_archives": paths_to_json(&summary.empty_archives),
+                "oversized_archives": paths_to_json(&summary.oversized_archives),
+                "busy_archives": paths_to_json(&summary.busy_archives),
+                "unsupported_archives": paths_to_json(&summary.unsupported_archives),
+                "no_space_archives": paths_to_json(&summary.no_space_archives),
+                "blocked_archives": paths_to_json(&summary.blocked_archives),
+                "suspected_fakes": paths_to_json(&summary.suspected_fakes),
+                "long_name_archives": paths_to_json(&summary.long_name_archives),
+                "name_conflict_archives": paths_to_json(&summary.name_conflict_archives),
+                "downloading_archives": paths_to_json(&summary.downloading_archives),
+                "missing_content_archives": paths_to_json(&summary.missing_content_archives),
+                "locked_archives": summary.locked_archives.iter().map(|(archive, dest)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "dest": dest.to_string_lossy(),
+                })).collect::<Vec<_>>(),
+                "sidecar_skipped_archives": paths_to_json(&summary.sidecar_skipped_archives),
+                "capped_removals": summary.capped_removals.iter().map(|(path, size)| json!({
+                    "path": path.to_string_lossy(),
+                    "size": size,
+                })).collect::<Vec<_>>(),
+                "spared_archives": summary.spared_archives.iter().map(|(archive, rank, release)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "rank": rank,
+                    "release": release,
+                })).collect::<Vec<_>>(),
+                "suspended_devices": summary.suspended_devices.iter().map(|(device, errors)| json!({
+                    "device": device,
+                    "io_errors": errors,
+                })).collect::<Vec<_>>(),
+                "suspended_archives": paths_to_json(&summary.suspended_archives),
+                "renamed_sets": summary.renamed_sets.iter().map(|(archive, volumes)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "volumes": paths_to_json(volumes),
+                })).collect::<Vec<_>>(),
+                "vanished_archives": paths_to_json(&summary.vanished_archives),
+                "backup_copied_bytes": summary.backup_copied_bytes,
+                "backup_reflinked_bytes": summary.backup_reflinked_bytes,
+                "backup_failures": summary.backup_failures.iter().map(|(archive, error)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "error": error,
+                })).collect::<Vec<_>>(),
+                "partial_removals": summary.partial_removals.iter().map(|(archive, failed)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "failed": failed.iter().map(|(path, error)| json!({
+                        "path": path.to_string_lossy(),
+                        "error": error,
+                    })).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>(),
+                "purged_backups": paths_to_json(&summary.purged_backups),
+                "skipped_archives": paths_to_json(&summary.skipped_archives),
+                "failed_archives": summary.failed_archives.iter().map(|(archive, failure)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "kind": failure.kind(),
+                    "error": failure.to_string(),
+                })).collect::<Vec<_>>(),
+                "failures": failure_counts(&summary.failed_archives),
+                "snapshots": summary.snapshots.iter().map(|snapshot| json!({
+                    "name": snapshot.name,
+                    "volume": snapshot.volume.to_string_lossy(),
+                    "created": datetime::rfc3339(snapshot.created),
+                })).collect::<Vec<_>>(),
+                "snapshots_pruned": summary.snapshots_pruned,
+                "repairable_archives": summary.repairable_archives.iter().map(|(archive, missing, recovery)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "missing_volumes": missing,
+                    "recovery_volumes": recovery,
+                })).collect::<Vec<_>>(),
+                "flapping_archives": summary.flapping_archives.iter().map(|(archive, count, file)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "reextractions": count,
+                    "file": file.to_string_lossy(),
+                })).collect::<Vec<_>>(),
+                "nested_archives": summary.nested_archives.iter().map(|(archive, entries)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "nested": entries
+                        .iter()
+                        .filter(|entry| entry.fate == "enqueued")
+                        .map(|entry| entry.path.to_string_lossy())
+                        .collect::<Vec<_>>(),
+                    "entries": entries.iter().map(|entry| json!({
+                        "path": entry.path.to_string_lossy(),
+                        "fate": entry.fate,
+                        "outcome": entry.outcome,
+                    })).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>(),
+                "invalid_payload_archives": summary.invalid_payload_archives.iter().map(|(archive, invalid)| json!({
+                    "archive": archive.to_string_lossy(),
+                    "files": invalid.iter().map(|(path, reason)| json!({
+                        "path": path.to_string_lossy(),
+                        "reason": reason,
+                    })).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>(),
+                "scanned_files": summary.scanned_files,
+                "clean_files": summary.scanned_files - summary.flagged_files,
+                "flagged_files": summary.flagged_files,
+                "quarantined_archives": summary.quarantined_archives.iter().map(|quarantine| json!({
+                    "archive": quarantine.archive.to_string_lossy(),
+                    "quarantine": quarantine.dir.to_string_lossy(),
+                    "files": quarantine.files.iter().map(|file| json!({
+                        "path": file.path.to_string_lossy(),
+                        "reason": file.reason,
+                        "output": file.output,
+                    })).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>(),
+                "duplicates": summary.duplicates.iter().map(|(original, duplicate, action)| json!({
+                    "original": original.to_string_lossy(),
+                    "duplicate": duplicate.to_string_lossy(),
+                    "action": action,
+                })).collect::<Vec<_>>(),
+                "chains": summary.chains.iter().map(|chain| json!({
+                    "root": chain.root.to_string_lossy(),
+                    "nested": chain.nested.iter().map(|(archive, parent)| json!({
+                        "archive": archive.to_string_lossy(),
+                        "parent": parent.to_string_lossy(),
+                    })).collect::<Vec<_>>(),
+                    "status": chain.status,
+                })).collect::<Vec<_>>(),
+                "releases": releases_to_json(&summary.releases, &summary.archive_ids),
+                "archive_ids": summary.archive_ids.iter().map(|(path, id)| {
+                    (path.to_string_lossy().into_owned(), Value::from(id.as_str()))
+                }).collect::<serde_json::Map<_, _>>(),
+                "slowest": summary.slowest(3).iter().map(|timing| json!({
+                    "archive": timing.path.to_string_lossy(),
+                    "duration_secs": timing.duration.as_secs_f64(),
+                    "bytes": timing.bytes,
+                    "mb_per_sec": timing.mb_per_sec(),
+                })).collect::<Vec<_>>(),
+                "dropped_events": dropped_events,
+            }),
+        }
+    }
+}
+
+/// The run summary as published in the run_summary event.
+#[cfg(feature = "http")]
+pub fn summary_to_json(summary: &RunSummary) -> Value {
+    Event::RunSummary { summary }.to_json(0)
+}
+
+/// Fan-out of events to the configured sinks. Emitting is a no-op when no sink is configured.
+#[derive(Default)]
+pub struct Events {
+    socket: Option<socket::EventSocket>,
+    /// The events written to stdout in the protocol of `--output ndjson`.
+    stdout: bool,
+    #[cfg(feature = "tui")]
+    dashboard: Option<DashboardSink>,
+}
+
+impl Events {
+    pub fn new() -> Events {
+        Events::default()
+    }
+
+    pub fn with_socket(mut self, path: &Path) -> anyhow::Result<Events> {
+        self.socket = socket::EventSocket::bind(path)?;
+        Ok(self)
+    }
+
+    pub fn with_stdout(mut self) -> Events {
+        self.stdout = true;
+        self
+    }
+
+    #[cfg(feature = "tui")]
+    pub fn with_dashboard(mut self, sink: DashboardSink) -> Events {
+        self.dashboard = Some(sink);
+        self
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        #[cfg(feature = "tui")]
+        if let Some(dashboard) = &self.dashboard {
+            dashboard.observe(&event);
+        }
+        if let Some(socket) = &mut self.socket {
+            let mut line = event.to_json(socket.dropped()).to_string();
+            line.push('\n');
+            socket.send(line.as_bytes());
+        }
+        if self.stdout {
+            // Written as they happen, nothing is dropped: a wrapper that doesn't read stdout holds up the run.
+            let line = protocol::line(event.to_json(0));
+            debug_assert_eq!(protocol::validate(&line), Ok(()), "{}", line);
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(line.as_bytes()).and_then(|()| stdout.flush());
+        }
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use std::{
+        fs,
+        io::{self, Write},
+        os::unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::Context;
+
+    /// Maximum amount of bytes buffered for a slow client before events start getting dropped.
+    const MAX_PENDING: usize = 64 * 1024;
+
+    struct Client {
+        stream: UnixStream,
+        pending: Vec<u8>,
+    }
+
+    impl Client {
+        /// Write as much of the pending buffer as the socket accepts without blocking. Returns false if the client
+        /// went away.
+        fn flush(&mut self) -> bool {
+            while !self.pending.is_empty() {
+                match self.stream.write(&self.pending) {
+                    Ok(0) => return false,
+                    Ok(n) => {
+                        self.pending.drain(..n);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return false,
+                }
+            }
+            true
+        }
+    }
+
+    pub struct EventSocket {
+        path: PathBuf,
+        listener: UnixListener,
+        clients: Vec<Client>,
+        dropped: u64,
+    }
+
+    impl EventSocket {
+        pub fn bind(path: &Path) -> anyhow::Result<Option<EventSocket>> {
+            // A socket left behind by a previous run would make bind fail, but never remove anything else.
+            if let Ok(md) = fs::symlink_metadata(path) {
+                if md.file_type().is_socket() {
+                    fs::remove_file(path).context("remove stale event socket")?;
+                }
+            }
+            let listener = UnixListener::bind(path).context("bind event socket")?;
+            listener
+                .set_nonblocking(true)
+                .context("set event socket non-blocking")?;
+            log::info!("Publishing events on '{}'.", path.display());
+            Ok(Some(EventSocket {
+                path: path.to_path_buf(),
+                listener,
+                clients: Vec::new(),
+                dropped: 0,
+            }))
+        }
+
+        pub fn dropped(&self) -> u64 {
+            self.dropped
+        }
+
+        fn accept_clients(&mut self) {
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        if stream.set_nonblocking(true).is_ok() {
+                            log::debug!("Event socket client connected.");
+                            self.clients.push(Client {
+                                stream,
+                                pending: Vec::new(),
+                            });
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+        }
+
+        pub fn send(&mut self, line: &[u8]) {
+            self.accept_clients();
+            let mut dropped = 0;
+            self.clients.retain_mut(|client| {
+                if client.pending.len() + line.len() > MAX_PENDING {
+                    dropped += 1;
+                } else {
+                    client.pending.extend_from_slice(line);
+                }
+                let alive = client.flush();
+                if !alive {
+                    log::debug!("Event socket client disconnected.");
+                }
+                alive
+            });
+            self.dropped += dropped;
+        }
+    }
+
+    impl Drop for EventSocket {
+        fn drop(&mut self) {
+            for client in &mut self.clients {
+                // Best effort, a client that can't keep up loses whatever is still buffered.
+                client.flush();
+            }
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod socket {
+    use std::path::Path;
+
+    pub struct EventSocket;
+
+    impl EventSocket {
+        pub fn bind(_: &Path) -> anyhow::Result<Option<EventSocket>> {
+            log::warn!("Unix domain sockets are not available on this platform, --event-socket is ignored.");
+            Ok(None)
+        }
+
+        pub fn dropped(&self) -> u64 {
+            0
+        }
+
+        pub fn send(&mut self, _: &[u8]) {}
+    }
+}
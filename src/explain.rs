@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    archive::{EntryCheck, Found},
+    format_size,
+};
+
+/// What `explain` found out about an archive: each check a run makes, in order, with what it looked at and what came
+/// of it, then what the next run would do.
+#[derive(Debug)]
+pub struct Explanation {
+    archive: PathBuf,
+    steps: Vec<Step>,
+    verdict: Option<String>,
+}
+
+/// What the already-extracted check found for an entry, as a line of the explanation.
+pub fn describe_entry(check: &EntryCheck) -> String {
+    let name = check.name.display();
+    match (check.expected, check.found) {
+        (None, Found::Directory) => format!("'{}' directory, in place", name),
+        (Some(size), Found::File(found)) if size == found => format!("'{}' {}, in place", name, format_size(size)),
+        (Some(size), Found::File(found)) => format!("'{}' {}, {} wanted", name, format_size(found), format_size(size)),
+        (Some(size), Found::Missing) => format!("'{}' missing, {} wanted", name, format_size(size)),
+        (None, Found::Missing) => format!("'{}' missing, a directory wanted", name),
+        (Some(size), Found::Directory) => format!("'{}' is a directory, a file of {} wanted", name, format_size(size)),
+        (None, Found::File(_)) => format!("'{}' is a file, a directory wanted", name),
+    }
+}
+
+#[derive(Debug)]
+struct Step {
+    check: &'static str,
+    outcome: String,
+    details: Vec<String>,
+}
+
+impl Explanation {
+    pub fn new(archive: &Path) -> Explanation {
+        Explanation {
+            archive: archive.to_path_buf(),
+            steps: Vec::new(),
+            verdict: None,
+        }
+    }
+
+    pub fn step(&mut self, check: &'static str, outcome: impl Into<String>) {
+        self.steps.push(Step {
+            check,
+            outcome: outcome.into(),
+            details: Vec::new(),
+        });
+    }
+
+    /// Adds a line below the last step, such as one of the files it looked at.
+    pub fn detail(&mut self, detail: impl Into<String>) {
+        if let Some(step) = self.steps.last_mut() {
+            step.details.push(detail.into());
+        }
+    }
+
+    /// Ends the explanation with what the next run would do.
+    pub fn verdict(&mut self, verdict: impl Into<String>) {
+        self.verdict = Some(verdict.into());
+    }
+
+    pub fn print(&self) {
+        println!("{:<24} {}", "Archive", self.archive.display());
+        for step in &self.steps {
+            println!("{:<24} {}", step.check, step.outcome);
+            for detail in &step.details {
+                println!("{:<24} -> {}", "", detail);
+            }
+        }
+        if let Some(verdict) = &self.verdict {
+            println!("{:<24} {}", "Next run", verdict);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{fmt, io, path::PathBuf};
+
+use serde_json::{json, Value};
+
+/// Why an extraction failed, classified from the error codes of the archive libraries.
+#[derive(Debug, Clone)]
+pub enum ExtractionError {
+    /// Damaged data or headers. The volume is known for single part archives only.
+    Corrupt {
+        volume: Option<PathBuf>,
+        file: Option<PathBuf>,
+    },
+    /// A volume of a multipart set couldn't be opened. The name is known when it follows a naming scheme.
+    MissingVolume {
+        name: Option<PathBuf>,
+    },
+    WrongPassword,
+    /// A volume couldn't be read, the kind of error is known when it wasn't reported by unrar.
+    ReadError {
+        path: PathBuf,
+        io_kind: Option<io::ErrorKind>,
+    },
+    /// A file couldn't be created or written in the destination.
+    CreateError {
+        path: PathBuf,
+        io_kind: Option<io::ErrorKind>,
+    },
+    /// A file or directory of the destination belongs to another user and can't be written, as files extracted by a
+    /// run as root are to later runs as a user.
+    NotOwned {
+        path: PathBuf,
+        owner: String,
+    },
+    /// The sidecar of the archive couldn't be read or holds an invalid option, at the line when known.
+    InvalidSidecar {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    /// The extraction wrote more than the headers of the archive declare, aborted at `file`, which was removed.
+    Oversized {
+        file: PathBuf,
+        written: u64,
+    },
+    /// The processing of the archive panicked, a bug of rarscan or unrar rather than anything wrong with the archive.
+    Panicked {
+        message: String,
+    },
+    Unknown {
+        message: String,
+    },
+}
+
+impl ExtractionError {
+    /// Short name of the classification, used in the run summary.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ExtractionError::Corrupt { .. } => "corrupt",
+            ExtractionError::MissingVolume { .. } => "missing_volume",
+            ExtractionError::WrongPassword => "wrong_password",
+            ExtractionError::ReadError { .. } => "read_error",
+            ExtractionError::CreateError { .. } => "create_error",
+            ExtractionError::NotOwned { .. } => "not_owned",
+            ExtractionError::InvalidSidecar { .. } => "invalid_sidecar",
+            ExtractionError::Oversized { .. } => "oversized",
+            ExtractionError::Panicked { .. } => "panic",
+            ExtractionError::Unknown { .. } => "unknown",
+        }
+    }
+
+    /// What is likely to fix it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ExtractionError::Corrupt { .. } => {
+                "Download the damaged volume again or repair it with its recovery record."
+            }
+            ExtractionError::MissingVolume { .. } => "The set is incomplete, wait for the download to finish.",
+            ExtractionError::WrongPassword => "The archive is encrypted, extract it by hand with its password.",
+            ExtractionError::ReadError { .. } => "Check the permissions of the archive and the health of its disk.",
+            ExtractionError::CreateError { .. } => "Check the permissions and free space of the destination.",
+            ExtractionError::NotOwned { .. } => {
+                "Left by a run as another user, such as one with sudo. `rarscan fix-ownership` gives the files back."
+            }
+            ExtractionError::InvalidSidecar { .. } => "Fix the sidecar or remove it, the archive is tried again then.",
+            ExtractionError::Oversized { .. } => "The archive holds more than its headers declare, check it by hand.",
+            ExtractionError::Panicked { .. } => {
+                "A bug, report it with the message and the archive if you can share it."
+            }
+            ExtractionError::Unknown { .. } => "Try extracting the archive by hand to see what's wrong.",
+        }
+    }
+
+    /// Whether the destination is at fault rather than the archive.
+    pub fn is_destination(&self) -> bool {
+        matches!(
+            self,
+            ExtractionError::CreateError { .. } | ExtractionError::NotOwned { .. }
+        )
+    }
+
+    /// Whether the device failed, as a dying disk does, rather than the permissions, the free space or the archive.
+    pub fn is_io(&self) -> bool {
+        match self {
+            ExtractionError::ReadError { io_kind, .. } => io_kind.is_none_or(is_device_error),
+            ExtractionError::CreateError { io_kind, .. } => io_kind.is_some_and(is_device_error),
+            _ => false,
+        }
+    }
+
+    /// The failure as JSON, to carry it out of the extraction worker of --sandbox.
+    pub fn to_json(&self) -> Value {
+        let path = |path: &PathBuf| path.to_string_lossy().into_owned();
+        let io_kind = |kind: &Option<io::ErrorKind>| kind.map(|kind| format!("{:?}", kind));
+        let fields = match self {
+            ExtractionError::Corrupt { volume, file } => json!({
+                "volume": volume.as_ref().map(path),
+                "file": file.as_ref().map(path),
+            }),
+            ExtractionError::MissingVolume { name } => json!({ "name": name.as_ref().map(path) }),
+            ExtractionError::WrongPassword => json!({}),
+            ExtractionError::ReadError { path: p, io_kind: kind }
+            | ExtractionError::CreateError { path: p, io_kind: kind } => {
+                json!({ "path": path(p), "io_kind": io_kind(kind) })
+            }
+            ExtractionError::NotOwned { path: p, owner } => json!({ "path": path(p), "owner": owner }),
+            ExtractionError::InvalidSidecar { path: p, line, message } => {
+                json!({ "path": path(p), "line": line, "message": message })
+            }
+            ExtractionError::Oversized { file, written } => json!({ "file": path(file), "written": written }),
+            ExtractionError::Panicked { message } | ExtractionError::Unknown { message } => {
+                json!({ "message": message })
+            }
+        };
+        let mut value = json!({ "kind": self.kind() });
+        if let (Some(value), Value::Object(fields)) = (value.as_object_mut(), fields) {
+            value.extend(fields);
+        }
+        value
+    }
+
+    /// The failure `to_json` gave, `None` when it isn't one.
+    pub fn from_json(value: &Value) -> Option<ExtractionError> {
+        let string = |field: &str| value.get(field).and_then(Value::as_str).map(str::to_string);
+        let path = |field: &str| string(field).map(PathBuf::from);
+        let io_kind = || {
+            string("io_kind").map(|name| {
+                IO_KINDS
+                    .iter()
+                    .copied()
+                    .find(|kind| format!("{:?}", kind) == name)
+                    .unwrap_or(io::ErrorKind::Other)
+            })
+        };
+        Some(match value.get("kind")?.as_str()? {
+            "corrupt" => ExtractionError::Corrupt {
+                volume: path("volume"),
+                file: path("file"),
+            },
+            "missing_volume" => ExtractionError::MissingVolume { name: path("name") },
+            "wrong_password" => ExtractionError::WrongPassword,
+            "read_error" => ExtractionError::ReadError {
+                path: path("path")?,
+                io_kind: io_kind(),
+            },
+            "create_error" => ExtractionError::CreateError {
+                path: path("path")?,
+                io_kind: io_kind(),
+            },
+            "not_owned" => ExtractionError::NotOwned {
+                path: path("path")?,
+                owner: string("owner")?,
+            },
+            "invalid_sidecar" => ExtractionError::InvalidSidecar {
+                path: path("path")?,
+                line: value.get("line").and_then(Value::as_u64).map(|line| line as usize),
+                message: string("message")?,
+            },
+            "oversized" => ExtractionError::Oversized {
+                file: path("file")?,
+                written: value.get("written").and_then(Value::as_u64)?,
+            },
+            "panic" => ExtractionError::Panicked {
+                message: string("message")?,
+            },
+            "unknown" => ExtractionError::Unknown {
+                message: string("message")?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// The kinds of I/O errors told apart across the extraction worker, any other one comes back as `Other`.
+const IO_KINDS: &[io::ErrorKind] = &[
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::AlreadyExists,
+    io::ErrorKind::StorageFull,
+    io::ErrorKind::QuotaExceeded,
+    io::ErrorKind::ReadOnlyFilesystem,
+    io::ErrorKind::FileTooLarge,
+    io::ErrorKind::InvalidFilename,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::Unsupported,
+    io::ErrorKind::IsADirectory,
+    io::ErrorKind::NotADirectory,
+    io::ErrorKind::DirectoryNotEmpty,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::Interrupted,
+];
+
+fn is_device_error(kind: io::ErrorKind) -> bool {
+    use io::ErrorKind::*;
+
+    !matches!(
+        kind,
+        PermissionDenied
+            | NotFound
+            | AlreadyExists
+            | StorageFull
+            | QuotaExceeded
+            | ReadOnlyFilesystem
+            | FileTooLarge
+            | InvalidFilename
+            | InvalidInput
+            | Unsupported
+            | IsADirectory
+            | NotADirectory
+            | DirectoryNotEmpty
+    )
+}
+
+impl fmt::Display for ExtractionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractionError::Corrupt { volume, file } => {
+                write!(f, "corrupt data")?;
+                if let Some(file) = file {
+                    write!(f, " in '{}'", file.display())?;
+                }
+                if let Some(volume) = volume {
+                    write!(f, " of volume '{}'", volume.display())?;
+                }
+                Ok(())
+            }
+            ExtractionError::MissingVolume { name: Some(name) } => write!(f, "missing volume '{}'", name.display()),
+            ExtractionError::MissingVolume { name: None } => write!(f, "missing volume"),
+            ExtractionError::WrongPassword => write!(f, "missing or wrong password"),
+            ExtractionError::ReadError { path, io_kind } => {
+                write!(f, "could not read '{}'", path.display())?;
+                if let Some(kind) = io_kind {
+                    write!(f, ": {}", kind)?;
+                }
+                Ok(())
+            }
+            ExtractionError::CreateError { path, io_kind } => {
+                write!(f, "could not create '{}'", path.display())?;
+                if let Some(kind) = io_kind {
+                    write!(f, ": {}", kind)?;
+                }
+                Ok(())
+            }
+            ExtractionError::NotOwned { path, owner } => {
+                write!(f, "could not write '{}', owned by {}", path.display(), owner)
+            }
+            ExtractionError::InvalidSidecar { path, line, message } => {
+                write!(f, "invalid sidecar '{}'", path.display())?;
+                if let Some(line) = line {
+                    write!(f, " line {}", line)?;
+                }
+                write!(f, ": {}", message)
+            }
+            ExtractionError::Oversized { file, written } => write!(
+                f,
+                "wrote {} bytes up to '{}', more than the archive declares",
+                written,
+                file.display()
+            ),
+            ExtractionError::Panicked { message } => write!(f, "panicked: {}", message),
+            ExtractionError::Unknown { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ExtractionError {}
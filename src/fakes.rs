@@ -0,0 +1,133 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+
+use crate::{archive::Archive, template::file_category};
+
+/// Rules used when no rules file is given.
+const DEFAULT_RULES: &[&str] = &["ext:exe", "ext:scr", "ext:bat", "name:password.txt", "media-mismatch"];
+
+/// Words in a release directory name hinting at its media type.
+const VIDEO_HINTS: &[&str] = &[
+    "1080p", "2160p", "720p", "bluray", "hdtv", "web-dl", "webrip", "x264", "x265",
+];
+const AUDIO_HINTS: &[&str] = &["flac", "mp3", "320kbps", "v0", "lossless"];
+
+/// A heuristic flagging releases that are likely fakes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FakeRule {
+    /// `ext:<extension>`, an entry has this extension.
+    Extension(String),
+    /// `name:<file name>`, an entry has this file name.
+    FileName(String),
+    /// `media-mismatch`, a single entry archive whose file type doesn't match the media type of its directory name.
+    MediaMismatch,
+}
+
+impl FakeRule {
+    pub fn parse(s: &str) -> Result<FakeRule, String> {
+        if s == "media-mismatch" {
+            return Ok(FakeRule::MediaMismatch);
+        }
+        match s.split_once(':') {
+            Some(("ext", ext)) if !ext.is_empty() => {
+                Ok(FakeRule::Extension(ext.trim_start_matches('.').to_lowercase()))
+            }
+            Some(("name", name)) if !name.is_empty() => Ok(FakeRule::FileName(name.to_lowercase())),
+            _ => Err(format!(
+                "invalid rule '{}', expected ext:<extension>, name:<file name> or media-mismatch",
+                s
+            )),
+        }
+    }
+
+    fn matches(&self, archive: &Archive) -> Option<String> {
+        let mut files = archive.headers.iter().filter(|header| header.is_file());
+        match self {
+            FakeRule::Extension(ext) => files
+                .find(|header| {
+                    header
+                        .filename
+                        .extension()
+                        .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case(ext))
+                })
+                .map(|header| format!("it contains '{}'", header.filename.display())),
+            FakeRule::FileName(name) => files
+                .find(|header| {
+                    header
+                        .filename
+                        .file_name()
+                        .is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name))
+                })
+                .map(|header| format!("it contains '{}'", header.filename.display())),
+            FakeRule::MediaMismatch => {
+                let (Some(header), None) = (files.next(), files.next()) else {
+                    return None;
+                };
+                let expected = media_hint(&archive.path)?;
+                let actual = file_category(&header.filename);
+                (actual != expected).then(|| {
+                    format!(
+                        "its only file '{}' is {} but the release looks like {}",
+                        header.filename.display(),
+                        actual,
+                        expected
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Media type suggested by the name of the directory holding the archive.
+fn media_hint(path: &Path) -> Option<&'static str> {
+    let dir = path.parent()?.file_name()?.to_string_lossy().to_lowercase();
+    let words: Vec<&str> = dir.split(|c: char| !c.is_alphanumeric() && c != '-').collect();
+    let has = |hints: &[&str]| words.iter().any(|w| hints.contains(w));
+    if has(VIDEO_HINTS) {
+        Some("video")
+    } else if has(AUDIO_HINTS) {
+        Some("audio")
+    } else {
+        None
+    }
+}
+
+/// Inspects the headers of an archive before extraction for signs of a fake release.
+pub struct FakeDetector {
+    rules: Vec<FakeRule>,
+}
+
+impl Default for FakeDetector {
+    fn default() -> FakeDetector {
+        FakeDetector {
+            rules: DEFAULT_RULES
+                .iter()
+                .map(|rule| FakeRule::parse(rule).unwrap())
+                .collect(),
+        }
+    }
+}
+
+impl FakeDetector {
+    /// Loads rules from a file, one per line. Blank lines and lines starting with `#` are ignored.
+    pub fn load(path: &Path) -> anyhow::Result<FakeDetector> {
+        let content = fs::read_to_string(path).with_context(|| format!("read fake rules '{}'", path.display()))?;
+        let mut rules = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule =
+                FakeRule::parse(line).map_err(|e| anyhow::anyhow!("{}:{}: {}", path.display(), number + 1, e))?;
+            rules.push(rule);
+        }
+        Ok(FakeDetector { rules })
+    }
+
+    /// Returns why the archive looks like a fake, if it does.
+    pub fn check(&self, archive: &Archive) -> Option<String> {
+        self.rules.iter().find_map(|rule| rule.matches(archive))
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    cell::Cell,
+    sync::{Condvar, Mutex, OnceLock, PoisonError},
+};
+
+/// Descriptors left out of the budget for stdio, the log file and the event socket clients.
+const RESERVE: u64 = 32;
+/// Budget used when the limit can't be determined.
+const DEFAULT_BUDGET: usize = 256;
+
+static BUDGET: OnceLock<FdBudget> = OnceLock::new();
+
+thread_local! {
+    /// Descriptors the current thread holds from the budget.
+    static HELD: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts the file descriptors held by operations so that they wait for each other rather than failing with EMFILE.
+/// A thread holding some already doesn't wait for more, it would wait for itself: it may go past the budget, into
+/// the reserve.
+pub struct FdBudget {
+    size: usize,
+    available: Mutex<isize>,
+    released: Condvar,
+}
+
+/// Descriptors acquired from the budget, given back when dropped.
+pub struct FdGuard {
+    count: usize,
+}
+
+/// Soft limit on the number of open files of the process.
+#[cfg(unix)]
+pub fn open_files_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit only writes to `limit`.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)] // rlim_t isn't u64 on every platform.
+    Some(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+pub fn open_files_limit() -> Option<u64> {
+    None
+}
+
+/// Sets up the budget from `max_open_files`, or else from the limit of the process minus a reserve. Returns its size.
+pub fn init(max_open_files: Option<usize>) -> usize {
+    let size = match (max_open_files, open_files_limit()) {
+        (Some(max), _) => max,
+        (None, Some(limit)) => limit.saturating_sub(RESERVE).max(1).min(usize::MAX as u64) as usize,
+        (None, None) => DEFAULT_BUDGET,
+    }
+    .max(1);
+    let budget = FdBudget {
+        size,
+        available: Mutex::new(size.min(isize::MAX as usize) as isize),
+        released: Condvar::new(),
+    };
+    BUDGET.set(budget).ok().expect("file descriptor budget already set");
+    size
+}
+
+/// Acquires `count` descriptors, waiting for other holders to release theirs if needed. Never waits without a budget.
+pub fn acquire(count: usize) -> FdGuard {
+    let Some(budget) = BUDGET.get() else {
+        return FdGuard { count: 0 };
+    };
+    // A request larger than the budget could never be satisfied, let it through alone.
+    let count = count.min(budget.size);
+    let nested = HELD.get() > 0;
+    let mut available = budget.available.lock().unwrap_or_else(PoisonError::into_inner);
+    while !nested && *available < count as isize {
+        available = budget.released.wait(available).unwrap_or_else(PoisonError::into_inner);
+    }
+    *available -= count as isize;
+    HELD.set(HELD.get() + count);
+    FdGuard { count }
+}
+
+impl Drop for FdGuard {
+    fn drop(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        HELD.set(HELD.get() - self.count);
+        if let Some(budget) = BUDGET.get() {
+            *budget.available.lock().unwrap_or_else(PoisonError::into_inner) += self.count as isize;
+            budget.released.notify_all();
+        }
+    }
+}
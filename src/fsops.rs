@@ -0,0 +1,241 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::fds;
+
+/// Operations in flight at once unless --fs-jobs is given.
+pub const DEFAULT_JOBS: usize = 4;
+
+/// Tries of an operation failing with a transient error, the first one included.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for each of the next ones.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A change to the filesystem collected while archives are processed, made with the others of its batch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsOp {
+    Remove(PathBuf),
+    SetModified(PathBuf, SystemTime),
+}
+
+impl FsOp {
+    pub fn path(&self) -> &Path {
+        match self {
+            FsOp::Remove(path) | FsOp::SetModified(path, _) => path,
+        }
+    }
+}
+
+/// The calls to the filesystem the batches make. Tests put latency and failures in between.
+pub trait FileOps: Send + Sync {
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn set_modified(&self, path: &Path, mtime: SystemTime) -> io::Result<()>;
+}
+
+/// The filesystem itself.
+pub struct RealFs;
+
+impl FileOps for RealFs {
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn set_modified(&self, path: &Path, mtime: SystemTime) -> io::Result<()> {
+        let _fds = fds::acquire(1);
+        File::options().write(true).open(path)?.set_modified(mtime)
+    }
+}
+
+/// What a batch did: the result of each operation in the order they were given, the retries and the time it took.
+#[derive(Debug)]
+pub struct BatchReport {
+    pub results: Vec<io::Result<()>>,
+    pub retries: usize,
+    pub elapsed: Duration,
+}
+
+/// Makes batches of operations with up to `jobs` of them in flight. On a network share each of them is a few round
+/// trips, which then overlap. The caller settles what must come first, the audit log and the snapshots, before it
+/// hands over the batch.
+#[derive(Clone)]
+pub struct FsBatcher {
+    fs: Arc<dyn FileOps>,
+    jobs: usize,
+}
+
+impl FsBatcher {
+    pub fn new(fs: Arc<dyn FileOps>, jobs: usize) -> FsBatcher {
+        FsBatcher { fs, jobs: jobs.max(1) }
+    }
+
+    pub fn run(&self, ops: &[FsOp]) -> BatchReport {
+        let started = Instant::now();
+        let next = AtomicUsize::new(0);
+        let retries = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(ops.len()));
+        thread::scope(|scope| {
+            for _ in 0..self.jobs.min(ops.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(op) = ops.get(i) else {
+                        break;
+                    };
+                    let result = self.attempt(op, &retries);
+                    results.lock().expect("results lock poisoned").push((i, result));
+                });
+            }
+        });
+        let mut results = results.into_inner().expect("results lock poisoned");
+        // Back in the order of the batch.
+        results.sort_by_key(|(i, _)| *i);
+        BatchReport {
+            results: results.into_iter().map(|(_, result)| result).collect(),
+            retries: retries.into_inner(),
+            elapsed: started.elapsed(),
+        }
+    }
+
+    fn attempt(&self, op: &FsOp, retries: &AtomicUsize) -> io::Result<()> {
+        let mut delay = RETRY_DELAY;
+        for attempt in 1.. {
+            let result = match op {
+                FsOp::Remove(path) => self.fs.remove_file(path),
+                FsOp::SetModified(path, mtime) => self.fs.set_modified(path, *mtime),
+            };
+            match result {
+                Err(e) if is_transient(&e) && attempt < MAX_ATTEMPTS => {
+                    log::debug!("-> '{}': {}, trying again.", op.path().display(), e);
+                    retries.fetch_add(1, Ordering::Relaxed);
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+        unreachable!("the attempts end with a result")
+    }
+}
+
+/// Errors a network filesystem returns for a moment: a timeout, a busy server or an interrupted call.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::ResourceBusy
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A share answering each call after `latency`, failing the paths named `busy` a few times first and the ones
+    /// named `gone` always.
+    struct SlowFs {
+        latency: Duration,
+        calls: Mutex<Vec<PathBuf>>,
+    }
+
+    impl SlowFs {
+        fn new(latency: Duration) -> SlowFs {
+            SlowFs {
+                latency,
+                calls: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn call(&self, path: &Path) -> io::Result<()> {
+            thread::sleep(self.latency);
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(path.to_path_buf());
+            let tries = calls.iter().filter(|call| *call == path).count();
+            match path.file_name().and_then(|name| name.to_str()) {
+                Some("busy") if tries < MAX_ATTEMPTS as usize => Err(io::ErrorKind::TimedOut.into()),
+                Some("gone") => Err(io::ErrorKind::NotFound.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl FileOps for SlowFs {
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.call(path)
+        }
+
+        fn set_modified(&self, path: &Path, _: SystemTime) -> io::Result<()> {
+            self.call(path)
+        }
+    }
+
+    fn removals(count: usize) -> Vec<FsOp> {
+        (0..count)
+            .map(|i| FsOp::Remove(PathBuf::from(format!("/share/a.part{:02}.rar", i))))
+            .collect()
+    }
+
+    #[test]
+    fn transient_errors_are_tried_again_and_results_keep_their_order() {
+        let fs = Arc::new(SlowFs::new(Duration::ZERO));
+        let ops = [
+            FsOp::Remove(PathBuf::from("/share/a.rar")),
+            FsOp::Remove(PathBuf::from("/share/busy")),
+            FsOp::SetModified(PathBuf::from("/share/gone"), SystemTime::UNIX_EPOCH),
+            FsOp::SetModified(PathBuf::from("/share/b.rar"), SystemTime::UNIX_EPOCH),
+        ];
+        let report = FsBatcher::new(fs.clone(), 3).run(&ops);
+        let kinds: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| r.as_ref().err().map(io::Error::kind))
+            .collect();
+        assert_eq!(kinds, [None, None, Some(io::ErrorKind::NotFound), None]);
+        assert_eq!(report.retries, MAX_ATTEMPTS as usize - 1);
+        // A permanent error is given up on at once.
+        let calls = fs.calls.lock().unwrap();
+        assert_eq!(calls.iter().filter(|call| call.ends_with("gone")).count(), 1);
+        assert_eq!(calls.len(), ops.len() + report.retries);
+    }
+
+    #[test]
+    fn operations_overlap_up_to_the_jobs() {
+        let fs = Arc::new(SlowFs::new(Duration::from_millis(20)));
+        let report = FsBatcher::new(fs.clone(), 4).run(&removals(16));
+        assert!(report.results.iter().all(Result::is_ok));
+        // Four rounds of 20ms, far from the 320ms of one at a time.
+        assert!(report.elapsed < Duration::from_millis(200), "took {:?}", report.elapsed);
+        assert_eq!(fs.calls.lock().unwrap().len(), 16);
+    }
+
+    /// Removes a set of parts from a share with SMB like latency, one at a time and then in batches. Run with
+    /// `cargo test fs_batch_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn fs_batch_benchmark() {
+        let ops = removals(200);
+        for latency in [50, 150] {
+            let fs = Arc::new(SlowFs::new(Duration::from_millis(latency)));
+            let serial = FsBatcher::new(fs.clone(), 1).run(&ops).elapsed;
+            for jobs in [4, 16] {
+                let batched = FsBatcher::new(fs.clone(), jobs).run(&ops).elapsed;
+                println!(
+                    "{} removals at {}ms: {:.2}s one at a time, {:.2}s with {} jobs ({:.1}x)",
+                    ops.len(),
+                    latency,
+                    serial.as_secs_f64(),
+                    batched.as_secs_f64(),
+                    jobs,
+                    serial.as_secs_f64() / batched.as_secs_f64()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::fds;
+
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+/// External program consulted before the parts of an archive are removed. The program receives the archive path as
+/// its only argument, exit status 0 allows the removal and anything else keeps the parts for now.
+pub struct RemovalGate {
+    program: PathBuf,
+    timeout: Duration,
+}
+
+impl RemovalGate {
+    pub fn new(program: impl Into<PathBuf>, timeout: Duration) -> RemovalGate {
+        RemovalGate {
+            program: program.into(),
+            timeout,
+        }
+    }
+
+    /// Whether the gate allows the removal of the parts of `archive`. A gate that can't be run keeps them, the other
+    /// archives of the run are still looked at.
+    pub fn allows(&self, archive: &Path) -> bool {
+        match self.consult(archive) {
+            Ok(allowed) => allowed,
+            Err(e) => {
+                log::error!("-> Removal gate failed: {:#}, keeping parts.", e);
+                false
+            }
+        }
+    }
+
+    fn consult(&self, archive: &Path) -> anyhow::Result<bool> {
+        // Both ends of the stdout pipe until the gate is spawned.
+        let _fds = fds::acquire(2);
+        let mut child = Command::new(&self.program)
+            .arg(archive)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("spawn removal gate '{}'", self.program.display()))?;
+
+        // Read stdout on the side so a chatty gate can't block on a full pipe while we wait for it.
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stdout.read_to_end(&mut buf);
+            let _ = tx.send(buf);
+        });
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().context("wait for removal gate")? {
+                break Some(status);
+            }
+            if started.elapsed() >= self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        // A grandchild may keep the pipe open after the gate itself exited, don't wait on it forever.
+        let output = rx.recv_timeout(OUTPUT_GRACE).unwrap_or_default();
+        let output = String::from_utf8_lossy(&output);
+        let output = output.trim();
+
+        let allowed = match status {
+            Some(status) => {
+                let allowed = status.success();
+                log::info!(
+                    "-> Removal gate {} removal ({}).",
+                    if allowed { "allowed" } else { "denied" },
+                    status
+                );
+                allowed
+            }
+            None => {
+                log::warn!(
+                    "-> Removal gate timed out after {}s, keeping parts.",
+                    self.timeout.as_secs_f64()
+                );
+                false
+            }
+        };
+        if !output.is_empty() {
+            log::info!("-> Removal gate output: {}", output);
+        }
+        Ok(allowed)
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use glob::{MatchOptions, Pattern};
+
+/// Ignore file read from the root directory when it exists.
+pub const DEFAULT_IGNORE_FILE: &str = ".rarscanignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A line of an ignore file.
+#[derive(Debug)]
+struct IgnoreRule {
+    pattern: Pattern,
+    /// Re-includes what an earlier rule ignored, `!` prefix.
+    negated: bool,
+    /// Matches directories only, `/` suffix.
+    dir_only: bool,
+    /// Without a slash the pattern matches the name of a file at any depth, otherwise the path from the root.
+    name_only: bool,
+}
+
+impl IgnoreRule {
+    fn parse(line: &str) -> Result<Option<IgnoreRule>, String> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let name_only = !line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let pattern =
+            Pattern::new(&escape_backslashes(line)).map_err(|e| format!("invalid pattern '{}': {}", line, e))?;
+        Ok(Some(IgnoreRule {
+            pattern,
+            negated,
+            dir_only,
+            name_only,
+        }))
+    }
+
+    fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.name_only {
+            return relative
+                .file_name()
+                .is_some_and(|name| self.pattern.matches_with(&name.to_string_lossy(), MATCH_OPTIONS));
+        }
+        self.pattern.matches_path_with(relative, MATCH_OPTIONS)
+    }
+}
+
+/// Trailing spaces are dropped unless escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+/// Turns the backslash escapes of gitignore into the bracket escapes of glob.
+fn escape_backslashes(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next)) => {
+                chars.next();
+                escaped.push_str(&Pattern::escape(&next.to_string()));
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Paths to leave alone, from files with the syntax of .gitignore relative to the root directory. The last matching
+/// rule wins, and like git a path can't be re-included when one of its parent directories is ignored.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    root_dir: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    pub fn new(root_dir: impl Into<PathBuf>) -> IgnoreRules {
+        IgnoreRules {
+            root_dir: root_dir.into(),
+            rules: Vec::new(),
+        }
+    }
+
+    /// Adds the rules of `text`, after the ones already there.
+    pub fn add(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let rule = IgnoreRule::parse(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            self.rules.extend(rule);
+        }
+        Ok(())
+    }
+
+    pub fn add_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let text = fs::read_to_string(path).with_context(|| format!("read ignore file '{}'", path.display()))?;
+        self.add(&text)
+            .map_err(|e| anyhow::anyhow!("ignore file '{}', {}", path.display(), e))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `path`, a directory when `is_dir` is set, is ignored itself or through one of its parents. Paths outside
+    /// of the root directory aren't.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignoring_rule(path, is_dir).is_some()
+    }
+
+    /// The pattern of the rule ignoring `path`, like [`IgnoreRules::is_ignored`].
+    pub fn ignoring_rule(&self, path: &Path, is_dir: bool) -> Option<&str> {
+        let relative = path.strip_prefix(&self.root_dir).ok()?;
+        let mut parent = PathBuf::new();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            parent.push(component);
+            let last = components.peek().is_none();
+            if let Some(rule) = self.matching(&parent, !last || is_dir) {
+                return Some(rule.pattern.as_str());
+            }
+        }
+        None
+    }
+
+    fn matching(&self, relative: &Path, is_dir: bool) -> Option<&IgnoreRule> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(relative, is_dir))
+            .filter(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(text: &str) -> IgnoreRules {
+        let mut rules = IgnoreRules::new("/root");
+        rules.add(text).unwrap();
+        rules
+    }
+
+    fn ignored(rules: &IgnoreRules, path: &str) -> bool {
+        let is_dir = path.ends_with('/');
+        rules.is_ignored(&Path::new("/root").join(path.trim_end_matches('/')), is_dir)
+    }
+
+    // The examples of the documentation of gitignore.
+    #[test]
+    fn gitignore_examples() {
+        let r = rules("frotz/");
+        assert!(ignored(&r, "frotz/"));
+        assert!(ignored(&r, "a/frotz/"));
+        assert!(ignored(&r, "a/frotz/file"));
+        assert!(!ignored(&r, "a/frotz"));
+
+        let r = rules("doc/frotz/");
+        assert!(ignored(&r, "doc/frotz/"));
+        assert!(!ignored(&r, "a/doc/frotz/"));
+
+        let r = rules("foo/*");
+        assert!(ignored(&r, "foo/test.json"));
+        assert!(ignored(&r, "foo/bar/"));
+        assert!(ignored(&r, "foo/bar/hello.c"));
+        assert!(!ignored(&r, "foo"));
+
+        let r = rules("/*.c");
+        assert!(ignored(&r, "cat-file.c"));
+        assert!(!ignored(&r, "mozilla-sha1/sha1.c"));
+
+        let r = rules("**/foo\n**/foo/bar");
+        assert!(ignored(&r, "foo"));
+        assert!(ignored(&r, "x/y/foo"));
+        assert!(ignored(&r, "x/foo/bar"));
+
+        let r = rules("abc/**");
+        assert!(ignored(&r, "abc/x"));
+        assert!(ignored(&r, "abc/x/y"));
+        assert!(!ignored(&r, "abc/"));
+
+        let r = rules("a/**/b");
+        assert!(ignored(&r, "a/b"));
+        assert!(ignored(&r, "a/x/b"));
+        assert!(ignored(&r, "a/x/y/b"));
+
+        let r = rules("# comment\n\\#hash\n\\!bang\nspace\\ \n");
+        assert!(ignored(&r, "#hash"));
+        assert!(ignored(&r, "!bang"));
+        assert!(ignored(&r, "space "));
+        assert!(!ignored(&r, "# comment"));
+    }
+
+    #[test]
+    fn negation_reincludes() {
+        // Everything except the directory foo/bar.
+        let r = rules("/*\n!/foo\n/foo/*\n!/foo/bar");
+        assert!(ignored(&r, "top.rar"));
+        assert!(ignored(&r, "foo/other/x.rar"));
+        assert!(!ignored(&r, "foo/bar/x.rar"));
+
+        let r = rules("*.rar\n!keep.rar");
+        assert!(ignored(&r, "a/b.rar"));
+        assert!(!ignored(&r, "a/keep.rar"));
+
+        // A file can't be re-included when its directory is ignored.
+        let r = rules("tv/\n!tv/show.rar");
+        assert!(ignored(&r, "tv/show.rar"));
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    format_size,
+    state::StateFile,
+    verify::{self, FileKey},
+};
+
+/// How much `audit` may read in one run. The file in progress is always hashed to the end.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub time: Option<Duration>,
+    pub bytes: Option<u64>,
+}
+
+/// An extracted file whose content no longer matches the CRC recorded when it was verified.
+#[derive(Debug)]
+pub struct Corruption {
+    pub path: PathBuf,
+    /// The archive it was extracted from, and whether its parts are still there to extract it again.
+    pub archive: Option<(PathBuf, bool)>,
+    pub expected: u32,
+    pub found: u32,
+}
+
+/// What `audit` checked and found.
+#[derive(Debug, Default)]
+pub struct IntegritySummary {
+    pub checked: u64,
+    pub bytes: u64,
+    pub corrupt: Vec<Corruption>,
+    /// Files written since they were verified, which the next check of their archive hashes again.
+    pub changed: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+    /// Files with a CRC on record left for the next audit by the budget.
+    pub remaining: usize,
+}
+
+impl IntegritySummary {
+    pub fn log(&self) {
+        for corruption in &self.corrupt {
+            log::error!(
+                "-> '{}' is corrupt, CRC {:08x} instead of {:08x}.",
+                corruption.path.display(),
+                corruption.found,
+                corruption.expected
+            );
+            match &corruption.archive {
+                Some((archive, true)) => log::error!("   Extract it again from '{}'.", archive.display()),
+                Some((archive, false)) => {
+                    log::error!(
+                        "   Extracted from '{}', which is gone, download it again.",
+                        archive.display()
+                    )
+                }
+                None => log::error!("   The archive it was extracted from isn't known."),
+            }
+        }
+        for path in &self.changed {
+            log::info!("-> '{}' changed since it was verified, not audited.", path.display());
+        }
+        for (path, error) in &self.failed {
+            log::error!("-> Could not read '{}': {}", path.display(), error);
+        }
+        log::info!(
+            "Audited {} files, {}, {} corrupt, {} left for the next audit.",
+            self.checked,
+            format_size(self.bytes),
+            self.corrupt.len(),
+            self.remaining
+        );
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.corrupt.is_empty() && self.failed.is_empty()
+    }
+}
+
+/// Hashes the files below `dir` with a CRC on record, the ones verified the longest ago first, until the budget runs
+/// out. The files that still match are verified as of now, which moves them to the back for the next audit: each
+/// run goes on where the last one stopped, and successive ones go round the whole library. Corrupt files keep their
+/// CRC and stay at the front.
+pub fn audit(state: &mut StateFile, dir: &Path, budget: Budget) -> IntegritySummary {
+    let mut summary = IntegritySummary::default();
+    let started = Instant::now();
+    let files = state.audit_order(dir);
+    let total = files.len();
+    for (i, (path, key, expected)) in files.into_iter().enumerate() {
+        let exhausted = budget.time.is_some_and(|time| started.elapsed() >= time)
+            || budget.bytes.is_some_and(|bytes| summary.bytes >= bytes);
+        if exhausted {
+            summary.remaining = total - i;
+            break;
+        }
+        match FileKey::of(&path) {
+            Ok(current) if current == key => {}
+            // Gone since, the next compaction drops it.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Ok(_) => {
+                summary.changed.push(path);
+                continue;
+            }
+            Err(e) => {
+                summary.failed.push((path, e.to_string()));
+                continue;
+            }
+        }
+        let found = match verify::crc32(&path, None, |_, _| Ok(())) {
+            Ok(crc) => crc,
+            Err(e) => {
+                summary.failed.push((path, format!("{:#}", e)));
+                continue;
+            }
+        };
+        summary.checked += 1;
+        summary.bytes += key.size;
+        if found == expected {
+            state.set_verified(&path, key, found);
+            continue;
+        }
+        let archive = state
+            .extracted_from(&path)
+            .map(|archive| (archive.to_path_buf(), archive.exists()));
+        summary.corrupt.push(Corruption {
+            path,
+            archive,
+            expected,
+            found,
+        });
+    }
+    summary
+}
@@ -0,0 +1,41 @@
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use crate::fds;
+
+/// Returns the subset of `paths` currently held open by another process. This is best-effort: it looks at the file
+/// descriptors listed in /proc, so it finds nothing on platforms without /proc or for processes we can't inspect.
+pub fn open_by_other_processes(paths: &[PathBuf]) -> HashSet<PathBuf> {
+    let mut open = HashSet::new();
+    if paths.is_empty() {
+        return open;
+    }
+    // /proc and the fd directory of the process being looked at.
+    let _fds = fds::acquire(2);
+    let wanted: HashSet<PathBuf> = paths.iter().filter_map(|p| fs::canonicalize(p).ok()).collect();
+
+    let Ok(procs) = fs::read_dir("/proc") else {
+        log::debug!("/proc is not available, skipping the in use check");
+        return open;
+    };
+    let own_pid = std::process::id().to_string();
+    for proc in procs.flatten() {
+        let pid = proc.file_name();
+        let pid = pid.to_string_lossy();
+        if pid == own_pid || !pid.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        // Processes of other users or that exited in the meantime can't be read, skip them.
+        let Ok(fds) = fs::read_dir(proc.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if let Ok(target) = fs::read_link(fd.path()) {
+                if wanted.contains(target.as_path()) {
+                    log::debug!("'{}' is open by process {}", target.display(), pid);
+                    open.insert(target);
+                }
+            }
+        }
+    }
+    open
+}
@@ -0,0 +1,238 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::datetime::TimeZone;
+use lazy_static::lazy_static;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use simple_logger::SimpleLogger;
+use time::{
+    format_description::{self, OwnedFormatItem},
+    OffsetDateTime,
+};
+#[cfg(feature = "tui")]
+use {crate::tui::Capture, std::sync::Arc};
+
+lazy_static! {
+    static ref LINE_TIME_FORMAT: OwnedFormatItem =
+        format_description::parse_owned::<2>("[year]-[month]-[day]T[hour]:[minute]:[second]Z").unwrap();
+    static ref LOCAL_LINE_TIME_FORMAT: OwnedFormatItem = format_description::parse_owned::<2>(
+        "[year]-[month]-[day]T[hour]:[minute]:[second][offset_hour sign:mandatory]:[offset_minute]"
+    )
+    .unwrap();
+}
+
+/// Colors are used when the console is a terminal. `CLICOLOR_FORCE` forces them and `NO_COLOR` disables them.
+fn use_colors() -> bool {
+    let set = |name| env::var_os(name).is_some_and(|v| !v.is_empty() && v != "0");
+    if set("CLICOLOR_FORCE") {
+        return true;
+    }
+    if set("NO_COLOR") {
+        return false;
+    }
+    io::stdout().is_terminal()
+}
+
+/// Size based rotation of the log file: `path` is renamed to `path.1`, `path.1` to `path.2` and so on, keeping at most
+/// `keep` rotated files.
+pub struct Rotation {
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+struct FileSink {
+    path: PathBuf,
+    rotation: Rotation,
+    file: Option<File>,
+    size: u64,
+    failing: bool,
+}
+
+impl FileSink {
+    fn open(path: &Path, rotation: Rotation) -> io::Result<FileSink> {
+        let mut sink = FileSink {
+            path: path.to_path_buf(),
+            rotation,
+            file: None,
+            size: 0,
+            failing: false,
+        };
+        sink.reopen()?;
+        Ok(sink)
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(rotated(self.rotation.keep));
+            for n in (1..self.rotation.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.reopen()
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.rotation.max_size {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            self.reopen()?;
+        }
+        // Each line is written with a single call while holding the lock, so lines are never interleaved or split by
+        // a rotation.
+        let file = self.file.as_mut().expect("log file is open");
+        file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn log(&mut self, line: &[u8]) {
+        match self.write_line(line) {
+            Ok(()) => self.failing = false,
+            Err(e) => {
+                // Don't let a full disk stop the run, warn once on stderr and keep trying on the next lines.
+                if !self.failing {
+                    eprintln!("WARN  unable to write to log file '{}': {}", self.path.display(), e);
+                    self.failing = true;
+                }
+                self.file = None;
+            }
+        }
+    }
+}
+
+/// Logger writing to the console and optionally to a rotated log file.
+pub struct Logger {
+    level: LevelFilter,
+    console: Option<SimpleLogger>,
+    /// The console lines go to stderr, stdout is left to the events.
+    stderr: bool,
+    file: Option<Mutex<FileSink>>,
+    time_zone: TimeZone,
+    #[cfg(feature = "tui")]
+    capture: Option<Arc<Capture>>,
+}
+
+impl Logger {
+    pub fn new(level: LevelFilter) -> Logger {
+        Logger {
+            level,
+            console: Some(SimpleLogger::new().with_level(level).with_colors(use_colors())),
+            stderr: false,
+            file: None,
+            time_zone: TimeZone::UTC,
+            #[cfg(feature = "tui")]
+            capture: None,
+        }
+    }
+
+    /// Time zone of the timestamps in the log file, UTC by default.
+    pub fn with_time_zone(mut self, zone: TimeZone) -> Logger {
+        self.time_zone = zone;
+        self
+    }
+
+    pub fn without_console(mut self) -> Logger {
+        self.console = None;
+        self
+    }
+
+    /// Writes the console lines to stderr without colors, in the format of the console logger.
+    pub fn on_stderr(mut self) -> Logger {
+        self.console = None;
+        self.stderr = true;
+        self
+    }
+
+    /// Leaves the console to the dashboard while it is on screen.
+    #[cfg(feature = "tui")]
+    pub fn with_capture(mut self, capture: Arc<Capture>) -> Logger {
+        self.capture = Some(capture);
+        self
+    }
+
+    pub fn with_file(mut self, path: &Path, rotation: Rotation) -> io::Result<Logger> {
+        self.file = Some(Mutex::new(FileSink::open(path, rotation)?));
+        Ok(self)
+    }
+
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self))
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        #[cfg(feature = "tui")]
+        let captured = self.capture.as_ref().is_some_and(|capture| capture.capture(record));
+        #[cfg(not(feature = "tui"))]
+        let captured = false;
+        if let Some(console) = self.console.as_ref().filter(|_| !captured) {
+            console.log(record);
+        }
+        if self.stderr && !captured {
+            eprintln!("{:<5} [{}] {}", record.level(), record.target(), record.args());
+        }
+        if let Some(file) = &self.file {
+            let now = OffsetDateTime::now_utc();
+            let now = now.to_offset(self.time_zone.offset_at(now));
+            let format = if now.offset().is_utc() {
+                &*LINE_TIME_FORMAT
+            } else {
+                &*LOCAL_LINE_TIME_FORMAT
+            };
+            let time = now.format(format).unwrap_or_default();
+            let line = format!(
+                "{} {:<5} [{}] {}\n",
+                time,
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            if let Ok(mut file) = file.lock() {
+                file.log(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(console) = &self.console {
+            console.flush();
+        }
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                if let Some(file) = file.file.as_mut() {
+                    let _ = file.flush();
+                }
+            }
+        }
+    }
+}
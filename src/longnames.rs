@@ -0,0 +1,58 @@
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+};
+
+/// Longest file name most filesystems accept, in bytes.
+pub const NAME_MAX: usize = 255;
+/// Longest path the OS accepts, in bytes.
+pub const PATH_MAX: usize = 4096;
+/// Extensions longer than this aren't kept when shortening a name.
+const MAX_EXTENSION: usize = 16;
+
+/// Whether `path` has a component or a total length over the limits.
+pub fn too_long(path: &Path) -> bool {
+    path.as_os_str().len() >= PATH_MAX || path.components().any(|c| c.as_os_str().len() > NAME_MAX)
+}
+
+/// FNV-1a, stable across versions and platforms unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// Truncates a name over NAME_MAX and appends a hash of the full name so that truncated names stay distinct, keeping
+/// short extensions.
+fn shorten_name(name: &OsStr) -> OsString {
+    if name.len() <= NAME_MAX {
+        return name.to_os_string();
+    }
+    let lossy = name.to_string_lossy();
+    let ext = Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .filter(|ext| ext.len() <= MAX_EXTENSION)
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default();
+    let suffix = format!("~{:016x}{}", fnv1a(name.as_encoded_bytes()), ext);
+    let mut end = NAME_MAX - suffix.len();
+    while !lossy.is_char_boundary(end) {
+        end -= 1;
+    }
+    OsString::from(format!("{}{}", &lossy[..end], suffix))
+}
+
+/// `path` with every component over NAME_MAX shortened. The same component is always shortened the same way, so the
+/// files of a directory stay together.
+pub fn shorten(path: &Path) -> PathBuf {
+    path.components()
+        .map(|c| match c {
+            Component::Normal(name) => shorten_name(name),
+            c => c.as_os_str().to_os_string(),
+        })
+        .collect()
+}
@@ -1,263 +1,6656 @@
+// The run summary event outgrows the default limit of the json! macro.
+#![recursion_limit = "256"]
+
 use std::{
-    collections::VecDeque,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::OsString,
     fs::{self, File},
     io,
+    panic::{self, AssertUnwindSafe},
     path::{Path, PathBuf},
-    time::{Duration, SystemTime},
+    process::{self, ExitCode},
+    sync::{Arc, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Context;
-use clap::Parser;
-use lazy_static::lazy_static;
+use archive::{is_rar_file, is_zip_file, Archive, Format};
+use audit::{Action, AuditFormat, AuditLog};
+use backup::{Backup, Backups};
+use breaker::DeviceBreaker;
+use changelog::{Change, ChangeLog, FileState};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use content::RequiredContent;
+use control::Control;
+use ctl::{CtlSocket, Request};
+use datetime::{TimeContext, TimeZone};
+use dedup::{DuplicatePolicy, Fingerprint};
+use dirlock::{DirLock, Holder};
+use estimate::{Estimate, EstimateFormat, Throughput};
+use events::{ArchiveTiming, ChainSummary, Event, Events, NestedEntry, QuarantineSummary, ReleaseSummary, RunSummary};
+use explain::Explanation;
+use failure::ExtractionError;
+use fakes::FakeDetector;
+use fsops::{FsBatcher, FsOp, RealFs};
+use gate::RemovalGate;
+use ignore::IgnoreRules;
+use logger::{Logger, Rotation};
+use markers::InProgressMarkers;
+use media::MediaProber;
+use names::{NameMatching, NameRules};
+use naming::ArchiveNaming;
+use prealloc::Preallocate;
+use prefetch::Prefetch;
+use privileges::RunAs;
+use protocol::OutputFormat;
+use recovery::SetVolumes;
 use regex::Regex;
-use simple_logger::SimpleLogger;
-use time::{
-    format_description::{self, OwnedFormatItem},
-    OffsetDateTime,
-};
-use unrar::FileHeader;
+use removal::{RemovalCap, RemovalSet};
+use renamemap::{RenameMap, Renamed};
+use retention::{RemoveAfterRule, RemoveAfterRules};
+use sandbox::Sandbox;
+use scanner::Scanner;
+use sidecar::Overrides;
+use simulation::SimulationDir;
+use snapshot::{CommandTemplate, Snapshots};
+use spill::SpillQueue;
+use state::{Chain, StateFile};
+#[cfg(feature = "http")]
+use status::StatusServer;
+use template::DestTemplate;
+use time::format_description::OwnedFormatItem;
+#[cfg(feature = "telemetry")]
+use trace::{SpanId, Tracer};
+#[cfg(feature = "tui")]
+use tui::{Capture, Dashboard, DashboardSink};
+use unpackerr::{ImportSummary, Traces, Verdict};
+use verify::{Checkpoint, FileKey};
+use walk::{Listings, ScanProgress};
+use window::{ActiveDays, ActiveHours, ActiveWindow};
+
+mod archive;
+mod audit;
+mod backup;
+mod breaker;
+mod changelog;
+mod cleanup;
+mod config;
+mod content;
+mod control;
+mod ctl;
+mod datetime;
+mod dedup;
+mod dirlock;
+mod doctor;
+mod durable;
+mod estimate;
+mod events;
+mod explain;
+mod failure;
+mod fakes;
+mod fds;
+mod fsops;
+mod gate;
+mod ignore;
+mod integrity;
+mod inuse;
+mod logger;
+mod longnames;
+mod markers;
+mod media;
+mod mounts;
+mod names;
+mod naming;
+mod ownership;
+mod paths;
+mod perms;
+mod prealloc;
+mod prefetch;
+mod privileges;
+mod protocol;
+mod rarstream;
+mod recovery;
+mod release;
+mod removal;
+mod renamemap;
+mod retention;
+mod sandbox;
+mod scanner;
+mod setup;
+mod sidecar;
+mod simulation;
+mod snapshot;
+mod space;
+mod spill;
+mod state;
+#[cfg(feature = "http")]
+mod status;
+mod store;
+mod tarball;
+mod template;
+mod tier;
+#[cfg(feature = "telemetry")]
+mod trace;
+#[cfg(feature = "tui")]
+mod tui;
+mod unpackerr;
+#[cfg(feature = "self-update")]
+mod update;
+mod verify;
+mod volumes;
+mod walk;
+mod window;
+
+/// Spans are never started without the `telemetry` feature.
+#[cfg(not(feature = "telemetry"))]
+type SpanId = std::convert::Infallible;
+
+/// How the dates are shown, UTC and the default format unless --timezone and --time-format are given. Set on the main
+/// thread before any other starts.
+static TIME_CONTEXT: OnceLock<TimeContext> = OnceLock::new();
+
+fn time_context() -> &'static TimeContext {
+    TIME_CONTEXT.get_or_init(TimeContext::utc)
+}
+
+fn format_system_time(t: SystemTime) -> String {
+    time_context().format(t)
+}
 
-lazy_static! {
-    static ref RE_PART_FILE: Regex = Regex::new("part(\\d+).rar$").unwrap();
-    static ref TIME_FORMAT: OwnedFormatItem = format_description::parse_owned::<2>("[year]-[month]-[day]").unwrap();
+/// Parses a human readable size such as `500GiB`, `1.5 TB` or `1024`. Units are powers of 1024.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(format!("invalid size unit '{}'", unit.trim())),
+    };
+    Ok((number * multiplier as f64) as u64)
 }
 
-fn is_root_rar_file(path: &Path) -> bool {
-    let file_name = path.file_name().and_then(|s| s.to_str()).expect("invalid file_name");
-    if let Some(caps) = RE_PART_FILE.captures(file_name) {
-        let part_num = caps[1].parse::<u64>().unwrap();
-        return part_num == 1;
+/// Parses a percentage such as `90%` or `90`, above 0 and up to 100.
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let percent: f64 = s
+        .strip_suffix('%')
+        .unwrap_or(s)
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid percentage '{}'", s))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(format!("percentage '{}' isn't above 0% and up to 100%", s));
     }
-    file_name.ends_with(".rar")
+    Ok(percent)
 }
 
-fn format_system_time(t: SystemTime) -> String {
-    OffsetDateTime::from(t)
-        .format(&TIME_FORMAT)
-        .unwrap_or_else(|_| "Unknown".into())
+/// Size of the file at `path` without following symlinks, 0 when it's gone.
+fn file_size(path: &Path) -> u64 {
+    fs::symlink_metadata(path).map_or(0, |md| md.len())
+}
+
+fn describe_removal_cap(cap: &RemovalCap) -> String {
+    let limits: Vec<String> = [
+        cap.max_files().map(|files| format!("{} files", files)),
+        cap.max_bytes().map(format_size),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    limits.join(" and ")
+}
+
+/// Parses a duration such as `30d`, `12h` or `90m`. A bare number is a number of seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", s))?;
+    let multiplier = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        unit => return Err(format!("invalid duration unit '{}', expected s, m, h, d or w", unit)),
+    };
+    Ok(Duration::from_secs(number * multiplier))
+}
+
+/// Times an archive is extracted again because its files changed before it's reported as flapping.
+const DEFAULT_FLAPPING_THRESHOLD: u32 = 3;
+
+/// Runs in a row the processing of an archive panics before it's left alone.
+const DEFAULT_PANIC_LIMIT: u32 = 3;
+
+/// Memory taken by a queued archive, its path and what is recorded about it once processed.
+const QUEUED_ARCHIVE_COST: u64 = 1024;
+
+/// How long a broken chain of nested archives holds up the removal of its remaining members by default.
+const DEFAULT_CHAIN_GRACE: Duration = Duration::from_secs(72 * 60 * 60);
+
+/// How often a scan still going reports how far it got.
+const SCAN_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a destination locked by another instance is tried again while waiting for it.
+const DIR_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Leftovers of a release once extracted: the old style volumes, which are `.r00` to `.r99` and the like, and checksums.
+fn is_cruft(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext == "sfv" || (ext.starts_with('r') && ext.chars().count() == 3))
+}
+
+/// The pattern of cruft matched by `path`, for the audit log.
+fn cruft_pattern(path: &Path) -> &'static str {
+    match path.extension().is_some_and(|ext| ext == "sfv") {
+        true => "*.sfv",
+        false => "*.r??",
+    }
+}
+
+/// The instance holding a destination lock, for the log.
+fn describe_holder(holder: &Holder) -> String {
+    match holder.pid {
+        Some(pid) => format!("process {} extracting '{}'", pid, holder.archive.display()),
+        None => "another instance".to_string(),
+    }
+}
+
+/// Directory of `path`, `.` for a bare file name.
+fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// The volumes of the set of `nested` other than itself, which `archive` extracted into `dest` along with it.
+fn nested_volumes(
+    archive: &Archive,
+    dest: &Path,
+    nested: &Path,
+    naming: &ArchiveNaming,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let extracted: HashSet<PathBuf> = archive
+        .headers
+        .iter()
+        .filter(|header| header.is_file())
+        .map(|header| paths::canonical(&archive.extracted_path(dest, &header.filename)))
+        .collect();
+    let parts = archive::list_set_parts(nested, naming).context("list nested volumes")?;
+    Ok(parts
+        .iter()
+        .map(|part| paths::canonical(part))
+        .filter(|part| part != nested && extracted.contains(part))
+        .collect())
+}
+
+/// The message a panic was raised with, as far as it is a string.
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
+
+fn compression_ratio(packed_size: u64, unpacked_size: u64) -> f64 {
+    if packed_size == 0 {
+        return 1.0;
+    }
+    unpacked_size as f64 / packed_size as f64
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Extraction aborts when the bytes written exceed the declared unpacked size by this factor.
+const MAX_WRITTEN_FACTOR: f64 = 1.1;
+/// Slack on top of the factor so that tiny archives aren't aborted over rounding.
+const MAX_WRITTEN_SLACK: u64 = 1 << 20;
+
+/// Caps used unless given on the command line.
+const DEFAULT_MAX_UNPACKED_SIZE: u64 = 500 << 30;
+const DEFAULT_MAX_ENTRIES: usize = 1_000_000;
+
+/// Caps evaluated from the headers before an archive is extracted.
+pub struct Limits {
+    pub max_unpacked_size: u64,
+    pub max_entries: usize,
+    /// Whether a cap was given, rar archives are then written by rarscan to stop within an oversized file.
+    pub given: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Extracted,
+    AlreadyExtracted,
+    /// Left untouched because of the limits or because its files are in use.
+    Skipped,
+    /// Moved to the end of the queue to be retried.
+    Deferred,
+    /// Moved or deleted by something else after the scan.
+    Vanished,
+    /// The extraction failed, the reason is in the run summary.
+    Failed,
+    /// The set is missing volumes its recovery volumes can rebuild.
+    Repairable,
+    /// Extracted, but a media file failed the probe.
+    InvalidPayload,
+    /// Left untouched while a download client is still writing to its directory.
+    Downloading,
+    /// Left untouched because its device or the one of its destination had too many IO errors.
+    Suspended,
+    /// Extracted, but the scanner flagged files which were moved to the quarantine directory.
+    Quarantined,
+    /// Extracted, but no file matches --require-content.
+    MissingContent,
+}
+
+impl Outcome {
+    fn status(self) -> &'static str {
+        match self {
+            Outcome::Extracted => "extracted",
+            Outcome::AlreadyExtracted => "already_extracted",
+            Outcome::Skipped => "skipped",
+            Outcome::Deferred => "deferred",
+            Outcome::Vanished => "vanished",
+            Outcome::Failed => "failed",
+            Outcome::Repairable => "repairable",
+            Outcome::InvalidPayload => "invalid_payload",
+            Outcome::Downloading => "download_in_progress",
+            Outcome::Suspended => "device_suspended",
+            Outcome::Quarantined => "quarantined",
+            Outcome::MissingContent => "missing_required_content",
+        }
+    }
+}
+
+/// What became of an archive listed in the headers of another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NestedFate {
+    /// Left out of the extraction of its parent by the exclude patterns of its sidecar.
+    Filtered,
+    /// Extracted, but matched by the ignore rules.
+    Ignored,
+    /// Extracted, and enqueued from another archive already.
+    AlreadyEnqueued,
+    /// Not on disk after the extraction of its parent, moved or removed since.
+    Missing,
+    /// Not extracted, because this is a dry-run.
+    NotExtracted,
+    /// Not extracted in a dry-run, looked into from a copy of its volumes with --deep-dry-run.
+    Simulated,
+    /// Extracted with a parent whose files the scanner flagged, left alone.
+    Quarantined,
+    /// Extracted and enqueued, its own outcome follows once it's processed.
+    Enqueued,
+}
+
+impl NestedFate {
+    fn status(self) -> &'static str {
+        match self {
+            NestedFate::Filtered => "filtered",
+            NestedFate::Ignored => "ignored",
+            NestedFate::AlreadyEnqueued => "already_enqueued",
+            NestedFate::Missing => "missing",
+            NestedFate::NotExtracted => "not_extracted",
+            NestedFate::Simulated => "simulated",
+            NestedFate::Quarantined => "quarantined",
+            NestedFate::Enqueued => "enqueued",
+        }
+    }
+
+    /// Whether the extraction left the nested archive on disk for the scanner to look after. The one of a quarantined
+    /// parent stays as it is.
+    fn on_disk(self) -> bool {
+        matches!(
+            self,
+            NestedFate::Ignored | NestedFate::AlreadyEnqueued | NestedFate::Enqueued
+        )
+    }
+}
+
+/// What a run would do with an archive, worked out without touching anything.
+struct Plan {
+    archive: Archive,
+    dest: PathBuf,
+    extracted: bool,
+    /// Parts old enough to be removed.
+    removable: bool,
+}
+
+/// Where archives found inside of other archives are enqueued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NestedOrder {
+    /// Right after the archive containing them, so that a chain is finished before moving on.
+    Immediate,
+    /// At the end of the queue.
+    Deferred,
+}
+
+/// What to do with an archive extracted again more than --flapping-threshold times because its files keep changing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FlappingPolicy {
+    /// Warn and extract it again.
+    Warn,
+    /// Warn and leave it alone until its files are back in place.
+    Pause,
+}
+
+/// The mtime given to extracted files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExtractedMtime {
+    /// The one stored in the archive.
+    Keep,
+    /// The time of the extraction.
+    Now,
+    /// The mtime of the archive, like the one given to embedded archives.
+    Archive,
 }
 
 pub struct UnarchiveQueue {
     dry_run: bool,
     remove_after: Option<Duration>,
-    queue: VecDeque<PathBuf>,
+    queue: SpillQueue,
+    events: Events,
+    summary: RunSummary,
+    removal_gate: Option<RemovalGate>,
+    removal_cap: Option<RemovalCap>,
+    /// Whether the removals planned were already checked against the cap, before the chains were removed.
+    removal_plan_checked: bool,
+    recovery_tool: Option<PathBuf>,
+    markers: InProgressMarkers,
+    breaker: Option<DeviceBreaker>,
+    window: Option<ActiveWindow>,
+    wait_for_window: bool,
+    /// Whether the run stopped, or never started, outside of the window.
+    outside_window: bool,
+    /// When the window opens, while waiting for it.
+    paused_until: Option<String>,
+    /// Options read from the sidecars of the archives processed, by archive.
+    overrides: HashMap<PathBuf, Overrides>,
+    /// Volumes of renamed sets, found through the headers of their first volume. Never cruft whatever their name.
+    claimed_volumes: HashSet<PathBuf>,
+    snapshots: Option<Snapshots>,
+    /// A snapshot failed, nothing is removed for the rest of the run.
+    removals_aborted: bool,
+    kept_parts: HashSet<PathBuf>,
+    remove_empty_archives: bool,
+    suspicious_ratio: Option<f64>,
+    limits: Option<Limits>,
+    flatten_single_dir: bool,
+    slow_threshold: Option<f64>,
+    naming: ArchiveNaming,
+    skip_in_use: bool,
+    deferred: Vec<PathBuf>,
+    retrying: HashSet<PathBuf>,
+    outcomes: HashMap<PathBuf, Outcome>,
+    /// Parts and cruft removed and extracted files moved away, or that would be in a dry-run.
+    removed: HashSet<PathBuf>,
+    /// Members of archive chains processed by the run with their parts, by the root of their chain. Their parts are
+    /// removed together once the queue is drained.
+    pending_chains: BTreeMap<PathBuf, Vec<(PathBuf, Vec<PathBuf>)>>,
+    /// How long a chain missing a member deleted by something else holds up the removal of the rest.
+    chain_grace: Duration,
+    dest_template: Option<DestTemplate>,
+    /// Archives found inside of other archives, they are extracted where they are instead of the template's destination.
+    nested: HashSet<PathBuf>,
+    /// Canonical paths of the root archives queued so far, another spelling of one of them isn't queued again.
+    enqueued: HashSet<PathBuf>,
+    /// Inodes that must remain free once an archive is extracted.
+    inode_margin: u64,
+    /// Percentage of the destination's filesystem an extraction may bring it to.
+    max_disk_usage: Option<f64>,
+    /// Archives of each release directory whose parts are kept whatever their age, the newest extractions.
+    keep_newest: Option<usize>,
+    /// The archives spared by `keep_newest` in the last removal pass, with their rank and release.
+    spared_newest: HashMap<PathBuf, (usize, String)>,
+    /// Archives deferred for bringing their destination over `max_disk_usage`, retried once the parts of the archives
+    /// extracted before them are removed.
+    usage_deferred: Vec<PathBuf>,
+    /// The worker extracting the archives, confined to their directories and destination, with --sandbox.
+    sandbox: Option<Sandbox>,
+    nested_order: NestedOrder,
+    /// Whether the tarballs found inside of archives are unpacked like nested archives.
+    unpack_tarballs: bool,
+    /// Directories the paths of rename maps may point into, besides the archive's directory and its destination.
+    map_roots: Vec<PathBuf>,
+    extracted_mtime: ExtractedMtime,
+    preallocate: Preallocate,
+    fast_store_copy: bool,
+    /// The size up to which a dry-run looks into nested archives from a copy, with --deep-dry-run.
+    deep_dry_run: Option<u64>,
+    state: Option<StateFile>,
+    /// Whether setting mtimes works, by filesystem.
+    mtime_support: HashMap<u64, bool>,
+    fake_detector: Option<FakeDetector>,
+    changelog: Option<ChangeLog>,
+    audit_log: Option<AuditLog>,
+    backups: Option<Backups>,
+    /// Archives whose backup failed during this run, their parts are kept.
+    backup_failed: HashSet<PathBuf>,
+    remove_after_rules: Option<RemoveAfterRules>,
+    shorten_long_names: bool,
+    name_matching: NameMatching,
+    /// How the filesystems of the destinations compare names, by device.
+    name_rules: HashMap<u64, NameRules>,
+    prefetch_headers: bool,
+    prefetch: Option<Prefetch>,
+    follow_symlinks: bool,
+    /// Read every directory of the scan, even the ones unchanged since the last scan.
+    full_scan: bool,
+    symlinked_dirs: Vec<PathBuf>,
+    /// Every archive file found by the scan, by file name.
+    scanned: HashMap<OsString, Vec<PathBuf>>,
+    auto_adopt: bool,
+    fsync: bool,
+    inherit_dir_perms: bool,
+    duplicate_policy: DuplicatePolicy,
+    memory_bounded: bool,
+    media_prober: Option<MediaProber>,
+    /// Keep the parts of archives whose payload fails the media probe, for downloading them again.
+    require_valid_media: bool,
+    required_content: Option<RequiredContent>,
+    /// How long an archive retried at the end of the run waits for the lock of its destination.
+    dir_lock_wait: Duration,
+    /// Archives of the run with a file matching --require-content, for the chains they belong to.
+    content_found: HashSet<PathBuf>,
+    /// Runs the mtime fixups and the removals, several at once.
+    fs_batcher: FsBatcher,
+    /// Parts audited for removal and not removed yet, with their archive, none for cruft. Removed together by
+    /// [`UnarchiveQueue::flush_removals`].
+    queued_removals: Vec<(Option<PathBuf>, PathBuf)>,
+    scanner: Option<Scanner>,
+    verify_crc: bool,
+    /// Hash every file again instead of trusting the ones verified by earlier runs.
+    revalidate: bool,
+    /// Root directory of the scan, archives are grouped by release directory below it.
+    root_dir: Option<PathBuf>,
+    /// Releases left out by --only-incomplete-releases.
+    complete_releases: HashSet<String>,
+    #[cfg(feature = "http")]
+    status_server: Option<StatusServer>,
+    #[cfg(feature = "telemetry")]
+    tracer: Option<Tracer>,
+    control: Option<Arc<Control>>,
+    ctl_socket: Option<CtlSocket>,
+    ignore_rules: Option<IgnoreRules>,
+    /// Parts recorded for removal by the archives processed, removed by `apply_removals`.
+    removals: RemovalSet,
+    /// The parts of the sets `apply_removals` decided on, with their archive. The cruft pass leaves them to it.
+    known_parts: HashMap<PathBuf, PathBuf>,
+    no_remove: bool,
+    flapping_policy: FlappingPolicy,
+    flapping_threshold: u32,
+    panic_limit: u32,
 }
 
 impl UnarchiveQueue {
-    pub fn new(dry_run: bool, remove_after: Option<Duration>) -> UnarchiveQueue {
+    pub fn new(dry_run: bool, remove_after: Option<Duration>, events: Events) -> UnarchiveQueue {
         UnarchiveQueue {
             dry_run,
             remove_after,
-            queue: VecDeque::new(),
+            queue: SpillQueue::default(),
+            events,
+            summary: RunSummary::default(),
+            removal_gate: None,
+            removal_cap: None,
+            removal_plan_checked: false,
+            recovery_tool: None,
+            markers: InProgressMarkers::new(&[]).expect("default markers"),
+            breaker: None,
+            window: None,
+            wait_for_window: false,
+            outside_window: false,
+            paused_until: None,
+            overrides: HashMap::new(),
+            claimed_volumes: HashSet::new(),
+            snapshots: None,
+            removals_aborted: false,
+            kept_parts: HashSet::new(),
+            remove_empty_archives: false,
+            suspicious_ratio: None,
+            limits: None,
+            flatten_single_dir: false,
+            slow_threshold: None,
+            naming: ArchiveNaming::default(),
+            skip_in_use: false,
+            deferred: Vec::new(),
+            retrying: HashSet::new(),
+            outcomes: HashMap::new(),
+            removed: HashSet::new(),
+            pending_chains: BTreeMap::new(),
+            chain_grace: DEFAULT_CHAIN_GRACE,
+            dest_template: None,
+            nested: HashSet::new(),
+            enqueued: HashSet::new(),
+            inode_margin: 0,
+            max_disk_usage: None,
+            sandbox: None,
+            usage_deferred: Vec::new(),
+            keep_newest: None,
+            spared_newest: HashMap::new(),
+            nested_order: NestedOrder::Immediate,
+            unpack_tarballs: false,
+            map_roots: Vec::new(),
+            extracted_mtime: ExtractedMtime::Keep,
+            preallocate: Preallocate::Auto,
+            fast_store_copy: false,
+            deep_dry_run: None,
+            state: None,
+            mtime_support: HashMap::new(),
+            fake_detector: None,
+            changelog: None,
+            audit_log: None,
+            backups: None,
+            backup_failed: HashSet::new(),
+            remove_after_rules: None,
+            shorten_long_names: false,
+            name_matching: NameMatching::Auto,
+            name_rules: HashMap::new(),
+            prefetch_headers: false,
+            prefetch: None,
+            follow_symlinks: false,
+            full_scan: false,
+            symlinked_dirs: Vec::new(),
+            scanned: HashMap::new(),
+            auto_adopt: false,
+            duplicate_policy: DuplicatePolicy::ExtractAll,
+            memory_bounded: false,
+            media_prober: None,
+            require_valid_media: false,
+            required_content: None,
+            dir_lock_wait: Duration::ZERO,
+            content_found: HashSet::new(),
+            fs_batcher: FsBatcher::new(Arc::new(RealFs), fsops::DEFAULT_JOBS),
+            queued_removals: Vec::new(),
+            scanner: None,
+            verify_crc: false,
+            revalidate: false,
+            fsync: false,
+            inherit_dir_perms: false,
+            root_dir: None,
+            complete_releases: HashSet::new(),
+            #[cfg(feature = "http")]
+            status_server: None,
+            #[cfg(feature = "telemetry")]
+            tracer: None,
+            control: None,
+            ctl_socket: None,
+            ignore_rules: None,
+            removals: RemovalSet::default(),
+            known_parts: HashMap::new(),
+            no_remove: false,
+            flapping_policy: FlappingPolicy::Warn,
+            flapping_threshold: DEFAULT_FLAPPING_THRESHOLD,
+            panic_limit: DEFAULT_PANIC_LIMIT,
         }
     }
 
-    pub fn find_rar_files(&mut self, root_dir: impl AsRef<Path>) -> anyhow::Result<()> {
-        log::info!("Scanning for .rar files in '{}'", root_dir.as_ref().display());
-        let pattern = root_dir.as_ref().join("**/*.rar");
-        let pattern = pattern.to_string_lossy();
-        for entry in glob::glob(&pattern).context("glob .rar files")? {
-            let entry = entry?;
-            if is_root_rar_file(&entry) {
-                log::debug!("'{}' enqueued.", entry.display());
-                self.queue.push_back(entry);
-            }
-        }
-        Ok(())
+    pub fn with_prefetch_headers(mut self, prefetch_headers: bool) -> UnarchiveQueue {
+        self.prefetch_headers = prefetch_headers;
+        self
     }
 
-    pub fn process_next(&mut self) -> anyhow::Result<bool> {
-        match self.queue.pop_front() {
-            None => Ok(false),
-            Some(entry) => {
-                self.process_entry(entry).context("process entry")?;
-                Ok(true)
-            }
-        }
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> UnarchiveQueue {
+        self.follow_symlinks = follow_symlinks;
+        self
     }
 
-    fn process_entry(&mut self, entry: PathBuf) -> anyhow::Result<()> {
-        log::info!("Analyzing '{}'.", entry.display());
-        let entry_metadata = entry.metadata()?;
-        let entry_mtime = entry_metadata.modified()?;
+    pub fn with_full_scan(mut self, full_scan: bool) -> UnarchiveQueue {
+        self.full_scan = full_scan;
+        self
+    }
 
-        let archive = Archive::open(entry).context("archive open")?;
-        let dest = archive.path.as_path().parent().expect("no parent path");
+    #[cfg(feature = "http")]
+    pub fn with_status_server(mut self, server: StatusServer) -> UnarchiveQueue {
+        self.status_server = Some(server);
+        self
+    }
 
-        if archive.is_already_extracted(dest).context("is already extracted")? {
-            log::info!("-> Archive already extracted.");
-        } else {
-            log::info!("-> Extracting into '{}'.", dest.display());
-            if !self.dry_run {
-                archive.extract_into(dest).context("extract_into")?;
-            }
-        }
+    pub fn with_flapping_policy(mut self, policy: FlappingPolicy, threshold: u32) -> UnarchiveQueue {
+        self.flapping_policy = policy;
+        self.flapping_threshold = threshold;
+        self
+    }
 
-        for header in &archive.headers {
-            if is_root_rar_file(&header.filename) {
-                log::info!("-> Archive contains archive '{}', enqueuing", header.filename.display());
-                self.queue.push_back(dest.join(&header.filename));
+    /// Leaves archives alone once their processing panicked in `limit` runs in a row, until their first part changes.
+    pub fn with_panic_limit(mut self, limit: u32) -> UnarchiveQueue {
+        self.panic_limit = limit;
+        self
+    }
 
-                // When an embedded rar is extracted from the root rar, the mtime data is taken from the rar and applied
-                // on the extracted file. We get the original date of when the rar was created. This affects the removal
-                // system which depends on the date when the rar was extracted, not when it was originally created. This
-                // resets the mtime of the embedded rar to be the same as the root rar so they both get removed at the
-                // same time.
-                let extracted_path = dest.join(&header.filename);
-                let f = File::options()
-                    .write(true)
-                    .open(extracted_path)
-                    .context("opening embedded rar")?;
-                f.set_modified(entry_mtime).context("updating mtime on embedded rar")?;
-                log::info!(
-                    "-> Update '{}' mtime to {}",
-                    header.filename.display(),
-                    format_system_time(entry_mtime),
-                );
-            }
-        }
+    /// Records removals without applying them, nor removing cruft or chains of nested archives.
+    pub fn with_no_remove(mut self, no_remove: bool) -> UnarchiveQueue {
+        self.no_remove = no_remove;
+        self
+    }
 
-        if let Some(remove_after) = self.remove_after {
-            let parts = archive.list_parts().context("list parts")?;
-            log::debug!("-> Found {} parts", parts.len());
-            for entry in parts {
-                if self.should_remove(&entry, remove_after)? {
-                    log::info!("-> Removing archive/part '{}'.", entry.display(),);
-                    if !self.dry_run {
-                        fs::remove_file(entry).context("remove part")?;
-                    }
-                }
-            }
-        }
+    pub fn with_ignore_rules(mut self, rules: IgnoreRules) -> UnarchiveQueue {
+        self.ignore_rules = Some(rules);
+        self
+    }
 
-        Ok(())
+    /// Answers the requests of `rarscan ctl` on `socket` between archives.
+    pub fn with_ctl_socket(mut self, socket: CtlSocket) -> UnarchiveQueue {
+        self.ctl_socket = Some(socket);
+        self
     }
 
-    fn should_remove(&self, path: &Path, remove_after: Duration) -> anyhow::Result<bool> {
-        let md = path.metadata().context("stat part")?;
-        let mtime = md.modified().context("get part mtime")?;
-        let elapsed = mtime.elapsed().unwrap_or(Duration::from_millis(0));
-        Ok(elapsed > remove_after)
+    /// Reports the progress to the dashboard, which can pause, skip and quit through `control`.
+    #[cfg(feature = "tui")]
+    pub fn with_dashboard(mut self, sink: DashboardSink, control: Arc<Control>) -> UnarchiveQueue {
+        self.events = std::mem::take(&mut self.events).with_dashboard(sink);
+        self.control = Some(control);
+        self
     }
 
-    fn find_cruft(&self, root_dir: impl AsRef<Path>, remove_after: Duration) -> anyhow::Result<()> {
-        let remove_pattern = |pattern: &str| -> anyhow::Result<()> {
-            let pattern = root_dir.as_ref().join(pattern);
-            for entry in glob::glob(&pattern.to_string_lossy())? {
-                let entry = entry?;
-                if self.should_remove(&entry, remove_after)? {
-                    log::info!("Removing cruft '{}'.", entry.display());
-                    if !self.dry_run {
-                        fs::remove_file(&entry)?;
-                    }
-                }
-            }
-            Ok(())
-        };
-        remove_pattern("**/*.r??")?;
-        remove_pattern("**/*.sfv")?;
-        Ok(())
+    #[cfg(feature = "telemetry")]
+    pub fn with_tracer(mut self, tracer: Tracer) -> UnarchiveQueue {
+        self.tracer = Some(tracer);
+        self
     }
-}
 
-struct Archive {
-    pub path: PathBuf,
-    pub headers: Vec<FileHeader>,
-    pub parts_glob: PathBuf,
-}
+    /// Gives the directories created by extractions the group and setgid bit of their parent, and the extracted files
+    /// the group of their directory.
+    pub fn with_inherit_dir_perms(mut self, inherit_dir_perms: bool) -> UnarchiveQueue {
+        self.inherit_dir_perms = inherit_dir_perms;
+        self
+    }
 
-impl Archive {
-    pub fn open(path: impl Into<PathBuf>) -> anyhow::Result<Archive> {
-        let path = path.into();
-        let mut headers = Vec::new();
-        let archive = unrar::Archive::new(&path).open_for_listing()?;
-        for header in archive {
-            let header = header?;
-            headers.push(header);
-        }
+    pub fn with_fsync(mut self, fsync: bool) -> UnarchiveQueue {
+        self.fsync = fsync;
+        self
+    }
 
-        Ok(Archive {
-            parts_glob: unrar::Archive::new(&path).all_parts(),
-            path,
-            headers,
-        })
+    /// Bounds the memory used by the queue to about `budget` bytes, the archives over it wait in `spill_path`. The
+    /// index of scanned archives used to relocate vanished ones isn't kept either.
+    pub fn with_memory_budget(mut self, budget: u64, spill_path: PathBuf) -> UnarchiveQueue {
+        let capacity = usize::try_from(budget / QUEUED_ARCHIVE_COST).unwrap_or(usize::MAX);
+        self.queue = SpillQueue::bounded(capacity, spill_path);
+        self.memory_bounded = true;
+        self
     }
 
-    pub fn is_already_extracted(&self, dest: &Path) -> anyhow::Result<bool> {
-        for header in self.headers.iter() {
-            match fs::metadata(dest.join(&header.filename)) {
-                Ok(md) => {
-                    if md.len() != header.unpacked_size {
-                        log::debug!(
-                            "'{}' size mismatch, got {} want {}",
-                            header.filename.display(),
-                            header.unpacked_size,
-                            md.len()
-                        );
-                        return Ok(false);
-                    }
-                }
-                Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                    log::debug!("'{}' not found in destination", header.filename.display());
-                    return Ok(false);
-                }
-                Err(e) => return Err(e.into()),
-            }
-        }
-        Ok(true)
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> UnarchiveQueue {
+        self.duplicate_policy = duplicate_policy;
+        self
     }
 
-    pub fn extract_into(&self, dest: &Path) -> anyhow::Result<()> {
-        let mut archive = unrar::Archive::new(&self.path).open_for_processing()?;
-        while let Some(header) = archive.read_header()? {
-            archive = if header.entry().is_file() {
-                header.extract_with_base(dest)?
-            } else {
-                header.skip()?
-            };
-        }
-        Ok(())
+    pub fn with_media_prober(mut self, prober: MediaProber, require_valid_media: bool) -> UnarchiveQueue {
+        self.media_prober = Some(prober);
+        self.require_valid_media = require_valid_media;
+        self
     }
 
-    pub fn list_parts(&self) -> anyhow::Result<Vec<PathBuf>> {
-        let pattern = &self.parts_glob.to_string_lossy();
-        let mut results = Vec::new();
-        for entry in glob::glob(pattern).context("glob parts")? {
-            let entry = entry?;
-            results.push(entry);
-        }
-        Ok(results)
+    pub fn with_dir_lock_wait(mut self, wait: Duration) -> UnarchiveQueue {
+        self.dir_lock_wait = wait;
+        self
     }
-}
 
-#[derive(Parser, Debug)]
-struct Args {
-    root_dir: String,
-    #[arg(long, default_value = "info")]
-    log_level: log::LevelFilter,
-    #[arg(long, default_value = "false")]
-    dry_run: bool,
-    #[arg(long)]
-    remove_after_hours: Option<u64>,
-}
+    pub fn with_fs_batcher(mut self, batcher: FsBatcher) -> UnarchiveQueue {
+        self.fs_batcher = batcher;
+        self
+    }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    pub fn with_required_content(mut self, content: RequiredContent) -> UnarchiveQueue {
+        self.required_content = Some(content);
+        self
+    }
 
-    SimpleLogger::new()
-        .with_level(args.log_level)
-        .init()
-        .expect("unable to install logging");
+    pub fn with_scanner(mut self, scanner: Scanner) -> UnarchiveQueue {
+        self.scanner = Some(scanner);
+        self
+    }
 
-    let remove_after = args.remove_after_hours.map(|h| Duration::from_secs(60 * 60 * h));
+    pub fn with_verify_crc(mut self, verify_crc: bool, revalidate: bool) -> UnarchiveQueue {
+        self.verify_crc = verify_crc;
+        self.revalidate = revalidate;
+        self
+    }
 
-    let mut q = UnarchiveQueue::new(args.dry_run, remove_after);
-    q.find_rar_files(&args.root_dir)?;
-    while q.process_next()? {}
+    pub fn with_auto_adopt(mut self, auto_adopt: bool) -> UnarchiveQueue {
+        self.auto_adopt = auto_adopt;
+        self
+    }
 
-    if let Some(remove_after) = remove_after {
-        q.find_cruft(&args.root_dir, remove_after)?;
+    pub fn with_shorten_long_names(mut self, shorten_long_names: bool) -> UnarchiveQueue {
+        self.shorten_long_names = shorten_long_names;
+        self
     }
 
-    Ok(())
+    pub fn with_name_matching(mut self, name_matching: NameMatching) -> UnarchiveQueue {
+        self.name_matching = name_matching;
+        self
+    }
+
+    pub fn with_remove_after_rules(mut self, rules: RemoveAfterRules) -> UnarchiveQueue {
+        self.remove_after_rules = Some(rules);
+        self
+    }
+
+    pub fn with_changelog(mut self, path: impl Into<PathBuf>) -> UnarchiveQueue {
+        self.changelog = Some(ChangeLog::new(path, !self.dry_run));
+        self
+    }
+
+    /// Appends each removal and overwrite to `log` before doing it.
+    pub fn with_audit_log(mut self, log: AuditLog) -> UnarchiveQueue {
+        self.audit_log = Some(log);
+        self
+    }
+
+    /// Backs up the parts of each archive into `backups` before extracting it and before removing any of them.
+    pub fn with_backups(mut self, backups: Backups) -> UnarchiveQueue {
+        self.backups = Some(backups);
+        self
+    }
+
+    pub fn with_fake_detector(mut self, detector: FakeDetector) -> UnarchiveQueue {
+        self.fake_detector = Some(detector);
+        self
+    }
+
+    pub fn with_state_file(mut self, state: StateFile) -> UnarchiveQueue {
+        self.state = Some(state);
+        self
+    }
+
+    pub fn with_preallocate(mut self, preallocate: Preallocate) -> UnarchiveQueue {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Copies the entries of rar archives stored without compression straight out of their volume.
+    pub fn with_fast_store_copy(mut self, enabled: bool) -> UnarchiveQueue {
+        self.fast_store_copy = enabled;
+        self
+    }
+
+    pub fn with_deep_dry_run(mut self, max_size: Option<u64>) -> UnarchiveQueue {
+        self.deep_dry_run = max_size;
+        self
+    }
+
+    pub fn with_extracted_mtime(mut self, extracted_mtime: ExtractedMtime) -> UnarchiveQueue {
+        self.extracted_mtime = extracted_mtime;
+        self
+    }
+
+    pub fn with_chain_grace(mut self, chain_grace: Duration) -> UnarchiveQueue {
+        self.chain_grace = chain_grace;
+        self
+    }
+
+    pub fn with_nested_order(mut self, nested_order: NestedOrder) -> UnarchiveQueue {
+        self.nested_order = nested_order;
+        self
+    }
+
+    pub fn with_unpack_tarballs(mut self, unpack_tarballs: bool) -> UnarchiveQueue {
+        self.unpack_tarballs = unpack_tarballs;
+        self
+    }
+
+    pub fn with_map_roots(mut self, map_roots: Vec<PathBuf>) -> UnarchiveQueue {
+        self.map_roots = map_roots;
+        self
+    }
+
+    pub fn with_inode_margin(mut self, inode_margin: u64) -> UnarchiveQueue {
+        self.inode_margin = inode_margin;
+        self
+    }
+
+    pub fn with_keep_newest(mut self, keep_newest: Option<usize>) -> UnarchiveQueue {
+        self.keep_newest = keep_newest;
+        self
+    }
+
+    pub fn with_max_disk_usage(mut self, max_disk_usage: Option<f64>) -> UnarchiveQueue {
+        self.max_disk_usage = max_disk_usage;
+        self
+    }
+
+    pub fn with_sandbox(mut self, sandbox: Option<Sandbox>) -> UnarchiveQueue {
+        self.sandbox = sandbox;
+        self
+    }
+
+    pub fn with_dest_template(mut self, template: DestTemplate) -> UnarchiveQueue {
+        self.dest_template = Some(template);
+        self
+    }
+
+    pub fn with_skip_in_use(mut self, skip_in_use: bool) -> UnarchiveQueue {
+        self.skip_in_use = skip_in_use;
+        self
+    }
+
+    pub fn with_naming(mut self, naming: ArchiveNaming) -> UnarchiveQueue {
+        self.naming = naming;
+        self
+    }
+
+    pub fn with_slow_threshold(mut self, mb_per_sec: f64) -> UnarchiveQueue {
+        self.slow_threshold = Some(mb_per_sec);
+        self
+    }
+
+    pub fn with_flatten_single_dir(mut self, flatten_single_dir: bool) -> UnarchiveQueue {
+        self.flatten_single_dir = flatten_single_dir;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> UnarchiveQueue {
+        self.limits = Some(limits);
+        self
+    }
+
+    pub fn with_remove_empty_archives(mut self, remove_empty_archives: bool) -> UnarchiveQueue {
+        self.remove_empty_archives = remove_empty_archives;
+        self
+    }
+
+    pub fn with_suspicious_ratio(mut self, threshold: f64) -> UnarchiveQueue {
+        self.suspicious_ratio = Some(threshold);
+        self
+    }
+
+    pub fn with_removal_gate(mut self, gate: RemovalGate) -> UnarchiveQueue {
+        self.removal_gate = Some(gate);
+        self
+    }
+
+    /// Removes at most this many files, or bytes, in the run. The removals past the cap are left for a later run.
+    pub fn with_removal_cap(mut self, cap: RemovalCap) -> UnarchiveQueue {
+        self.removal_cap = Some(cap);
+        self
+    }
+
+    pub fn with_snapshots(mut self, snapshots: Snapshots) -> UnarchiveQueue {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    pub fn with_recovery_tool(mut self, tool: impl Into<PathBuf>) -> UnarchiveQueue {
+        self.recovery_tool = Some(tool.into());
+        self
+    }
+
+    pub fn with_in_progress_markers(mut self, markers: InProgressMarkers) -> UnarchiveQueue {
+        self.markers = markers;
+        self
+    }
+
+    /// Stops working on a device after `max_errors` IO errors on it in a row.
+    pub fn with_max_device_errors(mut self, max_errors: usize) -> UnarchiveQueue {
+        self.breaker = Some(DeviceBreaker::new(max_errors));
+        self
+    }
+
+    /// Extracts and removes only while `window` is open, waiting for it to open with `wait`.
+    pub fn with_active_window(mut self, window: ActiveWindow, wait: bool) -> UnarchiveQueue {
+        self.window = Some(window);
+        self.wait_for_window = wait;
+        self
+    }
+
+    /// Whether the heavy work may go on now. Outside of the window, waits for it to open with --wait-for-window and
+    /// returns false otherwise or when asked to quit meanwhile.
+    pub fn in_active_window(&mut self) -> bool {
+        let Some(window) = self.window.clone() else {
+            return true;
+        };
+        if self.outside_window {
+            return false;
+        }
+        while let Some(opens_at) = window.opens_at(SystemTime::now()) {
+            let now = SystemTime::now();
+            let until = window.describe(opens_at, now);
+            if !self.wait_for_window {
+                log::warn!("Outside of the active window {}, it opens at {}.", window, until);
+                self.outside_window = true;
+                return false;
+            }
+            if self.paused_until.as_ref() != Some(&until) {
+                log::info!("Outside of the active window {}, paused until {}.", window, until);
+                self.set_paused_until(Some(until));
+            }
+            // Wakes up now and then in case the clock jumps.
+            let nap = opens_at
+                .duration_since(now)
+                .unwrap_or_default()
+                .min(window::POLL_INTERVAL);
+            let deadline = Instant::now() + nap;
+            while Instant::now() < deadline {
+                self.serve_ctl();
+                if self.control.as_ref().is_some_and(|control| control.quitting()) {
+                    self.set_paused_until(None);
+                    return false;
+                }
+                thread::sleep(control::PAUSE_POLL_INTERVAL);
+            }
+        }
+        if self.paused_until.is_some() {
+            log::info!("The active window is open, resuming.");
+            self.set_paused_until(None);
+        }
+        true
+    }
+
+    fn set_paused_until(&mut self, until: Option<String>) {
+        #[cfg(feature = "http")]
+        if let Some(server) = &self.status_server {
+            server.update(|status| status.paused_until = until.clone());
+        }
+        self.paused_until = until;
+    }
+
+    pub fn find_rar_files(&mut self, root_dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        log::info!("Scanning for .rar files in '{}'", root_dir.as_ref().display());
+        self.root_dir = Some(root_dir.as_ref().to_path_buf());
+        self.events.emit(Event::ScanStart {
+            root_dir: root_dir.as_ref(),
+        });
+        let is_rar = |path: &Path| path.extension().is_some_and(|ext| ext == "rar");
+        let follow_symlinks = self.follow_symlinks;
+        let previous = self.previous_listings(root_dir.as_ref());
+        let started = Instant::now();
+        let mut progress = ScanProgress::default();
+        // Both are called by the walk, one at a time.
+        let queue = RefCell::new(&mut *self);
+        let mut last_report = started;
+        let on_file = |entry: PathBuf| queue.borrow_mut().scanned_rar_file(entry);
+        let on_dir = |at: &ScanProgress| {
+            progress = *at;
+            if last_report.elapsed() >= SCAN_PROGRESS_INTERVAL {
+                last_report = Instant::now();
+                queue.borrow_mut().report_scan_progress(&progress);
+            }
+        };
+        match &previous {
+            Some(previous) => {
+                let scan = walk::visit_files_incremental(root_dir.as_ref(), previous, is_rar, on_file, on_dir)
+                    .context("scan for .rar files")?;
+                log::info!(
+                    "-> {} of {} directories unchanged since the last scan.",
+                    scan.unchanged,
+                    scan.dirs
+                );
+                if let Some(state) = &mut self.state {
+                    state.set_listings(root_dir.as_ref(), scan.listings);
+                }
+            }
+            None => {
+                let followed = walk::visit_files(root_dir.as_ref(), follow_symlinks, is_rar, on_file, on_dir)
+                    .context("scan for .rar files")?;
+                // Where the archives found through them are queued.
+                self.symlinked_dirs = followed.iter().map(|dir| paths::canonical(dir)).collect();
+            }
+        }
+        self.summary.scan_dirs = progress.dirs as u64;
+        self.summary.scan_duration = started.elapsed();
+        log::info!(
+            "Scan complete: {} archives enqueued from {} directories in {:.1}s.",
+            self.summary.root_archives_enqueued,
+            progress.dirs,
+            self.summary.scan_duration.as_secs_f64()
+        );
+        self.events.emit(Event::ScanDone {
+            dirs: progress.dirs as u64,
+            rar_files: self.summary.rar_files_seen,
+            archives: self.summary.root_archives_enqueued,
+            duration: self.summary.scan_duration,
+        });
+        if self.summary.root_archives_enqueued == 0 && mounts::looks_unmounted(root_dir.as_ref()) {
+            self.summary.unmounted_root = Some(root_dir.as_ref().to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Queues `entry` if it's the root of a set, as soon as the scan finds it, so that a bounded queue never holds all
+    /// of them.
+    fn scanned_rar_file(&mut self, entry: PathBuf) -> io::Result<()> {
+        if self.is_set_aside(&entry) {
+            return Ok(());
+        }
+        self.summary.rar_files_seen += 1;
+        if self.is_ignored(&entry, false) {
+            log::debug!("'{}' is ignored.", entry.display());
+            return Ok(());
+        }
+        if !self.memory_bounded {
+            if let Some(name) = entry.file_name() {
+                self.scanned.entry(name.to_owned()).or_default().push(entry.clone());
+            }
+        }
+        if self.naming.is_root_rar_file(&entry) {
+            let archive = paths::canonical(&entry);
+            if !self.enqueued.insert(archive.clone()) {
+                log::debug!("'{}' is '{}', enqueued already.", entry.display(), archive.display());
+                return Ok(());
+            }
+            let entry = archive;
+            log::debug!("'{}' enqueued.", entry.display());
+            self.summary.root_archives_enqueued += 1;
+            self.events.emit(Event::ArchiveFound { path: &entry });
+            self.queue
+                .push_back(entry)
+                .map_err(|e| io::Error::other(format!("{:#}", e)))?;
+        }
+        Ok(())
+    }
+
+    fn report_scan_progress(&mut self, progress: &ScanProgress) {
+        log::info!(
+            "-> Scanned {} directories, {} of {} at the top, {} .rar files seen, {} archives enqueued.",
+            progress.dirs,
+            progress.top_dirs_entered,
+            progress.top_dirs,
+            self.summary.rar_files_seen,
+            self.summary.root_archives_enqueued
+        );
+        self.events.emit(Event::ScanProgress {
+            progress,
+            rar_files: self.summary.rar_files_seen,
+            archives: self.summary.root_archives_enqueued,
+        });
+    }
+
+    /// The listings of the directories below `root` kept by the last scan, unless every directory is to be read: with
+    /// --full-scan, without a state file or on a filesystem whose directory mtimes can't be trusted.
+    fn previous_listings(&self, root: &Path) -> Option<Listings> {
+        if self.full_scan || self.follow_symlinks {
+            return None;
+        }
+        let listings = self.state.as_ref()?.listings(root);
+        if let Some(mount) = mounts::unreliable_dir_mtimes(root) {
+            log::info!(
+                "-> Directory mtimes can't be trusted on '{}', reading every directory.",
+                mount.display()
+            );
+            return None;
+        }
+        Some(listings)
+    }
+
+    /// Whether the scan found no root archive at all, for --expect-archives.
+    /// Answers the requests waiting on the control socket.
+    fn serve_ctl(&mut self) {
+        let Some(socket) = self.ctl_socket.take() else {
+            return;
+        };
+        socket.serve(|request| self.handle_ctl(request));
+        self.ctl_socket = Some(socket);
+    }
+
+    fn handle_ctl(&mut self, request: Request) -> serde_json::Value {
+        use serde_json::json;
+
+        log::debug!("Control request: {:?}", request);
+        match request {
+            Request::Status => json!({
+                "ok": true,
+                "paused": self.control.as_ref().is_some_and(|control| control.is_paused()),
+                "paused_until": self.paused_until,
+                "current_archive": None::<String>,
+                "queue_length": self.queue.len(),
+                "deferred": self.deferred.len(),
+                "archives_processed": self.summary.archives_processed,
+                "archives_extracted": self.summary.archives_extracted,
+                "archives_failed": self.summary.failed_archives.len(),
+            }),
+            Request::Queue => {
+                let mut queue = Vec::new();
+                let listed = self.queue.retain(|path| {
+                    queue.push(json!({ "position": queue.len() + 1, "path": path.to_string_lossy() }));
+                    Ok(true)
+                });
+                match listed {
+                    Ok(()) => json!({
+                        "ok": true,
+                        "queue": queue,
+                        "deferred": self.deferred.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>(),
+                    }),
+                    Err(e) => ctl::error(format!("{:#}", e)),
+                }
+            }
+            Request::Prioritize(path) => match self.take_queued(&path) {
+                Ok(Some((archive, position))) => {
+                    log::info!("Moving '{}' to the front of the queue on request.", archive.display());
+                    self.queue.push_front(archive.clone());
+                    self.journal_ctl(|state| state.prioritize(&archive));
+                    json!({
+                        "ok": true,
+                        "path": archive.to_string_lossy(),
+                        "position": 1,
+                        "previous_position": position,
+                    })
+                }
+                Ok(None) => ctl::error(format!("'{}' is not queued", path.display())),
+                Err(e) => ctl::error(format!("{:#}", e)),
+            },
+            Request::Drop(path) => match self.take_queued(&path) {
+                Ok(Some((archive, position))) => {
+                    log::info!("Dropping '{}' from the queue on request.", archive.display());
+                    self.journal_ctl(|state| state.drop_queued(&archive));
+                    json!({ "ok": true, "path": archive.to_string_lossy(), "position": position })
+                }
+                Ok(None) => ctl::error(format!("'{}' is not queued", path.display())),
+                Err(e) => ctl::error(format!("{:#}", e)),
+            },
+            Request::Pause | Request::Resume => {
+                let paused = request == Request::Pause;
+                log::info!("{} on request.", if paused { "Pausing" } else { "Resuming" });
+                self.control.get_or_insert_with(Default::default).set_paused(paused);
+                json!({ "ok": true, "paused": paused })
+            }
+        }
+    }
+
+    /// Takes the archive at `path` out of the queue or of the deferred archives, with its position counting the
+    /// deferred archives after the queue.
+    fn take_queued(&mut self, path: &Path) -> anyhow::Result<Option<(PathBuf, usize)>> {
+        let mut position = 0;
+        let mut found = None;
+        self.queue.retain(|queued| {
+            position += 1;
+            if found.is_none() && ctl::same_path(queued, path) {
+                found = Some((queued.to_path_buf(), position));
+                return Ok(false);
+            }
+            Ok(true)
+        })?;
+        if found.is_none() {
+            if let Some(index) = self.deferred.iter().position(|queued| ctl::same_path(queued, path)) {
+                found = Some((self.deferred.remove(index), self.queue.len() + index + 1));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Records a request of the control socket in the state file right away, so that a crash doesn't lose it.
+    fn journal_ctl(&mut self, record: impl FnOnce(&mut StateFile)) {
+        if let Some(state) = self.state.as_mut().filter(|_| !self.dry_run) {
+            record(state);
+            if let Err(e) = state.save() {
+                log::error!("Unable to save the state file: {:#}", e);
+            }
+        }
+    }
+
+    /// Applies the requests of the control socket recorded by a run that didn't finish.
+    pub fn replay_ctl(&mut self) -> anyhow::Result<()> {
+        let Some(state) = &self.state else {
+            return Ok(());
+        };
+        let (prioritized, dropped) = (state.prioritized().to_vec(), state.dropped().to_vec());
+        if prioritized.is_empty() && dropped.is_empty() {
+            return Ok(());
+        }
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.retain(|entry| {
+            let drop = dropped.iter().any(|path| path == entry);
+            if drop {
+                log::info!(
+                    "Leaving out '{}', dropped from the queue of the last run.",
+                    entry.display()
+                );
+            }
+            Ok(!drop)
+        })?;
+        for archive in prioritized {
+            let mut found = false;
+            queue.retain(|entry| {
+                found |= entry == archive;
+                Ok(entry != archive)
+            })?;
+            if found {
+                log::info!(
+                    "Moving '{}' to the front, prioritized in the last run.",
+                    archive.display()
+                );
+                queue.push_front(archive);
+            }
+        }
+        self.queue = queue;
+        Ok(())
+    }
+
+    pub fn found_no_archives(&self) -> bool {
+        self.summary.root_archives_enqueued == 0
+    }
+
+    pub fn process_next(&mut self) -> anyhow::Result<bool> {
+        self.serve_ctl();
+        // Paused through the control socket, which keeps being served meanwhile.
+        while self.ctl_socket.is_some()
+            && self
+                .control
+                .as_ref()
+                .is_some_and(|control| control.is_paused() && !control.quitting())
+        {
+            thread::sleep(control::PAUSE_POLL_INTERVAL);
+            self.serve_ctl();
+        }
+        if let Some(control) = &self.control {
+            control.wait_while_paused();
+            if control.quitting() {
+                log::info!(
+                    "Quitting on request, {} archives left in the queue.",
+                    self.queue.len() + self.deferred.len()
+                );
+                return Ok(false);
+            }
+            // A skip asked for between archives isn't meant for the next one.
+            control.take_skip();
+        }
+        // Checked between archives only, the one in progress always finishes.
+        if !self.in_active_window() {
+            log::info!(
+                "Stopping outside of the active window, {} archives left in the queue.",
+                self.queue.len() + self.deferred.len()
+            );
+            return Ok(false);
+        }
+        if self.queue.is_empty() && !self.deferred.is_empty() {
+            for entry in self.deferred.drain(..) {
+                log::info!("Retrying deferred archive '{}'.", entry.display());
+                self.retrying.insert(entry.clone());
+                self.queue.push_back(entry)?;
+            }
+        }
+        match self.queue.pop_front()? {
+            None => {
+                if !self.no_remove {
+                    let now = SystemTime::now();
+                    let planned = self
+                        .removals
+                        .iter()
+                        .flat_map(|(_, parts)| parts)
+                        .filter(|(_, eligible_at)| *eligible_at < now)
+                        .map(|(part, _)| part.clone())
+                        .chain(
+                            self.pending_chains
+                                .values()
+                                .flatten()
+                                .flat_map(|(_, parts)| parts.clone()),
+                        )
+                        .collect();
+                    self.check_removal_plan(planned);
+                    self.remove_chains()?;
+                }
+                if let Some(state) = &mut self.state {
+                    state.clear_ctl();
+                }
+                Ok(false)
+            }
+            Some(entry) => {
+                #[cfg(feature = "http")]
+                if let Some(server) = &self.status_server {
+                    server.update(|status| {
+                        status.current = Some(entry.clone());
+                        status.queue_len = self.queue.len() + self.deferred.len();
+                    });
+                }
+                let span = self.start_span("rarscan.archive");
+                self.trace_attr("rarscan.archive.path", entry.to_string_lossy());
+                self.events.emit(Event::ArchiveStart { archive: &entry });
+                // A panic fails the archive alone, what it held is dropped on the way out.
+                let result = match panic::catch_unwind(AssertUnwindSafe(|| self.process_entry(entry.clone()))) {
+                    Ok(result) => {
+                        // Processed without panicking this time, unless it was left alone for it.
+                        let left_alone = self.panic_streak(&entry).is_some_and(|count| count >= self.panic_limit);
+                        if let Some(state) = self.state.as_mut().filter(|_| result.is_ok() && !left_alone) {
+                            state.forget_panics(&entry);
+                        }
+                        result
+                    }
+                    Err(payload) => Ok(self.panicked(&entry, panic_message(&*payload))),
+                };
+                self.end_archive_span(span, &entry, &result);
+                let outcome = result.context("process entry")?;
+                self.events.emit(Event::ArchiveDone {
+                    archive: &entry,
+                    archive_id: self.summary.archive_ids.get(&entry).map(String::as_str),
+                    outcome: outcome.status(),
+                });
+                if self.nested.contains(&entry) {
+                    self.record_nested_outcome(&entry, outcome);
+                }
+                self.record_sighting(&entry);
+                self.outcomes.insert(entry, outcome);
+                self.update_status();
+                Ok(true)
+            }
+        }
+    }
+
+    /// Starts a span under the innermost open one, when tracing.
+    #[cfg(feature = "telemetry")]
+    fn start_span(&mut self, name: &str) -> Option<SpanId> {
+        self.tracer.as_mut().map(|tracer| tracer.start(name))
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn end_span(&mut self, span: Option<SpanId>) {
+        if let (Some(tracer), Some(span)) = (&mut self.tracer, span) {
+            tracer.end(span);
+        }
+    }
+
+    /// Ends the span of an archive with its outcome.
+    #[cfg(feature = "telemetry")]
+    fn end_archive_span(&mut self, span: Option<SpanId>, entry: &Path, result: &anyhow::Result<Outcome>) {
+        if let (Some(tracer), Some(span)) = (&mut self.tracer, span) {
+            match result {
+                Ok(outcome) => {
+                    tracer.set(span, "rarscan.outcome", outcome.status());
+                    let failure = self.summary.failed_archives.last().filter(|(path, _)| *path == entry);
+                    if let Some((_, failure)) = failure {
+                        tracer.fail(span, failure.kind(), failure.to_string());
+                    }
+                }
+                Err(e) => tracer.fail(span, "internal", format!("{:#}", e)),
+            }
+            tracer.end(span);
+        }
+    }
+
+    #[cfg(feature = "telemetry")]
+    fn fail_span(&mut self, span: Option<SpanId>, class: &str, message: impl ToString) {
+        if let (Some(tracer), Some(span)) = (&mut self.tracer, span) {
+            tracer.fail(span, class, message.to_string());
+        }
+    }
+
+    /// Sets an attribute of the innermost open span, when tracing.
+    #[cfg(feature = "telemetry")]
+    fn trace_attr(&mut self, key: &str, value: impl Into<serde_json::Value>) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.set_current(key, value);
+        }
+    }
+
+    #[cfg(not(feature = "telemetry"))]
+    fn start_span(&mut self, _: &str) -> Option<SpanId> {
+        None
+    }
+
+    #[cfg(not(feature = "telemetry"))]
+    fn end_span(&mut self, _: Option<SpanId>) {}
+
+    #[cfg(not(feature = "telemetry"))]
+    fn end_archive_span(&mut self, _: Option<SpanId>, _: &Path, _: &anyhow::Result<Outcome>) {}
+
+    #[cfg(not(feature = "telemetry"))]
+    fn fail_span(&mut self, _: Option<SpanId>, _: &str, _: impl ToString) {}
+
+    #[cfg(not(feature = "telemetry"))]
+    fn trace_attr(&mut self, _: &str, _: impl Into<serde_json::Value>) {}
+
+    /// Runs `f` in a span, which fails along with it.
+    fn traced<T>(&mut self, name: &str, f: impl FnOnce(&mut Self) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let span = self.start_span(name);
+        let result = f(self);
+        if let Err(e) = &result {
+            self.fail_span(span, "internal", format!("{:#}", e));
+        }
+        self.end_span(span);
+        result
+    }
+
+    fn update_status(&self) {
+        #[cfg(feature = "http")]
+        if let Some(server) = &self.status_server {
+            server.update(|status| {
+                status.current = None;
+                status.queue_len = self.queue.len() + self.deferred.len();
+                status.archives_processed = self.summary.archives_processed;
+                status.archives_extracted = self.summary.archives_extracted;
+                status.archives_failed = self.summary.failed_archives.len() as u64;
+                status.parts_removed = self.summary.parts_removed;
+            });
+        }
+    }
+
+    /// Adds the outcome of the nested archive at `path` to the fate it was enqueued with.
+    fn record_nested_outcome(&mut self, path: &Path, outcome: Outcome) {
+        let entries = self.summary.nested_archives.iter_mut().flat_map(|(_, entries)| entries);
+        for entry in entries.filter(|entry| entry.path == path) {
+            entry.outcome = Some(outcome.status());
+        }
+    }
+
+    /// Notes an archive which disappeared since the scan. What the state file knows about it moves to an archive with
+    /// the same name found by the scan, for when the same release was in two places.
+    fn vanished(&mut self, entry: PathBuf) -> Outcome {
+        log::info!("-> Archive vanished before processing, skipping.");
+        if let Some(state) = &mut self.state {
+            let relocated = entry
+                .file_name()
+                .and_then(|name| self.scanned.get(name))
+                .into_iter()
+                .flatten()
+                .find(|path| **path != entry && path.exists());
+            if let Some(relocated) = relocated {
+                if state.relocate(&entry, relocated) {
+                    log::info!("-> Moved its state to '{}'.", relocated.display());
+                }
+            }
+        }
+        self.summary.vanished_archives.push(entry);
+        Outcome::Vanished
+    }
+
+    /// Gives the archive its id, the fingerprint of its first part and entries, once per version of its first part.
+    /// When the state file last saw it somewhere that is gone, what it records about it follows: the files extracted
+    /// into its directory along when the directory was renamed.
+    fn identify(&mut self, archive: &Archive) -> anyhow::Result<()> {
+        let key = FileKey::of(&archive.path)?;
+        let recorded = self
+            .state
+            .as_ref()
+            .and_then(|state| state.archive_id(&archive.path, key));
+        let id = match recorded {
+            Some(id) => id.to_string(),
+            None => Fingerprint::of(archive)?.to_string(),
+        };
+        log::debug!("-> Archive id {}.", id);
+        if let Some(state) = &mut self.state {
+            match state.last_seen(&id).map(Path::to_path_buf) {
+                Some(previous) if previous == archive.path => {}
+                // A copy, the id stays with the archive seen first.
+                Some(previous) if fs::symlink_metadata(&previous).is_ok() => {}
+                Some(previous) => {
+                    let (from, to) = (parent_dir(&previous), parent_dir(&archive.path));
+                    if from != to && fs::symlink_metadata(from).is_err() {
+                        let moved = state.relocate_dir(from, to);
+                        log::info!(
+                            "-> Its directory was renamed from '{}', moved the state of {} paths below it.",
+                            from.display(),
+                            moved
+                        );
+                    }
+                    if let Some(previous) = state.last_seen(&id).map(Path::to_path_buf) {
+                        if previous != archive.path {
+                            state.relocate(&previous, &archive.path);
+                            log::info!(
+                                "-> Same archive as '{}', which is gone, moved its state here.",
+                                previous.display()
+                            );
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        self.summary.archive_ids.insert(archive.path.clone(), id);
+        Ok(())
+    }
+
+    /// Records where the archive at `path` was seen with its id, once the state file knows something about it.
+    fn record_sighting(&mut self, path: &Path) {
+        let (Some(state), Some(id)) = (&mut self.state, self.summary.archive_ids.get(path)) else {
+            return;
+        };
+        if !state.knows(path) || state.last_seen(id).is_some_and(|seen| seen != path && seen.exists()) {
+            return;
+        }
+        if let Ok(key) = FileKey::of(path) {
+            state.set_seen(id, path, key);
+        }
+    }
+
+    /// Runs in a row the processing of `entry` panicked, as long as its first part is the one it panicked on.
+    fn panic_streak(&self, entry: &Path) -> Option<u32> {
+        let (fingerprint, count) = self.state.as_ref()?.panics(entry)?;
+        let current = Fingerprint::of_part(entry).ok()?;
+        (current.to_string() == fingerprint).then_some(count)
+    }
+
+    /// Fails the archive whose processing panicked, keeping its parts. Counts the panic in the state file, the archive
+    /// is left alone once it panicked --panic-limit runs in a row.
+    fn panicked(&mut self, entry: &Path, message: String) -> Outcome {
+        let failure = ExtractionError::Panicked { message };
+        log::error!("-> Processing {}. Its parts will not be removed.", failure);
+        log::error!("-> {}", failure.hint());
+        if let Some(state) = &mut self.state {
+            if let Ok(fingerprint) = Fingerprint::of_part(entry) {
+                let count = state.add_panic(entry, fingerprint.to_string());
+                if count >= self.panic_limit {
+                    log::warn!(
+                        "-> Panicked in {} runs in a row, left alone until its first part changes.",
+                        count
+                    );
+                }
+            }
+        }
+        self.kept_parts
+            .extend(archive::list_set_parts(entry, &self.naming).unwrap_or_else(|_| vec![entry.to_path_buf()]));
+        self.summary.failed_archives.push((entry.to_path_buf(), failure));
+        Outcome::Failed
+    }
+
+    /// Records the archives found extracted by something else in the state file, without extracting or removing
+    /// anything.
+    pub fn adopt_all(&mut self) -> anyhow::Result<()> {
+        while let Some(entry) = self.queue.pop_front()? {
+            log::info!("Analyzing '{}'.", entry.display());
+            let plan = self.plan(entry)?;
+            self.summary.archives_processed += 1;
+            if !plan.extracted {
+                log::info!("-> Archive not extracted, leaving it alone.");
+            } else if !self.adopt(&plan.archive, &plan.dest)? {
+                log::info!("-> Extraction already recorded.");
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes over the archives found from unpackerr: the ones it extracted are adopted, the ones it left partly
+    /// extracted are extracted again and the others are left alone. With `clean`, the extractions complete in a
+    /// staging directory of unpackerr are moved into place and adopted, and the staging directories of the archives
+    /// are removed. The ones of no archive found are kept, they may hold the only copy of their files.
+    pub fn import_unpackerr(&mut self, clean: bool) -> anyhow::Result<ImportSummary> {
+        let root_dir = self.root_dir.clone().expect("scanned before importing");
+        let mut import = ImportSummary::default();
+        let mut recognized = BTreeSet::new();
+        let mut requeued = Vec::new();
+        while let Some(entry) = self.queue.pop_front()? {
+            if unpackerr::in_staging_dir(&entry) {
+                log::debug!("'{}' is in a staging directory of unpackerr.", entry.display());
+                continue;
+            }
+            log::info!("Analyzing '{}'.", entry.display());
+            let plan = self.plan(entry)?;
+            self.summary.archives_processed += 1;
+            let files: Vec<&Path> = plan
+                .archive
+                .headers
+                .iter()
+                .filter(|header| header.is_file())
+                .map(|header| header.filename.as_path())
+                .collect();
+            let staged = unpackerr::staged_dest(&root_dir, &plan.archive.path)
+                .filter(|(dest, _)| files.iter().any(|file| dest.join(file).exists()));
+            let traces = Traces {
+                extracted_in_place: plan.extracted && self.verify_extracted(&plan.archive, &plan.dest)?.is_none(),
+                partly_in_place: files.iter().any(|file| plan.dest.join(file).exists()),
+                staged: match &staged {
+                    Some((dest, _)) => Some(
+                        plan.archive
+                            .is_already_extracted(dest)
+                            .context("is already extracted")?
+                            && self.verify_extracted(&plan.archive, dest)?.is_none(),
+                    ),
+                    None => None,
+                },
+            };
+            if let Some((_, staging)) = &staged {
+                recognized.insert(staging.clone());
+            }
+            match Verdict::of(traces) {
+                Verdict::Adopt => {
+                    if !self.adopt(&plan.archive, &plan.dest)? {
+                        log::info!("-> Extraction already recorded.");
+                    }
+                    import.adopted.push(plan.archive.path);
+                }
+                Verdict::MoveIntoPlace if clean => {
+                    let (staged_dest, _) = staged.expect("staged");
+                    log::info!(
+                        "-> Extracted into '{}', moving its {} files into '{}'.",
+                        staged_dest.display(),
+                        files.len(),
+                        plan.dest.display()
+                    );
+                    if !self.dry_run {
+                        for file in &files {
+                            let (from, to) = (staged_dest.join(file), plan.dest.join(file));
+                            unpackerr::move_into_place(&from, &to)
+                                .with_context(|| format!("move '{}' to '{}'", from.display(), to.display()))?;
+                        }
+                        self.adopt(&plan.archive, &plan.dest)?;
+                    }
+                    import.moved.push(plan.archive.path);
+                }
+                Verdict::MoveIntoPlace => {
+                    log::info!(
+                        "-> Extracted into '{}', leaving it there.",
+                        staged.expect("staged").0.display()
+                    );
+                    import.staged.push(plan.archive.path);
+                }
+                Verdict::Requeue => {
+                    log::info!("-> Partly extracted, extracting it again.");
+                    requeued.push(plan.archive.path.clone());
+                    import.requeued.push(plan.archive.path);
+                }
+                Verdict::Untouched => {
+                    log::info!("-> Not extracted, leaving it to a run.");
+                    import.untouched.push(plan.archive.path);
+                }
+            }
+        }
+
+        for staging in unpackerr::staging_dirs(&root_dir).context("scan for staging directories")? {
+            if !clean || !recognized.contains(&staging) {
+                import.kept_staging_dirs.push(staging);
+                continue;
+            }
+            log::info!("Removing staging directory '{}'.", staging.display());
+            if !self.dry_run {
+                if self.audit_log.is_some() {
+                    for file in walk::find_files(&staging, false, |_| true).context("list staging directory")? {
+                        self.audit(Action::Remove, &file, None, "unpackerr-staging")?;
+                    }
+                }
+                fs::remove_dir_all(&staging).with_context(|| format!("remove '{}'", staging.display()))?;
+            }
+            import.removed_staging_dirs.push(staging);
+        }
+
+        for entry in requeued {
+            self.queue.push_back(entry)?;
+        }
+        while self.process_next()? {}
+        Ok(import)
+    }
+
+    /// Opens the archive at `entry` and works out what processing it would do.
+    fn plan(&mut self, entry: PathBuf) -> anyhow::Result<Plan> {
+        let entry_mtime = self.mtime(&entry)?;
+        // An invalid sidecar fails its archive when it's processed, until then it's as if there was none.
+        let overrides = Overrides::load(&entry).ok().flatten();
+        let password = overrides.as_ref().and_then(|overrides| overrides.password.as_deref());
+        let mut archive = Archive::open_with_password(entry, &self.naming, password).context("archive open")?;
+        if let Some(overrides) = &overrides {
+            archive.set_exclude(overrides.exclude.clone());
+        }
+        if self.flatten_single_dir {
+            archive.flatten_single_dir();
+        }
+        if self.shorten_long_names {
+            archive.shorten_long_names();
+        }
+        let dest = self.destination(&archive, entry_mtime, overrides.as_ref())?;
+        self.apply_rename_map(&mut archive, &dest);
+        self.apply_tiered(&mut archive, &dest);
+        self.apply_name_rules(&mut archive, &dest);
+        let extracted = archive.is_already_extracted(&dest).context("is already extracted")?;
+        let removable = match self.resolve_remove_after(&archive.path, false) {
+            Some(remove_after) => self.should_remove_set(&archive.list_parts().context("list parts")?, remove_after)?,
+            None => false,
+        };
+        Ok(Plan {
+            archive,
+            dest,
+            extracted,
+            removable,
+        })
+    }
+
+    /// Looks at the recovery volumes of a set that failed for a missing volume. When they can rebuild the missing ones,
+    /// the outcome is `Repairable`, or `Deferred` once the recovery tool rebuilt them and the archive waits to be tried
+    /// again.
+    fn recover_set(&mut self, path: &Path, failure: &ExtractionError) -> Option<Outcome> {
+        if !matches!(failure, ExtractionError::MissingVolume { .. }) {
+            return None;
+        }
+        let volumes = SetVolumes::of(path);
+        if volumes.recovery.is_empty() {
+            return None;
+        }
+        // A missing last volume leaves no gap behind.
+        let missing = volumes.missing.max(1);
+        if !volumes.repairable(missing) {
+            log::warn!(
+                "-> Set is missing {} volumes, its {} recovery volumes aren't enough to rebuild them.",
+                missing,
+                volumes.recovery.len()
+            );
+            return None;
+        }
+        log::warn!(
+            "-> Set is incomplete but repairable ({} missing, {} recovery volumes).",
+            missing,
+            volumes.recovery.len()
+        );
+        let tool = self.recovery_tool.as_ref().filter(|_| !self.dry_run);
+        if let Some(tool) = tool.filter(|_| !self.retrying.contains(path)) {
+            match recovery::rebuild(tool, path) {
+                Ok(()) => {
+                    log::info!("-> Rebuilt the missing volumes, trying the archive again at the end of the run.");
+                    self.deferred.push(path.to_path_buf());
+                    return Some(Outcome::Deferred);
+                }
+                Err(e) => log::error!("-> Couldn't rebuild the missing volumes: {:#}", e),
+            }
+        }
+        self.summary
+            .repairable_archives
+            .push((path.to_path_buf(), missing, volumes.recovery.len()));
+        Some(Outcome::Repairable)
+    }
+
+    /// Device of `path`, or of its closest existing parent for a destination not created yet.
+    fn device_of(path: &Path) -> Option<u64> {
+        path.ancestors().find_map(|path| space::device(path).ok())
+    }
+
+    fn on_suspended_device(&self, path: &Path) -> bool {
+        let Some(breaker) = &self.breaker else {
+            return false;
+        };
+        Self::device_of(path).is_some_and(|device| breaker.is_suspended(device))
+    }
+
+    /// Counts an IO failure against the device of `path`, the archive or the destination that failed.
+    fn trip_breaker(&mut self, path: &Path, failure: &ExtractionError) {
+        let Some(breaker) = self.breaker.as_mut().filter(|_| failure.is_io()) else {
+            return;
+        };
+        let Some(device) = Self::device_of(path) else {
+            return;
+        };
+        if breaker.failure(device, Instant::now()) {
+            let errors = breaker.errors(device);
+            let name = path
+                .ancestors()
+                .find_map(mounts::device_name)
+                .unwrap_or_else(|| format!("of '{}'", path.display()));
+            log::error!(
+                "-> Device {} suspended after {} IO errors, nothing on it is touched for the rest of the run.",
+                name,
+                errors
+            );
+            self.summary.suspended_devices.push((name, errors));
+        }
+    }
+
+    fn reset_breaker(&mut self, archive: &Path, dest: &Path) {
+        let Some(breaker) = &mut self.breaker else {
+            return;
+        };
+        for device in [archive, dest].into_iter().filter_map(Self::device_of) {
+            breaker.success(device);
+        }
+    }
+
+    /// Whether `path` is a recovery volume of a set still there and not extracted by this run.
+    fn awaits_extraction(&self, path: &Path) -> bool {
+        if !recovery::is_recovery_volume(path) {
+            return false;
+        }
+        let Some(first) = SetVolumes::of(path).first else {
+            return false;
+        };
+        !matches!(
+            self.outcomes.get(&first),
+            Some(Outcome::Extracted | Outcome::AlreadyExtracted | Outcome::InvalidPayload)
+        )
+    }
+
+    /// Points the entries of `archive` renamed by its rename map at their current path. Paths outside of the allowed
+    /// directories are ignored, and so is a map that can't be read.
+    fn apply_rename_map(&self, archive: &mut Archive, dest: &Path) {
+        let map = match RenameMap::load(&archive.path) {
+            Ok(Some(map)) => map,
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!("-> Ignoring the rename map: {:#}", e);
+                return;
+            }
+        };
+        let mut roots = vec![dest.to_path_buf()];
+        roots.extend(archive.path.parent().map(Path::to_path_buf));
+        roots.extend(self.map_roots.iter().cloned());
+        let mut renamed = HashMap::new();
+        for (name, entry) in map.entries() {
+            if !renamemap::within(&entry.path, &roots) {
+                log::warn!(
+                    "-> Ignoring the rename of '{}' to '{}', it's outside of the allowed directories.",
+                    name.display(),
+                    entry.path.display()
+                );
+                continue;
+            }
+            renamed.insert(name.to_path_buf(), entry);
+        }
+        log::debug!("-> {} entries renamed by the rename map.", renamed.len());
+        archive.set_renamed(renamed);
+    }
+
+    /// Points the already-extracted check of the files moved to the tier directory at where they are now, and of the
+    /// directories they were in when those are gone.
+    fn apply_tiered(&self, archive: &mut Archive, dest: &Path) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let tiered: Vec<(&Path, &Path)> = state.tiered_files(&archive.path).collect();
+        if tiered.is_empty() {
+            return;
+        }
+        let mut renamed: HashMap<PathBuf, Renamed> = HashMap::new();
+        for (path, to) in &tiered {
+            if let Ok(name) = path.strip_prefix(dest) {
+                let entry = Renamed {
+                    path: to.to_path_buf(),
+                    size: None,
+                };
+                renamed.insert(name.to_path_buf(), entry);
+            }
+        }
+        for header in archive.headers.iter().filter(|header| header.is_directory()) {
+            let dir = dest.join(&header.filename);
+            if dir.exists() {
+                continue;
+            }
+            // Where a file that was below it went, up as many levels as it was below it.
+            let moved = tiered.iter().find_map(|(path, to)| {
+                let below = path.strip_prefix(&dir).ok()?.components().count();
+                to.ancestors().nth(below).map(Path::to_path_buf)
+            });
+            if let Some(path) = moved {
+                renamed.insert(header.filename.clone(), Renamed { path, size: None });
+            }
+        }
+        if !renamed.is_empty() {
+            log::debug!("-> {} files moved to the tier directory.", renamed.len());
+            archive.add_renamed(renamed);
+        }
+    }
+
+    /// How the filesystem of `dest` compares names, probed once per device with --name-matching auto.
+    fn name_rules(&mut self, dest: &Path) -> NameRules {
+        if self.name_matching != NameMatching::Auto {
+            return self.name_matching.rules(dest);
+        }
+        match Self::device_of(dest) {
+            Some(device) => *self.name_rules.entry(device).or_insert_with(|| NameRules::probe(dest)),
+            None => NameRules::probe(dest),
+        }
+    }
+
+    /// Points the already-extracted check of the entries `dest` stores under another spelling, with another case or
+    /// normalization its filesystem ignores, at the files as they are stored.
+    fn apply_name_rules(&mut self, archive: &mut Archive, dest: &Path) {
+        let rules = self.name_rules(dest);
+        if rules.is_exact() {
+            return;
+        }
+        let mut stored = HashMap::new();
+        for header in archive
+            .headers
+            .iter()
+            .filter(|header| archive.renamed(&header.filename).is_none())
+        {
+            match rules.find(dest, &header.filename) {
+                Ok(Some(path)) => {
+                    log::debug!("-> '{}' is stored as '{}'.", header.filename.display(), path.display());
+                    stored.insert(header.filename.clone(), Renamed { path, size: None });
+                }
+                Ok(None) => {}
+                Err(e) => log::debug!("-> Could not look for '{}': {}", header.filename.display(), e),
+            }
+        }
+        archive.add_renamed(stored);
+    }
+
+    /// Goes through the checks a run makes for the root archive at `path` without touching anything, with what each
+    /// looked at and found, and what the next run would therefore do with it.
+    pub fn explain(&mut self, path: &Path) -> anyhow::Result<Explanation> {
+        let mut explanation = Explanation::new(path);
+        let hours = |duration: Duration| format!("{:.1}h", duration.as_secs_f64() / 3600.0);
+        explanation.step("Root detection", self.naming.describe(path));
+        if !self.naming.is_root_rar_file(path) {
+            explanation.verdict("nothing, only root archives are processed");
+            return Ok(explanation);
+        }
+        if self.is_set_aside(path) {
+            explanation.step("Set aside", "in the backup or quarantine directory");
+            explanation.verdict("nothing, these directories aren't scanned");
+            return Ok(explanation);
+        }
+        match self.ignore_rules.as_ref().map(|rules| rules.ignoring_rule(path, false)) {
+            Some(Some(rule)) => {
+                explanation.step("Ignore rules", format!("ignored by '{}'", rule));
+                explanation.verdict("nothing, it's ignored");
+                return Ok(explanation);
+            }
+            Some(None) => explanation.step("Ignore rules", "no rule matches it"),
+            None => explanation.step("Ignore rules", "none"),
+        }
+        if let Some(marker) = self.markers.find(parent_dir(path)) {
+            explanation.step("Download markers", format!("'{}' is next to it", marker.display()));
+            explanation.verdict("skips it while the download is in progress, its parts are kept");
+            return Ok(explanation);
+        }
+        explanation.step("Download markers", "none next to it");
+        let overrides = match Overrides::load(path) {
+            Ok(overrides) => overrides,
+            Err(failure) => {
+                explanation.step("Sidecar", format!("invalid, {}", failure));
+                explanation.detail(failure.hint());
+                explanation.verdict("fails it, its parts are kept");
+                return Ok(explanation);
+            }
+        };
+        match &overrides {
+            Some(overrides) => explanation.step(
+                "Sidecar",
+                format!("'{}' sets {}", overrides.path.display(), overrides.keys().join(", ")),
+            ),
+            None => explanation.step("Sidecar", "none"),
+        }
+        if let Some(overrides) = overrides {
+            if overrides.skip {
+                explanation.verdict("skips it as its sidecar says, its parts are kept");
+                return Ok(explanation);
+            }
+            self.overrides.insert(path.to_path_buf(), overrides);
+        }
+
+        let plan = match self.plan(path.to_path_buf()) {
+            Ok(plan) => plan,
+            Err(e) => {
+                let Some(failure) = e.downcast_ref::<ExtractionError>() else {
+                    return Err(e);
+                };
+                explanation.step("Open", format!("fails, {}", failure));
+                explanation.detail(failure.hint());
+                let parts = archive::list_set_parts(path, &self.naming)?;
+                explanation.step("Parts", format!("{} found", parts.len()));
+                for part in &parts {
+                    explanation.detail(format!("'{}'", part.display()));
+                }
+                explanation.verdict("fails it, its parts are kept");
+                return Ok(explanation);
+            }
+        };
+        let archive = &plan.archive;
+        let parts = archive.list_parts().context("list parts")?;
+        explanation.step(
+            "Parts",
+            format!(
+                "{} found, {} packed → {} unpacked in {} entries",
+                parts.len(),
+                format_size(archive.packed_size().context("packed size")?),
+                format_size(archive.unpacked_size()),
+                archive.headers.len()
+            ),
+        );
+        for part in &parts {
+            let size = fs::metadata(part).context("stat part")?.len();
+            explanation.detail(format!("'{}' {}", part.display(), format_size(size)));
+        }
+        explanation.step("Destination", format!("'{}'", plan.dest.display()));
+
+        let checks = archive
+            .headers
+            .iter()
+            .map(|header| archive.check_entry(&plan.dest, header))
+            .collect::<io::Result<Vec<_>>>()
+            .context("is already extracted")?;
+        let changed = checks.iter().filter(|check| !check.matches()).count();
+        explanation.step(
+            "Already extracted",
+            match changed {
+                0 => format!("yes, the {} entries are in place", checks.len()),
+                _ => format!("no, {} of the {} entries are not in place", changed, checks.len()),
+            },
+        );
+        for check in &checks {
+            explanation.detail(explain::describe_entry(check));
+        }
+        if plan.extracted && self.verify_crc {
+            explanation.detail("the CRCs of the files are checked too with --verify-crc");
+        }
+        if !plan.extracted {
+            if let Some(quarantined) = self.state.as_ref().and_then(|state| state.quarantined(path)) {
+                if quarantined == Fingerprint::of(archive).context("fingerprint archive")?.to_string() {
+                    explanation.step("Quarantine", "its files were flagged by the scanner in an earlier run");
+                    explanation.verdict("leaves it in quarantine, its parts are kept");
+                    return Ok(explanation);
+                }
+                explanation.step("Quarantine", "flagged in an earlier run, the archive changed since");
+            }
+        }
+
+        let action = match plan.extracted {
+            true => "leaves it extracted".to_string(),
+            false => format!("extracts it into '{}'", plan.dest.display()),
+        };
+        let Some((remove_after, source)) = self.remove_after_source(path) else {
+            explanation.step("Removal", "no threshold applies, the parts are kept");
+            explanation.verdict(format!("{}, its parts are kept", action));
+            return Ok(explanation);
+        };
+        explanation.step(
+            "Removal",
+            match self.no_remove {
+                true => format!(
+                    "after {} ({}), but --no-remove keeps the parts",
+                    hours(remove_after),
+                    source
+                ),
+                false => format!("after {} ({})", hours(remove_after), source),
+            },
+        );
+        // The set goes at once, when its newest part is old enough.
+        let newest_age = self
+            .newest_mtime(&parts)?
+            .map(|newest| newest.elapsed().unwrap_or_default());
+        let removable = newest_age.is_some_and(|age| age > remove_after);
+        for part in &parts {
+            let age = self.mtime(part)?.elapsed().unwrap_or_default();
+            let eligibility = match removable {
+                true => "removable now".to_string(),
+                false => format!(
+                    "removable in {}",
+                    hours(remove_after.saturating_sub(newest_age.unwrap_or_default()))
+                ),
+            };
+            explanation.detail(format!("'{}' {} old, {}", part.display(), hours(age), eligibility));
+        }
+        explanation.verdict(match (self.no_remove, removable) {
+            (true, _) => format!("{}, its parts are kept", action),
+            (false, false) => format!("{}, its parts are removed once the newest is old enough", action),
+            (false, true) => format!("{}, then removes its parts", action),
+        });
+        Ok(explanation)
+    }
+
+    /// Works out what processing the queued archives would do, without touching anything. `assumed_throughput` in
+    /// bytes per second wins over the one measured by previous runs.
+    pub fn estimate(&mut self, root_dir: &Path, assumed_throughput: Option<u64>) -> anyhow::Result<Estimate> {
+        let mut estimate = Estimate::default();
+        let mut parts = HashSet::new();
+        while let Some(entry) = self.queue.pop_front()? {
+            log::debug!("Estimating '{}'.", entry.display());
+            let plan = self.plan(entry)?;
+            estimate.archives += 1;
+            if !plan.extracted {
+                estimate.to_extract += 1;
+                estimate.packed_bytes += plan.archive.packed_size().context("packed size")?;
+                estimate.unpacked_bytes += plan.archive.unpacked_size();
+            }
+            let archive_parts = plan.archive.list_parts().context("list parts")?;
+            if plan.removable {
+                for part in &archive_parts {
+                    estimate.freed_bytes += fs::metadata(part).context("stat part")?.len();
+                }
+            }
+            parts.extend(archive_parts);
+        }
+        if self.removes_anything() {
+            let is_cruft = |path: &Path| is_cruft(path) && !self.is_ignored(path, false) && !self.is_set_aside(path);
+            let files = walk::find_files(root_dir, false, is_cruft).context("scan for cruft")?;
+            for entry in files.into_iter().filter(|entry| !parts.contains(entry)) {
+                if let Some(remove_after) = self.resolve_remove_after(&entry, false) {
+                    if self.should_remove(&entry, remove_after)? {
+                        estimate.freed_bytes += fs::metadata(&entry).context("stat cruft")?.len();
+                    }
+                }
+            }
+        }
+        estimate.throughput = match assumed_throughput {
+            Some(rate) => Some(Throughput::Assumed(rate as f64)),
+            None => self
+                .state
+                .as_ref()
+                .and_then(StateFile::throughput)
+                .map(Throughput::Measured),
+        };
+        #[cfg(feature = "telemetry")]
+        if let Some(tracer) = &mut self.tracer {
+            tracer.finish();
+        }
+        Ok(estimate)
+    }
+
+    /// Drops the queued archives of the releases with nothing left to do: every archive is extracted and none has parts
+    /// old enough to be removed.
+    pub fn retain_incomplete_releases(&mut self) -> anyhow::Result<()> {
+        let mut incomplete = HashSet::new();
+        let mut releases = HashSet::new();
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.retain(|entry| {
+            let release = self.release_of(entry);
+            if !incomplete.contains(&release) {
+                let plan = self.plan(entry.to_path_buf())?;
+                if !plan.extracted || plan.removable {
+                    incomplete.insert(release.clone());
+                }
+                releases.insert(release);
+            }
+            Ok(true)
+        })?;
+        self.complete_releases = releases.difference(&incomplete).cloned().collect();
+        log::info!(
+            "{} releases out of {} are complete, leaving them out.",
+            self.complete_releases.len(),
+            releases.len()
+        );
+        queue.retain(|entry| Ok(!self.complete_releases.contains(&self.release_of(entry))))?;
+        self.queue = queue;
+        Ok(())
+    }
+
+    /// Release of an archive. Nested archives extracted outside of the root directory belong to the release of the
+    /// archive containing them.
+    fn release_of(&self, path: &Path) -> String {
+        let Some(root_dir) = &self.root_dir else {
+            return release::ROOT_RELEASE.to_string();
+        };
+        if let Some(release) = release::release_of(root_dir, path) {
+            return release;
+        }
+        let parent = self
+            .summary
+            .nested_archives
+            .iter()
+            .find(|(_, entries)| entries.iter().any(|entry| entry.path == path));
+        match parent {
+            Some((parent, _)) => self.release_of(parent),
+            None => release::ROOT_RELEASE.to_string(),
+        }
+    }
+
+    /// Groups the outcomes by release, along with the size of the archives and leftovers still in each release.
+    fn releases(&self) -> anyhow::Result<Vec<ReleaseSummary>> {
+        let Some(root_dir) = &self.root_dir else {
+            return Ok(Vec::new());
+        };
+        let mut releases: BTreeMap<String, ReleaseSummary> = BTreeMap::new();
+        let mut outcomes: Vec<_> = self.outcomes.iter().collect();
+        outcomes.sort_by_key(|(path, _)| *path);
+        for (path, outcome) in outcomes {
+            let name = self.release_of(path);
+            let release = releases.entry(name.clone()).or_insert_with(|| ReleaseSummary {
+                name,
+                archives: Vec::new(),
+                complete: true,
+                problems: 0,
+                pending_removal_bytes: 0,
+            });
+            release.archives.push((path.clone(), outcome.status()));
+            match outcome {
+                Outcome::Extracted | Outcome::AlreadyExtracted => {}
+                Outcome::Skipped
+                | Outcome::Deferred
+                | Outcome::Vanished
+                | Outcome::Failed
+                | Outcome::Repairable
+                | Outcome::InvalidPayload
+                | Outcome::Downloading
+                | Outcome::Suspended
+                | Outcome::Quarantined
+                | Outcome::MissingContent => {
+                    release.complete = false;
+                    release.problems += 1;
+                }
+            }
+        }
+        let leftovers = walk::find_files(root_dir, false, is_cruft).context("scan for leftovers")?;
+        for path in leftovers {
+            if self.removed.contains(&path) {
+                continue;
+            }
+            let Some(release) = release::release_of(root_dir, &path).and_then(|name| releases.get_mut(&name)) else {
+                continue;
+            };
+            release.pending_removal_bytes += fs::metadata(&path).map(|md| md.len()).unwrap_or(0);
+        }
+        Ok(releases.into_values().collect())
+    }
+
+    /// The first file of an already extracted archive not matching the CRC of its entry, always `None` without
+    /// --verify-crc. A file unchanged since it was last hashed is trusted from the state file, and the hashing of a
+    /// large file resumes from its last checkpoint when an earlier run was interrupted.
+    fn verify_extracted(&mut self, archive: &Archive, dest: &Path) -> anyhow::Result<Option<PathBuf>> {
+        if !self.verify_crc {
+            return Ok(None);
+        }
+        for header in archive.headers.iter().filter(|header| header.is_file()) {
+            let Some(expected) = header.crc else {
+                continue;
+            };
+            let path = match archive.renamed(&header.filename) {
+                // Transcoded, its size was checked.
+                Some(renamed) if renamed.size.is_some_and(|size| size != header.unpacked_size) => continue,
+                Some(renamed) => renamed.path.clone(),
+                None => dest.join(&header.filename),
+            };
+            let key = FileKey::of(&path).with_context(|| format!("stat extracted file '{}'", path.display()))?;
+            let cached = match &self.state {
+                Some(state) if !self.revalidate => state.verified(&path, key),
+                _ => None,
+            };
+            let crc = match cached {
+                Some(crc) => {
+                    self.summary.verify_cache_hits += 1;
+                    crc
+                }
+                None => {
+                    let resume = match &self.state {
+                        Some(state) if !self.revalidate => state.checkpoint(&path, key),
+                        _ => None,
+                    };
+                    if let Some(checkpoint) = resume {
+                        log::info!(
+                            "-> Resuming the hashing of '{}' at {}.",
+                            header.filename.display(),
+                            format_size(checkpoint.offset)
+                        );
+                    }
+                    let dry_run = self.dry_run;
+                    let state = &mut self.state;
+                    let crc = verify::crc32(&path, resume.map(|c| (c.offset, c.crc)), |offset, crc| {
+                        if let Some(state) = state.as_mut().filter(|_| !dry_run) {
+                            state.set_checkpoint(&path, Checkpoint { key, offset, crc });
+                            state.save()?;
+                        }
+                        Ok(())
+                    })
+                    .with_context(|| format!("hash extracted file '{}'", path.display()))?;
+                    if let Some(state) = &mut self.state {
+                        state.set_verified(&path, key, crc);
+                    }
+                    self.summary.verified_files += 1;
+                    crc
+                }
+            };
+            if crc != expected {
+                log::warn!(
+                    "-> '{}' doesn't match its CRC, extracting the archive again.",
+                    header.filename.display()
+                );
+                return Ok(Some(header.filename.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Records the files of an archive extracted by something else, with the mtime of the newest file as the time of
+    /// the extraction. Returns false when there's nothing to record or the extraction is already known.
+    fn adopt(&mut self, archive: &Archive, dest: &Path) -> anyhow::Result<bool> {
+        let Some(state) = &mut self.state else {
+            return Ok(false);
+        };
+        if state.records(&archive.path) {
+            return Ok(false);
+        }
+        let files: Vec<PathBuf> = archive
+            .headers
+            .iter()
+            .filter(|header| header.is_file())
+            .map(|header| dest.join(&header.filename))
+            .collect();
+        let mut newest = None;
+        for path in &files {
+            let mtime = fs::metadata(path)
+                .and_then(|md| md.modified())
+                .with_context(|| format!("stat '{}'", path.display()))?;
+            newest = newest.max(Some(mtime));
+        }
+        let Some(time) = newest else {
+            return Ok(false);
+        };
+        for path in &files {
+            state.set_extracted(path, &archive.path, time);
+        }
+        log::info!("-> Adopted the extraction, dated {}.", format_system_time(time));
+        self.summary.adopted_extractions += 1;
+        Ok(true)
+    }
+
+    /// Directory the archive is extracted into: the one of its sidecar, or the rendered --dest-template except for
+    /// nested archives which are always extracted next to themselves.
+    fn destination(
+        &self,
+        archive: &Archive,
+        mtime: SystemTime,
+        overrides: Option<&Overrides>,
+    ) -> anyhow::Result<PathBuf> {
+        let dest = match (
+            overrides.and_then(|overrides| overrides.dest.as_ref()),
+            &self.dest_template,
+        ) {
+            (Some(dest), _) => dest.clone(),
+            (None, Some(template)) if !self.nested.contains(&archive.path) => {
+                template.render(archive, mtime).context("render destination")?
+            }
+            // The parent of the directories of a spread set, which is canonical like the parent of a queued archive.
+            _ => match archive.spread_volumes() {
+                Some((parent, _)) => return Ok(parent.to_path_buf()),
+                None => return Ok(archive.path.parent().expect("no parent path").to_path_buf()),
+            },
+        };
+        Ok(paths::canonical(&dest))
+    }
+
+    /// Processes a single archive end to end, including the archives nested inside of it, without scanning for other
+    /// archives. Returns the outcome of the given archive.
+    pub fn process_single(&mut self, path: &Path) -> anyhow::Result<Outcome> {
+        self.enqueued.insert(path.to_path_buf());
+        self.queue.push_back(path.to_path_buf())?;
+        while self.process_next()? {}
+        Ok(self.outcomes[path])
+    }
+
+    fn process_entry(&mut self, entry: PathBuf) -> anyhow::Result<Outcome> {
+        log::info!("Analyzing '{}'.", entry.display());
+        match fs::symlink_metadata(&entry) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(self.vanished(entry)),
+            _ => {}
+        }
+        if !self.nested.contains(&entry) {
+            if let Some(marker) = self.markers.find(parent_dir(&entry)) {
+                log::warn!(
+                    "-> Download in progress, '{}' is next to it. Skipping.",
+                    marker.display()
+                );
+                self.kept_parts.extend(archive::list_set_parts(&entry, &self.naming)?);
+                self.summary.downloading_archives.push(entry);
+                return Ok(Outcome::Downloading);
+            }
+        }
+        if self.on_suspended_device(&entry) {
+            log::warn!("-> The device of the archive is suspended after IO errors, skipping.");
+            self.kept_parts.extend(archive::list_set_parts(&entry, &self.naming)?);
+            self.summary.suspended_archives.push(entry);
+            return Ok(Outcome::Suspended);
+        }
+        if let Some(count) = self.panic_streak(&entry).filter(|count| *count >= self.panic_limit) {
+            log::warn!(
+                "-> Processing it panicked in {} runs in a row, left alone until its first part changes. Its parts will not be removed.",
+                count
+            );
+            self.kept_parts.extend(archive::list_set_parts(&entry, &self.naming)?);
+            return Ok(Outcome::Quarantined);
+        }
+        let overrides = match Overrides::load(&entry) {
+            Ok(overrides) => overrides,
+            Err(failure) => {
+                log::error!("-> {}. Its parts will not be removed.", failure);
+                log::error!("-> {}", failure.hint());
+                self.kept_parts.extend(archive::list_set_parts(&entry, &self.naming)?);
+                self.summary.failed_archives.push((entry, failure));
+                return Ok(Outcome::Failed);
+            }
+        };
+        if let Some(overrides) = &overrides {
+            log::info!(
+                "-> Sidecar '{}' sets {}.",
+                overrides.path.display(),
+                overrides.keys().join(", ")
+            );
+            if overrides.skip {
+                log::info!("-> Skipped by its sidecar. Its parts will not be removed.");
+                self.kept_parts.extend(archive::list_set_parts(&entry, &self.naming)?);
+                self.summary.sidecar_skipped_archives.push(entry);
+                return Ok(Outcome::Skipped);
+            }
+        }
+        let password = overrides.as_ref().and_then(|overrides| overrides.password.as_deref());
+        let entry_mtime = self.mtime(&entry)?;
+
+        let span = self.start_span("rarscan.open");
+        let opened = match self.prefetch.take() {
+            // Prefetched without the password, which encrypted headers need.
+            Some(prefetch) if prefetch.path() == entry && password.is_none() => prefetch.finish(),
+            Some(prefetch) if prefetch.path() == entry => Archive::open_with_password(&entry, &self.naming, password),
+            prefetch => {
+                self.prefetch = prefetch;
+                Archive::open_with_password(&entry, &self.naming, password)
+            }
+        };
+        match &opened {
+            Ok(archive) => self.trace_attr("rarscan.entries", archive.headers.len() as u64),
+            Err(e) => match e.downcast_ref::<ExtractionError>() {
+                Some(failure) => self.fail_span(span, failure.kind(), failure),
+                None => self.fail_span(span, "internal", format!("{:#}", e)),
+            },
+        }
+        self.end_span(span);
+        let mut archive = match opened {
+            Ok(archive) => archive,
+            Err(e) => match e.downcast::<ExtractionError>() {
+                Ok(failure) => {
+                    log::error!(
+                        "-> Archive can't be opened, {}. Its parts will not be removed.",
+                        failure
+                    );
+                    log::error!("-> {}", failure.hint());
+                    self.trip_breaker(&entry, &failure);
+                    let recovered = self.recover_set(&entry, &failure);
+                    if recovered == Some(Outcome::Deferred) {
+                        return Ok(Outcome::Deferred);
+                    }
+                    self.kept_parts.extend(archive::list_set_parts(&entry, &self.naming)?);
+                    self.summary.failed_archives.push((entry, failure));
+                    return Ok(recovered.unwrap_or(Outcome::Failed));
+                }
+                Err(e) => return Err(e).context("archive open"),
+            },
+        };
+        archive.set_preallocate(self.preallocate);
+        archive.set_fast_store_copy(self.fast_store_copy);
+        self.identify(&archive).context("archive id")?;
+        if let Some(volumes) = archive.renamed_volumes() {
+            log::warn!(
+                "-> Volumes renamed after another set, following the headers through {} volumes:",
+                volumes.len()
+            );
+            for volume in volumes {
+                log::info!("   -> '{}'", volume.display());
+            }
+            self.claimed_volumes.extend(volumes.iter().cloned());
+            self.summary.renamed_sets.push((archive.path.clone(), volumes.to_vec()));
+        }
+        if let Some((parent, volumes)) = archive.spread_volumes() {
+            log::info!(
+                "-> Volumes spread across the directories of '{}', following the headers through {} volumes:",
+                parent.display(),
+                volumes.len()
+            );
+            for volume in volumes {
+                log::info!("   -> '{}'", volume.display());
+            }
+            self.claimed_volumes.extend(volumes.iter().cloned());
+        }
+        if let Some(overrides) = overrides.as_ref().filter(|overrides| !overrides.exclude.is_empty()) {
+            let excluded = archive.set_exclude(overrides.exclude.clone());
+            log::info!("-> Excluding {} entries.", excluded);
+        }
+        if self.flatten_single_dir {
+            if let Some(dir) = archive.flatten_single_dir() {
+                log::info!("-> Flattening top-level directory '{}'.", dir.display());
+            }
+        }
+        let dest = self.destination(&archive, entry_mtime, overrides.as_ref())?;
+        let sidecar_dest = overrides.as_ref().is_some_and(|overrides| overrides.dest.is_some());
+        if sidecar_dest || self.dest_template.is_some() && !self.nested.contains(&archive.path) {
+            log::info!("-> Destination '{}'.", dest.display());
+        }
+        if let Some(overrides) = overrides {
+            self.overrides.insert(archive.path.clone(), overrides);
+        }
+        if self.on_suspended_device(&dest) {
+            log::warn!("-> The device of the destination is suspended after IO errors, skipping.");
+            self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            self.summary.suspended_archives.push(archive.path.clone());
+            return Ok(Outcome::Suspended);
+        }
+        if !self.retrying.contains(&archive.path) {
+            self.summary.archives_processed += 1;
+        }
+
+        if archive.is_empty() {
+            log::warn!("-> Empty archive, it contains no files.");
+            self.summary.empty_archives.push(archive.path.clone());
+        }
+
+        let packed_size = archive.packed_size().context("packed size")?;
+        let unpacked_size = archive.unpacked_size();
+        let max_written = (unpacked_size as f64 * MAX_WRITTEN_FACTOR) as u64 + MAX_WRITTEN_SLACK;
+        archive.set_max_written(self.limits.is_some().then_some(max_written));
+        archive.set_stream_budget(self.limits.as_ref().is_some_and(|limits| limits.given));
+        let ratio = compression_ratio(packed_size, unpacked_size);
+        self.trace_attr("rarscan.archive.packed_bytes", packed_size);
+        self.trace_attr("rarscan.archive.unpacked_bytes", unpacked_size);
+        log::info!(
+            "-> {} packed → {} unpacked, ratio {:.2}",
+            format_size(packed_size),
+            format_size(unpacked_size),
+            ratio
+        );
+        if let Some(threshold) = self.suspicious_ratio {
+            if ratio > threshold {
+                log::warn!(
+                    "-> Suspicious archive, unpacked size is {:.2} times the packed size (threshold {:.2}).",
+                    ratio,
+                    threshold
+                );
+            }
+        }
+
+        log::debug!("-> Archive is {}", archive.describe());
+        let recovery_volumes = SetVolumes::of(&archive.path).recovery.len();
+        if recovery_volumes > 0 {
+            log::info!("-> Set has {} recovery volumes.", recovery_volumes);
+        }
+        if let Some(feature) = archive.unsupported_feature() {
+            log::error!(
+                "-> Archive requires {} which is not supported. Its parts will not be removed.",
+                feature
+            );
+            self.summary.unsupported_archives.push(archive.path.clone());
+            self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            return Ok(Outcome::Skipped);
+        }
+
+        if let Some(reason) = self
+            .fake_detector
+            .as_ref()
+            .and_then(|detector| detector.check(&archive))
+        {
+            log::warn!("-> Suspected fake, {}. Its parts will not be removed.", reason);
+            self.summary.suspected_fakes.push(archive.path.clone());
+            self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            return Ok(Outcome::Skipped);
+        }
+
+        if let Some(reason) = self.exceeds_limits(&archive) {
+            log::warn!("-> Skipping archive, {}. Its parts will not be removed.", reason);
+            self.summary.oversized_archives.push(archive.path.clone());
+            self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            return Ok(Outcome::Skipped);
+        }
+
+        if !archive.long_names(&dest).is_empty() && self.shorten_long_names {
+            for (name, short) in archive.shorten_long_names() {
+                log::info!("-> Shortening '{}' to '{}'.", name.display(), short.display());
+            }
+        }
+        let long_names = archive.long_names(&dest);
+        if !long_names.is_empty() {
+            log::error!(
+                "-> {} entries exceed the file name or path length limits. Its parts will not be removed:",
+                long_names.len()
+            );
+            for path in &long_names {
+                log::error!("   -> '{}'", path.display());
+            }
+            self.summary.long_name_archives.push(archive.path.clone());
+            self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            return Ok(Outcome::Skipped);
+        }
+        let rules = self.name_rules(&dest);
+        let files = archive.headers.iter().filter(|header| header.is_file());
+        let collisions = rules.collisions(files.map(|header| header.filename.as_path()));
+        if !collisions.is_empty() {
+            log::error!(
+                "-> {} pairs of entries would be the same file, the destination compares names {}. Its parts will not be removed:",
+                collisions.len(),
+                rules
+            );
+            for (first, second) in &collisions {
+                log::error!("   -> '{}' and '{}'", first.display(), second.display());
+            }
+            self.summary.name_conflict_archives.push(archive.path.clone());
+            self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            return Ok(Outcome::Skipped);
+        }
+
+        self.apply_rename_map(&mut archive, &dest);
+        self.apply_tiered(&mut archive, &dest);
+        self.apply_name_rules(&mut archive, &dest);
+        let changed = self.traced("rarscan.verify", |q| {
+            match archive.changed_entry(&dest).context("is already extracted")? {
+                Some(file) => Ok(Some(file.to_path_buf())),
+                None => q.verify_extracted(&archive, &dest),
+            }
+        })?;
+        // Changes to the files of an extraction rarscan did before make it extract the archive again.
+        let changed = changed.map(|file| dest.join(file));
+        let extracted = changed.is_none();
+        let reextraction = changed
+            .as_ref()
+            .filter(|_| self.state.as_ref().is_some_and(|state| state.records(&archive.path)));
+        let fingerprint = Fingerprint::of(&archive).context("fingerprint archive")?;
+        let duplicate = self.duplicate_of(&archive, &fingerprint)?;
+        let quarantined = self.state.as_ref().and_then(|state| state.quarantined(&archive.path));
+        if !extracted && quarantined == Some(fingerprint.to_string().as_str()) {
+            log::warn!("-> Quarantined by the scanner in an earlier run, not extracting it again. Its parts will not be removed.");
+            self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            return Ok(Outcome::Quarantined);
+        }
+        let outcome = if extracted {
+            log::info!("-> Archive already extracted.");
+            if let Some(state) = &mut self.state {
+                state.forget_flaps(&archive.path);
+            }
+            let unrecorded = self.state.as_ref().is_some_and(|state| !state.records(&archive.path));
+            if unrecorded && !archive.is_empty() {
+                if self.auto_adopt {
+                    self.adopt(&archive, &dest)?;
+                } else {
+                    log::debug!("-> Extraction not recorded, it was done by something else.");
+                    self.summary.unrecorded_extractions += 1;
+                }
+            }
+            if let Some(original) = &duplicate {
+                let original = original.archive.path.clone();
+                self.summary
+                    .duplicates
+                    .push((original, archive.path.clone(), "already_extracted"));
+            }
+            Outcome::AlreadyExtracted
+        } else if self.link_duplicate(&archive, &dest, duplicate.as_ref())? {
+            Outcome::Extracted
+        } else {
+            if let Some(original) = duplicate.as_ref().map(|plan| plan.archive.path.clone()) {
+                if self.duplicate_policy == DuplicatePolicy::Skip {
+                    log::info!("-> Duplicate of '{}', skipping.", original.display());
+                    self.summary
+                        .duplicates
+                        .push((original, archive.path.clone(), "skipped"));
+                    self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                    return Ok(Outcome::Skipped);
+                }
+                self.summary
+                    .duplicates
+                    .push((original, archive.path.clone(), "extracted"));
+            }
+            if let Some(file) = reextraction.filter(|_| self.flapping_policy == FlappingPolicy::Pause) {
+                let count = self.state.as_ref().map_or(0, |state| state.flaps(&archive.path));
+                if count > self.flapping_threshold {
+                    log::warn!(
+                        "-> Archive re-extracted {} times already, '{}' keeps changing externally. Not extracting it \
+                         again until its files are back in place.",
+                        count,
+                        file.display()
+                    );
+                    self.summary
+                        .flapping_archives
+                        .push((archive.path.clone(), count, file.clone()));
+                    self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                    return Ok(Outcome::Skipped);
+                }
+            }
+            if self.skip_in_use && !self.dry_run {
+                let existing: Vec<PathBuf> = archive
+                    .headers
+                    .iter()
+                    .filter(|header| header.is_file())
+                    .map(|header| dest.join(&header.filename))
+                    .filter(|path| path.exists())
+                    .collect();
+                let in_use = inuse::open_by_other_processes(&existing);
+                if !in_use.is_empty() {
+                    for path in &in_use {
+                        log::warn!("-> '{}' is open by another process.", path.display());
+                    }
+                    if self.retrying.contains(&archive.path) {
+                        log::warn!("-> Archive still in use, leaving it untouched.");
+                        self.summary.busy_archives.push(archive.path.clone());
+                        self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                        return Ok(Outcome::Skipped);
+                    }
+                    log::info!("-> Deferring archive to the end of the run.");
+                    self.deferred.push(archive.path.clone());
+                    return Ok(Outcome::Deferred);
+                }
+            }
+
+            if self.is_blocked(&archive, &dest).context("free space")? {
+                self.summary.blocked_archives.push(archive.path.clone());
+                self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                return Ok(Outcome::Skipped);
+            }
+
+            if let Some(reason) = self.lacks_space(&archive, &dest).context("free space")? {
+                log::error!("-> Not extracting, {}. Its parts will not be removed.", reason);
+                self.summary.no_space_archives.push(archive.path.clone());
+                self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                return Ok(Outcome::Skipped);
+            }
+
+            if let Some((percent, max)) = self.over_disk_usage(&archive, &dest).context("disk usage")? {
+                if self.retrying.contains(&archive.path) {
+                    log::error!(
+                        "-> Not extracting, it would still bring '{}' to {:.1}% used, over --max-disk-usage {}%. Its \
+                         parts will not be removed.",
+                        dest.display(),
+                        percent,
+                        max
+                    );
+                    self.summary.no_space_archives.push(archive.path.clone());
+                    self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                    return Ok(Outcome::Skipped);
+                }
+                log::info!(
+                    "-> Deferring, extracting would bring '{}' to {:.1}% used, over --max-disk-usage {}%.",
+                    dest.display(),
+                    percent,
+                    max
+                );
+                self.usage_deferred.push(archive.path.clone());
+                return Ok(Outcome::Deferred);
+            }
+
+            // Created for its lock, but still new to the extraction.
+            let missing_dest = match self.inherit_dir_perms && !self.dry_run {
+                true => perms::missing_dirs([dest.clone()]),
+                false => BTreeSet::new(),
+            };
+            let _dest_lock = match self.dry_run {
+                true => None,
+                false => match self.lock_dest(&archive.path, &dest)? {
+                    Ok(lock) => Some(lock),
+                    Err(holder) if self.retrying.contains(&archive.path) => {
+                        log::warn!(
+                            "-> '{}' is still locked by {}, leaving the archive for a later run.",
+                            dest.display(),
+                            describe_holder(&holder)
+                        );
+                        self.summary.locked_archives.push((archive.path.clone(), dest.clone()));
+                        self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                        return Ok(Outcome::Skipped);
+                    }
+                    Err(holder) => {
+                        log::info!(
+                            "-> '{}' is locked by {}, deferring to the end of the run.",
+                            dest.display(),
+                            describe_holder(&holder)
+                        );
+                        self.deferred.push(archive.path.clone());
+                        return Ok(Outcome::Deferred);
+                    }
+                },
+            };
+
+            if self.backups.is_some() {
+                let parts = archive.list_parts().context("list parts")?;
+                self.back_up(&archive.path, &parts);
+            }
+            log::info!("-> Extracting into '{}'.", dest.display());
+            let mut payload_valid = true;
+            let mut clean = true;
+            if !self.dry_run {
+                self.start_prefetch(&archive.path);
+            }
+            // Taken before anything is written so that overwrites report what was there.
+            let previous: HashMap<&Path, Option<FileState>> = match self.changelog {
+                Some(_) => archive
+                    .headers
+                    .iter()
+                    .filter(|header| header.is_file())
+                    .map(|header| (header.filename.as_path(), FileState::of(&dest.join(&header.filename))))
+                    .collect(),
+                None => HashMap::new(),
+            };
+            if !self.dry_run {
+                let span = self.start_span("rarscan.extract");
+                self.events.emit(Event::ExtractStart {
+                    archive: &archive.path,
+                    archive_id: self.summary.archive_ids.get(&archive.path).map(String::as_str),
+                    format: archive.format,
+                    dest: &dest,
+                    packed_size,
+                    unpacked_size,
+                });
+                // Listed before anything is created to tell the new directories from the ones already there.
+                let created: BTreeSet<PathBuf> = match self.inherit_dir_perms {
+                    true => perms::missing_dirs(archive.headers.iter().filter_map(|header| {
+                        let path = dest.join(&header.filename);
+                        match header.is_directory() {
+                            true => Some(path),
+                            false => path.parent().map(Path::to_path_buf),
+                        }
+                    })),
+                    false => BTreeSet::new(),
+                };
+                let created: BTreeSet<PathBuf> = missing_dest.into_iter().chain(created).collect();
+                fs::create_dir_all(&dest).context("create destination")?;
+                if self.audit_log.is_some() {
+                    let rule = match reextraction {
+                        Some(_) => "re-extraction",
+                        None => "extraction",
+                    };
+                    for header in archive.headers.iter().filter(|header| header.is_file()) {
+                        let path = dest.join(&header.filename);
+                        if fs::symlink_metadata(&path).is_ok() {
+                            self.audit(Action::Overwrite, &path, Some(&archive.path), rule)?;
+                        }
+                    }
+                }
+                let started = Instant::now();
+                let mut written = 0;
+                let mut skipped = false;
+                let mut files = Vec::new();
+                // Cloned for the callback to borrow the queue.
+                let sandbox = self.sandbox.clone();
+                let on_extracted = |file: &Path, size: u64| {
+                    written += size;
+                    if self.control.as_ref().is_some_and(|control| control.take_skip()) {
+                        skipped = true;
+                        anyhow::bail!("skipped on request");
+                    }
+                    self.events.emit(Event::FileExtracted {
+                        archive: &archive.path,
+                        file,
+                        size,
+                    });
+                    if self.fsync || self.inherit_dir_perms || self.extracted_mtime != ExtractedMtime::Keep {
+                        files.push(dest.join(file));
+                    }
+                    if let Some(state) = &mut self.state {
+                        state.set_extracted(&dest.join(file), &archive.path, SystemTime::now());
+                    }
+                    if let Some(changelog) = &mut self.changelog {
+                        changelog.record(Change::Written {
+                            path: dest.join(file),
+                            archive: archive.path.clone(),
+                            previous: previous.get(file).copied().flatten(),
+                            size,
+                        });
+                    }
+                    Ok(())
+                };
+                let result = match &sandbox {
+                    Some(sandbox) => sandbox.extract(&archive, &dest, archive.password(), on_extracted),
+                    None => archive.extract_into(&dest, on_extracted),
+                };
+                self.trace_attr("rarscan.bytes_written", written);
+                if let Some(ExtractionError::Oversized { file, written }) =
+                    result.as_ref().err().and_then(|e| e.downcast_ref())
+                {
+                    self.fail_span(span, "oversized", "wrote more than the archive declares");
+                    log::error!(
+                        "-> Aborted extraction at '{}', wrote {} but the archive declares {}. Its parts will not be \
+                         removed.",
+                        file.display(),
+                        format_size(*written),
+                        format_size(unpacked_size)
+                    );
+                    self.summary.oversized_archives.push(archive.path.clone());
+                    self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                    return Ok(Outcome::Skipped);
+                }
+                if skipped {
+                    self.fail_span(span, "skipped", "skipped on request");
+                    log::warn!("-> Extraction skipped on request. Its parts will not be removed.");
+                    self.summary.skipped_archives.push(archive.path.clone());
+                    self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                    return Ok(Outcome::Skipped);
+                }
+                if let Err(e) = result {
+                    let Some(failure) = e.downcast_ref::<ExtractionError>() else {
+                        return Err(e).context("extract_into");
+                    };
+                    self.fail_span(span, failure.kind(), failure);
+                    log::error!("-> Extraction failed, {}. Its parts will not be removed.", failure);
+                    log::error!("-> {}", failure.hint());
+                    if self.filled_destination(failure, &archive, &dest)? {
+                        let required = archive.unpacked_size();
+                        log::warn!(
+                            "-> The destination is full, not trying again until {} are free.",
+                            format_size(required)
+                        );
+                        if let Some(state) = &mut self.state {
+                            state.block(&archive.path, required);
+                        }
+                    }
+                    let failed_path = if failure.is_destination() { &dest } else { &archive.path };
+                    self.trip_breaker(failed_path, failure);
+                    let recovered = self.recover_set(&archive.path, failure);
+                    if recovered == Some(Outcome::Deferred) {
+                        return Ok(Outcome::Deferred);
+                    }
+                    self.summary
+                        .failed_archives
+                        .push((archive.path.clone(), failure.clone()));
+                    self.kept_parts.extend(archive.list_parts().context("list parts")?);
+                    return Ok(recovered.unwrap_or(Outcome::Failed));
+                }
+                self.reset_breaker(&archive.path, &dest);
+                match self.extracted_mtime {
+                    ExtractedMtime::Keep => {}
+                    ExtractedMtime::Now => self.set_extracted_mtimes(&dest, &files, SystemTime::now())?,
+                    ExtractedMtime::Archive => self.set_extracted_mtimes(&dest, &files, entry_mtime)?,
+                }
+                if self.inherit_dir_perms {
+                    let changed = perms::inherit(&created, &files).context("inherit directory permissions")?;
+                    log::debug!("-> Gave {} paths the group of their directory.", changed);
+                }
+                // Nothing below, the removal of the parts in particular, happens before the files are on disk.
+                if self.fsync {
+                    let started = Instant::now();
+                    durable::sync_extracted(&dest, &files).context("sync extracted files")?;
+                    log::info!(
+                        "-> Synced {} files in {:.1}s.",
+                        files.len(),
+                        started.elapsed().as_secs_f64()
+                    );
+                }
+
+                let timing = ArchiveTiming {
+                    path: archive.path.clone(),
+                    duration: started.elapsed(),
+                    bytes: written,
+                };
+                log::info!(
+                    "-> Extracted {} in {:.1}s ({:.1} MB/s).",
+                    format_size(timing.bytes),
+                    timing.duration.as_secs_f64(),
+                    timing.mb_per_sec()
+                );
+                if let Some(threshold) = self.slow_threshold {
+                    if timing.mb_per_sec() < threshold {
+                        log::warn!(
+                            "-> Slow extraction, {:.1} MB/s is below the threshold of {:.1} MB/s.",
+                            timing.mb_per_sec(),
+                            threshold
+                        );
+                    }
+                }
+                if let (Some(file), Some(state)) = (reextraction, &mut self.state) {
+                    let count = state.add_flap(&archive.path);
+                    if count > self.flapping_threshold {
+                        log::warn!(
+                            "-> Archive has been re-extracted {} times, destination file '{}' keeps changing externally.",
+                            count,
+                            file.display()
+                        );
+                        self.summary
+                            .flapping_archives
+                            .push((archive.path.clone(), count, file.clone()));
+                    }
+                }
+                self.end_span(span);
+                if self.scanner.is_some() {
+                    clean = self.traced("rarscan.scan", |q| q.scan_extracted(&archive, &dest))?;
+                }
+                // Only the files the scanner let through count as extracted.
+                if clean {
+                    // Checked against its CRC as it was extracted, the file is verified already.
+                    if let Some(state) = self.state.as_mut().filter(|_| self.verify_crc) {
+                        for header in archive.headers.iter().filter(|header| header.is_file()) {
+                            let path = dest.join(&header.filename);
+                            if let (Some(crc), Ok(key)) = (header.crc, FileKey::of(&path)) {
+                                state.set_verified(&path, key, crc);
+                            }
+                        }
+                    }
+                    self.events.emit(Event::ExtractDone {
+                        archive: &archive.path,
+                        timing: &timing,
+                    });
+                    match archive.format {
+                        Format::Tar(_) => self.summary.tarballs_unpacked += 1,
+                        _ => self.summary.archives_extracted += 1,
+                    }
+                    self.summary.timings.push(timing);
+                }
+                if self.media_prober.is_some() && clean {
+                    payload_valid = self.traced("rarscan.probe", |q| q.probe_media(&archive, &dest))?;
+                }
+            } else {
+                self.summary.pending_extractions += 1;
+                if let Some(changelog) = &mut self.changelog {
+                    for header in archive.headers.iter().filter(|header| header.is_file()) {
+                        changelog.record(Change::Written {
+                            path: dest.join(&header.filename),
+                            archive: archive.path.clone(),
+                            previous: previous[header.filename.as_path()],
+                            size: header.unpacked_size,
+                        });
+                    }
+                }
+            }
+            match (clean, payload_valid) {
+                (false, _) => Outcome::Quarantined,
+                (true, true) => Outcome::Extracted,
+                (true, false) => Outcome::InvalidPayload,
+            }
+        };
+
+        if let Some(state) = &mut self.state {
+            match outcome {
+                Outcome::Quarantined => state.quarantine(&archive.path, fingerprint.to_string()),
+                _ => state.unquarantine(&archive.path),
+            }
+        }
+        // Only the extractions the state file knows about can be originals, the others may not be ours to link.
+        if let Some(state) = self
+            .state
+            .as_mut()
+            .filter(|_| duplicate.is_none() && outcome != Outcome::Quarantined)
+        {
+            if outcome == Outcome::Extracted || state.records(&archive.path) {
+                state.set_fingerprint(fingerprint.to_string(), &archive.path);
+            }
+        }
+
+        // Every archive listed in the headers ends up with a fate, only the enqueued ones join the chain of the
+        // archive in the state file.
+        let mut nested = Vec::new();
+        let mut fates = Vec::new();
+        for name in archive.excluded().iter().filter(|name| self.is_nested_archive(name)) {
+            log::info!(
+                "-> Archive contains archive '{}', excluded from the extraction.",
+                name.display()
+            );
+            fates.push((dest.join(name), NestedFate::Filtered));
+        }
+        for header in &archive.headers {
+            if header.is_file() && self.is_nested_archive(&header.filename) {
+                let nested_path = paths::canonical(&archive.extracted_path(&dest, &header.filename));
+                let fate = if outcome == Outcome::Quarantined {
+                    log::info!(
+                        "-> Archive contains archive '{}', left alone with its quarantined parent.",
+                        header.filename.display()
+                    );
+                    NestedFate::Quarantined
+                } else if self.is_ignored(&nested_path, false) {
+                    log::info!("-> Archive contains archive '{}', ignored.", header.filename.display());
+                    NestedFate::Ignored
+                } else if fs::symlink_metadata(&nested_path).is_err() && self.dry_run && self.deep_dry_run.is_some() {
+                    log::info!(
+                        "-> Archive contains archive '{}', simulated in a dry-run:",
+                        header.filename.display()
+                    );
+                    self.simulate_nested(&archive, &header.filename, &nested_path, 1)?;
+                    NestedFate::Simulated
+                } else if fs::symlink_metadata(&nested_path).is_err() && self.dry_run {
+                    log::info!(
+                        "-> Archive contains archive '{}', not extracted in a dry-run.",
+                        header.filename.display()
+                    );
+                    NestedFate::NotExtracted
+                } else if fs::symlink_metadata(&nested_path).is_err() {
+                    log::warn!(
+                        "-> Archive contains archive '{}', missing after the extraction.",
+                        header.filename.display()
+                    );
+                    NestedFate::Missing
+                } else if !self.nested.insert(nested_path.clone()) {
+                    log::warn!(
+                        "-> Archive '{}' was already enqueued, skipping",
+                        header.filename.display()
+                    );
+                    NestedFate::AlreadyEnqueued
+                } else {
+                    NestedFate::Enqueued
+                };
+                fates.push((nested_path.clone(), fate));
+                if !fate.on_disk() {
+                    continue;
+                }
+
+                // When an embedded rar is extracted from the root rar, the mtime data is taken from the rar and applied
+                // on the extracted file. We get the original date of when the rar was created. This affects the removal
+                // system which depends on the date when the rar was extracted, not when it was originally created. This
+                // resets the mtime of the embedded rar to be the same as the root rar so they both get removed at the
+                // same time.
+                // The other volumes of a nested set would keep the mtime stored for them, whatever --extracted-mtime
+                // gave them, and be removed on a schedule of their own. All of them are set in one batch. This holds
+                // for one enqueued from another parent before too, this extraction wrote it again.
+                let volumes = nested_volumes(&archive, &dest, &nested_path, &self.naming)?;
+                let batch: Vec<PathBuf> = std::iter::once(nested_path.clone())
+                    .chain(volumes.iter().cloned())
+                    .collect();
+                self.set_mtimes(&batch, &archive.path, entry_mtime)?;
+                log::info!(
+                    "-> Update '{}' mtime to {}",
+                    header.filename.display(),
+                    format_system_time(entry_mtime),
+                );
+                if !volumes.is_empty() {
+                    log::info!("-> Update the mtime of its {} other volumes too.", volumes.len());
+                }
+
+                if fate != NestedFate::Enqueued {
+                    continue;
+                }
+                log::info!("-> Archive contains archive '{}', enqueuing", header.filename.display());
+                if let Some(state) = &mut self.state {
+                    state.add_nested(&archive.path, &nested_path);
+                }
+                nested.push(nested_path.clone());
+            }
+        }
+        match self.nested_order {
+            NestedOrder::Immediate => {
+                for path in nested.iter().rev() {
+                    self.queue.push_front(path.clone());
+                }
+            }
+            NestedOrder::Deferred => {
+                for path in nested {
+                    self.queue.push_back(path)?;
+                }
+            }
+        }
+        if !fates.is_empty() {
+            let entries = fates
+                .into_iter()
+                .map(|(path, fate)| NestedEntry {
+                    path,
+                    fate: fate.status(),
+                    outcome: None,
+                })
+                .collect();
+            self.summary.nested_archives.push((archive.path.clone(), entries));
+        }
+
+        let outcome = match outcome {
+            Outcome::Extracted | Outcome::AlreadyExtracted | Outcome::InvalidPayload
+                if !self.has_required_content(&archive, &dest) =>
+            {
+                Outcome::MissingContent
+            }
+            outcome => outcome,
+        };
+
+        if let Some(remove_after) = self.resolve_remove_after(&archive.path, true) {
+            if outcome == Outcome::MissingContent {
+                log::info!("-> Keeping its parts, it may be the only copy of the missing content.");
+                self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            } else if outcome == Outcome::InvalidPayload && self.require_valid_media {
+                log::info!("-> Keeping its parts to download the release again.");
+                self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            } else if outcome == Outcome::Quarantined {
+                log::info!("-> Keeping its parts, the scanner flagged its files.");
+                self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            } else if self.through_symlink(&archive.path) {
+                log::info!("-> Found through a symlinked directory, not removing its parts.");
+                self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            } else if archive.is_empty() && !self.remove_empty_archives {
+                log::info!("-> Not removing the parts of an empty archive.");
+                // Also protect the parts from the cruft pass, which would otherwise match them by extension.
+                self.kept_parts.extend(archive.list_parts().context("list parts")?);
+            } else if let Some(root) = self.chain_root(&archive.path) {
+                log::info!(
+                    "-> Part of the chain of '{}', its parts are removed with the chain.",
+                    root.display()
+                );
+                let parts = archive.list_parts().context("list parts")?;
+                // Found by the scan and enqueued again by its parent, an archive is still a member once.
+                let members = self.pending_chains.entry(root).or_default();
+                if !members.iter().any(|(member, _)| *member == archive.path) {
+                    members.push((archive.path.clone(), parts));
+                }
+            } else {
+                self.record_removal(&archive, remove_after)?;
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Records the parts of `archive` to remove once older than `remove_after`, in the state file too so that another
+    /// process can apply the removals.
+    fn record_removal(&mut self, archive: &Archive, remove_after: Duration) -> anyhow::Result<()> {
+        let parts = archive.list_parts().context("list parts")?;
+        log::debug!("-> Found {} parts", parts.len());
+        // The parts go together, once the newest is old enough. The sidecar goes with them.
+        let eligible_at = self.newest_mtime(&parts)?.map(|newest| newest + remove_after);
+        let mut candidates: Vec<(PathBuf, SystemTime)> = parts
+            .into_iter()
+            .filter_map(|part| Some((part, eligible_at?)))
+            .collect();
+        if let (Some(overrides), Some(eligible_at)) = (self.overrides.get(&archive.path), eligible_at) {
+            candidates.push((overrides.path.clone(), eligible_at));
+        }
+        if let Some(state) = &mut self.state {
+            state.set_pending_removal(&archive.path, &candidates);
+        }
+        self.removals.insert(archive.path.clone(), candidates);
+        Ok(())
+    }
+
+    /// The removals recorded by the archives processed so far.
+    pub fn take_removals(&mut self) -> RemovalSet {
+        std::mem::take(&mut self.removals)
+    }
+
+    /// The removals an earlier run left half done: the archive is gone, some of its parts aren't. Archives still there
+    /// are processed again and record their removal anew.
+    pub fn leftover_removals(&self) -> RemovalSet {
+        let mut leftovers = RemovalSet::default();
+        let Some(state) = &self.state else {
+            return leftovers;
+        };
+        for (archive, parts) in state.pending_removals().iter() {
+            if archive.exists() || self.removals.get(archive).is_some() {
+                continue;
+            }
+            let left = parts.iter().filter(|(part, _)| part.exists()).count();
+            if left == 0 {
+                continue;
+            }
+            log::info!(
+                "Retrying the removal of {} parts of '{}' left by an earlier run.",
+                left,
+                archive.display()
+            );
+            leftovers.insert(archive.to_path_buf(), parts.to_vec());
+        }
+        leftovers
+    }
+
+    /// Removes the parts of `removals` old enough, unless the removal gate objects. Parts gone already are skipped,
+    /// and archives left with nothing to remove are forgotten by the state file.
+    pub fn apply_removals(&mut self, removals: &RemovalSet) -> anyhow::Result<()> {
+        for (archive, parts) in removals.iter() {
+            self.known_parts
+                .extend(parts.iter().map(|(part, _)| (part.clone(), archive.to_path_buf())));
+        }
+        if self.no_remove {
+            if !removals.is_empty() {
+                log::info!("Not removing the parts of {} archives.", removals.len());
+            }
+            return Ok(());
+        }
+        let now = SystemTime::now();
+        self.spared_newest = self.newest_kept(removals.iter().map(|(archive, _)| archive.to_path_buf()).collect());
+        // Decided once for the whole set, by the part eligible last. Sets recorded by earlier versions have a time for
+        // each part.
+        let expired_at = |parts: &[(PathBuf, SystemTime)]| {
+            parts
+                .iter()
+                .map(|(_, eligible_at)| *eligible_at)
+                .max()
+                .is_some_and(|eligible_at| eligible_at < now)
+        };
+        if !self.removal_plan_checked {
+            let planned = removals
+                .iter()
+                .filter(|(archive, parts)| !self.spared_newest.contains_key(*archive) && expired_at(parts))
+                .flat_map(|(_, parts)| parts)
+                .map(|(part, _)| part.clone())
+                .collect();
+            self.check_removal_plan(planned);
+        }
+        let mut sets = Vec::new();
+        for (archive, parts) in removals.iter() {
+            let remaining: Vec<PathBuf> = parts
+                .iter()
+                .map(|(part, _)| part)
+                .filter(|part| !self.removed.contains(*part) && !self.kept_parts.contains(*part))
+                .filter(|part| fs::symlink_metadata(part).is_ok())
+                .cloned()
+                .collect();
+            let expired = match expired_at(parts) {
+                true => remaining.clone(),
+                false => Vec::new(),
+            };
+            if !expired.is_empty() {
+                if self.spare_newest(archive, expired.len()) {
+                    self.kept_parts.extend(expired);
+                    continue;
+                }
+                if !self.removal_gate_allows(archive) {
+                    log::info!(
+                        "Keeping {} expired parts of '{}' for now.",
+                        expired.len(),
+                        archive.display()
+                    );
+                    self.kept_parts.extend(expired);
+                    continue;
+                }
+                log::info!("Removing {} expired parts of '{}'.", expired.len(), archive.display());
+            }
+            self.remove_files(archive, expired, "age")?;
+            sets.push((archive, remaining));
+        }
+        self.flush_removals()?;
+        for (archive, remaining) in sets {
+            let done = remaining.iter().all(|part| self.removed.contains(part));
+            if let Some(state) = self.state.as_mut().filter(|_| done && !self.dry_run) {
+                state.forget_pending_removal(archive);
+            }
+        }
+        Ok(())
+    }
+
+    /// Audits the parts of `archive` and queues their removal for [`UnarchiveQueue::flush_removals`], `rule` says why
+    /// in the audit log.
+    fn remove_files(&mut self, archive: &Path, parts: Vec<PathBuf>, rule: &str) -> anyhow::Result<()> {
+        if parts.is_empty() {
+            return Ok(());
+        }
+        if !self.back_up(archive, &parts) {
+            log::warn!(
+                "-> Keeping the parts of '{}', they aren't backed up.",
+                archive.display()
+            );
+            self.kept_parts.extend(parts);
+            return Ok(());
+        }
+        // The parts of a set stay together, a set is never left half removed by the cap.
+        if !self.within_removal_cap(&parts) {
+            self.kept_parts.extend(parts);
+            return Ok(());
+        }
+        self.traced("rarscan.remove", |q| {
+            q.trace_attr("rarscan.parts", parts.len() as u64);
+            for entry in parts {
+                if !q.snapshot_before_removing(&entry) {
+                    q.kept_parts.insert(entry);
+                    continue;
+                }
+                match q.mtime(&entry) {
+                    Ok(mtime) => log::info!(
+                        "-> Removing archive/part '{}', modified {} ({} ago).",
+                        entry.display(),
+                        format_system_time(mtime),
+                        estimate::format_duration(mtime.elapsed().unwrap_or_default())
+                    ),
+                    Err(_) => log::info!("-> Removing archive/part '{}'.", entry.display()),
+                }
+                if let Some(changelog) = &mut q.changelog {
+                    changelog.record(Change::Removed {
+                        previous: FileState::of(&entry),
+                        path: entry.clone(),
+                        archive: Some(archive.to_path_buf()),
+                    });
+                }
+                if !q.dry_run {
+                    q.audit(Action::Remove, &entry, Some(archive), rule)?;
+                }
+                q.queued_removals.push((Some(archive.to_path_buf()), entry));
+            }
+            Ok(())
+        })
+    }
+
+    /// Removes the parts queued by [`UnarchiveQueue::remove_files`] and [`UnarchiveQueue::find_cruft`] in one batch.
+    /// They are all in the audit log by now, and past the snapshots and the backups of their set.
+    fn flush_removals(&mut self) -> anyhow::Result<()> {
+        let queued = std::mem::take(&mut self.queued_removals);
+        if queued.is_empty() {
+            return Ok(());
+        }
+        let batch: Vec<FsOp> = queued.iter().map(|(_, entry)| FsOp::Remove(entry.clone())).collect();
+        let results = self.run_fs_batch(&batch);
+        let mut queued_by_set: BTreeMap<&Path, usize> = BTreeMap::new();
+        let mut failed_by_set: BTreeMap<&Path, Vec<(PathBuf, String)>> = BTreeMap::new();
+        let mut cruft_error = None;
+        for ((archive, entry), result) in queued.iter().zip(results) {
+            if let Some(archive) = archive {
+                *queued_by_set.entry(archive).or_default() += 1;
+            }
+            if let Err(e) = result {
+                log::error!("-> Could not remove '{}': {}", entry.display(), e);
+                match archive {
+                    Some(archive) => failed_by_set
+                        .entry(archive)
+                        .or_default()
+                        .push((entry.clone(), e.to_string())),
+                    None => {
+                        cruft_error.get_or_insert((entry, e));
+                    }
+                }
+                continue;
+            }
+            if self.dry_run {
+                self.summary.pending_removals += 1;
+            } else {
+                if let Some(state) = self.state.as_mut().filter(|_| archive.is_some()) {
+                    state.forget(entry);
+                }
+                self.events.emit(Event::PartRemoved { path: entry });
+                self.summary.parts_removed += 1;
+            }
+            self.removed.insert(entry.clone());
+        }
+        // The state file keeps the removal of the set pending, the next run goes on with the rest.
+        for (archive, failed) in failed_by_set {
+            let total = queued_by_set[archive];
+            log::error!(
+                "-> Removed {} of the {} parts of '{}', the other {} are retried the next run.",
+                total - failed.len(),
+                total,
+                archive.display(),
+                failed.len()
+            );
+            self.summary.partial_removals.push((archive.to_path_buf(), failed));
+        }
+        match cruft_error {
+            Some((entry, e)) => Err(e).with_context(|| format!("remove cruft '{}'", entry.display())),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `batch`, only logging it in a dry-run, and adds it to the summary. The results are in the order of `batch`.
+    fn run_fs_batch(&mut self, batch: &[FsOp]) -> Vec<io::Result<()>> {
+        if self.dry_run {
+            log::info!("Would run {} queued filesystem operations:", batch.len());
+            for op in batch {
+                match op {
+                    FsOp::Remove(path) => log::info!("-> remove '{}'", path.display()),
+                    FsOp::SetModified(path, mtime) => log::info!(
+                        "-> set the mtime of '{}' to {}",
+                        path.display(),
+                        format_system_time(*mtime)
+                    ),
+                }
+            }
+            return batch.iter().map(|_| Ok(())).collect();
+        }
+        let report = self.fs_batcher.run(batch);
+        log::debug!(
+            "Ran {} filesystem operations in {:.2}s, {} tried again.",
+            batch.len(),
+            report.elapsed.as_secs_f64(),
+            report.retries
+        );
+        self.summary.fs_ops += batch.len() as u64;
+        self.summary.fs_op_retries += report.retries as u64;
+        self.summary.fs_op_duration += report.elapsed;
+        report.results
+    }
+
+    /// Warns up front when the removals planned and the cruft that goes with them go past the removal cap. Parts
+    /// removed already and kept don't count.
+    fn check_removal_plan(&mut self, planned: Vec<PathBuf>) {
+        self.removal_plan_checked = true;
+        if self.removal_cap.is_none() {
+            return;
+        }
+        let planned: HashSet<PathBuf> = planned
+            .into_iter()
+            .filter(|part| !self.removed.contains(part) && !self.kept_parts.contains(part))
+            .collect();
+        let cruft = match &self.root_dir {
+            Some(root_dir) if self.removes_anything() => {
+                self.cruft_candidates(root_dir, &planned).unwrap_or_else(|e| {
+                    log::debug!("Could not count the cruft planned for removal: {:#}", e);
+                    Vec::new()
+                })
+            }
+            _ => Vec::new(),
+        };
+        let Some(cap) = &self.removal_cap else {
+            return;
+        };
+        let bytes = planned.iter().chain(&cruft).map(|path| file_size(path)).sum();
+        if cap.exceeded_by((planned.len() + cruft.len()) as u64, bytes) {
+            let what = match cruft.len() {
+                0 => format!("{} parts", planned.len()),
+                count => format!("{} parts and {} cruft files", planned.len(), count),
+            };
+            log::warn!(
+                "{} ({}) are planned for removal, past the removal cap of {}. The rest is deferred.",
+                what,
+                format_size(bytes),
+                describe_removal_cap(cap)
+            );
+        }
+    }
+
+    /// Backs up `parts` of `archive` with --backup-parts-to, returns whether they are. Once it failed for an archive,
+    /// its parts are kept for the rest of the run.
+    fn back_up(&mut self, archive: &Path, parts: &[PathBuf]) -> bool {
+        let Some(backups) = self.backups.as_ref().filter(|_| !self.dry_run) else {
+            return true;
+        };
+        if self.backup_failed.contains(archive) {
+            return false;
+        }
+        match backups.back_up(archive, parts, SystemTime::now()) {
+            Ok(Backup::Existing(dir)) => {
+                log::debug!("-> Parts already backed up in '{}'.", dir.display());
+                true
+            }
+            Ok(Backup::Made { dir, copied, reflinked }) => {
+                log::info!(
+                    "-> Backed up {} parts into '{}', {} copied and {} reflinked.",
+                    parts.len(),
+                    dir.display(),
+                    format_size(copied),
+                    format_size(reflinked)
+                );
+                self.summary.backup_copied_bytes += copied;
+                self.summary.backup_reflinked_bytes += reflinked;
+                true
+            }
+            Err(e) => {
+                log::error!("-> Could not back up the parts of '{}': {}", archive.display(), e);
+                self.summary
+                    .backup_failures
+                    .push((archive.to_path_buf(), e.to_string()));
+                self.backup_failed.insert(archive.to_path_buf());
+                false
+            }
+        }
+    }
+
+    /// Whether `path` is in the directory of --backup-parts-to or in the quarantine directory, which are never scanned
+    /// nor cleaned.
+    fn is_set_aside(&self, path: &Path) -> bool {
+        self.backups.as_ref().is_some_and(|backups| backups.contains(path))
+            || self.scanner.as_ref().is_some_and(|scanner| scanner.contains(path))
+    }
+
+    /// Removes the days of backups older than `retention`, only lists them in a dry-run.
+    pub fn purge_backups(&mut self, retention: Duration) {
+        let Some(backups) = &self.backups else {
+            return;
+        };
+        match backups.purge(retention, SystemTime::now(), self.dry_run) {
+            Ok(purged) => self.summary.purged_backups = purged,
+            Err(e) => log::warn!("Could not purge the old backups: {}", e),
+        }
+    }
+
+    /// Whether the removal cap leaves room for removing every one of `paths`, counting them when it does. The ones
+    /// deferred are listed at the end of the run.
+    fn within_removal_cap(&mut self, paths: &[PathBuf]) -> bool {
+        let Some(cap) = &mut self.removal_cap else {
+            return true;
+        };
+        let sizes: Vec<u64> = paths.iter().map(|path| file_size(path)).collect();
+        if cap.admit(paths.len() as u64, sizes.iter().sum()) {
+            return true;
+        }
+        for (path, size) in paths.iter().zip(sizes) {
+            log::warn!(
+                "-> Over the removal cap, deferring '{}' ({}).",
+                path.display(),
+                format_size(size)
+            );
+            self.summary.capped_removals.push((path.clone(), size));
+        }
+        false
+    }
+
+    /// Appends to the audit log what is about to be done to `path`, which mustn't be done when it fails.
+    fn audit(&mut self, action: Action, path: &Path, archive: Option<&Path>, rule: &str) -> anyhow::Result<()> {
+        match &mut self.audit_log {
+            Some(log) => log.record(action, path, archive, rule),
+            None => Ok(()),
+        }
+    }
+
+    /// Snapshots the filesystem of `path` before the first removal in it. `false` when `path` must be kept, because a
+    /// snapshot failed and removals are off for the rest of the run.
+    fn snapshot_before_removing(&mut self, path: &Path) -> bool {
+        if self.removals_aborted {
+            return false;
+        }
+        let Some(snapshots) = self.snapshots.as_mut().filter(|_| !self.dry_run) else {
+            return true;
+        };
+        match snapshots.before_removing(path) {
+            Ok(None) => true,
+            Ok(Some(snapshot)) => {
+                log::info!(
+                    "Snapshotted '{}' as '{}' before removing from it.",
+                    snapshot.volume.display(),
+                    snapshot.name
+                );
+                if let Some(state) = &mut self.state {
+                    state.add_snapshot(snapshot.clone());
+                }
+                self.summary.snapshots.push(snapshot);
+                true
+            }
+            Err(e) if snapshots.best_effort() => {
+                log::warn!("Could not snapshot before removing, removing anyway: {:#}", e);
+                true
+            }
+            Err(e) => {
+                log::error!(
+                    "Could not snapshot before removing, nothing is removed for the rest of the run: {:#}",
+                    e
+                );
+                self.removals_aborted = true;
+                false
+            }
+        }
+    }
+
+    /// Deletes the snapshots recorded in the state file that are past the retention.
+    pub fn prune_snapshots(&mut self) {
+        let (Some(snapshots), Some(state)) = (&self.snapshots, &mut self.state) else {
+            return;
+        };
+        if self.dry_run {
+            return;
+        }
+        let now = SystemTime::now();
+        let expired: Vec<_> = state
+            .snapshots()
+            .iter()
+            .filter(|snapshot| snapshots.expired(snapshot, now))
+            .cloned()
+            .collect();
+        for snapshot in expired {
+            match snapshots.delete(&snapshot) {
+                Ok(()) => {
+                    log::info!("Deleted snapshot '{}'.", snapshot.name);
+                    state.forget_snapshot(&snapshot.name);
+                    self.summary.snapshots_pruned.push(snapshot.name);
+                }
+                Err(e) => log::warn!("Could not delete an expired snapshot: {:#}", e),
+            }
+        }
+    }
+
+    /// Root of the chain of nested archives `archive` belongs to, when it has nested archives or is one.
+    fn chain_root(&self, archive: &Path) -> Option<PathBuf> {
+        self.state.as_ref()?.chain_root(archive).map(Path::to_path_buf)
+    }
+
+    /// Removes the parts of each archive chain met by the run all at once, or none of them.
+    fn remove_chains(&mut self) -> anyhow::Result<()> {
+        if !self.pending_chains.is_empty() {
+            let candidates = self.pending_chains.keys().map(PathBuf::as_path);
+            let candidates = candidates.chain(self.removals.iter().map(|(archive, _)| archive));
+            self.spared_newest = self.newest_kept(candidates.map(Path::to_path_buf).collect());
+        }
+        for (root, members) in std::mem::take(&mut self.pending_chains) {
+            let Some(chain) = self.state.as_ref().and_then(|state| state.chain(&root)).cloned() else {
+                continue;
+            };
+            log::info!("Archive chain of '{}'.", root.display());
+            let status = self.remove_chain(&root, &chain, &members)?;
+            if status != "removed" && status != "pending_removal" {
+                self.kept_parts.extend(members.into_iter().flat_map(|(_, parts)| parts));
+            }
+            self.summary.chains.push(ChainSummary {
+                root,
+                nested: chain.nested,
+                status,
+            });
+        }
+        Ok(())
+    }
+
+    /// The parts of a chain are removed once every member is extracted and the oldest of them is past the threshold
+    /// of the root archive. A member deleted by something else holds up the rest for --chain-grace-hours only.
+    fn remove_chain(
+        &mut self,
+        root: &Path,
+        chain: &Chain,
+        members: &[(PathBuf, Vec<PathBuf>)],
+    ) -> anyhow::Result<&'static str> {
+        let mut missing = Vec::new();
+        for archive in std::iter::once(root).chain(chain.nested.iter().map(|(archive, _)| archive.as_path())) {
+            if members.iter().any(|(member, _)| member == archive) {
+                continue;
+            }
+            if archive.exists() {
+                log::info!(
+                    "-> '{}' isn't extracted, keeping the parts of every member.",
+                    archive.display()
+                );
+                return Ok("incomplete");
+            }
+            missing.push(archive);
+        }
+        if self.required_content.is_some() && !members.iter().any(|(member, _)| self.content_found.contains(member)) {
+            log::warn!("-> No member has a file matching --require-content, keeping the parts of every member.");
+            self.summary.missing_content_archives.push(root.to_path_buf());
+            return Ok("missing_required_content");
+        }
+        if let Some(first) = missing.first() {
+            let since = chain.broken_since.unwrap_or_else(SystemTime::now);
+            if chain.broken_since.is_none() {
+                if let Some(state) = &mut self.state {
+                    state.set_chain_broken(root, since);
+                }
+            }
+            let broken_for = since.elapsed().unwrap_or_default();
+            if broken_for < self.chain_grace {
+                log::warn!(
+                    "-> '{}' is gone, keeping the rest of the chain for {:.1}h more.",
+                    first.display(),
+                    (self.chain_grace - broken_for).as_secs_f64() / 3600.0
+                );
+                return Ok("broken");
+            }
+            log::warn!(
+                "-> '{}' is gone since {}, removing the rest of the chain.",
+                first.display(),
+                format_system_time(since)
+            );
+        }
+
+        let Some(remove_after) = self.resolve_remove_after(root, true) else {
+            return Ok("kept");
+        };
+        let mut oldest = None;
+        for part in members.iter().flat_map(|(_, parts)| parts) {
+            let mtime = self.mtime(part)?;
+            oldest = Some(oldest.map_or(mtime, |oldest: SystemTime| oldest.min(mtime)));
+        }
+        let expired = oldest.is_some_and(|oldest| oldest.elapsed().unwrap_or_default() > remove_after);
+        if !expired {
+            log::debug!("-> Chain not old enough to be removed.");
+            return Ok("kept");
+        }
+        if self.spare_newest(root, members.iter().map(|(_, parts)| parts.len()).sum()) {
+            return Ok("spared");
+        }
+        if !self.removal_gate_allows(root) {
+            log::info!("-> Keeping the parts of the chain for now.");
+            return Ok("kept");
+        }
+        for (archive, parts) in members {
+            self.remove_files(archive, parts.clone(), "chain-age")?;
+        }
+        self.flush_removals()?;
+        if self.removals_aborted {
+            return Ok("kept");
+        }
+        if members
+            .iter()
+            .flat_map(|(_, parts)| parts)
+            .any(|part| !self.removed.contains(part))
+        {
+            return Ok("capped");
+        }
+        if self.dry_run {
+            return Ok("pending_removal");
+        }
+        if let Some(state) = &mut self.state {
+            state.forget_chain(root);
+        }
+        Ok("removed")
+    }
+
+    /// The archive identical to `archive` extracted earlier somewhere else, opened like `archive` was. A fingerprint
+    /// shared by archives with different entries is a collision and doesn't count.
+    fn duplicate_of(&mut self, archive: &Archive, fingerprint: &Fingerprint) -> anyhow::Result<Option<Plan>> {
+        let Some(state) = &self.state else {
+            return Ok(None);
+        };
+        let Some(original) = state.fingerprint(&fingerprint.to_string()).map(Path::to_path_buf) else {
+            return Ok(None);
+        };
+        if original == archive.path || !original.exists() {
+            return Ok(None);
+        }
+        let original = match self.plan(original.clone()) {
+            Ok(plan) => plan,
+            Err(e) => {
+                log::debug!("-> Could not open '{}': {:#}", original.display(), e);
+                return Ok(None);
+            }
+        };
+        if original.archive.headers != archive.headers {
+            log::warn!(
+                "-> Same fingerprint as '{}' but different entries, not treating it as a duplicate.",
+                original.archive.path.display()
+            );
+            return Ok(None);
+        }
+        Ok(Some(original))
+    }
+
+    /// Links the files extracted from the original archive into the destination of its duplicate, with
+    /// --duplicate-policy link. Returns false when the files must be extracted instead.
+    fn link_duplicate(&mut self, archive: &Archive, dest: &Path, original: Option<&Plan>) -> anyhow::Result<bool> {
+        let Some(original) = original.filter(|_| self.duplicate_policy == DuplicatePolicy::Link) else {
+            return Ok(false);
+        };
+        if !original.extracted {
+            log::info!(
+                "-> Duplicate of '{}', which isn't extracted, extracting it.",
+                original.archive.path.display()
+            );
+            return Ok(false);
+        }
+        log::info!(
+            "-> Duplicate of '{}', linking its files from '{}'.",
+            original.archive.path.display(),
+            original.dest.display()
+        );
+        if self.dry_run {
+            self.summary.pending_extractions += 1;
+        } else {
+            for header in &archive.headers {
+                let to = dest.join(&header.filename);
+                if header.is_directory() {
+                    fs::create_dir_all(&to).context("create directory")?;
+                    continue;
+                }
+                let previous = FileState::of(&to);
+                if previous.is_some() {
+                    self.audit(Action::Overwrite, &to, Some(&archive.path), "duplicate")?;
+                }
+                if let Err(e) = dedup::link_file(&original.dest.join(&header.filename), &to) {
+                    log::warn!("-> Could not link '{}', extracting instead: {}", to.display(), e);
+                    return Ok(false);
+                }
+                if let Some(state) = &mut self.state {
+                    state.set_extracted(&to, &archive.path, SystemTime::now());
+                }
+                if let Some(changelog) = &mut self.changelog {
+                    changelog.record(Change::Written {
+                        path: to,
+                        archive: archive.path.clone(),
+                        previous,
+                        size: header.unpacked_size,
+                    });
+                }
+            }
+        }
+        let original = original.archive.path.clone();
+        self.summary.duplicates.push((original, archive.path.clone(), "linked"));
+        Ok(true)
+    }
+
+    /// Locks `dest` against the other instances extracting into it. When another one holds it, an archive retried at
+    /// the end of the run waits for it up to --dir-lock-wait-secs, the others get who holds it right away.
+    fn lock_dest(&mut self, archive: &Path, dest: &Path) -> anyhow::Result<Result<DirLock, Holder>> {
+        fs::create_dir_all(dest).context("create destination")?;
+        let wait = match self.retrying.contains(archive) {
+            true => self.dir_lock_wait,
+            false => Duration::ZERO,
+        };
+        let deadline = Instant::now() + wait;
+        loop {
+            match DirLock::acquire(dest, archive).context("lock destination")? {
+                Err(_) if Instant::now() < deadline => {
+                    self.serve_ctl();
+                    thread::sleep(DIR_LOCK_POLL_INTERVAL);
+                }
+                acquired => return Ok(acquired),
+            }
+        }
+    }
+
+    /// Whether a file of the archive matches --require-content. The archives of a chain have it when any member does,
+    /// which the removal of the chain checks.
+    fn has_required_content(&mut self, archive: &Archive, dest: &Path) -> bool {
+        let Some(content) = &self.required_content else {
+            return true;
+        };
+        match content.find(archive, dest, self.dry_run) {
+            Some(file) => {
+                log::debug!("-> '{}' is required content.", file.display());
+                self.content_found.insert(archive.path.clone());
+                true
+            }
+            None if self.chain_root(&archive.path).is_some() => true,
+            None => {
+                log::warn!(
+                    "-> Extracted, but no file matches {}.",
+                    content.patterns().collect::<Vec<_>>().join(", ")
+                );
+                self.summary.missing_content_archives.push(archive.path.clone());
+                false
+            }
+        }
+    }
+
+    /// Probes the media files just extracted from the archive, false when any is invalid.
+    fn probe_media(&mut self, archive: &Archive, dest: &Path) -> anyhow::Result<bool> {
+        let Some(prober) = &self.media_prober else {
+            return Ok(true);
+        };
+        let files: Vec<PathBuf> = archive
+            .headers
+            .iter()
+            .filter(|header| header.is_file())
+            .map(|header| dest.join(&header.filename))
+            .collect();
+        let invalid = prober.probe_all(&files).context("probe media")?;
+        if invalid.is_empty() {
+            return Ok(true);
+        }
+        for (path, reason) in &invalid {
+            log::warn!("-> '{}' is not a valid media file: {}", path.display(), reason);
+        }
+        self.summary
+            .invalid_payload_archives
+            .push((archive.path.clone(), invalid));
+        Ok(false)
+    }
+
+    /// Runs the scanner on the files just extracted from the archive and moves the flagged ones to the quarantine
+    /// directory, false when any is flagged.
+    fn scan_extracted(&mut self, archive: &Archive, dest: &Path) -> anyhow::Result<bool> {
+        let Some(scanner) = &self.scanner else {
+            return Ok(true);
+        };
+        let files: Vec<PathBuf> = archive
+            .headers
+            .iter()
+            .filter(|header| header.is_file())
+            .map(|header| dest.join(&header.filename))
+            .collect();
+        let flagged = scanner.scan(dest, &files);
+        self.summary.scanned_files += files.len() as u64;
+        self.summary.flagged_files += flagged.len() as u64;
+        if flagged.is_empty() {
+            return Ok(true);
+        }
+        for file in &flagged {
+            log::warn!("-> '{}' {}.", file.path.display(), file.reason);
+            if !file.output.is_empty() {
+                log::warn!("   -> Scanner output: {}", file.output);
+            }
+        }
+        let dir = scanner
+            .quarantine(&archive.path, dest, &flagged)
+            .context("move flagged files to the quarantine directory")?;
+        log::warn!(
+            "-> Moved {} flagged files to '{}'. Its parts will not be removed.",
+            flagged.len(),
+            dir.display()
+        );
+        // The files kept in the destination aren't recorded as extracted either, the archive is quarantined.
+        if let Some(state) = &mut self.state {
+            for path in files.iter().chain(flagged.iter().map(|file| &file.path)) {
+                state.forget_extracted(path);
+                state.forget_verified(path);
+            }
+        }
+        self.summary.quarantined_archives.push(QuarantineSummary {
+            archive: archive.path.clone(),
+            dir,
+            files: flagged,
+        });
+        Ok(false)
+    }
+
+    /// Lists the headers of the next archive in the background, at most one at a time.
+    fn start_prefetch(&mut self, current: &Path) {
+        if !self.prefetch_headers || self.prefetch.is_some() {
+            return;
+        }
+        // A spill file that can't be read fails the next archive, not its prefetch.
+        if let Ok(Some(next)) = self.queue.front() {
+            if next != current {
+                self.prefetch = Some(Prefetch::start(next.clone(), self.naming.clone()));
+            }
+        }
+    }
+
+    /// Returns the reason why the archive should not be extracted, if any.
+    fn exceeds_limits(&self, archive: &Archive) -> Option<String> {
+        let limits = self.limits.as_ref()?;
+        if archive.headers.len() > limits.max_entries {
+            return Some(format!(
+                "it has {} entries, more than the maximum of {}",
+                archive.headers.len(),
+                limits.max_entries
+            ));
+        }
+        if archive.unpacked_size() > limits.max_unpacked_size {
+            return Some(format!(
+                "it unpacks to {}, more than the maximum of {}",
+                format_size(archive.unpacked_size()),
+                format_size(limits.max_unpacked_size)
+            ));
+        }
+        None
+    }
+
+    /// Whether the archive is blocked by an earlier extraction that filled its destination, which is still short of
+    /// space. The block is lifted once enough is free.
+    fn is_blocked(&mut self, archive: &Archive, dest: &Path) -> anyhow::Result<bool> {
+        let Some(required) = self.state.as_ref().and_then(|state| state.blocked(&archive.path)) else {
+            return Ok(false);
+        };
+        let free = space::free_space(dest)?.map(|free| free.bytes);
+        if free.is_some_and(|free| free < required) {
+            log::info!(
+                "-> Blocked since its destination filled up, waiting for {} to be free.",
+                format_size(required)
+            );
+            return Ok(true);
+        }
+        log::info!("-> The destination has space again, resuming the blocked archive.");
+        if let Some(state) = &mut self.state {
+            state.unblock(&archive.path);
+        }
+        Ok(false)
+    }
+
+    /// Whether a failed extraction ran out of space, told by the error or, as unrar doesn't always say, by what is
+    /// left free afterwards.
+    fn filled_destination(&self, failure: &ExtractionError, archive: &Archive, dest: &Path) -> anyhow::Result<bool> {
+        let ExtractionError::CreateError { io_kind, .. } = failure else {
+            return Ok(false);
+        };
+        if matches!(io_kind, Some(io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded)) {
+            return Ok(true);
+        }
+        let free = space::free_space(dest)?;
+        Ok(free.is_some_and(|free| free.bytes < archive.unpacked_size()))
+    }
+
+    /// Compares the bytes and inodes the archive needs against what is free at `dest`.
+    fn lacks_space(&self, archive: &Archive, dest: &Path) -> anyhow::Result<Option<String>> {
+        let Some(free) = space::free_space(dest)? else {
+            return Ok(None);
+        };
+        let bytes = archive.unpacked_size();
+        let inodes = archive.headers.len() as u64;
+        log::info!(
+            "-> Needs {} and ~{} inodes, {} and {} inodes free.",
+            format_size(bytes),
+            inodes,
+            format_size(free.bytes),
+            free.inodes.map_or_else(|| "unknown".into(), |n| n.to_string())
+        );
+        if bytes > free.bytes {
+            return Ok(Some(format!(
+                "not enough space (need {}, have {})",
+                format_size(bytes),
+                format_size(free.bytes)
+            )));
+        }
+        if let Some(free_inodes) = free.inodes {
+            if inodes + self.inode_margin > free_inodes {
+                return Ok(Some(format!(
+                    "not enough inodes (need ~{}, have {})",
+                    inodes + self.inode_margin,
+                    free_inodes
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The percentage of the filesystem of `dest` in use once the archive is extracted, with the maximum, when it's
+    /// over `max_disk_usage`.
+    fn over_disk_usage(&self, archive: &Archive, dest: &Path) -> anyhow::Result<Option<(f64, f64)>> {
+        let Some(max) = self.max_disk_usage else {
+            return Ok(None);
+        };
+        let Some(free) = space::free_space(dest)? else {
+            return Ok(None);
+        };
+        let percent = free.usage.projected_percent(archive.unpacked_size());
+        Ok((percent > max).then_some((percent, max)))
+    }
+
+    /// Queues again the archives deferred for `--max-disk-usage`, once the removals freed what they could. Returns
+    /// whether there were any.
+    pub fn retry_over_disk_usage(&mut self) -> anyhow::Result<bool> {
+        if self.usage_deferred.is_empty() {
+            return Ok(false);
+        }
+        for entry in std::mem::take(&mut self.usage_deferred) {
+            log::info!("Retrying '{}' after the removals.", entry.display());
+            self.retrying.insert(entry.clone());
+            self.queue.push_back(entry)?;
+        }
+        Ok(true)
+    }
+
+    /// Archives found inside of archives which get extracted in turn.
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignore_rules
+            .as_ref()
+            .is_some_and(|rules| rules.is_ignored(path, is_dir))
+    }
+
+    /// Lists what the nested archive `name` of `parent`, which a dry-run doesn't extract, would extract and remove, from
+    /// a copy of its volumes. The archives nested in it in turn are looked into a level below.
+    fn simulate_nested(
+        &mut self,
+        parent: &Archive,
+        name: &Path,
+        nested_path: &Path,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        let indent = "   ".repeat(depth);
+        let (volumes, size) = simulation::nested_set(parent, name);
+        if self.deep_dry_run.is_some_and(|max_size| size > max_size) {
+            log::info!(
+                "{}-> [simulated] '{}' is {} with its volumes, past --deep-dry-run-max-size, not looked into.",
+                indent,
+                name.display(),
+                format_size(size)
+            );
+            return Ok(());
+        }
+        let dir = SimulationDir::create().context("create simulation directory")?;
+        let opened = parent
+            .extract_entries(&volumes, dir.path())
+            .and_then(|()| Archive::open(dir.path().join(name.file_name().unwrap_or_default()), &self.naming));
+        let nested = match opened {
+            Ok(nested) => nested,
+            Err(e) => {
+                log::warn!(
+                    "{}-> [simulated] '{}' can't be looked into: {:#}",
+                    indent,
+                    name.display(),
+                    e
+                );
+                return Ok(());
+            }
+        };
+        let dest = parent_dir(nested_path).to_path_buf();
+        let files: Vec<&archive::Entry> = nested.headers.iter().filter(|header| header.is_file()).collect();
+        log::info!(
+            "{}-> [simulated] '{}' would extract {} files, {}, into '{}':",
+            indent,
+            name.display(),
+            files.len(),
+            format_size(nested.unpacked_size()),
+            dest.display()
+        );
+        for header in &files {
+            log::info!("{}   -> '{}'", indent, header.filename.display());
+        }
+        self.summary.pending_extractions += 1;
+        match self.resolve_remove_after(nested_path, false) {
+            Some(remove_after) => log::info!(
+                "{}   -> [simulated] Its {} volumes would be removed with the chain once {} old.",
+                indent,
+                volumes.len(),
+                estimate::format_duration(remove_after)
+            ),
+            None => log::info!(
+                "{}   -> [simulated] Its {} volumes would be kept.",
+                indent,
+                volumes.len()
+            ),
+        }
+        for header in files {
+            if !self.is_nested_archive(&header.filename) {
+                continue;
+            }
+            if depth >= simulation::MAX_DEPTH {
+                log::warn!(
+                    "{}   -> [simulated] Contains archive '{}', nested too deep to look into.",
+                    indent,
+                    header.filename.display()
+                );
+                continue;
+            }
+            let path = nested.extracted_path(&dest, &header.filename);
+            self.simulate_nested(&nested, &header.filename, &path, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn is_nested_archive(&self, path: &Path) -> bool {
+        self.naming.is_root_rar_file(path) || is_zip_file(path) || (self.unpack_tarballs && tarball::is_tarball(path))
+    }
+
+    fn removal_gate_allows(&self, archive: &Path) -> bool {
+        self.removal_gate.as_ref().is_none_or(|gate| gate.allows(archive))
+    }
+
+    /// The mtime recorded in the state file for files on filesystems that refused it, or the one on disk.
+    fn mtime(&self, path: &Path) -> anyhow::Result<SystemTime> {
+        if let Some(mtime) = self.state.as_ref().and_then(|state| state.mtime(path)) {
+            return Ok(mtime);
+        }
+        let md = path.metadata().context("stat part")?;
+        md.modified().context("get part mtime")
+    }
+
+    /// Sets the mtime of an embedded archive and its other volumes, or records it in the state file when their
+    /// filesystem refuses it.
+    fn set_mtimes(&mut self, paths: &[PathBuf], archive: &Path, mtime: SystemTime) -> anyhow::Result<()> {
+        for path in paths {
+            if let Some(changelog) = &mut self.changelog {
+                changelog.record(Change::MtimeChanged {
+                    path: path.clone(),
+                    archive: archive.to_path_buf(),
+                    previous: FileState::of(path),
+                    mtime,
+                });
+            }
+        }
+        let mut batch = Vec::with_capacity(paths.len());
+        let mut recorded = Vec::new();
+        for path in paths {
+            match self.dry_run || self.supports_mtime(parent_dir(path))? {
+                true => batch.push(FsOp::SetModified(path.clone(), mtime)),
+                false => recorded.push(path.as_path()),
+            }
+        }
+        for (op, result) in batch.iter().zip(self.run_fs_batch(&batch)) {
+            let path = op.path();
+            match result {
+                Ok(()) => {
+                    if let Some(state) = self.state.as_mut().filter(|_| !self.dry_run) {
+                        state.forget(path);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    self.mtime_unsupported(parent_dir(path))?;
+                    recorded.push(path);
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("update mtime of embedded archive '{}'", path.display()))
+                }
+            }
+        }
+        for path in recorded {
+            match &mut self.state {
+                Some(state) => state.set_mtime(path, mtime),
+                None => log::warn!("-> No state file to record the mtime, it will age from its extraction."),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the mtime of freshly extracted files. Unlike for embedded archives nothing depends on it, so nothing is
+    /// recorded when the filesystem refuses it.
+    fn set_extracted_mtimes(&mut self, dest: &Path, files: &[PathBuf], mtime: SystemTime) -> anyhow::Result<()> {
+        if !self.supports_mtime(dest)? {
+            return Ok(());
+        }
+        let batch: Vec<FsOp> = files
+            .iter()
+            .map(|path| FsOp::SetModified(path.clone(), mtime))
+            .collect();
+        for (op, result) in batch.iter().zip(self.run_fs_batch(&batch)) {
+            result.with_context(|| format!("update mtime of extracted file '{}'", op.path().display()))?;
+        }
+        Ok(())
+    }
+
+    /// Probes once per filesystem whether mtimes can be set, some SMB and NFS mounts refuse it.
+    fn supports_mtime(&mut self, dir: &Path) -> anyhow::Result<bool> {
+        let device = space::device(dir).context("stat destination")?;
+        if let Some(&supported) = self.mtime_support.get(&device) {
+            return Ok(supported);
+        }
+        let probe = dir.join(format!(".rarscan-mtime-probe-{}", process::id()));
+        let result = File::create(&probe).and_then(|f| f.set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000_000)));
+        let _ = fs::remove_file(&probe);
+        match result {
+            Ok(()) => {
+                self.mtime_support.insert(device, true);
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                self.mtime_unsupported(dir)?;
+                Ok(false)
+            }
+            Err(e) => Err(e).context("probe mtime support"),
+        }
+    }
+
+    fn mtime_unsupported(&mut self, dir: &Path) -> anyhow::Result<()> {
+        let device = space::device(dir).context("stat destination")?;
+        if self.mtime_support.insert(device, false) != Some(false) {
+            log::warn!(
+                "The filesystem of '{}' doesn't allow setting mtimes, recording them in the state file instead.",
+                dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Threshold for removing `path`: the one of its sidecar, the most specific --remove-after-for rule matching it, or
+    /// the global one.
+    fn resolve_remove_after(&self, path: &Path, log_rule: bool) -> Option<Duration> {
+        let (remove_after, source) = self.remove_after_source(path)?;
+        if log_rule && source != "--remove-after-hours" {
+            log::info!(
+                "-> Removing parts after {:.1}h ({}).",
+                remove_after.as_secs_f64() / 3600.0,
+                source
+            );
+        }
+        Some(remove_after)
+    }
+
+    /// Like [`UnarchiveQueue::resolve_remove_after`], with where the threshold comes from.
+    fn remove_after_source(&self, path: &Path) -> Option<(Duration, String)> {
+        if let Some(remove_after) = self.overrides.get(path).and_then(|overrides| overrides.remove_after) {
+            return Some((remove_after, "sidecar".to_string()));
+        }
+        let matching = self
+            .remove_after_rules
+            .as_ref()
+            .map(|rules| rules.matching(path))
+            .unwrap_or_default();
+        match matching.split_first() {
+            Some((rule, [])) => Some((rule.remove_after, format!("rule '{}'", rule.pattern()))),
+            // The rules it won over, for the dry-run and explain to tell which applies.
+            Some((rule, others)) => Some((
+                rule.remove_after,
+                format!(
+                    "rule '{}' over {}",
+                    rule.pattern(),
+                    others
+                        .iter()
+                        .map(|other| format!("'{}'", other.pattern()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )),
+            None => self
+                .remove_after
+                .map(|remove_after| (remove_after, "--remove-after-hours".to_string())),
+        }
+    }
+
+    /// How many of the newest archives of the release of `path` are kept: the one of the most specific
+    /// --remove-after-for rule matching it, or --keep-newest.
+    fn keep_newest_for(&self, path: &Path) -> Option<usize> {
+        self.remove_after_rules
+            .as_ref()
+            .and_then(|rules| rules.resolve(path))
+            .and_then(|rule| rule.keep_newest)
+            .or(self.keep_newest)
+    }
+
+    /// The archives --keep-newest spares, with their rank by newest extraction in their release and the release. The
+    /// ones ranked are `candidates` and the archives the run found extracted, nested archives go with their chain.
+    fn newest_kept(&self, candidates: Vec<PathBuf>) -> HashMap<PathBuf, (usize, String)> {
+        let rules = self.remove_after_rules.as_ref();
+        if self.keep_newest.is_none() && !rules.is_some_and(RemoveAfterRules::keep_newest) {
+            return HashMap::new();
+        }
+        let extracted_at: HashMap<&Path, SystemTime> =
+            self.state
+                .iter()
+                .flat_map(|state| state.extracted())
+                .fold(HashMap::new(), |mut times, (_, entry)| {
+                    let time = times.entry(entry.archive.as_path()).or_insert(entry.time);
+                    *time = (*time).max(entry.time);
+                    times
+                });
+        let extracted = self
+            .outcomes
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, Outcome::Extracted | Outcome::AlreadyExtracted))
+            .map(|(path, _)| path.as_path());
+        let archives: BTreeSet<&Path> = candidates
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(extracted)
+            .filter(|path| !self.nested.contains(*path))
+            .collect();
+        let mut releases: BTreeMap<String, Vec<(SystemTime, &Path)>> = BTreeMap::new();
+        for archive in archives {
+            let time = match extracted_at.get(archive) {
+                Some(time) => *time,
+                None => self.mtime(archive).unwrap_or(UNIX_EPOCH),
+            };
+            releases
+                .entry(self.release_of(archive))
+                .or_default()
+                .push((time, archive));
+        }
+        let mut spared = HashMap::new();
+        for (release, mut archives) in releases {
+            archives.sort_by(|a, b| b.cmp(a));
+            for (rank, (_, archive)) in archives.into_iter().enumerate() {
+                if self.keep_newest_for(archive).is_some_and(|keep| rank < keep) {
+                    spared.insert(archive.to_path_buf(), (rank + 1, release.clone()));
+                }
+            }
+        }
+        spared
+    }
+
+    /// Notes that --keep-newest keeps `n` expired parts of `archive`.
+    fn spare_newest(&mut self, archive: &Path, n: usize) -> bool {
+        let Some((rank, release)) = self.spared_newest.get(archive).cloned() else {
+            return false;
+        };
+        log::info!(
+            "Keeping {} expired parts of '{}', kept: newest-{} of directory '{}'.",
+            n,
+            archive.display(),
+            rank,
+            release
+        );
+        self.summary
+            .spared_archives
+            .push((archive.to_path_buf(), rank, release));
+        true
+    }
+
+    /// Whether `path` was reached through a symlinked directory, its files then live outside of the tree.
+    fn through_symlink(&self, path: &Path) -> bool {
+        self.symlinked_dirs.iter().any(|dir| path.starts_with(dir))
+    }
+
+    /// Whether rules could remove files even without a global threshold.
+    fn removes_anything(&self) -> bool {
+        !self.no_remove && self.remove_after.is_some()
+            || self.remove_after_rules.as_ref().is_some_and(|rules| !rules.is_empty())
+    }
+
+    fn should_remove(&self, path: &Path, remove_after: Duration) -> anyhow::Result<bool> {
+        let mtime = self.mtime(path)?;
+        let elapsed = mtime.elapsed().unwrap_or(Duration::from_millis(0));
+        Ok(elapsed > remove_after)
+    }
+
+    /// The newest mtime of the parts of a set, which decides the removal of all of them at once: the first parts of a
+    /// long download are kept as long as the last one.
+    fn newest_mtime(&self, parts: &[PathBuf]) -> anyhow::Result<Option<SystemTime>> {
+        let mut newest = None;
+        for part in parts {
+            let mtime = self.mtime(part)?;
+            newest = Some(newest.map_or(mtime, |newest: SystemTime| newest.max(mtime)));
+        }
+        Ok(newest)
+    }
+
+    /// Whether the parts of a set are old enough to be removed, judged by the newest of them.
+    fn should_remove_set(&self, parts: &[PathBuf], remove_after: Duration) -> anyhow::Result<bool> {
+        let newest = self.newest_mtime(parts)?;
+        Ok(newest.is_some_and(|newest| newest.elapsed().unwrap_or_default() > remove_after))
+    }
+
+    /// The archive of the set `path` belongs to, among the ones `apply_removals` decided on: one of its parts, or a file
+    /// next to them named after the set.
+    fn known_set_of(&self, path: &Path) -> Option<&Path> {
+        if let Some(archive) = self.known_parts.get(path) {
+            return Some(archive);
+        }
+        let set = sidecar::set_name(path);
+        self.known_parts
+            .values()
+            .find(|archive| archive.parent() == path.parent() && sidecar::set_name(archive) == set)
+            .map(PathBuf::as_path)
+    }
+
+    fn find_cruft(&mut self, root_dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        for entry in self.cruft_candidates(root_dir.as_ref(), &HashSet::new())? {
+            if !self.within_removal_cap(std::slice::from_ref(&entry)) {
+                self.kept_parts.insert(entry);
+                continue;
+            }
+            if self.snapshot_before_removing(&entry) {
+                let mtime = self.mtime(&entry)?;
+                log::info!(
+                    "Removing cruft '{}', modified {} ({} ago).",
+                    entry.display(),
+                    format_system_time(mtime),
+                    estimate::format_duration(mtime.elapsed().unwrap_or_default())
+                );
+                if let Some(changelog) = &mut self.changelog {
+                    changelog.record(Change::Removed {
+                        previous: FileState::of(&entry),
+                        path: entry.clone(),
+                        archive: None,
+                    });
+                }
+                if !self.dry_run {
+                    self.audit(
+                        Action::Remove,
+                        &entry,
+                        None,
+                        &format!("cruft:{}", cruft_pattern(&entry)),
+                    )?;
+                }
+                self.queued_removals.push((None, entry));
+            }
+        }
+        self.flush_removals()
+    }
+
+    /// The cruft below `root_dir` old enough to be removed, or whose set is removed already. Counting the removals
+    /// ahead, `planned` are the parts about to be removed.
+    fn cruft_candidates(&self, root_dir: &Path, planned: &HashSet<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+        // Symlinked directories are never followed here, what they point to isn't ours to remove.
+        let is_cruft = |path: &Path| is_cruft(path) && !self.is_ignored(path, false) && !self.is_set_aside(path);
+        let files = walk::find_files(root_dir, false, is_cruft).context("scan for cruft")?;
+        // Whether a download is in progress in each directory, nothing in those is cruft yet.
+        let mut downloading: HashMap<PathBuf, bool> = HashMap::new();
+        let mut candidates = Vec::new();
+        for entry in files {
+            if !self.complete_releases.is_empty() && self.complete_releases.contains(&self.release_of(&entry)) {
+                continue;
+            }
+            if self.kept_parts.contains(&entry) {
+                log::debug!("'{}' is kept.", entry.display());
+                continue;
+            }
+            if self.removed.contains(&entry) || planned.contains(&entry) {
+                continue;
+            }
+            if self.claimed_volumes.contains(&entry) {
+                log::debug!("'{}' is a volume of a renamed set.", entry.display());
+                continue;
+            }
+            let dir = parent_dir(&entry);
+            let in_progress = *downloading
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| self.markers.find(dir).is_some());
+            if in_progress {
+                log::debug!(
+                    "'{}' is kept while a download is in progress next to it.",
+                    entry.display()
+                );
+                continue;
+            }
+            if self.awaits_extraction(&entry) {
+                log::debug!("'{}' is kept until its set is extracted.", entry.display());
+                continue;
+            }
+            // What the removal of its set decided, rather than its own age.
+            let set_removed = match self.known_set_of(&entry) {
+                Some(archive) if self.removed.contains(archive) || planned.contains(archive) => true,
+                Some(archive) => {
+                    log::debug!("'{}' is kept with the set of '{}'.", entry.display(), archive.display());
+                    continue;
+                }
+                None => false,
+            };
+            if !set_removed {
+                let Some(remove_after) = self.resolve_remove_after(&entry, false) else {
+                    continue;
+                };
+                if !self.should_remove(&entry, remove_after)? {
+                    continue;
+                }
+            }
+            candidates.push(entry);
+        }
+        Ok(candidates)
+    }
+
+    /// Moves the files extracted longer than `after` ago from under `root_dir` to the same relative path under `to`.
+    fn tier_extracted(&mut self, root_dir: &Path, after: Duration, to: &Path) -> anyhow::Result<()> {
+        let Some(state) = &self.state else {
+            return Ok(());
+        };
+        let mut expired: Vec<(PathBuf, PathBuf)> = state
+            .extracted()
+            .filter(|(_, entry)| entry.time.elapsed().unwrap_or_default() > after)
+            .map(|(path, entry)| (path.to_path_buf(), entry.archive.clone()))
+            .collect();
+        expired.sort();
+
+        for (path, archive) in expired {
+            // The archive could be extracted again in this run, don't move its files from under it.
+            if self.queue.contains(&archive) || self.deferred.contains(&archive) {
+                log::debug!("'{}' is still queued, not moving its files.", archive.display());
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(root_dir) else {
+                log::warn!("'{}' is outside of the root directory, not moving it.", path.display());
+                continue;
+            };
+            let Some(size) = FileState::of(&path).map(|state| state.size) else {
+                if let Some(state) = &mut self.state {
+                    state.forget_extracted(&path);
+                }
+                continue;
+            };
+            let target = to.join(relative);
+            log::info!("Moving '{}' to '{}'.", path.display(), target.display());
+            if let Some(changelog) = &mut self.changelog {
+                changelog.record(Change::Moved {
+                    path: path.clone(),
+                    to: target.clone(),
+                    archive: archive.clone(),
+                    size,
+                });
+            }
+            if !self.dry_run {
+                tier::move_file(&path, &target).with_context(|| format!("move '{}'", path.display()))?;
+                if let Some(state) = &mut self.state {
+                    state.set_tiered(&path, &target);
+                }
+            }
+            self.summary.tiered_files += 1;
+            self.summary.tiered_bytes += size;
+            self.removed.insert(path);
+        }
+        Ok(())
+    }
+
+    /// Removes the directories left empty by the removal of parts and cruft.
+    fn remove_empty_dirs(&mut self, root_dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        if self.removals_aborted {
+            return Ok(());
+        }
+        let dirs = self
+            .removed
+            .iter()
+            .filter_map(|path| path.parent())
+            .map(Path::to_path_buf)
+            .collect();
+        let removed = cleanup::remove_empty_dirs(root_dir.as_ref(), &dirs, &self.removed, self.dry_run)
+            .context("remove empty directories")?;
+        self.summary.empty_dirs_removed += removed.len() as u64;
+        if let Some(changelog) = &mut self.changelog {
+            for path in removed {
+                changelog.record(Change::DirRemoved { path });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn destination_failed(&self) -> bool {
+        self.summary
+            .failed_archives
+            .iter()
+            .any(|(_, failure)| failure.is_destination())
+    }
+
+    pub fn removal_capped(&self) -> bool {
+        !self.summary.capped_removals.is_empty()
+    }
+
+    pub fn outside_window(&self) -> bool {
+        self.outside_window
+    }
+
+    pub fn device_suspended(&self) -> bool {
+        !self.summary.suspended_devices.is_empty()
+    }
+
+    pub fn archive_failed(&self) -> bool {
+        self.summary
+            .failed_archives
+            .iter()
+            .any(|(_, failure)| !failure.is_destination())
+    }
+
+    /// Whether a dry-run found anything to extract, remove or move.
+    pub fn has_pending_changes(&self) -> bool {
+        let summary = &self.summary;
+        summary.pending_extractions + summary.pending_removals + summary.tiered_files + summary.empty_dirs_removed > 0
+    }
+
+    pub fn finish(&mut self) {
+        match self.releases() {
+            Ok(releases) => self.summary.releases = releases,
+            Err(e) => log::warn!("Could not group the archives by release: {:#}", e),
+        }
+        if !self.summary.releases.is_empty() {
+            let complete = self.summary.releases.iter().filter(|release| release.complete).count();
+            log::info!("{} releases, {} complete:", self.summary.releases.len(), complete);
+            for release in &self.summary.releases {
+                log::info!(
+                    "-> '{}': {} archives, {}, {} pending removal",
+                    release.name,
+                    release.archives.len(),
+                    if release.complete {
+                        "complete".to_string()
+                    } else {
+                        format!("incomplete with {} problems", release.problems)
+                    },
+                    format_size(release.pending_removal_bytes)
+                );
+                for (path, status) in &release.archives {
+                    log::debug!("   -> '{}' {}", path.display(), status);
+                }
+            }
+        }
+        if self.root_dir.is_some() {
+            log::info!(
+                "Found {} .rar files, {} enqueued as root archives and {} left out as other parts or ignored.",
+                self.summary.rar_files_seen,
+                self.summary.root_archives_enqueued,
+                self.summary.rar_files_seen - self.summary.root_archives_enqueued
+            );
+        }
+        if let Some(root) = &self.summary.unmounted_root {
+            log::warn!(
+                "Root '{}' is empty and listed as a mount point, is its filesystem mounted?",
+                root.display()
+            );
+        }
+        if self.dry_run {
+            log::info!(
+                "Pending: extractions={} removals={} moves={} empty_dirs={}",
+                self.summary.pending_extractions,
+                self.summary.pending_removals,
+                self.summary.tiered_files,
+                self.summary.empty_dirs_removed
+            );
+        }
+        if self.summary.tarballs_unpacked > 0 {
+            log::info!("Unpacked {} tarballs.", self.summary.tarballs_unpacked);
+        }
+        if self.summary.tiered_files > 0 {
+            log::info!(
+                "Moved {} extracted files ({}).",
+                self.summary.tiered_files,
+                format_size(self.summary.tiered_bytes)
+            );
+        }
+        let verified = self.summary.verified_files + self.summary.verify_cache_hits;
+        if verified > 0 {
+            log::info!(
+                "Verified {} files, {} hashed and {} trusted from earlier runs ({:.0}% cache hits).",
+                verified,
+                self.summary.verified_files,
+                self.summary.verify_cache_hits,
+                100.0 * self.summary.verify_cache_hits as f64 / verified as f64
+            );
+        }
+        if self.summary.adopted_extractions > 0 {
+            log::info!("Adopted {} pre-existing extractions.", self.summary.adopted_extractions);
+        }
+        if self.summary.unrecorded_extractions > 0 {
+            log::warn!(
+                "{} archives were extracted by something else, use --auto-adopt or `rarscan adopt` to record them.",
+                self.summary.unrecorded_extractions
+            );
+        }
+        if self.summary.empty_dirs_removed > 0 {
+            log::info!("Removed {} empty directories.", self.summary.empty_dirs_removed);
+        }
+        if self.summary.fs_ops > 0 {
+            log::info!(
+                "Ran {} removals and mtime fixups in {:.1}s, {} retries after transient errors.",
+                self.summary.fs_ops,
+                self.summary.fs_op_duration.as_secs_f64(),
+                self.summary.fs_op_retries
+            );
+        }
+        if !self.summary.unsupported_archives.is_empty() {
+            log::warn!(
+                "{} archives skipped for requiring unsupported features:",
+                self.summary.unsupported_archives.len()
+            );
+            for path in &self.summary.unsupported_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.failed_archives.is_empty() {
+            log::error!("{} archives failed to extract:", self.summary.failed_archives.len());
+            for (path, failure) in &self.summary.failed_archives {
+                log::error!("-> '{}' ({}): {}", path.display(), failure.kind(), failure);
+            }
+        }
+        if !self.summary.renamed_sets.is_empty() {
+            log::warn!(
+                "{} sets had volumes renamed after another set, extracted by following their headers:",
+                self.summary.renamed_sets.len()
+            );
+            for (archive, volumes) in &self.summary.renamed_sets {
+                log::warn!("-> '{}' ({} volumes)", archive.display(), volumes.len());
+            }
+        }
+        if self.summary.backup_copied_bytes + self.summary.backup_reflinked_bytes > 0 {
+            log::info!(
+                "Backed up {} of parts, {} copied and {} reflinked.",
+                format_size(self.summary.backup_copied_bytes + self.summary.backup_reflinked_bytes),
+                format_size(self.summary.backup_copied_bytes),
+                format_size(self.summary.backup_reflinked_bytes)
+            );
+        }
+        if !self.summary.backup_failures.is_empty() {
+            log::error!(
+                "{} archives could not be backed up, their parts were kept:",
+                self.summary.backup_failures.len()
+            );
+            for (path, error) in &self.summary.backup_failures {
+                log::error!("-> '{}': {}", path.display(), error);
+            }
+        }
+        if !self.summary.partial_removals.is_empty() {
+            log::error!(
+                "{} archives were left partly removed, the rest of their parts is retried the next run:",
+                self.summary.partial_removals.len()
+            );
+            for (archive, failed) in &self.summary.partial_removals {
+                log::error!("-> '{}', {} parts left:", archive.display(), failed.len());
+                for (path, error) in failed {
+                    log::error!("   -> '{}': {}", path.display(), error);
+                }
+            }
+        }
+        if !self.summary.purged_backups.is_empty() {
+            log::info!(
+                "{} days of backups {} past the retention:",
+                self.summary.purged_backups.len(),
+                if self.dry_run { "would be purged" } else { "purged" }
+            );
+            for path in &self.summary.purged_backups {
+                log::info!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.vanished_archives.is_empty() {
+            log::info!(
+                "{} archives vanished before they were processed:",
+                self.summary.vanished_archives.len()
+            );
+            for path in &self.summary.vanished_archives {
+                log::info!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.spared_archives.is_empty() {
+            log::info!(
+                "{} archives past their threshold kept by --keep-newest:",
+                self.summary.spared_archives.len()
+            );
+            for (path, rank, release) in &self.summary.spared_archives {
+                log::info!("-> '{}', newest-{} of '{}'", path.display(), rank, release);
+            }
+        }
+        if !self.summary.capped_removals.is_empty() {
+            log::warn!(
+                "Removal cap hit: {} more files ({}) were eligible but deferred:",
+                self.summary.capped_removals.len(),
+                format_size(self.summary.capped_removals.iter().map(|(_, size)| size).sum())
+            );
+            for (path, size) in &self.summary.capped_removals {
+                log::warn!("-> '{}' ({})", path.display(), format_size(*size));
+            }
+        }
+        for (device, errors) in &self.summary.suspended_devices {
+            log::error!("Device {} suspended after {} IO errors.", device, errors);
+        }
+        if !self.summary.suspended_archives.is_empty() {
+            log::warn!(
+                "{} archives skipped because their device is suspended:",
+                self.summary.suspended_archives.len()
+            );
+            for path in &self.summary.suspended_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.downloading_archives.is_empty() {
+            log::info!(
+                "{} archives skipped while their download is in progress:",
+                self.summary.downloading_archives.len()
+            );
+            for path in &self.summary.downloading_archives {
+                log::info!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.sidecar_skipped_archives.is_empty() {
+            log::info!(
+                "{} archives skipped by their sidecar:",
+                self.summary.sidecar_skipped_archives.len()
+            );
+            for path in &self.summary.sidecar_skipped_archives {
+                log::info!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.skipped_archives.is_empty() {
+            log::warn!(
+                "{} archives skipped on request, partly extracted:",
+                self.summary.skipped_archives.len()
+            );
+            for path in &self.summary.skipped_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.long_name_archives.is_empty() {
+            log::warn!(
+                "{} archives skipped for entries exceeding the path length limits:",
+                self.summary.long_name_archives.len()
+            );
+            for path in &self.summary.long_name_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.name_conflict_archives.is_empty() {
+            log::warn!(
+                "{} archives skipped for entries that would be the same file in their destination:",
+                self.summary.name_conflict_archives.len()
+            );
+            for path in &self.summary.name_conflict_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.suspected_fakes.is_empty() {
+            log::warn!(
+                "{} archives skipped as suspected fakes:",
+                self.summary.suspected_fakes.len()
+            );
+            for path in &self.summary.suspected_fakes {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.busy_archives.is_empty() {
+            log::warn!(
+                "{} archives left untouched because their files are in use:",
+                self.summary.busy_archives.len()
+            );
+            for path in &self.summary.busy_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.no_space_archives.is_empty() {
+            log::warn!(
+                "{} archives skipped for lack of space or inodes:",
+                self.summary.no_space_archives.len()
+            );
+            for path in &self.summary.no_space_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.blocked_archives.is_empty() {
+            log::warn!(
+                "{} archives blocked until their destination has space again:",
+                self.summary.blocked_archives.len()
+            );
+            for path in &self.summary.blocked_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.snapshots.is_empty() {
+            log::info!("{} snapshots taken before removing:", self.summary.snapshots.len());
+            for snapshot in &self.summary.snapshots {
+                log::info!("-> '{}' of '{}'", snapshot.name, snapshot.volume.display());
+            }
+        }
+        if !self.summary.snapshots_pruned.is_empty() {
+            log::info!("Deleted {} expired snapshots.", self.summary.snapshots_pruned.len());
+        }
+        if !self.summary.repairable_archives.is_empty() {
+            log::warn!(
+                "{} archives can be repaired from their recovery volumes:",
+                self.summary.repairable_archives.len()
+            );
+            for (archive, missing, recovery) in &self.summary.repairable_archives {
+                log::warn!(
+                    "-> '{}' ({} missing, {} recovery volumes)",
+                    archive.display(),
+                    missing,
+                    recovery
+                );
+            }
+        }
+        if !self.summary.flapping_archives.is_empty() {
+            log::warn!(
+                "{} archives keep being extracted again, their files change behind rarscan's back:",
+                self.summary.flapping_archives.len()
+            );
+            for (archive, count, file) in &self.summary.flapping_archives {
+                log::warn!(
+                    "-> '{}' re-extracted {} times, '{}' keeps changing",
+                    archive.display(),
+                    count,
+                    file.display()
+                );
+            }
+        }
+        if !self.summary.nested_archives.is_empty() {
+            log::info!("Nested archives:");
+            for (parent, entries) in &self.summary.nested_archives {
+                log::info!("-> '{}'", parent.display());
+                for entry in entries {
+                    match entry.outcome {
+                        Some(outcome) => log::info!("   -> '{}' ({}, {})", entry.path.display(), entry.fate, outcome),
+                        None => log::info!("   -> '{}' ({})", entry.path.display(), entry.fate),
+                    }
+                }
+            }
+        }
+        if self.summary.scanned_files > 0 {
+            log::info!(
+                "Scanned {} extracted files, {} clean and {} flagged.",
+                self.summary.scanned_files,
+                self.summary.scanned_files - self.summary.flagged_files,
+                self.summary.flagged_files
+            );
+        }
+        if !self.summary.quarantined_archives.is_empty() {
+            log::warn!(
+                "{} archives quarantined by the scanner, their parts were kept:",
+                self.summary.quarantined_archives.len()
+            );
+            for quarantine in &self.summary.quarantined_archives {
+                log::warn!(
+                    "-> '{}' into '{}'",
+                    quarantine.archive.display(),
+                    quarantine.dir.display()
+                );
+                for file in &quarantine.files {
+                    log::warn!("   -> '{}': {}", file.path.display(), file.reason);
+                }
+            }
+        }
+        if !self.summary.invalid_payload_archives.is_empty() {
+            log::warn!(
+                "{} archives extracted but their payload is invalid:",
+                self.summary.invalid_payload_archives.len()
+            );
+            for (archive, invalid) in &self.summary.invalid_payload_archives {
+                log::warn!("-> '{}'", archive.display());
+                for (path, reason) in invalid {
+                    log::warn!("   -> '{}': {}", path.display(), reason);
+                }
+            }
+        }
+        if !self.summary.locked_archives.is_empty() {
+            log::warn!(
+                "{} archives left for a later run, another instance kept their destination locked:",
+                self.summary.locked_archives.len()
+            );
+            for (archive, dest) in &self.summary.locked_archives {
+                log::warn!("-> '{}' into '{}'", archive.display(), dest.display());
+            }
+        }
+        if !self.summary.missing_content_archives.is_empty() {
+            log::warn!(
+                "{} archives extracted without the required content, their parts are kept:",
+                self.summary.missing_content_archives.len()
+            );
+            for path in &self.summary.missing_content_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.duplicates.is_empty() {
+            let mut groups: BTreeMap<&Path, Vec<(&Path, &str)>> = BTreeMap::new();
+            for (original, duplicate, action) in &self.summary.duplicates {
+                groups.entry(original).or_default().push((duplicate, action));
+            }
+            log::warn!(
+                "{} archives are duplicates of archives found elsewhere:",
+                self.summary.duplicates.len()
+            );
+            for (original, duplicates) in groups {
+                log::warn!("-> '{}'", original.display());
+                for (duplicate, action) in duplicates {
+                    log::warn!("   -> '{}' ({})", duplicate.display(), action.replace('_', " "));
+                }
+            }
+        }
+        if !self.summary.chains.is_empty() {
+            log::info!("Archive chains:");
+            for chain in &self.summary.chains {
+                log::info!("-> '{}' ({})", chain.root.display(), chain.status.replace('_', " "));
+                log_chain_members(&chain.nested, &chain.root, 1);
+            }
+        }
+        if !self.summary.timings.is_empty() {
+            log::info!("Slowest extractions:");
+            for timing in self.summary.slowest(3) {
+                log::info!(
+                    "-> '{}' {:.1} MB/s ({} in {:.1}s)",
+                    timing.path.display(),
+                    timing.mb_per_sec(),
+                    format_size(timing.bytes),
+                    timing.duration.as_secs_f64()
+                );
+            }
+        }
+        if !self.summary.oversized_archives.is_empty() {
+            log::warn!(
+                "{} archives skipped for exceeding the size or entry limits:",
+                self.summary.oversized_archives.len()
+            );
+            for path in &self.summary.oversized_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if !self.summary.empty_archives.is_empty() {
+            log::warn!("{} empty archives found:", self.summary.empty_archives.len());
+            for path in &self.summary.empty_archives {
+                log::warn!("-> '{}'", path.display());
+            }
+        }
+        if let Some(changelog) = &self.changelog {
+            if let Err(e) = changelog.write() {
+                log::error!("Unable to write the changelog: {:#}", e);
+            }
+        }
+        if !self.dry_run {
+            if let Some(state) = &mut self.state {
+                let bytes: u64 = self.summary.timings.iter().map(|timing| timing.bytes).sum();
+                let secs: f64 = self
+                    .summary
+                    .timings
+                    .iter()
+                    .map(|timing| timing.duration.as_secs_f64())
+                    .sum();
+                if bytes > 0 && secs > 0.0 {
+                    state.set_throughput(bytes as f64 / secs);
+                }
+                if let Err(e) = state.save() {
+                    log::error!("Unable to save the state file: {:#}", e);
+                }
+            }
+        }
+        #[cfg(feature = "http")]
+        if let Some(server) = &self.status_server {
+            let summary = events::summary_to_json(&self.summary);
+            server.update(|status| status.last_summary = Some(summary));
+        }
+        self.events.emit(Event::RunSummary { summary: &self.summary });
+        #[cfg(feature = "telemetry")]
+        if let Some(tracer) = &mut self.tracer {
+            tracer.set_current("rarscan.archives_processed", self.summary.archives_processed);
+            tracer.set_current("rarscan.archives_extracted", self.summary.archives_extracted);
+            tracer.set_current("rarscan.archives_failed", self.summary.failed_archives.len() as u64);
+            tracer.set_current("rarscan.parts_removed", self.summary.parts_removed);
+            tracer.finish();
+        }
+    }
+}
+
+/// Logs the archives found in `parent` as a tree below it.
+fn log_chain_members(nested: &[(PathBuf, PathBuf)], parent: &Path, depth: usize) {
+    for (archive, _) in nested.iter().filter(|(_, p)| p == parent) {
+        log::info!("{}-> '{}'", "   ".repeat(depth), archive.display());
+        log_chain_members(nested, archive, depth + 1);
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true, args_override_self = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(required_unless_present = "print_config")]
+    root_dir: Option<PathBuf>,
+    /// Read flags from this file, one on each line without its dashes: `remove-after-hours = 48`, or `fsync` for a
+    /// switch. The ones of the command line win. `rarscan setup` writes one.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    #[arg(long, global = true, default_value = "info")]
+    log_level: log::LevelFilter,
+    /// Maximum number of files open at once, defaults to the limit of the process minus a reserve.
+    #[arg(long, global = true)]
+    max_open_files: Option<usize>,
+    /// Format dates in the local time zone instead of UTC, same as `--timezone local`.
+    #[arg(long, global = true, default_value = "false", conflicts_with = "timezone")]
+    local_time: bool,
+    /// Time zone of the dates in the log: utc, local or a tz database name such as Europe/Paris.
+    #[arg(long, global = true, value_name = "ZONE")]
+    timezone: Option<String>,
+    /// strftime-like format of the dates in the messages, for example `%d/%m/%Y %H:%M`. JSON always uses RFC 3339.
+    #[arg(long, global = true, value_name = "FORMAT", default_value = datetime::DEFAULT_FORMAT, value_parser = datetime::parse_format)]
+    time_format: OwnedFormatItem,
+    /// Also write the log to this file.
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// Rotate the log file once it grows past this size.
+    #[arg(long, global = true, default_value = "10M", value_parser = parse_size)]
+    log_max_size: u64,
+    /// Number of rotated log files to keep.
+    #[arg(long, global = true, default_value = "5")]
+    log_keep: usize,
+    /// Only write the log to the log file.
+    #[arg(long, global = true, default_value = "false", requires = "log_file")]
+    no_stderr: bool,
+    #[arg(long, global = true, default_value = "false")]
+    dry_run: bool,
+    #[arg(long, global = true)]
+    remove_after_hours: Option<u64>,
+    /// What to do with archives extracted again more than --flapping-threshold times because something keeps
+    /// changing their files: warn, or warn and stop extracting them.
+    #[arg(long, global = true, value_enum, default_value = "warn")]
+    flapping_policy: FlappingPolicy,
+    /// Extractions of an archive over changed files before it counts as flapping.
+    #[arg(long, global = true, default_value = "3")]
+    flapping_threshold: u32,
+    /// Runs in a row the processing of an archive may panic before it's left alone, until its first part changes.
+    #[arg(long, global = true, default_value = "3")]
+    panic_limit: u32,
+    /// Only extract: record the parts to remove in the state file for `rarscan clean --from-state` instead of removing
+    /// them, and leave cruft and chains of nested archives alone.
+    #[arg(long, global = true, default_value = "false")]
+    no_remove: bool,
+    /// Hours a chain of nested archives missing a member deleted by something else keeps the parts of the others.
+    #[arg(long, global = true, default_value = "72")]
+    chain_grace_hours: u64,
+    /// Remove-after threshold for the archives and cruft matching a glob relative to the root directory, e.g.
+    /// tv/**=3d. Can be repeated, the most specific matching glob applies, the one naming the most directories
+    /// literally, and --remove-after-hours is the fallback. `tv/**=3d,keep-newest=1` overrides --keep-newest too.
+    #[arg(long, global = true, value_parser = RemoveAfterRule::parse)]
+    remove_after_for: Vec<RemoveAfterRule>,
+    /// Keep the parts of the N archives of each release directory extracted last, however old. The archives nested in
+    /// them are kept with them.
+    #[arg(long, global = true, value_name = "N")]
+    keep_newest: Option<usize>,
+    /// Publish JSON events, one per line, on a Unix domain socket at this path.
+    #[arg(long, global = true)]
+    event_socket: Option<PathBuf>,
+    /// What stdout carries. `ndjson` writes the events as they happen, one JSON object per line with the version of
+    /// their schema in `v`, and moves the log to stderr.
+    #[arg(long, global = true, value_enum, default_value = "log")]
+    output: OutputFormat,
+    /// Answer `rarscan ctl` on a Unix domain socket at this path, between archives. `rarscan ctl` connects to it.
+    #[arg(long, global = true)]
+    control_socket: Option<PathBuf>,
+    /// Program run with the archive path before its parts are removed, exit status 0 allows the removal.
+    #[arg(long, global = true)]
+    removal_gate: Option<PathBuf>,
+    #[arg(long, global = true, default_value = "10")]
+    removal_gate_timeout_secs: u64,
+    /// Remove at most this many files in a run, parts and cruft together. The rest is deferred to a later run and the
+    /// run exits with status 7.
+    #[arg(long, global = true, value_name = "N")]
+    max_remove_files: Option<u64>,
+    /// Remove at most this much data in a run, such as `500GiB`. Like --max-remove-files.
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    max_remove_bytes: Option<u64>,
+    /// Snapshot command run once per filesystem before the first removal in it, for example
+    /// `btrfs subvolume snapshot -r {path} {path}/.rarscan-snap-{ts}`. `{path}` is the root of the filesystem and `{ts}`
+    /// the time of the run, the last argument is recorded as the name of the snapshot. When it fails nothing is removed
+    /// for the rest of the run.
+    #[arg(long, global = true, value_name = "COMMAND", value_parser = CommandTemplate::parse)]
+    snapshot_before_remove: Option<CommandTemplate>,
+    /// Remove anyway when a snapshot fails.
+    #[arg(long, global = true, default_value = "false", requires = "snapshot_before_remove")]
+    snapshot_best_effort: bool,
+    /// Delete the snapshots taken longer ago than this, for example `7d`, with --snapshot-delete.
+    #[arg(long, global = true, value_parser = parse_duration, requires_all = ["snapshot_before_remove", "snapshot_delete"])]
+    snapshot_retention: Option<Duration>,
+    /// Command deleting a snapshot past the retention, for example `btrfs subvolume delete {snapshot}`.
+    #[arg(long, global = true, value_name = "COMMAND", value_parser = CommandTemplate::parse, requires = "snapshot_retention")]
+    snapshot_delete: Option<CommandTemplate>,
+    /// Skip the sets next to a file matching this glob, a download still writing to their directory. Adds to the
+    /// built-in markers: *.parts, *.!qB, *.part and .unpackignore.
+    #[arg(long, global = true, value_name = "GLOB")]
+    in_progress_marker: Vec<String>,
+    /// Stop working on a device, the one of archives or of destinations, after this many IO errors on it in a row
+    /// (or twice as many within 10 minutes). Bad passwords and corrupt archives don't count.
+    #[arg(long, global = true, value_name = "N")]
+    max_device_errors: Option<usize>,
+    /// Extract and remove only between these times of the day, `22:00-06:00` runs at night. In the zone of
+    /// --timezone, the local one by default. The archive in progress finishes when the window closes.
+    #[arg(long, global = true, value_name = "HH:MM-HH:MM", value_parser = ActiveHours::parse)]
+    active_hours: Option<ActiveHours>,
+    /// Extract and remove only on these days, `mon-fri` or `sat,sun`. With --active-hours, the day a window starts.
+    #[arg(long, global = true, value_name = "DAYS", value_parser = ActiveDays::parse)]
+    active_days: Option<ActiveDays>,
+    /// Outside of the active window, sleep until it opens instead of exiting with status 6.
+    #[arg(long, global = true, default_value = "false")]
+    wait_for_window: bool,
+    /// Switch to this user, and group, before touching any archive: `media`, `media:media` or `1000:1000`. The log file
+    /// and the sockets are opened before, the state file and everything else as that user.
+    #[arg(long, global = true, value_name = "USER[:GROUP]", value_parser = RunAs::parse)]
+    run_as: Option<RunAs>,
+    /// Print the settings resolved from the arguments and exit.
+    #[arg(long, default_value = "false")]
+    print_config: bool,
+    /// rar binary rebuilding the missing volumes of incomplete sets from their .rev recovery volumes, with `rar rc`.
+    #[arg(long, global = true, value_name = "RAR")]
+    recovery_tool: Option<PathBuf>,
+    /// Remove the directories left empty once parts and cruft are removed, unless they hold a .rarscan-keep file.
+    #[arg(long, global = true, default_value = "false")]
+    remove_empty_dirs: bool,
+    /// Also remove the parts of archives that contain no files once they age.
+    #[arg(long, global = true, default_value = "false")]
+    remove_empty_archives: bool,
+    /// Skip archives that look like fake releases, such as archives containing executables or a password.txt.
+    #[arg(long, global = true, default_value = "false")]
+    detect_fakes: bool,
+    /// Rules used by --detect-fakes, one per line: ext:<extension>, name:<file name> or media-mismatch.
+    #[arg(long, global = true, requires = "detect_fakes")]
+    fake_rules: Option<PathBuf>,
+    /// Skip archives that unpack to more than this size, 500GiB unless given. Given, this or --max-entries has
+    /// rarscan write the files of rar archives itself, to stop within a file that grows past its declared size.
+    #[arg(long, global = true, value_parser = parse_size)]
+    max_unpacked_size: Option<u64>,
+    /// Move extracted files once they were extracted this long ago, e.g. 30d. Requires --archive-extracted-to.
+    #[arg(long, global = true, value_parser = parse_duration, requires = "archive_extracted_to")]
+    archive_extracted_after: Option<Duration>,
+    /// Directory receiving the extracted files moved by --archive-extracted-after, under their path relative to the
+    /// root directory.
+    #[arg(long, global = true, requires = "archive_extracted_after")]
+    archive_extracted_to: Option<PathBuf>,
+    /// Write every file created, overwritten or removed during the run to this file as JSON Lines.
+    #[arg(long, global = true)]
+    changelog: Option<PathBuf>,
+    /// Append a line to this file before any file is removed or overwritten, with the time, the action, the path, the
+    /// size and mtime of the file, the archive it's for and the rule behind it: age, chain-age, cruft:PATTERN,
+    /// extraction, re-extraction, duplicate or unpackerr-staging. rarscan never truncates nor rotates it, and leaves a
+    /// file alone when its line can't be written.
+    #[arg(long, global = true)]
+    audit_log: Option<PathBuf>,
+    /// Format of the lines of --audit-log.
+    #[arg(long, global = true, value_enum, default_value = "json", requires = "audit_log")]
+    audit_format: AuditFormat,
+    /// Sync --audit-log to disk after each line, before the action it records.
+    #[arg(long, global = true, default_value = "false", requires = "audit_log")]
+    audit_fsync: bool,
+    /// Copy the parts of an archive into this directory before extracting it and before removing any of them, under
+    /// YYYY-MM-DD/SET. A reflink is made when the filesystem allows it. Parts backed up already with the same sizes
+    /// are not copied again, and the parts of an archive whose backup fails are kept.
+    #[arg(long, global = true)]
+    backup_parts_to: Option<PathBuf>,
+    /// Remove the days of backups of --backup-parts-to older than this many days.
+    #[arg(long, global = true, requires = "backup_parts_to")]
+    backup_retention: Option<u64>,
+    /// File keeping data between runs, defaults to .rarscan-state.json in the root directory.
+    #[arg(long, global = true)]
+    state_file: Option<PathBuf>,
+    /// Start from an empty state when the state file is corrupt and so is its backup, instead of refusing to run.
+    #[arg(long, global = true, default_value = "false")]
+    reset_state: bool,
+    /// Hash the extracted files verified with --verify-crc again once their result is this many days old.
+    #[arg(long, global = true, default_value = "90")]
+    verified_max_age_days: u64,
+    /// When to process the archives found inside of other archives.
+    #[arg(long, global = true, value_enum, default_value = "immediate")]
+    nested_order: NestedOrder,
+    /// Unpack the .tar, .tar.gz, .tgz and .tar.zst files found inside of archives like nested archives. Links inside
+    /// of them are only created when they point inside of the destination.
+    #[arg(long, global = true, default_value = "false")]
+    unpack_tarballs: bool,
+    /// Mtime of the extracted files: the one stored in the archive, the extraction time or the archive's mtime.
+    #[arg(long, global = true, value_enum, default_value = "keep")]
+    extracted_mtime: ExtractedMtime,
+    /// Allocate the extracted files up front to keep them from getting fragmented: only where rarscan writes them,
+    /// zip archives, always, which extracts rar archives through rarscan rather than unrar, or never.
+    #[arg(long, global = true, value_enum, default_value = "auto")]
+    preallocate: Preallocate,
+    /// Copy the entries of rar archives stored without compression straight out of their volume, as a reflink of the
+    /// range where the filesystem allows it, and check them against the CRC32 of their header. Entries compressed,
+    /// encrypted or split across volumes are extracted by unrar as usual.
+    #[arg(long, global = true, default_value = "false")]
+    fast_store_copy: bool,
+    /// Inodes that must remain free on the destination once an archive is extracted.
+    #[arg(long, global = true, default_value = "1000")]
+    inode_margin: u64,
+    /// Extract each archive in a process of its own, confined by Landlock to reading the directories of its parts and
+    /// writing its destination, without network sockets. Linux only, extractions aren't confined where the kernel can't.
+    #[arg(long, global = true, default_value = "false")]
+    sandbox: bool,
+    /// Percentage of the destination's filesystem an extraction may bring it to, such as `90%`, counted like df does.
+    /// Archives that would go over it are deferred until the parts of the others are removed, and skipped if they
+    /// still would. Composes with the free space and inodes checks.
+    #[arg(long, global = true, value_parser = parse_percent)]
+    max_disk_usage: Option<f64>,
+    /// Directory the paths of rename maps may point into, besides the archive's directory and its destination. Can be
+    /// repeated.
+    #[arg(long, global = true)]
+    map_root: Vec<PathBuf>,
+    /// Skip archives that have more entries than this, 1000000 unless given.
+    #[arg(long, global = true)]
+    max_entries: Option<usize>,
+    /// Extract the content of an archive's only top-level directory straight into the destination when that directory
+    /// is named after the directory holding the archive.
+    #[arg(long, global = true, default_value = "false")]
+    flatten_single_dir: bool,
+    /// Give the directories created by an extraction the group and setgid bit of their parent directory, and the
+    /// extracted files the group of the directory holding them, when the process is allowed to.
+    #[arg(long, global = true, default_value = "false")]
+    inherit_dir_perms: bool,
+    /// Show a full-screen dashboard of the queue instead of the log when scanning from a terminal. Keys: p pauses after
+    /// the current archive, s skips the archive being extracted and keeps its parts, q or Ctrl-C quits after the
+    /// current archive.
+    #[cfg(feature = "tui")]
+    #[arg(long, global = true, default_value = "false")]
+    tui: bool,
+    /// Flush the extracted files and their directories to disk before going on, the parts in particular are only ever
+    /// removed once their content is durable.
+    #[arg(long, global = true, default_value = "false")]
+    fsync: bool,
+    /// Only process the release directories, the ones directly below the root directory, where an archive still needs
+    /// to be extracted or removed.
+    #[arg(long, global = true, default_value = "false")]
+    only_incomplete_releases: bool,
+    /// With --dry-run, look into the archives nested in the ones to extract, and the ones nested in those, by
+    /// extracting their volumes alone into a temporary directory. The plan then shows their own extractions and
+    /// removals, marked as simulated.
+    #[arg(long, global = true, default_value = "false", requires = "dry_run")]
+    deep_dry_run: bool,
+    /// The size of the volumes of a nested set past which --deep-dry-run doesn't copy it to look into it.
+    #[arg(long, global = true, default_value = "1GiB", value_parser = parse_size)]
+    deep_dry_run_max_size: u64,
+    /// With --dry-run, exit with status 8 when there are changes pending and 0 otherwise.
+    #[arg(long, global = true, default_value = "false", requires = "dry_run")]
+    check: bool,
+    /// Exit with status 4 when the scan finds no root archive at all, which usually means the root directory isn't
+    /// what it should be, like a mount point with nothing mounted.
+    #[arg(long, global = true, default_value = "false")]
+    expect_archives: bool,
+    /// Serve the progress of the run over HTTP on this address, at /healthz, /status and /metrics.
+    #[cfg(feature = "http")]
+    #[arg(long, global = true, value_name = "ADDR:PORT")]
+    http_status: Option<String>,
+    /// Leave alone the archives and cruft matching the patterns of this file, which has the syntax of .gitignore and is
+    /// relative to the root directory. Applied after the .rarscanignore of the root directory, if any.
+    #[arg(long, global = true)]
+    ignore_file: Option<PathBuf>,
+    /// Export a trace of the run to this OpenTelemetry collector, over OTLP/HTTP with JSON encoding. Each archive gets a
+    /// span with children for opening, verification, extraction and removal. Only http:// URLs are supported.
+    #[cfg(feature = "telemetry")]
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+    /// Record the archives found extracted by other tools in the state file, like `adopt` does.
+    #[arg(long, global = true, default_value = "false")]
+    auto_adopt: bool,
+    /// Check the files of already extracted archives against the CRCs of their entries and extract again the ones that
+    /// don't match. Files unchanged since they were last verified aren't read again. The CRCs are recorded for `rarscan
+    /// audit`, the files extracted with it included.
+    #[arg(long, global = true, default_value = "false")]
+    verify_crc: bool,
+    /// What to do with an archive identical to one already extracted elsewhere: extract it again, skip it, or hard
+    /// link (or reflink) the files extracted from the other one into its destination.
+    #[arg(long, global = true, value_enum, default_value = "extract-all")]
+    duplicate_policy: DuplicatePolicy,
+    /// Memory the queue of archives may take, for example `512M`. The archives over it wait in a file beside the state
+    /// file until their turn comes.
+    #[arg(long, global = true, value_parser = parse_size)]
+    memory_budget: Option<u64>,
+    /// Check the media files of extracted archives with this ffprobe, or with a minimal check of their container
+    /// header when no program is given. Archives with invalid media files are reported.
+    #[arg(long, global = true, value_name = "FFPROBE", num_args = 0..=1)]
+    probe_media: Option<Option<PathBuf>>,
+    /// Seconds a media file gets to be probed before it counts as invalid.
+    #[arg(long, global = true, default_value = "30")]
+    probe_timeout_secs: u64,
+    /// Keep the parts of the archives whose media files fail the probe, to download them again.
+    #[arg(long, global = true, default_value = "false", requires = "probe_media")]
+    require_valid_media: bool,
+    /// Seconds an archive deferred because another instance is extracting into its destination waits for it at the
+    /// end of the run, before it's left for a later run.
+    #[arg(long, global = true, default_value = "60")]
+    dir_lock_wait_secs: u64,
+    /// Removals and mtime fixups in flight at the same time. On a network share, where each of them waits for round
+    /// trips, more of them make the removals faster.
+    #[arg(long, global = true, default_value = "4")]
+    fs_jobs: usize,
+    /// Only count an archive as extracted when one of its files matches this glob, such as `*.mkv`. Can be given more
+    /// than once, any of them will do. The parts of the others are kept, and the files of the archives nested in one
+    /// count for the chain.
+    #[arg(long, global = true, value_name = "GLOB")]
+    require_content: Vec<String>,
+    /// Run this scanner on each extracted file before the archive counts as extracted, `{file}` in the command being
+    /// the file, appended when missing. With `{dir}` it runs once on the destination instead. Exit status 0 passes,
+    /// anything else moves the flagged files to --quarantine-dir and keeps the parts. Nothing is scanned in a dry-run.
+    #[arg(long, global = true, value_name = "COMMAND", value_parser = CommandTemplate::parse, requires = "quarantine_dir")]
+    scan_command: Option<CommandTemplate>,
+    /// Directory the files flagged by --scan-command are moved to, below a directory named after their archive.
+    #[arg(long, global = true, requires = "scan_command")]
+    quarantine_dir: Option<PathBuf>,
+    /// Files scanned at the same time.
+    #[arg(long, global = true, default_value = "1")]
+    scan_jobs: usize,
+    /// Seconds the scanner gets for a file, or for the destination with `{dir}`, before the files count as flagged.
+    #[arg(long, global = true, default_value = "300")]
+    scan_timeout_secs: u64,
+    /// With --verify-crc, hash every file again instead of trusting earlier verifications.
+    #[arg(long, global = true, default_value = "false", requires = "verify_crc")]
+    revalidate: bool,
+    /// Descend into symlinked directories when scanning for archives. Archives found through them are never removed.
+    #[arg(long, global = true, default_value = "false")]
+    follow_symlinks: bool,
+    /// Read every directory when scanning for archives. Otherwise the directories whose mtime is the same as at the
+    /// last scan are taken from the state file, except on network and FUSE filesystems.
+    #[arg(long, global = true, default_value = "false")]
+    full_scan: bool,
+    /// Don't list the headers of the next archive while the current one extracts.
+    #[arg(long, global = true, default_value = "false")]
+    no_prefetch: bool,
+    /// Truncate the entry names over the file name limit and append a hash of the full name, instead of skipping the
+    /// archive.
+    #[arg(long, global = true, default_value = "false")]
+    shorten_long_names: bool,
+    /// How the names of the entries are compared to the files already in the destination, and which entries can't be
+    /// extracted side by side. `auto` finds out how the filesystem of each destination compares them.
+    #[arg(long, global = true, value_enum, default_value = "auto")]
+    name_matching: NameMatching,
+    /// Defer archives whose existing destination files are open by another process instead of overwriting them.
+    #[arg(long, global = true, default_value = "false")]
+    skip_in_use: bool,
+    /// Files matching this regex are root archives, can be repeated. When given, files matching the part pattern are
+    /// only root archives if they match one of these.
+    #[arg(long, global = true, value_parser = naming::parse_regex)]
+    root_pattern: Vec<Regex>,
+    /// Regex matching the parts of a multi-part set, its first capture group is the part number.
+    #[arg(long, global = true, value_parser = naming::parse_part_pattern)]
+    part_pattern: Option<Regex>,
+    /// Look for the volumes missing next to the first one of a set in the directories next to its own, `cd1/`,
+    /// `cd2/` and so on, by their set name and their headers. Such a set extracts into the parent of those directories.
+    #[arg(long, global = true, default_value = "false")]
+    parts_across_dirs: bool,
+    /// Extract into this directory instead of next to the archive. Relative templates start from the archive's
+    /// directory. Placeholders: {archive_stem}, {parent_dir}, {year} and {ext-category} (video, audio or other).
+    #[arg(long, global = true, value_parser = DestTemplate::parse)]
+    dest_template: Option<DestTemplate>,
+    /// Warn about archives extracting slower than this many MB/s.
+    #[arg(long, global = true)]
+    slow_threshold: Option<f64>,
+    /// Warn about archives whose unpacked size is more than this many times their packed size.
+    #[arg(long, global = true)]
+    suspicious_ratio: Option<f64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Process a single archive and the archives nested inside of it, without scanning or removing cruft.
+    One { path: PathBuf },
+    /// Print each check a run makes for an archive, what it looked at and what came of it, then what the next run
+    /// would do with it. Nothing is changed.
+    Explain {
+        path: PathBuf,
+        /// Directory the runs scan, whose state file, ignore rules and --remove-after-for rules apply. The directory of
+        /// the archive by default.
+        #[arg(long)]
+        root: Option<PathBuf>,
+    },
+    /// Record the archives already extracted by other tools in the state file, without extracting or removing anything.
+    Adopt { dir: PathBuf },
+    /// Print how much work a run over a directory would do and roughly how long it would take, without touching
+    /// anything.
+    Estimate {
+        dir: PathBuf,
+        /// Output format, the JSON one is printed alone, without the log on the console.
+        #[arg(long, value_enum, default_value = "table")]
+        format: EstimateFormat,
+        /// Extraction throughput per second to assume instead of the one measured by previous runs, e.g. 120M.
+        #[arg(long, value_parser = parse_size)]
+        assume_throughput: Option<u64>,
+    },
+    /// Remove the parts recorded by earlier runs once they are old enough, for runs done with --no-remove.
+    Clean {
+        dir: PathBuf,
+        /// Apply the removals recorded in the state file of the directory.
+        #[arg(long, required = true)]
+        from_state: bool,
+    },
+    /// Give the extracted files and their directories owned by another user back to --owner, as the ones left behind
+    /// by a run with sudo. Only the extractions recorded in the state file of the directory are looked at when it has
+    /// one. Changing the owner takes root, --dry-run only lists them. Exits with 1 when some keep another owner.
+    FixOwnership {
+        dir: PathBuf,
+        /// Who should own the files: `media`, `media:media` or `1000:1000`.
+        #[arg(long, value_name = "USER[:GROUP]", value_parser = RunAs::parse)]
+        owner: RunAs,
+        /// Look at everything below the directory, not only the extractions of the state file.
+        #[arg(long, default_value = "false")]
+        whole_dir: bool,
+    },
+    /// Print the versions rarscan was built with and run a self-test against embedded fixtures.
+    Doctor,
+    /// Ask for the directory to scan, whether and when to remove the parts, where to extract and how to schedule the
+    /// runs, then write the config file and print the crontab line or the systemd units running rarscan with it. The
+    /// flags of the answers, --remove-after-hours and --dest-template, are the defaults of the questions. Nothing is
+    /// extracted or removed.
+    Setup {
+        dir: Option<PathBuf>,
+        /// Take the answers from the flags and the defaults without asking, for scripts.
+        #[arg(long)]
+        yes: bool,
+        #[arg(long, value_enum)]
+        schedule: Option<setup::Schedule>,
+        /// How often the runs start, such as `30m` or `6h`. Every hour by default.
+        #[arg(long, value_parser = parse_duration)]
+        every: Option<Duration>,
+        /// Where to write the config file.
+        #[arg(long, default_value = "rarscan.conf")]
+        config_path: PathBuf,
+    },
+    /// Install the latest release from GitHub over this binary when it's newer, verified against its published
+    /// SHA-256. Proxies are taken from HTTPS_PROXY and the like. rarscan never looks for updates otherwise.
+    #[cfg(feature = "self-update")]
+    SelfUpdate {
+        /// Only report whether a newer release exists.
+        #[arg(long)]
+        check_update: bool,
+    },
+    /// Record in the rename map of an archive that its entry `old`, named relative to the destination, is now the
+    /// file at `new`. The already-extracted check then looks for it there.
+    MapRename {
+        archive: PathBuf,
+        old: PathBuf,
+        new: PathBuf,
+    },
+    /// Look at or change the queue of an instance running with --control-socket, it answers between archives.
+    Ctl {
+        /// Print the response as JSON.
+        #[arg(long)]
+        json: bool,
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Take over a directory unpackerr extracted into: adopt the extractions it completed, extract again the ones it
+    /// left partly done and report the archives left alone. Archives in its `_unpackerred` staging directories are
+    /// ignored.
+    ImportUnpackerr {
+        dir: PathBuf,
+        /// Move the extractions complete in a staging directory next to their archive, and remove the staging
+        /// directories of the archives found.
+        #[arg(long)]
+        clean: bool,
+    },
+    /// The extraction of one archive for --sandbox, run by rarscan itself.
+    #[command(name = sandbox::WORKER_COMMAND, hide = true)]
+    ExtractWorker {
+        archive: PathBuf,
+        dest: PathBuf,
+        /// Directory the worker may read, besides the destination.
+        #[arg(long)]
+        read: Vec<PathBuf>,
+        /// Bytes the extraction may write before it's aborted.
+        #[arg(long)]
+        max_written: Option<u64>,
+        /// Write the files of rar archives through rarscan, for --max-written to stop within a file.
+        #[arg(long)]
+        stream_budget: bool,
+    },
+    /// Inspect the state file of a directory.
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    /// Hash the extracted files below a directory again and compare them to the CRCs recorded by --verify-crc, the
+    /// ones verified the longest ago first, to find the ones rotting on disk. Or query the --audit-log.
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Audit {
+        #[command(subcommand)]
+        command: Option<AuditCommand>,
+        #[arg(required = true)]
+        dir: Option<PathBuf>,
+        /// Stop once this long went by, such as `2h`, after the file being hashed. The next audit goes on from there.
+        #[arg(long, value_parser = parse_duration)]
+        budget: Option<Duration>,
+        /// Stop once this much was read, such as `500GiB`, after the file being hashed.
+        #[arg(long, value_parser = parse_size)]
+        byte_budget: Option<u64>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    /// Print the progress of the run and whether it's paused.
+    Status,
+    /// List the queued archives in order, then the deferred ones.
+    Queue,
+    /// Move a queued archive to the front of the queue.
+    Prioritize {
+        path: PathBuf,
+    },
+    /// Take an archive out of the queue, it's left alone until the next run.
+    Drop {
+        path: PathBuf,
+    },
+    /// Stop before the next archive, until resumed.
+    Pause,
+    Resume,
+}
+
+impl CtlCommand {
+    fn request(&self) -> Request {
+        // The running instance may have been started from another directory.
+        let absolute = |path: &Path| fs::canonicalize(path).or_else(|_| std::path::absolute(path));
+        match self {
+            CtlCommand::Status => Request::Status,
+            CtlCommand::Queue => Request::Queue,
+            CtlCommand::Prioritize { path } => Request::Prioritize(absolute(path).unwrap_or_else(|_| path.clone())),
+            CtlCommand::Drop { path } => Request::Drop(absolute(path).unwrap_or_else(|_| path.clone())),
+            CtlCommand::Pause => Request::Pause,
+            CtlCommand::Resume => Request::Resume,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum StateCommand {
+    /// Print the number of entries of each kind and the size of the state file, without changing it.
+    Stats { dir: PathBuf },
+    /// Print the archives the state file knows, where they were last seen, without changing it.
+    List {
+        dir: PathBuf,
+        /// Print the id of each archive before it, which stays the same when it's renamed or moved.
+        #[arg(long)]
+        ids: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum AuditCommand {
+    /// Print the lines of the audit log matching a regular expression, exits with status 1 when none does.
+    Grep { pattern: String },
+}
+
+/// Exit status of a run where an archive couldn't be extracted because of a problem with the archive.
+const ARCHIVE_FAILED: u8 = 2;
+/// Exit status of a run where an archive couldn't be extracted because of a problem with the destination.
+const DESTINATION_FAILED: u8 = 3;
+/// Exit status of a run that stopped using a device after IO errors on it.
+const DEVICE_SUSPENDED: u8 = 5;
+/// Exit status of an --expect-archives run that found no root archive.
+const NO_ARCHIVES: u8 = 4;
+/// Exit status of a run started, or stopped, outside of the --active-hours window.
+const OUTSIDE_WINDOW: u8 = 6;
+/// Exit status of a run that deferred removals for going past --max-remove-files or --max-remove-bytes.
+const REMOVAL_CAPPED: u8 = 7;
+/// Exit status of a --check run that found changes to make.
+const CHANGES_PENDING: u8 = 8;
+/// Exit status of an `audit` that found corrupt files, or files it couldn't read.
+const CORRUPTION_FOUND: u8 = 9;
+
+fn usage_error(message: String) -> ! {
+    Args::command().error(ErrorKind::InvalidValue, message).exit()
+}
+
+/// Switches to the --run-as user for good. Nothing may open an archive before.
+fn drop_privileges(args: &Args) -> anyhow::Result<()> {
+    if let Some(run_as) = &args.run_as {
+        run_as
+            .apply()
+            .with_context(|| format!("switch to user {}, refusing to go on", run_as))?;
+        log::info!("Running as {}.", privileges::current_user());
+    }
+    Ok(())
+}
+
+/// The options of the run the extraction worker of --sandbox takes.
+fn worker_sandbox(args: &Args) -> Sandbox {
+    Sandbox {
+        naming: ArchiveNaming::new(args.root_pattern.clone(), args.part_pattern.clone())
+            .with_parts_across_dirs(args.parts_across_dirs),
+        preallocate: args.preallocate,
+        fast_store_copy: args.fast_store_copy,
+    }
+}
+
+/// The sandbox of --sandbox, `None` with a warning when the kernel can't confine the worker.
+fn run_sandbox(args: &Args) -> Option<Sandbox> {
+    match sandbox::landlock_abi() {
+        Ok(abi) => log::info!("Extracting in a sandbox, Landlock {}.", abi),
+        Err(reason) => {
+            log::warn!("--sandbox: {}, extracting without a sandbox.", reason);
+            return None;
+        }
+    }
+    Some(worker_sandbox(args))
+}
+
+/// Prints the settings a run would use, the defaults included.
+fn print_config(args: &Args, markers: &InProgressMarkers) {
+    let or_none = |path: Option<&Path>| path.map_or_else(|| "-".to_string(), |path| path.display().to_string());
+    let state_file = args
+        .state_file
+        .clone()
+        .or_else(|| args.root_dir.as_ref().map(|dir| dir.join(state::DEFAULT_STATE_FILE)));
+    let remove_after = args
+        .remove_after_hours
+        .map_or_else(|| "never".to_string(), |hours| format!("{}h", hours));
+    println!("{:<24} {}", "Root directory", or_none(args.root_dir.as_deref()));
+    println!("{:<24} {}", "State file", or_none(state_file.as_deref()));
+    println!("{:<24} {}", "Config file", or_none(args.config.as_deref()));
+    println!("{:<24} {}", "Dry run", args.dry_run);
+    if let Some(context) = TIME_CONTEXT.get() {
+        println!("{:<24} {}", "Time zone", context.zone);
+    }
+    println!("{:<24} {}", "Remove after", remove_after);
+    println!("{:<24} {}", "Recovery tool", or_none(args.recovery_tool.as_deref()));
+    println!("{:<24} {}", "Control socket", or_none(args.control_socket.as_deref()));
+    for (i, pattern) in markers.patterns().enumerate() {
+        println!("{:<24} {}", if i == 0 { "In-progress markers" } else { "" }, pattern);
+    }
+    for (i, pattern) in args.require_content.iter().enumerate() {
+        println!("{:<24} {}", if i == 0 { "Required content" } else { "" }, pattern);
+    }
+}
+
+/// Rules of the .rarscanignore of `root_dir` followed by the ones of `ignore_file`, `None` when there are none.
+fn load_ignore_rules(root_dir: &Path, ignore_file: Option<&Path>) -> anyhow::Result<Option<IgnoreRules>> {
+    let mut rules = IgnoreRules::new(root_dir);
+    let default = root_dir.join(ignore::DEFAULT_IGNORE_FILE);
+    if default.is_file() {
+        rules.add_file(&default)?;
+    }
+    if let Some(path) = ignore_file {
+        rules.add_file(path)?;
+    }
+    Ok((!rules.is_empty()).then_some(rules))
+}
+
+/// Gives an id to the archives recorded by a state file from before they had one, the ones that are still there.
+fn identify_recorded(state: &mut StateFile) {
+    if !state.unidentified() {
+        return;
+    }
+    let archives = state.unidentified_archives();
+    let mut identified = 0;
+    for path in &archives {
+        let id = Archive::open(path, &ArchiveNaming::default()).and_then(|archive| Ok(Fingerprint::of(&archive)?));
+        if let (Ok(id), Ok(key)) = (id, FileKey::of(path)) {
+            state.set_seen(&id.to_string(), path, key);
+            identified += 1;
+        }
+    }
+    state.set_identified();
+    log::info!(
+        "Migrated the state file to archive ids, {} of {} archives identified.",
+        identified,
+        archives.len()
+    );
+}
+
+/// Loads the state file at `path`, dropping what's outdated when its compaction is due. With --run-as it's loaded
+/// after the switch: the file is replaced when it's saved rather than written through a handle opened before, so
+/// `run_as` must be able to write its directory, which is checked before anything else.
+fn load_state(
+    path: PathBuf,
+    reset: bool,
+    verified_max_age: Duration,
+    run_as: Option<&RunAs>,
+) -> anyhow::Result<StateFile> {
+    let mut state = StateFile::load(path, reset)?;
+    if let Some(run_as) = run_as {
+        state
+            .check_writable()
+            .with_context(|| format!("the state file can't be saved as {}, refusing to go on", run_as))?;
+    }
+    state.set_verified_max_age(verified_max_age);
+    identify_recorded(&mut state);
+    let now = SystemTime::now();
+    if state.compaction_due(now) {
+        let dropped = state.compact(now);
+        log::info!("Compacted the state file, {} outdated entries dropped.", dropped);
+    }
+    Ok(state)
+}
+
+/// Resolves the path given to `one` to the root archive of its set.
+fn resolve_single(path: &Path) -> anyhow::Result<PathBuf> {
+    fs::metadata(path).with_context(|| format!("resolve '{}'", path.display()))?;
+    Ok(paths::canonical(&unrar::Archive::new(path).first_part()))
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let mut args = match config::expand(std::env::args_os().collect()) {
+        Ok(args) => Args::parse_from(args),
+        Err(e) => usage_error(e),
+    };
+    // Explaining changes nothing, like a dry-run, and has nothing to report to the outside.
+    if matches!(args.command, Some(Command::Explain { .. })) {
+        args.dry_run = true;
+        args.event_socket = None;
+        args.output = OutputFormat::Log;
+        args.control_socket = None;
+        args.changelog = None;
+        #[cfg(feature = "http")]
+        {
+            args.http_status = None;
+        }
+        #[cfg(feature = "telemetry")]
+        {
+            args.otlp_endpoint = None;
+        }
+    }
+
+    let timezone = match &args.timezone {
+        Some(timezone) => timezone.as_str(),
+        None if args.local_time => "local",
+        None => "utc",
+    };
+    // Looked up first, the offset can't be determined soundly once other threads exist. Only a fallback for when
+    // neither TZ nor /etc/localtime give the rules of the local time zone.
+    let has_window = args.active_hours.is_some() || args.active_days.is_some();
+    // The window is in local time unless a zone is given.
+    let window_timezone = args.timezone.as_deref().unwrap_or("local");
+    let local_offset =
+        (timezone == "local" || has_window && window_timezone == "local").then(datetime::startup_local_offset);
+    let offset = local_offset.clone().and_then(Result::ok);
+    let zone = TimeZone::resolve(timezone, offset).unwrap_or_else(|e| usage_error(e));
+    let window = has_window.then(|| {
+        let zone = TimeZone::resolve(window_timezone, offset).unwrap_or_else(|e| usage_error(e));
+        ActiveWindow::new(args.active_hours, args.active_days, zone)
+    });
+    let context = TimeContext {
+        zone,
+        format: args.time_format.clone(),
+    };
+    TIME_CONTEXT.set(context).expect("time context already set");
+    let zone = &time_context().zone;
+
+    let mut logger = Logger::new(args.log_level).with_time_zone(zone.clone());
+    #[cfg(feature = "tui")]
+    let capture = Arc::new(Capture::default());
+    #[cfg(feature = "tui")]
+    if args.tui {
+        logger = logger.with_capture(capture.clone());
+    }
+    let json_output = matches!(
+        &args.command,
+        Some(Command::Estimate {
+            format: EstimateFormat::Json,
+            ..
+        })
+    );
+    let worker = matches!(&args.command, Some(Command::ExtractWorker { .. }));
+    if args.no_stderr || json_output {
+        logger = logger.without_console();
+    } else if args.output == OutputFormat::Ndjson || worker {
+        logger = logger.on_stderr();
+    }
+    if let Some(path) = &args.log_file {
+        let rotation = Rotation {
+            max_size: args.log_max_size,
+            keep: args.log_keep,
+        };
+        logger = logger
+            .with_file(path, rotation)
+            .with_context(|| format!("open log file '{}'", path.display()))?;
+    }
+    logger.init().expect("unable to install logging");
+    if let Some(Command::ExtractWorker {
+        archive,
+        dest,
+        read,
+        max_written,
+        stream_budget,
+    }) = &args.command
+    {
+        return Ok(sandbox::run_worker(
+            archive,
+            dest,
+            read,
+            *max_written,
+            *stream_budget,
+            &worker_sandbox(&args),
+        ));
+    }
+    if let (Some(Err(e)), true) = (local_offset, zone.is_utc()) {
+        log::warn!("Unable to determine the local time zone, using UTC: {}", e);
+    }
+    let budget = fds::init(args.max_open_files);
+    match fds::open_files_limit() {
+        Some(limit) => log::info!("Open files limit is {}, keeping at most {} open.", limit, budget),
+        None => log::info!("Open files limit is unknown, keeping at most {} open.", budget),
+    }
+
+    if let Some(Command::Setup {
+        dir,
+        yes,
+        schedule,
+        every,
+        config_path,
+    }) = &args.command
+    {
+        let answers = setup::Answers {
+            dir: dir.clone(),
+            remove_after_hours: args.remove_after_hours,
+            dest: args.dest_template.clone(),
+            schedule: *schedule,
+            every: *every,
+            config_path: config_path.clone(),
+        };
+        let naming = ArchiveNaming::new(args.root_pattern.clone(), args.part_pattern.clone());
+        setup::run(answers, *yes, &naming)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Doctor) = &args.command {
+        drop_privileges(&args)?;
+        return Ok(if doctor::run()? {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        });
+    }
+    #[cfg(feature = "self-update")]
+    if let Some(Command::SelfUpdate { check_update }) = &args.command {
+        update::run(*check_update)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::State {
+        command: StateCommand::Stats { dir },
+    }) = &args.command
+    {
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        StateFile::load(state_file, args.reset_state)?.print_stats();
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::State {
+        command: StateCommand::List { dir, ids },
+    }) = &args.command
+    {
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        let mut state = StateFile::load(state_file, args.reset_state)?;
+        identify_recorded(&mut state);
+        for (path, id) in state.archive_ids() {
+            match ids {
+                true => println!("{} {}", id, path.display()),
+                false => println!("{}", path.display()),
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::FixOwnership { dir, owner, whole_dir }) = &args.command {
+        let dir = &paths::canonical(dir);
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        let state = match state_file.exists() {
+            true => Some(StateFile::load(state_file, args.reset_state)?),
+            false => None,
+        };
+        let paths = ownership::paths_to_check(dir, state.as_ref(), *whole_dir).context("list the extracted files")?;
+        let summary = ownership::fix(&paths, owner.uid(), owner.gid(), args.dry_run);
+        summary.log(&owner.to_string(), args.dry_run);
+        return Ok(match summary.is_clean(args.dry_run) {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        });
+    }
+
+    if let Some(Command::Audit {
+        command: Some(AuditCommand::Grep { pattern }),
+        ..
+    }) = &args.command
+    {
+        let Some(path) = &args.audit_log else {
+            usage_error("audit grep needs the --audit-log to read".into());
+        };
+        let pattern = Regex::new(pattern).unwrap_or_else(|e| usage_error(format!("invalid pattern: {}", e)));
+        return Ok(match audit::grep(path, &pattern)? {
+            0 => ExitCode::FAILURE,
+            _ => ExitCode::SUCCESS,
+        });
+    }
+
+    if let Some(Command::Ctl { json, command }) = &args.command {
+        let Some(socket) = &args.control_socket else {
+            usage_error("ctl needs the --control-socket of the running instance".into());
+        };
+        let request = command.request();
+        let response = ctl::send(socket, &request)?;
+        match json {
+            true => println!("{}", response),
+            false => ctl::print_response(&request, &response),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Command::MapRename { archive, old, new }) = &args.command {
+        drop_privileges(&args)?;
+        let naming = ArchiveNaming::new(args.root_pattern.clone(), args.part_pattern.clone())
+            .with_parts_across_dirs(args.parts_across_dirs);
+        let mut archive = Archive::open(resolve_single(archive)?, &naming).context("archive open")?;
+        if args.flatten_single_dir {
+            archive.flatten_single_dir();
+        }
+        let mut roots: Vec<PathBuf> = archive.path.parent().map(Path::to_path_buf).into_iter().collect();
+        roots.extend(args.map_root.iter().cloned());
+        renamemap::map_rename(&archive, old, new, &roots)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let markers = InProgressMarkers::new(&args.in_progress_marker).unwrap_or_else(|e| usage_error(format!("{:#}", e)));
+    if args.print_config {
+        print_config(&args, &markers);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let remove_after = args.remove_after_hours.map(|h| Duration::from_secs(60 * 60 * h));
+    let verified_max_age = Duration::from_secs(24 * 60 * 60 * args.verified_max_age_days);
+
+    let mut events = Events::new();
+    if let Some(path) = &args.event_socket {
+        events = events.with_socket(path)?;
+    }
+    if args.output == OutputFormat::Ndjson {
+        events = events.with_stdout();
+    }
+
+    if let Some(Command::Audit {
+        command: None,
+        dir: Some(dir),
+        budget,
+        byte_budget,
+    }) = &args.command
+    {
+        drop_privileges(&args)?;
+        let state_writer = args.run_as.clone();
+        let dir = &paths::canonical(dir);
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        if !state_file.exists() {
+            anyhow::bail!("no state file at '{}', nothing was verified", state_file.display());
+        }
+        let mut state = load_state(state_file, args.reset_state, verified_max_age, state_writer.as_ref())?;
+        let budget = integrity::Budget {
+            time: *budget,
+            bytes: *byte_budget,
+        };
+        let summary = integrity::audit(&mut state, dir, budget);
+        summary.log();
+        for corruption in &summary.corrupt {
+            events.emit(Event::Corruption {
+                path: &corruption.path,
+                archive: corruption.archive.as_ref().map(|(archive, _)| archive.as_path()),
+                expected: corruption.expected,
+                found: corruption.found,
+            });
+        }
+        if !args.dry_run {
+            state.save()?;
+        }
+        return Ok(match summary.is_clean() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::from(CORRUPTION_FOUND),
+        });
+    }
+    #[cfg(feature = "http")]
+    let status_server = args.http_status.as_deref().map(StatusServer::bind).transpose()?;
+    let ctl_socket = match &args.control_socket {
+        Some(path) => CtlSocket::bind(path)?,
+        None => None,
+    };
+    #[cfg(feature = "telemetry")]
+    let tracer = args
+        .otlp_endpoint
+        .as_deref()
+        .map(|url| Tracer::new(url).unwrap_or_else(|e| usage_error(e.to_string())));
+    drop_privileges(&args)?;
+    let state_writer = args.run_as.clone().filter(|_| !args.dry_run);
+    let sandbox = args.sandbox.then(|| run_sandbox(&args)).flatten();
+
+    let mut q = UnarchiveQueue::new(args.dry_run, remove_after, events)
+        .with_naming(
+            ArchiveNaming::new(args.root_pattern, args.part_pattern).with_parts_across_dirs(args.parts_across_dirs),
+        )
+        .with_remove_empty_archives(args.remove_empty_archives)
+        .with_flatten_single_dir(args.flatten_single_dir)
+        .with_skip_in_use(args.skip_in_use)
+        .with_shorten_long_names(args.shorten_long_names)
+        .with_name_matching(args.name_matching)
+        .with_prefetch_headers(!args.no_prefetch)
+        .with_follow_symlinks(args.follow_symlinks)
+        .with_full_scan(args.full_scan)
+        .with_auto_adopt(args.auto_adopt)
+        .with_verify_crc(args.verify_crc, args.revalidate)
+        .with_fsync(args.fsync)
+        .with_inherit_dir_perms(args.inherit_dir_perms)
+        .with_duplicate_policy(args.duplicate_policy)
+        .with_inode_margin(args.inode_margin)
+        .with_max_disk_usage(args.max_disk_usage)
+        .with_sandbox(sandbox)
+        .with_keep_newest(args.keep_newest)
+        .with_extracted_mtime(args.extracted_mtime)
+        .with_preallocate(args.preallocate)
+        .with_fast_store_copy(args.fast_store_copy)
+        .with_deep_dry_run(args.deep_dry_run.then_some(args.deep_dry_run_max_size))
+        .with_chain_grace(Duration::from_secs(60 * 60 * args.chain_grace_hours))
+        .with_no_remove(args.no_remove)
+        .with_flapping_policy(args.flapping_policy, args.flapping_threshold)
+        .with_panic_limit(args.panic_limit)
+        .with_nested_order(args.nested_order)
+        .with_unpack_tarballs(args.unpack_tarballs)
+        .with_map_roots(args.map_root.clone())
+        .with_in_progress_markers(markers);
+    if let Some(max_errors) = args.max_device_errors {
+        q = q.with_max_device_errors(max_errors);
+    }
+    if let Some(window) = window {
+        q = q.with_active_window(window, args.wait_for_window);
+    }
+    q = q.with_limits(Limits {
+        max_unpacked_size: args.max_unpacked_size.unwrap_or(DEFAULT_MAX_UNPACKED_SIZE),
+        max_entries: args.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES),
+        given: args.max_unpacked_size.is_some() || args.max_entries.is_some(),
+    });
+    if let Some(path) = &args.changelog {
+        q = q.with_changelog(path);
+    }
+    if let Some(path) = args.audit_log.as_ref().filter(|_| !args.dry_run) {
+        if args.log_file.as_ref() == Some(path) {
+            usage_error("--audit-log can't be the --log-file, which gets rotated".into());
+        }
+        q = q.with_audit_log(AuditLog::open(path, args.audit_format, args.audit_fsync)?);
+    }
+    if let Some(dir) = &args.backup_parts_to {
+        q = q.with_backups(Backups::new(dir.clone(), time_context().zone.clone()));
+        if let Some(days) = args.backup_retention {
+            q.purge_backups(Duration::from_secs(days * 24 * 60 * 60));
+        }
+    }
+    if let Some(ffprobe) = args.probe_media {
+        let timeout = Duration::from_secs(args.probe_timeout_secs);
+        q = q.with_media_prober(MediaProber::new(ffprobe, timeout), args.require_valid_media);
+    }
+    q = q.with_dir_lock_wait(Duration::from_secs(args.dir_lock_wait_secs));
+    q = q.with_fs_batcher(FsBatcher::new(Arc::new(RealFs), args.fs_jobs));
+    if !args.require_content.is_empty() {
+        let content = RequiredContent::new(&args.require_content).unwrap_or_else(|e| usage_error(format!("{:#}", e)));
+        q = q.with_required_content(content);
+    }
+    if let (Some(command), Some(dir)) = (&args.scan_command, &args.quarantine_dir) {
+        let timeout = Duration::from_secs(args.scan_timeout_secs);
+        q = q.with_scanner(Scanner::new(command.clone(), args.scan_jobs, timeout, dir.clone()));
+    }
+    #[cfg(feature = "http")]
+    if let Some(server) = status_server {
+        q = q.with_status_server(server);
+    }
+    if let Some(socket) = ctl_socket {
+        q = q.with_ctl_socket(socket);
+    }
+    #[cfg(feature = "telemetry")]
+    if let Some(tracer) = tracer {
+        q = q.with_tracer(tracer);
+    }
+    if args.detect_fakes {
+        let detector = match &args.fake_rules {
+            Some(path) => FakeDetector::load(path)?,
+            None => FakeDetector::default(),
+        };
+        q = q.with_fake_detector(detector);
+    }
+    if let Some(template) = args.dest_template {
+        q = q.with_dest_template(template);
+    }
+    if let Some(threshold) = args.slow_threshold {
+        q = q.with_slow_threshold(threshold);
+    }
+    if let Some(threshold) = args.suspicious_ratio {
+        q = q.with_suspicious_ratio(threshold);
+    }
+    if let Some(program) = &args.removal_gate {
+        q = q.with_removal_gate(RemovalGate::new(
+            program,
+            Duration::from_secs(args.removal_gate_timeout_secs),
+        ));
+    }
+    if args.max_remove_files.is_some() || args.max_remove_bytes.is_some() {
+        q = q.with_removal_cap(RemovalCap::new(args.max_remove_files, args.max_remove_bytes));
+    }
+    if let Some(tool) = &args.recovery_tool {
+        q = q.with_recovery_tool(tool);
+    }
+    if let Some(create) = args.snapshot_before_remove.clone() {
+        let mut snapshots = Snapshots::new(create, args.snapshot_best_effort);
+        if let (Some(retention), Some(delete)) = (args.snapshot_retention, args.snapshot_delete.clone()) {
+            snapshots = snapshots.with_retention(retention, delete);
+        }
+        q = q.with_snapshots(snapshots);
+    }
+    if let Some(Command::Adopt { dir }) = &args.command {
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        q = q.with_state_file(load_state(
+            state_file,
+            args.reset_state,
+            verified_max_age,
+            state_writer.as_ref(),
+        )?);
+        if let Some(rules) = load_ignore_rules(dir, args.ignore_file.as_deref())? {
+            q = q.with_ignore_rules(rules);
+        }
+        q.find_rar_files(dir)?;
+        q.adopt_all()?;
+        q.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::ImportUnpackerr { dir, clean }) = &args.command {
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        q = q.with_state_file(load_state(
+            state_file,
+            args.reset_state,
+            verified_max_age,
+            state_writer.as_ref(),
+        )?);
+        if let Some(rules) = load_ignore_rules(dir, args.ignore_file.as_deref())? {
+            q = q.with_ignore_rules(rules);
+        }
+        // The checksums are what tells a complete extraction of unpackerr from one it was interrupted in.
+        q = q.with_verify_crc(true, args.revalidate);
+        q.find_rar_files(dir)?;
+        let import = q.import_unpackerr(*clean)?;
+        q.finish();
+        import.log();
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Clean { dir, .. }) = &args.command {
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        let state = load_state(state_file, args.reset_state, verified_max_age, state_writer.as_ref())?;
+        let removals = state.pending_removals().clone();
+        q = q.with_state_file(state);
+        q.apply_removals(&removals)?;
+        q.prune_snapshots();
+        q.finish();
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Estimate {
+        dir,
+        format,
+        assume_throughput,
+    }) = &args.command
+    {
+        let dir = &paths::canonical(dir);
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => dir.join(state::DEFAULT_STATE_FILE),
+        };
+        q = q.with_state_file(load_state(
+            state_file,
+            args.reset_state,
+            verified_max_age,
+            state_writer.as_ref(),
+        )?);
+        let rules = RemoveAfterRules::new(dir, args.remove_after_for);
+        q = q.with_remove_after_rules(rules.unwrap_or_else(|e| usage_error(e)));
+        if let Some(rules) = load_ignore_rules(dir, args.ignore_file.as_deref())? {
+            q = q.with_ignore_rules(rules);
+        }
+        q.find_rar_files(dir)?;
+        q.estimate(dir, *assume_throughput)?.print(*format);
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Explain { path, root }) = &args.command {
+        let path = resolve_single(path)?;
+        let root = match root {
+            Some(root) => paths::canonical(root),
+            None => path.parent().expect("no parent path").to_path_buf(),
+        };
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => root.join(state::DEFAULT_STATE_FILE),
+        };
+        if state_file.exists() {
+            q = q.with_state_file(StateFile::load(state_file, false)?);
+        }
+        let rules = RemoveAfterRules::new(&root, args.remove_after_for);
+        q = q.with_remove_after_rules(rules.unwrap_or_else(|e| usage_error(e)));
+        if let Some(rules) = load_ignore_rules(&root, args.ignore_file.as_deref())? {
+            q = q.with_ignore_rules(rules);
+        }
+        q.explain(&path)?.print();
+        return Ok(ExitCode::SUCCESS);
+    }
+    let single = match &args.command {
+        Some(Command::One { path }) => {
+            if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("rar")) {
+                usage_error(format!("'{}' is not a .rar file", path.display()));
+            }
+            Some(path.as_path())
+        }
+        _ => {
+            let root_dir = args.root_dir.as_deref().expect("root_dir is required");
+            match fs::metadata(root_dir) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    usage_error(format!("root '{}' does not exist", root_dir.display()))
+                }
+                Err(e) => return Err(e).with_context(|| format!("stat root '{}'", root_dir.display())),
+                // A rar given as the root is processed on its own, like `one` does.
+                Ok(md) if md.is_file() && is_rar_file(root_dir) => Some(root_dir),
+                Ok(md) if !md.is_dir() => {
+                    usage_error(format!("root '{}' must be a directory or rar file", root_dir.display()))
+                }
+                Ok(_) => None,
+            }
+        }
+    };
+    if let Some(path) = single {
+        let path = resolve_single(path)?;
+        let state_file = match &args.state_file {
+            Some(state_file) => state_file.clone(),
+            None => path.parent().expect("no parent path").join(state::DEFAULT_STATE_FILE),
+        };
+        q = q.with_state_file(load_state(
+            state_file,
+            args.reset_state,
+            verified_max_age,
+            state_writer.as_ref(),
+        )?);
+        let rules = RemoveAfterRules::new(path.parent().expect("no parent path"), args.remove_after_for);
+        q = q.with_remove_after_rules(rules.unwrap_or_else(|e| usage_error(e)));
+        if !q.in_active_window() {
+            return Ok(ExitCode::from(OUTSIDE_WINDOW));
+        }
+        let outcome = q.process_single(&path)?;
+        let removals = q.take_removals();
+        if q.in_active_window() {
+            q.apply_removals(&removals)?;
+        }
+        q.prune_snapshots();
+        q.finish();
+        if args.check && q.has_pending_changes() {
+            return Ok(ExitCode::from(CHANGES_PENDING));
+        }
+        if q.removal_capped() {
+            return Ok(ExitCode::from(REMOVAL_CAPPED));
+        }
+        if q.outside_window() {
+            return Ok(ExitCode::from(OUTSIDE_WINDOW));
+        }
+        return Ok(match outcome {
+            Outcome::Extracted | Outcome::AlreadyExtracted | Outcome::InvalidPayload => ExitCode::SUCCESS,
+            Outcome::Failed if q.destination_failed() => ExitCode::from(DESTINATION_FAILED),
+            Outcome::Skipped
+            | Outcome::Deferred
+            | Outcome::Vanished
+            | Outcome::Failed
+            | Outcome::Repairable
+            | Outcome::Downloading
+            | Outcome::Quarantined
+            | Outcome::MissingContent => ExitCode::from(2),
+            Outcome::Suspended => ExitCode::from(DEVICE_SUSPENDED),
+        });
+    }
+
+    let root_dir = &paths::canonical(args.root_dir.as_deref().expect("root_dir is required"));
+    let state_file = match &args.state_file {
+        Some(state_file) => state_file.clone(),
+        None => root_dir.join(state::DEFAULT_STATE_FILE),
+    };
+    if let Some(budget) = args.memory_budget {
+        q = q.with_memory_budget(budget, state_file.with_extension("queue"));
+    }
+    q = q.with_state_file(load_state(
+        state_file,
+        args.reset_state,
+        verified_max_age,
+        state_writer.as_ref(),
+    )?);
+    let rules = RemoveAfterRules::new(root_dir, args.remove_after_for);
+    q = q.with_remove_after_rules(rules.unwrap_or_else(|e| usage_error(e)));
+    if let Some(rules) = load_ignore_rules(root_dir, args.ignore_file.as_deref())? {
+        q = q.with_ignore_rules(rules);
+    }
+    // Started last, a usage error above must not leave the terminal on the dashboard.
+    #[cfg(feature = "tui")]
+    let dashboard = match args.tui {
+        true => Dashboard::start(capture).context("start the dashboard")?,
+        false => None,
+    };
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = &dashboard {
+        q = q.with_dashboard(dashboard.sink(), dashboard.control());
+    }
+    if !q.in_active_window() {
+        #[cfg(feature = "tui")]
+        drop(dashboard);
+        return Ok(ExitCode::from(OUTSIDE_WINDOW));
+    }
+    q.find_rar_files(root_dir)?;
+    q.replay_ctl()?;
+    if args.only_incomplete_releases {
+        q.retain_incomplete_releases()?;
+    }
+    while q.process_next()? {}
+    // The window may have closed during the last archive.
+    let in_window = q.in_active_window();
+    let mut removals = q.take_removals();
+    removals.extend(q.leftover_removals());
+    if in_window {
+        q.apply_removals(&removals)?;
+        // What the removals freed may make room for the archives over --max-disk-usage.
+        while q.in_active_window() && q.retry_over_disk_usage()? {
+            while q.process_next()? {}
+            let removals = q.take_removals();
+            q.apply_removals(&removals)?;
+        }
+
+        if q.removes_anything() {
+            q.find_cruft(root_dir)?;
+        }
+        if let (Some(after), Some(to)) = (args.archive_extracted_after, &args.archive_extracted_to) {
+            q.tier_extracted(root_dir, after, to)?;
+        }
+        if args.remove_empty_dirs && (q.removes_anything() || args.archive_extracted_after.is_some()) {
+            q.remove_empty_dirs(root_dir)?;
+        }
+    }
+    q.prune_snapshots();
+    // The summary goes to the console once the terminal is given back.
+    #[cfg(feature = "tui")]
+    drop(dashboard);
+    q.finish();
+
+    if args.expect_archives && q.found_no_archives() {
+        return Ok(ExitCode::from(NO_ARCHIVES));
+    }
+    if args.check && q.has_pending_changes() {
+        return Ok(ExitCode::from(CHANGES_PENDING));
+    }
+    if q.device_suspended() {
+        return Ok(ExitCode::from(DEVICE_SUSPENDED));
+    }
+    if q.destination_failed() {
+        return Ok(ExitCode::from(DESTINATION_FAILED));
+    }
+    if q.archive_failed() {
+        return Ok(ExitCode::from(ARCHIVE_FAILED));
+    }
+    if q.removal_capped() {
+        return Ok(ExitCode::from(REMOVAL_CAPPED));
+    }
+    if q.outside_window() {
+        return Ok(ExitCode::from(OUTSIDE_WINDOW));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_past_4_gib_keep_their_value() {
+        const SIZE: u64 = 6 << 30;
+        assert_eq!(format_size(SIZE), "6.0 GiB");
+        assert_eq!(format_size(SIZE + 1), "6.0 GiB");
+        assert_eq!(format_size(3 << 40), "3.0 TiB");
+        assert_eq!(parse_size("6g"), Ok(SIZE));
+        assert_eq!(parse_size("6.5GiB"), Ok(SIZE + (512 << 20)));
+        assert_eq!(compression_ratio(1 << 30, SIZE), 6.0);
+        let max_written = (SIZE as f64 * MAX_WRITTEN_FACTOR) as u64 + MAX_WRITTEN_SLACK;
+        assert!(max_written > SIZE);
+
+        let mut cap = RemovalCap::new(None, Some(SIZE));
+        assert!(cap.admit(1, 5 << 30));
+        assert!(!cap.admit(1, 2 << 30));
+        assert!(cap.admit(1, 1 << 30));
+    }
 }
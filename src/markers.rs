@@ -0,0 +1,83 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use glob::{MatchOptions, Pattern};
+
+/// Files download clients leave next to a set while it's incomplete: Firefox, qBittorrent, rtorrent and the
+/// `.unpackignore` some tools drop to keep unpackers away.
+pub const DEFAULT_MARKERS: &[&str] = &["*.parts", "*.!qB", "*.part", ".unpackignore"];
+
+/// Glob patterns matched against the file names in the directory of an archive, a match means a download is still
+/// writing to it.
+#[derive(Debug, Clone)]
+pub struct InProgressMarkers {
+    patterns: Vec<Pattern>,
+}
+
+impl InProgressMarkers {
+    /// The default markers followed by `extra`.
+    pub fn new(extra: &[String]) -> anyhow::Result<InProgressMarkers> {
+        let patterns = DEFAULT_MARKERS
+            .iter()
+            .copied()
+            .chain(extra.iter().map(String::as_str))
+            .map(|pattern| Pattern::new(pattern).with_context(|| format!("invalid marker pattern '{}'", pattern)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(InProgressMarkers { patterns })
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.patterns.iter().map(Pattern::as_str)
+    }
+
+    /// The first file of `dir` matching a marker, `None` when there's none or `dir` can't be read.
+    pub fn find(&self, dir: &Path) -> Option<PathBuf> {
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        for entry in fs::read_dir(dir).ok()?.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if let Some(pattern) = self
+                .patterns
+                .iter()
+                .find(|pattern| pattern.matches_with(&name, options))
+            {
+                log::debug!("'{}' matches the in-progress marker '{}'.", name, pattern.as_str());
+                return Some(entry.path());
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_markers_match_partial_downloads() {
+        let markers = InProgressMarkers::new(&["*.crdownload".to_string()]).unwrap();
+        let options = MatchOptions {
+            case_sensitive: false,
+            ..MatchOptions::new()
+        };
+        let matches = |name: &str| {
+            markers
+                .patterns
+                .iter()
+                .any(|pattern| pattern.matches_with(name, options))
+        };
+        assert!(matches("show.part3.rar.!qB"));
+        assert!(matches("show.r07.part"));
+        assert!(matches("show.rar.PARTS"));
+        assert!(matches(".unpackignore"));
+        assert!(matches("movie.mkv.crdownload"));
+        assert!(!matches("show.part3.rar"));
+        assert!(InProgressMarkers::new(&["[".to_string()]).is_err());
+    }
+}
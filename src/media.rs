@@ -0,0 +1,159 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::fds;
+
+/// Files probed at the same time.
+const PARALLEL_PROBES: usize = 4;
+
+const VIDEO_EXTENSIONS: &[&str] = &["avi", "m4v", "mkv", "mov", "mp4", "webm"];
+const AUDIO_EXTENSIONS: &[&str] = &["flac", "m4a", "mp3"];
+
+/// Boxes an MP4 file can start with.
+const MP4_BOXES: &[&[u8; 4]] = &[b"ftyp", b"moov", b"mdat", b"free", b"skip", b"wide"];
+
+pub fn is_media_file(path: &Path) -> bool {
+    extension(path)
+        .is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.as_str()) || AUDIO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+fn extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_ascii_lowercase())
+}
+
+/// Checks that extracted media files are readable containers, with ffprobe when given or by looking at their headers.
+pub struct MediaProber {
+    ffprobe: Option<PathBuf>,
+    timeout: Duration,
+}
+
+impl MediaProber {
+    pub fn new(ffprobe: Option<PathBuf>, timeout: Duration) -> MediaProber {
+        MediaProber { ffprobe, timeout }
+    }
+
+    /// Probes the media files among `files`, a few at a time. Returns the invalid ones with the reason.
+    pub fn probe_all(&self, files: &[PathBuf]) -> anyhow::Result<Vec<(PathBuf, String)>> {
+        let media: Vec<&PathBuf> = files.iter().filter(|path| is_media_file(path)).collect();
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..PARALLEL_PROBES.min(media.len()) {
+                scope.spawn(|| {
+                    while let Some(path) = media.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let result = self.probe(path);
+                        results
+                            .lock()
+                            .expect("results lock poisoned")
+                            .push(((*path).clone(), result));
+                    }
+                });
+            }
+        });
+        let mut invalid = Vec::new();
+        for (path, result) in results.into_inner().expect("results lock poisoned") {
+            if let Some(reason) = result? {
+                invalid.push((path, reason));
+            }
+        }
+        invalid.sort();
+        Ok(invalid)
+    }
+
+    /// Why the file isn't a valid container, `None` when it is.
+    fn probe(&self, path: &Path) -> anyhow::Result<Option<String>> {
+        match &self.ffprobe {
+            Some(ffprobe) => self.ffprobe(ffprobe, path),
+            None => Ok(check_header(path)),
+        }
+    }
+
+    fn ffprobe(&self, ffprobe: &Path, path: &Path) -> anyhow::Result<Option<String>> {
+        // Both ends of the stderr pipe until ffprobe is spawned, and the file it opens.
+        let _fds = fds::acquire(3);
+        let mut child = Command::new(ffprobe)
+            .args(["-v", "error", "-show_entries", "format=format_name", "-of", "csv=p=0"])
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("spawn ffprobe '{}'", ffprobe.display()))?;
+
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf);
+            let _ = tx.send(buf);
+        });
+
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().context("wait for ffprobe")? {
+                break status;
+            }
+            if started.elapsed() >= self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(Some(format!("ffprobe timed out after {}s", self.timeout.as_secs_f64())));
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+        let output = rx.recv_timeout(Duration::from_secs(1)).unwrap_or_default();
+        let output = String::from_utf8_lossy(&output);
+        let first_error = output.lines().next().unwrap_or_default().trim();
+        Ok(match (status.success(), first_error) {
+            (true, "") => None,
+            (true, error) => Some(error.to_string()),
+            (false, "") => Some(format!("ffprobe failed ({})", status)),
+            (false, error) => Some(error.to_string()),
+        })
+    }
+}
+
+/// Minimal sanity check of the container header, for when there's no ffprobe.
+fn check_header(path: &Path) -> Option<String> {
+    let mut start = [0; 12];
+    let read = {
+        let _fds = fds::acquire(1);
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Some(format!("cannot open: {}", e)),
+        };
+        let mut read = 0;
+        while read < start.len() {
+            match file.read(&mut start[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) => return Some(format!("cannot read: {}", e)),
+            }
+        }
+        read
+    };
+    if read == 0 {
+        return Some("empty file".into());
+    }
+    let start = &start[..read];
+    let valid = match extension(path).as_deref() {
+        Some("mkv" | "webm") => start.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]),
+        Some("mp4" | "m4v" | "m4a" | "mov") => start.len() >= 8 && MP4_BOXES.iter().any(|b| &start[4..8] == *b),
+        Some("avi") => start.len() == 12 && start.starts_with(b"RIFF") && &start[8..12] == b"AVI ",
+        Some("flac") => start.starts_with(b"fLaC"),
+        Some("mp3") => start.starts_with(b"ID3") || (start.len() >= 2 && start[0] == 0xff && start[1] & 0xe0 == 0xe0),
+        _ => true,
+    };
+    (!valid).then(|| "unrecognized container header".into())
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Whether `dir` is empty and looks like it should have a filesystem mounted on it: it's in /proc/mounts, as an
+/// automount that didn't come up would be, or it's in /etc/fstab but not mounted.
+pub fn looks_unmounted(dir: &Path) -> bool {
+    let empty = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none());
+    if !empty {
+        return false;
+    }
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+    let listed = |table: &str| fs::read_to_string(table).is_ok_and(|content| mount_points(&content).contains(&dir));
+    listed("/proc/mounts") || listed("/etc/fstab")
+}
+
+/// What is mounted on the deepest mount point above `path` according to /proc/mounts, such as `/dev/sdc1`.
+pub fn device_name(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).ok()?;
+    let table = fs::read_to_string("/proc/mounts").ok()?;
+    mounts(&table)
+        .into_iter()
+        .filter(|(_, point)| path.starts_with(point))
+        .max_by_key(|(_, point)| point.components().count())
+        .map(|(source, _)| source)
+}
+
+/// Filesystems whose directories may keep their mtime while entries are added to them, such as network mounts
+/// caching attributes, or FUSE filesystems passing on whatever their backend reports.
+const UNRELIABLE_DIR_MTIMES: &[&str] = &["nfs", "nfs4", "cifs", "smb3", "smbfs", "9p", "afs", "davfs", "ncpfs"];
+
+/// The mount the directory mtimes below `root` can't be trusted on: the one `root` is on, or one below it.
+pub fn unreliable_dir_mtimes(root: &Path) -> Option<PathBuf> {
+    let root = fs::canonicalize(root).ok()?;
+    let table = fs::read_to_string("/proc/mounts").ok()?;
+    let types = mount_types(&table);
+    let own = types
+        .iter()
+        .filter(|(point, _)| root.starts_with(point))
+        .max_by_key(|(point, _)| point.components().count());
+    own.into_iter()
+        .chain(types.iter().filter(|(point, _)| point.starts_with(&root)))
+        .find(|(_, fs_type)| UNRELIABLE_DIR_MTIMES.contains(&fs_type.as_str()) || fs_type.starts_with("fuse."))
+        .map(|(point, _)| point.clone())
+}
+
+/// Mount points and their filesystem type, for each line of a table formatted like /proc/mounts.
+fn mount_types(table: &str) -> Vec<(PathBuf, String)> {
+    table
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let point = PathBuf::from(unescape(fields.next()?));
+            Some((point, fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// Mount points listed in a table formatted like /proc/mounts and /etc/fstab.
+fn mount_points(table: &str) -> Vec<PathBuf> {
+    mounts(table).into_iter().map(|(_, point)| point).collect()
+}
+
+/// What is mounted and where, for each line of a mount table.
+fn mounts(table: &str) -> Vec<(String, PathBuf)> {
+    table
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let source = unescape(fields.next()?);
+            Some((source, PathBuf::from(unescape(fields.next()?))))
+        })
+        .collect()
+}
+
+/// Undoes the octal escapes of the whitespace and backslashes in the fields of a mount table, `\040` for a space.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|digits| digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        match octal {
+            Some(digits) if bytes[i] == b'\\' => {
+                out.push(digits.iter().fold(0u8, |n, d| n.wrapping_mul(8) + (d - b'0')));
+                i += 4;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mount_tables() {
+        let table = "# <fs> <dir> <type>\n/dev/sda1 / ext4 rw 0 0\n\nnas:/media /mnt/my\\040media nfs defaults 0 0\n";
+        assert_eq!(
+            mount_points(table),
+            vec![PathBuf::from("/"), PathBuf::from("/mnt/my media")]
+        );
+        assert_eq!(unescape("a\\134b\\01"), "a\\b\\01");
+        assert_eq!(
+            mount_types(table)[1],
+            (PathBuf::from("/mnt/my media"), "nfs".to_string())
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fmt,
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+/// How the names of the entries of an archive are compared to the files of its destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NameMatching {
+    /// As the filesystem of each destination compares them, found out with temporary files.
+    Auto,
+    /// Byte for byte.
+    Exact,
+    /// Regardless of case.
+    IgnoreCase,
+    /// Regardless of the Unicode normalization of accented letters, composed (NFC) or decomposed (NFD).
+    IgnoreNormalization,
+    /// Regardless of both.
+    IgnoreBoth,
+}
+
+/// Which names a filesystem takes for the same file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NameRules {
+    pub ignores_case: bool,
+    pub ignores_normalization: bool,
+}
+
+/// Accented Latin letters and their decomposition into a letter and a combining mark, by code point.
+#[rustfmt::skip]
+const DECOMPOSITIONS: &[(char, char, char)] = &[
+    ('À', 'A', '\u{300}'), ('Á', 'A', '\u{301}'), ('Â', 'A', '\u{302}'), ('Ã', 'A', '\u{303}'), ('Ä', 'A', '\u{308}'),
+    ('Å', 'A', '\u{30a}'), ('Ç', 'C', '\u{327}'), ('È', 'E', '\u{300}'), ('É', 'E', '\u{301}'), ('Ê', 'E', '\u{302}'),
+    ('Ë', 'E', '\u{308}'), ('Ì', 'I', '\u{300}'), ('Í', 'I', '\u{301}'), ('Î', 'I', '\u{302}'), ('Ï', 'I', '\u{308}'),
+    ('Ñ', 'N', '\u{303}'), ('Ò', 'O', '\u{300}'), ('Ó', 'O', '\u{301}'), ('Ô', 'O', '\u{302}'), ('Õ', 'O', '\u{303}'),
+    ('Ö', 'O', '\u{308}'), ('Ù', 'U', '\u{300}'), ('Ú', 'U', '\u{301}'), ('Û', 'U', '\u{302}'), ('Ü', 'U', '\u{308}'),
+    ('Ý', 'Y', '\u{301}'), ('à', 'a', '\u{300}'), ('á', 'a', '\u{301}'), ('â', 'a', '\u{302}'), ('ã', 'a', '\u{303}'),
+    ('ä', 'a', '\u{308}'), ('å', 'a', '\u{30a}'), ('ç', 'c', '\u{327}'), ('è', 'e', '\u{300}'), ('é', 'e', '\u{301}'),
+    ('ê', 'e', '\u{302}'), ('ë', 'e', '\u{308}'), ('ì', 'i', '\u{300}'), ('í', 'i', '\u{301}'), ('î', 'i', '\u{302}'),
+    ('ï', 'i', '\u{308}'), ('ñ', 'n', '\u{303}'), ('ò', 'o', '\u{300}'), ('ó', 'o', '\u{301}'), ('ô', 'o', '\u{302}'),
+    ('õ', 'o', '\u{303}'), ('ö', 'o', '\u{308}'), ('ù', 'u', '\u{300}'), ('ú', 'u', '\u{301}'), ('û', 'u', '\u{302}'),
+    ('ü', 'u', '\u{308}'), ('ý', 'y', '\u{301}'), ('ÿ', 'y', '\u{308}'), ('Ā', 'A', '\u{304}'), ('ā', 'a', '\u{304}'),
+    ('Ă', 'A', '\u{306}'), ('ă', 'a', '\u{306}'), ('Ą', 'A', '\u{328}'), ('ą', 'a', '\u{328}'), ('Ć', 'C', '\u{301}'),
+    ('ć', 'c', '\u{301}'), ('Ĉ', 'C', '\u{302}'), ('ĉ', 'c', '\u{302}'), ('Ċ', 'C', '\u{307}'), ('ċ', 'c', '\u{307}'),
+    ('Č', 'C', '\u{30c}'), ('č', 'c', '\u{30c}'), ('Ď', 'D', '\u{30c}'), ('ď', 'd', '\u{30c}'), ('Ē', 'E', '\u{304}'),
+    ('ē', 'e', '\u{304}'), ('Ĕ', 'E', '\u{306}'), ('ĕ', 'e', '\u{306}'), ('Ė', 'E', '\u{307}'), ('ė', 'e', '\u{307}'),
+    ('Ę', 'E', '\u{328}'), ('ę', 'e', '\u{328}'), ('Ě', 'E', '\u{30c}'), ('ě', 'e', '\u{30c}'), ('Ĝ', 'G', '\u{302}'),
+    ('ĝ', 'g', '\u{302}'), ('Ğ', 'G', '\u{306}'), ('ğ', 'g', '\u{306}'), ('Ġ', 'G', '\u{307}'), ('ġ', 'g', '\u{307}'),
+    ('Ģ', 'G', '\u{327}'), ('ģ', 'g', '\u{327}'), ('Ĥ', 'H', '\u{302}'), ('ĥ', 'h', '\u{302}'), ('Ĩ', 'I', '\u{303}'),
+    ('ĩ', 'i', '\u{303}'), ('Ī', 'I', '\u{304}'), ('ī', 'i', '\u{304}'), ('Ĭ', 'I', '\u{306}'), ('ĭ', 'i', '\u{306}'),
+    ('Į', 'I', '\u{328}'), ('į', 'i', '\u{328}'), ('İ', 'I', '\u{307}'), ('Ĵ', 'J', '\u{302}'), ('ĵ', 'j', '\u{302}'),
+    ('Ķ', 'K', '\u{327}'), ('ķ', 'k', '\u{327}'), ('Ĺ', 'L', '\u{301}'), ('ĺ', 'l', '\u{301}'), ('Ļ', 'L', '\u{327}'),
+    ('ļ', 'l', '\u{327}'), ('Ľ', 'L', '\u{30c}'), ('ľ', 'l', '\u{30c}'), ('Ń', 'N', '\u{301}'), ('ń', 'n', '\u{301}'),
+    ('Ņ', 'N', '\u{327}'), ('ņ', 'n', '\u{327}'), ('Ň', 'N', '\u{30c}'), ('ň', 'n', '\u{30c}'), ('Ō', 'O', '\u{304}'),
+    ('ō', 'o', '\u{304}'), ('Ŏ', 'O', '\u{306}'), ('ŏ', 'o', '\u{306}'), ('Ő', 'O', '\u{30b}'), ('ő', 'o', '\u{30b}'),
+    ('Ŕ', 'R', '\u{301}'), ('ŕ', 'r', '\u{301}'), ('Ŗ', 'R', '\u{327}'), ('ŗ', 'r', '\u{327}'), ('Ř', 'R', '\u{30c}'),
+    ('ř', 'r', '\u{30c}'), ('Ś', 'S', '\u{301}'), ('ś', 's', '\u{301}'), ('Ŝ', 'S', '\u{302}'), ('ŝ', 's', '\u{302}'),
+    ('Ş', 'S', '\u{327}'), ('ş', 's', '\u{327}'), ('Š', 'S', '\u{30c}'), ('š', 's', '\u{30c}'), ('Ţ', 'T', '\u{327}'),
+    ('ţ', 't', '\u{327}'), ('Ť', 'T', '\u{30c}'), ('ť', 't', '\u{30c}'), ('Ũ', 'U', '\u{303}'), ('ũ', 'u', '\u{303}'),
+    ('Ū', 'U', '\u{304}'), ('ū', 'u', '\u{304}'), ('Ŭ', 'U', '\u{306}'), ('ŭ', 'u', '\u{306}'), ('Ů', 'U', '\u{30a}'),
+    ('ů', 'u', '\u{30a}'), ('Ű', 'U', '\u{30b}'), ('ű', 'u', '\u{30b}'), ('Ų', 'U', '\u{328}'), ('ų', 'u', '\u{328}'),
+    ('Ŵ', 'W', '\u{302}'), ('ŵ', 'w', '\u{302}'), ('Ŷ', 'Y', '\u{302}'), ('ŷ', 'y', '\u{302}'), ('Ÿ', 'Y', '\u{308}'),
+    ('Ź', 'Z', '\u{301}'), ('ź', 'z', '\u{301}'), ('Ż', 'Z', '\u{307}'), ('ż', 'z', '\u{307}'), ('Ž', 'Z', '\u{30c}'),
+    ('ž', 'z', '\u{30c}'),
+];
+
+impl NameMatching {
+    /// The rules for `dir`, probed there with `Auto`.
+    pub fn rules(self, dir: &Path) -> NameRules {
+        let (ignores_case, ignores_normalization) = match self {
+            NameMatching::Auto => return NameRules::probe(dir),
+            NameMatching::Exact => (false, false),
+            NameMatching::IgnoreCase => (true, false),
+            NameMatching::IgnoreNormalization => (false, true),
+            NameMatching::IgnoreBoth => (true, true),
+        };
+        NameRules {
+            ignores_case,
+            ignores_normalization,
+        }
+    }
+}
+
+impl NameRules {
+    /// Finds out how the filesystem of `dir`, or of its closest existing parent, compares names: whether a file
+    /// created under one spelling answers to the others. Exact when no file can be created there. The directory
+    /// keeps its modification time, which tells the scans what changed.
+    pub fn probe(dir: &Path) -> NameRules {
+        let Some(dir) = dir.ancestors().find(|dir| dir.is_dir()) else {
+            return NameRules::default();
+        };
+        let modified = fs::metadata(dir).and_then(|md| md.modified());
+        let name = format!(".rarscan-probe-{}-\u{e9}", std::process::id());
+        let probe = dir.join(&name);
+        if File::options().write(true).create_new(true).open(&probe).is_err() {
+            log::debug!(
+                "Can't probe how '{}' compares names, comparing them exactly.",
+                dir.display()
+            );
+            return NameRules::default();
+        }
+        let answers = |spelling: String| fs::symlink_metadata(dir.join(spelling)).is_ok();
+        let rules = NameRules {
+            ignores_case: answers(name.to_ascii_uppercase()),
+            ignores_normalization: answers(name.replace('\u{e9}', "e\u{301}")),
+        };
+        let _ = fs::remove_file(&probe);
+        if let Ok(modified) = modified {
+            let _ = File::open(dir).and_then(|dir| dir.set_modified(modified));
+        }
+        log::debug!("'{}' compares names with {:?}.", dir.display(), rules);
+        rules
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !self.ignores_case && !self.ignores_normalization
+    }
+
+    /// What `name` is compared by, `None` when it isn't valid UTF-8 and only matches itself.
+    fn key(&self, name: &OsStr) -> Option<String> {
+        let name = name.to_str()?;
+        let mut key = String::with_capacity(name.len());
+        for c in name.chars() {
+            let decomposed = DECOMPOSITIONS
+                .binary_search_by_key(&c, |(composed, _, _)| *composed)
+                .ok()
+                .filter(|_| self.ignores_normalization);
+            match decomposed {
+                Some(i) => key.extend([DECOMPOSITIONS[i].1, DECOMPOSITIONS[i].2]),
+                None => key.push(c),
+            }
+        }
+        Some(match self.ignores_case {
+            true => key.to_lowercase(),
+            false => key,
+        })
+    }
+
+    pub fn same(&self, a: &OsStr, b: &OsStr) -> bool {
+        a == b || self.key(a).is_some_and(|key| Some(key) == self.key(b))
+    }
+
+    /// Where `dest` stores the entry `name` under another spelling, looked up in the listing of each directory on the
+    /// way. `None` when it's there as spelled or not at all.
+    pub fn find(&self, dest: &Path, name: &Path) -> io::Result<Option<PathBuf>> {
+        if self.is_exact() || fs::symlink_metadata(dest.join(name)).is_ok() {
+            return Ok(None);
+        }
+        let mut path = dest.to_path_buf();
+        for component in name.components() {
+            let Component::Normal(component) = component else {
+                path.push(component);
+                continue;
+            };
+            if fs::symlink_metadata(path.join(component)).is_ok() {
+                path.push(component);
+                continue;
+            }
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let stored = entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name())
+                .find(|stored| self.same(stored, component));
+            match stored {
+                Some(stored) => path.push(stored),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(path))
+    }
+
+    /// Pairs of `names` the filesystem takes for the same file although they differ, the first of the two comes first
+    /// in `names`.
+    pub fn collisions<'a>(&self, names: impl IntoIterator<Item = &'a Path>) -> Vec<(&'a Path, &'a Path)> {
+        let mut collisions = Vec::new();
+        if self.is_exact() {
+            return collisions;
+        }
+        let mut seen: HashMap<PathBuf, &Path> = HashMap::new();
+        for name in names {
+            let key: Option<PathBuf> = name
+                .components()
+                .map(|component| self.key(component.as_os_str()).map(PathBuf::from))
+                .collect();
+            let Some(key) = key else {
+                continue;
+            };
+            match seen.get(&key) {
+                Some(first) if *first != name => collisions.push((*first, name)),
+                Some(_) => {}
+                None => {
+                    seen.insert(key, name);
+                }
+            }
+        }
+        collisions
+    }
+}
+
+impl fmt::Display for NameRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ignores_case, self.ignores_normalization) {
+            (false, false) => write!(f, "exactly"),
+            (true, false) => write!(f, "regardless of case"),
+            (false, true) => write!(f, "regardless of normalization"),
+            (true, true) => write!(f, "regardless of case and normalization"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: NameRules = NameRules {
+        ignores_case: true,
+        ignores_normalization: true,
+    };
+
+    #[test]
+    fn decompositions_are_sorted() {
+        assert!(DECOMPOSITIONS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn names_compare_by_the_rules() {
+        let nfc = OsStr::new("Caf\u{e9}.mkv");
+        let nfd = OsStr::new("Cafe\u{301}.mkv");
+        assert!(!NameRules::default().same(nfc, nfd));
+        assert!(BOTH.same(nfc, nfd));
+        assert!(BOTH.same(nfc, OsStr::new("CAFE\u{301}.MKV")));
+        assert!(!BOTH.same(nfc, OsStr::new("Cafe.mkv")));
+        let case = NameRules {
+            ignores_case: true,
+            ignores_normalization: false,
+        };
+        assert!(case.same(OsStr::new("a.TXT"), OsStr::new("A.txt")));
+        assert!(!case.same(nfc, nfd));
+    }
+
+    #[test]
+    fn colliding_names_are_paired() {
+        let names = [
+            Path::new("Show/a.txt"),
+            Path::new("show/A.txt"),
+            Path::new("b\u{e9}.txt"),
+            Path::new("be\u{301}.txt"),
+            Path::new("c.txt"),
+        ];
+        assert_eq!(BOTH.collisions(names), vec![(names[0], names[1]), (names[2], names[3])]);
+        assert!(NameRules::default().collisions(names).is_empty());
+    }
+}
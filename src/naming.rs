@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+pub const DEFAULT_PART_PATTERN: &str = "part(\\d+).rar$";
+
+/// Rules used to tell root archives apart from the other parts of a multi-part set.
+///
+/// A file matching one of the root patterns is a root archive. Otherwise a file matching the part pattern is a part,
+/// and it's the root only when no root patterns are configured and its part number is 1. Any other `.rar` file is a
+/// root archive on its own.
+#[derive(Clone)]
+pub struct ArchiveNaming {
+    root_patterns: Vec<Regex>,
+    part_pattern: Regex,
+    custom_part_pattern: bool,
+    /// Whether the volumes missing next to the first one are looked for in the directories next to its own.
+    parts_across_dirs: bool,
+}
+
+/// Which of the rules a file name falls under.
+enum Classified<'a> {
+    /// Matches this root pattern.
+    Root(&'a str),
+    /// Matches the part pattern with this part number.
+    Part(u64),
+    Plain,
+}
+
+impl Default for ArchiveNaming {
+    fn default() -> ArchiveNaming {
+        ArchiveNaming {
+            root_patterns: Vec::new(),
+            part_pattern: Regex::new(DEFAULT_PART_PATTERN).unwrap(),
+            custom_part_pattern: false,
+            parts_across_dirs: false,
+        }
+    }
+}
+
+impl ArchiveNaming {
+    pub fn new(root_patterns: Vec<Regex>, part_pattern: Option<Regex>) -> ArchiveNaming {
+        let mut naming = ArchiveNaming {
+            root_patterns,
+            ..ArchiveNaming::default()
+        };
+        if let Some(part_pattern) = part_pattern {
+            naming.part_pattern = part_pattern;
+            naming.custom_part_pattern = true;
+        }
+        naming
+    }
+
+    pub fn with_parts_across_dirs(mut self, parts_across_dirs: bool) -> ArchiveNaming {
+        self.parts_across_dirs = parts_across_dirs;
+        self
+    }
+
+    pub fn parts_across_dirs(&self) -> bool {
+        self.parts_across_dirs
+    }
+
+    /// The flags giving another rarscan process the same naming, the extraction worker of --sandbox.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for pattern in &self.root_patterns {
+            args.extend(["--root-pattern".to_string(), pattern.as_str().to_string()]);
+        }
+        if self.custom_part_pattern {
+            args.extend(["--part-pattern".to_string(), self.part_pattern.as_str().to_string()]);
+        }
+        if self.parts_across_dirs {
+            args.push("--parts-across-dirs".to_string());
+        }
+        args
+    }
+
+    pub fn is_root_rar_file(&self, path: &Path) -> bool {
+        let file_name = path.file_name().and_then(|s| s.to_str()).expect("invalid file_name");
+        match self.classify(file_name) {
+            Classified::Root(_) => true,
+            Classified::Part(part_num) => self.root_patterns.is_empty() && part_num == 1,
+            Classified::Plain => file_name.ends_with(".rar"),
+        }
+    }
+
+    /// Why `path` is a root archive or not, for `explain`.
+    pub fn describe(&self, path: &Path) -> String {
+        let file_name = path.file_name().and_then(|s| s.to_str()).expect("invalid file_name");
+        let root = match self.is_root_rar_file(path) {
+            true => "root archive",
+            false => "not a root archive",
+        };
+        match self.classify(file_name) {
+            Classified::Root(pattern) => format!("{}, matches the root pattern '{}'", root, pattern),
+            Classified::Part(part_num) if self.root_patterns.is_empty() => format!(
+                "{}, part {} by the part pattern '{}'",
+                root,
+                part_num,
+                self.part_pattern.as_str()
+            ),
+            Classified::Part(part_num) => format!(
+                "{}, part {} by the part pattern '{}' and matches no root pattern",
+                root,
+                part_num,
+                self.part_pattern.as_str()
+            ),
+            Classified::Plain if self.root_patterns.is_empty() => format!("{}, matches no part pattern", root),
+            Classified::Plain => format!("{}, matches neither the root patterns nor the part pattern", root),
+        }
+    }
+
+    fn classify(&self, file_name: &str) -> Classified<'_> {
+        if let Some(re) = self.root_patterns.iter().find(|re| re.is_match(file_name)) {
+            return Classified::Root(re.as_str());
+        }
+        match self.part_number(file_name) {
+            Some(part_num) => Classified::Part(part_num),
+            None => Classified::Plain,
+        }
+    }
+
+    fn part_number(&self, file_name: &str) -> Option<u64> {
+        let caps = self.part_pattern.captures(file_name)?;
+        caps.get(1)?.as_str().parse().ok()
+    }
+
+    /// Glob matching every part of the set `path` belongs to, when a custom part pattern applies to it. The default
+    /// naming is left to unrar which also knows about the old `.r00` style.
+    pub fn parts_glob(&self, path: &Path) -> Option<PathBuf> {
+        if !self.custom_part_pattern {
+            return None;
+        }
+        let file_name = path.file_name()?.to_str()?;
+        let number = self.part_pattern.captures(file_name)?.get(1)?;
+        let pattern = format!(
+            "{}*{}",
+            glob::Pattern::escape(&file_name[..number.start()]),
+            glob::Pattern::escape(&file_name[number.end()..])
+        );
+        let dir = path.parent()?.to_string_lossy();
+        Some(Path::new(&glob::Pattern::escape(&dir)).join(pattern))
+    }
+
+    /// The part pattern when it was customized, files matched by `parts_glob` must also match it.
+    pub fn custom_part_pattern(&self) -> Option<&Regex> {
+        self.custom_part_pattern.then_some(&self.part_pattern)
+    }
+}
+
+/// Parses a regex for clap, reporting the position of syntax errors.
+pub fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| e.to_string())
+}
+
+/// Parses a part pattern for clap, which needs a capture group for the part number.
+pub fn parse_part_pattern(s: &str) -> Result<Regex, String> {
+    let re = parse_regex(s)?;
+    if re.captures_len() < 2 {
+        return Err("the pattern has no capture group, its first one must capture the part number".into());
+    }
+    Ok(re)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming(roots: &[&str], part: Option<&str>) -> ArchiveNaming {
+        ArchiveNaming::new(
+            roots.iter().map(|re| Regex::new(re).unwrap()).collect(),
+            part.map(|re| Regex::new(re).unwrap()),
+        )
+    }
+
+    #[test]
+    fn default_scheme() {
+        let naming = ArchiveNaming::default();
+        for (name, root) in [
+            ("movie.rar", true),
+            ("movie.part1.rar", true),
+            ("movie.part01.rar", true),
+            ("movie.part001.rar", true),
+            ("movie.part2.rar", false),
+            ("movie.part10.rar", false),
+            ("movie.r00", false),
+            ("movie.zip", false),
+            ("movie.part1.rar.txt", false),
+        ] {
+            assert_eq!(naming.is_root_rar_file(Path::new(name)), root, "{}", name);
+        }
+    }
+
+    #[test]
+    fn numbered_volumes_scheme() {
+        let naming = naming(&["\\.000\\.rar$"], Some("\\.(\\d{3})\\.rar$"));
+        for (name, root) in [
+            ("name.000.rar", true),
+            ("name.001.rar", false),
+            ("name.002.rar", false),
+            ("name.rar", true),
+        ] {
+            assert_eq!(naming.is_root_rar_file(Path::new(name)), root, "{}", name);
+        }
+    }
+
+    #[test]
+    fn vol_scheme() {
+        let naming = naming(&["\\.vol0*1\\+\\d+\\.rar$"], Some("\\.vol(\\d+)\\+\\d+\\.rar$"));
+        for (name, root) in [
+            ("name.vol01+02.rar", true),
+            ("name.vol03+04.rar", false),
+            ("name.rar", true),
+        ] {
+            assert_eq!(naming.is_root_rar_file(Path::new(name)), root, "{}", name);
+        }
+    }
+
+    #[test]
+    fn custom_part_pattern_keeps_part_one_as_root() {
+        let naming = naming(&[], Some("\\.(\\d{3})\\.rar$"));
+        assert!(naming.is_root_rar_file(Path::new("name.001.rar")));
+        assert!(!naming.is_root_rar_file(Path::new("name.002.rar")));
+    }
+
+    #[test]
+    fn parts_glob() {
+        assert_eq!(
+            ArchiveNaming::default().parts_glob(Path::new("/a/name.part1.rar")),
+            None
+        );
+
+        let naming = naming(&["\\.000\\.rar$"], Some("\\.(\\d{3})\\.rar$"));
+        assert_eq!(
+            naming.parts_glob(Path::new("/a/[x]/name.000.rar")),
+            Some(PathBuf::from("/a/[[]x[]]/name.*.rar"))
+        );
+        assert_eq!(naming.parts_glob(Path::new("/a/name.rar")), None);
+        assert!(naming.custom_part_pattern().is_some());
+    }
+
+    #[test]
+    fn invalid_regex_reports_position() {
+        let err = parse_regex("part(\\d+.rar$").unwrap_err();
+        assert!(err.contains("unclosed group"), "{}", err);
+        let err = parse_part_pattern("part\\d+\\.rar$").unwrap_err();
+        assert!(err.contains("no capture group"), "{}", err);
+        assert!(parse_part_pattern("part(\\d+)\\.rar$").is_ok());
+    }
+}
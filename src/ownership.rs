@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{privileges, state::StateFile, walk};
+
+/// What `fix-ownership` found and changed.
+#[derive(Debug, Default)]
+pub struct OwnershipSummary {
+    pub checked: u64,
+    /// Files and directories owned by someone else, with their uid and gid.
+    pub foreign: Vec<(PathBuf, u32, u32)>,
+    pub changed: u64,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl OwnershipSummary {
+    pub fn log(&self, owner: &str, dry_run: bool) {
+        for (path, uid, gid) in &self.foreign {
+            log::info!("-> '{}' is owned by {}:{}", path.display(), uid, gid);
+        }
+        for (path, error) in &self.failed {
+            log::error!("-> Could not change the owner of '{}': {}", path.display(), error);
+        }
+        log::info!(
+            "Checked {} paths, {} not owned by {}, {} {}.",
+            self.checked,
+            self.foreign.len(),
+            owner,
+            self.changed,
+            if dry_run { "would be changed" } else { "changed" }
+        );
+    }
+
+    /// Whether paths are left with another owner.
+    pub fn is_clean(&self, dry_run: bool) -> bool {
+        self.foreign.is_empty() || !dry_run && self.failed.is_empty()
+    }
+}
+
+/// The extracted files recorded by the state file and the directories holding them below `dir`, or everything below
+/// `dir` with `whole_dir`.
+pub fn paths_to_check(dir: &Path, state: Option<&StateFile>, whole_dir: bool) -> io::Result<Vec<PathBuf>> {
+    let files = match state.filter(|_| !whole_dir) {
+        Some(state) => state
+            .extracted()
+            .map(|(path, _)| path.to_path_buf())
+            .filter(|path| path.starts_with(dir))
+            .collect(),
+        None => walk::find_files(dir, false, |_| true)?,
+    };
+    let mut paths = BTreeSet::new();
+    for file in files {
+        paths.extend(
+            file.ancestors()
+                .skip(1)
+                .take_while(|parent| parent.starts_with(dir) && *parent != dir)
+                .map(Path::to_path_buf),
+        );
+        paths.insert(file);
+    }
+    Ok(paths.into_iter().collect())
+}
+
+/// Gives `paths` to `uid` and `gid`, only reports the ones owned by someone else in a dry-run. Links are changed
+/// themselves, never what they point to.
+pub fn fix(paths: &[PathBuf], uid: u32, gid: u32, dry_run: bool) -> OwnershipSummary {
+    let mut summary = OwnershipSummary::default();
+    for path in paths {
+        let Ok(md) = fs::symlink_metadata(path) else {
+            continue;
+        };
+        summary.checked += 1;
+        let (owner, group) = imp::owner(&md);
+        if (owner, group) == (uid, gid) {
+            continue;
+        }
+        summary.foreign.push((path.clone(), owner, group));
+        if dry_run {
+            summary.changed += 1;
+            continue;
+        }
+        match imp::chown(path, uid, gid) {
+            Ok(()) => summary.changed += 1,
+            Err(e) => summary.failed.push((path.clone(), e.to_string())),
+        }
+    }
+    summary
+}
+
+/// What kept `path` from being written when it belongs to another user: the file itself, or the directory it's to be
+/// created in. `None` when the process owns both or may write them anyway.
+pub fn foreign_owner(path: &Path) -> Option<(PathBuf, u32)> {
+    let uid = privileges::effective_uid();
+    if let Ok(md) = fs::symlink_metadata(path) {
+        let (owner, _) = imp::owner(&md);
+        let denied = match File::options().write(true).open(path) {
+            Err(e) => e.kind() == io::ErrorKind::PermissionDenied,
+            Ok(_) => false,
+        };
+        return (owner != uid && denied).then(|| (path.to_path_buf(), owner));
+    }
+    let parent = path.ancestors().skip(1).find(|parent| parent.exists())?;
+    let (owner, _) = imp::owner(&fs::metadata(parent).ok()?);
+    let probe = parent.join(format!(".rarscan-probe-{}", std::process::id()));
+    let denied = match File::options().write(true).create_new(true).open(&probe) {
+        Err(e) => e.kind() == io::ErrorKind::PermissionDenied,
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            false
+        }
+    };
+    (owner != uid && denied).then(|| (parent.to_path_buf(), owner))
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{fs::Metadata, io, os::unix::fs::MetadataExt, path::Path};
+
+    pub fn owner(md: &Metadata) -> (u32, u32) {
+        (md.uid(), md.gid())
+    }
+
+    pub fn chown(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{fs::Metadata, io, path::Path};
+
+    pub fn owner(_: &Metadata) -> (u32, u32) {
+        (0, 0)
+    }
+
+    pub fn chown(_: &Path, _: u32, _: u32) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// The one spelling of `path` used for the queue and the keys of the state file: absolute, without `.` and `..`, with
+/// the symlinks of the directories leading to it resolved and each name in the case the directory stores it when the
+/// filesystem ignores case. A file keeps its own name even when it's a link, the other parts of its set are found next
+/// to it by name. What doesn't exist is only cleaned up lexically.
+pub fn canonical(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !path.is_dir() => {
+            let parent = resolve(parent);
+            let name = imp::stored_name(&parent, name);
+            parent.join(name)
+        }
+        _ => resolve(&path),
+    }
+}
+
+/// `path` with its `.` and `..` components resolved lexically.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` resolved as far as it exists, the rest normalized on top of it.
+fn resolve(path: &Path) -> PathBuf {
+    let components: Vec<Component> = path.components().collect();
+    for existing in (1..=components.len()).rev() {
+        let Ok(resolved) = fs::canonicalize(components[..existing].iter().collect::<PathBuf>()) else {
+            continue;
+        };
+        let mut path = PathBuf::new();
+        for component in resolved.components() {
+            match component {
+                Component::Normal(name) => {
+                    let name = imp::stored_name(&path, name);
+                    path.push(name);
+                }
+                component => path.push(component),
+            }
+        }
+        path.extend(&components[existing..]);
+        return normalize(&path);
+    }
+    normalize(path)
+}
+
+/// `name` with the case of its letters swapped, `None` when that changes nothing.
+fn swap_case(name: &OsStr) -> Option<String> {
+    let name = name.to_str()?;
+    let swapped: String = name
+        .chars()
+        .map(|c| match c.is_lowercase() {
+            true => c.to_uppercase().next().unwrap_or(c),
+            false => c.to_lowercase().next().unwrap_or(c),
+        })
+        .collect();
+    (swapped != name).then_some(swapped)
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        ffi::{OsStr, OsString},
+        fs,
+        os::unix::fs::MetadataExt,
+        path::Path,
+    };
+
+    /// The spelling `dir` stores `name` with. Only looked up when the same file answers to `name` with its case
+    /// swapped, the filesystem ignores case then.
+    pub fn stored_name(dir: &Path, name: &OsStr) -> OsString {
+        let Some(swapped) = super::swap_case(name) else {
+            return name.to_owned();
+        };
+        let same_file = match (
+            fs::symlink_metadata(dir.join(name)),
+            fs::symlink_metadata(dir.join(swapped)),
+        ) {
+            (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+            _ => false,
+        };
+        if !same_file {
+            return name.to_owned();
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return name.to_owned();
+        };
+        let lower = name.to_string_lossy().to_lowercase();
+        let mut stored = None;
+        for entry in entries.flatten() {
+            let entry = entry.file_name();
+            // Hard links of the same file under both spellings on a filesystem which does care about case.
+            if entry == name {
+                return entry;
+            }
+            if stored.is_none() && entry.to_str().is_some_and(|entry| entry.to_lowercase() == lower) {
+                stored = Some(entry);
+            }
+        }
+        stored.unwrap_or_else(|| name.to_owned())
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{
+        ffi::{OsStr, OsString},
+        path::Path,
+    };
+
+    pub fn stored_name(_: &Path, name: &OsStr) -> OsString {
+        name.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn spellings_of_a_path_are_canonical() {
+        let root = std::env::temp_dir().join(format!("rarscan-paths-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("data/downloads/show")).unwrap();
+        fs::write(root.join("data/downloads/show/show.rar"), b"rar").unwrap();
+        std::os::unix::fs::symlink(root.join("data/downloads"), root.join("dl")).unwrap();
+        std::os::unix::fs::symlink("show.rar", root.join("data/downloads/show/alias.rar")).unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        let archive = root.join("data/downloads/show/show.rar");
+
+        assert_eq!(canonical(&root.join("dl/show/show.rar")), archive);
+        assert_eq!(
+            canonical(&root.join("data/downloads/../downloads/./show/show.rar")),
+            archive
+        );
+        assert_eq!(canonical(&root.join("dl/")), root.join("data/downloads"));
+        assert_eq!(
+            canonical(Path::new(&format!("{}/dl/show/", root.display()))),
+            root.join("data/downloads/show")
+        );
+        // A link to a part keeps its name, like what isn't there yet.
+        assert_eq!(
+            canonical(&root.join("dl/show/alias.rar")),
+            archive.with_file_name("alias.rar")
+        );
+        assert_eq!(
+            canonical(&root.join("dl/new/../show.rar")),
+            root.join("data/downloads/show.rar")
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::BTreeSet, io, path::PathBuf};
+
+/// Directories that don't exist yet among `dirs` and their parents. Sorted, so parents come before their children.
+pub fn missing_dirs(dirs: impl IntoIterator<Item = PathBuf>) -> BTreeSet<PathBuf> {
+    let mut missing = BTreeSet::new();
+    for dir in dirs {
+        for ancestor in dir.ancestors() {
+            if missing.contains(ancestor) || ancestor.as_os_str().is_empty() || ancestor.exists() {
+                break;
+            }
+            missing.insert(ancestor.to_path_buf());
+        }
+    }
+    missing
+}
+
+/// Gives the directories created by an extraction the group and setgid bit of their parent, parents first, then the
+/// files the group of their directory. Changes the process isn't allowed to make are skipped. Returns the number of
+/// paths changed.
+pub fn inherit(created: &BTreeSet<PathBuf>, files: &[PathBuf]) -> io::Result<usize> {
+    let mut changed = 0;
+    for dir in created {
+        if let Some(parent) = dir.parent() {
+            changed += imp::inherit(parent, dir, true)? as usize;
+        }
+    }
+    for file in files {
+        if let Some(parent) = file.parent() {
+            changed += imp::inherit(parent, file, false)? as usize;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        fs, io,
+        os::unix::fs::{chown, MetadataExt, PermissionsExt},
+        path::Path,
+    };
+
+    const SETGID: u32 = 0o2000;
+
+    /// Copies the group of `parent` to `path`, and its setgid bit when `path` is a directory.
+    pub fn inherit(parent: &Path, path: &Path, is_dir: bool) -> io::Result<bool> {
+        let parent_md = fs::metadata(parent)?;
+        let md = fs::symlink_metadata(path)?;
+        if md.file_type().is_symlink() {
+            return Ok(false);
+        }
+        let mut changed = false;
+        if md.gid() != parent_md.gid() {
+            match chown(path, None, Some(parent_md.gid())) {
+                Ok(()) => changed = true,
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    log::debug!("Not allowed to change the group of '{}'.", path.display());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if is_dir && parent_md.mode() & SETGID != 0 && md.mode() & SETGID == 0 {
+            // Changing the group may have cleared the bits, the mode is read again.
+            let mode = fs::metadata(path)?.mode();
+            match fs::set_permissions(path, fs::Permissions::from_mode((mode | SETGID) & 0o7777)) {
+                Ok(()) => changed = true,
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    log::debug!("Not allowed to set the setgid bit of '{}'.", path.display());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{io, path::Path};
+
+    /// There are no groups to inherit.
+    pub fn inherit(_: &Path, _: &Path, _: bool) -> io::Result<bool> {
+        Ok(false)
+    }
+}
@@ -0,0 +1,71 @@
+use std::{fs::File, io, path::Path};
+
+use clap::ValueEnum;
+
+/// When the extracted files are allocated up front, so that they don't end up fragmented by growing as they're
+/// written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preallocate {
+    /// Where rarscan writes the files itself, the entries of zip archives. unrar writes the files of rar archives.
+    Auto,
+    /// Rar archives too, by extracting them through rarscan rather than letting unrar write the files.
+    Always,
+    Never,
+}
+
+impl Preallocate {
+    /// Whether the files of rar archives are written by rarscan.
+    pub fn streams_rar(self) -> bool {
+        self == Preallocate::Always
+    }
+
+    pub fn enabled(self) -> bool {
+        self != Preallocate::Never
+    }
+}
+
+/// Creates `path` to write `size` bytes into, with the space reserved when `preallocate` is set. Filesystems that
+/// can't reserve space just grow the file as usual. The file is as long as `size` until cut with [`finish`].
+pub fn create(path: &Path, size: u64, preallocate: bool) -> io::Result<File> {
+    let file = File::create(path)?;
+    if preallocate && size > 0 {
+        reserve(&file, size);
+    }
+    Ok(file)
+}
+
+/// Cuts `file` to the `written` bytes, whether the writing went through or not, the space reserved past them would
+/// pass for data when the extraction is checked.
+pub fn finish(file: &File, written: u64) -> io::Result<()> {
+    file.set_len(written)
+}
+
+#[cfg(target_os = "linux")]
+fn reserve(file: &File, size: u64) {
+    use std::os::unix::io::AsRawFd;
+
+    let Ok(len) = libc::off_t::try_from(size) else {
+        log::debug!("Not preallocating {} bytes, past the file offsets of this build.", size);
+        return;
+    };
+    // Unlike posix_fallocate, fallocate doesn't fall back on writing zeroes where it isn't supported.
+    // SAFETY: fallocate doesn't touch memory, it only needs a valid descriptor.
+    if unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } != 0 {
+        log::debug!("Could not preallocate {} bytes: {}", size, io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reserve(_: &File, _: u64) {}
+
+/// Largest file whose space can be reserved. 32-bit builds may have 32-bit file offsets, larger files then grow as
+/// usual.
+#[cfg(target_os = "linux")]
+pub fn max_reserve() -> u64 {
+    libc::off_t::MAX as u64
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn max_reserve() -> u64 {
+    0
+}
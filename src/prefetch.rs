@@ -0,0 +1,36 @@
+use std::{
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+use crate::{archive::Archive, naming::ArchiveNaming};
+
+/// Headers of the next archive, listed on a background thread while the current one extracts.
+pub struct Prefetch {
+    path: PathBuf,
+    handle: JoinHandle<anyhow::Result<Archive>>,
+}
+
+impl Prefetch {
+    pub fn start(path: PathBuf, naming: ArchiveNaming) -> Prefetch {
+        log::debug!("Prefetching the headers of '{}'.", path.display());
+        let handle = {
+            let path = path.clone();
+            thread::spawn(move || Archive::open(path, &naming))
+        };
+        Prefetch { path, handle }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits for the listing. Errors are the ones opening the archive would have returned, a panic of the listing is
+    /// raised again here for the archive to fail with it.
+    pub fn finish(self) -> anyhow::Result<Archive> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+
+/// User and group the run switches to with --run-as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunAs {
+    /// Name of the user, for its supplementary groups. Unknown for a uid without a passwd entry.
+    user: Option<String>,
+    uid: u32,
+    gid: u32,
+}
+
+impl fmt::Display for RunAs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{} ({}:{})", user, self.uid, self.gid),
+            None => write!(f, "{}:{}", self.uid, self.gid),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        ffi::{CStr, CString},
+        io,
+    };
+
+    use anyhow::Context;
+
+    use super::RunAs;
+
+    /// Size of the buffer of the passwd and group lookups, grown when too small.
+    const LOOKUP_BUFFER: usize = 1024;
+
+    /// Parses `user[:group]` with names or numeric ids, the group defaults to the primary group of the user.
+    pub fn parse(s: &str) -> Result<RunAs, String> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        let passwd = match user.parse::<u32>() {
+            Ok(uid) => lookup_user(None, uid)?,
+            Err(_) => lookup_user(Some(user), 0)?,
+        };
+        let (name, uid, primary_gid) = match (passwd, user.parse::<u32>()) {
+            (Some(passwd), _) => passwd,
+            (None, Ok(uid)) => (None, uid, None),
+            (None, Err(_)) => return Err(format!("no user '{}'", user)),
+        };
+        let gid = match group {
+            Some(group) => match group.parse::<u32>() {
+                Ok(gid) => gid,
+                Err(_) => lookup_group(group)?.ok_or_else(|| format!("no group '{}'", group))?,
+            },
+            None => primary_gid.ok_or_else(|| format!("uid {} has no passwd entry, give its group too", uid))?,
+        };
+        Ok(RunAs { user: name, uid, gid })
+    }
+
+    type Passwd = (Option<String>, u32, Option<u32>);
+
+    /// The name, uid and primary gid of the user named `name`, or else of `uid`.
+    fn lookup_user(name: Option<&str>, uid: u32) -> Result<Option<Passwd>, String> {
+        let name = name
+            .map(|name| CString::new(name).map_err(|_| format!("invalid user name '{}'", name)))
+            .transpose()?;
+        let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER];
+        loop {
+            // SAFETY: passwd is plain data filled by the call, with pointers into `buffer`.
+            let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+            let mut found = std::ptr::null_mut();
+            // SAFETY: every pointer is valid for the duration of the call, the length is the one of `buffer`.
+            let ret = unsafe {
+                match &name {
+                    Some(name) => libc::getpwnam_r(
+                        name.as_ptr(),
+                        &mut passwd,
+                        buffer.as_mut_ptr(),
+                        buffer.len(),
+                        &mut found,
+                    ),
+                    None => libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut found),
+                }
+            };
+            match ret {
+                0 if found.is_null() => return Ok(None),
+                // SAFETY: pw_name points into `buffer`, which is still alive.
+                0 => {
+                    let user = unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned();
+                    return Ok(Some((Some(user), passwd.pw_uid, Some(passwd.pw_gid))));
+                }
+                libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+                e => return Err(format!("look up user: {}", io::Error::from_raw_os_error(e))),
+            }
+        }
+    }
+
+    fn lookup_group(name: &str) -> Result<Option<u32>, String> {
+        let name = CString::new(name).map_err(|_| format!("invalid group name '{}'", name))?;
+        let mut buffer = vec![0 as libc::c_char; LOOKUP_BUFFER];
+        loop {
+            // SAFETY: group is plain data filled by the call, with pointers into `buffer`.
+            let mut group: libc::group = unsafe { std::mem::zeroed() };
+            let mut found = std::ptr::null_mut();
+            // SAFETY: every pointer is valid for the duration of the call, the length is the one of `buffer`.
+            let ret =
+                unsafe { libc::getgrnam_r(name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+            match ret {
+                0 if found.is_null() => return Ok(None),
+                0 => return Ok(Some(group.gr_gid)),
+                libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+                e => return Err(format!("look up group: {}", io::Error::from_raw_os_error(e))),
+            }
+        }
+    }
+
+    fn check(ret: libc::c_int, what: &str) -> anyhow::Result<()> {
+        if ret != 0 {
+            return Err(io::Error::last_os_error()).context(what.to_string());
+        }
+        Ok(())
+    }
+
+    /// Switches the real, effective and saved ids of every thread to `run_as`, for good.
+    pub fn apply(run_as: &RunAs) -> anyhow::Result<()> {
+        // SAFETY: none of these calls touch memory of the process, the name outlives initgroups.
+        unsafe {
+            match run_as.user.as_deref().map(CString::new) {
+                Some(Ok(user)) => check(libc::initgroups(user.as_ptr(), run_as.gid as _), "initgroups")?,
+                _ => check(libc::setgroups(1, &run_as.gid), "setgroups")?,
+            }
+            check(libc::setgid(run_as.gid), "setgid")?;
+            check(libc::setuid(run_as.uid), "setuid")?;
+            let switched = libc::getuid() == run_as.uid
+                && libc::geteuid() == run_as.uid
+                && libc::getgid() == run_as.gid
+                && libc::getegid() == run_as.gid;
+            anyhow::ensure!(switched, "the ids didn't change");
+            // Getting root back must be impossible once dropped.
+            anyhow::ensure!(
+                run_as.uid == 0 || libc::setuid(0) != 0,
+                "root privileges can still be regained"
+            );
+        }
+        Ok(())
+    }
+
+    /// Effective uid of the process.
+    pub fn effective_uid() -> u32 {
+        // SAFETY: always succeeds and touches no memory.
+        unsafe { libc::geteuid() }
+    }
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        lookup_user(None, uid).ok().flatten().and_then(|(name, _, _)| name)
+    }
+
+    /// Effective uid and gid of the process, with the name of the user when it has one.
+    pub fn current() -> String {
+        // SAFETY: always succeed and touch no memory.
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        match lookup_user(None, uid) {
+            Ok(Some((Some(user), _, _))) => format!("{} (uid {}, gid {})", user, uid, gid),
+            _ => format!("uid {}, gid {}", uid, gid),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use super::RunAs;
+
+    pub fn parse(_: &str) -> Result<RunAs, String> {
+        Err("--run-as is only supported on Unix".into())
+    }
+
+    pub fn apply(_: &RunAs) -> anyhow::Result<()> {
+        anyhow::bail!("--run-as is only supported on Unix")
+    }
+
+    pub fn effective_uid() -> u32 {
+        0
+    }
+
+    pub fn user_name(_: u32) -> Option<String> {
+        None
+    }
+
+    pub fn current() -> String {
+        "unknown".into()
+    }
+}
+
+impl RunAs {
+    pub fn parse(s: &str) -> Result<RunAs, String> {
+        imp::parse(s)
+    }
+
+    /// Drops the privileges of the process to the user and group, failing unless they can't be regained.
+    pub fn apply(&self) -> anyhow::Result<()> {
+        imp::apply(self)
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+}
+
+/// Effective uid of the process.
+pub fn effective_uid() -> u32 {
+    imp::effective_uid()
+}
+
+/// `root (uid 0)`, or the uid alone when it has no passwd entry.
+pub fn describe_uid(uid: u32) -> String {
+    match imp::user_name(uid) {
+        Some(name) => format!("{} (uid {})", name, uid),
+        None => format!("uid {}", uid),
+    }
+}
+
+/// Effective user and group of the process.
+pub fn current_user() -> String {
+    imp::current()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_users_and_groups() {
+        let root = RunAs::parse("root").unwrap();
+        assert_eq!((root.user.as_deref(), root.uid, root.gid), (Some("root"), 0, 0));
+        assert_eq!(RunAs::parse("0:0").unwrap(), root);
+        assert_eq!(RunAs::parse("root:12345").unwrap().gid, 12345);
+        assert_eq!(RunAs::parse("54321:54321").unwrap().user, None);
+        assert!(RunAs::parse("54321").is_err());
+        assert!(RunAs::parse("no-such-user-rarscan").is_err());
+        assert!(RunAs::parse("root:no-such-group-rarscan").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use clap::ValueEnum;
+use serde_json::Value;
+
+/// Version of the protocol, the `v` of every line. Fields may be added to the events of a version and new events may
+/// appear, wrappers ignore what they don't know. Removing a field, changing its type or its meaning bumps it.
+pub const VERSION: u64 = 1;
+
+/// What stdout carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// The log, for people.
+    Log,
+    /// The events as they happen, one JSON object per line, for wrappers. The log goes to stderr.
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    String,
+    /// A string or null.
+    OptionalString,
+    Integer,
+    Number,
+    Array,
+}
+
+impl Kind {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Kind::String => value.is_string(),
+            Kind::OptionalString => value.is_string() || value.is_null(),
+            Kind::Integer => value.is_u64(),
+            Kind::Number => value.is_number(),
+            Kind::Array => value.is_array(),
+        }
+    }
+}
+
+/// The events of version 1 and the fields each of them is guaranteed to have.
+const EVENTS: &[(&str, &[(&str, Kind)])] = &[
+    ("scan_start", &[("root_dir", Kind::String)]),
+    ("archive_found", &[("path", Kind::String)]),
+    (
+        "scan_progress",
+        &[
+            ("dirs", Kind::Integer),
+            ("top_dirs_entered", Kind::Integer),
+            ("top_dirs", Kind::Integer),
+            ("rar_files", Kind::Integer),
+            ("archives", Kind::Integer),
+        ],
+    ),
+    (
+        "scan_done",
+        &[
+            ("dirs", Kind::Integer),
+            ("rar_files", Kind::Integer),
+            ("archives", Kind::Integer),
+            ("duration_secs", Kind::Number),
+        ],
+    ),
+    ("archive_start", &[("archive", Kind::String)]),
+    (
+        "extract_start",
+        &[
+            ("archive", Kind::String),
+            ("format", Kind::String),
+            ("dest", Kind::String),
+            ("packed_size", Kind::Integer),
+            ("unpacked_size", Kind::Integer),
+        ],
+    ),
+    (
+        "file_extracted",
+        &[
+            ("archive", Kind::String),
+            ("file", Kind::String),
+            ("size", Kind::Integer),
+        ],
+    ),
+    (
+        "extract_done",
+        &[
+            ("archive", Kind::String),
+            ("duration_secs", Kind::Number),
+            ("bytes", Kind::Integer),
+            ("mb_per_sec", Kind::Number),
+        ],
+    ),
+    ("archive_done", &[("archive", Kind::String), ("outcome", Kind::String)]),
+    ("part_removed", &[("path", Kind::String)]),
+    (
+        "corruption",
+        &[
+            ("path", Kind::String),
+            ("archive", Kind::OptionalString),
+            ("expected_crc", Kind::String),
+            ("found_crc", Kind::String),
+        ],
+    ),
+    (
+        "run_summary",
+        &[
+            ("rar_files_seen", Kind::Integer),
+            ("root_archives_enqueued", Kind::Integer),
+            ("archives_processed", Kind::Integer),
+            ("archives_extracted", Kind::Integer),
+            ("parts_removed", Kind::Integer),
+            ("failed_archives", Kind::Array),
+            ("releases", Kind::Array),
+        ],
+    ),
+];
+
+/// An event as a line of the protocol.
+pub fn line(mut event: Value) -> String {
+    if let Some(event) = event.as_object_mut() {
+        event.insert("v".into(), VERSION.into());
+    }
+    let mut line = event.to_string();
+    line.push('\n');
+    line
+}
+
+/// Checks a line against the schema of this version. Fields it doesn't know are fine, events it doesn't know aren't:
+/// every event this version emits is in the schema.
+pub fn validate(line: &str) -> Result<(), String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| format!("not JSON: {}", e))?;
+    let object = value.as_object().ok_or("not an object")?;
+    match object.get("v").and_then(Value::as_u64) {
+        Some(VERSION) => {}
+        v => return Err(format!("version {:?} instead of {}", v, VERSION)),
+    }
+    let event = object.get("event").and_then(Value::as_str).ok_or("no event")?;
+    let (_, fields) = EVENTS
+        .iter()
+        .find(|(name, _)| *name == event)
+        .ok_or_else(|| format!("unknown event '{}'", event))?;
+    for (field, kind) in *fields {
+        match object.get(*field) {
+            Some(value) if kind.matches(value) => {}
+            Some(value) => return Err(format!("{}: '{}' isn't {:?}: {}", event, field, kind, value)),
+            None => return Err(format!("{}: '{}' is missing", event, field)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A run recorded with `--output ndjson`, the contract wrappers of version 1 rely on.
+    const RECORDED_RUN: &str = include_str!("../fixtures/protocol-v1.ndjson");
+
+    #[test]
+    fn recorded_run_conforms_to_the_schema() {
+        for (i, line) in RECORDED_RUN.lines().enumerate() {
+            assert_eq!(validate(line), Ok(()), "line {}: {}", i + 1, line);
+        }
+
+        // Fields added within a version are ignored, anything else isn't.
+        let extra = r#"{"v":1,"event":"part_removed","path":"/a.rar","size":12}"#;
+        assert_eq!(validate(extra), Ok(()));
+        assert!(validate(r#"{"v":2,"event":"part_removed","path":"/a.rar"}"#).is_err());
+        assert!(validate(r#"{"v":1,"event":"part_removed","path":12}"#).is_err());
+        assert!(validate(r#"{"v":1,"event":"part_removed"}"#).is_err());
+        assert!(validate(r#"{"v":1,"event":"renamed"}"#).is_err());
+    }
+}
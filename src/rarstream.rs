@@ -0,0 +1,346 @@
+use std::{
+    ffi::CString,
+    io,
+    os::raw::c_int,
+    path::{Path, PathBuf},
+    slice,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use unrar::error::{Code, UnrarError, When};
+use unrar_sys as native;
+
+/// Host OS of the entries made on unix, whose attributes are a mode.
+const HOST_UNIX: u32 = 3;
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// An entry of a [`RarStream`], as given by its header.
+#[derive(Debug)]
+pub struct StreamEntry {
+    pub filename: PathBuf,
+    pub unpacked_size: u64,
+    pub directory: bool,
+    /// Links, unrar has to create them itself. Only the ones made on unix are told apart.
+    pub link: bool,
+    /// To the two seconds, the precision of the DOS time of the header.
+    pub mtime: Option<SystemTime>,
+    /// Permissions of entries made on unix.
+    pub mode: Option<u32>,
+}
+
+/// Why reading an entry failed.
+#[derive(Debug)]
+pub enum ReadError {
+    Unrar(UnrarError),
+    /// The data couldn't be written.
+    Write(io::Error),
+}
+
+type Write<'a> = dyn FnMut(&[u8]) -> io::Result<()> + 'a;
+
+/// What the callback of unrar writes the data of an entry to.
+struct Sink<'a> {
+    write: Option<&'a mut Write<'a>>,
+    error: Option<io::Error>,
+}
+
+/// A rar archive opened for extraction with the unrar library directly, so that the data of its entries goes through
+/// rarscan rather than unrar writing the files itself.
+pub struct RarStream {
+    handle: *const native::Handle,
+}
+
+impl RarStream {
+    pub fn open(path: &Path, password: Option<&str>) -> Result<RarStream, UnrarError> {
+        let name = to_wide(path);
+        let mut data = ffi::OpenArchiveDataEx::new(name.as_ptr(), native::RAR_OM_EXTRACT);
+        // SAFETY: `data` has the layout of the library and it and the name it points to outlive the call.
+        let handle = unsafe { ffi::RAROpenArchiveEx(&mut data) };
+        let stream = RarStream { handle };
+        let open_result = data.open_result;
+        match open_result as c_int {
+            native::ERAR_SUCCESS if !handle.is_null() => {}
+            code => return Err(error(code, When::Open)),
+        }
+        if let Some(password) = password {
+            // A password with a nul byte can't be given to unrar, and can't be right either.
+            let password = CString::new(password).map_err(|_| error(native::ERAR_BAD_PASSWORD, When::Open))?;
+            // SAFETY: the handle is open, unrar copies the password.
+            unsafe { native::RARSetPassword(stream.handle, password.as_ptr()) };
+        }
+        Ok(stream)
+    }
+
+    /// The next entry, `None` at the end of the archive. It must be read, extracted or skipped before the next one.
+    pub fn next_entry(&mut self) -> Result<Option<StreamEntry>, UnrarError> {
+        let mut sink = Sink {
+            write: None,
+            error: None,
+        };
+        self.set_callback(&mut sink);
+        let mut header = ffi::HeaderDataEx::boxed();
+        // SAFETY: the handle is open and `header` has the layout of the library.
+        match unsafe { ffi::RARReadHeaderEx(self.handle, &mut *header) } {
+            native::ERAR_SUCCESS => {}
+            native::ERAR_END_ARCHIVE => return Ok(None),
+            code => return Err(error(code, When::Read)),
+        }
+        // Copied out of the packed struct, its fields can't be borrowed.
+        let filename_w = header.filename_w;
+        let unix = header.host_os == HOST_UNIX;
+        let file_attr = header.file_attr;
+        Ok(Some(StreamEntry {
+            filename: PathBuf::from(from_wide(&filename_w)),
+            unpacked_size: (header.unp_size_high as u64) << 32 | header.unp_size as u64,
+            directory: header.flags & native::RHDF_DIRECTORY != 0,
+            link: unix && file_attr & S_IFMT == S_IFLNK,
+            mtime: dos_time(header.file_time),
+            mode: unix.then_some(file_attr & 0o7777),
+        }))
+    }
+
+    /// Decompresses the current entry, handing its data to `write` as it comes. An error of `write` stops it.
+    pub fn read(&mut self, write: &mut Write) -> Result<(), ReadError> {
+        let mut sink = Sink {
+            write: Some(write),
+            error: None,
+        };
+        let code = self.process(&mut sink, native::RAR_TEST, None);
+        match (sink.error, code) {
+            (Some(e), _) => Err(ReadError::Write(e)),
+            (None, native::ERAR_SUCCESS) => Ok(()),
+            (None, code) => Err(ReadError::Unrar(error(code, When::Process))),
+        }
+    }
+
+    /// Lets unrar extract the current entry to `path`.
+    pub fn extract_to(&mut self, path: &Path) -> Result<(), UnrarError> {
+        let mut sink = Sink {
+            write: None,
+            error: None,
+        };
+        let name = to_wide(path);
+        match self.process(&mut sink, native::RAR_EXTRACT, Some(&name)) {
+            native::ERAR_SUCCESS => Ok(()),
+            code => Err(error(code, When::Process)),
+        }
+    }
+
+    pub fn skip(&mut self) -> Result<(), UnrarError> {
+        let mut sink = Sink {
+            write: None,
+            error: None,
+        };
+        match self.process(&mut sink, native::RAR_SKIP, None) {
+            native::ERAR_SUCCESS => Ok(()),
+            code => Err(error(code, When::Process)),
+        }
+    }
+
+    fn process(&mut self, sink: &mut Sink, operation: c_int, dest: Option<&[native::WCHAR]>) -> c_int {
+        self.set_callback(sink);
+        let dest = dest.map_or(std::ptr::null(), |dest| dest.as_ptr());
+        // SAFETY: the handle is open, the sink set as the callback data outlives the call and `dest` is either null or
+        // a nul terminated wide string.
+        unsafe { native::RARProcessFileW(self.handle, operation, std::ptr::null(), dest) }
+    }
+
+    fn set_callback(&mut self, sink: &mut Sink) {
+        // SAFETY: the handle is open. The callback is set again with a live sink before every call that may use it.
+        unsafe { native::RARSetCallback(self.handle, Some(callback), sink as *mut Sink as native::LPARAM) };
+    }
+}
+
+impl Drop for RarStream {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            // SAFETY: the handle is open and not used after this.
+            unsafe { native::RARCloseArchive(self.handle) };
+        }
+    }
+}
+
+extern "C" fn callback(msg: native::UINT, user_data: native::LPARAM, p1: native::LPARAM, p2: native::LPARAM) -> c_int {
+    if user_data == 0 {
+        return 0;
+    }
+    // SAFETY: `user_data` is the sink set along with the callback, alive for the duration of the call invoking it.
+    let sink = unsafe { &mut *(user_data as *mut Sink) };
+    match msg {
+        // A volume that isn't there, unrar asks for it and stops when refused.
+        native::UCM_CHANGEVOLUME | native::UCM_CHANGEVOLUMEW if p2 == native::RAR_VOL_ASK => -1,
+        native::UCM_PROCESSDATA => {
+            let Some(write) = &mut sink.write else {
+                return 0;
+            };
+            // SAFETY: unrar hands out `p2` bytes of data at `p1`.
+            let data = unsafe { slice::from_raw_parts(p1 as *const u8, p2 as usize) };
+            match write(data) {
+                Ok(()) => 0,
+                Err(e) => {
+                    sink.error = Some(e);
+                    -1
+                }
+            }
+        }
+        native::UCM_NEEDPASSWORD | native::UCM_NEEDPASSWORDW => -1,
+        _ => 0,
+    }
+}
+
+/// The DOS time of a header, which unrar takes as local time.
+#[cfg(unix)]
+fn dos_time(time: u32) -> Option<SystemTime> {
+    // SAFETY: a zeroed tm is valid.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = (time >> 25) as i32 + 80;
+    tm.tm_mon = ((time >> 21) & 0xf) as i32 - 1;
+    tm.tm_mday = ((time >> 16) & 0x1f) as i32;
+    tm.tm_hour = ((time >> 11) & 0x1f) as i32;
+    tm.tm_min = ((time >> 5) & 0x3f) as i32;
+    tm.tm_sec = ((time & 0x1f) * 2) as i32;
+    tm.tm_isdst = -1;
+    // SAFETY: mktime only reads and normalizes `tm`.
+    let secs = unsafe { libc::mktime(&mut tm) };
+    (time != 0 && secs >= 0).then(|| UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+#[cfg(not(unix))]
+fn dos_time(_: u32) -> Option<SystemTime> {
+    None
+}
+
+fn error(code: c_int, when: When) -> UnrarError {
+    UnrarError::from(Code::from(code).unwrap_or(Code::Unknown), when)
+}
+
+#[cfg(not(windows))]
+fn to_wide(path: &Path) -> Vec<native::WCHAR> {
+    let path = path.to_string_lossy();
+    path.chars().map(|c| c as native::WCHAR).chain([0]).collect()
+}
+
+#[cfg(windows)]
+fn to_wide(path: &Path) -> Vec<native::WCHAR> {
+    use std::os::windows::ffi::OsStrExt;
+
+    path.as_os_str().encode_wide().chain([0]).collect()
+}
+
+#[cfg(not(windows))]
+fn from_wide(wide: &[native::WCHAR]) -> String {
+    wide.iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| char::from_u32(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+#[cfg(windows)]
+fn from_wide(wide: &[native::WCHAR]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
+
+/// The structs of `dll.hpp` with their layout, which is packed: the binding aligns their pointers, which moves the
+/// fields after them. Only the functions taking one are declared again, the library is linked by the binding.
+#[allow(non_snake_case)]
+mod ffi {
+    use std::{
+        mem::{self, offset_of, size_of},
+        os::raw::{c_char, c_int, c_uint},
+    };
+
+    use libc::wchar_t;
+    use unrar_sys::{Callback, Handle, LPARAM};
+
+    #[repr(C, packed)]
+    pub struct HeaderDataEx {
+        pub archive_name: [c_char; 1024],
+        pub archive_name_w: [wchar_t; 1024],
+        pub filename: [c_char; 1024],
+        pub filename_w: [wchar_t; 1024],
+        pub flags: c_uint,
+        pub pack_size: c_uint,
+        pub pack_size_high: c_uint,
+        pub unp_size: c_uint,
+        pub unp_size_high: c_uint,
+        pub host_os: c_uint,
+        pub file_crc: c_uint,
+        pub file_time: c_uint,
+        pub unp_ver: c_uint,
+        pub method: c_uint,
+        pub file_attr: c_uint,
+        pub comment_buffer: *mut c_char,
+        pub comment_buffer_size: c_uint,
+        pub comment_size: c_uint,
+        pub comment_state: c_uint,
+        pub dict_size: c_uint,
+        pub hash_type: c_uint,
+        pub hash: [c_char; 32],
+        pub redir_type: c_uint,
+        pub redir_name: *mut wchar_t,
+        pub redir_name_size: c_uint,
+        pub dir_target: c_uint,
+        pub mtime_low: c_uint,
+        pub mtime_high: c_uint,
+        pub ctime_low: c_uint,
+        pub ctime_high: c_uint,
+        pub atime_low: c_uint,
+        pub atime_high: c_uint,
+        pub reserved: [c_uint; 988],
+    }
+
+    impl HeaderDataEx {
+        /// A zeroed header, large, kept off the stack.
+        pub fn boxed() -> Box<HeaderDataEx> {
+            // SAFETY: integers, arrays of them and null pointers, all zeroes is valid.
+            Box::new(unsafe { mem::zeroed() })
+        }
+    }
+
+    #[repr(C, packed)]
+    pub struct OpenArchiveDataEx {
+        pub archive_name: *const c_char,
+        pub archive_name_w: *const wchar_t,
+        pub open_mode: c_uint,
+        pub open_result: c_uint,
+        pub comment_buffer: *mut c_char,
+        pub comment_buffer_size: c_uint,
+        pub comment_size: c_uint,
+        pub comment_state: c_uint,
+        pub flags: c_uint,
+        pub callback: Option<Callback>,
+        pub user_data: LPARAM,
+        pub op_flags: c_uint,
+        pub comment_buffer_w: *mut wchar_t,
+        pub reserved: [c_uint; 25],
+    }
+
+    impl OpenArchiveDataEx {
+        pub fn new(archive_name_w: *const wchar_t, open_mode: c_uint) -> OpenArchiveDataEx {
+            // SAFETY: integers, null pointers and a `None` callback, all zeroes is valid.
+            let mut data: OpenArchiveDataEx = unsafe { mem::zeroed() };
+            data.archive_name_w = archive_name_w;
+            data.open_mode = open_mode;
+            data
+        }
+    }
+
+    const WCHAR: usize = size_of::<wchar_t>();
+    const PTR: usize = size_of::<usize>();
+    // Computed from the fields of dll.hpp one by one, with no padding.
+    const _: () = assert!(offset_of!(HeaderDataEx, flags) == 2 * 1024 + 2 * 1024 * WCHAR);
+    const _: () = assert!(offset_of!(HeaderDataEx, comment_buffer) == 2 * 1024 + 2 * 1024 * WCHAR + 11 * 4);
+    const _: () = assert!(offset_of!(HeaderDataEx, redir_name) == offset_of!(HeaderDataEx, comment_buffer) + PTR + 56);
+    const _: () = assert!(size_of::<HeaderDataEx>() == offset_of!(HeaderDataEx, redir_name) + PTR + 8 * 4 + 988 * 4);
+    const _: () = assert!(offset_of!(OpenArchiveDataEx, open_result) == 2 * PTR + 4);
+    const _: () = assert!(offset_of!(OpenArchiveDataEx, comment_buffer_w) == 4 * PTR + 4 * 7 + size_of::<LPARAM>());
+    const _: () =
+        assert!(size_of::<OpenArchiveDataEx>() == offset_of!(OpenArchiveDataEx, comment_buffer_w) + PTR + 25 * 4);
+
+    extern "C" {
+        pub fn RAROpenArchiveEx(data: *mut OpenArchiveDataEx) -> *const Handle;
+        pub fn RARReadHeaderEx(handle: *const Handle, data: *mut HeaderDataEx) -> c_int;
+    }
+}
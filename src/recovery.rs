@@ -0,0 +1,154 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::Context;
+
+use crate::fds;
+
+/// The volumes of a set found next to its first volume.
+#[derive(Debug, Default)]
+pub struct SetVolumes {
+    /// Volumes missing between the first one and the last one there.
+    pub missing: usize,
+    /// The `.rev` files of the set.
+    pub recovery: Vec<PathBuf>,
+    /// The first volume there, which is the archive the set is queued as.
+    pub first: Option<PathBuf>,
+}
+
+impl SetVolumes {
+    /// Looks for the volumes and the recovery volumes of the set of `path` in its directory.
+    pub fn of(path: &Path) -> SetVolumes {
+        let mut volumes = SetVolumes::default();
+        let Some(name) = set_name(path) else {
+            return volumes;
+        };
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let Ok(entries) = fs::read_dir(dir) else {
+            return volumes;
+        };
+        let mut numbers = BTreeSet::new();
+        let mut start = 0;
+        for entry in entries.flatten() {
+            let sibling = entry.path();
+            if set_name(&sibling).as_ref() != Some(&name) {
+                continue;
+            }
+            if is_recovery_volume(&sibling) {
+                volumes.recovery.push(sibling);
+            } else if let Some((number, first)) = volume_number(&sibling) {
+                start = first;
+                if numbers.first().is_none_or(|&n| number < n) {
+                    volumes.first = Some(sibling);
+                }
+                numbers.insert(number);
+            }
+        }
+        volumes.recovery.sort();
+        if let Some(&last) = numbers.last() {
+            volumes.missing = (start..=last).filter(|n| !numbers.contains(n)).count();
+        }
+        volumes
+    }
+
+    /// Whether the recovery volumes are enough to rebuild the `missing` volumes.
+    pub fn repairable(&self, missing: usize) -> bool {
+        !self.recovery.is_empty() && self.recovery.len() >= missing
+    }
+}
+
+pub fn is_recovery_volume(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("rev"))
+}
+
+/// Name shared by the volumes and the recovery volumes of a set, lowercase: `name.part01.rar`, `name.r00`,
+/// `name.part01.rev` and `name_1.rev` are all `name`.
+fn set_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_str()?.to_lowercase();
+    let (stem, ext) = file_name.rsplit_once('.')?;
+    let stem = match ext {
+        "rev" => strip_number(stem, "_").unwrap_or(stem),
+        "rar" => stem,
+        _ if is_old_volume(ext) => return Some(stem.to_string()),
+        _ => return None,
+    };
+    Some(strip_number(stem, ".part").unwrap_or(stem).to_string())
+}
+
+/// Position of a volume in its set along with the position of the first one, which is 1 for `.partN.rar` and 0 for
+/// `.rar` followed by `.r00`.
+pub fn volume_number(path: &Path) -> Option<(u32, u32)> {
+    let file_name = path.file_name()?.to_str()?.to_lowercase();
+    let (stem, ext) = file_name.rsplit_once('.')?;
+    match ext {
+        "rar" => match stem.rsplit_once(".part").and_then(|(_, n)| n.parse().ok()) {
+            Some(n) => Some((n, 1)),
+            None => Some((0, 0)),
+        },
+        _ if is_old_volume(ext) => ext[1..].parse::<u32>().ok().map(|n| (n + 1, 0)),
+        _ => None,
+    }
+}
+
+fn is_old_volume(ext: &str) -> bool {
+    ext.len() == 3 && ext.starts_with('r') && ext[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+/// `stem` without a trailing `<separator><digits>`.
+fn strip_number<'a>(stem: &'a str, separator: &str) -> Option<&'a str> {
+    let (rest, number) = stem.rsplit_once(separator)?;
+    (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit())).then_some(rest)
+}
+
+/// Rebuilds the missing volumes of the set of `archive` from its recovery volumes with `rar rc`.
+pub fn rebuild(tool: &Path, archive: &Path) -> anyhow::Result<()> {
+    // Both ends of the stderr pipe until the tool is spawned.
+    let _fds = fds::acquire(2);
+    let output = Command::new(tool)
+        .args(["rc", "-y"])
+        .arg(archive)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .with_context(|| format!("spawn recovery tool '{}'", tool.display()))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::ensure!(
+        output.status.success(),
+        "recovery tool failed ({}): {}",
+        output.status,
+        stderr.trim()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_names_and_numbers() {
+        assert_eq!(
+            set_name(Path::new("a/Show.S01E01.part01.rar")).as_deref(),
+            Some("show.s01e01")
+        );
+        assert_eq!(
+            set_name(Path::new("Show.S01E01.part3.rev")).as_deref(),
+            Some("show.s01e01")
+        );
+        assert_eq!(set_name(Path::new("movie.r07")).as_deref(), Some("movie"));
+        assert_eq!(set_name(Path::new("movie_2.rev")).as_deref(), Some("movie"));
+        assert_eq!(set_name(Path::new("movie.nfo")), None);
+        assert_eq!(volume_number(Path::new("Show.part02.rar")), Some((2, 1)));
+        assert_eq!(volume_number(Path::new("movie.rar")), Some((0, 0)));
+        assert_eq!(volume_number(Path::new("movie.r00")), Some((1, 0)));
+        assert_eq!(volume_number(Path::new("movie_1.rev")), None);
+    }
+}
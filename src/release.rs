@@ -0,0 +1,28 @@
+use std::path::{Component, Path};
+
+/// Name of the group of the archives sitting directly in the root directory.
+pub const ROOT_RELEASE: &str = "<root>";
+
+/// Release of a path under `root_dir`: the directory directly below `root_dir` holding it. `None` when the path is
+/// outside of `root_dir`.
+pub fn release_of(root_dir: &Path, path: &Path) -> Option<String> {
+    let mut components = path.strip_prefix(root_dir).ok()?.components();
+    Some(match (components.next(), components.next()) {
+        (Some(Component::Normal(dir)), Some(_)) => dir.to_string_lossy().into_owned(),
+        _ => ROOT_RELEASE.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_is_the_directory_below_the_root() {
+        let root = Path::new("/tv");
+        assert_eq!(release_of(root, Path::new("/tv/show/show.rar")).unwrap(), "show");
+        assert_eq!(release_of(root, Path::new("/tv/show/subs/subs.rar")).unwrap(), "show");
+        assert_eq!(release_of(root, Path::new("/tv/loose.rar")).unwrap(), ROOT_RELEASE);
+        assert_eq!(release_of(root, Path::new("/movies/movie.rar")), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Map, Value};
+
+/// Parts of extracted archives to remove, each with the time it becomes old enough. Recorded while extracting and
+/// applied separately, possibly by another process through the state file.
+#[derive(Debug, Clone, Default)]
+pub struct RemovalSet {
+    archives: BTreeMap<PathBuf, Vec<(PathBuf, SystemTime)>>,
+}
+
+impl RemovalSet {
+    /// Records the parts of `archive`, replacing the ones recorded before.
+    pub fn insert(&mut self, archive: PathBuf, parts: Vec<(PathBuf, SystemTime)>) {
+        self.archives.insert(archive, parts);
+    }
+
+    /// Adds the archives of `other`, replacing the ones recorded here.
+    pub fn extend(&mut self, other: RemovalSet) {
+        self.archives.extend(other.archives);
+    }
+
+    pub fn get(&self, archive: &Path) -> Option<&[(PathBuf, SystemTime)]> {
+        self.archives.get(archive).map(Vec::as_slice)
+    }
+
+    pub fn remove(&mut self, archive: &Path) -> bool {
+        self.archives.remove(archive).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &[(PathBuf, SystemTime)])> {
+        self.archives
+            .iter()
+            .map(|(archive, parts)| (archive.as_path(), parts.as_slice()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.archives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.archives.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let archives: Map<String, Value> = self
+            .archives
+            .iter()
+            .map(|(archive, parts)| {
+                let parts: Vec<Value> = parts
+                    .iter()
+                    .map(|(path, eligible_at)| {
+                        json!({
+                            "path": path.to_string_lossy(),
+                            "eligible_at": secs(*eligible_at),
+                        })
+                    })
+                    .collect();
+                (archive.to_string_lossy().into_owned(), Value::from(parts))
+            })
+            .collect();
+        Value::from(archives)
+    }
+
+    pub fn from_json(value: &Value) -> RemovalSet {
+        let mut set = RemovalSet::default();
+        for (archive, parts) in value.as_object().into_iter().flatten() {
+            let parts = parts
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|part| {
+                    let path = part.get("path")?.as_str()?;
+                    let eligible_at = part.get("eligible_at")?.as_f64()?;
+                    Some((PathBuf::from(path), UNIX_EPOCH + Duration::from_secs_f64(eligible_at)))
+                })
+                .collect();
+            set.insert(PathBuf::from(archive), parts);
+        }
+        set
+    }
+}
+
+/// Limits of --max-remove-files and --max-remove-bytes on what a run removes, parts and cruft together. Guards
+/// against a bug or a misconfigured threshold deleting everything at once.
+#[derive(Debug, Clone, Default)]
+pub struct RemovalCap {
+    max_files: Option<u64>,
+    max_bytes: Option<u64>,
+    files: u64,
+    bytes: u64,
+}
+
+impl RemovalCap {
+    pub fn new(max_files: Option<u64>, max_bytes: Option<u64>) -> RemovalCap {
+        RemovalCap {
+            max_files,
+            max_bytes,
+            ..RemovalCap::default()
+        }
+    }
+
+    /// Whether removing `files` more files of `bytes` in total goes past the cap.
+    pub fn exceeded_by(&self, files: u64, bytes: u64) -> bool {
+        self.max_files.is_some_and(|max| self.files + files > max)
+            || self.max_bytes.is_some_and(|max| self.bytes + bytes > max)
+    }
+
+    /// Counts the removal of `files` files of `bytes` in total, unless it goes past the cap.
+    pub fn admit(&mut self, files: u64, bytes: u64) -> bool {
+        if self.exceeded_by(files, bytes) {
+            return false;
+        }
+        self.files += files;
+        self.bytes += bytes;
+        true
+    }
+
+    pub fn max_files(&self) -> Option<u64> {
+        self.max_files
+    }
+
+    pub fn max_bytes(&self) -> Option<u64> {
+        self.max_bytes
+    }
+}
+
+fn secs(t: SystemTime) -> f64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_cap_counts_files_and_bytes() {
+        let mut cap = RemovalCap::new(Some(3), Some(1000));
+        assert!(cap.admit(2, 600));
+        assert!(cap.exceeded_by(1, 500));
+        assert!(!cap.admit(1, 500));
+        // What doesn't fit is left out, smaller removals still go through.
+        assert!(cap.admit(1, 400));
+        assert!(!cap.admit(1, 0));
+
+        let mut unlimited = RemovalCap::new(None, None);
+        assert!(unlimited.admit(u32::MAX as u64, u32::MAX as u64));
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde_json::{json, Map, Value};
+
+use crate::{archive::Archive, paths};
+
+/// Where an entry extracted from an archive went once renamed by something else, and its size when it was mapped,
+/// which differs from the entry's when it was transcoded too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Renamed {
+    pub path: PathBuf,
+    pub size: Option<u64>,
+}
+
+/// The `<archive-stem>.rarscan-map` next to an archive, mapping the names of its entries relative to the destination
+/// to their current path. Relative paths start from the directory of the map.
+#[derive(Debug)]
+pub struct RenameMap {
+    path: PathBuf,
+    entries: BTreeMap<PathBuf, Renamed>,
+}
+
+impl RenameMap {
+    fn path_of(archive: &Path) -> PathBuf {
+        archive.with_extension("rarscan-map")
+    }
+
+    /// Loads the map of `archive`, `None` when it has none. Entries may map to a bare path or to an object with the
+    /// path and the size of the file.
+    pub fn load(archive: &Path) -> anyhow::Result<Option<RenameMap>> {
+        let path = RenameMap::path_of(archive);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read rename map '{}'", path.display())),
+        };
+        let value: Value =
+            serde_json::from_str(&content).with_context(|| format!("parse rename map '{}'", path.display()))?;
+        let mut map = RenameMap {
+            path,
+            entries: BTreeMap::new(),
+        };
+        for (name, entry) in value.get("renames").and_then(Value::as_object).into_iter().flatten() {
+            let renamed = match entry {
+                Value::String(path) => Renamed {
+                    path: PathBuf::from(path),
+                    size: None,
+                },
+                entry => {
+                    let path = entry.get("path").and_then(Value::as_str);
+                    let path = path.with_context(|| format!("rename of '{}' has no path", name))?;
+                    Renamed {
+                        path: PathBuf::from(path),
+                        size: entry.get("size").and_then(Value::as_u64),
+                    }
+                }
+            };
+            map.entries.insert(PathBuf::from(name), renamed);
+        }
+        Ok(Some(map))
+    }
+
+    /// Loads the map of `archive`, an empty one when it has none.
+    fn load_or_default(archive: &Path) -> anyhow::Result<RenameMap> {
+        Ok(RenameMap::load(archive)?.unwrap_or_else(|| RenameMap {
+            path: RenameMap::path_of(archive),
+            entries: BTreeMap::new(),
+        }))
+    }
+
+    /// The renamed entries with their path resolved.
+    pub fn entries(&self) -> impl Iterator<Item = (&Path, Renamed)> {
+        let dir = self.path.parent().unwrap_or(Path::new(""));
+        self.entries.iter().map(move |(name, renamed)| {
+            let renamed = Renamed {
+                path: dir.join(&renamed.path),
+                size: renamed.size,
+            };
+            (name.as_path(), renamed)
+        })
+    }
+
+    fn insert(&mut self, name: PathBuf, renamed: Renamed) {
+        self.entries.insert(name, renamed);
+    }
+
+    /// Writes the map, replacing it atomically so that tools reading it never see half of it.
+    fn save(&self) -> anyhow::Result<()> {
+        let renames: Map<String, Value> = self
+            .entries
+            .iter()
+            .map(|(name, renamed)| {
+                let entry = json!({
+                    "path": renamed.path.to_string_lossy(),
+                    "size": renamed.size,
+                });
+                (name.to_string_lossy().into_owned(), entry)
+            })
+            .collect();
+        let content = json!({ "version": 1, "renames": renames }).to_string();
+        let tmp = self.path.with_extension("rarscan-map.tmp");
+        fs::write(&tmp, content).context("write rename map")?;
+        fs::rename(&tmp, &self.path).context("replace rename map")?;
+        Ok(())
+    }
+}
+
+/// Maps the entry `old` of `archive` to the file at `new`, updating the map of the archive. `new` must be inside of
+/// one of `roots`.
+pub fn map_rename(archive: &Archive, old: &Path, new: &Path, roots: &[PathBuf]) -> anyhow::Result<()> {
+    let header = archive
+        .headers
+        .iter()
+        .find(|header| header.filename == old)
+        .with_context(|| format!("'{}' has no entry '{}'", archive.path.display(), old.display()))?;
+    anyhow::ensure!(
+        header.is_file(),
+        "'{}' is a directory, only files can be mapped",
+        old.display()
+    );
+    let new = fs::canonicalize(new).with_context(|| format!("resolve '{}'", new.display()))?;
+    anyhow::ensure!(
+        within(&new, roots),
+        "'{}' is outside of the directory of the archive and of the --map-root directories",
+        new.display()
+    );
+    let size = fs::metadata(&new).context("stat renamed file")?.len();
+    let mut map = RenameMap::load_or_default(&archive.path)?;
+    let dir = map.path.parent().and_then(|dir| fs::canonicalize(dir).ok());
+    let path = match dir.as_deref().and_then(|dir| new.strip_prefix(dir).ok()) {
+        Some(relative) => relative.to_path_buf(),
+        None => new.clone(),
+    };
+    map.insert(old.to_path_buf(), Renamed { path, size: Some(size) });
+    map.save()?;
+    log::info!(
+        "Mapped '{}' to '{}' in '{}'.",
+        old.display(),
+        new.display(),
+        map.path.display()
+    );
+    Ok(())
+}
+
+/// Whether `path` is inside of one of `roots`, symlinks resolved when it exists.
+pub fn within(path: &Path, roots: &[PathBuf]) -> bool {
+    let resolve = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| paths::normalize(path));
+    let path = resolve(path);
+    roots.iter().any(|root| path.starts_with(resolve(root)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_within_roots() {
+        let roots = [PathBuf::from("/nonexistent/tv"), PathBuf::from("/nonexistent/plex")];
+        assert!(within(Path::new("/nonexistent/tv/show/a.mkv"), &roots));
+        assert!(within(Path::new("/nonexistent/tv/../plex/a.mkv"), &roots));
+        assert!(!within(Path::new("/nonexistent/tv/../../etc/passwd"), &roots));
+        assert!(!within(Path::new("/nonexistent/tvshows/a.mkv"), &roots));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use glob::{MatchOptions, Pattern};
+
+use crate::parse_duration;
+
+/// Remove-after threshold for the archives matching a glob relative to the root directory, e.g. `tv/**=3d`, and
+/// optionally the number of archives of each release directory kept whatever their age, e.g. `tv/**=3d,keep-newest=1`.
+#[derive(Debug, Clone)]
+pub struct RemoveAfterRule {
+    text: String,
+    pattern: Pattern,
+    pub remove_after: Duration,
+    /// Overrides --keep-newest.
+    pub keep_newest: Option<usize>,
+}
+
+impl RemoveAfterRule {
+    /// Parses a `<glob>=<duration>[,keep-newest=<n>]` rule for clap.
+    pub fn parse(s: &str) -> Result<RemoveAfterRule, String> {
+        let (rule, keep_newest) = match s.rsplit_once(",keep-newest=") {
+            Some((rule, n)) => {
+                let n = n
+                    .parse()
+                    .map_err(|_| format!("invalid keep-newest '{}' in rule '{}'", n, s))?;
+                (rule, Some(n))
+            }
+            None => (s, None),
+        };
+        let (pattern, duration) = rule
+            .rsplit_once('=')
+            .ok_or_else(|| format!("invalid rule '{}', expected <glob>=<duration>", s))?;
+        Ok(RemoveAfterRule {
+            text: pattern.to_string(),
+            pattern: Pattern::new(pattern).map_err(|e| format!("invalid glob '{}': {}", pattern, e))?,
+            remove_after: parse_duration(duration)?,
+            keep_newest,
+        })
+    }
+
+    /// Patterns naming more segments literally are more specific, then the ones with fewer `**`, then the ones with
+    /// more literal characters: `tv/sh` over `tv/*`, `tv/*` over `tv/**` and `tv/sh*` over `tv/s*`.
+    fn specificity(&self) -> (usize, usize, usize) {
+        let segments: Vec<&str> = self.text.split('/').collect();
+        let literal = segments.iter().filter(|segment| is_literal(segment)).count();
+        let bounded = segments.iter().filter(|segment| **segment != "**").count();
+        let chars = self.text.chars().filter(|c| !is_meta(*c) && *c != '/').count();
+        (literal, bounded, chars)
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.text
+    }
+}
+
+fn is_meta(c: char) -> bool {
+    matches!(c, '*' | '?' | '[' | ']')
+}
+
+fn is_literal(segment: &str) -> bool {
+    !segment.contains(is_meta)
+}
+
+/// Whether some path can match both patterns, compared segment by segment with `**` standing for any number of them.
+fn overlap(a: &[&str], b: &[&str]) -> bool {
+    match (a.first(), b.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => overlap(&a[1..], b) || (!b.is_empty() && overlap(a, &b[1..])),
+        (_, Some(&"**")) => overlap(b, a),
+        (Some(x), Some(y)) => segments_overlap(x, y) && overlap(&a[1..], &b[1..]),
+        _ => false,
+    }
+}
+
+/// Whether some name can match both segments. Two globs are told apart by their literal start and end only.
+fn segments_overlap(a: &str, b: &str) -> bool {
+    match (is_literal(a), is_literal(b)) {
+        (true, true) => a == b,
+        (true, false) => Pattern::new(b).is_ok_and(|pattern| pattern.matches(a)),
+        (false, true) => Pattern::new(a).is_ok_and(|pattern| pattern.matches(b)),
+        (false, false) => {
+            let prefix = |s: &str| s[..s.find(is_meta).unwrap_or(s.len())].to_string();
+            let suffix = |s: &str| s[s.rfind(is_meta).map_or(0, |i| i + 1)..].to_string();
+            let (a_prefix, b_prefix) = (prefix(a), prefix(b));
+            let (a_suffix, b_suffix) = (suffix(a), suffix(b));
+            (a_prefix.starts_with(&b_prefix) || b_prefix.starts_with(&a_prefix))
+                && (a_suffix.ends_with(&b_suffix) || b_suffix.ends_with(&a_suffix))
+        }
+    }
+}
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Per path remove-after thresholds, resolved by the most specific matching rule.
+pub struct RemoveAfterRules {
+    root_dir: PathBuf,
+    rules: Vec<RemoveAfterRule>,
+}
+
+impl RemoveAfterRules {
+    /// Rejects rules of equal specificity which can match the same paths with different thresholds.
+    pub fn new(root_dir: impl Into<PathBuf>, rules: Vec<RemoveAfterRule>) -> Result<RemoveAfterRules, String> {
+        for (i, a) in rules.iter().enumerate() {
+            for b in &rules[i + 1..] {
+                let overlap = overlap(
+                    &a.text.split('/').collect::<Vec<_>>(),
+                    &b.text.split('/').collect::<Vec<_>>(),
+                );
+                let differ = a.remove_after != b.remove_after || a.keep_newest != b.keep_newest;
+                if a.specificity() == b.specificity() && differ && overlap {
+                    return Err(format!(
+                        "--remove-after-for rules '{}' and '{}' conflict, they are equally specific",
+                        a.text, b.text
+                    ));
+                }
+            }
+        }
+        Ok(RemoveAfterRules {
+            root_dir: root_dir.into(),
+            rules,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether a rule keeps the newest archives of its releases.
+    pub fn keep_newest(&self) -> bool {
+        self.rules.iter().any(|rule| rule.keep_newest.is_some())
+    }
+
+    /// The most specific rule matching `path`, if any.
+    pub fn resolve(&self, path: &Path) -> Option<&RemoveAfterRule> {
+        self.matching(path).into_iter().next()
+    }
+
+    /// The rules matching `path`, the most specific first.
+    pub fn matching(&self, path: &Path) -> Vec<&RemoveAfterRule> {
+        let Ok(relative) = path.strip_prefix(&self.root_dir) else {
+            return Vec::new();
+        };
+        let mut rules: Vec<&RemoveAfterRule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.pattern.matches_path_with(relative, MATCH_OPTIONS))
+            .collect();
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.specificity()));
+        rules
+    }
+}
@@ -0,0 +1,451 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::{self, ExitCode, Stdio},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::{archive::Archive, failure::ExtractionError, naming::ArchiveNaming, prealloc::Preallocate, protocol};
+
+/// Name of the hidden subcommand running the extractions of --sandbox.
+pub const WORKER_COMMAND: &str = "__extract-worker";
+
+/// First version of Landlock able to forbid TCP connections, Linux 6.7.
+pub const NETWORK_ABI: u32 = 4;
+
+/// The version of Landlock the kernel supports, or why it can't confine the worker.
+pub fn landlock_abi() -> Result<u32, String> {
+    imp::abi()
+}
+
+/// Confines the calling thread and the processes it starts to reading the files below `read`, to reading and
+/// writing below `write`, and to no sockets but Unix ones. There is no way back.
+pub fn confine(read: &[PathBuf], write: &[PathBuf]) -> io::Result<()> {
+    imp::confine(read, write)
+}
+
+/// What the extraction worker needs of the options of the run to open and extract an archive like the run would.
+#[derive(Clone)]
+pub struct Sandbox {
+    pub naming: ArchiveNaming,
+    pub preallocate: Preallocate,
+    pub fast_store_copy: bool,
+}
+
+impl Sandbox {
+    /// Extracts `archive` into `dest` like `Archive::extract_into`, from a worker process only able to read the
+    /// directories of its parts and to write `dest`. An error of `on_extracted` stops the worker.
+    pub fn extract(
+        &self,
+        archive: &Archive,
+        dest: &Path,
+        password: Option<&str>,
+        mut on_extracted: impl FnMut(&Path, u64) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut read: Vec<PathBuf> = archive
+            .list_parts()
+            .context("list parts")?
+            .iter()
+            .filter_map(|part| part.parent().map(Path::to_path_buf))
+            .collect();
+        read.sort();
+        read.dedup();
+        let mut command = process::Command::new(std::env::current_exe().context("locate the rarscan binary")?);
+        command
+            .arg(WORKER_COMMAND)
+            .args(["--log-level", "warn"])
+            .args(self.naming.args())
+            .arg("--preallocate")
+            .arg(
+                self.preallocate
+                    .to_possible_value()
+                    .expect("no skipped variant")
+                    .get_name(),
+            );
+        if self.fast_store_copy {
+            command.arg("--fast-store-copy");
+        }
+        if let Some(max) = archive.max_written() {
+            command.arg("--max-written").arg(max.to_string());
+        }
+        if archive.stream_budget() {
+            command.arg("--stream-budget");
+        }
+        for dir in &read {
+            command.arg("--read").arg(dir);
+        }
+        let mut child = command
+            .arg(&archive.path)
+            .arg(dest)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("start the extraction worker")?;
+        // Through stdin, the arguments of a process are there for every user to see.
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        stdin
+            .write_all(password.unwrap_or_default().as_bytes())
+            .context("send the password to the extraction worker")?;
+        drop(stdin);
+        let mut failure = None;
+        for line in BufReader::new(child.stdout.take().expect("piped stdout")).lines() {
+            let line = line.context("read the extraction worker")?;
+            let event: Value = serde_json::from_str(&line).context("parse the extraction worker")?;
+            match event.get("event").and_then(Value::as_str) {
+                Some("file_extracted") => {
+                    let file = event.get("file").and_then(Value::as_str).unwrap_or_default();
+                    let size = event.get("size").and_then(Value::as_u64).unwrap_or_default();
+                    if let Err(e) = on_extracted(Path::new(file), size) {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(e);
+                    }
+                }
+                Some("extract_failed") => failure = Some(event),
+                _ => {}
+            }
+        }
+        let status = child.wait().context("wait for the extraction worker")?;
+        if let Some(event) = failure {
+            if let Some(failure) = event.get("failure").and_then(ExtractionError::from_json) {
+                return Err(failure.into());
+            }
+            let error = event.get("error").and_then(Value::as_str).unwrap_or("failed");
+            anyhow::bail!("extraction worker: {}", error);
+        }
+        if !status.success() {
+            // Killed, or panicked in unrar, before it could say why.
+            return Err(ExtractionError::Panicked {
+                message: format!("the extraction worker exited with {}", status),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// The extraction worker: confines itself to `read` and `dest`, reads the password from stdin, then extracts
+/// `archive` and reports each file on stdout with a `file_extracted` line of the protocol. A failure ends it with an
+/// `extract_failed` line, which only the run that started it understands.
+pub fn run_worker(
+    archive: &Path,
+    dest: &Path,
+    read: &[PathBuf],
+    max_written: Option<u64>,
+    stream_budget: bool,
+    sandbox: &Sandbox,
+) -> ExitCode {
+    let mut stdout = io::stdout().lock();
+    let Err(e) = work(archive, dest, read, max_written, stream_budget, sandbox, &mut stdout) else {
+        return ExitCode::SUCCESS;
+    };
+    let failed = match e.downcast_ref::<ExtractionError>() {
+        Some(failure) => json!({
+            "event": "extract_failed",
+            "archive": archive.to_string_lossy(),
+            "failure": failure.to_json(),
+        }),
+        None => json!({
+            "event": "extract_failed",
+            "archive": archive.to_string_lossy(),
+            "error": format!("{:#}", e),
+        }),
+    };
+    let _ = stdout.write_all(protocol::line(failed).as_bytes());
+    ExitCode::FAILURE
+}
+
+fn work(
+    archive: &Path,
+    dest: &Path,
+    read: &[PathBuf],
+    max_written: Option<u64>,
+    stream_budget: bool,
+    sandbox: &Sandbox,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let mut password = String::new();
+    io::stdin().read_to_string(&mut password).context("read the password")?;
+    confine(read, &[dest.to_path_buf()]).context("confine the extraction worker")?;
+    let password = (!password.is_empty()).then_some(password.as_str());
+    let mut opened = Archive::open_with_password(archive, &sandbox.naming, password)?;
+    opened.set_preallocate(sandbox.preallocate);
+    opened.set_fast_store_copy(sandbox.fast_store_copy);
+    opened.set_max_written(max_written);
+    opened.set_stream_budget(stream_budget);
+    opened.extract_into(dest, |file, size| {
+        let extracted = json!({
+            "event": "file_extracted",
+            "archive": archive.to_string_lossy(),
+            "file": file.to_string_lossy(),
+            "size": size,
+        });
+        out.write_all(protocol::line(extracted).as_bytes())?;
+        out.flush()?;
+        Ok(())
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{
+        fs::OpenOptions,
+        io,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::fs::OpenOptionsExt,
+        },
+        path::PathBuf,
+    };
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+    const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+    const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+    const ACCESS_NET_BIND_TCP: u64 = 1 << 0;
+    const ACCESS_NET_CONNECT_TCP: u64 = 1 << 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+        handled_access_net: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: libc::c_int,
+    }
+
+    pub fn abi() -> Result<u32, String> {
+        // SAFETY: without attributes the call only returns the version.
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi >= 0 {
+            return Ok(abi as u32);
+        }
+        let e = io::Error::last_os_error();
+        Err(match e.raw_os_error() {
+            Some(libc::ENOSYS) => "the kernel has no Landlock, it takes Linux 5.13".into(),
+            Some(libc::EOPNOTSUPP) => "Landlock is off in the kernel, add it to the lsm= boot parameter".into(),
+            _ => format!("Landlock is unavailable: {}", e),
+        })
+    }
+
+    /// The rights on files of Landlock `abi`: the ones it knows are denied unless a rule grants them.
+    fn handled_fs(abi: u32) -> u64 {
+        match abi {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            _ => (1 << 16) - 1,
+        }
+    }
+
+    fn check(ret: libc::c_long) -> io::Result<libc::c_long> {
+        match ret {
+            -1 => Err(io::Error::last_os_error()),
+            ret => Ok(ret),
+        }
+    }
+
+    pub fn confine(read: &[PathBuf], write: &[PathBuf]) -> io::Result<()> {
+        let abi = abi().map_err(|e| io::Error::new(io::ErrorKind::Unsupported, e))?;
+        let handled = handled_fs(abi);
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+            handled_access_net: match abi >= super::NETWORK_ABI {
+                true => ACCESS_NET_BIND_TCP | ACCESS_NET_CONNECT_TCP,
+                false => 0,
+            },
+        };
+        // SAFETY: `attr` outlives the call and the size is its own.
+        let ruleset = check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0 as libc::c_uint,
+            )
+        })?;
+        // SAFETY: the descriptor was just created and nothing else owns it.
+        let ruleset = unsafe { OwnedFd::from_raw_fd(ruleset as libc::c_int) };
+        let read = read.iter().map(|path| (path, ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR));
+        let write = write.iter().map(|path| (path, handled));
+        for (path, access) in read.chain(write) {
+            let dir = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+                .open(path)?;
+            let rule = PathBeneathAttr {
+                allowed_access: access,
+                parent_fd: dir.as_raw_fd(),
+            };
+            // SAFETY: `rule` and both descriptors outlive the call.
+            check(unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &rule as *const PathBeneathAttr,
+                    0 as libc::c_uint,
+                )
+            })?;
+        }
+        // SAFETY: plain prctl, Landlock takes it of a process without CAP_SYS_ADMIN.
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the ruleset is a valid descriptor for the duration of the call.
+        check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0 as libc::c_uint) })?;
+        seccomp::deny_sockets()
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64"))]
+    mod seccomp {
+        use std::io;
+
+        #[cfg(target_arch = "x86_64")]
+        const AUDIT_ARCH: u32 = 0xc000_003e;
+        #[cfg(target_arch = "aarch64")]
+        const AUDIT_ARCH: u32 = 0xc000_00b7;
+        #[cfg(target_arch = "riscv64")]
+        const AUDIT_ARCH: u32 = 0xc000_00f3;
+        /// Set in the number of the syscalls of the x32 ABI, which share the architecture of x86_64.
+        const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+        /// Offsets in `struct seccomp_data`.
+        const DATA_NR: u32 = 0;
+        const DATA_ARCH: u32 = 4;
+        const DATA_ARG0: u32 = 16;
+
+        fn stmt(code: u32, k: u32) -> libc::sock_filter {
+            jump(code, k, 0, 0)
+        }
+
+        fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+            libc::sock_filter {
+                code: code as u16,
+                jt,
+                jf,
+                k,
+            }
+        }
+
+        /// Landlock only forbids TCP, and only from its version 4 on: a seccomp filter fails the creation of any socket
+        /// but a Unix one with EACCES, like Landlock does. Syscalls of another architecture kill the process.
+        pub fn deny_sockets() -> io::Result<()> {
+            let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+            let jeq = libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K;
+            let jge = libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K;
+            let ret = libc::BPF_RET | libc::BPF_K;
+            let filter = [
+                stmt(load, DATA_ARCH),
+                jump(jeq, AUDIT_ARCH, 1, 0),
+                stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+                stmt(load, DATA_NR),
+                jump(jge, X32_SYSCALL_BIT, 5, 0),
+                jump(jeq, libc::SYS_socket as u32, 0, 3),
+                // The low half of the domain, the argument is an int.
+                stmt(load, DATA_ARG0),
+                jump(jeq, libc::AF_UNIX as u32, 1, 0),
+                stmt(ret, libc::SECCOMP_RET_ERRNO | libc::EACCES as u32),
+                stmt(ret, libc::SECCOMP_RET_ALLOW),
+                stmt(ret, libc::SECCOMP_RET_KILL_PROCESS),
+            ];
+            let program = libc::sock_fprog {
+                len: filter.len() as u16,
+                filter: filter.as_ptr() as *mut libc::sock_filter,
+            };
+            // SAFETY: `program` and the filter it points to outlive the call, no_new_privs is set already.
+            if unsafe {
+                libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                )
+            } != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+    mod seccomp {
+        /// Without the number of the architecture for a filter to check, only Landlock forbids TCP.
+        pub fn deny_sockets() -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::{io, path::PathBuf};
+
+    pub fn abi() -> Result<u32, String> {
+        Err("Landlock is only found on Linux".into())
+    }
+
+    pub fn confine(_: &[PathBuf], _: &[PathBuf]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, abi().unwrap_err()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn confined_worker_only_writes_its_destination() {
+        if let Err(reason) = landlock_abi() {
+            eprintln!("skipped, {}", reason);
+            return;
+        }
+        let root = std::env::temp_dir().join(format!("rarscan-sandbox-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["archives", "dest", "elsewhere"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("archives/show.rar"), b"rar").unwrap();
+
+        // Confined like the worker, a thread of its own keeps the rest of the tests out of it.
+        let confined = root.clone();
+        std::thread::spawn(move || {
+            let root = confined;
+            confine(&[root.join("archives")], &[root.join("dest")]).unwrap();
+            assert_eq!(fs::read(root.join("archives/show.rar")).unwrap(), b"rar");
+            fs::write(root.join("dest/file.txt"), b"extracted").unwrap();
+            fs::create_dir(root.join("dest/subdir")).unwrap();
+            for outside in ["elsewhere/file.txt", "archives/file.txt", "file.txt"] {
+                let e = fs::write(root.join(outside), b"escaped").unwrap_err();
+                assert_eq!(e.kind(), io::ErrorKind::PermissionDenied, "{}", outside);
+            }
+            let e = fs::remove_file(root.join("archives/show.rar")).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+            let e = std::net::UdpSocket::bind("127.0.0.1:0").unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+            let e = std::net::TcpStream::connect("127.0.0.1:9").unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+            std::os::unix::net::UnixDatagram::unbound().unwrap();
+        })
+        .join()
+        .unwrap();
+
+        assert!(root.join("dest/file.txt").exists());
+        assert!(!root.join("elsewhere/file.txt").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs, io,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{fds, sidecar, snapshot::CommandTemplate, tier};
+
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+/// Bytes of the output of a scan kept for the report.
+const MAX_OUTPUT: usize = 4096;
+
+/// External scanner the files of an extraction go through before the archive counts as extracted. `{file}` in the
+/// command is the file to scan, appended when missing, or `{dir}` the destination to scan the whole extraction at
+/// once. Exit status 0 passes, anything else flags the files.
+pub struct Scanner {
+    command: CommandTemplate,
+    jobs: usize,
+    timeout: Duration,
+    quarantine: PathBuf,
+}
+
+/// A file the scanner didn't pass, with why and what it printed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flagged {
+    pub path: PathBuf,
+    pub reason: String,
+    pub output: String,
+}
+
+impl Scanner {
+    pub fn new(command: CommandTemplate, jobs: usize, timeout: Duration, quarantine: PathBuf) -> Scanner {
+        Scanner {
+            command,
+            jobs: jobs.max(1),
+            timeout,
+            quarantine,
+        }
+    }
+
+    /// Scans `files`, extracted into `dest`, at most --scan-jobs at a time. Returns the flagged ones.
+    pub fn scan(&self, dest: &Path, files: &[PathBuf]) -> Vec<Flagged> {
+        if self.command.uses("dir") {
+            let dest_str = dest.to_string_lossy();
+            return match self.run(&self.command.expand(&[("dir", &dest_str)])) {
+                Ok(()) => Vec::new(),
+                Err((reason, output)) => files
+                    .iter()
+                    .map(|path| Flagged {
+                        path: path.clone(),
+                        reason: reason.clone(),
+                        output: output.clone(),
+                    })
+                    .collect(),
+            };
+        }
+        let next = AtomicUsize::new(0);
+        let flagged = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..self.jobs.min(files.len()) {
+                scope.spawn(|| {
+                    while let Some(path) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        if let Err((reason, output)) = self.run(&self.command_for(path)) {
+                            flagged.lock().expect("flagged lock poisoned").push(Flagged {
+                                path: path.clone(),
+                                reason,
+                                output,
+                            });
+                        }
+                    }
+                });
+            }
+        });
+        let mut flagged = flagged.into_inner().expect("flagged lock poisoned");
+        flagged.sort_by(|a, b| a.path.cmp(&b.path));
+        flagged
+    }
+
+    fn command_for(&self, path: &Path) -> Vec<String> {
+        let path_str = path.to_string_lossy();
+        let mut command = self.command.expand(&[("file", &path_str)]);
+        if !self.command.uses("file") {
+            command.push(path_str.into_owned());
+        }
+        command
+    }
+
+    /// Runs the scanner, the reason and the output are the error when it doesn't pass. One that can't be started or
+    /// runs out of time doesn't pass either.
+    fn run(&self, command: &[String]) -> Result<(), (String, String)> {
+        // Both ends of the stdout and stderr pipes until the scanner is spawned.
+        let _fds = fds::acquire(4);
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| (format!("could not run '{}': {}", command[0], e), String::new()))?;
+
+        // Read the output on the side so a chatty scanner can't block on a full pipe while we wait for it.
+        let (tx, rx) = mpsc::channel();
+        for mut pipe in [
+            Box::new(child.stdout.take().expect("stdout is piped")) as Box<dyn Read + Send>,
+            Box::new(child.stderr.take().expect("stderr is piped")),
+        ] {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = pipe.read_to_end(&mut buf);
+                let _ = tx.send(buf);
+            });
+        }
+
+        let started = Instant::now();
+        let status: Option<ExitStatus> = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Some(status),
+                Ok(None) if started.elapsed() < self.timeout => thread::sleep(Duration::from_millis(20)),
+                _ => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break None;
+                }
+            }
+        };
+
+        // A grandchild may keep the pipes open after the scanner itself exited, don't wait on it forever.
+        let mut output = Vec::new();
+        for _ in 0..2 {
+            output.extend(rx.recv_timeout(OUTPUT_GRACE).unwrap_or_default());
+        }
+        output.truncate(MAX_OUTPUT);
+        let output = String::from_utf8_lossy(&output).trim().to_string();
+        match status {
+            Some(status) if status.success() => Ok(()),
+            Some(status) => Err((format!("flagged by the scanner ({})", status), output)),
+            None => Err((
+                format!("the scanner timed out after {}s", self.timeout.as_secs_f64()),
+                output,
+            )),
+        }
+    }
+
+    /// Moves the `flagged` files extracted from `archive` into `SET/` of the quarantine directory, at their path below
+    /// `dest`, or into `SET.2/` and so on when a quarantine of the same name is there already. Returns where they went.
+    pub fn quarantine(&self, archive: &Path, dest: &Path, flagged: &[Flagged]) -> io::Result<PathBuf> {
+        let set = sidecar::set_name(archive);
+        let mut n = 1;
+        let target = loop {
+            let candidate = match n {
+                1 => self.quarantine.join(&set),
+                n => self.quarantine.join(format!("{}.{}", set, n)),
+            };
+            if fs::symlink_metadata(&candidate).is_err() {
+                break candidate;
+            }
+            n += 1;
+        };
+        for file in flagged {
+            let relative = file.path.strip_prefix(dest).unwrap_or(&file.path);
+            let to = match relative.is_absolute() {
+                true => target.join(relative.file_name().unwrap_or_default()),
+                false => target.join(relative),
+            };
+            tier::move_file(&file.path, &to)?;
+        }
+        Ok(target)
+    }
+
+    /// Whether `path` is in the quarantine directory.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.quarantine)
+    }
+}
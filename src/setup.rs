@@ -0,0 +1,348 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+
+use crate::{archive, config, format_size, naming::ArchiveNaming, parse_duration, template::DestTemplate, walk};
+
+/// Hours the parts are kept by default once their archive is extracted.
+const DEFAULT_REMOVE_AFTER_HOURS: u64 = 48;
+
+/// How the runs are started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Schedule {
+    /// A line of the crontab.
+    Cron,
+    /// A service and its timer.
+    Systemd,
+}
+
+/// The answers given as flags. They are the defaults of the questions, and with --yes the answers.
+pub struct Answers {
+    pub dir: Option<PathBuf>,
+    pub remove_after_hours: Option<u64>,
+    pub dest: Option<DestTemplate>,
+    pub schedule: Option<Schedule>,
+    pub every: Option<Duration>,
+    pub config_path: PathBuf,
+}
+
+/// Asks the questions on stdin, the same again until the answer is valid. With --yes only the defaults are taken,
+/// and an invalid one is an error.
+struct Prompt<R> {
+    input: R,
+    yes: bool,
+}
+
+impl<R: BufRead> Prompt<R> {
+    fn ask<T>(
+        &mut self,
+        question: &str,
+        default: Option<String>,
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> anyhow::Result<T> {
+        if self.yes {
+            let answer = default.with_context(|| format!("{}: no answer, give it as a flag with --yes", question))?;
+            return parse(&answer).map_err(|e| anyhow::anyhow!("{}: {}", question, e));
+        }
+        loop {
+            match &default {
+                Some(default) => print!("{} [{}]: ", question, default),
+                None => print!("{}: ", question),
+            }
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                println!();
+                anyhow::bail!("setup cancelled, nothing was written");
+            }
+            let answer = match (line.trim(), &default) {
+                ("", Some(default)) => default.as_str(),
+                ("", None) => {
+                    println!("-> An answer is needed.");
+                    continue;
+                }
+                (answer, _) => answer,
+            };
+            match parse(answer) {
+                Ok(value) => return Ok(value),
+                Err(e) => println!("-> {}, try again.", e),
+            }
+        }
+    }
+}
+
+/// Asks what a run should do, writes the config file and prints how to schedule the runs. Nothing is extracted or
+/// removed, the directory is only looked at for the counts.
+pub fn run(given: Answers, yes: bool, naming: &ArchiveNaming) -> anyhow::Result<()> {
+    let mut prompt = Prompt {
+        input: io::stdin().lock(),
+        yes,
+    };
+    let dir = prompt.ask(
+        "Directory to scan for archives",
+        given.dir.map(|dir| dir.display().to_string()),
+        parse_dir,
+    )?;
+    let rars = walk::find_files(&dir, false, archive::is_rar_file).context("scan the directory")?;
+    let roots = rars.iter().filter(|path| naming.is_root_rar_file(path)).count();
+    println!("-> Found {} .rar files below it, {} archives.", rars.len(), roots);
+
+    println!();
+    println!(
+        "Once an archive is extracted, rarscan can remove its parts, the .rar files, after they are a given number"
+    );
+    println!("of hours old. The extracted files are always kept, as are the parts of archives that fail to extract.");
+    let remove = prompt.ask(
+        "Remove the parts of extracted archives? (yes/no)",
+        Some(
+            if given.remove_after_hours.is_some() {
+                "yes"
+            } else {
+                "no"
+            }
+            .into(),
+        ),
+        parse_yes_no,
+    )?;
+    let remove_after_hours = match remove {
+        true => Some(
+            prompt.ask(
+                "Hours to keep them",
+                Some(
+                    given
+                        .remove_after_hours
+                        .unwrap_or(DEFAULT_REMOVE_AFTER_HOURS)
+                        .to_string(),
+                ),
+                parse_hours,
+            )?,
+        ),
+        false => None,
+    };
+    if let Some(hours) = remove_after_hours {
+        let threshold = SystemTime::now() - Duration::from_secs(60 * 60 * hours);
+        let old: Vec<u64> = rars
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .filter(|metadata| metadata.modified().is_ok_and(|mtime| mtime < threshold))
+            .map(|metadata| metadata.len())
+            .collect();
+        println!(
+            "-> {} .rar files, {}, are older than {}h now: they would be removed once their archive is extracted.",
+            old.len(),
+            format_size(old.iter().sum()),
+            hours
+        );
+    }
+
+    println!();
+    let dest = prompt.ask(
+        "Directory to extract into, `-` for next to each archive",
+        Some(given.dest.map_or_else(|| "-".into(), |dest| dest.to_string())),
+        parse_dest,
+    )?;
+    let schedule = prompt.ask(
+        "Run from cron or a systemd timer? (cron/systemd)",
+        Some(
+            given
+                .schedule
+                .unwrap_or(Schedule::Systemd)
+                .to_possible_value()
+                .expect("no skipped variant")
+                .get_name()
+                .to_string(),
+        ),
+        |s| Schedule::from_str(s, true).map_err(|_| format!("'{}' is neither cron nor systemd", s)),
+    )?;
+    let every = prompt.ask(
+        "How often, such as 30m or 2h",
+        Some(
+            given
+                .every
+                .map_or_else(|| "1h".into(), |every| format!("{}m", every.as_secs() / 60)),
+        ),
+        |s| parse_every(s, schedule),
+    )?;
+
+    let config_path = std::path::absolute(&given.config_path).context("config path")?;
+    if config_path.exists() && !yes {
+        let overwrite = prompt.ask(
+            &format!("'{}' exists, overwrite it? (yes/no)", config_path.display()),
+            Some("no".into()),
+            parse_yes_no,
+        )?;
+        if !overwrite {
+            anyhow::bail!("setup cancelled, nothing was written");
+        }
+    }
+    let mut options = Vec::new();
+    if let Some(hours) = remove_after_hours {
+        options.push(("remove-after-hours", Some(hours.to_string())));
+    }
+    if let Some(dest) = &dest {
+        options.push(("dest-template", Some(dest.to_string())));
+    }
+    let header =
+        "Written by `rarscan setup`, read with --config. A flag on each line, named without its dashes, a value\n\
+                  after `=`. The flags of the command line win over the ones of this file.";
+    fs::write(&config_path, config::render(header, &options))
+        .with_context(|| format!("write config '{}'", config_path.display()))?;
+    println!();
+    println!("Wrote '{}'.", config_path.display());
+
+    let exe = std::env::current_exe().context("locate the rarscan binary")?;
+    let command = format!("{} --config {} {}", quote(&exe), quote(&config_path), quote(&dir));
+    println!();
+    match schedule {
+        Schedule::Cron => {
+            println!("Add this line to the crontab with `crontab -e`:");
+            println!();
+            println!("{} {}", cron_schedule(every).expect("checked by parse_every"), command);
+        }
+        Schedule::Systemd => {
+            println!("Save this as /etc/systemd/system/rarscan.service:");
+            println!();
+            println!("[Unit]");
+            println!("Description=Extract the archives of {}", dir.display());
+            println!();
+            println!("[Service]");
+            println!("Type=oneshot");
+            println!("ExecStart={}", command);
+            println!();
+            println!("And this as /etc/systemd/system/rarscan.timer:");
+            println!();
+            println!("[Unit]");
+            println!("Description=Run rarscan every {} minutes", every.as_secs() / 60);
+            println!();
+            println!("[Timer]");
+            println!("OnBootSec=5min");
+            println!("OnUnitActiveSec={}min", every.as_secs() / 60);
+            println!();
+            println!("[Install]");
+            println!("WantedBy=timers.target");
+            println!();
+            println!("Then start it with `systemctl daemon-reload && systemctl enable --now rarscan.timer`.");
+        }
+    }
+    Ok(())
+}
+
+fn parse_dir(s: &str) -> Result<PathBuf, String> {
+    let dir = std::path::absolute(s).map_err(|e| e.to_string())?;
+    match fs::metadata(&dir) {
+        Ok(metadata) if metadata.is_dir() => Ok(dir),
+        Ok(_) => Err(format!("'{}' isn't a directory", dir.display())),
+        Err(e) => Err(format!("'{}': {}", dir.display(), e)),
+    }
+}
+
+fn parse_yes_no(s: &str) -> Result<bool, String> {
+    match s.to_lowercase().as_str() {
+        "y" | "yes" => Ok(true),
+        "n" | "no" => Ok(false),
+        _ => Err(format!("'{}' is neither yes nor no", s)),
+    }
+}
+
+fn parse_hours(s: &str) -> Result<u64, String> {
+    s.parse().map_err(|_| format!("'{}' isn't a number of hours", s))
+}
+
+/// A destination template, `None` for next to each archive. A directory must exist, or the one it would be created
+/// in: the runs create it, not the setup.
+fn parse_dest(s: &str) -> Result<Option<DestTemplate>, String> {
+    if s == "-" {
+        return Ok(None);
+    }
+    let template = DestTemplate::parse(s)?;
+    if !s.contains('{') && Path::new(s).is_absolute() {
+        let dir = Path::new(s);
+        let parent = dir.parent().unwrap_or(dir);
+        if dir.exists() && !dir.is_dir() {
+            return Err(format!("'{}' isn't a directory", dir.display()));
+        }
+        if !dir.exists() && !parent.is_dir() {
+            return Err(format!("'{}' doesn't exist", parent.display()));
+        }
+    }
+    Ok(Some(template))
+}
+
+/// How often the runs start, a whole number of minutes `schedule` can repeat evenly.
+fn parse_every(s: &str, schedule: Schedule) -> Result<Duration, String> {
+    let every = parse_duration(s)?;
+    if every.as_secs() < 60 || !every.as_secs().is_multiple_of(60) {
+        return Err(format!("'{}' isn't a whole number of minutes", s));
+    }
+    if schedule == Schedule::Cron && cron_schedule(every).is_none() {
+        return Err(format!(
+            "cron can't run every '{}' evenly, take a divisor of an hour or a day",
+            s
+        ));
+    }
+    Ok(every)
+}
+
+/// The five fields of a crontab line running every `every`, when cron can.
+fn cron_schedule(every: Duration) -> Option<String> {
+    let minutes = every.as_secs() / 60;
+    match minutes {
+        1 => Some("* * * * *".into()),
+        2..=59 if 60u64.is_multiple_of(minutes) => Some(format!("*/{} * * * *", minutes)),
+        60 => Some("0 * * * *".into()),
+        1440 => Some("0 0 * * *".into()),
+        _ if minutes.is_multiple_of(60) && 24u64.is_multiple_of(minutes / 60) => {
+            Some(format!("0 */{} * * *", minutes / 60))
+        }
+        _ => None,
+    }
+}
+
+/// `path` as one word of a shell or systemd command line.
+fn quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    match path
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+:,@".contains(c))
+    {
+        true => path.into_owned(),
+        false => format!("'{}'", path.replace('\'', r"'\''")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_are_checked_until_valid() {
+        let mut prompt = Prompt {
+            input: io::Cursor::new("maybe\n\nY\n"),
+            yes: false,
+        };
+        assert!(prompt.ask("Remove?", None, parse_yes_no).unwrap());
+        let mut prompt = Prompt {
+            input: io::Cursor::new("2x\n"),
+            yes: false,
+        };
+        assert!(prompt.ask("Hours", Some("48".into()), parse_hours).is_err());
+        let mut prompt = Prompt {
+            input: io::Cursor::new(""),
+            yes: true,
+        };
+        assert_eq!(prompt.ask("Hours", Some("48".into()), parse_hours).unwrap(), 48);
+        assert!(prompt.ask("Hours", Some("x".into()), parse_hours).is_err());
+
+        assert_eq!(cron_schedule(Duration::from_secs(15 * 60)).unwrap(), "*/15 * * * *");
+        assert_eq!(cron_schedule(Duration::from_secs(6 * 3600)).unwrap(), "0 */6 * * *");
+        assert!(parse_every("45m", Schedule::Cron).is_err());
+        assert!(parse_every("45m", Schedule::Systemd).is_ok());
+        assert_eq!(quote(Path::new("/data/my show's")), r"'/data/my show'\''s'");
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use glob::Pattern;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::failure::ExtractionError;
+
+/// Suffix of the override file of a set, `show.rarscan.toml` next to `show.part01.rar`.
+pub const SUFFIX: &str = ".rarscan.toml";
+
+lazy_static! {
+    /// The volume number and extension of the first volume, what's left is the name of the set.
+    static ref SET_SUFFIX: Regex = Regex::new(r"(?i)(\.part\d+)?(\.tar)?\.[^.]+$").unwrap();
+}
+
+/// The keys a sidecar may set and the type of their value.
+const KEYS: &[(&str, &str)] = &[
+    ("skip", "a boolean"),
+    ("dest", "a string"),
+    ("password", "a string"),
+    ("exclude", "an array of strings"),
+    ("remove_after_hours", "an integer"),
+];
+
+/// Options of a single archive read from its sidecar, they take precedence over the command line for that archive
+/// only.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub path: PathBuf,
+    /// Leave the archive alone, parts included.
+    pub skip: bool,
+    /// Directory to extract into, relative to the directory of the sidecar.
+    pub dest: Option<PathBuf>,
+    pub password: Option<String>,
+    /// Entries not extracted, matched like the patterns of an ignore file.
+    pub exclude: Vec<Pattern>,
+    pub remove_after: Option<Duration>,
+    /// Keys set by the sidecar, in the order of the file.
+    keys: Vec<&'static str>,
+}
+
+/// The sidecar of the set whose first volume is `archive`.
+pub fn path_of(archive: &Path) -> PathBuf {
+    archive.with_file_name(format!("{}{}", set_name(archive), SUFFIX))
+}
+
+/// The name of the set whose first volume is `archive`, `show` for `show.part01.rar`.
+pub fn set_name(archive: &Path) -> String {
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    SET_SUFFIX.replace(&name, "").into_owned()
+}
+
+impl Overrides {
+    /// Reads the sidecar next to `archive`, `None` when it has none.
+    pub fn load(archive: &Path) -> Result<Option<Overrides>, ExtractionError> {
+        let path = path_of(archive);
+        let invalid = |line, message| ExtractionError::InvalidSidecar {
+            path: path.clone(),
+            line,
+            message,
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(invalid(None, e.to_string())),
+        };
+        let mut overrides = Overrides {
+            path: path.clone(),
+            ..Overrides::default()
+        };
+        for (index, line) in text.lines().enumerate() {
+            let Some((key, value)) = parse_line(line).map_err(|e| invalid(Some(index + 1), e))? else {
+                continue;
+            };
+            overrides.set(&key, value).map_err(|e| invalid(Some(index + 1), e))?;
+        }
+        Ok(Some(overrides))
+    }
+
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        let Some(&(key, expected)) = KEYS.iter().find(|(name, _)| *name == key) else {
+            let known: Vec<&str> = KEYS.iter().map(|(name, _)| *name).collect();
+            return Err(format!("unknown key '{}', expected one of {}", key, known.join(", ")));
+        };
+        if self.keys.contains(&key) {
+            return Err(format!("key '{}' is set twice", key));
+        }
+        let mismatch = || format!("'{}' must be {}", key, expected);
+        match (key, value) {
+            ("skip", Value::Bool(skip)) => self.skip = skip,
+            ("dest", Value::String(dest)) if dest.is_empty() => return Err("'dest' is empty".into()),
+            ("dest", Value::String(dest)) => {
+                self.dest = Some(parent_dir(&self.path).join(dest));
+            }
+            ("password", Value::String(password)) => self.password = Some(password),
+            ("exclude", Value::Array(patterns)) => {
+                for pattern in patterns {
+                    let Value::String(pattern) = pattern else {
+                        return Err(mismatch());
+                    };
+                    let pattern =
+                        Pattern::new(&pattern).map_err(|e| format!("invalid pattern '{}': {}", pattern, e))?;
+                    self.exclude.push(pattern);
+                }
+            }
+            ("remove_after_hours", Value::Integer(hours)) => {
+                let hours = u64::try_from(hours).map_err(|_| "'remove_after_hours' is negative".to_string())?;
+                self.remove_after = Some(Duration::from_secs(60 * 60 * hours));
+            }
+            _ => return Err(mismatch()),
+        }
+        self.keys.push(key);
+        Ok(())
+    }
+
+    /// Keys set by the sidecar.
+    pub fn keys(&self) -> &[&'static str] {
+        &self.keys
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Whether a pattern of `exclude` matches the entry `name` or one of the directories holding it. A pattern without a
+/// slash matches a name at any depth.
+pub fn is_excluded(exclude: &[Pattern], name: &Path) -> bool {
+    exclude.iter().any(|pattern| {
+        let name_only = !pattern.as_str().contains('/');
+        name.ancestors()
+            .filter(|path| !path.as_os_str().is_empty())
+            .any(|path| match name_only {
+                true => path
+                    .file_name()
+                    .is_some_and(|name| pattern.matches(&name.to_string_lossy())),
+                false => pattern.matches_path(path),
+            })
+    })
+}
+
+/// A value of the subset of TOML sidecars are written in.
+#[derive(Debug, PartialEq)]
+enum Value {
+    String(String),
+    Bool(bool),
+    Integer(i64),
+    Array(Vec<Value>),
+}
+
+/// The key and value of a `key = value` line, `None` for blank lines and comments.
+fn parse_line(line: &str) -> Result<Option<(String, Value)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    if line.starts_with('[') {
+        return Err("tables are not supported, set the keys at the top level".into());
+    }
+    let Some((key, value)) = line.split_once('=') else {
+        return Err(format!("expected 'key = value', got '{}'", line));
+    };
+    let key = key.trim();
+    let key = match key.strip_prefix('"').and_then(|key| key.strip_suffix('"')) {
+        Some(key) => key,
+        None if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') => key,
+        None => return Err(format!("invalid key '{}'", key)),
+    };
+    let (value, rest) = parse_value(value.trim_start())?;
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected '{}' after the value of '{}'", rest, key));
+    }
+    Ok(Some((key.to_string(), value)))
+}
+
+/// Parses the value at the start of `s`, returns it with what follows.
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(value), &rest[i + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        return Err("unterminated string".into());
+    }
+    if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), after));
+            }
+            let (value, after) = parse_value(rest)?;
+            values.push(value);
+            rest = after.trim_start();
+            match rest.strip_prefix(',') {
+                Some(after) => rest = after,
+                None if rest.starts_with(']') => {}
+                None => return Err("expected ',' or ']' in array, arrays must fit on one line".into()),
+            }
+        }
+    }
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+        .unwrap_or(s.len());
+    let (word, rest) = s.split_at(end);
+    match word {
+        "true" => Ok((Value::Bool(true), rest)),
+        "false" => Ok((Value::Bool(false), rest)),
+        "" => Err("missing value".into()),
+        _ => match word.replace('_', "").parse() {
+            Ok(integer) => Ok((Value::Integer(integer), rest)),
+            Err(_) => Err(format!("invalid value '{}'", word)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sidecar_lines() {
+        assert_eq!(parse_line("  # comment").unwrap(), None);
+        assert_eq!(
+            parse_line(r#"dest = "movies/\"x\"" # where"#).unwrap(),
+            Some(("dest".into(), Value::String("movies/\"x\"".into())))
+        );
+        assert_eq!(
+            parse_line("exclude = ['Sample', \"*.nfo\",]").unwrap(),
+            Some((
+                "exclude".into(),
+                Value::Array(vec![Value::String("Sample".into()), Value::String("*.nfo".into())])
+            ))
+        );
+        assert_eq!(
+            parse_line("remove_after_hours = 1_000").unwrap(),
+            Some(("remove_after_hours".into(), Value::Integer(1000)))
+        );
+        assert!(parse_line("[rarscan]").is_err());
+        assert!(parse_line("skip = yes").is_err());
+        assert!(parse_line("dest = \"movies").is_err());
+        assert!(parse_line("exclude = [\"a\"").is_err());
+
+        let mut overrides = Overrides {
+            path: PathBuf::from("/tv/show/show.rarscan.toml"),
+            ..Overrides::default()
+        };
+        overrides.set("dest", Value::String("../done".into())).unwrap();
+        assert_eq!(overrides.dest, Some(PathBuf::from("/tv/show/../done")));
+        assert!(overrides.set("dest", Value::String("again".into())).is_err());
+        assert!(overrides.set("skip", Value::String("true".into())).is_err());
+        assert!(overrides.set("remove_after_hours", Value::Integer(-1)).is_err());
+        let unknown = overrides.set("pasword", Value::String("x".into())).unwrap_err();
+        assert!(unknown.starts_with("unknown key 'pasword'"), "{}", unknown);
+        assert_eq!(overrides.keys(), ["dest"]);
+    }
+
+    #[test]
+    fn sidecars_are_named_after_the_set() {
+        assert_eq!(
+            path_of(Path::new("/tv/show.part01.rar")),
+            PathBuf::from("/tv/show.rarscan.toml")
+        );
+        assert_eq!(
+            path_of(Path::new("show.s01e01.rar")),
+            PathBuf::from("show.s01e01.rarscan.toml")
+        );
+        assert_eq!(
+            path_of(Path::new("/a/movie.tar.gz")),
+            PathBuf::from("/a/movie.rarscan.toml")
+        );
+
+        let exclude = [Pattern::new("Sample").unwrap(), Pattern::new("extras/*.nfo").unwrap()];
+        assert!(is_excluded(&exclude, Path::new("Sample")));
+        assert!(is_excluded(&exclude, Path::new("movie/Sample/sample.mkv")));
+        assert!(is_excluded(&exclude, Path::new("extras/info.nfo")));
+        assert!(!is_excluded(&exclude, Path::new("movie/extras/info.nfo")));
+        assert!(!is_excluded(&exclude, Path::new("movie/movie.mkv")));
+    }
+}
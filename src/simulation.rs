@@ -0,0 +1,62 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{archive::Archive, recovery, sidecar};
+
+/// Directories created by the process for simulations, for unique names.
+static SIMULATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Levels of nesting a deep dry-run looks into at most.
+pub const MAX_DEPTH: usize = 8;
+
+/// A directory of its own the volumes of a nested set are extracted into for a deep dry-run, removed when dropped.
+pub struct SimulationDir {
+    path: PathBuf,
+}
+
+impl SimulationDir {
+    pub fn create() -> io::Result<SimulationDir> {
+        let path = std::env::temp_dir().join(format!(
+            "rarscan-simulated-{}-{}",
+            process::id(),
+            SIMULATED.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path)?;
+        Ok(SimulationDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SimulationDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The entries of `parent` making up the set of its nested archive `name`: the files next to it with its set name and
+/// named like volumes. With their total size.
+pub fn nested_set(parent: &Archive, name: &Path) -> (HashSet<PathBuf>, u64) {
+    let set = sidecar::set_name(name);
+    let volumes: Vec<_> = parent
+        .headers
+        .iter()
+        .filter(|header| header.is_file() && header.filename.parent() == name.parent())
+        .filter(|header| {
+            header.filename == name
+                || recovery::volume_number(&header.filename).is_some() && sidecar::set_name(&header.filename) == set
+        })
+        .collect();
+    let size = volumes.iter().map(|header| header.unpacked_size).sum();
+    (
+        volumes.into_iter().map(|header| header.filename.clone()).collect(),
+        size,
+    )
+}
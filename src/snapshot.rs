@@ -0,0 +1,203 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use time::{format_description, OffsetDateTime};
+
+use crate::fds;
+
+/// A snapshot taken before removing files from a filesystem.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Last argument of the command that took it, the path or name of the snapshot for `btrfs` and `zfs`.
+    pub name: String,
+    /// Root of the filesystem, subvolume or dataset snapshotted.
+    pub volume: PathBuf,
+    pub created: SystemTime,
+}
+
+/// A command given as a single string, split on whitespace, with `{name}` placeholders replaced in each word.
+#[derive(Debug, Clone)]
+pub struct CommandTemplate {
+    words: Vec<String>,
+}
+
+impl CommandTemplate {
+    pub fn parse(s: &str) -> Result<CommandTemplate, String> {
+        let words: Vec<String> = s.split_whitespace().map(str::to_string).collect();
+        if words.is_empty() {
+            return Err("empty command".into());
+        }
+        Ok(CommandTemplate { words })
+    }
+
+    /// Whether `{name}` is in the command.
+    pub fn uses(&self, name: &str) -> bool {
+        let placeholder = format!("{{{}}}", name);
+        self.words.iter().any(|word| word.contains(&placeholder))
+    }
+
+    pub fn expand(&self, values: &[(&str, &str)]) -> Vec<String> {
+        self.words
+            .iter()
+            .map(|word| {
+                values.iter().fold(word.clone(), |word, (name, value)| {
+                    word.replace(&format!("{{{}}}", name), value)
+                })
+            })
+            .collect()
+    }
+}
+
+/// Takes a snapshot of each filesystem before the first removal in it, and deletes the snapshots past the retention.
+pub struct Snapshots {
+    create: CommandTemplate,
+    delete: Option<CommandTemplate>,
+    retention: Option<Duration>,
+    best_effort: bool,
+    /// Shared by the snapshots of the run, taken at the first one.
+    timestamp: Option<String>,
+    /// Volumes snapshotted by this run, or that failed to be.
+    volumes: HashSet<PathBuf>,
+}
+
+impl Snapshots {
+    pub fn new(create: CommandTemplate, best_effort: bool) -> Snapshots {
+        Snapshots {
+            create,
+            delete: None,
+            retention: None,
+            best_effort,
+            timestamp: None,
+            volumes: HashSet::new(),
+        }
+    }
+
+    /// Deletes the snapshots older than `retention` with `delete`.
+    pub fn with_retention(mut self, retention: Duration, delete: CommandTemplate) -> Snapshots {
+        self.retention = Some(retention);
+        self.delete = Some(delete);
+        self
+    }
+
+    /// Whether removals go on when a snapshot fails.
+    pub fn best_effort(&self) -> bool {
+        self.best_effort
+    }
+
+    /// Snapshots the filesystem of `path` unless this run did already, or tried to. `{path}` in the command is the root
+    /// of the filesystem and `{ts}` the time of the first snapshot of the run.
+    pub fn before_removing(&mut self, path: &Path) -> anyhow::Result<Option<Snapshot>> {
+        let volume = volume_of(path.parent().unwrap_or(path));
+        if !self.volumes.insert(volume.clone()) {
+            return Ok(None);
+        }
+        let timestamp = self.timestamp.get_or_insert_with(timestamp).clone();
+        let volume_str = volume.to_string_lossy();
+        let command = self.create.expand(&[("path", &volume_str), ("ts", &timestamp)]);
+        run(&command).with_context(|| format!("snapshot '{}'", volume.display()))?;
+        Ok(Some(Snapshot {
+            name: command.last().cloned().unwrap_or_default(),
+            volume,
+            created: SystemTime::now(),
+        }))
+    }
+
+    /// Whether `snapshot` is past the retention as of `now`.
+    pub fn expired(&self, snapshot: &Snapshot, now: SystemTime) -> bool {
+        self.retention
+            .is_some_and(|retention| now.duration_since(snapshot.created).unwrap_or_default() > retention)
+    }
+
+    /// Deletes `snapshot`, `{snapshot}` in the command is its name and `{path}` the filesystem it's of.
+    pub fn delete(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let Some(delete) = &self.delete else {
+            return Ok(());
+        };
+        let volume = snapshot.volume.to_string_lossy();
+        let command = delete.expand(&[("snapshot", &snapshot.name), ("path", &volume)]);
+        run(&command).with_context(|| format!("delete snapshot '{}'", snapshot.name))
+    }
+}
+
+fn run(command: &[String]) -> anyhow::Result<()> {
+    // Both ends of the stderr pipe until the command is spawned.
+    let _fds = fds::acquire(2);
+    let output = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .with_context(|| format!("spawn '{}'", command[0]))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::ensure!(
+        output.status.success(),
+        "'{}' failed ({}): {}",
+        command.join(" "),
+        output.status,
+        stderr.trim()
+    );
+    Ok(())
+}
+
+/// UTC, compact enough for the name of a snapshot: `20240131T235959Z`.
+fn timestamp() -> String {
+    let format = format_description::parse("[year][month][day]T[hour][minute][second]Z").expect("timestamp format");
+    OffsetDateTime::now_utc()
+        .format(&format)
+        .unwrap_or_else(|_| "unknown".into())
+}
+
+/// The topmost directory above `dir` on the same device, which is the mount point of its filesystem or the root of
+/// its btrfs subvolume.
+#[cfg(unix)]
+fn volume_of(dir: &Path) -> PathBuf {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let Ok(dev) = fs::metadata(&dir).map(|md| md.dev()) else {
+        return dir;
+    };
+    let mut volume = dir.as_path();
+    while let Some(parent) = volume.parent() {
+        if fs::metadata(parent).map(|md| md.dev()).ok() != Some(dev) {
+            break;
+        }
+        volume = parent;
+    }
+    volume.to_path_buf()
+}
+
+#[cfg(not(unix))]
+fn volume_of(dir: &Path) -> PathBuf {
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    dir.ancestors().last().map(Path::to_path_buf).unwrap_or(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_templates() {
+        let template = CommandTemplate::parse("btrfs subvolume snapshot -r {path} {path}/.rarscan-snap-{ts}").unwrap();
+        assert_eq!(
+            template.expand(&[("path", "/srv"), ("ts", "20240131T235959Z")]),
+            [
+                "btrfs",
+                "subvolume",
+                "snapshot",
+                "-r",
+                "/srv",
+                "/srv/.rarscan-snap-20240131T235959Z"
+            ]
+        );
+        assert!(CommandTemplate::parse("  ").is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::{io, path::Path};
+
+/// Space available to unprivileged users on a filesystem.
+#[derive(Debug, Clone, Copy)]
+pub struct FreeSpace {
+    pub bytes: u64,
+    /// `None` when the filesystem doesn't report inode counts.
+    pub inodes: Option<u64>,
+    pub usage: Usage,
+}
+
+/// The sizes of a filesystem, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub size: u64,
+    pub free: u64,
+    /// Free to unprivileged users, the blocks reserved for root left out.
+    pub available: u64,
+}
+
+impl Usage {
+    /// The percentage of the filesystem in use once `bytes` more are written, counted like df does: the blocks
+    /// reserved for root are neither used nor available, so a full filesystem is at 100% for unprivileged users.
+    pub fn projected_percent(&self, bytes: u64) -> f64 {
+        let used = self.size.saturating_sub(self.free);
+        let usable = used + self.available;
+        if usable == 0 {
+            return 100.0;
+        }
+        (used + bytes) as f64 * 100.0 / usable as f64
+    }
+}
+
+/// Free space on the filesystem that holds `path`, or would hold it once created. `None` when the platform can't tell.
+pub fn free_space(path: &Path) -> io::Result<Option<FreeSpace>> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no existing ancestor"))?;
+    imp::free_space(existing)
+}
+
+/// Identifier of the filesystem holding `path`.
+pub fn device(path: &Path) -> io::Result<u64> {
+    imp::device(path)
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{ffi::CString, io, mem, os::unix::ffi::OsStrExt, path::Path};
+
+    use super::{FreeSpace, Usage};
+
+    pub fn free_space(path: &Path) -> io::Result<Option<FreeSpace>> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: `path` is a valid C string and `stat` is only read after statvfs succeeded.
+        let stat = unsafe {
+            let mut stat: libc::statvfs = mem::zeroed();
+            if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            stat
+        };
+        Ok(Some(of_stat(stat)))
+    }
+
+    #[allow(clippy::unnecessary_cast)] // The fields of statvfs aren't u64 on every platform.
+    pub fn of_stat(stat: libc::statvfs) -> FreeSpace {
+        let block = stat.f_frsize as u64;
+        FreeSpace {
+            bytes: stat.f_bavail as u64 * block,
+            // Filesystems without fixed inode tables, such as btrfs, report zero inodes.
+            inodes: (stat.f_files != 0).then_some(stat.f_favail as u64),
+            usage: Usage {
+                size: stat.f_blocks as u64 * block,
+                free: stat.f_bfree as u64 * block,
+                available: stat.f_bavail as u64 * block,
+            },
+        }
+    }
+
+    pub fn device(path: &Path) -> io::Result<u64> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(path.metadata()?.dev())
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{io, path::Path};
+
+    use super::FreeSpace;
+
+    pub fn free_space(_: &Path) -> io::Result<Option<FreeSpace>> {
+        Ok(None)
+    }
+
+    /// Every path is assumed to be on the same filesystem.
+    pub fn device(_: &Path) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn stat(blocks: u64, bfree: u64, bavail: u64) -> libc::statvfs {
+        // SAFETY: statvfs is plain data, all zeroes is a valid value.
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        stat.f_frsize = 4096;
+        stat.f_blocks = blocks as _;
+        stat.f_bfree = bfree as _;
+        stat.f_bavail = bavail as _;
+        stat
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn usage_is_projected_like_df_counts_it() {
+        // 1000 blocks, 400 free, no reserved blocks.
+        let usage = imp::of_stat(stat(1000, 400, 400)).usage;
+        assert_eq!(usage.projected_percent(0), 60.0);
+        assert_eq!(usage.projected_percent(100 * 4096), 70.0);
+
+        // 50 blocks of the 400 free are reserved for root: 600 used of the 950 usable.
+        let reserved = imp::of_stat(stat(1000, 400, 350));
+        assert_eq!(reserved.bytes, 350 * 4096);
+        let percent = reserved.usage.projected_percent(0);
+        assert!((percent - 600.0 * 100.0 / 950.0).abs() < 1e-9, "{}", percent);
+        // Filling what's available reaches 100%, the reserved blocks still free.
+        assert_eq!(reserved.usage.projected_percent(350 * 4096), 100.0);
+        assert!(reserved.usage.projected_percent(400 * 4096) > 100.0);
+
+        // Everything free is reserved.
+        let full = imp::of_stat(stat(1000, 50, 0)).usage;
+        assert_eq!(full.projected_percent(0), 100.0);
+        assert_eq!(imp::of_stat(stat(0, 0, 0)).usage.projected_percent(0), 100.0);
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::VecDeque,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+/// Queue of archive paths which keeps at most `capacity` of them in memory when bounded. The tail is written to a
+/// spill file and read back as the front drains, the order is the same as if everything was kept in memory.
+#[derive(Default)]
+pub struct SpillQueue {
+    memory: VecDeque<PathBuf>,
+    bound: Option<(usize, PathBuf)>,
+    spill: Option<Spill>,
+}
+
+/// Paths written to the spill file and not read back yet, separated by NUL bytes which can't appear in paths.
+struct Spill {
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+    pending: usize,
+}
+
+impl SpillQueue {
+    /// Queue keeping at most `capacity` paths in memory and the others in the file at `spill_path`.
+    pub fn bounded(capacity: usize, spill_path: PathBuf) -> SpillQueue {
+        SpillQueue {
+            memory: VecDeque::new(),
+            bound: Some((capacity.max(1), spill_path)),
+            spill: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.spill.as_ref().map_or(0, |spill| spill.pending)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push_back(&mut self, path: PathBuf) -> anyhow::Result<()> {
+        let Some((capacity, spill_path)) = &self.bound else {
+            self.memory.push_back(path);
+            return Ok(());
+        };
+        if self.spill.is_none() && self.memory.len() < *capacity {
+            self.memory.push_back(path);
+            return Ok(());
+        }
+        if self.spill.is_none() {
+            log::debug!(
+                "Queue is over {} archives, spilling to '{}'.",
+                capacity,
+                spill_path.display()
+            );
+            self.spill = Some(Spill::create(spill_path).context("create queue spill file")?);
+        }
+        let spill = self.spill.as_mut().expect("spill file created");
+        spill.writer.write_all(path.as_os_str().as_encoded_bytes())?;
+        spill.writer.write_all(b"\0")?;
+        spill.pending += 1;
+        Ok(())
+    }
+
+    /// Puts `path` first, in memory even when that goes over the capacity.
+    pub fn push_front(&mut self, path: PathBuf) {
+        self.memory.push_front(path);
+    }
+
+    pub fn pop_front(&mut self) -> anyhow::Result<Option<PathBuf>> {
+        self.refill()?;
+        Ok(self.memory.pop_front())
+    }
+
+    pub fn front(&mut self) -> anyhow::Result<Option<&PathBuf>> {
+        self.refill()?;
+        Ok(self.memory.front())
+    }
+
+    /// Whether `path` is queued. Only the paths in memory are looked at.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.memory.iter().any(|queued| queued == path)
+    }
+
+    /// Keeps the queued paths `keep` accepts, in order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Path) -> anyhow::Result<bool>) -> anyhow::Result<()> {
+        for _ in 0..self.len() {
+            let path = self.pop_front()?.expect("queue length");
+            if keep(&path)? {
+                self.push_back(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads spilled paths back once memory is empty, up to the capacity. The spill file is removed when drained.
+    fn refill(&mut self) -> anyhow::Result<()> {
+        let (Some(spill), Some((capacity, spill_path))) = (&mut self.spill, &self.bound) else {
+            return Ok(());
+        };
+        if !self.memory.is_empty() {
+            return Ok(());
+        }
+        spill.writer.flush().context("write queue spill file")?;
+        let mut buf = Vec::new();
+        while spill.pending > 0 && self.memory.len() < *capacity {
+            buf.clear();
+            spill.reader.read_until(0, &mut buf).context("read queue spill file")?;
+            buf.pop();
+            // SAFETY: the bytes were written from an OsStr by this process.
+            let path = unsafe { OsStr::from_encoded_bytes_unchecked(&buf) };
+            self.memory.push_back(PathBuf::from(path));
+            spill.pending -= 1;
+        }
+        if spill.pending == 0 {
+            self.spill = None;
+            fs::remove_file(spill_path).context("remove queue spill file")?;
+        }
+        Ok(())
+    }
+}
+
+impl Spill {
+    fn create(path: &Path) -> io::Result<Spill> {
+        let writer = File::create(path)?;
+        let reader = File::open(path)?;
+        Ok(Spill {
+            writer: BufWriter::new(writer),
+            reader: BufReader::new(reader),
+            pending: 0,
+        })
+    }
+}
+
+impl Drop for SpillQueue {
+    fn drop(&mut self) {
+        if let (Some(_), Some((_, spill_path))) = (self.spill.take(), &self.bound) {
+            let _ = fs::remove_file(spill_path);
+        }
+    }
+}
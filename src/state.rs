@@ -0,0 +1,1320 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    fds, format_size, format_system_time, paths,
+    removal::RemovalSet,
+    snapshot::Snapshot,
+    verify::{Checkpoint, FileKey},
+    walk::{Listing, Listings},
+};
+
+pub const DEFAULT_STATE_FILE: &str = ".rarscan-state.json";
+
+/// Version of the file written. Paths are kept in their canonical spelling since version 2, archives have an id since
+/// version 3.
+const VERSION: u64 = 3;
+
+/// How often the entries of paths that are gone are dropped.
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long what is recorded below the directory of an archive that is gone is kept, for the archive to show up
+/// somewhere else with its id.
+const MOVE_GRACE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A file written by an extraction.
+#[derive(Debug, Clone)]
+pub struct Extracted {
+    pub archive: PathBuf,
+    pub time: SystemTime,
+}
+
+/// A file of an extraction moved out of the root directory by --archive-extracted-to, counted as extracted still.
+#[derive(Debug, Clone)]
+pub struct Tiered {
+    pub archive: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Archives nested in a root archive, the one not nested in any other, each with the archive it was found in.
+#[derive(Debug, Clone, Default)]
+pub struct Chain {
+    pub nested: Vec<(PathBuf, PathBuf)>,
+    /// When a member was first found deleted by something else.
+    pub broken_since: Option<SystemTime>,
+}
+
+/// Where an archive with a given id was last seen, and the key of its first part then.
+#[derive(Debug, Clone)]
+struct Sighting {
+    path: PathBuf,
+    key: FileKey,
+    seen: SystemTime,
+}
+
+/// `path` moved from below `from` to below `to`, `None` when it isn't below `from`.
+fn rebase(path: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    path.strip_prefix(from).ok().map(|rest| to.join(rest))
+}
+
+fn secs(t: SystemTime) -> f64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+fn from_secs(secs: f64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs_f64(secs)
+}
+
+/// Nanoseconds, for the mtimes compared for equality.
+fn nanos(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+fn listing_from_json(value: &Value) -> Option<Listing> {
+    let names = |key: &str| -> Option<Vec<PathBuf>> {
+        value
+            .get(key)?
+            .as_array()?
+            .iter()
+            .map(|name| name.as_str().map(PathBuf::from))
+            .collect()
+    };
+    Some(Listing {
+        mtime: from_nanos(value.get("mtime")?.as_u64()?),
+        dirs: names("dirs")?,
+        files: names("files")?,
+    })
+}
+
+/// The previous version of the state file at `path`, kept when it's replaced.
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("json.bak")
+}
+
+/// Whether something is at `path`, a dangling link counts.
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// Content of the file at `path`, `None` when it's missing.
+fn read(path: &Path) -> anyhow::Result<Option<String>> {
+    let _fds = fds::acquire(1);
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("read state file '{}'", path.display())),
+    }
+}
+
+fn key_to_json(key: &FileKey) -> Value {
+    json!({
+        "size": key.size,
+        "mtime": key.mtime,
+        "inode": key.inode,
+    })
+}
+
+fn key_from_json(value: &Value) -> Option<FileKey> {
+    Some(FileKey {
+        size: value.get("size")?.as_u64()?,
+        mtime: value.get("mtime")?.as_f64()?,
+        inode: value.get("inode")?.as_u64()?,
+    })
+}
+
+/// Data kept between runs: the mtimes that couldn't be set on filesystems refusing it and the files written by
+/// extractions, keyed by path.
+pub struct StateFile {
+    path: PathBuf,
+    mtimes: HashMap<PathBuf, SystemTime>,
+    extracted: HashMap<PathBuf, Extracted>,
+    /// Extracted files moved to the tier directory, by where they were extracted.
+    tiered: HashMap<PathBuf, Tiered>,
+    /// Bytes written per second by the extractions of the last run that extracted anything.
+    throughput: Option<f64>,
+    /// Archives whose destination filled up during their extraction, with the free bytes needed to try again.
+    blocked: HashMap<PathBuf, u64>,
+    /// CRC32 of extracted files as last hashed, valid while the file still has the same key, and when they were.
+    verified: HashMap<PathBuf, (FileKey, u32, SystemTime)>,
+    /// How long a hash is trusted for the already-extracted check, its CRC is still what `audit` checks against later.
+    verified_max_age: Duration,
+    /// Progress of files whose hashing was interrupted.
+    checkpoints: HashMap<PathBuf, Checkpoint>,
+    /// Chains of nested archives, by their root archive.
+    chains: HashMap<PathBuf, Chain>,
+    /// Where each archive was last seen, by its id. The path changes when the same archive shows up somewhere else,
+    /// what is recorded about it follows.
+    archives: HashMap<String, Sighting>,
+    /// Whether the file is from before archives had an id, they are given one before anything else.
+    unidentified: bool,
+    /// The first archive extracted with each fingerprint.
+    fingerprints: HashMap<String, PathBuf>,
+    /// Times each archive was extracted again because its files changed.
+    flaps: HashMap<PathBuf, u32>,
+    /// Archives whose files the scanner flagged, with their fingerprint. They aren't extracted again until it changes.
+    quarantined: HashMap<PathBuf, String>,
+    /// Archives whose processing panicked, with the fingerprint of their first part and the number of runs in a row it
+    /// did. They are left alone once it happened --panic-limit times, until the first part changes.
+    panics: HashMap<PathBuf, (String, u32)>,
+    /// What the last scan found in the directories that didn't change during it.
+    listings: Listings,
+    /// Removals recorded but not applied yet.
+    removals: RemovalSet,
+    /// Snapshots taken before removals, until they are deleted.
+    snapshots: Vec<Snapshot>,
+    /// Archives moved to the front or dropped from the queue through the control socket during the current run, in
+    /// order, applied again by the next run when this one doesn't finish.
+    prioritized: Vec<PathBuf>,
+    dropped: Vec<PathBuf>,
+    compacted_at: Option<SystemTime>,
+    /// Whether the file was corrupt, it must not replace the backup.
+    recovered: bool,
+    dirty: bool,
+}
+
+impl StateFile {
+    /// Loads the state at `path`, a missing file is an empty state. A corrupt file is replaced by its backup, or by an
+    /// empty state when `reset` is set and there's no usable backup.
+    pub fn load(path: impl Into<PathBuf>, reset: bool) -> anyhow::Result<StateFile> {
+        let path = path.into();
+        let mut state = StateFile {
+            path,
+            mtimes: HashMap::new(),
+            extracted: HashMap::new(),
+            tiered: HashMap::new(),
+            throughput: None,
+            blocked: HashMap::new(),
+            verified: HashMap::new(),
+            verified_max_age: Duration::MAX,
+            checkpoints: HashMap::new(),
+            chains: HashMap::new(),
+            archives: HashMap::new(),
+            unidentified: false,
+            fingerprints: HashMap::new(),
+            flaps: HashMap::new(),
+            quarantined: HashMap::new(),
+            panics: HashMap::new(),
+            listings: Listings::new(),
+            removals: RemovalSet::default(),
+            snapshots: Vec::new(),
+            prioritized: Vec::new(),
+            dropped: Vec::new(),
+            compacted_at: None,
+            recovered: false,
+            dirty: false,
+        };
+        let Some(content) = read(&state.path)? else {
+            return Ok(state);
+        };
+        let value = match serde_json::from_str::<Map<String, Value>>(&content) {
+            Ok(value) => Value::Object(value),
+            Err(e) => {
+                log::warn!("State file '{}' is corrupt: {}", state.path.display(), e);
+                state.recovered = true;
+                state.dirty = true;
+                state.recover(reset)?
+            }
+        };
+        if let Some(mtimes) = value.get("mtimes").and_then(Value::as_object) {
+            for (path, secs) in mtimes {
+                if let Some(secs) = secs.as_f64() {
+                    state.mtimes.insert(PathBuf::from(path), from_secs(secs));
+                }
+            }
+        }
+        if let Some(extracted) = value.get("extracted").and_then(Value::as_object) {
+            for (path, entry) in extracted {
+                let archive = entry.get("archive").and_then(Value::as_str);
+                let time = entry.get("time").and_then(Value::as_f64);
+                if let (Some(archive), Some(time)) = (archive, time) {
+                    let entry = Extracted {
+                        archive: PathBuf::from(archive),
+                        time: from_secs(time),
+                    };
+                    state.extracted.insert(PathBuf::from(path), entry);
+                }
+            }
+        }
+        if let Some(tiered) = value.get("tiered").and_then(Value::as_object) {
+            for (path, entry) in tiered {
+                let archive = entry.get("archive").and_then(Value::as_str);
+                let to = entry.get("to").and_then(Value::as_str);
+                if let (Some(archive), Some(to)) = (archive, to) {
+                    let entry = Tiered {
+                        archive: PathBuf::from(archive),
+                        to: PathBuf::from(to),
+                    };
+                    state.tiered.insert(PathBuf::from(path), entry);
+                }
+            }
+        }
+        if let Some(blocked) = value.get("blocked").and_then(Value::as_object) {
+            for (path, required) in blocked {
+                if let Some(required) = required.as_u64() {
+                    state.blocked.insert(PathBuf::from(path), required);
+                }
+            }
+        }
+        if let Some(quarantined) = value.get("quarantined").and_then(Value::as_object) {
+            for (path, fingerprint) in quarantined {
+                if let Some(fingerprint) = fingerprint.as_str() {
+                    state.quarantined.insert(PathBuf::from(path), fingerprint.to_string());
+                }
+            }
+        }
+        if let Some(panics) = value.get("panics").and_then(Value::as_object) {
+            for (path, entry) in panics {
+                let fingerprint = entry.get("fingerprint").and_then(Value::as_str);
+                let count = entry.get("count").and_then(Value::as_u64);
+                if let (Some(fingerprint), Some(count)) = (fingerprint, count) {
+                    state
+                        .panics
+                        .insert(PathBuf::from(path), (fingerprint.to_string(), count as u32));
+                }
+            }
+        }
+        if let Some(listings) = value.get("listings").and_then(Value::as_object) {
+            for (path, entry) in listings {
+                if let Some(listing) = listing_from_json(entry) {
+                    state.listings.insert(PathBuf::from(path), listing);
+                }
+            }
+        }
+        if let Some(verified) = value.get("verified").and_then(Value::as_object) {
+            for (path, entry) in verified {
+                let crc = entry.get("crc").and_then(Value::as_u64);
+                // Rows from before the time was recorded count as verified now.
+                let time = entry
+                    .get("time")
+                    .and_then(Value::as_f64)
+                    .map_or_else(SystemTime::now, from_secs);
+                if let (Some(key), Some(crc)) = (key_from_json(entry), crc) {
+                    state.verified.insert(PathBuf::from(path), (key, crc as u32, time));
+                }
+            }
+        }
+        if let Some(checkpoints) = value.get("checkpoints").and_then(Value::as_object) {
+            for (path, entry) in checkpoints {
+                let offset = entry.get("offset").and_then(Value::as_u64);
+                let crc = entry.get("crc").and_then(Value::as_u64);
+                if let (Some(key), Some(offset), Some(crc)) = (key_from_json(entry), offset, crc) {
+                    let checkpoint = Checkpoint {
+                        key,
+                        offset,
+                        crc: crc as u32,
+                    };
+                    state.checkpoints.insert(PathBuf::from(path), checkpoint);
+                }
+            }
+        }
+        if let Some(chains) = value.get("chains").and_then(Value::as_object) {
+            for (root, entry) in chains {
+                let mut chain = Chain {
+                    nested: Vec::new(),
+                    broken_since: entry.get("broken_since").and_then(Value::as_f64).map(from_secs),
+                };
+                for nested in entry.get("nested").and_then(Value::as_array).into_iter().flatten() {
+                    let archive = nested.get("archive").and_then(Value::as_str);
+                    let parent = nested.get("parent").and_then(Value::as_str);
+                    if let (Some(archive), Some(parent)) = (archive, parent) {
+                        chain.nested.push((PathBuf::from(archive), PathBuf::from(parent)));
+                    }
+                }
+                state.chains.insert(PathBuf::from(root), chain);
+            }
+        }
+        if let Some(archives) = value.get("archives").and_then(Value::as_object) {
+            for (id, entry) in archives {
+                let path = entry.get("path").and_then(Value::as_str);
+                let seen = entry.get("seen").and_then(Value::as_f64);
+                if let (Some(path), Some(key), Some(seen)) = (path, key_from_json(entry), seen) {
+                    let sighting = Sighting {
+                        path: PathBuf::from(path),
+                        key,
+                        seen: from_secs(seen),
+                    };
+                    state.archives.insert(id.clone(), sighting);
+                }
+            }
+        }
+        if let Some(fingerprints) = value.get("fingerprints").and_then(Value::as_object) {
+            for (fingerprint, archive) in fingerprints {
+                if let Some(archive) = archive.as_str() {
+                    state.fingerprints.insert(fingerprint.clone(), PathBuf::from(archive));
+                }
+            }
+        }
+        if let Some(flaps) = value.get("flaps").and_then(Value::as_object) {
+            for (archive, count) in flaps {
+                if let Some(count) = count.as_u64() {
+                    state.flaps.insert(PathBuf::from(archive), count as u32);
+                }
+            }
+        }
+        for entry in value.get("snapshots").and_then(Value::as_array).into_iter().flatten() {
+            let name = entry.get("name").and_then(Value::as_str);
+            let volume = entry.get("volume").and_then(Value::as_str);
+            let created = entry.get("created").and_then(Value::as_f64);
+            if let (Some(name), Some(volume), Some(created)) = (name, volume, created) {
+                state.snapshots.push(Snapshot {
+                    name: name.to_string(),
+                    volume: PathBuf::from(volume),
+                    created: from_secs(created),
+                });
+            }
+        }
+        let paths = |key: &str| -> Vec<PathBuf> {
+            let paths = value.get("ctl").and_then(|ctl| ctl.get(key)).and_then(Value::as_array);
+            paths
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(PathBuf::from)
+                .collect()
+        };
+        state.prioritized = paths("prioritized");
+        state.dropped = paths("dropped");
+        if let Some(removals) = value.get("pending_removals") {
+            state.removals = RemovalSet::from_json(removals);
+        }
+        state.throughput = value.get("throughput").and_then(Value::as_f64);
+        state.compacted_at = value.get("compacted_at").and_then(Value::as_f64).map(from_secs);
+        let version = value.get("version").and_then(Value::as_u64).unwrap_or(1);
+        state.unidentified = version < 3 && state.len() > 0;
+        if version < 2 {
+            let rekeyed = state.canonicalize_paths();
+            if rekeyed > 0 {
+                log::info!(
+                    "Migrated the state file to canonical paths, {} entries re-keyed.",
+                    rekeyed
+                );
+                state.dirty = true;
+            }
+        }
+        Ok(state)
+    }
+
+    /// Spells every path of the state the way the queue does, once for the files written before it did. Returns the
+    /// number of entries whose path changed.
+    fn canonicalize_paths(&mut self) -> usize {
+        fn rekey<V>(map: &mut HashMap<PathBuf, V>, rekeyed: &mut usize) {
+            *map = map
+                .drain()
+                .map(|(path, value)| {
+                    let canonical = paths::canonical(&path);
+                    *rekeyed += usize::from(canonical != path);
+                    (canonical, value)
+                })
+                .collect();
+        }
+        let mut rekeyed = 0;
+        rekey(&mut self.mtimes, &mut rekeyed);
+        rekey(&mut self.extracted, &mut rekeyed);
+        rekey(&mut self.tiered, &mut rekeyed);
+        rekey(&mut self.blocked, &mut rekeyed);
+        rekey(&mut self.verified, &mut rekeyed);
+        rekey(&mut self.checkpoints, &mut rekeyed);
+        rekey(&mut self.chains, &mut rekeyed);
+        rekey(&mut self.flaps, &mut rekeyed);
+        rekey(&mut self.quarantined, &mut rekeyed);
+        rekey(&mut self.panics, &mut rekeyed);
+        rekey(&mut self.listings, &mut rekeyed);
+        let spelled = self
+            .extracted
+            .values_mut()
+            .map(|entry| &mut entry.archive)
+            .chain(self.tiered.values_mut().map(|entry| &mut entry.archive))
+            .chain(self.fingerprints.values_mut())
+            .chain(
+                self.chains
+                    .values_mut()
+                    .flat_map(|chain| chain.nested.iter_mut().flat_map(|(archive, parent)| [archive, parent])),
+            )
+            .chain(self.archives.values_mut().map(|sighting| &mut sighting.path))
+            .chain(self.prioritized.iter_mut())
+            .chain(self.dropped.iter_mut());
+        for path in spelled {
+            *path = paths::canonical(path);
+        }
+        rekeyed
+    }
+
+    /// Content of the backup, replacing the corrupt state file.
+    fn recover(&self, reset: bool) -> anyhow::Result<Value> {
+        let backup = backup_path(&self.path);
+        let content = read(&backup)?;
+        match content.as_deref().map(serde_json::from_str::<Map<String, Value>>) {
+            Some(Ok(value)) => {
+                log::warn!(
+                    "Using its backup '{}' instead, what the last run recorded is lost.",
+                    backup.display()
+                );
+                return Ok(Value::Object(value));
+            }
+            Some(Err(e)) => log::warn!("Its backup '{}' is corrupt too: {}", backup.display(), e),
+            None => log::warn!("It has no backup."),
+        }
+        if !reset {
+            bail!(
+                "state file '{}' is corrupt, use --reset-state to start from an empty state",
+                self.path.display()
+            );
+        }
+        log::warn!("Starting from an empty state as asked by --reset-state.");
+        Ok(Value::Object(Map::new()))
+    }
+
+    /// Whether there's something to compact and the last compaction was long enough ago, as of `now`.
+    pub fn compaction_due(&self, now: SystemTime) -> bool {
+        self.len() > 0
+            && self
+                .compacted_at
+                .and_then(|at| now.duration_since(at).ok())
+                .is_none_or(|since| since >= COMPACTION_INTERVAL)
+    }
+
+    /// Drops the entries of the paths that no longer exist as of `now`. Returns the number of entries dropped. What is
+    /// below the directory of an archive gone for less than the move grace is kept, it may have been renamed.
+    pub fn compact(&mut self, now: SystemTime) -> usize {
+        let before = self.len();
+        self.archives.retain(|_, sighting| {
+            exists(&sighting.path) || now.duration_since(sighting.seen).unwrap_or_default() < MOVE_GRACE
+        });
+        let moving: Vec<PathBuf> = self
+            .archives
+            .values()
+            .filter(|sighting| !exists(&sighting.path))
+            .filter_map(|sighting| sighting.path.parent().map(Path::to_path_buf))
+            .collect();
+        let exists = |path: &Path| exists(path) || moving.iter().any(|dir| path.starts_with(dir));
+        self.mtimes.retain(|path, _| exists(path));
+        self.extracted.retain(|path, _| exists(path));
+        // Gone with their archive, or from the tier directory.
+        self.tiered
+            .retain(|_, entry| exists(&entry.archive) && entry.to.exists());
+        self.blocked.retain(|archive, _| exists(archive));
+        self.verified.retain(|path, _| exists(path));
+        self.checkpoints.retain(|path, _| exists(path));
+        self.fingerprints.retain(|_, archive| exists(archive));
+        self.flaps.retain(|archive, _| exists(archive));
+        self.quarantined.retain(|archive, _| exists(archive));
+        self.panics.retain(|archive, _| exists(archive));
+        self.listings.retain(|dir, _| exists(dir));
+        self.compacted_at = Some(now);
+        self.dirty = true;
+        before - self.len()
+    }
+
+    /// Number of entries, removals pending and chains aside, which are dropped on their own.
+    fn len(&self) -> usize {
+        self.mtimes.len()
+            + self.extracted.len()
+            + self.tiered.len()
+            + self.blocked.len()
+            + self.verified.len()
+            + self.checkpoints.len()
+            + self.fingerprints.len()
+            + self.flaps.len()
+            + self.quarantined.len()
+            + self.panics.len()
+            + self.listings.len()
+            + self.archives.len()
+    }
+
+    /// Prints the number of entries of each kind and the size of the file and its backup.
+    pub fn print_stats(&self) {
+        let size = |path: &Path| match fs::metadata(path) {
+            Ok(md) => format_size(md.len()),
+            Err(_) => "missing".to_string(),
+        };
+        println!("{:<24} {}", "State file", self.path.display());
+        println!("{:<24} {}", "Size", size(&self.path));
+        println!("{:<24} {}", "Backup size", size(&backup_path(&self.path)));
+        println!("{:<24} {}", "Extracted files", self.extracted.len());
+        println!("{:<24} {}", "Tiered files", self.tiered.len());
+        println!("{:<24} {}", "Verified files", self.verified.len());
+        println!("{:<24} {}", "Hashing checkpoints", self.checkpoints.len());
+        println!("{:<24} {}", "Kept mtimes", self.mtimes.len());
+        println!("{:<24} {}", "Fingerprints", self.fingerprints.len());
+        println!("{:<24} {}", "Nested chains", self.chains.len());
+        println!("{:<24} {}", "Identified archives", self.archives.len());
+        println!("{:<24} {}", "Blocked archives", self.blocked.len());
+        println!("{:<24} {}", "Flapping archives", self.flaps.len());
+        println!("{:<24} {}", "Quarantined archives", self.quarantined.len());
+        println!("{:<24} {}", "Panicked archives", self.panics.len());
+        println!("{:<24} {}", "Directory listings", self.listings.len());
+        println!("{:<24} {}", "Pending removals", self.removals.len());
+        println!("{:<24} {}", "Snapshots", self.snapshots.len());
+        match self.compacted_at {
+            Some(at) => println!("{:<24} {}", "Last compaction", format_system_time(at)),
+            None => println!("{:<24} never", "Last compaction"),
+        }
+    }
+
+    pub fn mtime(&self, path: &Path) -> Option<SystemTime> {
+        self.mtimes.get(path).copied()
+    }
+
+    pub fn set_mtime(&mut self, path: &Path, mtime: SystemTime) {
+        self.mtimes.insert(path.to_path_buf(), mtime);
+        self.dirty = true;
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        if self.mtimes.remove(path).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_extracted(&mut self, path: &Path, archive: &Path, time: SystemTime) {
+        let entry = Extracted {
+            archive: archive.to_path_buf(),
+            time,
+        };
+        self.extracted.insert(path.to_path_buf(), entry);
+        self.dirty = true;
+    }
+
+    pub fn extracted(&self) -> impl Iterator<Item = (&Path, &Extracted)> {
+        self.extracted.iter().map(|(path, entry)| (path.as_path(), entry))
+    }
+
+    pub fn throughput(&self) -> Option<f64> {
+        self.throughput
+    }
+
+    pub fn set_throughput(&mut self, bytes_per_sec: f64) {
+        self.throughput = Some(bytes_per_sec);
+        self.dirty = true;
+    }
+
+    /// Free bytes needed before the blocked `archive` is tried again.
+    pub fn blocked(&self, archive: &Path) -> Option<u64> {
+        self.blocked.get(archive).copied()
+    }
+
+    pub fn block(&mut self, archive: &Path, required: u64) {
+        self.blocked.insert(archive.to_path_buf(), required);
+        self.dirty = true;
+    }
+
+    pub fn unblock(&mut self, archive: &Path) {
+        if self.blocked.remove(archive).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Fingerprint of the quarantined `archive` when its files were flagged.
+    pub fn quarantined(&self, archive: &Path) -> Option<&str> {
+        self.quarantined.get(archive).map(String::as_str)
+    }
+
+    pub fn quarantine(&mut self, archive: &Path, fingerprint: String) {
+        self.quarantined.insert(archive.to_path_buf(), fingerprint);
+        self.dirty = true;
+    }
+
+    pub fn unquarantine(&mut self, archive: &Path) {
+        if self.quarantined.remove(archive).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// The fingerprint of the first part of `archive` when its processing last panicked, and the runs in a row it did.
+    pub fn panics(&self, archive: &Path) -> Option<(&str, u32)> {
+        self.panics
+            .get(archive)
+            .map(|(fingerprint, count)| (fingerprint.as_str(), *count))
+    }
+
+    /// Counts one more panic processing `archive`, returns the new count. It starts over when the first part changed.
+    pub fn add_panic(&mut self, archive: &Path, fingerprint: String) -> u32 {
+        let entry = self.panics.entry(archive.to_path_buf()).or_default();
+        if entry.0 != fingerprint {
+            *entry = (fingerprint, 0);
+        }
+        entry.1 += 1;
+        self.dirty = true;
+        entry.1
+    }
+
+    pub fn forget_panics(&mut self, archive: &Path) {
+        if self.panics.remove(archive).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// The listings of the directories below `root`, for a scan to reuse.
+    pub fn listings(&self, root: &Path) -> Listings {
+        self.listings
+            .iter()
+            .filter(|(dir, _)| dir.starts_with(root))
+            .map(|(dir, listing)| (dir.clone(), listing.clone()))
+            .collect()
+    }
+
+    /// Replaces the listings below `root` by the ones of the last scan of it.
+    pub fn set_listings(&mut self, root: &Path, listings: Listings) {
+        let below = self.listings.keys().filter(|dir| dir.starts_with(root)).count();
+        if below == listings.len()
+            && listings
+                .iter()
+                .all(|(dir, listing)| self.listings.get(dir) == Some(listing))
+        {
+            return;
+        }
+        self.listings.retain(|dir, _| !dir.starts_with(root));
+        self.listings.extend(listings);
+        self.dirty = true;
+    }
+
+    /// CRC32 of the file at `path` as last hashed, when it still has the same key and was hashed less than the
+    /// verified max age ago.
+    pub fn verified(&self, path: &Path, key: FileKey) -> Option<u32> {
+        let now = SystemTime::now();
+        self.verified
+            .get(path)
+            .filter(|(verified_key, _, time)| {
+                *verified_key == key && now.duration_since(*time).unwrap_or_default() <= self.verified_max_age
+            })
+            .map(|&(_, crc, _)| crc)
+    }
+
+    /// Files hashed longer than `max_age` ago are hashed again when checked.
+    pub fn set_verified_max_age(&mut self, max_age: Duration) {
+        self.verified_max_age = max_age;
+    }
+
+    /// The files below `dir` with a CRC on record, with their key and CRC as last hashed, the ones hashed the longest
+    /// ago first.
+    pub fn audit_order(&self, dir: &Path) -> Vec<(PathBuf, FileKey, u32)> {
+        let mut files: Vec<_> = self.verified.iter().filter(|(path, _)| path.starts_with(dir)).collect();
+        files.sort_by(|(a, (_, _, a_time)), (b, (_, _, b_time))| a_time.cmp(b_time).then_with(|| a.cmp(b)));
+        files
+            .into_iter()
+            .map(|(path, &(key, crc, _))| (path.clone(), key, crc))
+            .collect()
+    }
+
+    /// The archive the file at `path` was extracted from.
+    pub fn extracted_from(&self, path: &Path) -> Option<&Path> {
+        self.extracted.get(path).map(|extracted| extracted.archive.as_path())
+    }
+
+    pub fn set_verified(&mut self, path: &Path, key: FileKey, crc: u32) {
+        self.checkpoints.remove(path);
+        self.verified.insert(path.to_path_buf(), (key, crc, SystemTime::now()));
+        self.dirty = true;
+    }
+
+    pub fn forget_verified(&mut self, path: &Path) {
+        self.checkpoints.remove(path);
+        if self.verified.remove(path).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Where the hashing of the file at `path` was interrupted, when it still has the same key.
+    pub fn checkpoint(&self, path: &Path, key: FileKey) -> Option<Checkpoint> {
+        self.checkpoints
+            .get(path)
+            .filter(|checkpoint| checkpoint.key == key)
+            .copied()
+    }
+
+    pub fn set_checkpoint(&mut self, path: &Path, checkpoint: Checkpoint) {
+        self.checkpoints.insert(path.to_path_buf(), checkpoint);
+        self.dirty = true;
+    }
+
+    /// The archive extracted first with `fingerprint`.
+    pub fn fingerprint(&self, fingerprint: &str) -> Option<&Path> {
+        self.fingerprints.get(fingerprint).map(PathBuf::as_path)
+    }
+
+    pub fn set_fingerprint(&mut self, fingerprint: String, archive: &Path) {
+        if self.fingerprints.get(&fingerprint).map(PathBuf::as_path) != Some(archive) {
+            self.fingerprints.insert(fingerprint, archive.to_path_buf());
+            self.dirty = true;
+        }
+    }
+
+    /// Root of the chain `archive` belongs to, itself when it's the root.
+    pub fn chain_root(&self, archive: &Path) -> Option<&Path> {
+        if let Some((root, _)) = self.chains.get_key_value(archive) {
+            return Some(root);
+        }
+        self.chains
+            .iter()
+            .find(|(_, chain)| chain.nested.iter().any(|(nested, _)| nested == archive))
+            .map(|(root, _)| root.as_path())
+    }
+
+    pub fn chain(&self, root: &Path) -> Option<&Chain> {
+        self.chains.get(root)
+    }
+
+    /// Records `archive` as found inside of `parent`, in the chain of `parent`.
+    pub fn add_nested(&mut self, parent: &Path, archive: &Path) {
+        let root = self.chain_root(parent).unwrap_or(parent).to_path_buf();
+        let chain = self.chains.entry(root).or_default();
+        if !chain.nested.iter().any(|(nested, _)| nested == archive) {
+            chain.nested.push((archive.to_path_buf(), parent.to_path_buf()));
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_chain_broken(&mut self, root: &Path, since: SystemTime) {
+        if let Some(chain) = self.chains.get_mut(root) {
+            chain.broken_since = Some(since);
+            self.dirty = true;
+        }
+    }
+
+    pub fn forget_chain(&mut self, root: &Path) {
+        if self.chains.remove(root).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Times `archive` was extracted again because its files changed.
+    pub fn flaps(&self, archive: &Path) -> u32 {
+        self.flaps.get(archive).copied().unwrap_or(0)
+    }
+
+    /// Counts one more extraction of `archive` over changed files, returns the new count.
+    pub fn add_flap(&mut self, archive: &Path) -> u32 {
+        let count = self.flaps.entry(archive.to_path_buf()).or_default();
+        *count += 1;
+        self.dirty = true;
+        *count
+    }
+
+    pub fn forget_flaps(&mut self, archive: &Path) {
+        if self.flaps.remove(archive).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    pub fn add_snapshot(&mut self, snapshot: Snapshot) {
+        self.snapshots.push(snapshot);
+        self.dirty = true;
+    }
+
+    pub fn forget_snapshot(&mut self, name: &str) {
+        let before = self.snapshots.len();
+        self.snapshots.retain(|snapshot| snapshot.name != name);
+        if self.snapshots.len() != before {
+            self.dirty = true;
+        }
+    }
+
+    /// Archives prioritized through the control socket by a run that didn't finish, in order.
+    pub fn prioritized(&self) -> &[PathBuf] {
+        &self.prioritized
+    }
+
+    /// Archives dropped through the control socket by a run that didn't finish.
+    pub fn dropped(&self) -> &[PathBuf] {
+        &self.dropped
+    }
+
+    pub fn prioritize(&mut self, archive: &Path) {
+        self.dropped.retain(|path| path != archive);
+        self.prioritized.retain(|path| path != archive);
+        self.prioritized.push(archive.to_path_buf());
+        self.dirty = true;
+    }
+
+    pub fn drop_queued(&mut self, archive: &Path) {
+        self.prioritized.retain(|path| path != archive);
+        self.dropped.retain(|path| path != archive);
+        self.dropped.push(archive.to_path_buf());
+        self.dirty = true;
+    }
+
+    /// Forgets the requests of the control socket, once the run they were made for is done.
+    pub fn clear_ctl(&mut self) {
+        if !self.prioritized.is_empty() || !self.dropped.is_empty() {
+            self.prioritized.clear();
+            self.dropped.clear();
+            self.dirty = true;
+        }
+    }
+
+    pub fn pending_removals(&self) -> &RemovalSet {
+        &self.removals
+    }
+
+    /// Records the parts of `archive` to remove. The times are compared to the millisecond, which survives the file.
+    pub fn set_pending_removal(&mut self, archive: &Path, parts: &[(PathBuf, SystemTime)]) {
+        let close = |a: &SystemTime, b: &SystemTime| (secs(*a) - secs(*b)).abs() < 0.001;
+        let unchanged = self.removals.get(archive).is_some_and(|recorded| {
+            recorded.len() == parts.len()
+                && recorded
+                    .iter()
+                    .zip(parts)
+                    .all(|((a, a_at), (b, b_at))| a == b && close(a_at, b_at))
+        });
+        if !unchanged {
+            self.removals.insert(archive.to_path_buf(), parts.to_vec());
+            self.dirty = true;
+        }
+    }
+
+    pub fn forget_pending_removal(&mut self, archive: &Path) {
+        if self.removals.remove(archive) {
+            self.dirty = true;
+        }
+    }
+
+    /// Whether any file extracted from `archive` is recorded, moved to the tier directory or not.
+    pub fn records(&self, archive: &Path) -> bool {
+        self.extracted.values().any(|entry| entry.archive == archive)
+            || self.tiered.values().any(|entry| entry.archive == archive)
+    }
+
+    /// Moves what is recorded about the archive at `from` to `to`, which holds the same archive. Returns false when
+    /// nothing was recorded.
+    pub fn relocate(&mut self, from: &Path, to: &Path) -> bool {
+        fn rekey<V>(map: &mut HashMap<PathBuf, V>, from: &Path, to: &Path) -> bool {
+            let Some(value) = map.remove(from) else {
+                return false;
+            };
+            map.entry(to.to_path_buf()).or_insert(value);
+            true
+        }
+        let mut found = false;
+        found |= rekey(&mut self.mtimes, from, to);
+        found |= rekey(&mut self.blocked, from, to);
+        found |= rekey(&mut self.flaps, from, to);
+        found |= rekey(&mut self.quarantined, from, to);
+        found |= rekey(&mut self.panics, from, to);
+        found |= rekey(&mut self.chains, from, to);
+        if let Some(parts) = self.removals.get(from).map(<[_]>::to_vec) {
+            self.removals.remove(from);
+            self.removals.insert(to.to_path_buf(), parts);
+            found = true;
+        }
+        let spelled = self
+            .extracted
+            .values_mut()
+            .map(|entry| &mut entry.archive)
+            .chain(self.tiered.values_mut().map(|entry| &mut entry.archive))
+            .chain(self.fingerprints.values_mut())
+            .chain(
+                self.chains
+                    .values_mut()
+                    .flat_map(|chain| chain.nested.iter_mut().flat_map(|(archive, parent)| [archive, parent])),
+            );
+        for path in spelled.filter(|path| *path == from) {
+            *path = to.to_path_buf();
+            found = true;
+        }
+        self.dirty |= found;
+        found
+    }
+
+    /// Moves what is recorded below the directory `from`, which was renamed `to`: the files extracted into it and
+    /// their hashes, the archives in it and their parts. Returns the number of paths moved.
+    pub fn relocate_dir(&mut self, from: &Path, to: &Path) -> usize {
+        fn rekey<V>(map: &mut HashMap<PathBuf, V>, from: &Path, to: &Path) -> usize {
+            let below: Vec<PathBuf> = map.keys().filter(|path| path.starts_with(from)).cloned().collect();
+            for path in &below {
+                if let (Some(value), Some(moved)) = (map.remove(path), rebase(path, from, to)) {
+                    map.entry(moved).or_insert(value);
+                }
+            }
+            below.len()
+        }
+        let mut moved = 0;
+        moved += rekey(&mut self.mtimes, from, to);
+        moved += rekey(&mut self.extracted, from, to);
+        moved += rekey(&mut self.tiered, from, to);
+        moved += rekey(&mut self.blocked, from, to);
+        moved += rekey(&mut self.verified, from, to);
+        moved += rekey(&mut self.checkpoints, from, to);
+        moved += rekey(&mut self.chains, from, to);
+        moved += rekey(&mut self.flaps, from, to);
+        moved += rekey(&mut self.quarantined, from, to);
+        moved += rekey(&mut self.panics, from, to);
+        let removals: Vec<(PathBuf, Vec<(PathBuf, SystemTime)>)> = self
+            .removals
+            .iter()
+            .filter(|(archive, parts)| {
+                archive.starts_with(from) || parts.iter().any(|(part, _)| part.starts_with(from))
+            })
+            .map(|(archive, parts)| (archive.to_path_buf(), parts.to_vec()))
+            .collect();
+        for (archive, parts) in removals {
+            let parts = parts
+                .into_iter()
+                .map(|(part, eligible_at)| (rebase(&part, from, to).unwrap_or(part), eligible_at))
+                .collect();
+            self.removals.remove(&archive);
+            self.removals
+                .insert(rebase(&archive, from, to).unwrap_or(archive), parts);
+            moved += 1;
+        }
+        let spelled = self
+            .extracted
+            .values_mut()
+            .map(|entry| &mut entry.archive)
+            .chain(self.tiered.values_mut().map(|entry| &mut entry.archive))
+            .chain(self.fingerprints.values_mut())
+            .chain(
+                self.chains
+                    .values_mut()
+                    .flat_map(|chain| chain.nested.iter_mut().flat_map(|(archive, parent)| [archive, parent])),
+            )
+            .chain(self.archives.values_mut().map(|sighting| &mut sighting.path));
+        for path in spelled {
+            if let Some(moved) = rebase(path, from, to) {
+                *path = moved;
+            }
+        }
+        self.dirty |= moved > 0;
+        moved
+    }
+
+    /// The id of the archive at `path`, when it was seen there with its first part at `key`.
+    pub fn archive_id(&self, path: &Path, key: FileKey) -> Option<&str> {
+        self.archives
+            .iter()
+            .find(|(_, sighting)| sighting.path == path && sighting.key == key)
+            .map(|(id, _)| id.as_str())
+    }
+
+    /// Where the archive `id` was last seen.
+    pub fn last_seen(&self, id: &str) -> Option<&Path> {
+        self.archives.get(id).map(|sighting| sighting.path.as_path())
+    }
+
+    /// Records the archive `id` as seen at `path` with its first part at `key`, as of now. An archive still where it
+    /// was is seen again once a compaction interval went by, not to write the file on every run.
+    pub fn set_seen(&mut self, id: &str, path: &Path, key: FileKey) {
+        let now = SystemTime::now();
+        let recent = self.archives.get(id).is_some_and(|sighting| {
+            sighting.path == path
+                && sighting.key == key
+                && now.duration_since(sighting.seen).unwrap_or_default() < COMPACTION_INTERVAL
+        });
+        if recent {
+            return;
+        }
+        let sighting = Sighting {
+            path: path.to_path_buf(),
+            key,
+            seen: now,
+        };
+        self.archives.insert(id.to_string(), sighting);
+        self.dirty = true;
+    }
+
+    /// The archives identified, with where they were last seen, by path.
+    pub fn archive_ids(&self) -> Vec<(&Path, &str)> {
+        let mut ids: Vec<_> = self
+            .archives
+            .iter()
+            .map(|(id, sighting)| (sighting.path.as_path(), id.as_str()))
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Whether the file is from before archives had an id and some it records have none yet.
+    pub fn unidentified(&self) -> bool {
+        self.unidentified
+    }
+
+    /// The archives something is recorded about.
+    fn recorded_archives(&self) -> impl Iterator<Item = &Path> {
+        self.extracted
+            .values()
+            .map(|entry| entry.archive.as_path())
+            .chain(self.tiered.values().map(|entry| entry.archive.as_path()))
+            .chain(self.fingerprints.values().map(PathBuf::as_path))
+            .chain(self.blocked.keys().map(PathBuf::as_path))
+            .chain(self.flaps.keys().map(PathBuf::as_path))
+            .chain(self.quarantined.keys().map(PathBuf::as_path))
+            .chain(self.panics.keys().map(PathBuf::as_path))
+            .chain(self.chains.iter().flat_map(|(root, chain)| {
+                std::iter::once(root.as_path()).chain(chain.nested.iter().map(|(archive, _)| archive.as_path()))
+            }))
+            .chain(self.removals.iter().map(|(archive, _)| archive))
+    }
+
+    /// Whether anything is recorded about `archive`, its id is worth keeping then.
+    pub fn knows(&self, archive: &Path) -> bool {
+        self.recorded_archives().any(|recorded| recorded == archive)
+    }
+
+    /// The archives something is recorded about, that exist and have no id yet. For the migration of files from
+    /// before archives had an id.
+    pub fn unidentified_archives(&self) -> Vec<PathBuf> {
+        let mut archives: Vec<PathBuf> = self
+            .recorded_archives()
+            .filter(|archive| !self.archives.values().any(|sighting| sighting.path == *archive))
+            .filter(|archive| archive.is_file())
+            .map(Path::to_path_buf)
+            .collect();
+        archives.sort();
+        archives.dedup();
+        archives
+    }
+
+    /// Marks the archives of a file from before they had an id as given one.
+    pub fn set_identified(&mut self) {
+        if self.unidentified {
+            self.unidentified = false;
+            self.dirty = true;
+        }
+    }
+
+    pub fn forget_extracted(&mut self, path: &Path) {
+        if self.extracted.remove(path).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Records the extracted file at `path` as moved to `to`, in place of its extraction.
+    pub fn set_tiered(&mut self, path: &Path, to: &Path) {
+        let Some(extracted) = self.extracted.remove(path) else {
+            return;
+        };
+        let entry = Tiered {
+            archive: extracted.archive,
+            to: to.to_path_buf(),
+        };
+        self.tiered.insert(path.to_path_buf(), entry);
+        self.dirty = true;
+    }
+
+    /// The files of `archive` moved to the tier directory, by where they were extracted.
+    pub fn tiered_files<'a>(&'a self, archive: &'a Path) -> impl Iterator<Item = (&'a Path, &'a Path)> {
+        self.tiered
+            .iter()
+            .filter(move |(_, entry)| entry.archive == archive)
+            .map(|(path, entry)| (path.as_path(), entry.to.as_path()))
+    }
+
+    /// Fails unless the state can be saved, which takes creating the file it's written to next to it. Rather than at
+    /// the end of a run that switched to a user who can't.
+    pub fn check_writable(&self) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        let _fds = fds::acquire(1);
+        fs::File::create(&tmp)
+            .and_then(|_| fs::remove_file(&tmp))
+            .with_context(|| format!("write state file next to '{}'", self.path.display()))
+    }
+
+    /// Writes the state if it changed. The file is replaced atomically so that a crash can't leave it truncated, the
+    /// previous one is kept as the backup.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mtimes: Map<String, Value> = self
+            .mtimes
+            .iter()
+            .map(|(path, mtime)| (path.to_string_lossy().into_owned(), Value::from(secs(*mtime))))
+            .collect();
+        let extracted: Map<String, Value> = self
+            .extracted
+            .iter()
+            .map(|(path, entry)| {
+                let entry = json!({
+                    "archive": entry.archive.to_string_lossy(),
+                    "time": secs(entry.time),
+                });
+                (path.to_string_lossy().into_owned(), entry)
+            })
+            .collect();
+        let tiered: Map<String, Value> = self
+            .tiered
+            .iter()
+            .map(|(path, entry)| {
+                let entry = json!({
+                    "archive": entry.archive.to_string_lossy(),
+                    "to": entry.to.to_string_lossy(),
+                });
+                (path.to_string_lossy().into_owned(), entry)
+            })
+            .collect();
+        let blocked: Map<String, Value> = self
+            .blocked
+            .iter()
+            .map(|(path, required)| (path.to_string_lossy().into_owned(), Value::from(*required)))
+            .collect();
+        let verified: Map<String, Value> = self
+            .verified
+            .iter()
+            .map(|(path, (key, crc, time))| {
+                let mut entry = key_to_json(key);
+                entry["crc"] = Value::from(*crc);
+                entry["time"] = Value::from(secs(*time));
+                (path.to_string_lossy().into_owned(), entry)
+            })
+            .collect();
+        let checkpoints: Map<String, Value> = self
+            .checkpoints
+            .iter()
+            .map(|(path, checkpoint)| {
+                let mut entry = key_to_json(&checkpoint.key);
+                entry["offset"] = Value::from(checkpoint.offset);
+                entry["crc"] = Value::from(checkpoint.crc);
+                (path.to_string_lossy().into_owned(), entry)
+            })
+            .collect();
+        let chains: Map<String, Value> = self
+            .chains
+            .iter()
+            .map(|(root, chain)| {
+                let nested: Vec<Value> = chain
+                    .nested
+                    .iter()
+                    .map(|(archive, parent)| {
+                        json!({
+                            "archive": archive.to_string_lossy(),
+                            "parent": parent.to_string_lossy(),
+                        })
+                    })
+                    .collect();
+                let entry = json!({
+                    "nested": nested,
+                    "broken_since": chain.broken_since.map(secs),
+                });
+                (root.to_string_lossy().into_owned(), entry)
+            })
+            .collect();
+        let archives: Map<String, Value> = self
+            .archives
+            .iter()
+            .map(|(id, sighting)| {
+                let mut entry = key_to_json(&sighting.key);
+                entry["path"] = Value::from(sighting.path.to_string_lossy());
+                entry["seen"] = Value::from(secs(sighting.seen));
+                (id.clone(), entry)
+            })
+            .collect();
+        let fingerprints: Map<String, Value> = self
+            .fingerprints
+            .iter()
+            .map(|(fingerprint, archive)| (fingerprint.clone(), Value::from(archive.to_string_lossy())))
+            .collect();
+        let flaps: Map<String, Value> = self
+            .flaps
+            .iter()
+            .map(|(archive, count)| (archive.to_string_lossy().into_owned(), Value::from(*count)))
+            .collect();
+        let quarantined: Map<String, Value> = self
+            .quarantined
+            .iter()
+            .map(|(archive, fingerprint)| {
+                (
+                    archive.to_string_lossy().into_owned(),
+                    Value::from(fingerprint.as_str()),
+                )
+            })
+            .collect();
+        let panics: Map<String, Value> = self
+            .panics
+            .iter()
+            .map(|(archive, (fingerprint, count))| {
+                (
+                    archive.to_string_lossy().into_owned(),
+                    json!({ "fingerprint": fingerprint, "count": count }),
+                )
+            })
+            .collect();
+        let listings: Map<String, Value> = self
+            .listings
+            .iter()
+            .map(|(dir, listing)| {
+                let names = |names: &[PathBuf]| {
+                    names
+                        .iter()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .collect::<Vec<_>>()
+                };
+                let entry = json!({
+                    "mtime": nanos(listing.mtime),
+                    "dirs": names(&listing.dirs),
+                    "files": names(&listing.files),
+                });
+                (dir.to_string_lossy().into_owned(), entry)
+            })
+            .collect();
+        let snapshots: Vec<Value> = self
+            .snapshots
+            .iter()
+            .map(|snapshot| {
+                json!({
+                    "name": snapshot.name,
+                    "volume": snapshot.volume.to_string_lossy(),
+                    "created": secs(snapshot.created),
+                })
+            })
+            .collect();
+        let content = json!({
+            "version": VERSION,
+            "mtimes": mtimes,
+            "extracted": extracted,
+            "tiered": tiered,
+            "throughput": self.throughput,
+            "blocked": blocked,
+            "verified": verified,
+            "checkpoints": checkpoints,
+            "chains": chains,
+            "archives": archives,
+            "fingerprints": fingerprints,
+            "flaps": flaps,
+            "quarantined": quarantined,
+            "panics": panics,
+            "listings": listings,
+            "pending_removals": self.removals.to_json(),
+            "snapshots": snapshots,
+            "ctl": {
+                "prioritized": self.prioritized.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>(),
+                "dropped": self.dropped.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>(),
+            },
+            "compacted_at": self.compacted_at.map(secs),
+        })
+        .to_string();
+        let tmp = self.path.with_extension("json.tmp");
+        let _fds = fds::acquire(1);
+        let mut file = fs::File::create(&tmp).context("write state file")?;
+        file.write_all(content.as_bytes()).context("write state file")?;
+        // On disk before the rename, or a crash could leave the renamed file empty.
+        file.sync_all().context("write state file")?;
+        drop(file);
+        if !self.recovered && self.path.exists() {
+            let backup = backup_path(&self.path);
+            // A link is enough, the rename leaves the previous file to the backup.
+            let _ = fs::remove_file(&backup);
+            if let Err(e) = fs::hard_link(&self.path, &backup).or_else(|_| fs::copy(&self.path, &backup).map(drop)) {
+                log::warn!("Could not back up the state file to '{}': {}", backup.display(), e);
+            }
+        }
+        fs::rename(&tmp, &self.path).context("replace state file")?;
+        self.recovered = false;
+        self.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+/// How long the server thread sleeps between checks for connections and shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// A client slower than this to send its request or read the response is dropped.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the endpoint reports, updated by the queue as it goes.
+#[derive(Debug, Default)]
+pub struct Status {
+    pub current: Option<PathBuf>,
+    pub queue_len: usize,
+    pub archives_processed: u64,
+    pub archives_extracted: u64,
+    pub archives_failed: u64,
+    pub parts_removed: u64,
+    /// When the --active-hours window opens, while waiting for it.
+    pub paused_until: Option<String>,
+    /// Run summary of the last finished run.
+    pub last_summary: Option<Value>,
+}
+
+/// Read-only HTTP endpoint serving /healthz, /status and /metrics from its own thread.
+pub struct StatusServer {
+    status: Arc<Mutex<Status>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatusServer {
+    pub fn bind(addr: &str) -> anyhow::Result<StatusServer> {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("bind the HTTP status endpoint on '{}'", addr))?;
+        listener
+            .set_nonblocking(true)
+            .context("set HTTP status endpoint non-blocking")?;
+        log::info!("Serving the status on http://{}/status.", listener.local_addr()?);
+
+        let status = Arc::new(Mutex::new(Status::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let status = status.clone();
+            let stop = stop.clone();
+            let started = Instant::now();
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Err(e) = serve(stream, &status, started) {
+                                log::debug!("HTTP status client failed: {}", e);
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                        Err(e) => log::debug!("HTTP status accept failed: {}", e),
+                    }
+                }
+            })
+        };
+        Ok(StatusServer {
+            status,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn update(&self, update: impl FnOnce(&mut Status)) {
+        update(&mut self.status.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(stream: TcpStream, status: &Mutex<Status>, started: Instant) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers aren't needed, but are read so that the client doesn't see its request cut short.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let (code, content_type, body) = if method != "GET" {
+        (
+            "405 Method Not Allowed",
+            "text/plain",
+            "only GET is supported\n".to_string(),
+        )
+    } else {
+        let status = status.lock().unwrap_or_else(PoisonError::into_inner);
+        let uptime = started.elapsed().as_secs_f64();
+        match path {
+            "/healthz" => ("200 OK", "text/plain", "ok\n".to_string()),
+            "/status" => ("200 OK", "application/json", status_json(&status, uptime).to_string()),
+            "/metrics" => ("200 OK", "text/plain; version=0.0.4", metrics(&status, uptime)),
+            _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        }
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn status_json(status: &Status, uptime: f64) -> Value {
+    json!({
+        "current_archive": status.current.as_ref().map(|path| path.to_string_lossy()),
+        "queue_length": status.queue_len,
+        "archives_processed": status.archives_processed,
+        "archives_extracted": status.archives_extracted,
+        "archives_failed": status.archives_failed,
+        "parts_removed": status.parts_removed,
+        "paused_until": status.paused_until,
+        "last_summary": status.last_summary,
+        "uptime_secs": uptime,
+    })
+}
+
+fn metrics(status: &Status, uptime: f64) -> String {
+    let metrics: [(&str, &str, &str, f64); 6] = [
+        (
+            "archives_processed_total",
+            "counter",
+            "Archives analyzed.",
+            status.archives_processed as f64,
+        ),
+        (
+            "archives_extracted_total",
+            "counter",
+            "Archives extracted.",
+            status.archives_extracted as f64,
+        ),
+        (
+            "archives_failed_total",
+            "counter",
+            "Archives that failed to extract.",
+            status.archives_failed as f64,
+        ),
+        (
+            "parts_removed_total",
+            "counter",
+            "Archive parts and cruft removed.",
+            status.parts_removed as f64,
+        ),
+        (
+            "queue_length",
+            "gauge",
+            "Archives waiting to be processed.",
+            status.queue_len as f64,
+        ),
+        ("uptime_seconds", "gauge", "Seconds since rarscan started.", uptime),
+    ];
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        out.push_str(&format!(
+            "# HELP rarscan_{name} {help}\n# TYPE rarscan_{name} {kind}\nrarscan_{name} {value}\n"
+        ));
+    }
+    out
+}
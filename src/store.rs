@@ -0,0 +1,342 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::Format,
+    fds,
+    volumes::{invalid, le_u16, le_u32, read_or_end, skip, vint},
+};
+
+const RAR4_MAIN: u8 = 0x73;
+const RAR4_FILE: u8 = 0x74;
+const RAR4_END: u8 = 0x7b;
+const RAR4_LONG_BLOCK: u16 = 0x8000;
+const RAR4_STORE: u8 = 0x30;
+const RAR5_FILE: u64 = 2;
+const RAR5_ENCRYPTION: u64 = 4;
+const RAR5_END: u64 = 5;
+/// Extra records of a RAR5 file header for encrypted entries and links.
+const RAR5_EXTRA_ENCRYPTION: u64 = 1;
+const RAR5_EXTRA_REDIRECTION: u64 = 5;
+const HOST_UNIX: u8 = 3;
+const S_IFMT: u64 = 0o170000;
+const S_IFLNK: u64 = 0o120000;
+const COPY_CHUNK: usize = 1 << 20;
+
+/// An entry stored without compression, whose data sits as is in a single volume.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEntry {
+    pub volume: PathBuf,
+    /// Where its data starts in the volume.
+    pub offset: u64,
+    pub size: u64,
+    pub crc: u32,
+}
+
+/// The entries of the volumes of an archive that --fast-store-copy can copy out of them, by their name inside of the
+/// archive. Entries compressed, split across volumes, encrypted, without a CRC32 or that are links are left out, and
+/// so are names found more than once.
+pub fn stored_entries(volumes: &[PathBuf]) -> io::Result<HashMap<PathBuf, StoredEntry>> {
+    let mut entries = HashMap::new();
+    let mut repeated = HashSet::new();
+    for volume in volumes {
+        for (name, entry) in read_volume(volume)? {
+            if entries.insert(name.clone(), entry).is_some() {
+                repeated.insert(name);
+            }
+        }
+    }
+    entries.retain(|name, _| !repeated.contains(name));
+    Ok(entries)
+}
+
+fn read_volume(path: &Path) -> io::Result<Vec<(PathBuf, StoredEntry)>> {
+    let _fds = fds::acquire(1);
+    let mut file = BufReader::new(File::open(path)?);
+    let mut signature = [0; 8];
+    let len = file.read(&mut signature)?;
+    match Format::detect_rar(&signature[..len]) {
+        Some(Format::Rar5) => read_rar5(path, &mut file),
+        Some(_) => {
+            file.seek(SeekFrom::Start(7))?;
+            read_rar4(path, &mut file)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+fn read_rar4(path: &Path, file: &mut (impl Read + Seek)) -> io::Result<Vec<(PathBuf, StoredEntry)>> {
+    let mut entries = Vec::new();
+    let mut base = [0; 7];
+    while read_or_end(file, &mut base)? {
+        let (kind, flags) = (base[2], le_u16(&base, 3)? as u16);
+        let size = le_u16(&base, 5)? as usize;
+        let mut body = vec![0; size.checked_sub(7).ok_or_else(invalid)?];
+        file.read_exact(&mut body)?;
+        let mut data = match flags & RAR4_LONG_BLOCK {
+            0 => 0,
+            _ => le_u32(&body, 0)?,
+        };
+        match kind {
+            // Encrypted headers.
+            RAR4_MAIN if flags & 0x0080 != 0 => return Ok(Vec::new()),
+            RAR4_FILE => {
+                let mut unpacked_size = le_u32(&body, 4)?;
+                let host = *body.get(8).ok_or_else(invalid)?;
+                let crc = le_u32(&body, 9)? as u32;
+                let method = *body.get(18).ok_or_else(invalid)?;
+                let name_size = le_u16(&body, 19)? as usize;
+                let attributes = le_u32(&body, 21)?;
+                let mut name_at = 25;
+                if flags & 0x0100 != 0 {
+                    data |= le_u32(&body, 25)? << 32;
+                    unpacked_size |= le_u32(&body, 29)? << 32;
+                    name_at = 33;
+                }
+                let name = body.get(name_at..name_at + name_size).ok_or_else(invalid)?;
+                let split = flags & 0x0003 != 0;
+                let encrypted = flags & 0x0004 != 0;
+                let directory = flags & 0x00e0 == 0x00e0;
+                let link = host == HOST_UNIX && attributes & S_IFMT == S_IFLNK;
+                // Names with a unicode part are left to unrar.
+                let name = std::str::from_utf8(name).ok().filter(|name| !name.contains('\0'));
+                if let Some(name) = name.filter(|_| {
+                    method == RAR4_STORE && data == unpacked_size && !split && !encrypted && !directory && !link
+                }) {
+                    entries.push((
+                        PathBuf::from(name.replace('\\', "/")),
+                        StoredEntry {
+                            volume: path.to_path_buf(),
+                            offset: file.stream_position()?,
+                            size: unpacked_size,
+                            crc,
+                        },
+                    ));
+                }
+            }
+            RAR4_END => break,
+            _ => {}
+        }
+        file.seek(SeekFrom::Current(i64::try_from(data).map_err(|_| invalid())?))?;
+    }
+    Ok(entries)
+}
+
+fn read_rar5(path: &Path, file: &mut (impl Read + Seek)) -> io::Result<Vec<(PathBuf, StoredEntry)>> {
+    let mut entries = Vec::new();
+    let mut crc = [0; 4];
+    while read_or_end(file, &mut crc)? {
+        let mut size = 0;
+        for shift in (0..21).step_by(7) {
+            let mut byte = [0];
+            file.read_exact(&mut byte)?;
+            size |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut header = vec![0; size];
+        file.read_exact(&mut header)?;
+        let mut fields = header.as_slice();
+        let kind = vint(&mut fields)?;
+        let flags = vint(&mut fields)?;
+        let extra = match flags & 0x0001 {
+            0 => 0,
+            _ => usize::try_from(vint(&mut fields)?).map_err(|_| invalid())?,
+        };
+        let data = if flags & 0x0002 != 0 { vint(&mut fields)? } else { 0 };
+        match kind {
+            RAR5_FILE => {
+                let file_flags = vint(&mut fields)?;
+                let unpacked_size = vint(&mut fields)?;
+                vint(&mut fields)?;
+                if file_flags & 0x0002 != 0 {
+                    skip(&mut fields, 4)?;
+                }
+                let crc = match file_flags & 0x0004 {
+                    0 => None,
+                    _ => Some(le_u32(skip(&mut fields, 4)?, 0)? as u32),
+                };
+                let compression = vint(&mut fields)?;
+                vint(&mut fields)?;
+                let name_size = usize::try_from(vint(&mut fields)?).map_err(|_| invalid())?;
+                let name = skip(&mut fields, name_size)?;
+                let extra = header.get(header.len().checked_sub(extra).ok_or_else(invalid)?..);
+                let plain = !has_record(
+                    extra.unwrap_or_default(),
+                    &[RAR5_EXTRA_ENCRYPTION, RAR5_EXTRA_REDIRECTION],
+                )?;
+                let split = flags & 0x0018 != 0;
+                let directory = file_flags & 0x0001 != 0;
+                let stored = (compression >> 7) & 0x7 == 0;
+                let name = std::str::from_utf8(name).ok();
+                match (name, crc) {
+                    (Some(name), Some(crc)) if stored && plain && !split && !directory && data == unpacked_size => {
+                        entries.push((
+                            PathBuf::from(name),
+                            StoredEntry {
+                                volume: path.to_path_buf(),
+                                offset: file.stream_position()?,
+                                size: unpacked_size,
+                                crc,
+                            },
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+            RAR5_ENCRYPTION => return Ok(Vec::new()),
+            RAR5_END => break,
+            _ => {}
+        }
+        file.seek(SeekFrom::Current(i64::try_from(data).map_err(|_| invalid())?))?;
+    }
+    Ok(entries)
+}
+
+/// Whether the extra area of a RAR5 header has a record of one of the `kinds`.
+fn has_record(mut extra: &[u8], kinds: &[u64]) -> io::Result<bool> {
+    while !extra.is_empty() {
+        let size = usize::try_from(vint(&mut extra)?).map_err(|_| invalid())?;
+        let mut record = skip(&mut extra, size)?;
+        if kinds.contains(&vint(&mut record)?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Copies the data of `entry` into `out` from the start, a reflink of the range when the filesystem allows it, and
+/// checks it against the CRC32 of the header. `out` holds anything after a failure.
+pub fn copy_entry(entry: &StoredEntry, out: &mut File) -> io::Result<()> {
+    let _fds = fds::acquire(1);
+    let mut volume = File::open(&entry.volume)?;
+    let reflinked = imp::clone_range(&volume, entry.offset, entry.size, out);
+    volume.seek(SeekFrom::Start(entry.offset))?;
+    let mut data = volume.take(entry.size);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; COPY_CHUNK];
+    let mut copied = 0;
+    loop {
+        let n = data.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        // The volume is read anyway for the CRC32, a reflink saves the writes.
+        if !reflinked {
+            out.write_all(&buf[..n])?;
+        }
+        copied += n as u64;
+    }
+    if copied != entry.size {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "volume shorter than its headers",
+        ));
+    }
+    let crc = hasher.finalize();
+    if crc != entry.crc {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("CRC32 {:08x} where the header has {:08x}", crc, entry.crc),
+        ));
+    }
+    log::debug!(
+        "-> Copied {} bytes out of '{}'{}.",
+        entry.size,
+        entry.volume.display(),
+        if reflinked { " as a reflink" } else { "" }
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{
+        fs::File,
+        io,
+        os::{fd::AsRawFd, unix::fs::MetadataExt},
+    };
+
+    /// Makes the first `size` bytes of `out` share the blocks of `volume` from `offset`. Only tried when the range
+    /// is aligned on the blocks of the filesystem, as FICLONERANGE wants.
+    pub fn clone_range(volume: &File, offset: u64, size: u64, out: &File) -> bool {
+        let Ok(metadata) = volume.metadata() else {
+            return false;
+        };
+        let block = metadata.blksize().max(1);
+        if size == 0 || !offset.is_multiple_of(block) || !size.is_multiple_of(block) {
+            return false;
+        }
+        let range = libc::file_clone_range {
+            src_fd: volume.as_raw_fd() as i64,
+            src_offset: offset,
+            src_length: size,
+            dest_offset: 0,
+        };
+        // SAFETY: both descriptors are open for the duration of the call and `range` outlives it.
+        if unsafe {
+            libc::ioctl(
+                out.as_raw_fd(),
+                libc::FICLONERANGE,
+                &range as *const libc::file_clone_range,
+            )
+        } != 0
+        {
+            log::debug!("-> No reflink of the range: {}", io::Error::last_os_error());
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::fs::File;
+
+    pub fn clone_range(_: &File, _: u64, _: u64, _: &File) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn stored_entries_are_copied_and_checked() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let entries = stored_entries(&[fixtures.join("plain.rar")]).unwrap();
+        let entry = entries.get(Path::new("dir/hello.txt")).expect("stored entry");
+        // Split across the volumes.
+        let parts: Vec<PathBuf> = (1..=3).map(|i| fixtures.join(format!("multi.part{}.rar", i))).collect();
+        assert!(stored_entries(&parts).unwrap().is_empty());
+
+        let path = std::env::temp_dir().join(format!("rarscan-store-test-{}", std::process::id()));
+        let mut out = File::create(&path).unwrap();
+        copy_entry(entry, &mut out).unwrap();
+        let mut expected = vec![0; entry.size as usize];
+        let mut volume = File::open(&entry.volume).unwrap();
+        volume.seek(SeekFrom::Start(entry.offset)).unwrap();
+        volume.read_exact(&mut expected).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), expected);
+
+        let wrong = StoredEntry {
+            crc: !entry.crc,
+            ..entry.clone()
+        };
+        let mut out = File::create(&path).unwrap();
+        assert_eq!(
+            copy_entry(&wrong, &mut out).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::MultiGzDecoder;
+
+/// Compression of a tarball, from its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of a `.tar`, `.tar.gz`, `.tgz` or `.tar.zst` file, `None` for other files.
+    pub fn of(path: &Path) -> Option<Compression> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar") {
+            Some(Compression::None)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Compression::Gzip)
+        } else if name.ends_with(".tar.zst") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "tar",
+            Compression::Gzip => "tar.gz",
+            Compression::Zstd => "tar.zst",
+        })
+    }
+}
+
+pub fn is_tarball(path: &Path) -> bool {
+    Compression::of(path).is_some()
+}
+
+/// Opens the tarball at `path` to read its entries in order, decompressing it as it goes.
+pub fn open(path: &Path, compression: Compression) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// `name` without its `.` components, `None` when it's absolute or climbs out of the destination.
+pub fn enclosed_name(name: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => enclosed.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!enclosed.as_os_str().is_empty()).then_some(enclosed)
+}
+
+/// Whether a symlink named `name` pointing at `target` stays inside of the destination, both being relative to it.
+pub fn link_within(name: &Path, target: &Path) -> bool {
+    let mut depth = name.components().count().saturating_sub(1);
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Symlinks followed by [`resolves_within`] before it gives up, like the kernel does.
+const MAX_LINK_HOPS: usize = 40;
+
+/// Whether `path`, relative to `dest`, stays inside of it once the symlinks already in `dest` are followed. Missing
+/// components are taken as directories to be created.
+pub fn resolves_within(dest: &Path, path: &Path) -> bool {
+    enum Part {
+        Name(OsString),
+        Parent,
+        Escape,
+    }
+    fn parts(path: &Path) -> impl DoubleEndedIterator<Item = Part> + '_ {
+        path.components().filter_map(|component| match component {
+            Component::Normal(name) => Some(Part::Name(name.to_os_string())),
+            Component::CurDir => None,
+            Component::ParentDir => Some(Part::Parent),
+            Component::RootDir | Component::Prefix(_) => Some(Part::Escape),
+        })
+    }
+    let mut pending: Vec<Part> = parts(path).rev().collect();
+    let mut resolved = PathBuf::new();
+    let mut hops = 0;
+    while let Some(part) = pending.pop() {
+        match part {
+            Part::Name(name) => {
+                resolved.push(name);
+                let Ok(metadata) = fs::symlink_metadata(dest.join(&resolved)) else {
+                    continue;
+                };
+                if !metadata.file_type().is_symlink() {
+                    continue;
+                }
+                hops += 1;
+                if hops > MAX_LINK_HOPS {
+                    return false;
+                }
+                let Ok(target) = fs::read_link(dest.join(&resolved)) else {
+                    return false;
+                };
+                resolved.pop();
+                pending.extend(parts(&target).rev());
+            }
+            Part::Parent => {
+                if !resolved.pop() {
+                    return false;
+                }
+            }
+            Part::Escape => return false,
+        }
+    }
+    true
+}
+
+/// Whether one of the components of `path` below `dest`, `path` itself included, is a symlink. Nothing is written
+/// through one, wherever it points.
+pub fn through_symlink(dest: &Path, path: &Path) -> bool {
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| fs::symlink_metadata(dest.join(ancestor)).is_ok_and(|m| m.file_type().is_symlink()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_from_extension() {
+        assert_eq!(Compression::of(Path::new("a/b.tar")), Some(Compression::None));
+        assert_eq!(Compression::of(Path::new("b.TAR.GZ")), Some(Compression::Gzip));
+        assert_eq!(Compression::of(Path::new("b.tgz")), Some(Compression::Gzip));
+        assert_eq!(Compression::of(Path::new("b.tar.zst")), Some(Compression::Zstd));
+        assert_eq!(Compression::of(Path::new("b.gz")), None);
+        assert_eq!(Compression::of(Path::new("tar")), None);
+    }
+
+    #[test]
+    fn names_and_links_stay_inside() {
+        assert_eq!(enclosed_name(Path::new("./a/b")), Some(PathBuf::from("a/b")));
+        assert_eq!(enclosed_name(Path::new("a/../../b")), None);
+        assert_eq!(enclosed_name(Path::new("/etc/passwd")), None);
+        assert!(link_within(Path::new("a/b/link"), Path::new("../c")));
+        assert!(link_within(Path::new("a/link"), Path::new("../c")));
+        assert!(!link_within(Path::new("a/link"), Path::new("../../c")));
+        assert!(!link_within(Path::new("link"), Path::new("/etc/passwd")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_resolved_against_the_ones_already_created() {
+        use std::os::unix::fs::symlink;
+
+        let dest = std::env::temp_dir().join(format!("rarscan-tarball-test-{}", std::process::id()));
+        fs::create_dir_all(dest.join("a")).unwrap();
+        symlink("..", dest.join("a/b")).unwrap();
+        assert!(resolves_within(&dest, Path::new("a/b")));
+        // Fine as text, out of `dest` through `a/b`.
+        assert!(link_within(Path::new("c"), Path::new("a/b/../..")));
+        assert!(!resolves_within(&dest, Path::new("a/b/../..")));
+        assert!(resolves_within(&dest, Path::new("a/b/a/x")));
+        symlink("loop", dest.join("loop")).unwrap();
+        assert!(!resolves_within(&dest, Path::new("loop/x")));
+        assert!(through_symlink(&dest, Path::new("a/b/x")));
+        assert!(!through_symlink(&dest, Path::new("a/x")));
+        fs::remove_dir_all(&dest).unwrap();
+    }
+}
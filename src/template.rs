@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use time::OffsetDateTime;
+
+use crate::archive::Archive;
+
+const VIDEO_EXTENSIONS: &[&str] = &["avi", "m2ts", "m4v", "mkv", "mov", "mp4", "mpg", "ts", "webm", "wmv"];
+const AUDIO_EXTENSIONS: &[&str] = &["aac", "alac", "ape", "flac", "m4a", "mp3", "ogg", "opus", "wav", "wma"];
+
+#[derive(Debug, Clone, Copy)]
+enum Placeholder {
+    ArchiveStem,
+    ParentDir,
+    Year,
+    ExtCategory,
+}
+
+impl Placeholder {
+    fn parse(name: &str) -> Option<Placeholder> {
+        Some(match name {
+            "archive_stem" => Placeholder::ArchiveStem,
+            "parent_dir" => Placeholder::ParentDir,
+            "year" => Placeholder::Year,
+            "ext-category" => Placeholder::ExtCategory,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Placeholder::ArchiveStem => "archive_stem",
+            Placeholder::ParentDir => "parent_dir",
+            Placeholder::Year => "year",
+            Placeholder::ExtCategory => "ext-category",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Destination directory of an archive rendered from a template such as `/mnt/media/{year}/{archive_stem}`.
+///
+/// Relative templates are rendered from the directory containing the archive. Placeholder values never contain path
+/// separators, and a rendered path with `..` components is refused.
+#[derive(Debug, Clone)]
+pub struct DestTemplate {
+    segments: Vec<Segment>,
+}
+
+/// The template as it was given.
+impl fmt::Display for DestTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => write!(f, "{}", s)?,
+                Segment::Placeholder(placeholder) => write!(f, "{{{}}}", placeholder.name())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DestTemplate {
+    /// Parses a template for clap, naming the offending placeholder when it isn't known.
+    pub fn parse(s: &str) -> Result<DestTemplate, String> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder '{}'", &rest[start..]))?;
+            let name = &rest[start + 1..start + end];
+            let placeholder = Placeholder::parse(name).ok_or_else(|| {
+                format!(
+                    "unknown placeholder '{{{}}}', expected one of {{archive_stem}}, {{parent_dir}}, {{year}} or \
+                     {{ext-category}}",
+                    name
+                )
+            })?;
+            segments.push(Segment::Placeholder(placeholder));
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("unmatched '}}' in '{}'", s));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+        if segments.is_empty() {
+            return Err("empty template".into());
+        }
+        let template = DestTemplate { segments };
+        // Placeholder values can't introduce `..`, so the literal parts decide whether the template escapes.
+        if has_parent_dir(&template.render_with(|_| "x".into())) {
+            return Err(format!("'{}' escapes the destination through '..'", s));
+        }
+        Ok(template)
+    }
+
+    fn render_with(&self, mut value: impl FnMut(Placeholder) -> String) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => rendered.push_str(s),
+                Segment::Placeholder(placeholder) => rendered.push_str(&sanitize(&value(*placeholder))),
+            }
+        }
+        rendered
+    }
+
+    pub fn render(&self, archive: &Archive, mtime: SystemTime) -> anyhow::Result<PathBuf> {
+        let rendered = self.render_with(|placeholder| match placeholder {
+            Placeholder::ArchiveStem => archive_stem(&archive.path),
+            Placeholder::ParentDir => archive
+                .path
+                .parent()
+                .and_then(|p| p.file_name())
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Placeholder::Year => OffsetDateTime::from(mtime).year().to_string(),
+            Placeholder::ExtCategory => ext_category(archive).into(),
+        });
+        if has_parent_dir(&rendered) {
+            anyhow::bail!("destination '{}' escapes through '..'", rendered);
+        }
+        let parent = archive.path.parent().expect("no parent path");
+        Ok(parent.join(rendered))
+    }
+}
+
+fn has_parent_dir(path: &str) -> bool {
+    Path::new(path).components().any(|c| c == Component::ParentDir)
+}
+
+/// Name of the archive without its extension and part number.
+fn archive_stem(path: &Path) -> String {
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    match stem.rsplit_once(".part") {
+        Some((name, number)) if !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) => name.into(),
+        _ => stem.into_owned(),
+    }
+}
+
+/// `video`, `audio` or `other` depending on which kind of file accounts for most of the unpacked size.
+fn ext_category(archive: &Archive) -> &'static str {
+    let mut sizes: HashMap<&'static str, u64> = HashMap::new();
+    for header in archive.headers.iter().filter(|header| header.is_file()) {
+        *sizes.entry(file_category(&header.filename)).or_default() += header.unpacked_size;
+    }
+    sizes
+        .into_iter()
+        .max_by_key(|&(category, size)| (size, category))
+        .map(|(category, _)| category)
+        .unwrap_or("other")
+}
+
+/// `video`, `audio` or `other` from the extension of `path`.
+pub fn file_category(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    if VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+        "video"
+    } else if AUDIO_EXTENSIONS.contains(&ext.as_str()) {
+        "audio"
+    } else {
+        "other"
+    }
+}
+
+/// Keeps a placeholder value within a single path component.
+fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c == '\0' { '_' } else { c })
+        .collect();
+    match value.as_str() {
+        "" | "." | ".." => "_".into(),
+        _ => value,
+    }
+}
@@ -0,0 +1,26 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+/// Moves `from` to `to`, creating the parent directories of `to`. Across filesystems the file is copied and synced
+/// before the source is removed, and keeps its mtime.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_then_remove(from, to),
+        result => result,
+    }
+}
+
+fn copy_then_remove(from: &Path, to: &Path) -> io::Result<()> {
+    let mtime = fs::metadata(from)?.modified()?;
+    fs::copy(from, to)?;
+    let copy = File::options().write(true).open(to)?;
+    copy.set_modified(mtime)?;
+    copy.sync_all()?;
+    fs::remove_file(from)
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+/// Ended spans are exported once this many have piled up, so that long runs show up before they are over.
+const BATCH_SIZE: usize = 256;
+/// A collector slower than this to take a batch is given up on.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle on a span started by a [`Tracer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanId(u64);
+
+#[derive(Debug)]
+struct Span {
+    id: SpanId,
+    parent: Option<SpanId>,
+    name: String,
+    start: SystemTime,
+    end: Option<SystemTime>,
+    attributes: Vec<(String, Value)>,
+    error: Option<String>,
+}
+
+/// OTLP/HTTP endpoint, with JSON encoding. Only plain HTTP is supported.
+#[derive(Debug)]
+struct Endpoint {
+    addr: String,
+    host: String,
+    path: String,
+}
+
+impl Endpoint {
+    /// Parses `http://host:port[/path]`. A URL without a path gets the standard `/v1/traces`.
+    fn parse(url: &str) -> anyhow::Result<Endpoint> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            anyhow::bail!("OTLP endpoint '{}' must be an http:// URL", url);
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(pos) if rest[pos..].len() > 1 => (&rest[..pos], rest[pos..].to_string()),
+            Some(pos) => (&rest[..pos], "/v1/traces".to_string()),
+            None => (rest, "/v1/traces".to_string()),
+        };
+        if authority.is_empty() {
+            anyhow::bail!("OTLP endpoint '{}' has no host", url);
+        }
+        let addr = match authority.rsplit_once(':') {
+            Some(_) => authority.to_string(),
+            None => format!("{}:4318", authority),
+        };
+        Ok(Endpoint {
+            addr,
+            host: authority.to_string(),
+            path,
+        })
+    }
+}
+
+/// Spans of a run, one trace per run, exported to an OpenTelemetry collector. Spans nest: a new span is a child of the
+/// innermost one still open. Whatever is left is ended and exported when the tracer is dropped, on failures too.
+pub struct Tracer {
+    endpoint: Endpoint,
+    trace_id: u128,
+    next_id: u64,
+    open: Vec<Span>,
+    ended: Vec<Span>,
+    run: SpanId,
+    finished: bool,
+    export_failed: bool,
+}
+
+impl Tracer {
+    /// Tracer exporting to `url`, with the span of the run started.
+    pub fn new(url: &str) -> anyhow::Result<Tracer> {
+        let endpoint = Endpoint::parse(url)?;
+        let random = RandomState::new();
+        let mut hasher = random.build_hasher();
+        hasher.write_u128(nanos(SystemTime::now()));
+        hasher.write_u32(std::process::id());
+        let high = hasher.finish();
+        hasher.write_u64(high);
+        let low = hasher.finish();
+        let mut tracer = Tracer {
+            endpoint,
+            trace_id: (high as u128) << 64 | low as u128,
+            next_id: low | 1,
+            open: Vec::new(),
+            ended: Vec::new(),
+            run: SpanId(0),
+            finished: false,
+            export_failed: false,
+        };
+        tracer.run = tracer.start("rarscan.run");
+        Ok(tracer)
+    }
+
+    pub fn start(&mut self, name: &str) -> SpanId {
+        // Span ids only need to be unique within the trace, which is ours alone.
+        let id = SpanId(self.next_id);
+        self.next_id = self.next_id.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.open.push(Span {
+            id,
+            parent: self.open.last().map(|span| span.id),
+            name: name.to_string(),
+            start: SystemTime::now(),
+            end: None,
+            attributes: Vec::new(),
+            error: None,
+        });
+        id
+    }
+
+    /// Sets an attribute of an open span, ignored once it has ended.
+    pub fn set(&mut self, span: SpanId, key: &str, value: impl Into<Value>) {
+        if let Some(span) = self.open.iter_mut().find(|open| open.id == span) {
+            span.attributes.push((key.to_string(), value.into()));
+        }
+    }
+
+    /// Sets an attribute of the innermost open span.
+    pub fn set_current(&mut self, key: &str, value: impl Into<Value>) {
+        if let Some(span) = self.open.last_mut() {
+            span.attributes.push((key.to_string(), value.into()));
+        }
+    }
+
+    /// Marks an open span as failed, `class` is recorded as its `error.type`.
+    pub fn fail(&mut self, span: SpanId, class: &str, message: impl Into<String>) {
+        self.set(span, "error.type", class);
+        if let Some(span) = self.open.iter_mut().find(|open| open.id == span) {
+            span.error = Some(message.into());
+        }
+    }
+
+    /// Ends `span` along with the spans still open under it.
+    pub fn end(&mut self, span: SpanId) {
+        let Some(pos) = self.open.iter().position(|open| open.id == span) else {
+            return;
+        };
+        let now = SystemTime::now();
+        for mut span in self.open.drain(pos..).rev() {
+            span.end = Some(now);
+            self.ended.push(span);
+        }
+        if self.ended.len() >= BATCH_SIZE {
+            self.export();
+        }
+    }
+
+    /// Ends the run normally and exports every span.
+    pub fn finish(&mut self) {
+        self.finished = true;
+        self.end(self.run);
+        self.export();
+    }
+
+    /// Sends the ended spans. A collector that can't be reached loses them, without failing the run.
+    fn export(&mut self) {
+        if self.ended.is_empty() {
+            return;
+        }
+        let spans = std::mem::take(&mut self.ended);
+        if let Err(e) = self.send(&spans) {
+            if !self.export_failed {
+                log::warn!("Could not export trace spans to '{}': {:#}", self.endpoint.host, e);
+                self.export_failed = true;
+            }
+        }
+    }
+
+    fn send(&self, spans: &[Span]) -> anyhow::Result<()> {
+        let body = self.to_json(spans).to_string();
+        let mut stream = TcpStream::connect(&self.endpoint.addr).context("connect to the OTLP endpoint")?;
+        stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+        stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.endpoint.path,
+            self.endpoint.host,
+            body.len(),
+            body
+        )
+        .context("send spans")?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status).context("read response")?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => anyhow::bail!("collector answered '{}'", status.trim_end()),
+        }
+    }
+
+    fn to_json(&self, spans: &[Span]) -> Value {
+        let spans: Vec<Value> = spans
+            .iter()
+            .map(|span| {
+                let mut value = json!({
+                    "traceId": format!("{:032x}", self.trace_id),
+                    "spanId": format!("{:016x}", span.id.0),
+                    "name": span.name,
+                    // Internal, the spans aren't requests to or from anything.
+                    "kind": 1,
+                    "startTimeUnixNano": nanos(span.start).to_string(),
+                    "endTimeUnixNano": nanos(span.end.unwrap_or(span.start)).to_string(),
+                    "attributes": span.attributes.iter().map(|(key, value)| attribute(key, value)).collect::<Vec<_>>(),
+                    "status": match &span.error {
+                        Some(message) => json!({"code": 2, "message": message}),
+                        None => json!({"code": 0}),
+                    },
+                });
+                if let Some(parent) = span.parent {
+                    value["parentSpanId"] = format!("{:016x}", parent.0).into();
+                }
+                value
+            })
+            .collect();
+        json!({
+            "resourceSpans": [{
+                "resource": {"attributes": [attribute("service.name", &"rarscan".into())]},
+                "scopeSpans": [{
+                    "scope": {"name": "rarscan", "version": env!("CARGO_PKG_VERSION")},
+                    "spans": spans,
+                }],
+            }],
+        })
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.fail(self.run, "aborted", "run ended by an error");
+            self.finished = true;
+            self.end(self.run);
+        }
+        self.export();
+    }
+}
+
+/// OTLP key-value pair, integers are encoded as strings like the protobuf JSON mapping wants.
+fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({"boolValue": b}),
+        Value::Number(n) if n.is_f64() => json!({"doubleValue": n}),
+        Value::Number(n) => json!({"intValue": n.to_string()}),
+        Value::String(s) => json!({"stringValue": s}),
+        other => json!({"stringValue": other.to_string()}),
+    };
+    json!({"key": key, "value": value})
+}
+
+fn nanos(t: SystemTime) -> u128 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
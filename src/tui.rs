@@ -0,0 +1,552 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use log::{Level, Record};
+
+use crate::{control::Control, estimate::format_duration, events::Event, format_size, walk::ScanProgress};
+
+/// How often the screen is redrawn, and so how long a resize or a key waits at most.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+/// Warnings kept for the pane of recent warnings.
+const MAX_WARNINGS: usize = 100;
+const KEYS_HELP: &str = "p pause after current   s skip current   q quit after current";
+
+/// Takes the warnings and errors logged while the dashboard is on screen, the console would scribble over it. The log
+/// file still gets them.
+#[derive(Debug, Default)]
+pub struct Capture {
+    active: AtomicBool,
+    warnings: Mutex<VecDeque<String>>,
+}
+
+impl Capture {
+    /// Whether `record` is taken by the dashboard rather than written to the console.
+    pub fn capture(&self, record: &Record) -> bool {
+        if !self.active.load(Ordering::Relaxed) {
+            return false;
+        }
+        if record.level() <= Level::Warn {
+            let mut warnings = self.warnings.lock().unwrap_or_else(PoisonError::into_inner);
+            if warnings.len() == MAX_WARNINGS {
+                warnings.pop_front();
+            }
+            warnings.push_back(format!("{:<5} {}", record.level(), record.args()));
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveStatus {
+    Queued,
+    Extracting,
+    Done(&'static str),
+}
+
+#[derive(Debug)]
+struct Current {
+    path: PathBuf,
+    unpacked_size: u64,
+    written: u64,
+    started: Instant,
+}
+
+/// What the dashboard knows of the run, from the events.
+#[derive(Debug, Default)]
+struct View {
+    root_dir: PathBuf,
+    started: Option<Instant>,
+    archives: Vec<(PathBuf, ArchiveStatus)>,
+    index: HashMap<PathBuf, usize>,
+    current: Option<Current>,
+    /// How far the scan got at its last report, until it's done.
+    scan: Option<ScanProgress>,
+    scanning: bool,
+    done: usize,
+    failed: usize,
+    parts_removed: u64,
+    bytes_written: u64,
+    /// Time spent extracting the archives done, for the throughput.
+    extracting: Duration,
+    finished: bool,
+}
+
+impl View {
+    fn set_status(&mut self, path: &Path, status: ArchiveStatus) {
+        match self.index.get(path) {
+            Some(&i) => self.archives[i].1 = status,
+            None => {
+                self.index.insert(path.to_path_buf(), self.archives.len());
+                self.archives.push((path.to_path_buf(), status));
+            }
+        }
+    }
+
+    fn observe(&mut self, event: &Event) {
+        match *event {
+            Event::ScanStart { root_dir } => {
+                self.root_dir = root_dir.to_path_buf();
+                self.started = Some(Instant::now());
+                self.scanning = true;
+            }
+            Event::ScanProgress { progress, .. } => self.scan = Some(*progress),
+            Event::ScanDone { .. } => self.scanning = false,
+            Event::ArchiveFound { path } => {
+                if !self.index.contains_key(path) {
+                    self.set_status(path, ArchiveStatus::Queued);
+                }
+            }
+            Event::ExtractStart {
+                archive, unpacked_size, ..
+            } => {
+                self.set_status(archive, ArchiveStatus::Extracting);
+                self.current = Some(Current {
+                    path: archive.to_path_buf(),
+                    unpacked_size,
+                    written: 0,
+                    started: Instant::now(),
+                });
+            }
+            Event::FileExtracted { size, .. } => {
+                if let Some(current) = &mut self.current {
+                    current.written += size;
+                }
+                self.bytes_written += size;
+            }
+            Event::ArchiveStart { .. } | Event::ExtractDone { .. } => {}
+            Event::ArchiveDone { archive, outcome, .. } => {
+                self.set_status(archive, ArchiveStatus::Done(outcome));
+                self.done += 1;
+                if outcome == "failed" {
+                    self.failed += 1;
+                }
+                if let Some(current) = self.current.take() {
+                    self.extracting += current.started.elapsed();
+                }
+            }
+            Event::PartRemoved { .. } => self.parts_removed += 1,
+            Event::Corruption { .. } => {}
+            Event::RunSummary { .. } => self.finished = true,
+        }
+    }
+
+    /// Bytes per second over the extractions so far, the current one included.
+    fn throughput(&self) -> Option<f64> {
+        let current = self.current.as_ref().map(|current| current.started.elapsed());
+        let secs = (self.extracting + current.unwrap_or_default()).as_secs_f64();
+        (secs > 0.0).then(|| self.bytes_written as f64 / secs)
+    }
+
+    /// Time left for the whole run, from the average time spent per archive so far.
+    fn eta(&self) -> Option<Duration> {
+        let started = self.started?;
+        if self.done == 0 {
+            return None;
+        }
+        let left = self.archives.len().saturating_sub(self.done);
+        Some(started.elapsed().div_f64(self.done as f64).mul_f64(left as f64))
+    }
+
+    fn display_path<'a>(&self, path: &'a Path) -> std::path::Display<'a> {
+        path.strip_prefix(&self.root_dir).unwrap_or(path).display()
+    }
+
+    /// The screen, `height` lines of at most `width` characters.
+    fn render(&self, warnings: &VecDeque<String>, control: &Control, width: usize, height: usize) -> Vec<String> {
+        let mut lines = Vec::with_capacity(height);
+
+        let mut header = format!("rarscan   {}/{} archives", self.done, self.archives.len());
+        if self.failed > 0 {
+            header += &format!("   {} failed", self.failed);
+        }
+        if self.parts_removed > 0 {
+            header += &format!("   {} parts removed", self.parts_removed);
+        }
+        if let Some(throughput) = self.throughput() {
+            header += &format!("   {}/s", format_size(throughput as u64));
+        }
+        if let Some(eta) = self.eta().filter(|_| !self.finished) {
+            header += &format!("   ETA {}", format_duration(eta));
+        }
+        if control.quitting() {
+            header += "   [quitting after the current archive]";
+        } else if control.is_paused() {
+            header += "   [paused after the current archive]";
+        }
+        lines.push(header);
+
+        match &self.current {
+            Some(current) => {
+                lines.push(format!("Extracting '{}'", self.display_path(&current.path)));
+                let ratio = match current.unpacked_size {
+                    0 => 1.0,
+                    size => (current.written as f64 / size as f64).min(1.0),
+                };
+                let label = format!(
+                    " {:>3}%  {} / {}",
+                    (ratio * 100.0) as u32,
+                    format_size(current.written),
+                    format_size(current.unpacked_size)
+                );
+                let bar_width = width.saturating_sub(label.len() + 2).min(60);
+                let filled = (ratio * bar_width as f64) as usize;
+                lines.push(format!(
+                    "[{}{}]{}",
+                    "#".repeat(filled),
+                    "-".repeat(bar_width - filled),
+                    label
+                ));
+            }
+            None if self.finished => lines.extend(["Done".to_string(), String::new()]),
+            None if self.scanning => {
+                lines.push(format!("Scanning '{}'", self.root_dir.display()));
+                lines.push(match &self.scan {
+                    Some(scan) => format!(
+                        "{} directories, {} of {} at the top",
+                        scan.dirs, scan.top_dirs_entered, scan.top_dirs
+                    ),
+                    None => String::new(),
+                });
+            }
+            None => lines.extend([String::new(), String::new()]),
+        }
+        lines.push(String::new());
+
+        let warning_rows = (height / 4).clamp(1, 8);
+        let queue_rows = height.saturating_sub(lines.len() + warning_rows + 3);
+        // The window follows the first archive not done yet, with some of the ones done above it.
+        let cursor = self
+            .archives
+            .iter()
+            .position(|(_, status)| !matches!(status, ArchiveStatus::Done(_)))
+            .unwrap_or(self.archives.len());
+        let first = cursor
+            .saturating_sub(queue_rows / 3)
+            .min(self.archives.len().saturating_sub(queue_rows));
+        for i in first..first + queue_rows {
+            lines.push(match self.archives.get(i) {
+                Some((path, status)) => {
+                    let status = match status {
+                        ArchiveStatus::Queued => "queued".to_string(),
+                        ArchiveStatus::Extracting => "extracting".to_string(),
+                        ArchiveStatus::Done(outcome) => outcome.replace('_', " "),
+                    };
+                    format!("{:<18}{}", status, self.display_path(path))
+                }
+                None => String::new(),
+            });
+        }
+
+        lines.push(format!("Warnings ({})", warnings.len()));
+        let skipped = warnings.len().saturating_sub(warning_rows);
+        for i in 0..warning_rows {
+            lines.push(warnings.get(skipped + i).cloned().unwrap_or_default());
+        }
+        lines.push(String::new());
+        lines.push(KEYS_HELP.to_string());
+
+        lines.truncate(height);
+        for line in &mut lines {
+            if let Some((end, _)) = line.char_indices().nth(width) {
+                line.truncate(end);
+            }
+        }
+        lines
+    }
+}
+
+/// Feeds the events of the queue to the dashboard.
+#[derive(Clone)]
+pub struct DashboardSink {
+    view: Arc<Mutex<View>>,
+}
+
+impl DashboardSink {
+    pub fn observe(&self, event: &Event) {
+        self.view.lock().unwrap_or_else(PoisonError::into_inner).observe(event);
+    }
+}
+
+/// Full-screen view of the queue, drawn and reading keys from its own thread. The terminal is given back as it was
+/// when dropped.
+pub struct Dashboard {
+    view: Arc<Mutex<View>>,
+    capture: Arc<Capture>,
+    control: Arc<Control>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Dashboard {
+    /// Takes over the terminal. When stdin or stdout isn't a terminal there is no dashboard, the log goes on as usual.
+    pub fn start(capture: Arc<Capture>) -> anyhow::Result<Option<Dashboard>> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            log::info!("Not running in a terminal, logging instead of showing the dashboard.");
+            return Ok(None);
+        }
+        let Some(terminal) = term::Terminal::enter().context("set up the terminal")? else {
+            log::info!("The terminal isn't supported, logging instead of showing the dashboard.");
+            return Ok(None);
+        };
+        capture.active.store(true, Ordering::Relaxed);
+
+        let view = Arc::new(Mutex::new(View::default()));
+        let control = Arc::new(Control::default());
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let view = view.clone();
+            let capture = capture.clone();
+            let control = control.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut size = (0, 0);
+                while !stop.load(Ordering::Relaxed) {
+                    for key in terminal.read_keys(REFRESH_INTERVAL) {
+                        match key {
+                            b'p' | b'P' => {
+                                control.toggle_pause();
+                            }
+                            b's' | b'S' => control.skip_current(),
+                            // Ctrl-C is read as a key, a second one doesn't wait for the current archive.
+                            3 if control.quitting() => {
+                                drop(terminal);
+                                std::process::exit(130);
+                            }
+                            b'q' | b'Q' | 3 => control.quit(),
+                            _ => {}
+                        }
+                    }
+                    let (width, height) = terminal.size();
+                    let lines = {
+                        let warnings = capture.warnings.lock().unwrap_or_else(PoisonError::into_inner);
+                        let view = view.lock().unwrap_or_else(PoisonError::into_inner);
+                        view.render(&warnings, &control, width, height)
+                    };
+                    let resized = size != (width, height);
+                    size = (width, height);
+                    if let Err(e) = terminal.draw(&lines, resized) {
+                        log::debug!("Could not draw the dashboard: {}", e);
+                    }
+                }
+            })
+        };
+        Ok(Some(Dashboard {
+            view,
+            capture,
+            control,
+            stop,
+            thread: Some(thread),
+        }))
+    }
+
+    pub fn sink(&self) -> DashboardSink {
+        DashboardSink {
+            view: self.view.clone(),
+        }
+    }
+
+    pub fn control(&self) -> Arc<Control> {
+        self.control.clone()
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.capture.active.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(unix)]
+mod term {
+    use std::{
+        io::{self, Write},
+        mem,
+        time::Duration,
+    };
+
+    /// The terminal in raw mode on the alternate screen, given back as it was when dropped.
+    pub struct Terminal {
+        saved: libc::termios,
+    }
+
+    impl Terminal {
+        pub fn enter() -> io::Result<Option<Terminal>> {
+            // SAFETY: tcgetattr only writes to `saved`.
+            let mut saved: libc::termios = unsafe { mem::zeroed() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = saved;
+            // Keys are read as they are typed, without echo, and Ctrl-C is read as a key so that quitting gives the
+            // terminal back.
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+            // SAFETY: `raw` is a valid termios, read from the terminal.
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let terminal = Terminal { saved };
+            let mut stdout = io::stdout();
+            write!(stdout, "\x1b[?1049h\x1b[?25l\x1b[2J")?;
+            stdout.flush()?;
+            Ok(Some(terminal))
+        }
+
+        /// Columns and rows, read again on every draw to follow resizes.
+        pub fn size(&self) -> (usize, usize) {
+            // SAFETY: TIOCGWINSZ only writes to `size`.
+            let mut size: libc::winsize = unsafe { mem::zeroed() };
+            if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+                return (80, 24);
+            }
+            (size.ws_col as usize, size.ws_row as usize)
+        }
+
+        /// Keys typed within `timeout`.
+        pub fn read_keys(&self, timeout: Duration) -> Vec<u8> {
+            let mut fd = libc::pollfd {
+                fd: libc::STDIN_FILENO,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `fd` is a single valid pollfd.
+            if unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+                return Vec::new();
+            }
+            let mut buf = [0u8; 32];
+            // SAFETY: at most `buf.len()` bytes are written to `buf`.
+            let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+            buf[..n.max(0) as usize].to_vec()
+        }
+
+        /// Draws the screen from the top, clearing it first after a resize.
+        pub fn draw(&self, lines: &[String], clear: bool) -> io::Result<()> {
+            let mut screen = String::from(if clear { "\x1b[2J\x1b[H" } else { "\x1b[H" });
+            for (i, line) in lines.iter().enumerate() {
+                if i > 0 {
+                    screen.push_str("\r\n");
+                }
+                screen.push_str(line);
+                screen.push_str("\x1b[K");
+            }
+            screen.push_str("\x1b[J");
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(screen.as_bytes())?;
+            stdout.flush()
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            let mut stdout = io::stdout();
+            let _ = write!(stdout, "\x1b[?25h\x1b[?1049l");
+            let _ = stdout.flush();
+            // SAFETY: `saved` is the termios read when entering.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved) };
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod term {
+    use std::{io, time::Duration};
+
+    /// The dashboard is only drawn on unix terminals.
+    pub struct Terminal;
+
+    impl Terminal {
+        pub fn enter() -> io::Result<Option<Terminal>> {
+            Ok(None)
+        }
+
+        pub fn size(&self) -> (usize, usize) {
+            (80, 24)
+        }
+
+        pub fn read_keys(&self, _: Duration) -> Vec<u8> {
+            Vec::new()
+        }
+
+        pub fn draw(&self, _: &[String], _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_fits_the_terminal() {
+        let mut view = View::default();
+        view.observe(&Event::ScanStart {
+            root_dir: Path::new("/tv"),
+        });
+        for name in ["a", "b", "c"] {
+            view.observe(&Event::ArchiveFound {
+                path: &Path::new("/tv").join(name).join(format!("{}.rar", name)),
+            });
+        }
+        view.observe(&Event::ArchiveDone {
+            archive: Path::new("/tv/a/a.rar"),
+            archive_id: None,
+            outcome: "already_extracted",
+        });
+        view.observe(&Event::ExtractStart {
+            archive: Path::new("/tv/b/b.rar"),
+            archive_id: None,
+            format: crate::archive::Format::Rar5,
+            dest: Path::new("/tv/b"),
+            packed_size: 100,
+            unpacked_size: 200,
+        });
+        view.observe(&Event::FileExtracted {
+            archive: Path::new("/tv/b/b.rar"),
+            file: Path::new("b.mkv"),
+            size: 50,
+        });
+        let warnings = VecDeque::from(["WARN  something".to_string()]);
+        let control = Control::default();
+        control.toggle_pause();
+
+        let lines = view.render(&warnings, &control, 40, 20);
+        assert_eq!(lines.len(), 20);
+        assert!(lines.iter().all(|line| line.chars().count() <= 40), "{:#?}", lines);
+        assert!(lines[0].starts_with("rarscan   1/3 archives"), "{:#?}", lines);
+        assert!(lines[1].starts_with("Extracting 'b/b.rar'"), "{:#?}", lines);
+        assert!(lines[2].contains(" 25%"), "{:#?}", lines);
+        assert!(
+            lines.contains(&format!("{:<18}{}", "already extracted", "a/a.rar")),
+            "{:#?}",
+            lines
+        );
+        assert!(
+            lines.contains(&format!("{:<18}{}", "extracting", "b/b.rar")),
+            "{:#?}",
+            lines
+        );
+        assert!(lines.contains(&"WARN  something".to_string()), "{:#?}", lines);
+        assert_eq!(lines[19], KEYS_HELP.chars().take(40).collect::<String>());
+
+        // Too small for the queue, the header still shows.
+        let lines = view.render(&warnings, &control, 20, 3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "rarscan   1/3 archiv");
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Suffix of the directory unpackerr extracts a download into, next to the download. The files are moved back into
+/// the download once the extraction succeeds and the directory removed, one left behind is an extraction that was
+/// interrupted or never moved back.
+pub const STAGING_SUFFIX: &str = "_unpackerred";
+
+/// Whether `path` is a staging directory of unpackerr or inside of one.
+pub fn in_staging_dir(path: &Path) -> bool {
+    path.components()
+        .any(|component| component.as_os_str().to_string_lossy().ends_with(STAGING_SUFFIX))
+}
+
+/// Where unpackerr would have left the files of `archive` in a staging directory that still exists, with that
+/// directory. The download is any directory holding the archive below `root_dir`, the files of an archive in a
+/// subdirectory of the download are staged under the same subdirectory.
+pub fn staged_dest(root_dir: &Path, archive: &Path) -> Option<(PathBuf, PathBuf)> {
+    let dir = archive.parent()?;
+    dir.ancestors()
+        .take_while(|download| download.starts_with(root_dir) && *download != root_dir)
+        .find_map(|download| {
+            let name = download.file_name()?.to_string_lossy();
+            let staging = download.with_file_name(format!("{}{}", name, STAGING_SUFFIX));
+            staging.is_dir().then(|| {
+                let relative = dir.strip_prefix(download).expect("ancestor");
+                (staging.join(relative), staging)
+            })
+        })
+}
+
+/// The staging directories directly in `dir` or below it, without looking inside of them.
+pub fn staging_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        if in_staging_dir(Path::new(&entry.file_name())) {
+            found.push(path);
+        } else {
+            found.extend(staging_dirs(&path)?);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// What the extraction of an archive by unpackerr got to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Traces {
+    /// Every file is next to the archive with the right size and checksum.
+    pub extracted_in_place: bool,
+    /// Some of its files are next to the archive.
+    pub partly_in_place: bool,
+    /// Whether a staging directory holds files of the archive, and all of them.
+    pub staged: Option<bool>,
+}
+
+/// What importing an archive does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// Recorded in the state file as extracted.
+    Adopt,
+    /// Complete in the staging directory, the files get moved next to the archive and adopted when cleaning.
+    MoveIntoPlace,
+    /// Extracted again, the files of unpackerr are incomplete.
+    Requeue,
+    /// Never extracted by unpackerr, a run extracts it like any other.
+    Untouched,
+}
+
+impl Verdict {
+    pub fn of(traces: Traces) -> Verdict {
+        match traces {
+            Traces {
+                extracted_in_place: true,
+                ..
+            } => Verdict::Adopt,
+            Traces {
+                staged: Some(true),
+                partly_in_place: false,
+                ..
+            } => Verdict::MoveIntoPlace,
+            Traces { staged: Some(_), .. }
+            | Traces {
+                partly_in_place: true, ..
+            } => Verdict::Requeue,
+            Traces { staged: None, .. } => Verdict::Untouched,
+        }
+    }
+}
+
+/// Moves `from` to `to`, across filesystems too, creating the directories of `to`.
+pub fn move_into_place(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    crate::tier::move_file(from, to)
+}
+
+/// How the archives of the directory were taken over.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub adopted: Vec<PathBuf>,
+    /// Adopted once their files were moved out of a staging directory.
+    pub moved: Vec<PathBuf>,
+    pub requeued: Vec<PathBuf>,
+    /// Complete in a staging directory, left there without --clean.
+    pub staged: Vec<PathBuf>,
+    pub untouched: Vec<PathBuf>,
+    /// Staging directories removed, or that would be in a dry-run.
+    pub removed_staging_dirs: Vec<PathBuf>,
+    /// Staging directories left behind, without --clean or holding files of no archive.
+    pub kept_staging_dirs: Vec<PathBuf>,
+}
+
+impl ImportSummary {
+    pub fn log(&self) {
+        log::info!(
+            "Imported {} archives from unpackerr: {} adopted, {} re-queued, {} left untouched.",
+            self.adopted.len() + self.moved.len() + self.requeued.len() + self.staged.len() + self.untouched.len(),
+            self.adopted.len() + self.moved.len(),
+            self.requeued.len(),
+            self.staged.len() + self.untouched.len()
+        );
+        let lists = [
+            (
+                &self.moved,
+                "adopted after moving their files out of the staging directory",
+            ),
+            (&self.requeued, "re-queued, unpackerr left them partly extracted"),
+            (
+                &self.staged,
+                "left complete in their staging directory, --clean moves them into place",
+            ),
+            (&self.untouched, "left untouched, they were never extracted"),
+            (&self.removed_staging_dirs, "staging directories removed"),
+            (&self.kept_staging_dirs, "staging directories kept"),
+        ];
+        for (paths, what) in lists {
+            if paths.is_empty() {
+                continue;
+            }
+            log::info!("{} {}:", paths.len(), what);
+            for path in paths {
+                log::info!("-> '{}'", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verdicts_follow_what_unpackerr_left() {
+        let traces = |extracted_in_place, partly_in_place, staged| Traces {
+            extracted_in_place,
+            partly_in_place,
+            staged,
+        };
+        assert_eq!(Verdict::of(traces(true, true, None)), Verdict::Adopt);
+        // Moved back, with the staging directory left behind.
+        assert_eq!(Verdict::of(traces(true, true, Some(true))), Verdict::Adopt);
+        assert_eq!(Verdict::of(traces(false, false, Some(true))), Verdict::MoveIntoPlace);
+        // Interrupted while moving the files back.
+        assert_eq!(Verdict::of(traces(false, true, Some(true))), Verdict::Requeue);
+        assert_eq!(Verdict::of(traces(false, false, Some(false))), Verdict::Requeue);
+        assert_eq!(Verdict::of(traces(false, true, None)), Verdict::Requeue);
+        assert_eq!(Verdict::of(traces(false, false, None)), Verdict::Untouched);
+    }
+
+    #[test]
+    fn staging_dirs_sit_next_to_the_download() {
+        let root = std::env::temp_dir().join(format!("rarscan-unpackerr-test-{}", std::process::id()));
+        let download = root.join("tv/Show.S01E01");
+        fs::create_dir_all(download.join("Subs")).unwrap();
+        fs::create_dir_all(root.join("tv/Show.S01E01_unpackerred/Subs")).unwrap();
+        fs::create_dir_all(root.join("tv/Movie_unpackerred")).unwrap();
+
+        assert_eq!(
+            staged_dest(&root, &download.join("show.rar")),
+            Some((
+                root.join("tv/Show.S01E01_unpackerred"),
+                root.join("tv/Show.S01E01_unpackerred")
+            ))
+        );
+        assert_eq!(
+            staged_dest(&root, &download.join("Subs/subs.rar")),
+            Some((
+                root.join("tv/Show.S01E01_unpackerred/Subs"),
+                root.join("tv/Show.S01E01_unpackerred")
+            ))
+        );
+        assert_eq!(staged_dest(&root, &root.join("tv/Other/other.rar")), None);
+        assert_eq!(
+            staging_dirs(&root).unwrap(),
+            [
+                root.join("tv/Movie_unpackerred"),
+                root.join("tv/Show.S01E01_unpackerred")
+            ]
+        );
+        assert!(in_staging_dir(&root.join("tv/Movie_unpackerred/movie.mkv")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
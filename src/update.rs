@@ -0,0 +1,277 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/sbstp/rarscan/releases/latest";
+const TARGET: &str = env!("RARSCAN_TARGET");
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The latest release, with the asset built for this target.
+struct Release {
+    version: Version,
+    asset_name: String,
+    asset_url: String,
+    /// SHA-256 given by GitHub along with the asset, hex encoded.
+    digest: Option<String>,
+    /// Checksum files among the assets of the release, by name.
+    checksum_urls: Vec<(String, String)>,
+}
+
+/// Major, minor and patch numbers of a release, pre-release suffixes are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Version(u64, u64, u64);
+
+impl Version {
+    fn parse(s: &str) -> Option<Version> {
+        let s = s.strip_prefix('v').unwrap_or(s);
+        let s = s.split(['-', '+']).next()?;
+        let mut numbers = s.split('.').map(|n| n.parse().ok());
+        let version = Version(
+            numbers.next()??,
+            numbers.next().unwrap_or(Some(0))?,
+            numbers.next().unwrap_or(Some(0))?,
+        );
+        numbers.next().is_none().then_some(version)
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+fn agent() -> ureq::Agent {
+    // HTTPS_PROXY, HTTP_PROXY and ALL_PROXY, like curl.
+    ureq::AgentBuilder::new()
+        .try_proxy_from_env(true)
+        .timeout(TIMEOUT)
+        .build()
+}
+
+fn get(agent: &ureq::Agent, url: &str) -> anyhow::Result<ureq::Response> {
+    agent
+        .get(url)
+        .set("User-Agent", concat!("rarscan/", env!("CARGO_PKG_VERSION")))
+        .set("Accept", "application/vnd.github+json")
+        .call()
+        .with_context(|| format!("GET {}", url))
+}
+
+fn latest_release(agent: &ureq::Agent) -> anyhow::Result<Release> {
+    let body: Value =
+        serde_json::from_reader(get(agent, LATEST_RELEASE_URL)?.into_reader()).context("parse the latest release")?;
+    let tag = body
+        .get("tag_name")
+        .and_then(Value::as_str)
+        .context("release without a tag")?;
+    let version = Version::parse(tag).with_context(|| format!("release tag '{}' is not a version", tag))?;
+    let assets: Vec<(String, String, Option<String>)> = body
+        .get("assets")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|asset| {
+            let name = asset.get("name")?.as_str()?.to_string();
+            let url = asset.get("browser_download_url")?.as_str()?.to_string();
+            let digest = asset.get("digest").and_then(Value::as_str);
+            let digest = digest
+                .and_then(|digest| digest.strip_prefix("sha256:"))
+                .map(str::to_lowercase);
+            Some((name, url, digest))
+        })
+        .collect();
+    let is_checksum = |name: &str| {
+        let name = name.to_lowercase();
+        name.ends_with(".sha256") || name.contains("sha256sums")
+    };
+    let (asset_name, asset_url, digest) = assets
+        .iter()
+        .find(|(name, _, _)| name.contains(TARGET) && !is_checksum(name))
+        .cloned()
+        .with_context(|| format!("release {} has no build for {}", tag, TARGET))?;
+    let checksum_urls = assets
+        .into_iter()
+        .filter(|(name, _, _)| is_checksum(name))
+        .map(|(name, url, _)| (name, url))
+        .collect();
+    Ok(Release {
+        version,
+        asset_name,
+        asset_url,
+        digest,
+        checksum_urls,
+    })
+}
+
+/// The checksum of `asset` in the sums of a `.sha256` or `SHA256SUMS` file, `<hex>  <name>` lines or a bare hash.
+fn find_checksum(sums: &str, asset: &str) -> Option<String> {
+    let mut lines = sums.lines().map(str::trim).filter(|line| !line.is_empty());
+    let checksum = lines.find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let hash = fields.next()?;
+        match fields.next() {
+            Some(name) if name.trim_start_matches('*') == asset => Some(hash),
+            Some(_) => None,
+            None => Some(hash),
+        }
+    })?;
+    (checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())).then(|| checksum.to_lowercase())
+}
+
+/// The published SHA-256 of the asset, from GitHub or else from the checksum files of the release.
+fn published_checksum(agent: &ureq::Agent, release: &Release) -> anyhow::Result<String> {
+    if let Some(digest) = &release.digest {
+        return Ok(digest.clone());
+    }
+    let sidecar = format!("{}.sha256", release.asset_name);
+    let mut urls: Vec<_> = release.checksum_urls.iter().collect();
+    // The file of the asset first, then the ones covering every asset.
+    urls.sort_by_key(|(name, _)| name != &sidecar);
+    for (name, url) in urls {
+        let sums = get(agent, url)?.into_string().context("download checksums")?;
+        if let Some(checksum) = find_checksum(&sums, &release.asset_name) {
+            log::debug!("SHA-256 of '{}' found in '{}'.", release.asset_name, name);
+            return Ok(checksum);
+        }
+    }
+    anyhow::bail!(
+        "release {} publishes no SHA-256 for '{}'",
+        release.version,
+        release.asset_name
+    )
+}
+
+/// The binary of the asset, which is either the binary itself or a tarball holding it.
+fn unpack(asset_name: &str, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if !asset_name.ends_with(".tar.gz") && !asset_name.ends_with(".tgz") {
+        return Ok(data);
+    }
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(data.as_slice()));
+    for entry in archive.entries().context("read release tarball")? {
+        let mut entry = entry.context("read release tarball")?;
+        let is_binary = entry.path()?.file_name().is_some_and(|name| name == "rarscan");
+        if is_binary && entry.header().entry_type().is_file() {
+            let mut binary = Vec::new();
+            entry.read_to_end(&mut binary).context("read release tarball")?;
+            return Ok(binary);
+        }
+    }
+    anyhow::bail!("'{}' holds no rarscan binary", asset_name)
+}
+
+/// Replaces the binary at `exe` with `binary`, through a file next to it renamed over it. When the system refuses to
+/// replace a running binary, the old one is moved out of the way first.
+fn replace(exe: &Path, binary: &[u8]) -> anyhow::Result<()> {
+    let tmp = exe.with_file_name(".rarscan.update");
+    let mut file = File::create(&tmp).with_context(|| format!("create '{}'", tmp.display()))?;
+    file.write_all(binary).context("write the new binary")?;
+    file.sync_all().context("write the new binary")?;
+    drop(file);
+    let permissions = fs::metadata(exe).context("stat the running binary")?.permissions();
+    fs::set_permissions(&tmp, permissions).context("make the new binary executable")?;
+    match fs::rename(&tmp, exe) {
+        Ok(()) => Ok(()),
+        Err(e) if is_text_busy(&e) => {
+            let old = exe.with_file_name(".rarscan.old");
+            fs::rename(exe, &old).context("move the running binary aside")?;
+            if let Err(e) = fs::rename(&tmp, exe) {
+                let _ = fs::rename(&old, exe);
+                return Err(e).context("replace the running binary");
+            }
+            // Still in use by this process where it can't be removed, the next update replaces it.
+            let _ = fs::remove_file(&old);
+            Ok(())
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e).context("replace the running binary")
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_text_busy(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ETXTBSY)
+}
+
+#[cfg(not(unix))]
+fn is_text_busy(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::PermissionDenied
+}
+
+/// Looks for a newer release and, unless `check_only`, installs it over the running binary.
+pub fn run(check_only: bool) -> anyhow::Result<()> {
+    let current = Version::parse(env!("CARGO_PKG_VERSION")).expect("package version");
+    let agent = agent();
+    let release = latest_release(&agent)?;
+    if release.version <= current {
+        println!("rarscan {} is up to date.", current);
+        return Ok(());
+    }
+    println!("rarscan {} is available, this is {}.", release.version, current);
+    if check_only {
+        return Ok(());
+    }
+    let expected = published_checksum(&agent, &release)?;
+    log::info!("Downloading '{}'.", release.asset_name);
+    let mut data = Vec::new();
+    get(&agent, &release.asset_url)?
+        .into_reader()
+        .read_to_end(&mut data)
+        .context("download the release")?;
+    let actual = format!("{:x}", Sha256::digest(&data));
+    anyhow::ensure!(
+        actual == expected,
+        "'{}' has SHA-256 {} but {} was published, not installing it",
+        release.asset_name,
+        actual,
+        expected
+    );
+    let binary = unpack(&release.asset_name, data)?;
+    let exe: PathBuf = env::current_exe()
+        .and_then(fs::canonicalize)
+        .context("locate the running binary")?;
+    replace(&exe, &binary)?;
+    println!("Updated '{}' to rarscan {}.", exe.display(), release.version);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(Version::parse("v0.10.0"), Some(Version(0, 10, 0)));
+        assert_eq!(Version::parse("1.2"), Some(Version(1, 2, 0)));
+        assert_eq!(Version::parse("1.2.3-rc1"), Some(Version(1, 2, 3)));
+        assert_eq!(Version::parse("nightly"), None);
+        assert!(Version::parse("0.10.1") > Version::parse("0.9.12"));
+    }
+
+    #[test]
+    fn find_checksums() {
+        let hash = "a".repeat(64);
+        let sums = format!(
+            "{}  rarscan-x86_64-unknown-linux-gnu\n{}  other\n",
+            hash,
+            "b".repeat(64)
+        );
+        assert_eq!(
+            find_checksum(&sums, "rarscan-x86_64-unknown-linux-gnu"),
+            Some(hash.clone())
+        );
+        assert_eq!(find_checksum(&format!("{}\n", hash.to_uppercase()), "any"), Some(hash));
+        assert_eq!(find_checksum(&sums, "missing"), None);
+        assert_eq!(find_checksum("not-a-hash  missing", "missing"), None);
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+/// Bytes hashed between two checkpoints of a file's progress.
+pub const CHECKPOINT_INTERVAL: u64 = 1 << 30;
+
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// What identifies the content of a file without reading it: when any of it changes, the file must be hashed again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileKey {
+    pub size: u64,
+    pub mtime: f64,
+    pub inode: u64,
+}
+
+impl FileKey {
+    pub fn of(path: &Path) -> io::Result<FileKey> {
+        let md = fs::metadata(path)?;
+        let mtime = md
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        Ok(FileKey {
+            size: md.len(),
+            mtime,
+            inode: inode(&md),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn inode(md: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    md.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_: &fs::Metadata) -> u64 {
+    0
+}
+
+/// Progress of hashing a file: the CRC of its first `offset` bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    pub key: FileKey,
+    pub offset: u64,
+    pub crc: u32,
+}
+
+/// CRC32 of the file at `path`, resumed from `resume` when given. `checkpoint` is called with the progress every
+/// [`CHECKPOINT_INTERVAL`] bytes.
+pub fn crc32(
+    path: &Path,
+    resume: Option<(u64, u32)>,
+    mut checkpoint: impl FnMut(u64, u32) -> anyhow::Result<()>,
+) -> anyhow::Result<u32> {
+    let mut file = File::open(path)?;
+    let (mut offset, crc) = resume.unwrap_or((0, 0));
+    file.seek(SeekFrom::Start(offset))?;
+    let mut hasher = crc32fast::Hasher::new_with_initial_len(crc, offset);
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut next_checkpoint = offset + CHECKPOINT_INTERVAL;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        hasher.update(&buf[..n]);
+        offset += n as u64;
+        if offset >= next_checkpoint {
+            checkpoint(offset, hasher.clone().finalize())?;
+            next_checkpoint = offset + CHECKPOINT_INTERVAL;
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn resumed_hash_matches_full_hash() {
+        let path = env::temp_dir().join(format!("rarscan-verify-{}", process::id()));
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+
+        let full = crc32(&path, None, |_, _| Ok(())).unwrap();
+        let half = crc32fast::hash(&data[..40_000]);
+        let resumed = crc32(&path, Some((40_000, half)), |_, _| Ok(())).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(full, crc32fast::hash(&data));
+        assert_eq!(resumed, full);
+    }
+}
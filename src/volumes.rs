@@ -0,0 +1,557 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    archive::{self, Format},
+    fds, recovery, sidecar,
+};
+
+const RAR4_MAIN: u8 = 0x73;
+const RAR4_FILE: u8 = 0x74;
+const RAR4_END: u8 = 0x7b;
+const RAR4_LONG_BLOCK: u16 = 0x8000;
+const RAR5_MAIN: u64 = 1;
+const RAR5_FILE: u64 = 2;
+const RAR5_ENCRYPTION: u64 = 4;
+const RAR5_END: u64 = 5;
+
+/// Link directories created by the process, for unique names.
+static LINKED: AtomicUsize = AtomicUsize::new(0);
+
+/// A piece of an entry at the start or the end of a volume.
+#[derive(Debug, Clone, PartialEq)]
+struct Piece {
+    name: Vec<u8>,
+    unpacked_size: u64,
+    /// Continued from the previous volume for the first piece, into the next one for the last.
+    continued: bool,
+}
+
+/// What the headers of a volume tell about its place in its set. RAR volumes don't record the name of the next
+/// volume, unrar derives it from the name of the current one. The entries split across volumes and the volume
+/// numbers are what ties them together once renamed.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct VolumeHeaders {
+    first: bool,
+    /// Position in the set from 0, when recorded.
+    number: Option<u64>,
+    /// Volumes named `.partN.rar` rather than `.rar`, `.r00`, `.r01`...
+    new_numbering: bool,
+    /// Another volume follows this one.
+    more: bool,
+    first_piece: Option<Piece>,
+    last_piece: Option<Piece>,
+}
+
+impl VolumeHeaders {
+    /// Reads the headers of the volume at `path`, `None` when it isn't a volume or its headers are encrypted.
+    fn read(path: &Path) -> io::Result<Option<VolumeHeaders>> {
+        let _fds = fds::acquire(1);
+        let mut file = BufReader::new(File::open(path)?);
+        let mut signature = [0; 8];
+        let len = file.read(&mut signature)?;
+        match Format::detect_rar(&signature[..len]) {
+            Some(Format::Rar5) => read_rar5(&mut file),
+            Some(_) => {
+                // The RAR4 signature is a byte shorter.
+                file.seek(SeekFrom::Start(7))?;
+                read_rar4(&mut file)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Whether `next` is the volume after this one.
+    fn followed_by(&self, next: &VolumeHeaders) -> bool {
+        if next.first || !self.more {
+            return false;
+        }
+        let numbered = match (self.number, next.number) {
+            (Some(number), Some(next)) if next != number + 1 => return false,
+            (Some(_), Some(_)) => true,
+            _ => false,
+        };
+        match (&self.last_piece, &next.first_piece) {
+            (Some(last), Some(first)) if last.continued || first.continued => {
+                last.continued
+                    && first.continued
+                    && last.name == first.name
+                    && last.unpacked_size == first.unpacked_size
+            }
+            // Split between two entries, only the numbers tell.
+            _ => numbered,
+        }
+    }
+}
+
+/// Reads exactly `buf.len()` bytes, false at the end of the file.
+pub fn read_or_end(file: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid volume headers")
+}
+
+pub fn le_u16(bytes: &[u8], at: usize) -> io::Result<u64> {
+    let bytes = bytes.get(at..at + 2).ok_or_else(invalid)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as u64)
+}
+
+pub fn le_u32(bytes: &[u8], at: usize) -> io::Result<u64> {
+    let bytes = bytes.get(at..at + 4).ok_or_else(invalid)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64)
+}
+
+fn read_rar4(file: &mut (impl Read + Seek)) -> io::Result<Option<VolumeHeaders>> {
+    let mut headers = VolumeHeaders::default();
+    let mut base = [0; 7];
+    while read_or_end(file, &mut base)? {
+        let (kind, flags) = (base[2], le_u16(&base, 3)? as u16);
+        let size = le_u16(&base, 5)? as usize;
+        let mut body = vec![0; size.checked_sub(7).ok_or_else(invalid)?];
+        file.read_exact(&mut body)?;
+        let mut data = match flags & RAR4_LONG_BLOCK {
+            0 => 0,
+            _ => le_u32(&body, 0)?,
+        };
+        match kind {
+            RAR4_MAIN => {
+                // Not a volume, or with encrypted headers.
+                if flags & 0x0001 == 0 || flags & 0x0080 != 0 {
+                    return Ok(None);
+                }
+                headers.new_numbering = flags & 0x0010 != 0;
+                headers.first = flags & 0x0100 != 0;
+            }
+            RAR4_FILE => {
+                let mut unpacked_size = le_u32(&body, 4)?;
+                let name_size = le_u16(&body, 19)? as usize;
+                let mut name_at = 25;
+                if flags & 0x0100 != 0 {
+                    data |= le_u32(&body, 25)? << 32;
+                    unpacked_size |= le_u32(&body, 29)? << 32;
+                    name_at = 33;
+                }
+                let name = body.get(name_at..name_at + name_size).ok_or_else(invalid)?.to_vec();
+                let piece = |continued| Piece {
+                    name: name.clone(),
+                    unpacked_size,
+                    continued,
+                };
+                headers.first_piece.get_or_insert_with(|| piece(flags & 0x0001 != 0));
+                headers.last_piece = Some(piece(flags & 0x0002 != 0));
+            }
+            RAR4_END => {
+                headers.more = flags & 0x0001 != 0;
+                if flags & 0x0008 != 0 {
+                    let at = if flags & 0x0002 != 0 { 4 } else { 0 };
+                    headers.number = Some(le_u16(&body, at)?);
+                }
+                return Ok(Some(headers));
+            }
+            _ => {}
+        }
+        file.seek(SeekFrom::Current(i64::try_from(data).map_err(|_| invalid())?))?;
+    }
+    // Without an end of archive block, only a split entry tells that a volume follows.
+    headers.more = headers.last_piece.as_ref().is_some_and(|piece| piece.continued);
+    Ok(Some(headers))
+}
+
+/// Reads a RAR5 variable length integer, 7 bits per byte with the high bit set on all but the last.
+pub fn vint(bytes: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..70).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or_else(invalid)?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid())
+}
+
+pub fn skip<'a>(bytes: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    let (skipped, rest) = (bytes.get(..len).ok_or_else(invalid)?, &bytes[len..]);
+    *bytes = rest;
+    Ok(skipped)
+}
+
+fn read_rar5(file: &mut (impl Read + Seek)) -> io::Result<Option<VolumeHeaders>> {
+    let mut headers = VolumeHeaders {
+        new_numbering: true,
+        ..VolumeHeaders::default()
+    };
+    let mut crc = [0; 4];
+    while read_or_end(file, &mut crc)? {
+        // The size is a vint itself, at most 3 bytes for the 2 MiB a header may take.
+        let mut size = 0;
+        for shift in (0..21).step_by(7) {
+            let mut byte = [0];
+            file.read_exact(&mut byte)?;
+            size |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut header = vec![0; size];
+        file.read_exact(&mut header)?;
+        let mut fields = header.as_slice();
+        let kind = vint(&mut fields)?;
+        let flags = vint(&mut fields)?;
+        if flags & 0x0001 != 0 {
+            vint(&mut fields)?;
+        }
+        let data = if flags & 0x0002 != 0 { vint(&mut fields)? } else { 0 };
+        match kind {
+            RAR5_MAIN => {
+                let archive_flags = vint(&mut fields)?;
+                if archive_flags & 0x0001 == 0 {
+                    return Ok(None);
+                }
+                // Every volume but the first has its number.
+                headers.first = archive_flags & 0x0002 == 0;
+                headers.number = Some(if headers.first { 0 } else { vint(&mut fields)? });
+            }
+            RAR5_FILE => {
+                let file_flags = vint(&mut fields)?;
+                let unpacked_size = vint(&mut fields)?;
+                vint(&mut fields)?;
+                if file_flags & 0x0002 != 0 {
+                    skip(&mut fields, 4)?;
+                }
+                if file_flags & 0x0004 != 0 {
+                    skip(&mut fields, 4)?;
+                }
+                vint(&mut fields)?;
+                vint(&mut fields)?;
+                let name_size = usize::try_from(vint(&mut fields)?).map_err(|_| invalid())?;
+                let name = skip(&mut fields, name_size)?.to_vec();
+                let piece = |continued| Piece {
+                    name: name.clone(),
+                    unpacked_size,
+                    continued,
+                };
+                headers.first_piece.get_or_insert_with(|| piece(flags & 0x0008 != 0));
+                headers.last_piece = Some(piece(flags & 0x0010 != 0));
+            }
+            RAR5_ENCRYPTION => return Ok(None),
+            RAR5_END => {
+                headers.more = vint(&mut fields)? & 0x0001 != 0;
+                return Ok(Some(headers));
+            }
+            _ => {}
+        }
+        file.seek(SeekFrom::Current(i64::try_from(data).map_err(|_| invalid())?))?;
+    }
+    headers.more = headers.last_piece.as_ref().is_some_and(|piece| piece.continued);
+    Ok(Some(headers))
+}
+
+/// Position of `path` in its set from 0 according to its name, `.partN.rar` or `.rar` followed by `.r00`.
+fn named_index(path: &Path) -> Option<usize> {
+    let (number, first) = recovery::volume_number(path)?;
+    number.checked_sub(first).map(|index| index as usize)
+}
+
+/// The volume at `index` of the set of `first` by name, counting from 0.
+fn named_volume(first: &Path, index: usize) -> Option<PathBuf> {
+    let archive = unrar::Archive::new(first);
+    match archive.is_multipart() {
+        true => archive.nth_part(index as i32 + 1),
+        false if index == 0 => Some(first.to_path_buf()),
+        false => Some(first.with_extension(format!("r{:02}", index - 1))),
+    }
+}
+
+/// The directory of `path`, `.` for a bare file name.
+fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// The files of `dir` other than `first` named like volumes.
+fn named_volumes(dir: &Path, first: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut volumes = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path != first && named_index(&path).is_some() {
+            volumes.push(path);
+        }
+    }
+    Ok(volumes)
+}
+
+/// The files named like volumes of the set of `first`, with its set name whatever its case, in the directories below
+/// the parent of its own, its own included.
+pub fn sibling_volumes(first: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(parent) = parent_dir(first).parent().filter(|dir| !dir.as_os_str().is_empty()) else {
+        return Ok(Vec::new());
+    };
+    let set = sidecar::set_name(first).to_lowercase();
+    let mut volumes = Vec::new();
+    for entry in fs::read_dir(parent)? {
+        let dir = entry?.path();
+        // Neither the files next to the directories nor the directories that can't be read hold volumes of the set.
+        if let Ok(named) = named_volumes(&dir, first) {
+            volumes.extend(
+                named
+                    .into_iter()
+                    .filter(|path| sidecar::set_name(path).to_lowercase() == set),
+            );
+        }
+    }
+    Ok(volumes)
+}
+
+/// `volumes` with their headers, those that can't be read aren't volumes of the chain.
+fn with_headers(volumes: Vec<PathBuf>) -> Vec<(PathBuf, VolumeHeaders)> {
+    volumes
+        .into_iter()
+        .filter_map(|path| match VolumeHeaders::read(&path) {
+            Ok(Some(headers)) => Some((path, headers)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Name of the link to the volume at `index` of a chain of `count` volumes, the name unrar looks for.
+fn link_name(index: usize, count: usize, new_numbering: bool) -> Option<String> {
+    match (new_numbering, index) {
+        (true, _) => Some(format!("volumes.part{:01$}.rar", index + 1, count.to_string().len())),
+        (false, 0) => Some("volumes.rar".into()),
+        (false, _) if index <= 100 => Some(format!("volumes.r{:02}", index - 1)),
+        (false, _) => None,
+    }
+}
+
+/// Volumes of a set followed through their headers, some of them named after another set.
+#[derive(Debug)]
+pub struct RenamedChain {
+    volumes: Vec<PathBuf>,
+    new_numbering: bool,
+}
+
+impl RenamedChain {
+    /// Follows the headers of the volumes next to `first`, from `first` to the last volume of its set. Renamed or
+    /// not, each volume must have the number of its position. `None` when every volume has the name that follows
+    /// from `first`, or when the chain breaks or forks before its end.
+    pub fn resolve(first: &Path) -> io::Result<Option<RenamedChain>> {
+        let candidates = with_headers(named_volumes(parent_dir(first), first)?);
+        let Some(chain) = RenamedChain::follow(first, &candidates)? else {
+            return Ok(None);
+        };
+        let renamed = chain
+            .volumes
+            .iter()
+            .enumerate()
+            .any(|(index, volume)| named_volume(first, index).as_ref() != Some(volume));
+        Ok(renamed.then_some(chain))
+    }
+
+    /// Follows the headers of the volumes of the set of `first` in the directories next to its own, `cd1/`, `cd2/`
+    /// and so on below the same parent, for the sets spread across them. The volumes are those with the set name of
+    /// `first`, whatever its case. `None` when the volumes are all in the directory of `first`, or when the chain
+    /// breaks or forks before its end.
+    pub fn resolve_across_dirs(first: &Path) -> io::Result<Option<RenamedChain>> {
+        let candidates = with_headers(sibling_volumes(first)?);
+        let Some(chain) = RenamedChain::follow(first, &candidates)? else {
+            return Ok(None);
+        };
+        let spread = chain.volumes.iter().any(|volume| volume.parent() != first.parent());
+        Ok(spread.then_some(chain))
+    }
+
+    /// Follows the headers from `first` through `candidates` to the last volume of its set.
+    fn follow(first: &Path, candidates: &[(PathBuf, VolumeHeaders)]) -> io::Result<Option<RenamedChain>> {
+        let Some(mut current) = VolumeHeaders::read(first)? else {
+            return Ok(None);
+        };
+        let new_numbering = current.new_numbering;
+        let mut volumes = vec![first.to_path_buf()];
+        while current.more {
+            let mut next = candidates
+                .iter()
+                .filter(|(path, headers)| named_index(path) == Some(volumes.len()) && current.followed_by(headers));
+            let (Some((path, headers)), None) = (next.next(), next.next()) else {
+                return Ok(None);
+            };
+            volumes.push(path.clone());
+            current = headers.clone();
+        }
+        Ok(Some(RenamedChain { volumes, new_numbering }))
+    }
+
+    /// Links the volumes under the names unrar expects, in a directory of their own.
+    pub fn link(self) -> io::Result<LinkedChain> {
+        let dir = std::env::temp_dir().join(format!(
+            "rarscan-volumes-{}-{}",
+            process::id(),
+            LINKED.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        let mut linked = LinkedChain {
+            first: PathBuf::new(),
+            dir,
+            volumes: self.volumes,
+        };
+        for (index, volume) in linked.volumes.iter().enumerate() {
+            let name = link_name(index, linked.volumes.len(), self.new_numbering)
+                .ok_or_else(|| io::Error::other("too many volumes for their naming"))?;
+            let link = linked.dir.join(name);
+            archive::make_symlink(&fs::canonicalize(volume)?, &link)?;
+            if index == 0 {
+                linked.first = link;
+            }
+        }
+        Ok(linked)
+    }
+}
+
+/// A renamed chain linked under the names unrar expects, the links are removed when dropped.
+#[derive(Debug)]
+pub struct LinkedChain {
+    /// The link to the first volume, for unrar to open.
+    first: PathBuf,
+    dir: PathBuf,
+    volumes: Vec<PathBuf>,
+}
+
+impl LinkedChain {
+    pub fn first_link(&self) -> &Path {
+        &self.first
+    }
+
+    pub fn volumes(&self) -> &[PathBuf] {
+        &self.volumes
+    }
+
+    /// The parent of the directories the volumes are in, when they aren't all in the same one.
+    pub fn common_parent(&self) -> Option<&Path> {
+        let dir = self.volumes[0].parent()?;
+        match self.volumes.iter().all(|volume| volume.parent() == Some(dir)) {
+            true => None,
+            false => dir.parent(),
+        }
+    }
+}
+
+impl Drop for LinkedChain {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volumes_are_named_like_unrar_expects() {
+        assert_eq!(
+            named_volume(Path::new("/a/Old.Name.part1.rar"), 2),
+            Some(PathBuf::from("/a/Old.Name.part3.rar"))
+        );
+        assert_eq!(
+            named_volume(Path::new("/a/show.rar"), 1),
+            Some(PathBuf::from("/a/show.r00"))
+        );
+        assert_eq!(link_name(0, 12, true).unwrap(), "volumes.part01.rar");
+        assert_eq!(link_name(11, 12, true).unwrap(), "volumes.part12.rar");
+        assert_eq!(link_name(0, 3, false).unwrap(), "volumes.rar");
+        assert_eq!(link_name(2, 3, false).unwrap(), "volumes.r01");
+        assert_eq!(named_index(Path::new("New.Name.part02.rar")), Some(1));
+        assert_eq!(named_index(Path::new("show.R07")), Some(8));
+        assert_eq!(named_index(Path::new("show.rev")), None);
+
+        let mut bytes: &[u8] = &[0x80 | 0x2c, 0x02, 0x05];
+        assert_eq!(vint(&mut bytes).unwrap(), 300);
+        assert_eq!(vint(&mut bytes).unwrap(), 5);
+        assert!(vint(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn volumes_follow_each_other_through_their_split_entries() {
+        let piece = |name: &str, continued| {
+            Some(Piece {
+                name: name.as_bytes().to_vec(),
+                unpacked_size: 3000,
+                continued,
+            })
+        };
+        let first = VolumeHeaders {
+            first: true,
+            more: true,
+            first_piece: piece("movie.mkv", false),
+            last_piece: piece("movie.mkv", true),
+            ..VolumeHeaders::default()
+        };
+        let second = VolumeHeaders {
+            first_piece: piece("movie.mkv", true),
+            last_piece: piece("movie.mkv", false),
+            ..VolumeHeaders::default()
+        };
+        assert!(first.followed_by(&second));
+        assert!(!second.followed_by(&first));
+        let other = VolumeHeaders {
+            first_piece: piece("other.mkv", true),
+            ..second.clone()
+        };
+        assert!(!first.followed_by(&other));
+        // Numbered volumes must be next to each other.
+        let numbered = |number| VolumeHeaders {
+            number: Some(number),
+            ..second.clone()
+        };
+        let first = VolumeHeaders {
+            number: Some(0),
+            ..first
+        };
+        assert!(first.followed_by(&numbered(1)));
+        assert!(!first.followed_by(&numbered(2)));
+    }
+
+    #[test]
+    fn sizes_past_4_gib_are_read_whole() {
+        const SIZE: u64 = 6 << 30;
+        let rar4 = include_bytes!("../fixtures/large.rar");
+        // Past the signature and the main header, the fixture isn't a volume.
+        let headers = read_rar4(&mut io::Cursor::new(&rar4[7 + 13..])).unwrap().unwrap();
+        assert_eq!(headers.first_piece.unwrap().unpacked_size, SIZE);
+
+        let encode = |mut value: u64| {
+            let mut bytes = Vec::new();
+            while value >= 0x80 {
+                bytes.push(value as u8 | 0x80);
+                value >>= 7;
+            }
+            bytes.push(value as u8);
+            bytes
+        };
+        // A file header without data: kind, flags, file flags, unpacked size, attributes, compression, host and name.
+        let mut header = Vec::new();
+        for value in [RAR5_FILE, 0, 0, SIZE, 0, 0, 0, 5] {
+            header.extend(encode(value));
+        }
+        header.extend(b"a.mkv");
+        let mut rar5 = vec![0; 4];
+        rar5.extend(encode(header.len() as u64));
+        rar5.extend(header);
+        let headers = read_rar5(&mut io::Cursor::new(rar5)).unwrap().unwrap();
+        assert_eq!(headers.first_piece.unwrap().unpacked_size, SIZE);
+        let mut bytes: &[u8] = &encode(SIZE);
+        assert_eq!(vint(&mut bytes).unwrap(), SIZE);
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::fds;
+
+/// Directory chain from the root to a symlink waiting to be followed, used to report loops.
+type Chain = Vec<(imp::DirId, PathBuf)>;
+
+/// Directories modified this recently aren't kept for the next scan, an entry added within the same tick of a coarse
+/// mtime wouldn't change it.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// What a scan found in a directory while it had `mtime`: the names of its subdirectories and of its matching files.
+/// Adding, removing or renaming an entry updates the mtime of its directory, so the listing holds while it's the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub mtime: SystemTime,
+    pub dirs: Vec<PathBuf>,
+    pub files: Vec<PathBuf>,
+}
+
+/// Listings of the directories below a root, by path.
+pub type Listings = HashMap<PathBuf, Listing>;
+
+/// The listings an incremental scan leaves for the next one, with the number of directories it went through and of
+/// the ones it didn't need to read.
+pub struct IncrementalScan {
+    pub listings: Listings,
+    pub dirs: usize,
+    pub unchanged: usize,
+}
+
+/// How far a scan got: the directories read so far, and of the ones right below the root the number entered and the
+/// total, a rough measure of the work left.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScanProgress {
+    pub dirs: usize,
+    pub top_dirs_entered: usize,
+    pub top_dirs: usize,
+}
+
+/// Finds the files under `root` accepted by `matches`, in sorted order. Symlinks to files are returned as is.
+/// Symlinks to directories are only descended into when `follow_symlinks` is set, after the real tree was walked, and
+/// at most once per directory so that loops terminate.
+pub fn find_files(root: &Path, follow_symlinks: bool, matches: impl Fn(&Path) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    visit_files(
+        root,
+        follow_symlinks,
+        matches,
+        |path| {
+            files.push(path);
+            Ok(())
+        },
+        |_| {},
+    )?;
+    Ok(files)
+}
+
+/// Like [`find_files`], but hands each file to `on_file` as soon as it's found instead of collecting them, and the
+/// progress to `on_dir` as each directory is read. Returns the symlinked directories that were descended into.
+pub fn visit_files(
+    root: &Path,
+    follow_symlinks: bool,
+    matches: impl Fn(&Path) -> bool,
+    on_file: impl FnMut(PathBuf) -> io::Result<()>,
+    on_dir: impl FnMut(&ScanProgress),
+) -> io::Result<Vec<PathBuf>> {
+    let mut walker = Walker {
+        follow_symlinks,
+        matches,
+        on_file,
+        on_dir,
+        progress: ScanProgress::default(),
+        visited: HashSet::new(),
+        links: Vec::new(),
+        followed: Vec::new(),
+    };
+    let md = fs::metadata(root)?;
+    let chain = vec![(imp::dir_id(root, &md)?, root.to_path_buf())];
+    walker.visited.insert(chain[0].0.clone());
+    walker.walk_dir(root, &chain)?;
+
+    while !walker.links.is_empty() {
+        for (link, mut chain) in std::mem::take(&mut walker.links) {
+            let md = fs::metadata(&link)?;
+            let id = imp::dir_id(&link, &md)?;
+            if let Some(pos) = chain.iter().position(|(visited, _)| *visited == id) {
+                let paths: Vec<String> = chain[pos..]
+                    .iter()
+                    .map(|(_, path)| path.as_path())
+                    .chain([link.as_path()])
+                    .map(|path| format!("'{}'", path.display()))
+                    .collect();
+                log::warn!("Symlink loop, not following: {}", paths.join(" -> "));
+                continue;
+            }
+            if !walker.visited.insert(id.clone()) {
+                log::debug!("'{}' points to a directory already scanned.", link.display());
+                continue;
+            }
+            log::debug!("Following symlink '{}'.", link.display());
+            walker.followed.push(link.clone());
+            chain.push((id, link.clone()));
+            walker.walk_dir(&link, &chain)?;
+        }
+    }
+    Ok(walker.followed)
+}
+
+/// Like [`visit_files`] without following symlinks, but the directories whose mtime is the one of their listing in
+/// `previous` aren't read again. Their subdirectories are still visited, each of them may have changed on its own.
+pub fn visit_files_incremental(
+    root: &Path,
+    previous: &Listings,
+    matches: impl Fn(&Path) -> bool,
+    on_file: impl FnMut(PathBuf) -> io::Result<()>,
+    on_dir: impl FnMut(&ScanProgress),
+) -> io::Result<IncrementalScan> {
+    let mut walker = IncrementalWalker {
+        previous,
+        matches,
+        on_file,
+        on_dir,
+        progress: ScanProgress::default(),
+        visited: HashSet::new(),
+        listings: Listings::new(),
+        unchanged: 0,
+        now: SystemTime::now(),
+    };
+    let md = fs::metadata(root)?;
+    walker.visited.insert(imp::dir_id(root, &md)?);
+    walker.walk_dir(root, &md, 0)?;
+    Ok(IncrementalScan {
+        listings: walker.listings,
+        dirs: walker.visited.len(),
+        unchanged: walker.unchanged,
+    })
+}
+
+struct IncrementalWalker<'a, F, G, H> {
+    previous: &'a Listings,
+    matches: F,
+    on_file: G,
+    on_dir: H,
+    progress: ScanProgress,
+    visited: HashSet<imp::DirId>,
+    listings: Listings,
+    unchanged: usize,
+    now: SystemTime,
+}
+
+impl<F: Fn(&Path) -> bool, G: FnMut(PathBuf) -> io::Result<()>, H: FnMut(&ScanProgress)>
+    IncrementalWalker<'_, F, G, H>
+{
+    /// Visits `dir`, `depth` levels below the root.
+    fn walk_dir(&mut self, dir: &Path, md: &Metadata, depth: usize) -> io::Result<()> {
+        let mtime = md.modified()?;
+        let (listing, reusable) = match self.previous.get(dir).filter(|listing| listing.mtime == mtime) {
+            Some(listing) => {
+                self.unchanged += 1;
+                (listing.clone(), true)
+            }
+            None => self.list_dir(dir, mtime)?,
+        };
+        self.progress.dirs += 1;
+        match depth {
+            0 => self.progress.top_dirs = listing.dirs.len(),
+            1 => self.progress.top_dirs_entered += 1,
+            _ => {}
+        }
+        (self.on_dir)(&self.progress);
+        let mut entries: Vec<(&PathBuf, bool)> = (listing.dirs.iter().map(|name| (name, true)))
+            .chain(listing.files.iter().map(|name| (name, false)))
+            .collect();
+        entries.sort();
+
+        for (name, is_dir) in entries {
+            let path = dir.join(name);
+            if !is_dir {
+                (self.on_file)(path)?;
+                continue;
+            }
+            let md = match fs::symlink_metadata(&path) {
+                Ok(md) if md.is_dir() => md,
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            // Reached through a bind mount already.
+            if !self.visited.insert(imp::dir_id(&path, &md)?) {
+                log::debug!("'{}' was already scanned.", path.display());
+                continue;
+            }
+            self.walk_dir(&path, &md, depth + 1)?;
+        }
+        if reusable && self.now.duration_since(mtime).is_ok_and(|age| age >= RACY_WINDOW) {
+            self.listings.insert(dir.to_path_buf(), listing);
+        }
+        Ok(())
+    }
+
+    /// Reads the listing of `dir`, and whether the next scan may reuse it. It may not with names that aren't UTF-8, nor
+    /// with matching symlinks, whose target can come and go without a change to the directory.
+    fn list_dir(&self, dir: &Path, mtime: SystemTime) -> io::Result<(Listing, bool)> {
+        let paths = {
+            let _fds = fds::acquire(1);
+            fs::read_dir(dir)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        let mut listing = Listing {
+            mtime,
+            dirs: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut reusable = true;
+        for path in paths {
+            let md = fs::symlink_metadata(&path)?;
+            let name = PathBuf::from(path.file_name().unwrap_or_default());
+            reusable &= name.to_str().is_some();
+            if md.is_symlink() {
+                if !(self.matches)(&path) {
+                    continue;
+                }
+                reusable = false;
+                match fs::metadata(&path) {
+                    Ok(target) if target.is_file() => listing.files.push(name),
+                    _ => log::debug!("'{}' is a broken symlink or not to a file.", path.display()),
+                }
+            } else if md.is_dir() {
+                listing.dirs.push(name);
+            } else if md.is_file() && (self.matches)(&path) {
+                listing.files.push(name);
+            }
+        }
+        listing.dirs.sort();
+        listing.files.sort();
+        Ok((listing, reusable))
+    }
+}
+
+struct Walker<F, G, H> {
+    follow_symlinks: bool,
+    matches: F,
+    on_file: G,
+    on_dir: H,
+    progress: ScanProgress,
+    visited: HashSet<imp::DirId>,
+    links: Vec<(PathBuf, Chain)>,
+    followed: Vec<PathBuf>,
+}
+
+impl<F: Fn(&Path) -> bool, G: FnMut(PathBuf) -> io::Result<()>, H: FnMut(&ScanProgress)> Walker<F, G, H> {
+    fn walk_dir(&mut self, dir: &Path, chain: &Chain) -> io::Result<()> {
+        let mut entries = {
+            let _fds = fds::acquire(1);
+            fs::read_dir(dir)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()?
+        };
+        entries.sort();
+        self.progress.dirs += 1;
+        // The chain holds the directories from the root, the root first. Symlinks followed later don't count.
+        match chain.len() {
+            1 if self.progress.dirs == 1 => {
+                self.progress.top_dirs = entries
+                    .iter()
+                    .filter(|path| fs::symlink_metadata(path).is_ok_and(|md| md.is_dir()))
+                    .count()
+            }
+            2 if self.followed.is_empty() => self.progress.top_dirs_entered += 1,
+            _ => {}
+        }
+        (self.on_dir)(&self.progress);
+
+        for path in entries {
+            let md = fs::symlink_metadata(&path)?;
+            if md.is_symlink() {
+                let Ok(target) = fs::metadata(&path) else {
+                    log::debug!("'{}' is a broken symlink.", path.display());
+                    continue;
+                };
+                if target.is_dir() {
+                    if self.follow_symlinks {
+                        self.links.push((path, chain.clone()));
+                    } else {
+                        log::debug!("Not following symlink '{}'.", path.display());
+                    }
+                } else if target.is_file() && (self.matches)(&path) {
+                    (self.on_file)(path)?;
+                }
+            } else if md.is_dir() {
+                let id = imp::dir_id(&path, &md)?;
+                // Already reached through a symlink followed earlier, or a bind mount.
+                if !self.visited.insert(id.clone()) {
+                    log::debug!("'{}' was already scanned.", path.display());
+                    continue;
+                }
+                let mut chain = chain.clone();
+                chain.push((id, path.clone()));
+                self.walk_dir(&path, &chain)?;
+            } else if md.is_file() && (self.matches)(&path) {
+                (self.on_file)(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{fs::Metadata, io, os::unix::fs::MetadataExt, path::Path};
+
+    /// Device and inode of a directory.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct DirId(u64, u64);
+
+    pub fn dir_id(_: &Path, md: &Metadata) -> io::Result<DirId> {
+        Ok(DirId(md.dev(), md.ino()))
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{fs::Metadata, io, path::Path, path::PathBuf};
+
+    /// Without inodes, the canonical path stands in for the identity of a directory.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct DirId(PathBuf);
+
+    pub fn dir_id(path: &Path, _: &Metadata) -> io::Result<DirId> {
+        path.canonicalize().map(DirId)
+    }
+}
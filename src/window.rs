@@ -0,0 +1,247 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use time::{OffsetDateTime, Weekday};
+
+use crate::datetime::TimeZone;
+
+const DAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+/// How far the opening of a window is looked for, a week and the longest DST shift.
+const LOOKAHEAD: Duration = Duration::from_secs(8 * 24 * 60 * 60);
+/// Longest sleep while waiting for the window, the time of the opening is computed again after in case the clock
+/// jumped.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Daily window of --active-hours, in minutes since midnight. It crosses midnight when it ends before it starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveHours {
+    start: u16,
+    end: u16,
+}
+
+impl ActiveHours {
+    /// Parses `HH:MM-HH:MM`, such as `22:00-06:00`.
+    pub fn parse(s: &str) -> Result<ActiveHours, String> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected HH:MM-HH:MM, got '{}'", s))?;
+        let (start, end) = (parse_clock(start.trim())?, parse_clock(end.trim())?);
+        if start == end {
+            return Err(format!(
+                "the window '{}' is empty, leave --active-hours out to run at any time",
+                s
+            ));
+        }
+        Ok(ActiveHours { start, end })
+    }
+
+    fn crosses_midnight(&self) -> bool {
+        self.end < self.start
+    }
+}
+
+fn parse_clock(s: &str) -> Result<u16, String> {
+    let invalid = || format!("invalid time '{}', expected HH:MM", s);
+    let (hours, minutes) = s.split_once(':').ok_or_else(invalid)?;
+    let hours: u16 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+    // 24:00 ends a window at midnight.
+    if minutes >= 60 || hours > 24 || hours == 24 && minutes > 0 {
+        return Err(invalid());
+    }
+    Ok((hours * 60 + minutes) % (24 * 60))
+}
+
+/// Days of --active-days, the window of a day may run into the next one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveDays {
+    /// Bit 0 is Monday.
+    days: u8,
+}
+
+impl ActiveDays {
+    /// Parses days and ranges of days separated by commas, such as `mon-fri` or `sat,sun`. A range may wrap around
+    /// the end of the week, `fri-mon`.
+    pub fn parse(s: &str) -> Result<ActiveDays, String> {
+        // The name of the day or its first three letters.
+        let day = |name: &str| {
+            let name = name.trim().to_ascii_lowercase();
+            DAYS.iter()
+                .position(|day| *day == name || day[..3] == name)
+                .map(|index| index as u8)
+                .ok_or_else(|| format!("unknown day '{}', expected mon, tue, wed, thu, fri, sat or sun", name))
+        };
+        let mut days = 0;
+        for part in s.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (day(first)?, day(last)?),
+                None => (day(part)?, day(part)?),
+            };
+            let mut index = first;
+            loop {
+                days |= 1 << index;
+                if index == last {
+                    break;
+                }
+                index = (index + 1) % 7;
+            }
+        }
+        Ok(ActiveDays { days })
+    }
+
+    fn contains(&self, weekday: Weekday) -> bool {
+        self.days & (1 << weekday.number_days_from_monday()) != 0
+    }
+}
+
+/// When heavy work is allowed, in the local time of a zone so that it follows DST.
+#[derive(Debug, Clone)]
+pub struct ActiveWindow {
+    hours: Option<ActiveHours>,
+    days: Option<ActiveDays>,
+    zone: TimeZone,
+}
+
+impl ActiveWindow {
+    pub fn new(hours: Option<ActiveHours>, days: Option<ActiveDays>, zone: TimeZone) -> ActiveWindow {
+        ActiveWindow { hours, days, zone }
+    }
+
+    pub fn is_open(&self, t: SystemTime) -> bool {
+        let local = self.zone.to_local(t);
+        let day_allowed = |t: OffsetDateTime| self.days.is_none_or(|days| days.contains(t.weekday()));
+        let Some(hours) = self.hours else {
+            return day_allowed(local);
+        };
+        let minute = local.hour() as u16 * 60 + local.minute() as u16;
+        if !hours.crosses_midnight() {
+            return (hours.start..hours.end).contains(&minute) && day_allowed(local);
+        }
+        // The hours past midnight belong to the window of the day before.
+        let yesterday = local - time::Duration::DAY;
+        minute >= hours.start && day_allowed(local) || minute < hours.end && day_allowed(yesterday)
+    }
+
+    /// When the window opens next, `None` while it's open. Found minute by minute in local time, which gets DST
+    /// transitions right without special cases.
+    pub fn opens_at(&self, now: SystemTime) -> Option<SystemTime> {
+        if self.is_open(now) {
+            return None;
+        }
+        let since_epoch = now.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let mut t = SystemTime::UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs() / 60 * 60 + 60);
+        while t < now + LOOKAHEAD {
+            if self.is_open(t) {
+                return Some(t);
+            }
+            t += Duration::from_secs(60);
+        }
+        None
+    }
+
+    /// `22:00`, with the day when it isn't within a day of `now`.
+    pub fn describe(&self, t: SystemTime, now: SystemTime) -> String {
+        let local = self.zone.to_local(t);
+        let clock = format!("{:02}:{:02}", local.hour(), local.minute());
+        match t.duration_since(now) {
+            Ok(wait) if wait >= Duration::from_secs(24 * 60 * 60) => {
+                let day = DAYS[local.weekday().number_days_from_monday() as usize];
+                format!("{}{} {}", day[..1].to_ascii_uppercase(), &day[1..3], clock)
+            }
+            _ => clock,
+        }
+    }
+}
+
+impl fmt::Display for ActiveWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let clock = |minutes: u16| format!("{:02}:{:02}", minutes / 60, minutes % 60);
+        match self.hours {
+            Some(hours) => write!(f, "{}-{}", clock(hours.start), clock(hours.end))?,
+            None => write!(f, "all day")?,
+        }
+        if let Some(days) = self.days {
+            let names: Vec<&str> = (0..7)
+                .filter(|i| days.days & (1 << i) != 0)
+                .map(|i| &DAYS[i][..3])
+                .collect();
+            write!(f, " on {}", names.join(","))?;
+        }
+        write!(f, " ({})", self.zone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The given UTC date and time.
+    fn at(date: [i32; 5]) -> SystemTime {
+        let [year, month, day, hour, minute] = date;
+        let date =
+            time::Date::from_calendar_date(year, time::Month::try_from(month as u8).unwrap(), day as u8).unwrap();
+        let t = date.with_hms(hour as u8, minute as u8, 0).unwrap().assume_utc();
+        SystemTime::UNIX_EPOCH + Duration::from_secs(t.unix_timestamp() as u64)
+    }
+
+    #[test]
+    fn parse_windows() {
+        assert_eq!(
+            ActiveHours::parse("22:00-06:00"),
+            Ok(ActiveHours { start: 1320, end: 360 })
+        );
+        assert_eq!(ActiveHours::parse("9:30-24:00"), Ok(ActiveHours { start: 570, end: 0 }));
+        assert!(ActiveHours::parse("22:00").is_err());
+        assert!(ActiveHours::parse("22:00-22:00").is_err());
+        assert!(ActiveHours::parse("25:00-06:00").is_err());
+        assert_eq!(ActiveDays::parse("mon-fri").unwrap().days, 0b0011111);
+        assert_eq!(ActiveDays::parse("Sat,sunday").unwrap().days, 0b1100000);
+        assert_eq!(ActiveDays::parse("fri-mon").unwrap().days, 0b1110001);
+        assert!(ActiveDays::parse("mo").is_err());
+        assert!(ActiveDays::parse("mon-funday").is_err());
+    }
+
+    #[test]
+    fn windows_cross_midnight_and_follow_dst() {
+        let nights = ActiveWindow::new(
+            Some(ActiveHours::parse("22:00-06:00").unwrap()),
+            Some(ActiveDays::parse("mon-fri").unwrap()),
+            TimeZone::UTC,
+        );
+        // Friday 2024-03-01.
+        assert!(!nights.is_open(at([2024, 3, 1, 21, 59])));
+        assert!(nights.is_open(at([2024, 3, 1, 22, 0])));
+        // Saturday morning is still the window of Friday night, not Saturday night.
+        assert!(nights.is_open(at([2024, 3, 2, 5, 59])));
+        assert!(!nights.is_open(at([2024, 3, 2, 6, 0])));
+        assert!(!nights.is_open(at([2024, 3, 2, 23, 0])));
+        let now = at([2024, 3, 2, 12, 30]);
+        let opens = nights.opens_at(now).unwrap();
+        assert_eq!(opens, at([2024, 3, 4, 22, 0]));
+        assert_eq!(nights.describe(opens, now), "Mon 22:00");
+        assert_eq!(nights.opens_at(at([2024, 3, 4, 23, 0])), None);
+
+        // Paris goes from UTC+1 to UTC+2 on 2024-03-31 at 01:00 UTC.
+        let Ok(paris) = TimeZone::resolve("Europe/Paris", None) else {
+            return;
+        };
+        let nights = ActiveWindow::new(Some(ActiveHours::parse("22:00-06:00").unwrap()), None, paris);
+        assert!(nights.is_open(at([2024, 3, 30, 21, 0])));
+        assert!(nights.is_open(at([2024, 3, 31, 3, 59])));
+        assert!(!nights.is_open(at([2024, 3, 31, 4, 0])));
+        let now = at([2024, 3, 31, 12, 0]);
+        let opens = nights.opens_at(now).unwrap();
+        assert_eq!(opens, at([2024, 3, 31, 20, 0]));
+        assert_eq!(nights.describe(opens, now), "22:00");
+    }
+}
@@ -0,0 +1,578 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn rar_file_as_root_is_processed() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    write_rar(&tmp.join("other/other.rar"), &[file("b.txt", b"world")]);
+
+    let run = rarscan([tmp.join("show/show.rar")]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/a.txt"), 5);
+    assert_missing(&tmp.join("other/b.txt"));
+}
+
+#[test]
+fn rar_file_without_extension_as_root_is_processed() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.bin"), &[file("a.txt", b"hello")]);
+
+    let run = rarscan([tmp.join("show/show.bin")]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/a.txt"), 5);
+}
+
+#[test]
+fn other_file_as_root_is_an_error() {
+    let tmp = TempDir::new();
+    std::fs::write(tmp.join("notes.txt"), b"not an archive").unwrap();
+
+    let run = rarscan([tmp.join("notes.txt")]);
+    assert!(!run.success);
+    assert!(run.log.contains("must be a directory or rar file"), "{}", run.log);
+}
+
+#[test]
+fn missing_root_is_an_error() {
+    let tmp = TempDir::new();
+
+    let run = rarscan([tmp.join("missing")]);
+    assert!(!run.success);
+    assert!(run.log.contains("does not exist"), "{}", run.log);
+}
+
+#[test]
+fn root_with_trailing_slash() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+
+    let run = rarscan([format!("{}/", tmp.root())]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/a.txt"), 5);
+}
+
+/// Runs against a few hundred archives with the open files limit lowered far below their count.
+#[cfg(unix)]
+#[test]
+fn many_archives_with_low_open_files_limit() {
+    use std::os::unix::process::CommandExt;
+
+    let tmp = TempDir::new();
+    for i in 0..300 {
+        write_rar(
+            &tmp.join(format!("dir{}/a{}.rar", i % 30, i)),
+            &[file(&format!("f{}.txt", i), b"x")],
+        );
+    }
+
+    let mut command = command();
+    command.arg(tmp.root());
+    // SAFETY: setrlimit is async-signal-safe.
+    unsafe {
+        command.pre_exec(|| {
+            let limit = libc::rlimit {
+                rlim_cur: 48,
+                rlim_max: 48,
+            };
+            if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let run = run(&mut command);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Open files limit is 48, keeping at most 16 open."),
+        "{}",
+        run.log
+    );
+    assert!(!run.log.contains("Too many open files"), "{}", run.log);
+    for i in 0..300 {
+        assert_file_size(&tmp.join(format!("dir{}/f{}.txt", i % 30, i)), 1);
+    }
+}
+
+#[test]
+fn check_reports_pending_changes_in_exit_status() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+
+    let run = rarscan(["--dry-run", "--check", tmp.root()]);
+    assert_eq!(run.code, Some(8), "{}", run.log);
+    assert!(run.log.contains("Pending: extractions=1 removals=0"), "{}", run.log);
+    assert_missing(&tmp.join("show/a.txt"));
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    let run = rarscan(["--dry-run", "--check", tmp.root()]);
+    assert_eq!(run.code, Some(0), "{}", run.log);
+
+    set_age(&tmp.join("show/show.rar"), std::time::Duration::from_secs(2 * 3600));
+    let run = rarscan(["--dry-run", "--check", "--remove-after-hours", "1", tmp.root()]);
+    assert_eq!(run.code, Some(8), "{}", run.log);
+    assert!(run.log.contains("Pending: extractions=0 removals=1"), "{}", run.log);
+    assert!(tmp.join("show/show.rar").exists());
+
+    let run = rarscan(["--check", tmp.root()]);
+    assert!(!run.success);
+    assert!(run.log.contains("--dry-run"), "{}", run.log);
+}
+
+#[test]
+fn estimate_reports_pending_work() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("done/done.rar"), &[file("b.txt", b"hello")]);
+    std::fs::write(tmp.join("done/done.sfv"), b"done.rar 00000000").unwrap();
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    write_rar(&tmp.join("show/show.rar"), &[file("a.bin", &payload(4000))]);
+    for path in ["done/done.rar", "done/done.sfv"] {
+        set_age(&tmp.join(path), std::time::Duration::from_secs(2 * 3600));
+    }
+
+    let run = rarscan(["estimate", tmp.root(), "--assume-throughput", "1K"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Archives to extract      1"), "{}", run.log);
+    assert!(run.log.contains("3.9 KiB"), "{}", run.log);
+    assert!(run.log.contains("(assumed)"), "{}", run.log);
+    assert!(run.log.contains("Estimated duration       3s"), "{}", run.log);
+    assert_missing(&tmp.join("show/a.bin"));
+
+    let run = rarscan(["estimate", tmp.root(), "--format", "json", "--remove-after-hours", "1"]);
+    assert!(run.success, "{}", run.log);
+    let estimate: serde_json::Value = serde_json::from_str(&run.log).unwrap();
+    assert_eq!(estimate["to_extract"], 1);
+    assert_eq!(estimate["unpacked_bytes"], 4000);
+    let freed = std::fs::metadata(tmp.join("done/done.rar")).unwrap().len() + 17;
+    assert_eq!(estimate["freed_bytes"], freed);
+    // The run extracting done.rar measured a throughput.
+    assert_eq!(estimate["throughput_source"], "measured");
+    assert!(tmp.join("done/done.rar").exists());
+}
+
+#[test]
+#[cfg(feature = "http")]
+fn http_status_bind_failure_is_fatal() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = taken.local_addr().unwrap().to_string();
+
+    let run = rarscan([tmp.root(), "--http-status", &addr]);
+    assert!(!run.success, "{}", run.log);
+    assert!(run.log.contains("bind the HTTP status endpoint"), "{}", run.log);
+    assert_missing(&tmp.join("show/a.txt"));
+}
+
+#[test]
+#[cfg(feature = "telemetry")]
+fn otlp_endpoint_receives_spans() {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    let collector = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", collector.local_addr().unwrap());
+    let received = std::thread::spawn(move || {
+        let (stream, _) = collector.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+            request.push_str(&line);
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (request, String::from_utf8(body).unwrap())
+    });
+
+    let run = rarscan([tmp.root(), "--otlp-endpoint", &url]);
+    assert!(run.success, "{}", run.log);
+    let (request, body) = received.join().unwrap();
+    assert!(request.starts_with("POST /v1/traces "), "{}", request);
+    let trace: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let spans = trace["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap();
+    let names: Vec<&str> = spans.iter().map(|span| span["name"].as_str().unwrap()).collect();
+    for name in [
+        "rarscan.run",
+        "rarscan.archive",
+        "rarscan.open",
+        "rarscan.verify",
+        "rarscan.extract",
+    ] {
+        assert!(names.contains(&name), "{:?}", names);
+    }
+    let archive = spans.iter().find(|span| span["name"] == "rarscan.archive").unwrap();
+    assert!(
+        body.contains(r#"{"key":"rarscan.outcome","value":{"stringValue":"extracted"}}"#),
+        "{}",
+        body
+    );
+    assert!(archive["attributes"].to_string().contains("show.rar"), "{}", body);
+    let run_span = spans.iter().find(|span| span["name"] == "rarscan.run").unwrap();
+    assert_eq!(archive["parentSpanId"], run_span["spanId"]);
+}
+
+#[test]
+#[cfg(feature = "tui")]
+fn tui_without_a_terminal_logs_as_usual() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+
+    let run = rarscan([tmp.root(), "--tui"]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("logging instead of showing the dashboard"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("Extracting into"), "{}", run.log);
+    assert_file_size(&tmp.join("show/a.txt"), 5);
+}
+
+#[test]
+fn help_lists_only_the_flags_of_compiled_features() {
+    let help = rarscan(["--help"]);
+    assert!(help.success, "{}", help.log);
+    let flags = [
+        ("--http-status", cfg!(feature = "http")),
+        ("--tui", cfg!(feature = "tui")),
+        ("--otlp-endpoint", cfg!(feature = "telemetry")),
+        ("self-update", cfg!(feature = "self-update")),
+    ];
+    for (flag, compiled) in flags {
+        assert_eq!(help.log.contains(flag), compiled, "{} in:\n{}", flag, help.log);
+    }
+
+    // A flag of a feature left out is unknown rather than ignored.
+    let tmp = TempDir::new();
+    let tui = rarscan([tmp.root(), "--tui"]);
+    assert_eq!(tui.success, cfg!(feature = "tui"), "{}", tui.log);
+}
+
+#[test]
+fn expect_archives_fails_a_scan_finding_none() {
+    let tmp = TempDir::new();
+    std::fs::create_dir(tmp.join("empty")).unwrap();
+
+    let run = rarscan([tmp.join("empty")]);
+    assert!(run.success, "{}", run.log);
+    let run = rarscan([tmp.join("empty").to_str().unwrap(), "--expect-archives"]);
+    assert_eq!(run.code, Some(4), "{}", run.log);
+    assert!(run.log.contains("Found 0 .rar files"), "{}", run.log);
+
+    write_multipart(&tmp.join("show/show"), "a.bin", &payload(3000), 1000);
+    let run = rarscan([tmp.root(), "--expect-archives"]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log
+            .contains("Found 3 .rar files, 1 enqueued as root archives and 2 left out"),
+        "{}",
+        run.log
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn ctl_pauses_and_reorders_a_running_queue() {
+    use std::{
+        fs,
+        os::unix::fs::PermissionsExt,
+        path::Path,
+        process::Stdio,
+        time::{Duration, Instant},
+    };
+
+    let tmp = TempDir::new();
+    let tools = TempDir::new();
+    for name in ["a", "b", "c", "d"] {
+        write_rar(
+            &tmp.join(format!("{}/{}.rar", name, name)),
+            &[file(&format!("{}.mkv", name), b"data")],
+        );
+    }
+    // Probing each media file takes a while, leaving time to send requests between archives.
+    let ffprobe = tools.join("ffprobe.sh");
+    fs::write(&ffprobe, "#!/bin/sh\nsleep 1\n").unwrap();
+    fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755)).unwrap();
+    let socket = tools.join("ctl.sock");
+    let ctl = |args: &[&str]| {
+        let mut all = vec!["--control-socket", socket.to_str().unwrap(), "ctl"];
+        all.extend(args);
+        rarscan(all)
+    };
+
+    let child = command()
+        .args([tmp.root(), "--probe-media", ffprobe.to_str().unwrap()])
+        .args(["--control-socket", socket.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while !socket.exists() && started.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let run = ctl(&["pause"]);
+    assert!(run.success, "{}", run.log);
+    let run = ctl(&["--json", "queue"]);
+    assert!(run.success, "{}", run.log);
+    let line = run.log.lines().find(|line| line.starts_with('{')).unwrap();
+    let response: serde_json::Value = serde_json::from_str(line).unwrap();
+    let queue: Vec<String> = response["queue"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["path"].as_str().unwrap().to_string())
+        .collect();
+    assert!(queue.len() >= 2, "{:?}", queue);
+    let (dropped, prioritized) = (&queue[0], &queue[queue.len() - 1]);
+
+    let run = ctl(&["drop", dropped]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("it was at position 1"), "{}", run.log);
+    let run = ctl(&["drop", dropped]);
+    assert!(!run.success, "{}", run.log);
+    assert!(run.log.contains("is not queued"), "{}", run.log);
+    let run = ctl(&["prioritize", prioritized]);
+    assert!(run.success, "{}", run.log);
+    let run = ctl(&["status"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Paused                   true"), "{}", run.log);
+    let run = ctl(&["resume"]);
+    assert!(run.success, "{}", run.log);
+
+    let output = child.wait_with_output().unwrap();
+    let log = String::from_utf8_lossy(&output.stderr).into_owned() + &String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", log);
+    assert!(log.contains("Dropping"), "{}", log);
+    let dropped = Path::new(dropped);
+    assert_missing(&dropped.with_extension("mkv"));
+    assert_file_size(&Path::new(prioritized).with_extension("mkv"), 4);
+    // The requests were only for the run they were made in.
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(state.contains(r#""dropped":[]"#), "{}", state);
+}
+
+#[cfg(unix)]
+#[test]
+fn instances_sharing_a_destination_take_turns() {
+    use std::{
+        fs,
+        os::unix::fs::PermissionsExt,
+        process::Stdio,
+        time::{Duration, Instant},
+    };
+
+    let first = TempDir::new();
+    let second = TempDir::new();
+    let shared = TempDir::new();
+    let tools = TempDir::new();
+    write_rar(&first.join("a/a.rar"), &[file("a.mkv", &payload(200_000))]);
+    write_rar(&second.join("b/b.rar"), &[file("b.mkv", &payload(300_000))]);
+    // The first instance holds the lock while it probes its file.
+    let ffprobe = tools.join("ffprobe.sh");
+    fs::write(&ffprobe, "#!/bin/sh\nsleep 2\n").unwrap();
+    fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755)).unwrap();
+    let lock = shared.join(".rarscan.dirlock");
+
+    let child = command()
+        .args([first.root(), "--dest-template", shared.root()])
+        .args(["--probe-media", ffprobe.to_str().unwrap()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while !lock.exists() && started.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(lock.exists());
+
+    let run = rarscan([
+        second.root(),
+        "--dest-template",
+        shared.root(),
+        "--dir-lock-wait-secs",
+        "0",
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("is locked by process"), "{}", run.log);
+    assert!(run.log.contains("1 archives left for a later run"), "{}", run.log);
+    assert_missing(&shared.join("b.mkv"));
+
+    let run = rarscan([second.root(), "--dest-template", shared.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("deferring to the end of the run"), "{}", run.log);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(shared.join("a.mkv")).unwrap(), payload(200_000));
+    assert_eq!(fs::read(shared.join("b.mkv")).unwrap(), payload(300_000));
+    assert_missing(&lock);
+}
+
+#[cfg(unix)]
+#[test]
+fn run_as_extracts_as_the_given_user() {
+    use std::{
+        fs,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+    };
+
+    let tmp = TempDir::new();
+    let run = rarscan(["--run-as", "no-such-user-rarscan", tmp.root()]);
+    assert!(!run.success);
+    assert!(run.log.contains("no user 'no-such-user-rarscan'"), "{}", run.log);
+
+    // Only root can switch to another user.
+    // SAFETY: always succeeds and touches no memory.
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    for dir in [tmp.path(), &tmp.join("show")] {
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o777)).unwrap();
+    }
+    // Where nobody can't save the state, before anything is extracted.
+    let private = tmp.join("private");
+    fs::create_dir(&private).unwrap();
+    let state_file = private.join("state.json");
+    let run = rarscan([
+        "--run-as",
+        "nobody",
+        "--state-file",
+        state_file.to_str().unwrap(),
+        tmp.root(),
+    ]);
+    assert!(!run.success);
+    assert!(
+        run.log.contains("the state file can't be saved as nobody"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("show/a.txt"));
+
+    // Left by a run as root, read and then replaced by one of nobody.
+    fs::write(tmp.join(".rarscan-state.json"), "{}").unwrap();
+    let run = rarscan(["--run-as", "nobody", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Running as nobody"), "{}", run.log);
+    let extracted = fs::metadata(tmp.join("show/a.txt")).unwrap();
+    let nobody = fs::metadata(tmp.join(".rarscan-state.json")).unwrap();
+    assert_ne!(extracted.uid(), 0);
+    assert_eq!((extracted.uid(), extracted.gid()), (nobody.uid(), nobody.gid()));
+}
+
+#[test]
+fn setup_writes_a_config_the_runs_take() {
+    use std::{
+        fs,
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("downloads/show/show.rar"), &[file("a.txt", b"hello")]);
+    let config = tmp.join("rarscan.conf");
+
+    // Scripted, the flags answer.
+    let run = rarscan([
+        "setup",
+        "--yes",
+        "--remove-after-hours",
+        "0",
+        "--schedule",
+        "cron",
+        "--every",
+        "15m",
+        "--config-path",
+        config.to_str().unwrap(),
+        tmp.join("downloads").to_str().unwrap(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Found 1 .rar files below it, 1 archives."),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("*/15 * * * * "), "{}", run.log);
+    assert_eq!(
+        fs::read_to_string(&config)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>(),
+        ["remove-after-hours = 0"]
+    );
+    // The wizard itself extracts and removes nothing.
+    assert_missing(&tmp.join("downloads/show/a.txt"));
+
+    let run = rarscan([
+        "--config",
+        config.to_str().unwrap(),
+        tmp.join("downloads").to_str().unwrap(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("downloads/show/a.txt"), 5);
+    assert_missing(&tmp.join("downloads/show/show.rar"));
+
+    // Asked, the invalid answers are asked again.
+    fs::create_dir_all(tmp.join("media")).unwrap();
+    let path = |name: &str| tmp.join(name).display().to_string();
+    let answers = [
+        &path("nowhere"),
+        &path("downloads"),
+        "sometimes",
+        "yes",
+        "two days",
+        "48",
+        &path("nowhere/media"),
+        &path("media"),
+        "",
+        "45s",
+        "2h",
+        "yes",
+    ];
+    let mut command: Command = command();
+    let mut child = command
+        .args(["setup", "--config-path", config.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(format!("{}\n", answers.join("\n")).as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", log);
+    assert_eq!(log.matches("try again").count(), 5, "{}", log);
+    assert!(log.contains("OnUnitActiveSec=120min"), "{}", log);
+    let written = fs::read_to_string(&config).unwrap();
+    assert!(written.contains("remove-after-hours = 48\n"), "{}", written);
+    assert!(
+        written.contains(&format!("dest-template = {}\n", tmp.join("media").display())),
+        "{}",
+        written
+    );
+}
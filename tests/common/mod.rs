@@ -0,0 +1,274 @@
+//! Helpers shared by the integration tests: scratch directories, a minimal RAR writer and a runner for the binary.
+
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// Scratch directory removed when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "rarscan-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn root(&self) -> &str {
+        self.path.to_str().expect("non UTF-8 temp dir")
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// An entry of a generated archive, a directory when `data` is `None`.
+pub struct RarEntry<'a> {
+    pub name: &'a str,
+    pub data: Option<&'a [u8]>,
+}
+
+pub fn file<'a>(name: &'a str, data: &'a [u8]) -> RarEntry<'a> {
+    RarEntry { name, data: Some(data) }
+}
+
+pub fn dir(name: &str) -> RarEntry<'_> {
+    RarEntry { name, data: None }
+}
+
+const MARK: &[u8] = b"Rar!\x1a\x07\x00";
+/// 2020-01-01 00:00:00 in MS-DOS format.
+const DOS_TIME: u32 = (40 << 25) | (1 << 21) | (1 << 16);
+
+const MAIN_VOLUME: u16 = 0x0001;
+const MAIN_NEW_NUMBERING: u16 = 0x0010;
+const MAIN_FIRST_VOLUME: u16 = 0x0100;
+const FILE_SPLIT_BEFORE: u16 = 0x0001;
+const FILE_SPLIT_AFTER: u16 = 0x0002;
+const FILE_DIRECTORY: u16 = 0x00e0;
+/// The name is followed by its UTF-16 encoding.
+const FILE_UNICODE: u16 = 0x0200;
+const LONG_BLOCK: u16 = 0x8000;
+const END_NEXT_VOLUME: u16 = 0x0001;
+const END_NO_CRC: u16 = 0x4000;
+
+fn block(kind: u8, flags: u16, body: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut header = vec![kind];
+    header.extend_from_slice(&flags.to_le_bytes());
+    header.extend_from_slice(&(7 + body.len() as u16).to_le_bytes());
+    header.extend_from_slice(body);
+    let crc = crc32fast::hash(&header) as u16;
+    let mut block = crc.to_le_bytes().to_vec();
+    block.extend_from_slice(&header);
+    block.extend_from_slice(data);
+    block
+}
+
+fn file_block(name: &str, data: &[u8], full: &[u8], flags: u16) -> Vec<u8> {
+    let directory = flags & FILE_DIRECTORY != 0;
+    let mut body = Vec::new();
+    body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(full.len() as u32).to_le_bytes());
+    body.push(3); // Unix host
+    let crc = if flags & FILE_SPLIT_AFTER != 0 { data } else { full };
+    body.extend_from_slice(&crc32fast::hash(crc).to_le_bytes());
+    body.extend_from_slice(&DOS_TIME.to_le_bytes());
+    body.push(29); // Version needed to extract
+    body.push(0x30); // Store
+    let (name, flags) = match name.is_ascii() {
+        true => (name.as_bytes().to_vec(), flags),
+        false => (unicode_name(name), flags | FILE_UNICODE),
+    };
+    body.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    let mode: u32 = if directory { 0o40755 } else { 0o100644 };
+    body.extend_from_slice(&mode.to_le_bytes());
+    body.extend_from_slice(&name);
+    block(0x74, LONG_BLOCK | flags, &body, data)
+}
+
+/// A name with other than ASCII in it as RAR4 stores it: an ASCII form, then after a NUL its UTF-16 units, each
+/// spelled out in full under a flag byte for every four of them.
+fn unicode_name(name: &str) -> Vec<u8> {
+    let mut out: Vec<u8> = name
+        .chars()
+        .map(|c| if c.is_ascii() { c as u8 } else { b'_' })
+        .collect();
+    out.push(0);
+    out.push(0); // High byte, unused by full units
+    let units: Vec<u16> = name.encode_utf16().collect();
+    for group in units.chunks(4) {
+        out.push(0b1010_1010);
+        for unit in group {
+            out.extend_from_slice(&unit.to_le_bytes());
+        }
+    }
+    out
+}
+
+/// Writes a single volume RAR4 archive storing `entries` uncompressed.
+pub fn write_rar(path: &Path, entries: &[RarEntry]) {
+    let mut out = MARK.to_vec();
+    out.extend(block(0x73, 0, &[0; 6], &[]));
+    for entry in entries {
+        out.extend(match entry.data {
+            Some(data) => file_block(entry.name, data, data, 0),
+            None => file_block(entry.name, &[], &[], FILE_DIRECTORY),
+        });
+    }
+    out.extend(block(0x7b, END_NO_CRC, &[], &[]));
+    write(path, &out);
+}
+
+/// Writes `data` as the file `name` split across `<base>.partN.rar` volumes of at most `volume_size` bytes of data.
+/// Returns the paths of the parts in order.
+pub fn write_multipart(base: &Path, name: &str, data: &[u8], volume_size: usize) -> Vec<PathBuf> {
+    let chunks: Vec<&[u8]> = data.chunks(volume_size).collect();
+    let mut parts = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let first = i == 0;
+        let last = i == chunks.len() - 1;
+        let mut main_flags = MAIN_VOLUME | MAIN_NEW_NUMBERING;
+        if first {
+            main_flags |= MAIN_FIRST_VOLUME;
+        }
+        let mut file_flags = 0;
+        if !first {
+            file_flags |= FILE_SPLIT_BEFORE;
+        }
+        if !last {
+            file_flags |= FILE_SPLIT_AFTER;
+        }
+        let mut out = MARK.to_vec();
+        out.extend(block(0x73, main_flags, &[0; 6], &[]));
+        out.extend(file_block(name, chunk, data, file_flags));
+        out.extend(block(
+            0x7b,
+            END_NO_CRC | if last { 0 } else { END_NEXT_VOLUME },
+            &[],
+            &[],
+        ));
+        let path = PathBuf::from(format!("{}.part{}.rar", base.display(), i + 1));
+        write(&path, &out);
+        parts.push(path);
+    }
+    parts
+}
+
+fn write(path: &Path, bytes: &[u8]) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+    fs::write(path, bytes).unwrap();
+}
+
+/// Deterministic content that doesn't compress, so sizes stay predictable.
+pub fn payload(size: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Moves the mtime of `path` into the past.
+pub fn set_age(path: &Path, age: Duration) {
+    set_mtime(path, SystemTime::now() - age);
+}
+
+pub fn set_mtime(path: &Path, mtime: SystemTime) {
+    // Directories can't be opened for writing, their owner sets their times all the same.
+    let file = match path.is_dir() {
+        true => File::open(path),
+        false => File::options().write(true).open(path),
+    }
+    .unwrap();
+    file.set_modified(mtime).unwrap();
+}
+
+pub fn mtime(path: &Path) -> SystemTime {
+    fs::metadata(path).unwrap().modified().unwrap()
+}
+
+#[track_caller]
+pub fn assert_file_size(path: &Path, size: u64) {
+    match fs::metadata(path) {
+        Ok(md) => {
+            assert!(md.is_file(), "'{}' is not a file", path.display());
+            assert_eq!(md.len(), size, "size of '{}'", path.display());
+        }
+        Err(e) => panic!("'{}' is missing: {}", path.display(), e),
+    }
+}
+
+#[track_caller]
+pub fn assert_missing(path: &Path) {
+    assert!(!path.exists(), "'{}' exists", path.display());
+}
+
+/// Output of a rarscan run.
+pub struct Run {
+    pub success: bool,
+    pub code: Option<i32>,
+    pub log: String,
+}
+
+/// Command running rarscan with its output piped and without the color settings of the environment.
+pub fn command() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rarscan"));
+    command
+        .env_remove("RUST_BACKTRACE")
+        .env_remove("NO_COLOR")
+        .env_remove("CLICOLOR_FORCE");
+    command
+}
+
+/// Runs rarscan with `args` and returns its log.
+pub fn rarscan<I, S>(args: I) -> Run
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    run(command().args(args))
+}
+
+pub fn run(command: &mut Command) -> Run {
+    let output = command.output().unwrap();
+    let mut log = String::from_utf8_lossy(&output.stdout).into_owned();
+    log.push_str(&String::from_utf8_lossy(&output.stderr));
+    Run {
+        success: output.status.success(),
+        code: output.status.code(),
+        log,
+    }
+}
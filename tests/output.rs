@@ -0,0 +1,141 @@
+mod common;
+
+use std::{
+    fs,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use common::*;
+
+/// A tree holding a single archive to extract.
+fn tree() -> TempDir {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    tmp
+}
+
+#[test]
+fn no_colors_when_piped() {
+    let tmp = tree();
+    let run = run(command().env_remove("TERM").arg(tmp.root()));
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("INFO"), "{}", run.log);
+    assert!(!run.log.contains('\x1b'), "{:?}", run.log);
+}
+
+#[test]
+fn clicolor_force_enables_colors() {
+    let tmp = tree();
+    let run = run(command().env_remove("TERM").env("CLICOLOR_FORCE", "1").arg(tmp.root()));
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains('\x1b'), "{:?}", run.log);
+}
+
+#[test]
+fn no_color_wins_over_terminal_detection() {
+    let tmp = tree();
+    let run = run(command().env("NO_COLOR", "1").arg(tmp.root()));
+    assert!(run.success, "{}", run.log);
+    assert!(!run.log.contains('\x1b'), "{:?}", run.log);
+}
+
+#[test]
+fn dates_are_utc_by_default() {
+    let tmp = TempDir::new();
+    let inner = tmp.join("inner.rar");
+    write_rar(&inner, &[file("inner.txt", b"nested")]);
+    let inner_bytes = fs::read(&inner).unwrap();
+    fs::remove_file(&inner).unwrap();
+    let outer = tmp.join("outer/outer.rar");
+    write_rar(&outer, &[file("inner.rar", &inner_bytes)]);
+    // 2020-01-01T23:30:00Z, already the next day east of UTC.
+    set_mtime(&outer, UNIX_EPOCH + Duration::from_secs(1_577_921_400));
+
+    let run = run(command().env_remove("TERM").env("TZ", "Asia/Tokyo").arg(tmp.root()));
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("mtime to 2020-01-01 23:30:00 +0000"), "{}", run.log);
+}
+
+#[cfg(unix)]
+#[test]
+fn dates_follow_the_time_zone_and_format() {
+    let nested = || {
+        let tmp = TempDir::new();
+        let inner = tmp.join("inner.rar");
+        write_rar(&inner, &[file("inner.txt", b"nested")]);
+        let inner_bytes = fs::read(&inner).unwrap();
+        fs::remove_file(&inner).unwrap();
+        let outer = tmp.join("outer/outer.rar");
+        write_rar(&outer, &[file("inner.rar", &inner_bytes)]);
+        set_mtime(&outer, UNIX_EPOCH + Duration::from_secs(1_577_921_400));
+        tmp
+    };
+
+    let tmp = nested();
+    let tokyo = run(command().args(["--timezone", "Asia/Tokyo"]).arg(tmp.root()));
+    assert!(tokyo.success, "{}", tokyo.log);
+    assert!(
+        tokyo.log.contains("mtime to 2020-01-02 08:30:00 +0900"),
+        "{}",
+        tokyo.log
+    );
+
+    // Local time through TZ, winter time in Paris.
+    let tmp = nested();
+    let paris = run(command()
+        .env("TZ", "Europe/Paris")
+        .args(["--timezone", "local", "--time-format", "%d/%m/%Y %H:%M"])
+        .arg(tmp.root()));
+    assert!(paris.success, "{}", paris.log);
+    assert!(paris.log.contains("mtime to 02/01/2020 00:30"), "{}", paris.log);
+
+    let unknown = run(command().args(["--timezone", "Mars/Olympus_Mons"]).arg(tmp.root()));
+    assert!(!unknown.success);
+    assert!(unknown.log.contains("unknown time zone"), "{}", unknown.log);
+}
+
+#[test]
+fn ndjson_output_replays_the_recorded_run() {
+    let tmp = TempDir::new();
+    fs::create_dir_all(tmp.join("movie")).unwrap();
+    fs::create_dir_all(tmp.join("show")).unwrap();
+    fs::write(tmp.join("show/plain.rar"), include_bytes!("../fixtures/plain.rar")).unwrap();
+    for (name, bytes) in [
+        ("multi.part1.rar", &include_bytes!("../fixtures/multi.part1.rar")[..]),
+        ("multi.part2.rar", include_bytes!("../fixtures/multi.part2.rar")),
+        ("multi.part3.rar", include_bytes!("../fixtures/multi.part3.rar")),
+    ] {
+        fs::write(tmp.join("movie").join(name), bytes).unwrap();
+        set_age(&tmp.join("movie").join(name), Duration::from_secs(3 * 24 * 60 * 60));
+    }
+
+    let output = command()
+        .args(["--output", "ndjson", "--remove-after-hours", "24", tmp.root()])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    // The log is on stderr only, each line of stdout is an event.
+    assert!(stderr.contains("INFO"), "{}", stderr);
+    assert!(
+        stderr.contains("Scan complete: 2 archives enqueued from 3 directories in"),
+        "{}",
+        stderr
+    );
+    let lines: Vec<serde_json::Value> = stdout.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+    // Same events in the same order as the recorded run, each with at least the fields it had.
+    let recorded: Vec<serde_json::Value> = include_str!("../fixtures/protocol-v1.ndjson")
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), recorded.len(), "{}", stdout);
+    for (line, recorded) in lines.iter().zip(&recorded) {
+        assert_eq!(line["v"], 1, "{}", line);
+        assert_eq!(line["event"], recorded["event"], "{}", stdout);
+        for field in recorded.as_object().unwrap().keys() {
+            assert!(line.get(field).is_some(), "'{}' is missing from {}", field, line);
+        }
+    }
+}
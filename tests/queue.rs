@@ -0,0 +1,2869 @@
+mod common;
+
+use std::{fs, time::Duration};
+
+use common::*;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[test]
+fn extracts_single_archive() {
+    let tmp = TempDir::new();
+    let data = payload(1000);
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[dir("sub"), file("sub/a.bin", &data), file("b.txt", b"hello")],
+    );
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/sub/a.bin"), 1000);
+    assert_file_size(&tmp.join("show/b.txt"), 5);
+    assert_eq!(fs::read(tmp.join("show/sub/a.bin")).unwrap(), data);
+}
+
+#[cfg(unix)]
+#[test]
+fn extracted_files_keep_their_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    for args in [&[][..], &["--preallocate", "always"]] {
+        let tmp = TempDir::new();
+        write_rar(&tmp.join("show/show.rar"), &[dir("sub"), file("sub/a.txt", b"hello")]);
+
+        let run = rarscan(args.iter().copied().chain([tmp.root()]));
+        assert!(run.success, "{}", run.log);
+        let mode = fs::metadata(tmp.join("show/sub/a.txt")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o644, "{:o} with {:?}", mode, args);
+    }
+}
+
+#[test]
+fn dry_run_leaves_tree_untouched() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    set_age(&tmp.join("show/show.rar"), 2 * DAY);
+
+    let run = rarscan(["--dry-run", "--remove-after-hours", "1", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Extracting into"), "{}", run.log);
+    assert_missing(&tmp.join("show/a.txt"));
+    assert!(tmp.join("show/show.rar").exists());
+}
+
+#[test]
+fn extracts_multipart_set() {
+    let tmp = TempDir::new();
+    let data = payload(5000);
+    let parts = write_multipart(&tmp.join("movie/movie"), "movie.mkv", &data, 2000);
+    assert_eq!(parts.len(), 3);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("movie/movie.mkv"), 5000);
+    assert_eq!(fs::read(tmp.join("movie/movie.mkv")).unwrap(), data);
+    // Only the first part is analyzed, the others belong to its set.
+    assert_eq!(run.log.matches("Analyzing").count(), 1, "{}", run.log);
+}
+
+#[test]
+fn extracts_nested_archive() {
+    let tmp = TempDir::new();
+    let inner = tmp.join("inner.rar");
+    write_rar(&inner, &[file("inner.txt", b"nested content")]);
+    let inner_bytes = fs::read(&inner).unwrap();
+    fs::remove_file(&inner).unwrap();
+    write_rar(&tmp.join("outer/outer.rar"), &[file("inner.rar", &inner_bytes)]);
+    set_age(&tmp.join("outer/outer.rar"), DAY);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("outer/inner.txt"), 14);
+    // The nested archive takes the mtime of the archive containing it so that they age together.
+    assert_eq!(mtime(&tmp.join("outer/inner.rar")), mtime(&tmp.join("outer/outer.rar")));
+}
+
+#[test]
+fn a_nested_archive_extracted_again_by_another_parent_keeps_aging_with_it() {
+    let tmp = TempDir::new();
+    let inner = tmp.join("inner.rar");
+    write_rar(&inner, &[file("inner.txt", b"nested content")]);
+    let inner_bytes = fs::read(&inner).unwrap();
+    fs::remove_file(&inner).unwrap();
+    write_rar(&tmp.join("outer/a.rar"), &[file("inner.rar", &inner_bytes)]);
+    write_rar(
+        &tmp.join("outer/b.rar"),
+        &[file("inner.rar", &inner_bytes), file("b.txt", b"b")],
+    );
+    set_age(&tmp.join("outer/a.rar"), DAY);
+    set_age(&tmp.join("outer/b.rar"), 2 * DAY);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("was already enqueued"), "{}", run.log);
+    // Written again by b.rar with the mtime stored for it, then given the one of b.rar.
+    assert_eq!(mtime(&tmp.join("outer/inner.rar")), mtime(&tmp.join("outer/b.rar")));
+}
+
+#[test]
+fn parts_are_kept_without_the_required_content() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("bad/bad.rar"),
+        &[file("bad.nfo", b"info"), file("bad.jpg", b"cover")],
+    );
+    write_rar(
+        &tmp.join("good/good.rar"),
+        &[file("good.nfo", b"info"), file("Good.MKV", b"movie")],
+    );
+    let inner = tmp.join("inner.rar");
+    write_rar(&inner, &[file("nested.mkv", b"nested movie")]);
+    let inner_bytes = fs::read(&inner).unwrap();
+    fs::remove_file(&inner).unwrap();
+    write_rar(&tmp.join("outer/outer.rar"), &[file("inner.rar", &inner_bytes)]);
+    for archive in ["bad/bad.rar", "good/good.rar", "outer/outer.rar"] {
+        set_age(&tmp.join(archive), 2 * DAY);
+    }
+
+    let run = rarscan([
+        "--remove-after-hours",
+        "24",
+        "--require-content",
+        "*.mp4",
+        "--require-content",
+        "*.mkv",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("bad/bad.nfo"), 4);
+    assert!(tmp.join("bad/bad.rar").exists(), "{}", run.log);
+    assert!(
+        run.log.contains("1 archives extracted without the required content"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("good/good.rar"));
+    // The movie of the chain is in the nested archive.
+    assert_file_size(&tmp.join("outer/nested.mkv"), 12);
+    assert_missing(&tmp.join("outer/outer.rar"));
+    assert_missing(&tmp.join("outer/inner.rar"));
+
+    let run = rarscan(["--remove-after-hours", "24", "--require-content", "*.mkv", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(tmp.join("bad/bad.rar").exists(), "{}", run.log);
+}
+
+#[test]
+fn volumes_of_a_nested_set_age_together() {
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("inner"), "inner.bin", &payload(3000), 1000);
+    let mut entries = Vec::new();
+    for part in &parts {
+        entries.push((
+            part.file_name().unwrap().to_str().unwrap().to_string(),
+            fs::read(part).unwrap(),
+        ));
+        fs::remove_file(part).unwrap();
+    }
+    let files: Vec<_> = entries.iter().map(|(name, bytes)| file(name, bytes)).collect();
+    write_rar(&tmp.join("outer/outer.rar"), &files);
+    set_age(&tmp.join("outer/outer.rar"), 2 * DAY);
+
+    let run = rarscan(["--extracted-mtime", "now", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("outer/inner.bin"), 3000);
+    assert!(
+        run.log.contains("Update the mtime of its 2 other volumes too."),
+        "{}",
+        run.log
+    );
+    let nested: Vec<_> = entries.iter().map(|(name, _)| tmp.join("outer").join(name)).collect();
+    for volume in &nested {
+        assert_eq!(
+            mtime(volume),
+            mtime(&tmp.join("outer/outer.rar")),
+            "{}",
+            volume.display()
+        );
+    }
+
+    // Removed in the same pass as the first volume of their set.
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    for volume in &nested {
+        assert_missing(volume);
+    }
+    assert_file_size(&tmp.join("outer/inner.bin"), 3000);
+}
+
+#[test]
+fn deep_dry_runs_simulate_nested_extractions() {
+    let tmp = TempDir::new();
+    let deepest = tmp.join("deepest.rar");
+    write_rar(&deepest, &[file("deep.txt", b"deep")]);
+    let deepest_bytes = fs::read(&deepest).unwrap();
+    fs::remove_file(&deepest).unwrap();
+    let parts = write_multipart(&tmp.join("inner"), "deepest.rar", &deepest_bytes, 40);
+    let mut entries = Vec::new();
+    for part in &parts {
+        entries.push((
+            part.file_name().unwrap().to_str().unwrap().to_string(),
+            fs::read(part).unwrap(),
+        ));
+        fs::remove_file(part).unwrap();
+    }
+    let files: Vec<_> = entries.iter().map(|(name, bytes)| file(name, bytes)).collect();
+    write_rar(&tmp.join("outer/outer.rar"), &files);
+
+    let run = rarscan(["--dry-run", "--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("'inner.part1.rar', not extracted in a dry-run."),
+        "{}",
+        run.log
+    );
+
+    let run = rarscan(["--dry-run", "--deep-dry-run", "--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    let simulated = format!(
+        "   -> [simulated] 'inner.part1.rar' would extract 1 files, {} B, into '{}':",
+        deepest_bytes.len(),
+        tmp.join("outer").display()
+    );
+    assert!(run.log.contains(&simulated), "{}", run.log);
+    assert!(
+        run.log.contains(&format!(
+            "      -> [simulated] Its {} volumes would be removed",
+            parts.len()
+        )),
+        "{}",
+        run.log
+    );
+    // A level deeper, from the copy of the volumes of the nested set.
+    assert!(
+        run.log
+            .contains("      -> [simulated] 'deepest.rar' would extract 1 files, 4 B, into"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("         -> 'deep.txt'"), "{}", run.log);
+    // Nothing but the archive on disk.
+    assert_eq!(fs::read_dir(tmp.join("outer")).unwrap().count(), 1);
+
+    let run = rarscan([
+        "--dry-run",
+        "--deep-dry-run",
+        "--deep-dry-run-max-size",
+        "10",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("past --deep-dry-run-max-size, not looked into."),
+        "{}",
+        run.log
+    );
+}
+
+#[test]
+fn archives_over_max_disk_usage_are_deferred_then_kept() {
+    let tmp = TempDir::new();
+    let archive = tmp.join("show/show.rar");
+    write_rar(&archive, &[file("show.mkv", &payload(2000))]);
+    set_age(&archive, 2 * DAY);
+
+    // Any filesystem in use is past a ten-thousandth of a percent.
+    let run = rarscan(["--max-disk-usage", "0.0001%", "--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("% used, over --max-disk-usage 0.0001%."),
+        "{}",
+        run.log
+    );
+    assert!(
+        run.log
+            .contains(&format!("Retrying '{}' after the removals.", archive.display())),
+        "{}",
+        run.log
+    );
+    assert!(
+        run.log.contains("-> Not extracting, it would still bring"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("show/show.mkv"));
+    assert_file_size(&archive, fs::metadata(&archive).unwrap().len());
+
+    let run = rarscan(["--max-disk-usage", "100", "--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/show.mkv"), 2000);
+    assert_missing(&archive);
+
+    let run = rarscan(["--max-disk-usage", "120%", tmp.root()]);
+    assert!(!run.success, "{}", run.log);
+}
+
+#[test]
+fn state_follows_an_archive_whose_directory_was_renamed() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("Show.S01/show.rar"), &[file("show.mkv", &payload(1000))]);
+    let state_file = tmp.join(".rarscan-state.json");
+
+    let run = rarscan(["--verify-crc", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    let run = rarscan(["state", "list", "--ids", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    let archive = tmp.join("Show.S01/show.rar");
+    let line = run
+        .log
+        .lines()
+        .find(|line| line.ends_with(&archive.display().to_string()));
+    let id = line.and_then(|line| line.split(' ').next()).unwrap().to_string();
+
+    fs::rename(tmp.join("Show.S01"), tmp.join("Show S01 (2020)")).unwrap();
+    let run = rarscan(["--verify-crc", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains(&format!(
+            "-> Its directory was renamed from '{}'",
+            tmp.join("Show.S01").display()
+        )),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("Archive already extracted"), "{}", run.log);
+    // The hash recorded before the rename is trusted, the extraction is still rarscan's.
+    assert!(
+        run.log.contains("0 hashed and 1 trusted from earlier runs"),
+        "{}",
+        run.log
+    );
+    assert!(!run.log.contains("extracted by something else"), "{}", run.log);
+    let state = fs::read_to_string(&state_file).unwrap();
+    assert!(!state.contains("Show.S01"), "{}", state);
+    let run = rarscan(["state", "list", "--ids", tmp.root()]);
+    let renamed = tmp.join("Show S01 (2020)/show.rar");
+    assert!(
+        run.log.contains(&format!("{} {}", id, renamed.display())),
+        "{}",
+        run.log
+    );
+    assert!(!run.log.contains("Show.S01"), "{}", run.log);
+
+    // A state file from before archives had an id gets them once.
+    let state: serde_json::Value = serde_json::from_str(&state).unwrap();
+    let mut state = state.as_object().unwrap().clone();
+    state.remove("archives");
+    state.insert("version".into(), 2.into());
+    fs::write(&state_file, serde_json::Value::Object(state).to_string()).unwrap();
+    let run = rarscan(["state", "list", "--ids", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log
+            .contains("Migrated the state file to archive ids, 1 of 1 archives identified."),
+        "{}",
+        run.log
+    );
+    assert!(
+        run.log.contains(&format!("{} {}", id, renamed.display())),
+        "{}",
+        run.log
+    );
+}
+
+#[test]
+fn nested_archives_each_have_a_fate() {
+    let tmp = TempDir::new();
+    let rar_bytes = |name: &str, content: &[u8]| {
+        let path = tmp.join(name);
+        write_rar(&path, &[file("content.txt", content)]);
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes
+    };
+    let inner = rar_bytes("inner.rar", b"inner");
+    let sample = rar_bytes("sample.rar", b"sample");
+    let skipped = rar_bytes("skipme.rar", b"skipped");
+    write_rar(
+        &tmp.join("outer/outer.rar"),
+        &[
+            file("inner/inner.rar", &inner),
+            file("Sample/sample.rar", &sample),
+            file("skipme/skipme.rar", &skipped),
+        ],
+    );
+    fs::write(tmp.join("outer/outer.rarscan.toml"), "exclude = [\"Sample\"]\n").unwrap();
+    fs::write(tmp.join(".rarscanignore"), "skipme.rar\n").unwrap();
+
+    let run = rarscan(["--dry-run", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("'inner/inner.rar', not extracted in a dry-run."),
+        "{}",
+        run.log
+    );
+    assert!(
+        run.log.contains("'Sample/sample.rar', excluded from the extraction."),
+        "{}",
+        run.log
+    );
+    assert!(!run.log.contains("vanished"), "{}", run.log);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Sample/sample.rar' (filtered)"), "{}", run.log);
+    assert!(run.log.contains("skipme/skipme.rar' (ignored)"), "{}", run.log);
+    assert!(
+        run.log.contains("inner/inner.rar' (enqueued, extracted)"),
+        "{}",
+        run.log
+    );
+    assert!(!run.log.contains("embedded archive"), "{}", run.log);
+    assert_file_size(&tmp.join("outer/inner/content.txt"), 5);
+    assert_missing(&tmp.join("outer/Sample"));
+    assert_missing(&tmp.join("outer/skipme/content.txt"));
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("inner/inner.rar' (enqueued, already_extracted)"),
+        "{}",
+        run.log
+    );
+}
+
+#[test]
+fn already_extracted_destination_is_kept() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    fs::write(tmp.join("show/a.txt"), b"HELLO").unwrap();
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Archive already extracted."), "{}", run.log);
+    assert_eq!(fs::read(tmp.join("show/a.txt")).unwrap(), b"HELLO");
+}
+
+#[test]
+fn removes_aged_parts() {
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("old/old"), "old.bin", &payload(3000), 1000);
+    for part in &parts {
+        set_age(part, 2 * DAY);
+    }
+    write_rar(&tmp.join("new/new.rar"), &[file("new.txt", b"fresh")]);
+
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    for part in &parts {
+        assert_missing(part);
+    }
+    assert_file_size(&tmp.join("old/old.bin"), 3000);
+    assert!(tmp.join("new/new.rar").exists());
+    assert_file_size(&tmp.join("new/new.txt"), 5);
+}
+
+#[test]
+fn a_removal_gate_that_cannot_run_keeps_the_parts() {
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("old/old"), "old.bin", &payload(3000), 1000);
+    for part in &parts {
+        set_age(part, 2 * DAY);
+    }
+    write_rar(&tmp.join("new/new.rar"), &[file("new.txt", b"fresh")]);
+    let gate = tmp.join("no-such-gate");
+
+    let run = rarscan([
+        "--remove-after-hours",
+        "24",
+        "--removal-gate",
+        gate.to_str().unwrap(),
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Removal gate failed"), "{}", run.log);
+    for part in &parts {
+        assert!(part.exists(), "{}", run.log);
+    }
+    assert_file_size(&tmp.join("new/new.txt"), 5);
+}
+
+#[test]
+fn a_set_is_removed_once_its_newest_part_is_old() {
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("show/show"), "show.bin", &payload(3000), 1000);
+    let (last, older) = parts.split_last().unwrap();
+    for part in older {
+        set_age(part, 2 * DAY);
+    }
+    set_age(last, DAY / 2);
+    let stray = tmp.join("show/show.r07");
+    fs::write(&stray, b"left by a repost").unwrap();
+    set_age(&stray, 2 * DAY);
+
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/show.bin"), 3000);
+    for part in &parts {
+        assert!(part.exists(), "{}", run.log);
+    }
+    assert!(stray.exists(), "{}", run.log);
+
+    set_age(last, 2 * DAY);
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    for part in &parts {
+        assert_missing(part);
+    }
+    assert_missing(&stray);
+}
+
+#[test]
+fn removals_past_the_cap_are_deferred() {
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("a/a"), "a.bin", &payload(3000), 1000);
+    for part in &parts {
+        set_age(part, 2 * DAY);
+    }
+    write_rar(&tmp.join("b/b.rar"), &[file("b.txt", b"small")]);
+    set_age(&tmp.join("b/b.rar"), 2 * DAY);
+
+    let run = rarscan([
+        "--dry-run",
+        "--remove-after-hours",
+        "24",
+        "--max-remove-files",
+        "2",
+        tmp.root(),
+    ]);
+    assert_eq!(run.code, Some(7), "{}", run.log);
+    assert!(
+        run.log.contains("4 parts") && run.log.contains("past the removal cap of 2 files"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("Over the removal cap, deferring"), "{}", run.log);
+    assert!(run.log.contains("b.rar'"), "{}", run.log);
+
+    let run = rarscan(["--remove-after-hours", "24", "--max-remove-files", "2", tmp.root()]);
+    assert_eq!(run.code, Some(7), "{}", run.log);
+    assert!(run.log.contains("Removal cap hit: 3 more files"), "{}", run.log);
+    // The set is kept whole, the archive after it still fits.
+    for part in &parts {
+        assert!(part.exists());
+    }
+    assert_missing(&tmp.join("b/b.rar"));
+
+    let run = rarscan(["--remove-after-hours", "24", "--max-remove-files", "3", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    for part in &parts {
+        assert_missing(part);
+    }
+}
+
+#[test]
+fn cruft_counts_toward_the_removal_cap_up_front() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    fs::write(tmp.join("show/show.sfv"), b"show.rar 00000000").unwrap();
+    fs::create_dir_all(tmp.join("stray")).unwrap();
+    fs::write(tmp.join("stray/stray.sfv"), b"gone.rar 00000000").unwrap();
+    for path in ["show/show.rar", "show/show.sfv", "stray/stray.sfv"] {
+        set_age(&tmp.join(path), 2 * DAY);
+    }
+
+    let run = rarscan(["--remove-after-hours", "24", "--max-remove-files", "2", tmp.root()]);
+    assert_eq!(run.code, Some(7), "{}", run.log);
+    let warning = run
+        .log
+        .find("1 parts and 2 cruft files")
+        .unwrap_or_else(|| panic!("{}", run.log));
+    // Before the first removal.
+    assert!(warning < run.log.find("Removing").unwrap(), "{}", run.log);
+}
+
+#[test]
+fn removals_and_overwrites_are_audited_before_they_happen() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("old/old.rar"), &[file("old.txt", b"old")]);
+    set_age(&tmp.join("old/old.rar"), 2 * DAY);
+    fs::write(tmp.join("old/old.sfv"), b"old.rar 00000000").unwrap();
+    set_age(&tmp.join("old/old.sfv"), 2 * DAY);
+    write_rar(&tmp.join("new/new.rar"), &[file("new.txt", b"new")]);
+    let audit = tmp.join("audit.tsv");
+    let audit_args = ["--audit-log", audit.to_str().unwrap(), "--audit-format", "tsv"];
+
+    let run = rarscan(
+        ["--dry-run", "--remove-after-hours", "24", tmp.root()]
+            .iter()
+            .chain(&audit_args),
+    );
+    assert!(run.success, "{}", run.log);
+    assert!(!audit.exists());
+
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()].iter().chain(&audit_args));
+    assert!(run.success, "{}", run.log);
+    assert_missing(&tmp.join("old/old.rar"));
+    fs::write(tmp.join("new/new.txt"), b"changed").unwrap();
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()].iter().chain(&audit_args));
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("new/new.txt"), 3);
+
+    let lines: Vec<Vec<String>> = fs::read_to_string(&audit)
+        .unwrap()
+        .lines()
+        .map(|line| line.split('\t').map(str::to_string).collect())
+        .collect();
+    let fields = |line: &Vec<String>| (line[1].clone(), line[2].clone(), line[3].clone(), line[6].clone());
+    let path = |name: &str| tmp.join(name).to_string_lossy().into_owned();
+    assert_eq!(
+        lines.iter().map(fields).collect::<Vec<_>>(),
+        [
+            ("remove".into(), path("old/old.rar"), lines[0][3].clone(), "age".into()),
+            ("remove".into(), path("old/old.sfv"), "16".into(), "cruft:*.sfv".into()),
+            (
+                "overwrite".into(),
+                path("new/new.txt"),
+                "7".into(),
+                "re-extraction".into()
+            ),
+        ]
+    );
+    assert_eq!(lines[0][5], path("old/old.rar"));
+
+    let grep = rarscan(["audit", "grep", "--audit-log", audit.to_str().unwrap(), "cruft:"]);
+    assert!(grep.success, "{}", grep.log);
+    assert!(
+        grep.log.contains("old.sfv") && !grep.log.contains("new.txt"),
+        "{}",
+        grep.log
+    );
+    let grep = rarscan([
+        "audit",
+        "grep",
+        "--audit-log",
+        audit.to_str().unwrap(),
+        "nothing-like-this",
+    ]);
+    assert_eq!(grep.code, Some(1), "{}", grep.log);
+}
+
+#[test]
+fn entries_past_4_gib_are_recognized_as_extracted() {
+    let tmp = TempDir::new();
+    // The fixture declares a single 6 GiB entry, large.bin, without its data.
+    let size = 6 << 30;
+    fs::create_dir_all(tmp.join("big")).unwrap();
+    fs::write(tmp.join("big/big.rar"), include_bytes!("../fixtures/large.rar")).unwrap();
+    let file = fs::File::create(tmp.join("big/large.bin")).unwrap();
+    file.set_len(size).unwrap();
+
+    let run = rarscan(["--dry-run", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("→ 6.0 GiB unpacked"), "{}", run.log);
+    assert!(run.log.contains("Archive already extracted."), "{}", run.log);
+
+    // One byte short is a mismatch, not one whose size wrapped around.
+    file.set_len(size - 1).unwrap();
+    let run = rarscan(["--dry-run", "--log-level", "debug", tmp.root()]);
+    assert!(
+        run.log
+            .contains(&format!("size mismatch, got {} want {}", size - 1, size)),
+        "{}",
+        run.log
+    );
+    assert!(!run.log.contains("Archive already extracted."), "{}", run.log);
+}
+
+#[test]
+fn renamed_volumes_are_followed_through_their_headers() {
+    let tmp = TempDir::new();
+    let data = payload(3000);
+    let parts = write_multipart(&tmp.join("show/Old.Name"), "show.mkv", &data, 1000);
+    let renamed = [tmp.join("show/New.Name.part2.rar"), tmp.join("show/New.Name.part3.rar")];
+    for (part, renamed) in parts[1..].iter().zip(&renamed) {
+        fs::rename(part, renamed).unwrap();
+    }
+    // The old naming, whose later volumes look like cruft.
+    let parts = write_multipart(&tmp.join("movie/movie"), "movie.mkv", &data, 1000);
+    let old = [
+        tmp.join("movie/Old.Movie.rar"),
+        tmp.join("movie/Movie.r00"),
+        tmp.join("movie/Movie.r01"),
+    ];
+    for (part, old) in parts.iter().zip(&old) {
+        fs::rename(part, old).unwrap();
+        set_age(old, 2 * DAY);
+    }
+
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log
+            .contains("Volumes renamed after another set, following the headers through 3 volumes"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("2 sets had volumes renamed"), "{}", run.log);
+    assert_file_size(&tmp.join("show/show.mkv"), 3000);
+    assert_file_size(&tmp.join("movie/movie.mkv"), 3000);
+    // The whole chain is the set, removed along with its first volume.
+    for part in &old {
+        assert_missing(part);
+    }
+    assert!(renamed.iter().all(|part| part.exists()));
+}
+
+#[test]
+fn parts_are_followed_across_directories() {
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("movie/cd1/Movie"), "movie.mkv", &payload(3000), 1000);
+    let moved = [
+        parts[0].clone(),
+        tmp.join("movie/cd2/Movie.part2.rar"),
+        tmp.join("movie/CD3/movie.part3.rar"),
+    ];
+    for (part, moved) in parts.iter().zip(&moved).skip(1) {
+        fs::create_dir_all(moved.parent().unwrap()).unwrap();
+        fs::rename(part, moved).unwrap();
+    }
+    for part in &moved {
+        set_age(part, 2 * DAY);
+    }
+    // Another set of the same name in a sibling isn't part of the chain.
+    write_multipart(&tmp.join("movie/extras/Other"), "other.mkv", &payload(1500), 1000);
+
+    let run = rarscan([tmp.root()]);
+    assert!(!run.success, "{}", run.log);
+    assert_missing(&tmp.join("movie/movie.mkv"));
+
+    let run = rarscan([
+        "--parts-across-dirs",
+        "--remove-after-hours",
+        "24",
+        "--remove-empty-dirs",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Volumes spread across the directories of"),
+        "{}",
+        run.log
+    );
+    assert_file_size(&tmp.join("movie/movie.mkv"), 3000);
+    assert_file_size(&tmp.join("movie/extras/other.mkv"), 1500);
+    // The parts are removed from every directory, which are left empty.
+    for part in &moved {
+        assert_missing(part);
+        assert_missing(part.parent().unwrap());
+    }
+}
+
+#[test]
+fn removes_nested_chain_together() {
+    let tmp = TempDir::new();
+    let inner = tmp.join("inner.rar");
+    write_rar(&inner, &[file("inner.txt", b"nested content")]);
+    let inner_bytes = fs::read(&inner).unwrap();
+    fs::remove_file(&inner).unwrap();
+    write_rar(&tmp.join("outer/outer.rar"), &[file("inner.rar", &inner_bytes)]);
+    set_age(&tmp.join("outer/outer.rar"), 2 * DAY);
+
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_missing(&tmp.join("outer/outer.rar"));
+    assert_missing(&tmp.join("outer/inner.rar"));
+    assert_file_size(&tmp.join("outer/inner.txt"), 14);
+}
+
+#[test]
+fn suspected_fakes_are_kept() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("Movie.2020.1080p.BluRay.x264/movie.rar"),
+        &[file("movie.iso", b"not really a movie")],
+    );
+    set_age(&tmp.join("Movie.2020.1080p.BluRay.x264/movie.rar"), 2 * DAY);
+    write_rar(
+        &tmp.join("Show.S01E01.720p.HDTV/show.rar"),
+        &[file("setup.exe", b"MZ"), file("password.txt", b"visit")],
+    );
+    set_age(&tmp.join("Show.S01E01.720p.HDTV/show.rar"), 2 * DAY);
+
+    let run = rarscan(["--detect-fakes", "--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("2 archives skipped as suspected fakes"), "{}", run.log);
+    assert!(run.log.contains("'setup.exe'"), "{}", run.log);
+    assert!(tmp.join("Show.S01E01.720p.HDTV/show.rar").exists());
+    assert_missing(&tmp.join("Show.S01E01.720p.HDTV/setup.exe"));
+    assert!(tmp.join("Movie.2020.1080p.BluRay.x264/movie.rar").exists());
+}
+
+#[test]
+fn changelog_lists_changes() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[file("a.txt", b"hello"), file("b.txt", b"world")],
+    );
+    fs::write(tmp.join("show/b.txt"), b"old").unwrap();
+    set_age(&tmp.join("show/show.rar"), 2 * DAY);
+    let changelog = tmp.join("changes.jsonl");
+
+    let args = [
+        "--remove-after-hours",
+        "24",
+        "--changelog",
+        changelog.to_str().unwrap(),
+        tmp.root(),
+    ];
+    let run = rarscan(["--dry-run"].iter().chain(&args));
+    assert!(run.success, "{}", run.log);
+    let dry_run = fs::read_to_string(&changelog).unwrap();
+    assert_eq!(dry_run.lines().count(), 3, "{}", dry_run);
+    assert!(
+        dry_run.lines().all(|line| line.contains(r#""applied":false"#)),
+        "{}",
+        dry_run
+    );
+
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    let lines: Vec<String> = fs::read_to_string(&changelog)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    assert!(lines[0].contains(r#""change":"created""#), "{}", lines[0]);
+    assert!(lines[1].contains(r#""change":"overwritten""#), "{}", lines[1]);
+    assert!(lines[1].contains(r#""old_size":3"#), "{}", lines[1]);
+    assert!(lines[2].contains(r#""change":"removed""#), "{}", lines[2]);
+    assert!(lines.iter().all(|line| line.contains(r#""applied":true"#)));
+}
+
+/// Moves the extraction times recorded in the state file `age` into the past.
+fn age_extractions(state_file: &std::path::Path, age: Duration) {
+    let mut state: serde_json::Value = serde_json::from_str(&fs::read_to_string(state_file).unwrap()).unwrap();
+    for entry in state["extracted"].as_object_mut().unwrap().values_mut() {
+        let time = entry["time"].as_f64().unwrap() - age.as_secs_f64();
+        entry["time"] = time.into();
+    }
+    fs::write(state_file, state.to_string()).unwrap();
+}
+
+#[test]
+fn tiers_old_extracted_files() {
+    let tmp = TempDir::new();
+    let root = tmp.join("root");
+    let archive_dir = tmp.join("archive");
+    write_rar(
+        &root.join("show/show.rar"),
+        &[dir("sub"), file("sub/a.bin", &payload(100))],
+    );
+    let root = root.to_str().unwrap();
+    let args = [
+        "--archive-extracted-after",
+        "7d",
+        "--archive-extracted-to",
+        archive_dir.to_str().unwrap(),
+        "--remove-empty-dirs",
+        root,
+    ];
+
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("root/show/sub/a.bin"), 100);
+    assert_missing(&archive_dir);
+
+    age_extractions(&tmp.join("root/.rarscan-state.json"), 8 * DAY);
+    let run = rarscan(["--dry-run"].iter().chain(&args));
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Moved 1 extracted files (100 B)."), "{}", run.log);
+    assert_file_size(&tmp.join("root/show/sub/a.bin"), 100);
+
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&archive_dir.join("show/sub/a.bin"), 100);
+    assert_missing(&tmp.join("root/show/sub"));
+    assert!(tmp.join("root/show/show.rar").exists());
+
+    // Moved, still extracted.
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Archive already extracted"), "{}", run.log);
+    assert!(!run.log.contains("Extracting into"), "{}", run.log);
+    assert_missing(&tmp.join("root/show/sub"));
+}
+
+#[test]
+fn only_directories_emptied_by_the_run_are_removed() {
+    let tmp = TempDir::new();
+    let root = tmp.join("root");
+    let archive_dir = tmp.join("archive");
+    write_rar(
+        &root.join("show/show.rar"),
+        &[
+            dir("gone"),
+            file("gone/a.bin", &payload(100)),
+            dir("kept"),
+            file("kept/b.bin", &payload(100)),
+            dir("kept/empty"),
+            dir("used"),
+            file("used/c.bin", &payload(100)),
+        ],
+    );
+    let root = root.to_str().unwrap();
+    let args = [
+        "--archive-extracted-after",
+        "7d",
+        "--archive-extracted-to",
+        archive_dir.to_str().unwrap(),
+        "--remove-empty-dirs",
+        root,
+    ];
+
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    fs::create_dir(tmp.join("root/show/used/made")).unwrap();
+
+    age_extractions(&tmp.join("root/.rarscan-state.json"), 8 * DAY);
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&archive_dir.join("show/gone/a.bin"), 100);
+    assert_file_size(&archive_dir.join("show/kept/b.bin"), 100);
+    assert_missing(&tmp.join("root/show/gone"));
+    // Left with the empty directory of the archive, which stays and keeps it.
+    assert!(tmp.join("root/show/kept/empty").is_dir(), "{}", run.log);
+    // The same with one made since.
+    assert!(tmp.join("root/show/used/made").is_dir(), "{}", run.log);
+}
+
+#[test]
+fn remove_after_rules_per_directory() {
+    let tmp = TempDir::new();
+    for dir in ["tv/show", "movies/movie", "music/album"] {
+        let path = tmp.join(format!("{}/a.rar", dir));
+        write_rar(&path, &[file("a.txt", b"hello")]);
+        set_age(&path, 2 * DAY);
+    }
+
+    let run = rarscan([
+        "--remove-after-for",
+        "tv/**=1d",
+        "--remove-after-for",
+        "movies/**=30d",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("(rule 'tv/**')"), "{}", run.log);
+    assert_missing(&tmp.join("tv/show/a.rar"));
+    assert!(tmp.join("movies/movie/a.rar").exists());
+    // No rule and no global threshold.
+    assert!(tmp.join("music/album/a.rar").exists());
+
+    let run = rarscan([
+        "--remove-after-hours",
+        "24",
+        "--remove-after-for",
+        "movies/**=30d",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(tmp.join("movies/movie/a.rar").exists());
+    assert_missing(&tmp.join("music/album/a.rar"));
+}
+
+#[test]
+fn conflicting_remove_after_rules_are_rejected() {
+    let tmp = TempDir::new();
+    let run = rarscan([
+        "--remove-after-for",
+        "tv/*=1d",
+        "--remove-after-for",
+        "tv/*=2d",
+        tmp.root(),
+    ]);
+    assert!(!run.success);
+    assert!(run.log.contains("conflict"), "{}", run.log);
+
+    // Both match tv/sh, naming a directory each.
+    let run = rarscan([
+        "--remove-after-for",
+        "tv/*=1d",
+        "--remove-after-for",
+        "*/sh=2d",
+        tmp.root(),
+    ]);
+    assert!(!run.success);
+    assert!(run.log.contains("conflict"), "{}", run.log);
+}
+
+#[test]
+fn the_rule_naming_more_directories_wins() {
+    let tmp = TempDir::new();
+    let path = tmp.join("tv/sh/special.rar");
+    write_rar(&path, &[file("a.txt", b"hello")]);
+    set_age(&path, 2 * DAY);
+    let args = [
+        "--remove-after-for",
+        "tv/sh/*=30d",
+        "--remove-after-for",
+        "*/*/special.rar=1d",
+        tmp.root(),
+    ];
+
+    let run = rarscan(["--dry-run"].iter().chain(&args));
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("(rule 'tv/sh/*' over '*/*/special.rar')"),
+        "{}",
+        run.log
+    );
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    assert!(path.exists(), "{}", run.log);
+}
+
+#[test]
+fn newest_archives_of_a_release_are_kept() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("other/other.rar"), &[file("other.txt", b"other")]);
+    set_age(&tmp.join("other/other.rar"), 2 * DAY);
+    for episode in ["e01", "e02", "e03"] {
+        let path = tmp.join(format!("show/{}/{}.rar", episode, episode));
+        write_rar(&path, &[file(&format!("{}.txt", episode), b"episode")]);
+        set_age(&path, 2 * DAY);
+        // One run each, for their extractions to come in order.
+        let run = rarscan([tmp.root()]);
+        assert!(run.success, "{}", run.log);
+    }
+
+    let run = rarscan([
+        "--dry-run",
+        "--remove-after-hours",
+        "24",
+        "--keep-newest",
+        "1",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    let kept = |archive: &str, rank: usize, release: &str| {
+        format!(
+            "Keeping 1 expired parts of '{}', kept: newest-{} of directory '{}'.",
+            tmp.join(archive).display(),
+            rank,
+            release
+        )
+    };
+    assert!(run.log.contains(&kept("show/e03/e03.rar", 1, "show")), "{}", run.log);
+    assert!(run.log.contains(&kept("other/other.rar", 1, "other")), "{}", run.log);
+    assert!(!run.log.contains(&kept("show/e02/e02.rar", 2, "show")), "{}", run.log);
+    assert!(
+        run.log
+            .contains("2 archives past their threshold kept by --keep-newest:"),
+        "{}",
+        run.log
+    );
+
+    // A rule overrides the global count for the releases it matches.
+    let run = rarscan([
+        "--remove-after-hours",
+        "24",
+        "--remove-after-for",
+        "show/**=1d,keep-newest=2",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert_missing(&tmp.join("show/e01/e01.rar"));
+    assert!(tmp.join("show/e02/e02.rar").exists());
+    assert!(tmp.join("show/e03/e03.rar").exists());
+    assert_missing(&tmp.join("other/other.rar"));
+}
+
+#[test]
+fn long_names_are_skipped_or_shortened() {
+    let tmp = TempDir::new();
+    let long = format!("{}.mkv", "a".repeat(300));
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[dir("sub"), file(&format!("sub/{}", long), b"hello")],
+    );
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("exceed the file name or path length limits"),
+        "{}",
+        run.log
+    );
+
+    let run = rarscan(["--shorten-long-names", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    let names: Vec<String> = fs::read_dir(tmp.join("show/sub"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(names.len(), 1, "{:?}", names);
+    assert_eq!(names[0].len(), 255, "{}", names[0]);
+    assert!(
+        names[0].starts_with("aaaa") && names[0].ends_with(".mkv"),
+        "{}",
+        names[0]
+    );
+    assert_file_size(&tmp.join("show/sub").join(&names[0]), 5);
+
+    let run = rarscan(["--shorten-long-names", tmp.root()]);
+    assert!(run.log.contains("Archive already extracted."), "{}", run.log);
+}
+
+#[test]
+fn prefetched_headers_match_sequential_open() {
+    for flags in [&[][..], &["--no-prefetch"][..]] {
+        let tmp = TempDir::new();
+        write_rar(&tmp.join("a/a.rar"), &[file("a.txt", b"first")]);
+        fs::create_dir_all(tmp.join("b")).unwrap();
+        fs::write(tmp.join("b/b.rar"), b"Rar!\x1a\x07\x00truncated").unwrap();
+
+        // The damaged headers are listed while a.rar extracts, the error only surfaces once b.rar is processed.
+        let run = rarscan(flags.iter().copied().chain([tmp.root()]));
+        assert!(!run.success, "{}", run.log);
+        assert_file_size(&tmp.join("a/a.txt"), 5);
+        assert!(run.log.contains("archive open"), "{}", run.log);
+        assert!(run.log.contains("File header damaged"), "{}", run.log);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_are_followed_only_on_request() {
+    use std::os::unix::fs::symlink;
+
+    let tmp = TempDir::new();
+    let outside = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("show.txt", b"inside")]);
+    write_rar(&outside.join("other/other.rar"), &[file("other.txt", b"outside")]);
+    fs::write(outside.join("other/other.sfv"), b"other.rar 00000000").unwrap();
+    for path in [
+        tmp.join("show/show.rar"),
+        outside.join("other/other.rar"),
+        outside.join("other/other.sfv"),
+    ] {
+        set_age(&path, 2 * DAY);
+    }
+    symlink(outside.join("other"), tmp.join("linked")).unwrap();
+    symlink(tmp.join("show"), tmp.join("show/loop")).unwrap();
+
+    let run = rarscan(["--dry-run", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_eq!(run.log.matches("Analyzing").count(), 1, "{}", run.log);
+
+    let run = rarscan(["--follow-symlinks", "--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Symlink loop"), "{}", run.log);
+    assert_file_size(&tmp.join("show/show.txt"), 6);
+    assert_file_size(&outside.join("other/other.txt"), 7);
+    // Only the parts inside of the tree are removed, the ones found through the symlink are left alone.
+    assert_missing(&tmp.join("show/show.rar"));
+    assert!(outside.join("other/other.rar").exists());
+    assert!(outside.join("other/other.sfv").exists());
+}
+
+#[test]
+fn unchanged_directories_are_not_read_again() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("tv/show/show.rar"), &[file("show.txt", b"show")]);
+    write_rar(&tmp.join("movies/movie/movie.rar"), &[file("movie.txt", b"movie")]);
+    let dirs = ["tv/show", "tv", "movies/movie", "movies"];
+    let age_dirs = || {
+        for dir in dirs {
+            set_age(&tmp.join(dir), DAY);
+        }
+    };
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    age_dirs();
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("-> 0 of 5 directories unchanged"), "{}", run.log);
+
+    // The root changes with the state file.
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("-> 4 of 5 directories unchanged"), "{}", run.log);
+    assert_eq!(run.log.matches("Archive already extracted").count(), 2, "{}", run.log);
+
+    // An archive added to an old directory changes its mtime, and only its own.
+    write_rar(&tmp.join("tv/show/extra.rar"), &[file("extra.txt", b"extra")]);
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("-> 3 of 5 directories unchanged"), "{}", run.log);
+    assert_file_size(&tmp.join("tv/show/extra.txt"), 5);
+
+    // So does one in a new directory, which changes its parent.
+    write_rar(&tmp.join("movies/sequel/sequel.rar"), &[file("sequel.txt", b"sequel")]);
+    age_dirs();
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("movies/sequel/sequel.txt"), 6);
+
+    let run = rarscan(["--full-scan", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(!run.log.contains("unchanged since the last scan"), "{}", run.log);
+    assert_eq!(run.log.matches("Archive already extracted").count(), 4, "{}", run.log);
+}
+
+#[test]
+fn adopts_extractions_done_by_other_tools() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("show.txt", b"inside")]);
+    write_rar(&tmp.join("other/other.rar"), &[file("other.txt", b"outside")]);
+    fs::write(tmp.join("show/show.txt"), b"inside").unwrap();
+    fs::write(tmp.join("other/other.txt"), b"outside").unwrap();
+    set_age(&tmp.join("show/show.txt"), 3 * DAY);
+    let state_file = tmp.join(".rarscan-state.json");
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("2 archives were extracted by something else"),
+        "{}",
+        run.log
+    );
+    assert!(!state_file.exists());
+
+    let run = rarscan(["adopt", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Adopted 2 pre-existing extractions"), "{}", run.log);
+    let state = fs::read_to_string(&state_file).unwrap();
+    assert!(state.contains("show.txt") && state.contains("other.txt"), "{}", state);
+    // Nothing was extracted again or removed.
+    assert!(tmp.join("show/show.rar").exists());
+    assert!(!run.log.contains("Extracting into"), "{}", run.log);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(!run.log.contains("extracted by something else"), "{}", run.log);
+
+    // The extraction time is the mtime of the newest file.
+    fs::remove_file(&state_file).unwrap();
+    let run = rarscan([
+        "--auto-adopt",
+        "--archive-extracted-after",
+        "2d",
+        "--archive-extracted-to",
+        &tmp.join("tier").to_string_lossy(),
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Adopted 2 pre-existing extractions"), "{}", run.log);
+    assert_file_size(&tmp.join("tier/show/show.txt"), 6);
+    assert_file_size(&tmp.join("other/other.txt"), 7);
+}
+
+#[test]
+fn imports_the_extractions_of_unpackerr() {
+    let tmp = TempDir::new();
+    // Laid out the way unpackerr leaves downloads: extracted into `<download>_unpackerred` next to the download, then
+    // moved back into the download.
+    let (done, staged, partial) = (payload(3000), payload(2000), payload(4000));
+    write_rar(&tmp.join("tv/Done.S01E01/done.rar"), &[file("done.mkv", &done)]);
+    fs::write(tmp.join("tv/Done.S01E01/done.mkv"), &done).unwrap();
+    write_rar(&tmp.join("tv/Staged.S01E02/staged.rar"), &[file("staged.mkv", &staged)]);
+    fs::create_dir_all(tmp.join("tv/Staged.S01E02_unpackerred")).unwrap();
+    fs::write(tmp.join("tv/Staged.S01E02_unpackerred/staged.mkv"), &staged).unwrap();
+    write_rar(
+        &tmp.join("tv/Partial.S01E03/partial.rar"),
+        &[file("partial.mkv", &partial)],
+    );
+    fs::create_dir_all(tmp.join("tv/Partial.S01E03_unpackerred")).unwrap();
+    fs::write(tmp.join("tv/Partial.S01E03_unpackerred/partial.mkv"), &partial[..1000]).unwrap();
+    write_rar(&tmp.join("tv/New.S01E04/new.rar"), &[file("new.mkv", &payload(1000))]);
+    fs::create_dir_all(tmp.join("tv/Orphan_unpackerred")).unwrap();
+    fs::write(tmp.join("tv/Orphan_unpackerred/orphan.mkv"), b"only copy").unwrap();
+    // Same size, but not the same content.
+    write_rar(&tmp.join("tv/Bad.S01E05/bad.rar"), &[file("bad.mkv", &payload(500))]);
+    fs::write(tmp.join("tv/Bad.S01E05/bad.mkv"), [0; 500]).unwrap();
+
+    let run = rarscan(["import-unpackerr", "--dry-run", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log
+            .contains("Imported 5 archives from unpackerr: 1 adopted, 2 re-queued, 2 left untouched."),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("tv/Partial.S01E03/partial.mkv"));
+    assert!(tmp.join("tv/Staged.S01E02_unpackerred").exists());
+
+    let run = rarscan(["import-unpackerr", "--clean", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log
+            .contains("Imported 5 archives from unpackerr: 2 adopted, 2 re-queued, 1 left untouched."),
+        "{}",
+        run.log
+    );
+    assert_file_size(&tmp.join("tv/Staged.S01E02/staged.mkv"), 2000);
+    assert_file_size(&tmp.join("tv/Partial.S01E03/partial.mkv"), 4000);
+    assert_eq!(fs::read(tmp.join("tv/Bad.S01E05/bad.mkv")).unwrap(), payload(500));
+    assert_missing(&tmp.join("tv/New.S01E04/new.mkv"));
+    assert_missing(&tmp.join("tv/Staged.S01E02_unpackerred"));
+    assert_missing(&tmp.join("tv/Partial.S01E03_unpackerred"));
+    assert!(tmp.join("tv/Orphan_unpackerred/orphan.mkv").exists());
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(state.contains("done.mkv") && state.contains("staged.mkv"), "{}", state);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(!run.log.contains("extracted by something else"), "{}", run.log);
+}
+
+#[cfg(unix)]
+#[test]
+fn vanished_archives_are_skipped() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("a/a.rar"), &[file("a.mp4", b"first")]);
+    write_rar(&tmp.join("b/dup.rar"), &[file("b.txt", b"second")]);
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    fs::create_dir_all(tmp.join("c")).unwrap();
+    fs::copy(tmp.join("b/dup.rar"), tmp.join("c/dup.rar")).unwrap();
+    fs::remove_file(tmp.join("a/a.mp4")).unwrap();
+
+    // The probe of what a.rar extracts again stands in for a download client removing a duplicate.
+    let ffprobe = tmp.join("ffprobe.sh");
+    fs::write(
+        &ffprobe,
+        format!("#!/bin/sh\nrm -f '{}'\n", tmp.join("b/dup.rar").display()),
+    )
+    .unwrap();
+    fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let run = rarscan(["--probe-media", &ffprobe.to_string_lossy(), tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Archive vanished before processing, skipping."),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("1 archives vanished"), "{}", run.log);
+    assert!(run.log.contains("Moved its state to"), "{}", run.log);
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(!state.contains("b/dup.rar"), "{}", state);
+    assert!(state.contains("c/dup.rar"), "{}", state);
+}
+
+#[test]
+fn fsync_precedes_part_removal() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[dir("sub"), file("sub/a.bin", &payload(1000)), file("b.txt", b"hello")],
+    );
+    set_age(&tmp.join("show/show.rar"), 2 * DAY);
+
+    let run = rarscan(["--fsync", "--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/sub/a.bin"), 1000);
+    assert_missing(&tmp.join("show/show.rar"));
+    let synced = run.log.find("Synced 2 files").expect(&run.log);
+    let removed = run.log.find("Removing archive/part").expect(&run.log);
+    assert!(synced < removed, "{}", run.log);
+}
+
+#[test]
+fn archives_are_grouped_by_release() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("show.txt", b"main")]);
+    write_rar(&tmp.join("done/done.rar"), &[file("done.txt", b"done")]);
+    write_rar(&tmp.join("loose.rar"), &[file("loose.txt", b"loose")]);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("3 releases, 3 complete:"), "{}", run.log);
+    assert!(run.log.contains("-> '<root>': 1 archives, complete"), "{}", run.log);
+
+    write_rar(&tmp.join("show/subs/subs.rar"), &[file("subs.srt", b"subtitles")]);
+    let run = rarscan(["--only-incomplete-releases", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("2 releases out of 3 are complete"), "{}", run.log);
+    let analyzed = format!("Analyzing '{}'", tmp.join("done/done.rar").display());
+    assert!(!run.log.contains(&analyzed), "{}", run.log);
+    assert!(run.log.contains("-> 'show': 2 archives, complete"), "{}", run.log);
+    assert_file_size(&tmp.join("show/subs/subs.srt"), 9);
+}
+
+#[test]
+fn extraction_failures_are_classified() {
+    let tmp = TempDir::new();
+    let data = payload(5000);
+    let parts = write_multipart(&tmp.join("movie/movie"), "movie.mkv", &data, 2000);
+    fs::remove_file(&parts[2]).unwrap();
+    write_rar(&tmp.join("bad/bad.rar"), &[file("bad.bin", &payload(1000))]);
+    let mut bytes = fs::read(tmp.join("bad/bad.rar")).unwrap();
+    let len = bytes.len();
+    bytes[len - 100] ^= 0xff;
+    fs::write(tmp.join("bad/bad.rar"), bytes).unwrap();
+    write_rar(&tmp.join("show/show.rar"), &[file("show.txt", b"fine")]);
+
+    let run = rarscan(["--remove-after-hours", "0", tmp.root()]);
+    assert_eq!(run.code, Some(2), "{}", run.log);
+    assert!(run.log.contains("2 archives failed to extract"), "{}", run.log);
+    assert!(run.log.contains("(missing_volume)"), "{}", run.log);
+    assert!(run.log.contains("movie.part3.rar"), "{}", run.log);
+    assert!(run.log.contains("(corrupt)"), "{}", run.log);
+    assert_file_size(&tmp.join("show/show.txt"), 4);
+    // The parts of the failed archives are kept.
+    assert!(parts[0].exists());
+    assert!(tmp.join("bad/bad.rar").exists());
+
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[dir("sub"), file("sub/a.txt", b"hello")]);
+    fs::write(tmp.join("show/sub"), b"in the way").unwrap();
+    let run = rarscan([tmp.root()]);
+    assert_eq!(run.code, Some(3), "{}", run.log);
+    assert!(run.log.contains("(create_error)"), "{}", run.log);
+}
+
+#[test]
+fn extracted_mtime_policies() {
+    for (policy, root) in [("keep", "k"), ("now", "n"), ("archive", "a")] {
+        let tmp = TempDir::new();
+        write_rar(&tmp.join(format!("{root}/{root}.rar")), &[file("a.txt", b"hello")]);
+        set_age(&tmp.join(format!("{root}/{root}.rar")), DAY);
+        let started = std::time::SystemTime::now();
+
+        let run = rarscan([tmp.root(), "--extracted-mtime", policy]);
+        assert!(run.success, "{}", run.log);
+        let extracted = mtime(&tmp.join(format!("{root}/a.txt")));
+        let archive = mtime(&tmp.join(format!("{root}/{root}.rar")));
+        match policy {
+            // The fixtures store a date in 2020, long before the archive was written.
+            "keep" => assert!(extracted < archive - DAY, "{policy}"),
+            "now" => assert!(extracted >= started - Duration::from_secs(1), "{policy}"),
+            _ => assert_eq!(extracted, archive, "{policy}"),
+        }
+    }
+}
+
+#[test]
+fn blocked_archives_wait_for_free_space() {
+    let tmp = TempDir::new();
+    let archive = tmp.join("show/show.rar");
+    write_rar(&archive, &[file("a.txt", b"hello")]);
+    // As left by a run whose extraction filled the destination, needing more than any disk has free.
+    let state = tmp.join(".rarscan-state.json");
+    let blocked = |required: u64| {
+        let mut blocked = serde_json::Map::new();
+        blocked.insert(archive.to_string_lossy().into_owned(), required.into());
+        fs::write(
+            &state,
+            serde_json::json!({ "version": 1, "blocked": blocked }).to_string(),
+        )
+        .unwrap();
+    };
+    blocked(u64::MAX / 2);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log
+            .contains("1 archives blocked until their destination has space again"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("show/a.txt"));
+
+    blocked(1);
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("resuming the blocked archive"), "{}", run.log);
+    assert_file_size(&tmp.join("show/a.txt"), 5);
+    let state: serde_json::Value = serde_json::from_str(&fs::read_to_string(&state).unwrap()).unwrap();
+    assert_eq!(state["blocked"], serde_json::json!({}));
+}
+
+#[test]
+fn verify_crc_caches_results_and_extracts_mismatches_again() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    assert!(rarscan([tmp.root()]).success);
+
+    let run = rarscan([tmp.root(), "--verify-crc"]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Verified 1 files, 1 hashed and 0 trusted"),
+        "{}",
+        run.log
+    );
+
+    let run = rarscan([tmp.root(), "--verify-crc"]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log
+            .contains("0 hashed and 1 trusted from earlier runs (100% cache hits)"),
+        "{}",
+        run.log
+    );
+
+    let run = rarscan([tmp.root(), "--verify-crc", "--revalidate"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("1 hashed and 0 trusted"), "{}", run.log);
+
+    fs::write(tmp.join("show/a.txt"), b"jello").unwrap();
+    let run = rarscan([tmp.root(), "--verify-crc"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("doesn't match its CRC"), "{}", run.log);
+    assert_eq!(fs::read(tmp.join("show/a.txt")).unwrap(), b"hello");
+}
+
+#[test]
+fn audits_go_round_the_library_and_report_rot() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[file("a.txt", b"hello"), file("b.txt", b"world")],
+    );
+    let run = rarscan([tmp.root(), "--verify-crc"]);
+    assert!(run.success, "{}", run.log);
+
+    // One file per audit, the one verified the longest ago first.
+    let run = rarscan(["audit", tmp.root(), "--byte-budget", "1"]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Audited 1 files, 5 B, 0 corrupt, 1 left"),
+        "{}",
+        run.log
+    );
+    let state = || fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    let verified_at = |state: &str, file: &str| {
+        let state: serde_json::Value = serde_json::from_str(state).unwrap();
+        state["verified"][tmp.join(file).to_str().unwrap()]["time"]
+            .as_f64()
+            .unwrap()
+    };
+    let first = state();
+    assert!(verified_at(&first, "show/a.txt") > verified_at(&first, "show/b.txt"));
+    let run = rarscan(["audit", tmp.root(), "--byte-budget", "1"]);
+    assert!(run.success, "{}", run.log);
+    let second = state();
+    assert!(verified_at(&second, "show/b.txt") > verified_at(&first, "show/b.txt"));
+    assert_eq!(verified_at(&second, "show/a.txt"), verified_at(&first, "show/a.txt"));
+
+    // Rot changes the content but neither the size nor the modification time.
+    let mtime = fs::metadata(tmp.join("show/b.txt")).unwrap().modified().unwrap();
+    let mut data = fs::read(tmp.join("show/b.txt")).unwrap();
+    data[0] ^= 0x20;
+    fs::write(tmp.join("show/b.txt"), &data).unwrap();
+    set_mtime(&tmp.join("show/b.txt"), mtime);
+    let run = rarscan(["audit", tmp.root(), "--budget", "1h"]);
+    assert_eq!(run.code, Some(9), "{}", run.log);
+    assert!(run.log.contains("show/b.txt' is corrupt"), "{}", run.log);
+    assert!(run.log.contains("Extract it again from '"), "{}", run.log);
+    assert!(
+        run.log.contains("Audited 2 files, 10 B, 1 corrupt, 0 left"),
+        "{}",
+        run.log
+    );
+    // And keeps being reported until it's fixed.
+    let run = rarscan(["audit", tmp.root()]);
+    assert_eq!(run.code, Some(9), "{}", run.log);
+}
+
+#[test]
+fn broken_chain_is_removed_after_grace_period() {
+    let tmp = TempDir::new();
+    let inner = tmp.join("inner.rar");
+    write_rar(&inner, &[file("inner.txt", b"nested content")]);
+    let inner_bytes = fs::read(&inner).unwrap();
+    fs::remove_file(&inner).unwrap();
+    write_rar(&tmp.join("outer/outer.rar"), &[file("inner.rar", &inner_bytes)]);
+    assert!(rarscan([tmp.root()]).success);
+
+    // The root of the chain is deleted by hand, leaving the archive nested in it.
+    fs::remove_file(tmp.join("outer/outer.rar")).unwrap();
+    set_age(&tmp.join("outer/inner.rar"), 2 * DAY);
+    let run = rarscan(["--remove-after-hours", "24", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("outer.rar' is gone, keeping the rest of the chain"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("outer/outer.rar' (broken)"), "{}", run.log);
+    assert_file_size(&tmp.join("outer/inner.rar"), inner_bytes.len() as u64);
+
+    let run = rarscan(["--remove-after-hours", "24", "--chain-grace-hours", "0", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("removing the rest of the chain"), "{}", run.log);
+    assert_missing(&tmp.join("outer/inner.rar"));
+    assert_file_size(&tmp.join("outer/inner.txt"), 14);
+}
+
+#[test]
+fn invalid_media_payload_is_reported() {
+    let tmp = TempDir::new();
+    let mkv = [0x1a, 0x45, 0xdf, 0xa3, 0x93, 0x42, 0x82, 0x88];
+    write_rar(&tmp.join("good/good.rar"), &[file("good.mkv", &mkv)]);
+    write_rar(&tmp.join("bad/bad.rar"), &[file("bad.mkv", b"<html>404</html>")]);
+    set_age(&tmp.join("good/good.rar"), 2 * DAY);
+    set_age(&tmp.join("bad/bad.rar"), 2 * DAY);
+
+    let run = rarscan([
+        tmp.root(),
+        "--remove-after-hours",
+        "24",
+        "--probe-media",
+        "--require-valid-media",
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("1 archives extracted but their payload is invalid"),
+        "{}",
+        run.log
+    );
+    assert!(
+        run.log.contains("bad.mkv': unrecognized container header"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("good/good.rar"));
+    // The parts are kept for downloading the release again.
+    assert_file_size(&tmp.join("bad/bad.mkv"), 16);
+    assert!(tmp.join("bad/bad.rar").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn media_is_probed_with_ffprobe_when_given() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.mp4", b"anything")]);
+    let ffprobe = tmp.join("ffprobe.sh");
+    fs::write(
+        &ffprobe,
+        "#!/bin/sh\necho \"$7: Invalid data found when processing input\" >&2\nexit 1\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ffprobe, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let run = rarscan([
+        tmp.root(),
+        "--remove-after-hours",
+        "0",
+        "--probe-media",
+        ffprobe.to_str().unwrap(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("a.mp4: Invalid data found when processing input"),
+        "{}",
+        run.log
+    );
+    // Without --require-valid-media the probe doesn't hold up the removal.
+    assert_missing(&tmp.join("show/show.rar"));
+}
+
+#[cfg(unix)]
+#[test]
+fn files_flagged_by_the_scanner_are_quarantined_and_keep_their_parts() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[file("a.txt", b"hello"), file("sub/bad.txt", b"EICAR")],
+    );
+    write_rar(&tmp.join("clean/clean.rar"), &[file("c.txt", b"fine")]);
+    let scanner = tmp.join("scan.sh");
+    fs::write(
+        &scanner,
+        "#!/bin/sh
+if grep -rq EICAR \"$1\"; then echo \"$1: Eicar-Test-Signature FOUND\"; exit 1; fi\n",
+    )
+    .unwrap();
+    fs::set_permissions(&scanner, fs::Permissions::from_mode(0o755)).unwrap();
+    let quarantine = tmp.join("quarantine");
+    let args = |command: &str| {
+        [
+            tmp.root().to_string(),
+            "--remove-after-hours".into(),
+            "0".into(),
+            "--scan-command".into(),
+            command.into(),
+            "--quarantine-dir".into(),
+            quarantine.to_str().unwrap().into(),
+            "--verify-crc".into(),
+            "--output".into(),
+            "ndjson".into(),
+        ]
+    };
+
+    let run = rarscan(
+        ["--dry-run".to_string()]
+            .into_iter()
+            .chain(args(scanner.to_str().unwrap())),
+    );
+    assert!(run.success, "{}", run.log);
+    assert!(!run.log.contains("Scanned"), "{}", run.log);
+
+    let run = rarscan(args(scanner.to_str().unwrap()));
+    assert!(run.log.contains("sub/bad.txt' flagged by the scanner"), "{}", run.log);
+    assert!(run.log.contains("Eicar-Test-Signature FOUND"), "{}", run.log);
+    assert!(
+        run.log.contains("Scanned 3 extracted files, 2 clean and 1 flagged."),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("show/sub/bad.txt"));
+    assert_eq!(fs::read(quarantine.join("show/sub/bad.txt")).unwrap(), b"EICAR");
+    assert_file_size(&tmp.join("show/a.txt"), 5);
+    assert!(tmp.join("show/show.rar").exists());
+    assert_missing(&tmp.join("clean/clean.rar"));
+    // Only the clean archive counts as extracted, none of the files of the other is on record.
+    let done: Vec<_> = run
+        .log
+        .lines()
+        .filter(|line| line.contains(r#""extract_done""#))
+        .collect();
+    assert_eq!(done.len(), 1, "{}", run.log);
+    assert!(done[0].contains("clean.rar"), "{}", run.log);
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(state.contains("c.txt"), "{}", state);
+    assert!(!state.contains("a.txt"), "{}", state);
+
+    // Not extracted again until the archive changes.
+    let run = rarscan(args(scanner.to_str().unwrap()));
+    assert!(
+        run.log.contains("Quarantined by the scanner in an earlier run"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("show/sub/bad.txt"));
+    assert!(tmp.join("show/show.rar").exists());
+
+    // Once on the whole destination, which flags all of its files.
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[file("a.txt", b"hello"), file("sub/bad.txt", b"EICAR again")],
+    );
+    let run = rarscan(args(&format!("{} {{dir}}", scanner.display())));
+    assert!(
+        run.log.contains("Scanned 2 extracted files, 0 clean and 2 flagged."),
+        "{}",
+        run.log
+    );
+    assert_eq!(fs::read(quarantine.join("show.2/a.txt")).unwrap(), b"hello");
+    assert!(tmp.join("show/show.rar").exists());
+}
+
+#[test]
+fn duplicate_archives_are_skipped_or_linked() {
+    for policy in ["skip", "link"] {
+        let tmp = TempDir::new();
+        write_rar(&tmp.join("movies/show/show.rar"), &[file("show.mkv", b"payload")]);
+        write_rar(&tmp.join("tv/show/show.rar"), &[file("show.mkv", b"payload")]);
+
+        let run = rarscan([tmp.root(), "--duplicate-policy", policy]);
+        assert!(run.success, "{}", run.log);
+        assert!(
+            run.log
+                .contains("1 archives are duplicates of archives found elsewhere"),
+            "{}",
+            run.log
+        );
+        assert_file_size(&tmp.join("movies/show/show.mkv"), 7);
+        match policy {
+            "skip" => {
+                assert!(run.log.contains("tv/show/show.rar' (skipped)"), "{}", run.log);
+                assert_missing(&tmp.join("tv/show/show.mkv"));
+            }
+            _ => {
+                assert!(run.log.contains("tv/show/show.rar' (linked)"), "{}", run.log);
+                assert_file_size(&tmp.join("tv/show/show.mkv"), 7);
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::MetadataExt;
+                    let original = fs::metadata(tmp.join("movies/show/show.mkv")).unwrap();
+                    let linked = fs::metadata(tmp.join("tv/show/show.mkv")).unwrap();
+                    assert_eq!(original.ino(), linked.ino());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn large_queue_spills_over_memory_budget() {
+    let tmp = TempDir::new();
+    for i in 0..200 {
+        write_rar(
+            &tmp.join(format!("r{:03}/r{:03}.rar", i, i)),
+            &[file("a.txt", b"hello")],
+        );
+    }
+
+    let run = rarscan([tmp.root(), "--memory-budget", "8K", "--log-level", "debug"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("spilling to"), "{}", run.log);
+    for i in 0..200 {
+        assert_file_size(&tmp.join(format!("r{:03}/a.txt", i)), 5);
+    }
+    let leftover = fs::read_dir(tmp.path())
+        .unwrap()
+        .any(|entry| entry.unwrap().path().extension().is_some_and(|ext| ext == "queue"));
+    assert!(!leftover);
+}
+
+#[test]
+fn ignore_files_follow_gitignore_semantics() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("tv/old/old.rar"), &[file("a.txt", b"hello")]);
+    write_rar(&tmp.join("tv/keep/keep.rar"), &[file("a.txt", b"hello")]);
+    write_rar(&tmp.join("movies/movie/movie.rar"), &[file("a.txt", b"hello")]);
+    fs::write(tmp.join(".rarscanignore"), "# only tv/keep in tv\ntv/*\n!tv/keep/\n").unwrap();
+    fs::write(tmp.join("extra-ignore"), "movies/\n").unwrap();
+
+    let extra = tmp.join("extra-ignore");
+    let run = rarscan([tmp.root(), "--ignore-file", extra.to_str().unwrap()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("tv/keep/a.txt"), 5);
+    assert_missing(&tmp.join("tv/old/a.txt"));
+    assert_missing(&tmp.join("movies/movie/a.txt"));
+}
+
+#[test]
+fn removals_recorded_with_no_remove_are_applied_by_clean() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("old/old.rar"), &[file("a.txt", b"hello")]);
+    write_rar(&tmp.join("new/new.rar"), &[file("b.txt", b"hello")]);
+    set_age(&tmp.join("old/old.rar"), 2 * DAY);
+
+    let run = rarscan([tmp.root(), "--remove-after-hours", "24", "--no-remove"]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("old/a.txt"), 5);
+    assert!(tmp.join("old/old.rar").exists());
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(state.contains("pending_removals"), "{}", state);
+
+    let run = rarscan(["clean", tmp.root(), "--from-state"]);
+    assert!(run.success, "{}", run.log);
+    assert_missing(&tmp.join("old/old.rar"));
+    // Not old enough yet, it stays recorded for a later clean.
+    assert!(tmp.join("new/new.rar").exists());
+    let state: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap()).unwrap();
+    let pending = state["pending_removals"].as_object().unwrap();
+    assert_eq!(pending.len(), 1, "{:?}", pending);
+    assert!(pending.keys().all(|archive| archive.ends_with("new/new.rar")));
+}
+
+#[test]
+fn flapping_archives_are_reported_and_paused() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+
+    // Something else keeps truncating the extracted file.
+    for i in 1..=3 {
+        fs::write(tmp.join("show/a.txt"), b"x").unwrap();
+        let run = rarscan([tmp.root(), "--flapping-threshold", "2"]);
+        assert!(run.success, "{}", run.log);
+        assert_file_size(&tmp.join("show/a.txt"), 5);
+        assert_eq!(run.log.contains("has been re-extracted 3 times"), i == 3, "{}", run.log);
+    }
+
+    fs::write(tmp.join("show/a.txt"), b"x").unwrap();
+    let args = [tmp.root(), "--flapping-threshold", "2", "--flapping-policy", "pause"];
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("1 archives keep being extracted again"), "{}", run.log);
+    assert_file_size(&tmp.join("show/a.txt"), 1);
+
+    // Once the file is back in place the count starts over.
+    fs::write(tmp.join("show/a.txt"), b"hello").unwrap();
+    let run = rarscan(args);
+    assert!(run.success, "{}", run.log);
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(state.contains(r#""flaps":{}"#), "{}", state);
+}
+
+#[test]
+fn names_are_matched_as_the_destination_compares_them() {
+    let tmp = TempDir::new();
+    // Composed in the archive, decomposed on disk as by HFS+, and in another case.
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[file("Caf\u{e9}.txt", b"hello"), file("Readme.TXT", b"world")],
+    );
+    fs::write(tmp.join("show/Cafe\u{301}.txt"), b"hello").unwrap();
+    fs::write(tmp.join("show/readme.txt"), b"world").unwrap();
+
+    let run = rarscan([tmp.root(), "--dry-run", "--name-matching", "exact"]);
+    assert!(!run.log.contains("Archive already extracted."), "{}", run.log);
+
+    let run = rarscan([tmp.root(), "--name-matching", "ignore-both"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Archive already extracted."), "{}", run.log);
+    assert_missing(&tmp.join("show/Caf\u{e9}.txt"));
+    assert_missing(&tmp.join("show/Readme.TXT"));
+
+    // Entries that would land on the same file are reported rather than extracted over one another.
+    write_rar(
+        &tmp.join("other/other.rar"),
+        &[
+            file("a.txt", b"hello"),
+            file("sub/A.TXT", b"world"),
+            file("Sub/a.txt", b"again"),
+        ],
+    );
+    let run = rarscan([tmp.root(), "--name-matching", "ignore-both"]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("1 pairs of entries would be the same file"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("'sub/A.TXT' and 'Sub/a.txt'"), "{}", run.log);
+    assert!(
+        run.log
+            .contains("1 archives skipped for entries that would be the same file"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("other/a.txt"));
+    assert!(tmp.join("other/other.rar").exists());
+}
+
+// The panic is raised by a hook of debug builds.
+#[cfg(debug_assertions)]
+#[test]
+fn a_panic_fails_its_archive_alone() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("bad/bad.rar"),
+        &[file("a.txt", b"hello"), file("b.txt", b"world")],
+    );
+    write_rar(&tmp.join("good/good.rar"), &[file("c.txt", b"fine")]);
+    let panicking = || {
+        run(command().env("RARSCAN_TEST_PANIC_LISTING", "bad.rar").args([
+            tmp.root(),
+            "--remove-after-hours",
+            "0",
+            "--panic-limit",
+            "2",
+        ]))
+    };
+
+    for i in 1..=2 {
+        let run = panicking();
+        assert!(!run.success, "{}", run.log);
+        assert!(
+            run.log.contains("-> Processing panicked: listing '") && run.log.contains("panicked for the test"),
+            "{}",
+            run.log
+        );
+        assert_eq!(run.log.contains("Panicked in 2 runs in a row"), i == 2, "{}", run.log);
+        assert!(tmp.join("bad/bad.rar").exists());
+        assert_missing(&tmp.join("bad/a.txt"));
+        if i == 1 {
+            // The run went on with the next archive.
+            assert_file_size(&tmp.join("good/c.txt"), 4);
+            assert_missing(&tmp.join("good/good.rar"));
+        }
+    }
+
+    // Left alone from then on, until it changes.
+    let run = panicking();
+    assert!(
+        run.log.contains("left alone until its first part changes"),
+        "{}",
+        run.log
+    );
+    assert!(!run.log.contains("panicked for the test"), "{}", run.log);
+    assert!(tmp.join("bad/bad.rar").exists());
+
+    write_rar(
+        &tmp.join("bad/bad.rar"),
+        &[file("a.txt", b"hello"), file("b.txt", b"again")],
+    );
+    let run = rarscan([tmp.root(), "--remove-after-hours", "0", "--panic-limit", "2"]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("bad/b.txt"), 5);
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(state.contains(r#""panics":{}"#), "{}", state);
+}
+
+#[cfg(unix)]
+#[test]
+fn created_directories_inherit_the_group_of_their_parent() {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("sub/deep/a.txt", b"hello")]);
+    // Without the setgid bit on the parent, nothing but rarscan hands its group down.
+    if let Err(e) = chown(tmp.join("show"), None, Some(4242)) {
+        assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+        return;
+    }
+
+    let run = rarscan([tmp.root(), "--inherit-dir-perms"]);
+    assert!(run.success, "{}", run.log);
+    for path in ["show/sub", "show/sub/deep", "show/sub/deep/a.txt"] {
+        assert_eq!(fs::metadata(tmp.join(path)).unwrap().gid(), 4242, "{}", path);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn fix_ownership_gives_extracted_files_back() {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("sub/a.txt", b"hello")]);
+    fs::write(tmp.join("show/notes.txt"), b"not extracted").unwrap();
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    let me = fs::metadata(tmp.join("show/sub/a.txt")).unwrap();
+    let owner = format!("{}:{}", me.uid(), me.gid());
+    let run = rarscan(["fix-ownership", tmp.root(), "--owner", &owner]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Checked 3 paths, 0 not owned"), "{}", run.log);
+
+    // Only root can hand files to someone else, like a run with sudo would.
+    if let Err(e) = chown(tmp.join("show/sub/a.txt"), Some(4242), Some(4242)) {
+        assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+        return;
+    }
+    chown(tmp.join("show/notes.txt"), Some(4242), Some(4242)).unwrap();
+    let run = rarscan(["fix-ownership", tmp.root(), "--owner", &owner, "--dry-run"]);
+    assert_eq!(run.code, Some(1), "{}", run.log);
+    assert!(run.log.contains("a.txt' is owned by 4242:4242"), "{}", run.log);
+    assert!(!run.log.contains("notes.txt"), "{}", run.log);
+    assert_eq!(fs::metadata(tmp.join("show/sub/a.txt")).unwrap().uid(), 4242);
+
+    let run = rarscan(["fix-ownership", tmp.root(), "--owner", &owner]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("1 not owned"), "{}", run.log);
+    assert_eq!(fs::metadata(tmp.join("show/sub/a.txt")).unwrap().uid(), me.uid());
+    assert_eq!(fs::metadata(tmp.join("show/notes.txt")).unwrap().uid(), 4242);
+
+    let run = rarscan(["fix-ownership", tmp.root(), "--owner", &owner, "--whole-dir"]);
+    assert!(run.success, "{}", run.log);
+    assert_eq!(fs::metadata(tmp.join("show/notes.txt")).unwrap().uid(), me.uid());
+}
+
+#[test]
+fn preallocated_files_have_their_exact_size() {
+    let tmp = TempDir::new();
+    let data = payload(5000);
+    write_multipart(&tmp.join("movie/movie"), "movie.mkv", &data, 2000);
+    write_rar(&tmp.join("show/show.rar"), &[dir("sub"), file("sub/a.txt", b"hello")]);
+    let run = rarscan([tmp.root(), "--preallocate", "always"]);
+    assert!(run.success, "{}", run.log);
+    assert_eq!(fs::read(tmp.join("movie/movie.mkv")).unwrap(), data);
+    assert_file_size(&tmp.join("show/sub/a.txt"), 5);
+    let streamed = mtime(&tmp.join("show/sub/a.txt"));
+
+    // Like unrar would have written it.
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[dir("sub"), file("sub/a.txt", b"hello")]);
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_eq!(mtime(&tmp.join("show/sub/a.txt")), streamed);
+
+    // An extraction aborted midway doesn't leave the file at its preallocated size, which would pass for extracted.
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("movie/movie"), "movie.mkv", &data, 2000);
+    let part = fs::read(&parts[1]).unwrap();
+    fs::write(&parts[1], &part[..part.len() - 1500]).unwrap();
+    for _ in 0..2 {
+        let run = rarscan([tmp.root(), "--preallocate", "always"]);
+        assert_eq!(run.code, Some(2), "{}", run.log);
+        assert!(run.log.contains("(corrupt)"), "{}", run.log);
+        let size = fs::metadata(tmp.join("movie/movie.mkv")).unwrap().len();
+        assert!(size < 5000, "{}", size);
+    }
+}
+
+#[test]
+fn corrupt_state_files_fall_back_on_their_backup() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.txt", b"hello")]);
+    let state = tmp.join(".rarscan-state.json");
+    let backup = tmp.join(".rarscan-state.json.bak");
+    let mut mtimes = serde_json::Map::new();
+    mtimes.insert(tmp.join("gone/gone.rar").to_string_lossy().into_owned(), 1.0.into());
+    fs::write(
+        &state,
+        serde_json::json!({ "version": 1, "mtimes": mtimes }).to_string(),
+    )
+    .unwrap();
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("1 outdated entries dropped"), "{}", run.log);
+    let saved = fs::read_to_string(&state).unwrap();
+    assert!(saved.contains("a.txt") && !saved.contains("gone.rar"), "{}", saved);
+    assert!(fs::read_to_string(&backup).unwrap().contains("gone.rar"));
+
+    // As left by a crash while it was written.
+    fs::write(&state, &saved[..saved.len() / 2]).unwrap();
+    let run = rarscan(["state", "stats", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("is corrupt") && run.log.contains("Using its backup"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("Kept mtimes              1"), "{}", run.log);
+
+    fs::write(&backup, b"{").unwrap();
+    let run = rarscan([tmp.root()]);
+    assert!(!run.success, "{}", run.log);
+    assert!(run.log.contains("use --reset-state"), "{}", run.log);
+    let run = rarscan([tmp.root(), "--reset-state"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Starting from an empty state"), "{}", run.log);
+    // The corrupt file doesn't replace the backup.
+    assert_eq!(fs::read(&backup).unwrap(), b"{");
+    let run = rarscan(["state", "stats", tmp.root()]);
+    assert!(run.success && !run.log.contains("corrupt"), "{}", run.log);
+}
+
+#[test]
+fn unpacks_tarballs_found_inside_of_archives() {
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), Default::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(5);
+    header.set_mode(0o644);
+    header.set_mtime(1_000_000_000);
+    builder.append_data(&mut header, "data/a.txt", &b"hello"[..]).unwrap();
+    for (kind, name, target) in [
+        (tar::EntryType::Symlink, "data/link", "a.txt"),
+        (tar::EntryType::Symlink, "data/escape", "../../outside"),
+        (tar::EntryType::Link, "data/hard", "data/a.txt"),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_size(0);
+        builder.append_link(&mut header, name, target).unwrap();
+    }
+    let tarball = builder.into_inner().unwrap().finish().unwrap();
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("backup.tar.gz", &tarball)]);
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(tmp.join("show/backup.tar.gz").exists());
+    assert_missing(&tmp.join("show/data"));
+
+    let run = rarscan([tmp.root(), "--unpack-tarballs"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Unpacked 1 tarballs."), "{}", run.log);
+    assert_eq!(fs::read(tmp.join("show/data/a.txt")).unwrap(), b"hello");
+    assert_eq!(
+        mtime(&tmp.join("show/data/a.txt")),
+        std::time::UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+    );
+    assert_eq!(
+        fs::read_link(tmp.join("show/data/link")).unwrap(),
+        std::path::Path::new("a.txt")
+    );
+    assert_eq!(fs::read(tmp.join("show/data/hard")).unwrap(), b"hello");
+    assert!(run.log.contains("it points outside of the destination"), "{}", run.log);
+    assert!(fs::symlink_metadata(tmp.join("show/data/escape")).is_err());
+    let state = fs::read_to_string(tmp.join(".rarscan-state.json")).unwrap();
+    assert!(state.contains("data/a.txt"), "{}", state);
+
+    let run = rarscan([tmp.root(), "--unpack-tarballs"]);
+    assert!(run.success, "{}", run.log);
+    assert!(!run.log.contains("Unpacked"), "{}", run.log);
+}
+
+#[cfg(unix)]
+#[test]
+fn tarball_links_chained_out_of_the_destination_are_not_followed() {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    builder.append_data(&mut header, "a/", &b""[..]).unwrap();
+    for (name, target) in [("a/b", ".."), ("c", "a/b/../..")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, name, target).unwrap();
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_size(4);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, "c/evil.txt", &b"evil"[..]).unwrap();
+    let tarball = builder.into_inner().unwrap();
+    let tmp = TempDir::new();
+    let root = tmp.join("root");
+    write_rar(&root.join("show/show.rar"), &[file("payload.tar", &tarball)]);
+
+    let run = rarscan([root.to_str().unwrap(), "--unpack-tarballs"]);
+    assert!(run.success, "{}", run.log);
+    assert_missing(&tmp.join("evil.txt"));
+    assert_missing(&root.join("evil.txt"));
+    assert_eq!(fs::read(root.join("show/c/evil.txt")).unwrap(), b"evil");
+    assert!(!fs::symlink_metadata(root.join("show/c")).unwrap().is_symlink());
+    assert_eq!(
+        fs::read_link(root.join("show/a/b")).unwrap(),
+        std::path::Path::new("..")
+    );
+}
+
+#[test]
+fn rar_entries_climbing_out_of_the_destination_fail_the_archive() {
+    let tmp = TempDir::new();
+    let root = tmp.join("root");
+    let archive = root.join("show/show.rar");
+    write_rar(&archive, &[file("a.txt", b"hello"), file("../../evil.txt", b"evil")]);
+
+    // Left to unrar, then written by rarscan itself.
+    for args in [&[][..], &["--preallocate", "always"][..]] {
+        let run = rarscan(args.iter().copied().chain([root.to_str().unwrap()]));
+        assert!(!run.success);
+        assert!(run.log.contains("has an unsafe path"), "{}", run.log);
+        assert_missing(&tmp.join("evil.txt"));
+        assert_missing(&root.join("evil.txt"));
+        assert_missing(&root.join("show/a.txt"));
+        assert!(archive.exists());
+    }
+}
+
+#[test]
+fn rename_maps_point_the_extracted_check_at_renamed_files() {
+    let tmp = TempDir::new();
+    let archive = tmp.join("show/show.rar");
+    write_rar(&archive, &[file("a.mkv", b"episode")]);
+    assert!(rarscan([tmp.root()]).success);
+    fs::create_dir(tmp.join("show/Season 1")).unwrap();
+    // Renamed and transcoded.
+    fs::remove_file(tmp.join("show/a.mkv")).unwrap();
+    fs::write(tmp.join("show/Season 1/Show - s01e01.mkv"), b"transcoded episode").unwrap();
+
+    let outside = TempDir::new();
+    fs::write(outside.join("a.mkv"), b"episode").unwrap();
+    let run = rarscan([
+        "map-rename",
+        archive.to_str().unwrap(),
+        "a.mkv",
+        outside.join("a.mkv").to_str().unwrap(),
+    ]);
+    assert!(!run.success, "{}", run.log);
+    assert!(
+        run.log.contains("is outside of the directory of the archive"),
+        "{}",
+        run.log
+    );
+
+    let renamed = tmp.join("show/Season 1/Show - s01e01.mkv");
+    let run = rarscan([
+        "map-rename",
+        archive.to_str().unwrap(),
+        "a.mkv",
+        renamed.to_str().unwrap(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    let map = fs::read_to_string(tmp.join("show/show.rarscan-map")).unwrap();
+    assert!(map.contains("\"path\":\"Season 1/Show - s01e01.mkv\""), "{}", map);
+
+    let run = rarscan([tmp.root(), "--verify-crc"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Archive already extracted"), "{}", run.log);
+    assert_missing(&tmp.join("show/a.mkv"));
+
+    // A map pointing outside of the allowed directories is ignored.
+    let mut renames = serde_json::Map::new();
+    renames.insert(
+        "a.mkv".into(),
+        outside.join("a.mkv").to_string_lossy().into_owned().into(),
+    );
+    let map = serde_json::json!({ "version": 1, "renames": renames });
+    fs::write(tmp.join("show/show.rarscan-map"), map.to_string()).unwrap();
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("outside of the allowed directories"), "{}", run.log);
+    assert_file_size(&tmp.join("show/a.mkv"), 7);
+}
+
+#[cfg(unix)]
+#[test]
+fn recovery_volumes_make_incomplete_sets_repairable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new();
+    let data = payload(5000);
+    let parts = write_multipart(&tmp.join("movie/movie"), "movie.mkv", &data, 2000);
+    let elsewhere = TempDir::new();
+    let spare = elsewhere.join("movie.part2.rar");
+    fs::rename(&parts[1], &spare).unwrap();
+    for n in 1..=2 {
+        fs::write(tmp.join(format!("movie/movie.part{}.rev", n)), b"recovery").unwrap();
+    }
+
+    let run = rarscan(["--remove-after-hours", "0", tmp.root()]);
+    assert_eq!(run.code, Some(2), "{}", run.log);
+    assert!(
+        run.log
+            .contains("Set is incomplete but repairable (1 missing, 2 recovery volumes)"),
+        "{}",
+        run.log
+    );
+    assert!(run.log.contains("1 archives can be repaired"), "{}", run.log);
+    // The recovery volumes are kept along with the parts until the set is extracted.
+    assert!(tmp.join("movie/movie.part1.rev").exists());
+    assert!(parts[2].exists());
+
+    // A recovery tool rebuilding the missing volume lets the archive be extracted in the same run.
+    let rar = elsewhere.join("rar.sh");
+    fs::write(
+        &rar,
+        format!(
+            "#!/bin/sh\n[ \"$1\" = rc ] || exit 1\ncp '{}' '{}'\n",
+            spare.display(),
+            parts[1].display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&rar, fs::Permissions::from_mode(0o755)).unwrap();
+    let run = rarscan([
+        "--remove-after-hours",
+        "0",
+        "--recovery-tool",
+        &rar.to_string_lossy(),
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Rebuilt the missing volumes"), "{}", run.log);
+    assert_file_size(&tmp.join("movie/movie.mkv"), 5000);
+    assert_missing(&parts[0]);
+    assert_missing(&tmp.join("movie/movie.part1.rev"));
+}
+
+#[test]
+fn explain_narrates_the_checks_of_an_archive() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("show/show.rar"),
+        &[file("a.txt", b"hello"), file("b.txt", b"world!")],
+    );
+    set_age(&tmp.join("show/show.rar"), 2 * DAY);
+    fs::write(tmp.join("show/a.txt"), b"hello").unwrap();
+    fs::write(tmp.join("show/b.txt"), b"wor").unwrap();
+    fs::write(tmp.join(".rarscanignore"), "*.sample.rar\n").unwrap();
+
+    let archive = tmp.join("show/show.rar");
+    let run = rarscan([
+        "--remove-after-hours",
+        "24",
+        "explain",
+        "--root",
+        tmp.root(),
+        archive.to_str().unwrap(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    for line in [
+        "Root detection           root archive, matches no part pattern",
+        "Ignore rules             no rule matches it",
+        "Already extracted        no, 1 of the 2 entries are not in place",
+        "-> 'a.txt' 5 B, in place",
+        "-> 'b.txt' 3 B, 6 B wanted",
+        "Removal                  after 24.0h (--remove-after-hours)",
+        "48.0h old, removable now",
+        "Next run                 extracts it into ",
+    ] {
+        assert!(run.log.contains(line), "{}\n{}", line, run.log);
+    }
+    // Nothing was touched.
+    assert_file_size(&tmp.join("show/b.txt"), 3);
+    assert!(archive.exists());
+    assert_missing(&tmp.join(".rarscan-state.json"));
+
+    fs::rename(&archive, tmp.join("show/show.sample.rar")).unwrap();
+    let run = rarscan([
+        "explain",
+        "--root",
+        tmp.root(),
+        tmp.join("show/show.sample.rar").to_str().unwrap(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("ignored by '*.sample.rar'"), "{}", run.log);
+    assert!(
+        run.log.contains("Next run                 nothing, it's ignored"),
+        "{}",
+        run.log
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn spellings_of_the_same_root_share_one_state() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("data/downloads/show/show.rar"), &[file("show.txt", b"show")]);
+    std::os::unix::fs::symlink(tmp.join("data/downloads"), tmp.join("dl")).unwrap();
+    let state_file = tmp.join("state.json");
+    let state_arg = state_file.to_str().unwrap();
+    let archive = fs::canonicalize(tmp.join("data/downloads/show/show.rar")).unwrap();
+    let analyzing = format!("Analyzing '{}'", archive.display());
+
+    let run = rarscan(["--state-file", state_arg, &format!("{}/dl/", tmp.root())]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains(&analyzing), "{}", run.log);
+    let state = fs::read_to_string(&state_file).unwrap();
+    assert!(state.contains("data/downloads/show/show.txt"), "{}", state);
+    assert!(!state.contains("/dl/"), "{}", state);
+
+    for root in [tmp.join("data/downloads/../downloads"), tmp.join("dl/show/./show.rar")] {
+        let run = rarscan(["--state-file", state_arg, root.to_str().unwrap()]);
+        assert!(run.success, "{}", run.log);
+        assert_eq!(run.log.matches(&analyzing).count(), 1, "{}", run.log);
+        assert!(run.log.contains("Archive already extracted"), "{}", run.log);
+    }
+
+    // A state file from before the paths were canonical is re-keyed once.
+    let spelled = state
+        .replace("data/downloads/", "dl/")
+        .replace("\"version\":3", "\"version\":1");
+    fs::write(&state_file, spelled).unwrap();
+    let run = rarscan(["--state-file", state_arg, tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Migrated the state file to canonical paths"),
+        "{}",
+        run.log
+    );
+    assert!(!fs::read_to_string(&state_file).unwrap().contains("/dl/"));
+}
+
+// Reading /proc/self/mem from its start fails with EIO, like a dying disk.
+#[cfg(target_os = "linux")]
+#[test]
+fn io_errors_suspend_the_failing_device() {
+    let tmp = TempDir::new();
+    fs::create_dir_all(tmp.join("a-sick")).unwrap();
+    for n in 1..=4 {
+        std::os::unix::fs::symlink("/proc/self/mem", tmp.join(format!("a-sick/show{}.rar", n))).unwrap();
+    }
+    write_rar(&tmp.join("z-healthy/movie.rar"), &[file("movie.mkv", b"movie")]);
+
+    let run = rarscan(["--follow-symlinks", "--max-device-errors", "2", tmp.root()]);
+    assert_eq!(run.code, Some(5), "{}", run.log);
+    assert!(run.log.contains("suspended after 2 IO errors"), "{}", run.log);
+    assert!(
+        run.log.contains("2 archives skipped because their device is suspended"),
+        "{}",
+        run.log
+    );
+    assert_file_size(&tmp.join("z-healthy/movie.mkv"), 5);
+}
+
+#[test]
+fn sets_are_skipped_while_a_download_is_in_progress() {
+    let tmp = TempDir::new();
+    let data = payload(5000);
+    let parts = write_multipart(&tmp.join("show/show"), "show.mkv", &data, 2000);
+    let marker = tmp.join("show/show.part3.rar.!qB");
+    fs::write(&marker, b"partial").unwrap();
+    fs::write(tmp.join("show/show.sfv"), b"checksums").unwrap();
+
+    let run = rarscan(["--remove-after-hours", "0", tmp.root()]);
+    assert!(run.log.contains("Download in progress"), "{}", run.log);
+    assert!(
+        run.log.contains("1 archives skipped while their download"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("show/show.mkv"));
+    assert!(parts[0].exists());
+    assert!(tmp.join("show/show.sfv").exists());
+
+    fs::remove_file(&marker).unwrap();
+    let run = rarscan(["--remove-after-hours", "0", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/show.mkv"), 5000);
+    assert_missing(&parts[0]);
+
+    let run = rarscan(["--print-config", "--in-progress-marker", "*.crdownload"]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("*.!qB"), "{}", run.log);
+    assert!(run.log.contains("*.crdownload"), "{}", run.log);
+}
+
+/// A window of `--active-hours` from `start` to `end` minutes away from now, in UTC.
+fn hours_from_now(start: i64, end: i64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+        / 60;
+    let clock = |minutes: i64| {
+        let minutes = (now + minutes).rem_euclid(24 * 60);
+        format!("{:02}:{:02}", minutes / 60, minutes % 60)
+    };
+    format!("{}-{}", clock(start), clock(end))
+}
+
+#[test]
+fn archives_are_extracted_inside_the_active_window_only() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("show.mkv", &payload(1000))]);
+
+    let closed = hours_from_now(120, 180);
+    let run = rarscan(["--timezone", "utc", "--active-hours", &closed, tmp.root()]);
+    assert_eq!(run.code, Some(6), "{}", run.log);
+    assert!(run.log.contains("Outside of the active window"), "{}", run.log);
+    assert!(
+        run.log.contains(&format!("it opens at {}", &closed[..5])),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("show/show.mkv"));
+
+    let open = hours_from_now(-60, 60);
+    let run = rarscan([
+        "--timezone",
+        "utc",
+        "--active-hours",
+        &open,
+        "--active-days",
+        "mon-sun",
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/show.mkv"), 1000);
+}
+
+#[test]
+fn sidecars_override_the_options_of_their_archive() {
+    let tmp = TempDir::new();
+    write_rar(
+        &tmp.join("movie/movie.rar"),
+        &[
+            file("movie.mkv", &payload(3000)),
+            file("Sample/sample.mkv", &payload(100)),
+        ],
+    );
+    let sidecar = tmp.join("movie/movie.rarscan.toml");
+    fs::write(
+        &sidecar,
+        "# one-off\ndest = \"../done\"\nexclude = [\"Sample\"]\nremove_after_hours = 0\n",
+    )
+    .unwrap();
+    write_rar(&tmp.join("show/show.rar"), &[file("show.mkv", &payload(1000))]);
+    fs::write(tmp.join("show/show.rarscan.toml"), "skip = true\n").unwrap();
+    write_rar(&tmp.join("bad/bad.rar"), &[file("bad.mkv", &payload(1000))]);
+    fs::write(tmp.join("bad/bad.rarscan.toml"), "dest = 'x'\npasword = \"secret\"\n").unwrap();
+
+    let run = rarscan(["--dry-run", tmp.root()]);
+    assert!(
+        run.log
+            .contains("movie.rarscan.toml' sets dest, exclude, remove_after_hours."),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("done"));
+
+    let run = rarscan([tmp.root()]);
+    assert_eq!(run.code, Some(2), "{}", run.log);
+    assert_file_size(&tmp.join("done/movie.mkv"), 3000);
+    assert_missing(&tmp.join("done/Sample"));
+    assert_missing(&tmp.join("movie/movie.rar"));
+    assert_missing(&sidecar);
+    assert!(run.log.contains("1 archives skipped by their sidecar"), "{}", run.log);
+    assert_missing(&tmp.join("show/show.mkv"));
+    assert!(tmp.join("show/show.rar").exists());
+    assert!(
+        run.log.contains("bad.rarscan.toml' line 2: unknown key 'pasword'"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("bad/bad.mkv"));
+    assert!(tmp.join("bad/bad.rar").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn snapshots_are_taken_before_removing() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmp = TempDir::new();
+    let tools = TempDir::new();
+    let log = tools.join("calls.log");
+    let script = |name: &str, status: u8| {
+        let path = tools.join(name);
+        let body = format!(
+            "#!/bin/sh\necho {} \"$@\" >> '{}'\nexit {}\n",
+            name,
+            log.display(),
+            status
+        );
+        fs::write(&path, body).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    };
+    let (snap, failing, delete) = (script("snap", 0), script("failing", 1), script("delete", 0));
+    write_rar(&tmp.join("a/a.rar"), &[file("a.txt", b"hello")]);
+    write_rar(&tmp.join("b/b.rar"), &[file("b.txt", b"world")]);
+    fs::write(tmp.join("b/b.sfv"), b"b.rar 00000000").unwrap();
+
+    // A snapshot that fails leaves everything in place, the extractions still happen.
+    let run = rarscan([
+        "--remove-after-hours",
+        "0",
+        "--snapshot-before-remove",
+        &format!("{} {{path}}", failing),
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("nothing is removed for the rest of the run"),
+        "{}",
+        run.log
+    );
+    assert_file_size(&tmp.join("a/a.txt"), 5);
+    assert_file_size(&tmp.join("b/b.txt"), 5);
+    assert!(tmp.join("a/a.rar").exists());
+    assert!(tmp.join("b/b.sfv").exists());
+
+    // One snapshot for the filesystem, taken before the first removal.
+    let run = rarscan([
+        "--remove-after-hours",
+        "0",
+        "--snapshot-before-remove",
+        &format!("{} {{path}} {{path}}/.snap-{{ts}}", snap),
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("1 snapshots taken before removing"), "{}", run.log);
+    assert_missing(&tmp.join("a/a.rar"));
+    assert_missing(&tmp.join("b/b.sfv"));
+    let calls = fs::read_to_string(&log).unwrap();
+    assert_eq!(
+        calls.lines().filter(|line| line.starts_with("snap ")).count(),
+        1,
+        "{}",
+        calls
+    );
+
+    // Past the retention, the snapshot recorded in the state file is deleted.
+    write_rar(&tmp.join("c/c.rar"), &[file("c.txt", b"again")]);
+    let run = rarscan([
+        "--snapshot-before-remove",
+        &format!("{} {{path}}", snap),
+        "--snapshot-retention",
+        "0s",
+        "--snapshot-delete",
+        &format!("{} {{snapshot}}", delete),
+        tmp.root(),
+    ]);
+    assert!(run.success, "{}", run.log);
+    assert!(run.log.contains("Deleted 1 expired snapshots"), "{}", run.log);
+    let calls = fs::read_to_string(&log).unwrap();
+    assert!(
+        calls
+            .lines()
+            .any(|line| line.starts_with("delete ") && line.contains("/.snap-")),
+        "{}",
+        calls
+    );
+}
+
+#[test]
+fn ctl_requests_of_an_unfinished_run_are_applied_again() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("a/a.rar"), &[file("a.txt", b"hello")]);
+    write_rar(&tmp.join("b/b.rar"), &[file("b.txt", b"world")]);
+    let dropped = tmp.join("a/a.rar");
+    let state = serde_json::json!({
+        "version": 1,
+        "ctl": { "prioritized": [], "dropped": [dropped.to_string_lossy()] },
+    });
+    fs::write(tmp.join(".rarscan-state.json"), state.to_string()).unwrap();
+
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert!(
+        run.log.contains("dropped from the queue of the last run"),
+        "{}",
+        run.log
+    );
+    assert_missing(&tmp.join("a/a.txt"));
+    assert_file_size(&tmp.join("b/b.txt"), 5);
+
+    // Once a run finished, the requests are forgotten.
+    let run = rarscan([tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("a/a.txt"), 5);
+}
+
+#[test]
+fn parts_are_backed_up_before_extraction_and_kept_when_that_fails() {
+    let tmp = TempDir::new();
+    let parts = write_multipart(&tmp.join("show/show"), "show.bin", &payload(3000), 1000);
+    for part in &parts {
+        set_age(part, 2 * DAY);
+    }
+    let backups = tmp.join("backups");
+    let args = |extra: &[&'static str]| {
+        let mut args = vec![
+            "--backup-parts-to",
+            backups.to_str().unwrap(),
+            "--remove-after-hours",
+            "24",
+        ];
+        args.extend_from_slice(extra);
+        args.push(tmp.root());
+        args
+    };
+
+    let run = rarscan(args(&["--no-remove"]));
+    assert!(run.success, "{}", run.log);
+    assert_file_size(&tmp.join("show/show.bin"), 3000);
+    let days: Vec<_> = fs::read_dir(&backups)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(days.len(), 1);
+    let backup = days[0].join("show");
+    for part in &parts {
+        assert_eq!(
+            fs::read(backup.join(part.file_name().unwrap())).unwrap(),
+            fs::read(part).unwrap()
+        );
+    }
+    assert!(run.log.contains("parts into '"), "{}", run.log);
+
+    // A backup that can't be made keeps the parts.
+    fs::remove_dir_all(&backups).unwrap();
+    fs::write(&backups, b"not a directory").unwrap();
+    let run = rarscan(args(&[]));
+    assert!(run.success, "{}", run.log);
+    assert!(parts.iter().all(|part| part.exists()));
+    assert!(run.log.contains("could not be backed up"), "{}", run.log);
+
+    // The backup of the first run is gone, the parts are backed up again before they are removed.
+    fs::remove_file(&backups).unwrap();
+    let run = rarscan(args(&[]));
+    assert!(run.success, "{}", run.log);
+    assert_missing(&parts[0]);
+    assert!(backup.join("show.part1.rar").exists(), "{}", run.log);
+}
+
+#[test]
+fn stored_entries_are_copied_out_of_their_volume_and_checked() {
+    let tmp = TempDir::new();
+    let data = payload(5000);
+    write_rar(
+        &tmp.join("single/single.rar"),
+        &[file("a.bin", &data), file("sub/b.txt", b"b")],
+    );
+    write_multipart(&tmp.join("multi/multi"), "big.bin", &data, 2000);
+
+    let run = rarscan(["--fast-store-copy", "--log-level", "debug", tmp.root()]);
+    assert!(run.success, "{}", run.log);
+    assert_eq!(fs::read(tmp.join("single/a.bin")).unwrap(), data);
+    assert_eq!(fs::read(tmp.join("single/sub/b.txt")).unwrap(), b"b");
+    // Split across volumes, extracted by unrar.
+    assert_eq!(fs::read(tmp.join("multi/big.bin")).unwrap(), data);
+    assert_eq!(run.log.matches("-> Copied ").count(), 2, "{}", run.log);
+
+    // Data that doesn't match the CRC32 of its header is extracted by unrar, which fails on it.
+    let archive = tmp.join("corrupt/corrupt.rar");
+    write_rar(&archive, &[file("c.bin", &data)]);
+    let mut bytes = fs::read(&archive).unwrap();
+    let at = bytes.windows(data.len()).position(|window| window == data).unwrap() + 100;
+    bytes[at] ^= 0xff;
+    fs::write(&archive, bytes).unwrap();
+    let run = rarscan(["--fast-store-copy", tmp.join("corrupt").to_str().unwrap()]);
+    assert!(!run.success, "{}", run.log);
+    assert!(
+        run.log.contains("Could not copy 'c.bin' out of its volume"),
+        "{}",
+        run.log
+    );
+}
+
+#[test]
+fn stored_entries_are_copied_with_the_smallest_fd_budget() {
+    let tmp = TempDir::new();
+    write_rar(&tmp.join("show/show.rar"), &[file("a.bin", &payload(5000))]);
+
+    // The copy opens the volume while the extraction holds the whole budget.
+    let mut child = command()
+        .args(["--max-open-files", "2", "--fast-store-copy", tmp.root()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed() > Duration::from_secs(30) {
+            child.kill().unwrap();
+            panic!("rarscan hung with a budget of 2 descriptors");
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    assert!(status.success());
+    assert_eq!(fs::read(tmp.join("show/a.bin")).unwrap(), payload(5000));
+}
+
+/// Compares --fast-store-copy with unrar on a large store-only archive, run with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn fast_store_copy_benchmark() {
+    let tmp = TempDir::new();
+    let data = payload(256 << 20);
+    write_rar(&tmp.join("store/store.rar"), &[file("store.bin", &data)]);
+    drop(data);
+    for args in [&[][..], &["--fast-store-copy"][..]] {
+        let _ = fs::remove_file(tmp.join("store/store.bin"));
+        let _ = fs::remove_file(tmp.join(".rarscan-state.json"));
+        let started = std::time::Instant::now();
+        let run = rarscan(args.iter().copied().chain([tmp.root()]));
+        assert!(run.success, "{}", run.log);
+        eprintln!("{:?}: {:.2?}", args, started.elapsed());
+    }
+}
+
+#[test]
+fn sandboxed_extractions_are_handled_like_the_others() {
+    let tmp = TempDir::new();
+    let data = payload(5000);
+    let parts = write_multipart(&tmp.join("movie/movie"), "movie.mkv", &data, 2000);
+    write_rar(&tmp.join("bad/bad.rar"), &[file("bad.bin", &payload(1000))]);
+    let mut bytes = fs::read(tmp.join("bad/bad.rar")).unwrap();
+    let len = bytes.len();
+    bytes[len - 100] ^= 0xff;
+    fs::write(tmp.join("bad/bad.rar"), bytes).unwrap();
+    write_rar(&tmp.join("show/show.rar"), &[dir("sub"), file("sub/a.txt", b"hello")]);
+
+    let run = rarscan(["--sandbox", "--remove-after-hours", "0", tmp.root()]);
+    assert_eq!(run.code, Some(2), "{}", run.log);
+    assert!(
+        run.log.contains("Extracting in a sandbox") || run.log.contains("extracting without a sandbox"),
+        "{}",
+        run.log
+    );
+    assert_eq!(fs::read(tmp.join("movie/movie.mkv")).unwrap(), data);
+    assert_file_size(&tmp.join("show/sub/a.txt"), 5);
+    assert_missing(&parts[0]);
+    assert_missing(&tmp.join("show/show.rar"));
+    // The failure comes back from the worker classified.
+    assert!(run.log.contains("1 archives failed to extract"), "{}", run.log);
+    assert!(run.log.contains("(corrupt)"), "{}", run.log);
+    assert!(tmp.join("bad/bad.rar").exists());
+}